@@ -0,0 +1,83 @@
+//! Benchmarks of contention on the input state of a task
+//!
+//! Successors of a node read the state it completes as soon as they're woken, all at once for a
+//! wide fan-out, so the readers here share one state: either wait for its completion and read
+//! it, or keep reading it once it's completed.
+//!
+//! Run with `cargo bench --bench exec_state`
+
+use std::sync::Arc;
+
+use app_server::{
+    engine::{task::ExecState, State},
+    pipeline::nodes::{Message, NodeInput},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const READERS: [usize; 3] = [10, 100, 500];
+/// Reads of each reader of a completed state
+const READS: usize = 100;
+
+fn state() -> State {
+    State::new(Message {
+        value: NodeInput::String("why".to_string()),
+        ..Message::empty()
+    })
+}
+
+fn bench_exec_state(c: &mut Criterion) {
+    // multi-threaded, so that woken readers contend for the state
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("wake");
+    for readers in READERS {
+        group.bench_with_input(BenchmarkId::from_parameter(readers), &readers, |b, &readers| {
+            b.to_async(&rt).iter(|| async move {
+                let input = Arc::new(ExecState::new_with_resettable(false));
+                let waiters = (0..readers)
+                    .map(|_| {
+                        let input = input.clone();
+                        tokio::spawn(async move { input.wait_for_state().await.0.get_out() })
+                    })
+                    .collect::<Vec<_>>();
+                input.set_state(state());
+                for waiter in waiters {
+                    waiter.await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("read");
+    for readers in READERS {
+        let input = Arc::new(ExecState::new_with_resettable(false));
+        input.set_state(state());
+        group.bench_with_input(BenchmarkId::from_parameter(readers), &readers, |b, &readers| {
+            b.to_async(&rt).iter(|| read_concurrently(&input, readers))
+        });
+    }
+    group.finish();
+}
+
+async fn read_concurrently(input: &Arc<ExecState>, readers: usize) {
+    let handles = (0..readers)
+        .map(|_| {
+            let input = input.clone();
+            tokio::spawn(async move {
+                for _ in 0..READS {
+                    std::hint::black_box(input.get_state());
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+criterion_group!(benches, bench_exec_state);
+criterion_main!(benches);
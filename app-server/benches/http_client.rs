@@ -0,0 +1,56 @@
+//! Benchmarks of concurrent calls with the shared HTTP client
+//!
+//! Rounds of concurrent calls against a mock provider, with the shared client, whose later rounds
+//! reuse the pooled connections of the first one, and with a client per call, as nodes used to
+//! construct, which connects on every call. Connections per round are printed for both.
+//!
+//! Run with `cargo bench --features testing --bench http_client`
+
+use std::sync::atomic::Ordering;
+
+use app_server::{http_client::build_client, testing::mock_http_provider};
+use criterion::{criterion_group, criterion_main, Criterion};
+use tokio::runtime::Runtime;
+
+const CALLS: usize = 200;
+
+/// `CALLS` concurrent calls, with `client` or a client per call if it's unset
+async fn call_concurrently(client: Option<&reqwest::Client>, url: &str) {
+    let calls = (0..CALLS)
+        .map(|_| {
+            let client = client.cloned().unwrap_or_default();
+            let url = url.to_string();
+            tokio::spawn(async move { client.get(url).send().await?.bytes().await })
+        })
+        .collect::<Vec<_>>();
+    for call in calls {
+        call.await.unwrap().unwrap();
+    }
+}
+
+fn bench_calls(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let client = build_client();
+    let clients = [("shared_client", Some(&client)), ("client_per_call", None)];
+
+    let mut group = c.benchmark_group("concurrent_calls");
+    for (name, client) in clients {
+        let (url, connections) = rt.block_on(mock_http_provider());
+        for round in 1..=2 {
+            rt.block_on(call_concurrently(client, &url));
+            println!(
+                "{}: {} connections after round {}",
+                name,
+                connections.load(Ordering::SeqCst),
+                round
+            );
+        }
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| call_concurrently(client, &url))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_calls);
+criterion_main!(benches);
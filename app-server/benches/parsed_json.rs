@@ -0,0 +1,75 @@
+//! Benchmarks of nodes reading the JSON of the same message
+//!
+//! A chain of five nodes reads a field of a 2 MB document: each parsing the text of the message,
+//! sharing the parse of the message, and looking the field up by pointer.
+//!
+//! Run with `cargo bench --bench parsed_json`
+
+use std::sync::Arc;
+
+use app_server::pipeline::nodes::{Message, NodeInput};
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde_json::{json, Value};
+
+const READERS: usize = 5;
+
+/// About 2 MB of search results, as an LLM or API node would output
+fn large_document() -> String {
+    let results = (0..10_000)
+        .map(|i| {
+            json!({
+                "id": i,
+                "title": format!("Result {}", i),
+                "snippet": "lorem ipsum dolor sit amet ".repeat(6),
+                "tags": ["a", "b", "c"],
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({"results": results, "answer": {"text": "42", "score": 0.9}}).to_string()
+}
+
+fn message(document: &str) -> Arc<Message> {
+    Arc::new(Message {
+        value: NodeInput::String(document.to_string()),
+        ..Message::empty()
+    })
+}
+
+fn bench_json_readers(c: &mut Criterion) {
+    let document = large_document();
+
+    let mut group = c.benchmark_group("json_readers");
+    group.sample_size(20);
+    group.bench_function("reparsed", |b| {
+        b.iter(|| {
+            let message = message(&document);
+            for _ in 0..READERS {
+                let NodeInput::String(text) = &message.value else {
+                    unreachable!()
+                };
+                let value = serde_json::from_str::<Value>(text).unwrap();
+                assert_eq!(value["answer"]["text"], "42");
+            }
+        })
+    });
+    group.bench_function("shared", |b| {
+        b.iter(|| {
+            let message = message(&document);
+            for _ in 0..READERS {
+                assert_eq!(message.json().unwrap()["answer"]["text"], "42");
+            }
+        })
+    });
+    group.bench_function("pointer", |b| {
+        b.iter(|| {
+            let message = message(&document);
+            for _ in 0..READERS {
+                assert_eq!(message.json_pointer("/answer/text"), Some(json!("42")));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_json_readers);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+//! Benchmarks of preparing runs of pipeline graphs
+//!
+//! Runs of COMMIT versions are prepared from the cached plan of their graph, see
+//! `pipeline::compiled`, which is compared to deserializing the graph JSON and creating its tasks,
+//! as every run did before graphs were compiled. Saved graphs are validated with their nodes
+//! compiled in parallel, which is compared to compiling them one by one.
+//!
+//! Run with `cargo bench --features testing --bench pipeline`
+
+use app_server::{
+    pipeline::{
+        compiled::CompiledGraph,
+        nodes::Node,
+        utils::parse_graph,
+        validation::{check_topology, validate_graph},
+        Graph,
+    },
+    testing::{template_chain_graph, wide_graph},
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Templates of the 30-node pipeline, with its input and output
+const TEMPLATES: usize = 28;
+/// Nodes of the graph saved by the pipeline builder, half of them outputs
+const WIDE_NODES: usize = 400;
+
+fn bench_prepare_run(c: &mut Criterion) {
+    let value = template_chain_graph(TEMPLATES);
+    let graph = serde_json::from_value::<Graph>(value.clone()).unwrap();
+    let compiled = CompiledGraph::compile(&graph).unwrap();
+
+    let mut group = c.benchmark_group("prepare_run");
+    group.bench_function("parsed", |b| {
+        b.iter(|| {
+            let graph = serde_json::from_value::<Graph>(value.clone()).unwrap();
+            parse_graph(graph).unwrap()
+        })
+    });
+    group.bench_function("compiled", |b| {
+        b.iter(|| {
+            compiled.check_values(&graph.env, &graph.secrets).unwrap();
+            compiled.instantiate(&graph).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_validate(c: &mut Criterion) {
+    let graph = serde_json::from_value::<Graph>(wide_graph(WIDE_NODES)).unwrap();
+
+    let mut group = c.benchmark_group("validate");
+    group.bench_function("one_by_one", |b| {
+        b.iter(|| {
+            check_topology(&graph);
+            for node in graph.nodes.values() {
+                if let Node::StringTemplate(node) = node {
+                    let mut handlebars = handlebars::Handlebars::new();
+                    handlebars
+                        .register_template_string("template", &node.text)
+                        .unwrap();
+                } else if let Node::Extractor(node) = node {
+                    fancy_regex::Regex::new(&node.format).unwrap();
+                }
+            }
+        })
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| assert!(validate_graph(&graph).is_empty()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_prepare_run, bench_validate);
+criterion_main!(benches);
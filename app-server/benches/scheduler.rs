@@ -0,0 +1,167 @@
+//! Benchmarks of the engine scheduler
+//!
+//! Graphs of nodes doing no work, so that runs measure the engine itself: completing input
+//! states of successors, assembling inputs and spawning tasks. Allocations per run are printed
+//! for each graph, as they dominate the time of wide graphs.
+//!
+//! Run with `cargo bench --features testing --bench scheduler`
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use app_server::{
+    engine::Engine,
+    testing::{NoopBehavior, NoopGraph, OfflineServices},
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+
+/// Counts allocations to report them next to the timings
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const SIZES: [usize; 3] = [10, 100, 1000];
+/// Below the recursion depth limit of the engine
+const LOOP_ITERATIONS: f64 = 5.0;
+
+/// Input, `size` nodes one after another, output
+fn chain(size: usize) -> NoopGraph {
+    let mut graph = NoopGraph::default();
+    let mut prev = graph.node(NoopBehavior::Forward);
+    for _ in 0..size {
+        let node = graph.node(NoopBehavior::Forward);
+        graph.edge(prev, node);
+        prev = node;
+    }
+    let output = graph.node(NoopBehavior::Forward);
+    graph.edge(prev, output);
+    graph
+}
+
+/// Input followed by `size` outputs
+fn fan_out(size: usize) -> NoopGraph {
+    let mut graph = NoopGraph::default();
+    let input = graph.node(NoopBehavior::Forward);
+    for _ in 0..size {
+        let output = graph.node(NoopBehavior::Forward);
+        graph.edge(input, output);
+    }
+    graph
+}
+
+/// Input, `size` nodes in parallel, a node joining all of them, output
+fn diamond(size: usize) -> NoopGraph {
+    let mut graph = NoopGraph::default();
+    let input = graph.node(NoopBehavior::Forward);
+    let join = graph.node(NoopBehavior::Forward);
+    for _ in 0..size {
+        let node = graph.node(NoopBehavior::Forward);
+        graph.edge(input, node);
+        graph.edge(node, join);
+    }
+    let output = graph.node(NoopBehavior::Forward);
+    graph.edge(join, output);
+    graph
+}
+
+/// Loop with a body of `size` nodes, run `LOOP_ITERATIONS` times
+fn cycle(size: usize) -> NoopGraph {
+    let mut graph = NoopGraph::default();
+    let input = graph.node(NoopBehavior::Forward);
+    let head = graph.node(NoopBehavior::Count);
+    graph.cyclic_edge(input, head, "loop");
+    let mut prev = head;
+    for _ in 0..size {
+        let node = graph.node(NoopBehavior::Forward);
+        graph.edge(prev, node);
+        prev = node;
+    }
+    let again = graph.node(NoopBehavior::Below(LOOP_ITERATIONS));
+    graph.edge(prev, again);
+    graph.cyclic_edge(again, head, "loop");
+    let done = graph.node(NoopBehavior::AtLeast(LOOP_ITERATIONS));
+    graph.edge(prev, done);
+    let output = graph.node(NoopBehavior::Forward);
+    graph.edge(done, output);
+    graph
+}
+
+/// Builds a graph of the shape with the given number of nodes
+type Shape = fn(usize) -> NoopGraph;
+
+fn run_once(rt: &Runtime, services: &OfflineServices, graph: &NoopGraph) {
+    let mut engine =
+        Engine::with_tasks_and_context(graph.tasks(), services.context(), None, None, None);
+    rt.block_on(engine.run(None, None, vec![]))
+        .expect("Benchmark graph failed to run");
+}
+
+fn bench_scheduler(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let services = rt.block_on(async { OfflineServices::default() });
+    let shapes: [(&str, Shape); 4] = [
+        ("chain", chain),
+        ("fan_out", fan_out),
+        ("diamond", diamond),
+        ("cycle", cycle),
+    ];
+
+    for (name, shape) in shapes {
+        let mut group = c.benchmark_group(name);
+        for size in SIZES {
+            let graph = shape(size);
+
+            run_once(&rt, &services, &graph);
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            run_once(&rt, &services, &graph);
+            println!(
+                "{}/{}: {} allocations per run",
+                name,
+                size,
+                ALLOCATIONS.load(Ordering::Relaxed) - before
+            );
+
+            group.bench_with_input(BenchmarkId::from_parameter(size), &graph, |b, graph| {
+                b.to_async(&rt).iter_batched(
+                    || {
+                        Engine::with_tasks_and_context(
+                            graph.tasks(),
+                            services.context(),
+                            None,
+                            None,
+                            None,
+                        )
+                    },
+                    |mut engine| async move {
+                        engine
+                            .run(None, None, vec![])
+                            .await
+                            .expect("Benchmark graph failed to run")
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_scheduler);
+criterion_main!(benches);
@@ -8,6 +8,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .out_dir("./src/semantic_search/")
         .compile(&[proto_file], &["proto"])?;
 
+    tonic_build::configure()
+        .protoc_arg("--experimental_allow_proto3_optional") // for older systems
+        .build_client(true)
+        .build_server(true)
+        .out_dir("./src/grpc/")
+        .compile(&["./proto/pipeline_runner_grpc.proto"], &["proto"])?;
+
     tonic_build::configure()
         .protoc_arg("--experimental_allow_proto3_optional") // for older systems
         .build_client(false)
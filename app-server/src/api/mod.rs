@@ -1,2 +1,3 @@
+pub mod openapi;
 pub mod utils;
 pub mod v1;
@@ -0,0 +1,406 @@
+//! OpenAPI document of the API, generated from the annotations of the handlers
+//!
+//! Served at `/api/openapi.json`, and browsable with Swagger UI at `/api/swagger-ui/index.html`
+//! if `ENABLE_SWAGGER_UI` is set.
+
+use std::env;
+
+use actix_web::{get, web, HttpResponse};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::{Config, SwaggerUi};
+
+use crate::{
+    api, datasets,
+    db::{self, evaluations, events, pipelines, runs, trace},
+    files::attachment::FileAttachment,
+    language_model::{
+        ChatMessage, ChatMessageContent, ChatMessageContentPart, ChatMessageImage,
+        ChatMessageImageUrl, ChatMessageText,
+    },
+    pipeline::{nodes, runner::PipelineRunnerError, trace::RunTrace},
+    routes, webhooks,
+};
+
+const OPENAPI_JSON_PATH: &str = "/api/openapi.json";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Laminar API",
+        description = "`/v1` endpoints are authenticated with project API keys, \
+            `/api/v1/projects` endpoints with user API keys, \
+            `/api/v1/admin` endpoints with the shared secret."
+    ),
+    paths(
+        api::v1::pipelines::run_pipeline_graph,
+        api::v1::pipelines::ping_healthcheck,
+        api::v1::runs::submit_run,
+        api::v1::runs::get_run,
+        api::v1::runs::cancel_run,
+        api::v1::runs::get_node_io,
+        api::v1::runs::get_run_graph_dot,
+        api::v1::runs::replay_run,
+        api::v1::file_pipelines::get_file_pipelines,
+        api::v1::file_pipelines::run_file_pipeline,
+        api::v1::traces::process_traces,
+        api::v1::traces::get_events_for_session,
+        api::v1::traces::export_trace,
+        api::v1::metrics::process_metrics,
+        api::v1::evaluations::create_evaluation,
+        api::v1::evaluations::update_evaluation,
+        api::v1::evaluations::upload_evaluation_datapoints,
+        api::v1::evaluations::run_evaluation,
+        api::v1::evaluations::get_evaluation,
+        api::v1::evaluations::diff_evaluations,
+        api::v1::evaluations::compare_evaluations,
+        api::v1::evaluations::create_evaluation_backfill,
+        api::v1::evaluations::get_evaluation_backfill,
+        api::v1::evaluations::get_evaluation_backfill_scores,
+        routes::node_types::get_node_types,
+        routes::pipelines::get_pipelines,
+        routes::pipelines::create_pipeline,
+        routes::pipelines::get_pipeline_by_id,
+        routes::pipelines::update_pipeline,
+        routes::pipelines::delete_pipeline,
+        routes::pipelines::validate_pipeline_graph,
+        routes::pipelines::get_pipeline_graph_dot,
+        routes::pipelines::get_pipeline_diff,
+        routes::pipelines::get_pipeline_input_schema,
+        routes::pipelines::get_node_model_config,
+        routes::pipelines::simulate_pipeline,
+        routes::pipelines::get_node_routes,
+        routes::pipelines::get_pipeline_runs,
+        routes::webhooks::create_webhook,
+        routes::webhooks::get_webhooks,
+        routes::webhooks::enable_webhook,
+        routes::webhooks::delete_webhook,
+        routes::traces::get_traces,
+        routes::traces::get_single_trace,
+        routes::traces::delete_user_traces,
+        routes::traces::get_single_span,
+        routes::traces::get_traces_metrics,
+        routes::datasets::create_dataset,
+        routes::datasets::get_datasets,
+        routes::datasets::get_dataset,
+        routes::datasets::rename_dataset,
+        routes::datasets::delete_dataset,
+        routes::datasets::upload_datapoint_file,
+        routes::datasets::import_datapoints,
+        routes::datasets::create_datapoints,
+        routes::datasets::update_datapoint_data,
+        routes::datasets::delete_datapoints,
+        routes::datasets::delete_all_datapoints,
+        routes::datasets::get_datapoints,
+        routes::datasets::index_dataset,
+        routes::labeling_queues::create_queue,
+        routes::labeling_queues::get_queues,
+        routes::labeling_queues::lease_next_run,
+        routes::labeling_queues::submit_label,
+        routes::labeling_queues::get_labels,
+        routes::labeling_queues::export_labels,
+        routes::prompts::create_prompt_version,
+        routes::prompts::get_prompts,
+        routes::prompts::get_prompt,
+        routes::prompts::set_prompt_label,
+        routes::semantic_index::get_semantic_index,
+        routes::semantic_index::update_semantic_index,
+        routes::semantic_index::reindex_semantic_index,
+        routes::semantic_index::get_reindex_job,
+        routes::semantic_index::resume_reindex_job,
+        routes::dead_letters::get_dead_letter_stats,
+        routes::dead_letters::get_dead_letters,
+        routes::dead_letters::purge_dead_letters,
+        routes::dead_letters::get_dead_letter,
+        routes::dead_letters::retry_dead_letter,
+        routes::dead_letters::delete_dead_letter,
+    ),
+    components(schemas(
+        api::v1::pipelines::GraphRequest,
+        api::v1::pipelines::CurrentTraceAndSpan,
+        crate::runs::TraceMode,
+        api::v1::runs::ReplayRequest,
+        api::v1::file_pipelines::FilePipelineRunRequest,
+        api::v1::file_pipelines::FilePipelineRunOutput,
+        crate::pipeline::file_source::FilePipelineStatus,
+        api::v1::evaluations::EvaluationComparison,
+        nodes::NodeInput,
+        nodes::ConditionedValue,
+        nodes::GraphRunOutput,
+        nodes::GraphOutput,
+        nodes::StreamChunk,
+        nodes::NodeStreamChunk,
+        nodes::NodeStreamEnd,
+        nodes::BreakpointChunk,
+        nodes::RunEndpointEventError,
+        crate::engine::blocked::BlockedNode,
+        crate::engine::blocked::BlockedInput,
+        crate::engine::blocked::BlockReason,
+        crate::engine::blocked::SourceState,
+        PipelineRunnerError,
+        RunTrace,
+        crate::pipeline::validation::GraphDiagnostic,
+        crate::pipeline::diff::GraphDiff,
+        crate::pipeline::diff::NodeSummary,
+        crate::pipeline::diff::NodeChange,
+        crate::pipeline::diff::FieldChange,
+        crate::pipeline::diff::EdgeSummary,
+        crate::pipeline::inputs::InputError,
+        crate::pipeline::outputs::MissingOutputReason,
+        crate::pipeline::credentials::CredentialRequirement,
+        crate::pipeline::model_defaults::ModelDefaults,
+        crate::pipeline::model_defaults::ResolvedModelConfig,
+        crate::pipeline::model_defaults::ConfigLayer,
+        crate::pipeline::simulate::CompletionLengths,
+        crate::pipeline::simulate::Estimate,
+        crate::pipeline::simulate::NodeSimulation,
+        crate::pipeline::simulate::SimulationConfig,
+        crate::pipeline::simulate::SimulationReport,
+        crate::ch::routes::RouteBucket,
+        crate::ch::routes::RouteCount,
+        crate::ch::routes::RouteDistribution,
+        crate::ch::routes::RouteShift,
+        crate::ch::routes::RouteStats,
+        routes::node_types::NodeTypeSchema,
+        FileAttachment,
+        ChatMessage,
+        ChatMessageContent,
+        ChatMessageContentPart,
+        ChatMessageText,
+        ChatMessageImageUrl,
+        ChatMessageImage,
+        runs::Run,
+        runs::RunStatus,
+        runs::RunSummary,
+        crate::runs::node_io::NodeIoRecord,
+        crate::runs::node_io::RecordedInput,
+        crate::runs::node_io::RecordedOutput,
+        crate::runs::node_io::InputState,
+        crate::secrets::ScrubbedSecret,
+        evaluations::Evaluation,
+        evaluations::EvaluationStatus,
+        crate::evaluations::EvaluationConfig,
+        crate::evaluations::backfill::BackfillConfig,
+        crate::evaluations::backfill::SkipReason,
+        db::evaluation_backfills::EvaluationBackfill,
+        db::evaluation_backfills::BackfillRunScore,
+        crate::datasets::sampling::Sampling,
+        crate::evaluations::EvaluationStats,
+        crate::evaluations::EvaluatorStats,
+        crate::evaluations::RowDiff,
+        crate::evaluations::EvaluatorComparison,
+        crate::evaluations::RegressedRow,
+        crate::evaluations::stats::ScoreStats,
+        crate::evaluations::stats::HistogramBucket,
+        crate::evaluations::stats::PairedComparison,
+        crate::evaluations::stats::ConfidenceInterval,
+        crate::evaluations::evaluators::EvaluatorConfig,
+        crate::evaluations::evaluators::Evaluator,
+        events::EventWithTemplateName,
+        events::EventSource,
+        db::event_templates::EventType,
+        trace::Trace,
+        trace::TraceWithEvents,
+        trace::Span,
+        trace::SpanType,
+        routes::traces::TraceWithSpanPreviews,
+        routes::traces::SpanWithEvents,
+        datasets::Dataset,
+        datasets::datapoints::Datapoint,
+        datasets::schema::DatasetSchema,
+        datasets::schema::DatasetColumn,
+        datasets::schema::ColumnType,
+        datasets::import::ImportReport,
+        datasets::import::RowError,
+        pipelines::Pipeline,
+        pipelines::PipelineWithTargetVersion,
+        db::webhooks::WebhookInfo,
+        webhooks::WebhookEventType,
+        db::labeling_queues::LabelingQueue,
+        db::labeling_queues::RunLabel,
+        db::labeling_queues::RunLease,
+        routes::labeling_queues::LeasedRun,
+        routes::labeling_queues::LabelExport,
+        crate::labeling::QueueFilter,
+        crate::labeling::ScoreRange,
+        crate::labeling::ErrorKind,
+        crate::labeling::LabelSchema,
+        crate::labeling::LabelField,
+        crate::labeling::LabelKind,
+        db::prompts::Prompt,
+        db::prompts::PromptVersion,
+        db::prompts::PromptLabel,
+        routes::prompts::PromptWithVersions,
+        db::retention::RetentionPurge,
+        db::retention::PurgedTrace,
+        crate::retention::PurgeKind,
+        routes::semantic_index::SemanticIndex,
+        db::semantic_indexes::ReindexJob,
+        crate::semantic_search::index::IndexCollection,
+        crate::semantic_search::index::IndexConfig,
+        crate::semantic_search::index::ChunkConfig,
+        crate::semantic_search::index::EmbeddingModel,
+        db::dead_letters::DeadLetter,
+        db::dead_letters::DeadLetterInfo,
+        db::dead_letters::DeadLetterStats,
+        crate::dead_letters::DeadLetterKind,
+    )),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "runs", description = "Pipeline runs, with their streamed events"),
+        (name = "pipelines"),
+        (name = "webhooks", description = "Notifications of finished API runs"),
+        (name = "traces"),
+        (name = "evaluations"),
+        (name = "datasets"),
+        (name = "labeling", description = "Queues of runs for human review, and their labels"),
+        (name = "prompts", description = "Versioned prompts which nodes reference by name"),
+        (name = "admin", description = "Dead letters of span exports and run jobs"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        for (name, description) in [
+            ("project_api_key", "Project API key"),
+            ("user_api_key", "User API key"),
+            (
+                "shared_secret",
+                "Shared secret of the frontend and admin tools",
+            ),
+        ] {
+            components.add_security_scheme(
+                name,
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .description(Some(description))
+                        .build(),
+                ),
+            );
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref OPENAPI_JSON: String = ApiDoc::openapi()
+        .to_json()
+        .expect("Failed to serialize OpenAPI document");
+}
+
+#[get("/api/openapi.json")]
+async fn get_openapi_json() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(OPENAPI_JSON.as_str())
+}
+
+fn swagger_ui_enabled() -> bool {
+    env::var("ENABLE_SWAGGER_UI")
+        .ok()
+        .and_then(|enabled| enabled.parse::<bool>().ok())
+        .unwrap_or(false)
+}
+
+/// Serve the OpenAPI document, and Swagger UI if it's enabled
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_openapi_json);
+    if swagger_ui_enabled() {
+        cfg.service(
+            SwaggerUi::new("/api/swagger-ui/{_:.*}").config(Config::from(OPENAPI_JSON_PATH)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut HashSet<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(reference)) => {
+                            refs.insert(reference);
+                        }
+                        _ => collect_refs(value, refs),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| collect_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_openapi_document_is_valid() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+
+        let paths = doc["paths"].as_object().unwrap();
+        for path in [
+            "/v1/pipeline/run",
+            "/v1/runs",
+            "/v1/runs/{run_id}",
+            "/v1/traces",
+            "/api/v1/projects/{project_id}/traces",
+            "/api/v1/projects/{project_id}/datasets/{dataset_id}/datapoints",
+        ] {
+            assert!(paths.contains_key(path), "Missing path {path}");
+        }
+
+        // every referenced schema is a component
+        let mut refs = HashSet::new();
+        collect_refs(&doc, &mut refs);
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        for reference in refs {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("Unexpected reference {reference}"));
+            assert!(schemas.contains_key(name), "Missing schema {name}");
+        }
+
+        // every path parameter is declared, and every operation has a declared security scheme
+        let security_schemes = doc["components"]["securitySchemes"].as_object().unwrap();
+        for (path, item) in paths {
+            let path_params = path
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+                .collect::<Vec<_>>();
+            for (method, operation) in item.as_object().unwrap() {
+                let declared = operation["parameters"]
+                    .as_array()
+                    .map(|params| {
+                        params
+                            .iter()
+                            .filter(|param| param["in"] == "path")
+                            .filter_map(|param| param["name"].as_str())
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                for param in &path_params {
+                    assert!(
+                        declared.contains(param),
+                        "{method} {path} doesn't declare {param}"
+                    );
+                }
+                for requirement in operation["security"].as_array().unwrap() {
+                    for scheme in requirement.as_object().unwrap().keys() {
+                        assert!(security_schemes.contains_key(scheme));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use uuid::Uuid;
 
+use crate::db::api_keys::{ApiKeyScope, ProjectApiKey};
 use crate::db::pipelines::PipelineVersion;
 use crate::pipeline::utils::get_target_pipeline_version_cache_key;
 use crate::routes::error;
@@ -10,6 +11,19 @@ use crate::{
     db::{self, DB},
 };
 
+pub const PRODUCTION_PIPELINE_VERSION_ALIAS: &str = "production";
+
+pub fn require_api_key_scope(
+    project_api_key: &ProjectApiKey,
+    scope: ApiKeyScope,
+) -> Result<(), error::Error> {
+    if project_api_key.has_scope(scope) {
+        Ok(())
+    } else {
+        Err(error::Error::missing_api_key_scope(scope))
+    }
+}
+
 pub async fn query_target_pipeline_version(
     db: Arc<DB>,
     cache: Arc<Cache>,
@@ -37,3 +51,32 @@ pub async fn query_target_pipeline_version(
         }
     }
 }
+
+/// Resolve the pipeline version to run
+///
+/// Runs execute the version promoted to the `production` alias (target version),
+/// unless a COMMIT version is pinned by its id or content hash.
+pub async fn query_pipeline_version(
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    project_id: Uuid,
+    pipeline_name: String,
+    pipeline_version: Option<String>,
+) -> Result<Option<PipelineVersion>, error::Error> {
+    match pipeline_version.as_deref() {
+        None | Some(PRODUCTION_PIPELINE_VERSION_ALIAS) => {
+            query_target_pipeline_version(db, cache, project_id, pipeline_name).await
+        }
+        Some(version) => {
+            let pipeline_version =
+                db::pipelines::pipeline_version::get_commit_pipeline_version_by_pipeline_name(
+                    &db.pool,
+                    project_id,
+                    &pipeline_name,
+                    version,
+                )
+                .await?;
+            Ok(pipeline_version)
+        }
+    }
+}
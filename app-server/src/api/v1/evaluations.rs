@@ -1,53 +1,228 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use actix_web::{post, put, web, HttpResponse};
-use serde::Deserialize;
+use actix_web::{get, http::StatusCode, post, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
 
 use crate::{
-    db::{self, api_keys::ProjectApiKey, DB},
-    routes::types::ResponseResult,
+    api::utils::{
+        query_pipeline_version, require_api_key_scope, PRODUCTION_PIPELINE_VERSION_ALIAS,
+    },
+    auth::rate_limit::ApiKeyRateLimiter,
+    cache::Cache,
+    ch::{self, routes::RouteDistribution},
+    datasets::DatasetLimits,
+    db::{
+        self,
+        api_keys::{ApiKeyScope, ProjectApiKey},
+        evaluations::{Evaluation, EvaluationDatapointPreview, EvaluationStatus},
+        pipelines::PipelineVersion,
+        DB,
+    },
+    evaluations::{
+        self,
+        backfill::{self, BackfillConfig, BackfillContext},
+        compare_results, diff_results,
+        evaluators::{Evaluator, EvaluatorConfig, DEFAULT_THRESHOLD},
+        EvaluationConfig, EvaluationContext, EvaluationStats, EvaluatorComparison,
+    },
+    pipeline::{diff::GraphDiff, runner::PipelineRunner},
+    routes::{error::Error, types::ResponseResult},
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct CreateEvaluationRequest {
     name: String,
     #[serde(default)]
     metadata: Option<Value>,
+    /// Pipeline, dataset and evaluators of an evaluation run by the server with `run`,
+    /// the config of an existing evaluation is kept if not set
+    #[serde(default)]
+    config: Option<EvaluationConfig>,
+}
+
+/// Resolve the COMMIT version of the pipeline, which the API key must be allowed to run
+async fn resolve_pipeline_version(
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    project_api_key: &ProjectApiKey,
+    pipeline: &String,
+    pipeline_version: &Option<String>,
+) -> Result<PipelineVersion, Error> {
+    let version = query_pipeline_version(
+        db,
+        cache,
+        project_api_key.project_id,
+        pipeline.clone(),
+        pipeline_version.clone(),
+    )
+    .await?;
+    let Some(version) = version else {
+        return Err(match pipeline_version {
+            Some(version) if version != PRODUCTION_PIPELINE_VERSION_ALIAS => {
+                Error::no_pipeline_version(pipeline, version)
+            }
+            _ => Error::no_target_pipeline(pipeline),
+        });
+    };
+    if !project_api_key.can_run_pipeline(&version.pipeline_id) {
+        return Err(Error::pipeline_not_allowed(pipeline));
+    }
+    Ok(version)
+}
+
+/// Validate the config and resolve the versions of its pipelines
+async fn resolve_config(
+    mut config: EvaluationConfig,
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    project_api_key: &ProjectApiKey,
+) -> Result<EvaluationConfig, Error> {
+    if config.evaluators.is_empty() {
+        return Err(Error::invalid_request(Some(
+            "Evaluation must have at least one evaluator",
+        )));
+    }
+    db::datasets::get_dataset(&db.pool, project_api_key.project_id, config.dataset_id)
+        .await
+        .map_err(|_| Error::invalid_request(Some("Dataset not found")))?;
+    if let Some(sampling) = &mut config.sampling {
+        sampling
+            .validate(DatasetLimits::from_env().max_rows)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+        sampling.resolve_seed();
+    }
+
+    let version = resolve_pipeline_version(
+        db.clone(),
+        cache.clone(),
+        project_api_key,
+        &config.pipeline,
+        &config.pipeline_version,
+    )
+    .await?;
+    config.pipeline_version_id = Some(version.id);
+
+    for (name, version) in &config.prompt_versions {
+        let prompt = db::prompts::get_prompt(&db.pool, &project_api_key.project_id, name).await?;
+        if !prompt.is_some_and(|prompt| (1..=prompt.latest_version).contains(version)) {
+            return Err(Error::invalid_request(Some(&format!(
+                "Prompt {} has no version {}",
+                name, version
+            ))));
+        }
+    }
+
+    resolve_evaluators(&mut config.evaluators, db, cache, project_api_key).await?;
+    Ok(config)
+}
+
+/// Validate the evaluators and resolve the versions of their judge pipelines
+async fn resolve_evaluators(
+    evaluators: &mut [EvaluatorConfig],
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    project_api_key: &ProjectApiKey,
+) -> Result<(), Error> {
+    if evaluators.is_empty() {
+        return Err(Error::invalid_request(Some(
+            "At least one evaluator must be configured",
+        )));
+    }
+    for evaluator in evaluators {
+        evaluator
+            .validate()
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+        if let Evaluator::LlmJudge {
+            pipeline,
+            pipeline_version,
+            pipeline_version_id,
+        } = &mut evaluator.evaluator
+        {
+            let version = resolve_pipeline_version(
+                db.clone(),
+                cache.clone(),
+                project_api_key,
+                pipeline,
+                pipeline_version,
+            )
+            .await?;
+            *pipeline_version_id = Some(version.id);
+        }
+    }
+    Ok(())
 }
 
+/// Create an evaluation, or update the metadata of the evaluation with the same name
+///
+/// Evaluations with a config are run by the server, the results of others are uploaded with
+/// `evaluation-datapoints`.
+#[utoipa::path(
+    post,
+    path = "/v1/evaluations",
+    tag = "evaluations",
+    request_body(content = inline(CreateEvaluationRequest)),
+    responses((status = 200, body = Evaluation)),
+    security(("project_api_key" = [])),
+)]
 #[post("evaluations")]
 async fn create_evaluation(
     req: web::Json<CreateEvaluationRequest>,
     db: web::Data<DB>,
+    cache: web::Data<Cache>,
     project_api_key: ProjectApiKey,
 ) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
     let project_id = project_api_key.project_id;
+    let db = db.into_inner();
+    let req = req.into_inner();
+
+    let config = match req.config {
+        Some(config) => {
+            let config =
+                resolve_config(config, db.clone(), cache.into_inner(), &project_api_key).await?;
+            Some(serde_json::to_value(config).map_err(anyhow::Error::from)?)
+        }
+        None => None,
+    };
     let evaluation = db::evaluations::create_evaluation(
         &db.pool,
         &req.name,
-        db::evaluations::EvaluationStatus::Started,
+        EvaluationStatus::Started,
         project_id,
-        req.metadata.clone(),
+        req.metadata,
+        config,
     )
     .await?;
     Ok(HttpResponse::Ok().json(evaluation))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct UpdateEvaluationRequest {
     name: String,
-    status: db::evaluations::EvaluationStatus,
+    status: EvaluationStatus,
 }
 
+/// Update the status of the evaluation with the name
+#[utoipa::path(
+    put,
+    path = "/v1/evaluations",
+    tag = "evaluations",
+    request_body(content = inline(UpdateEvaluationRequest)),
+    responses((status = 200)),
+    security(("project_api_key" = [])),
+)]
 #[put("evaluations")]
 async fn update_evaluation(
     req: web::Json<UpdateEvaluationRequest>,
     db: web::Data<DB>,
     project_api_key: ProjectApiKey,
 ) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
     let project_id = project_api_key.project_id;
     let req = req.into_inner();
     db::evaluations::update_evaluation_status_by_name(&db.pool, req.name, project_id, req.status)
@@ -55,7 +230,7 @@ async fn update_evaluation(
     Ok(HttpResponse::Ok().json(()))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct RequestEvaluationDatapoint {
     data: Value,
@@ -66,19 +241,30 @@ struct RequestEvaluationDatapoint {
     scores: HashMap<String, f64>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct UploadEvaluationDatapointsRequest {
     name: String, // evaluation name
+    #[schema(inline)]
     points: Vec<RequestEvaluationDatapoint>,
 }
 
+/// Add results of datapoints to the evaluation with the name
+#[utoipa::path(
+    post,
+    path = "/v1/evaluation-datapoints",
+    tag = "evaluations",
+    request_body(content = inline(UploadEvaluationDatapointsRequest)),
+    responses((status = 200)),
+    security(("project_api_key" = [])),
+)]
 #[post("evaluation-datapoints")]
 async fn upload_evaluation_datapoints(
     req: web::Json<UploadEvaluationDatapointsRequest>,
     db: web::Data<DB>,
     project_api_key: ProjectApiKey,
 ) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
     let project_id = project_api_key.project_id;
     let evaluation_id = db::evaluations::get_evaluation_by_name(&db.pool, project_id, &req.name)
         .await?
@@ -141,3 +327,559 @@ async fn upload_evaluation_datapoints(
     .await?;
     Ok(HttpResponse::Ok().json(evaluation_datapoint))
 }
+
+/// Evaluation of the project, 404 if it's of another project
+async fn get_project_evaluation(
+    db: &DB,
+    project_api_key: &ProjectApiKey,
+    evaluation_id: Uuid,
+) -> Result<Option<Evaluation>, Error> {
+    let evaluation = db::evaluations::get_evaluation_by_id(&db.pool, evaluation_id).await?;
+    Ok(evaluation.filter(|evaluation| evaluation.project_id == project_api_key.project_id))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RunEvaluationRequest {
+    /// Env of the pipeline runs, e.g. provider API keys which aren't project secrets
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+/// Run the evaluation on the rows of its dataset, or its sample, replacing the results of the
+/// previous run
+///
+/// The evaluation runs in the background, poll `GET evaluations/{evaluation_id}` for its status
+/// and results.
+#[utoipa::path(
+    post,
+    path = "/v1/evaluations/{evaluation_id}/run",
+    tag = "evaluations",
+    params(("evaluation_id" = Uuid, Path, description = "Id of the evaluation")),
+    request_body(content = inline(RunEvaluationRequest)),
+    responses(
+        (status = 202, description = "Evaluation is started. Body is `{evaluationId, status}`"),
+        (status = 400, description = "Evaluation has no config, results of such evaluations are uploaded"),
+        (status = 404, description = "Evaluation not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("evaluations/{evaluation_id}/run")]
+async fn run_evaluation(
+    path: web::Path<Uuid>,
+    req: web::Json<RunEvaluationRequest>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    rate_limiter: web::Data<Arc<ApiKeyRateLimiter>>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let evaluation_id = path.into_inner();
+    let db = db.into_inner();
+
+    let Some(evaluation) = get_project_evaluation(&db, &project_api_key, evaluation_id).await?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(config) = evaluation.config else {
+        return Err(Error::invalid_request(Some(
+            "Evaluation has no config, it can't be run by the server",
+        )));
+    };
+    let config = serde_json::from_value::<EvaluationConfig>(config).map_err(anyhow::Error::from)?;
+
+    let context = EvaluationContext::new(
+        db.clone(),
+        pipeline_runner.as_ref().clone(),
+        rate_limiter.as_ref().clone(),
+        project_api_key,
+        evaluation_id,
+        config,
+        req.into_inner().env,
+    )
+    .await?;
+    db::evaluations::start_evaluation_run(&db.pool, evaluation_id).await?;
+    tokio::spawn(evaluations::run_evaluation(context, evaluation_id));
+
+    Ok(
+        HttpResponse::build(StatusCode::ACCEPTED).json(serde_json::json!({
+            "evaluationId": evaluation_id,
+            "status": EvaluationStatus::Started,
+        })),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct EvaluationResponse {
+    evaluation: Evaluation,
+    /// Stats of the last finished run, for evaluations run by the server
+    stats: Option<EvaluationStats>,
+    /// Results of rows, linked to the traces of their pipeline and judge runs
+    #[schema(value_type = Vec<Object>)]
+    results: Vec<EvaluationDatapointPreview>,
+}
+
+/// Get the evaluation with its stats and the results of its rows
+#[utoipa::path(
+    get,
+    path = "/v1/evaluations/{evaluation_id}",
+    tag = "evaluations",
+    params(("evaluation_id" = Uuid, Path, description = "Id of the evaluation")),
+    responses(
+        (status = 200, body = inline(EvaluationResponse)),
+        (status = 404, description = "Evaluation not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("evaluations/{evaluation_id}")]
+async fn get_evaluation(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    let evaluation_id = path.into_inner();
+    let Some(evaluation) = get_project_evaluation(&db, &project_api_key, evaluation_id).await?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let results = db::evaluations::get_evaluation_results(&db.pool, evaluation_id).await?;
+    let stats = evaluation
+        .stats
+        .clone()
+        .and_then(|stats| serde_json::from_value(stats).ok());
+
+    Ok(HttpResponse::Ok().json(EvaluationResponse {
+        evaluation,
+        stats,
+        results,
+    }))
+}
+
+fn check_same_dataset(evaluation: &Evaluation, baseline: &Evaluation) -> Result<(), Error> {
+    let dataset_id = |evaluation: &Evaluation| {
+        evaluation
+            .config
+            .as_ref()
+            .and_then(|config| config.get("datasetId").cloned())
+    };
+    if dataset_id(evaluation).is_none() || dataset_id(evaluation) != dataset_id(baseline) {
+        return Err(Error::invalid_request(Some(
+            "Only evaluations of the same dataset can be compared",
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct EvaluationDiffQuery {
+    /// Evaluation to compare against, of the same dataset
+    baseline_id: Uuid,
+}
+
+/// Rows whose scores changed from the baseline evaluation of the same dataset
+///
+/// Rows are matched by datapoint, rows evaluated by only one of the evaluations are included.
+#[utoipa::path(
+    get,
+    path = "/v1/evaluations/{evaluation_id}/diff",
+    tag = "evaluations",
+    params(
+        ("evaluation_id" = Uuid, Path, description = "Id of the evaluation"),
+        EvaluationDiffQuery,
+    ),
+    responses(
+        (status = 200, body = [RowDiff]),
+        (status = 400, description = "Evaluations are not of the same dataset"),
+        (status = 404, description = "Evaluation not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("evaluations/{evaluation_id}/diff")]
+async fn diff_evaluations(
+    path: web::Path<Uuid>,
+    query: web::Query<EvaluationDiffQuery>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    let evaluation_id = path.into_inner();
+    let baseline_id = query.baseline_id;
+
+    let evaluation = get_project_evaluation(&db, &project_api_key, evaluation_id).await?;
+    let baseline = get_project_evaluation(&db, &project_api_key, baseline_id).await?;
+    let (Some(evaluation), Some(baseline)) = (evaluation, baseline) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    check_same_dataset(&evaluation, &baseline)?;
+
+    let results = db::evaluations::get_evaluation_results(&db.pool, evaluation_id).await?;
+    let baseline_results = db::evaluations::get_evaluation_results(&db.pool, baseline_id).await?;
+
+    Ok(HttpResponse::Ok().json(diff_results(baseline_results, results)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct EvaluationCompareQuery {
+    /// Score at which rows pass each evaluator, the evaluators' thresholds in the config of the
+    /// evaluations if not set
+    #[serde(default)]
+    threshold: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationComparison {
+    baseline_id: Uuid,
+    evaluation_id: Uuid,
+    /// By evaluator name
+    #[schema(inline)]
+    evaluators: HashMap<String, EvaluatorComparison>,
+    /// Changes of the evaluated graph from the baseline's, if they ran different versions of the
+    /// same pipeline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    graph_diff: Option<GraphDiff>,
+    /// Executions of each route of the switch nodes in the rows' traces, to surface changes which
+    /// shift routing. Not set if the routes couldn't be counted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    route_distribution: Option<Vec<RouteDistribution>>,
+}
+
+/// Evaluators of the evaluation's config, empty for evaluations uploaded with their results
+fn config_evaluators(evaluation: &Evaluation) -> Vec<EvaluatorConfig> {
+    evaluation
+        .config
+        .as_ref()
+        .and_then(|config| config.get("evaluators").cloned())
+        .and_then(|evaluators| serde_json::from_value(evaluators).ok())
+        .unwrap_or_default()
+}
+
+/// Pipeline version of the evaluation's config, unset for evaluations uploaded with their results
+fn config_pipeline_version_id(evaluation: &Evaluation) -> Option<Uuid> {
+    evaluation
+        .config
+        .as_ref()
+        .and_then(|config| config.get("pipelineVersionId").cloned())
+        .and_then(|version_id| serde_json::from_value(version_id).ok())
+}
+
+/// Diff of the versions the evaluations ran, if they're different versions of the same pipeline
+async fn versions_diff(
+    db: &DB,
+    pipeline_runner: &PipelineRunner,
+    baseline: &Evaluation,
+    evaluation: &Evaluation,
+) -> Result<Option<GraphDiff>, Error> {
+    let (Some(from), Some(to)) = (
+        config_pipeline_version_id(baseline),
+        config_pipeline_version_id(evaluation),
+    ) else {
+        return Ok(None);
+    };
+    if from == to {
+        return Ok(None);
+    }
+    let from = db::pipelines::pipeline_version::get_pipeline_version(&db.pool, &from).await?;
+    let to = db::pipelines::pipeline_version::get_pipeline_version(&db.pool, &to).await?;
+    if from.pipeline_id != to.pipeline_id {
+        return Ok(None);
+    }
+    match pipeline_runner.diff_versions(&from, &to) {
+        Ok(diff) => Ok(Some(diff)),
+        Err(e) => {
+            log::warn!("Failed to diff versions {} and {}: {}", from.id, to.id, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Routes of the switch nodes in the executor traces of the evaluations' rows
+async fn route_distribution(
+    clickhouse: clickhouse::Client,
+    project_id: Uuid,
+    baseline_results: &[EvaluationDatapointPreview],
+    results: &[EvaluationDatapointPreview],
+) -> Option<Vec<RouteDistribution>> {
+    let trace_ids = |results: &[EvaluationDatapointPreview]| {
+        results
+            .iter()
+            .filter_map(|result| result.executor_trace_id)
+            .collect::<Vec<_>>()
+    };
+    let counts = futures::future::try_join(
+        ch::routes::get_trace_route_counts(
+            clickhouse.clone(),
+            project_id,
+            &trace_ids(baseline_results),
+        ),
+        ch::routes::get_trace_route_counts(clickhouse, project_id, &trace_ids(results)),
+    )
+    .await;
+    match counts {
+        Ok((baseline_counts, counts)) => {
+            Some(ch::routes::compare_route_counts(&baseline_counts, &counts))
+        }
+        Err(e) => {
+            log::warn!("Failed to count the routes of evaluations: {}", e);
+            None
+        }
+    }
+}
+
+/// Score statistics of two evaluations of the same dataset, and a paired comparison of the rows
+/// they both scored
+///
+/// For each evaluator, reports the rows which improved, regressed and didn't change from the
+/// baseline, a bootstrap 95% confidence interval of the mean score difference, and the regressed
+/// rows with their traces. Evaluations of different versions of the same pipeline also report
+/// the changes of the graph between the versions. The executions of each route of the switch
+/// nodes in the rows' traces are reported for both evaluations.
+#[utoipa::path(
+    get,
+    path = "/v1/evaluations/{baseline_id}/compare/{evaluation_id}",
+    tag = "evaluations",
+    params(
+        ("baseline_id" = Uuid, Path, description = "Id of the baseline evaluation"),
+        ("evaluation_id" = Uuid, Path, description = "Id of the evaluation"),
+        EvaluationCompareQuery,
+    ),
+    responses(
+        (status = 200, body = EvaluationComparison),
+        (status = 400, description = "Evaluations are not of the same dataset"),
+        (status = 404, description = "Evaluation not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("evaluations/{baseline_id}/compare/{evaluation_id}")]
+async fn compare_evaluations(
+    path: web::Path<(Uuid, Uuid)>,
+    query: web::Query<EvaluationCompareQuery>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    clickhouse: web::Data<clickhouse::Client>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    let (baseline_id, evaluation_id) = path.into_inner();
+
+    let evaluation = get_project_evaluation(&db, &project_api_key, evaluation_id).await?;
+    let baseline = get_project_evaluation(&db, &project_api_key, baseline_id).await?;
+    let (Some(evaluation), Some(baseline)) = (evaluation, baseline) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    check_same_dataset(&evaluation, &baseline)?;
+    if query
+        .threshold
+        .is_some_and(|threshold| !threshold.is_finite())
+    {
+        return Err(Error::invalid_request(Some("Threshold must be a number")));
+    }
+
+    let evaluators = config_evaluators(&evaluation)
+        .into_iter()
+        .chain(config_evaluators(&baseline))
+        .collect::<Vec<_>>();
+    let threshold = |name: &str| {
+        query.threshold.unwrap_or_else(|| {
+            evaluators
+                .iter()
+                .find(|evaluator| evaluator.name() == name)
+                .map(|evaluator| evaluator.threshold())
+                .unwrap_or(DEFAULT_THRESHOLD)
+        })
+    };
+
+    let results = db::evaluations::get_evaluation_results(&db.pool, evaluation_id).await?;
+    let baseline_results = db::evaluations::get_evaluation_results(&db.pool, baseline_id).await?;
+    let graph_diff = versions_diff(&db, &pipeline_runner, &baseline, &evaluation).await?;
+    let route_distribution = route_distribution(
+        clickhouse.as_ref().clone(),
+        project_api_key.project_id,
+        &baseline_results,
+        &results,
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(EvaluationComparison {
+        baseline_id,
+        evaluation_id,
+        evaluators: compare_results(&baseline_results, &results, threshold),
+        graph_diff,
+        route_distribution,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct CreateBackfillRequest {
+    config: BackfillConfig,
+    /// Env of the judge runs, e.g. provider API keys which aren't project secrets
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+/// Score the runs of a pipeline in a time range with evaluators, e.g. a newly added judge
+///
+/// Runs aren't executed again, their recorded node I/O is scored, so only runs of pipelines
+/// recording node I/O can be scored, others are skipped. The backfill runs in the background,
+/// poll `GET evaluation-backfills/{backfill_id}` for its progress.
+#[utoipa::path(
+    post,
+    path = "/v1/evaluation-backfills",
+    tag = "evaluations",
+    request_body(content = inline(CreateBackfillRequest)),
+    responses(
+        (status = 202, description = "Backfill is started", body = EvaluationBackfill),
+        (status = 400, description = "Config is invalid, or the pipeline doesn't exist"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("evaluation-backfills")]
+async fn create_evaluation_backfill(
+    req: web::Json<CreateBackfillRequest>,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    rate_limiter: web::Data<Arc<ApiKeyRateLimiter>>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let db = db.into_inner();
+    let CreateBackfillRequest { mut config, env } = req.into_inner();
+    if config.start_time >= config.end_time {
+        return Err(Error::invalid_request(Some(
+            "Start time must be before end time",
+        )));
+    }
+    let Some(pipeline) = db::pipelines::get_pipeline_by_name(
+        &db.pool,
+        &project_api_key.project_id,
+        &config.pipeline,
+    )
+    .await?
+    else {
+        return Err(Error::invalid_request(Some(&format!(
+            "Pipeline '{}' not found",
+            config.pipeline
+        ))));
+    };
+    if !project_api_key.can_run_pipeline(&pipeline.id) {
+        return Err(Error::pipeline_not_allowed(&config.pipeline));
+    }
+    resolve_evaluators(
+        &mut config.evaluators,
+        db.clone(),
+        cache.into_inner(),
+        &project_api_key,
+    )
+    .await?;
+
+    let backfill_id = Uuid::new_v4();
+    let project_id = project_api_key.project_id;
+    let context = BackfillContext::new(
+        db.clone(),
+        pipeline_runner.as_ref().clone(),
+        rate_limiter.as_ref().clone(),
+        project_api_key,
+        backfill_id,
+        config,
+        env,
+    )
+    .await?;
+    let backfill = db::evaluation_backfills::create_backfill(
+        &db.pool,
+        &backfill_id,
+        &project_id,
+        &pipeline.id,
+        &context.config,
+    )
+    .await?;
+    tokio::spawn(backfill::run_backfill(context, backfill.clone()));
+
+    Ok(HttpResponse::Accepted().json(backfill))
+}
+
+/// Get the status and progress of the backfill
+#[utoipa::path(
+    get,
+    path = "/v1/evaluation-backfills/{backfill_id}",
+    tag = "evaluations",
+    params(("backfill_id" = Uuid, Path, description = "Id of the backfill")),
+    responses(
+        (status = 200, body = EvaluationBackfill),
+        (status = 404, description = "Backfill not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("evaluation-backfills/{backfill_id}")]
+async fn get_evaluation_backfill(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    let backfill_id = path.into_inner();
+    match db::evaluation_backfills::get_backfill(
+        &db.pool,
+        &project_api_key.project_id,
+        &backfill_id,
+    )
+    .await?
+    {
+        Some(backfill) => Ok(HttpResponse::Ok().json(backfill)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+const DEFAULT_BACKFILL_SCORES_LIMIT: i64 = 100;
+const MAX_BACKFILL_SCORES_LIMIT: i64 = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct BackfillScoresQuery {
+    /// Number of runs, 100 by default and at most 1000
+    #[serde(default)]
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: Option<i64>,
+}
+
+/// Scores of the runs the backfill went through so far, linked to the runs, in order of the
+/// runs' creation
+#[utoipa::path(
+    get,
+    path = "/v1/evaluation-backfills/{backfill_id}/scores",
+    tag = "evaluations",
+    params(("backfill_id" = Uuid, Path, description = "Id of the backfill"), BackfillScoresQuery),
+    responses(
+        (status = 200, body = [BackfillRunScore]),
+        (status = 404, description = "Backfill not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("evaluation-backfills/{backfill_id}/scores")]
+async fn get_evaluation_backfill_scores(
+    path: web::Path<Uuid>,
+    query: web::Query<BackfillScoresQuery>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    let backfill_id = path.into_inner();
+    if db::evaluation_backfills::get_backfill(&db.pool, &project_api_key.project_id, &backfill_id)
+        .await?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_BACKFILL_SCORES_LIMIT)
+        .clamp(1, MAX_BACKFILL_SCORES_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let scores =
+        db::evaluation_backfills::get_run_scores(&db.pool, &backfill_id, limit, offset).await?;
+    Ok(HttpResponse::Ok().json(scores))
+}
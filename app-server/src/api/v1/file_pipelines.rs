@@ -0,0 +1,117 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    api::utils::require_api_key_scope,
+    db::{
+        api_keys::{ApiKeyScope, ProjectApiKey},
+        DB,
+    },
+    pipeline::{
+        file_source::FilePipelines,
+        nodes::{GraphOutput, NodeInput},
+        runner::PipelineRunner,
+        RunType,
+    },
+    routes::{
+        error::{self, pipeline_runner_to_http_error},
+        types::ResponseResult,
+    },
+    secrets,
+};
+
+/// Pipelines loaded from the files of `PIPELINES_DIR`, with the diagnostics of their last load
+#[utoipa::path(
+    get,
+    path = "/v1/file-pipelines",
+    tag = "pipelines",
+    responses(
+        (status = 200, description = "Statuses of the pipeline files", body = Vec<FilePipelineStatus>),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("file-pipelines")]
+async fn get_file_pipelines(
+    file_pipelines: web::Data<Arc<FilePipelines>>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    Ok(HttpResponse::Ok().json(file_pipelines.statuses()))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePipelineRunRequest {
+    #[serde(default)]
+    pub inputs: HashMap<String, NodeInput>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePipelineRunOutput {
+    pub outputs: BTreeMap<String, GraphOutput>,
+    /// Id of the run in logs, file pipeline runs aren't recorded
+    pub run_id: Uuid,
+}
+
+/// Run the last version of the pipeline file which compiled
+#[utoipa::path(
+    post,
+    path = "/v1/file-pipelines/{name}/run",
+    tag = "runs",
+    params(("name" = String, Path, description = "Name of the file without `.pipeline.json`")),
+    request_body = FilePipelineRunRequest,
+    responses(
+        (status = 200, description = "Outputs of the run", body = FilePipelineRunOutput),
+        (status = 400, description = "Pipeline not loaded, invalid request, or the run failed"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("file-pipelines/{name}/run")]
+async fn run_file_pipeline(
+    name: web::Path<String>,
+    req: web::Json<FilePipelineRunRequest>,
+    file_pipelines: web::Data<Arc<FilePipelines>>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let name = name.into_inner();
+    let req = req.into_inner();
+    let Some(pipeline) = file_pipelines.get(&name) else {
+        return Err(error::Error::no_target_pipeline(&name));
+    };
+
+    let run_id = Uuid::new_v4();
+    let mut env = req.env;
+    env.insert(
+        "collection_name".to_string(),
+        project_api_key.project_id.to_string(),
+    );
+    let mut graph = pipeline.graph.clone();
+    graph
+        .setup(&req.inputs, &env, &req.metadata, &RunType::Endpoint)
+        .map_err(|e| pipeline_runner_to_http_error(e.into(), run_id))?;
+    graph.secrets = secrets::get_project_secrets(&db.pool, &project_api_key.project_id).await?;
+
+    let output = pipeline_runner
+        .run_compiled(graph, pipeline.compiled.clone(), None)
+        .await
+        .map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
+    Ok(HttpResponse::Ok().json(FilePipelineRunOutput {
+        outputs: output.graph_outputs(),
+        run_id,
+    }))
+}
@@ -1,5 +1,13 @@
 use actix_web::{post, HttpResponse};
 
+#[utoipa::path(
+    post,
+    path = "/v1/metrics",
+    tag = "traces",
+    request_body(content = String, content_type = "application/x-protobuf", description = "Protobuf encoded OTLP export request"),
+    responses((status = 200, description = "Metrics are accepted, but not processed yet")),
+    security(("project_api_key" = [])),
+)]
 #[post("metrics")]
 pub async fn process_metrics() -> HttpResponse {
     // This is a placeholder that just returns ok, so that client otel exporters
@@ -1,4 +1,7 @@
 pub mod evaluations;
+pub mod file_pipelines;
 pub mod metrics;
+pub mod multipart;
 pub mod pipelines;
+pub mod runs;
 pub mod traces;
@@ -0,0 +1,249 @@
+use std::{collections::HashMap, env};
+
+use actix_multipart::{Field, Multipart};
+use actix_web::{dev::Payload, http::header::CONTENT_TYPE, web, FromRequest, HttpRequest};
+use futures_util::{future::LocalBoxFuture, StreamExt};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use super::pipelines::GraphRequest;
+use crate::{
+    files::attachment::{new_upload_path, FileAttachment},
+    pipeline::{nodes::NodeInput, utils::canonicalize_json},
+    routes::error,
+};
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+const DEFAULT_MAX_TOTAL_SIZE: u64 = 200 * 1024 * 1024;
+const DEFAULT_FILE_MIME_TYPE: &str = "application/octet-stream";
+/// Part with the JSON run request, all other parts are inputs named after their parts
+const REQUEST_PART_NAME: &str = "request";
+
+/// Run request, sent either as JSON or as multipart/form-data
+///
+/// Multipart requests have a `request` part with the same JSON as the JSON body, and any number
+/// of input parts. File parts, i.e. the ones with a filename, are streamed to disk and become
+/// `NodeInput::File` inputs. Other parts are parsed as JSON values of their inputs, or taken
+/// as strings if they are `text/plain`.
+pub struct RunRequest {
+    pub request: GraphRequest,
+    /// Hash of the request content, equal for retries of the same request. JSON is hashed
+    /// regardless of key order, and multipart requests regardless of their boundary.
+    pub content_hash: String,
+}
+
+struct UploadLimits {
+    max_file_size: u64,
+    max_total_size: u64,
+}
+
+fn env_size(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|size| size.parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
+impl UploadLimits {
+    fn from_env() -> Self {
+        Self {
+            max_file_size: env_size("RUN_UPLOAD_MAX_FILE_SIZE", DEFAULT_MAX_FILE_SIZE),
+            max_total_size: env_size("RUN_UPLOAD_MAX_TOTAL_SIZE", DEFAULT_MAX_TOTAL_SIZE),
+        }
+    }
+
+    fn check(&self, part_name: &str, part_size: u64, total_size: u64) -> Result<(), error::Error> {
+        if part_size > self.max_file_size {
+            return Err(error::Error::upload_too_large(&format!(
+                "Part '{part_name}' is larger than {} bytes",
+                self.max_file_size
+            )));
+        }
+        if total_size > self.max_total_size {
+            return Err(error::Error::upload_too_large(&format!(
+                "Request is larger than {} bytes",
+                self.max_total_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl FromRequest for RunRequest {
+    type Error = error::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let mime_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        match mime_type.as_str() {
+            "application/json" => {
+                let json = web::Json::<Value>::from_request(req, payload);
+                Box::pin(async move {
+                    let json = json
+                        .await
+                        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?
+                        .into_inner();
+                    let mut hasher = Sha256::new();
+                    hasher.update(canonicalize_json(&json).to_string().as_bytes());
+                    let request = serde_json::from_value::<GraphRequest>(json)
+                        .map_err(|e| error::Error::deserialization_error(Some(e)))?;
+                    Ok(RunRequest {
+                        request,
+                        content_hash: hex::encode(hasher.finalize()),
+                    })
+                })
+            }
+            "multipart/form-data" => {
+                let multipart = Multipart::new(req.headers(), payload.take());
+                Box::pin(read_multipart_run_request(
+                    multipart,
+                    UploadLimits::from_env(),
+                ))
+            }
+            _ => Box::pin(async move {
+                Err(error::Error::unsupported_content_type(
+                    &content_type,
+                    &["application/json", "multipart/form-data"],
+                ))
+            }),
+        }
+    }
+}
+
+async fn read_multipart_run_request(
+    mut payload: Multipart,
+    limits: UploadLimits,
+) -> Result<RunRequest, error::Error> {
+    let mut request = None;
+    let mut inputs = HashMap::new();
+    let mut total_size = 0;
+    let mut hasher = Sha256::new();
+
+    while let Some(field) = payload.next().await {
+        let mut field = field?;
+        let content_disposition = field.content_disposition();
+        let name = content_disposition
+            .get_name()
+            .ok_or_else(|| error::Error::invalid_request(Some("Multipart part has no name")))?
+            .to_string();
+        // This does not handle filename_ext ("filename*")
+        let filename = content_disposition.get_filename().map(String::from);
+        let mime_type = field
+            .content_type()
+            .map(|mime_type| mime_type.essence_str().to_string());
+        for header in [Some(&name), filename.as_ref(), mime_type.as_ref()] {
+            hasher.update(header.map(String::as_bytes).unwrap_or_default());
+            hasher.update([0]);
+        }
+
+        if let Some(filename) = filename {
+            let file = write_temp_file(
+                &mut field,
+                &name,
+                filename,
+                mime_type,
+                &limits,
+                &mut total_size,
+                &mut hasher,
+            )
+            .await?;
+            inputs.insert(name, NodeInput::File(file));
+            continue;
+        }
+
+        let mut value = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            total_size += chunk.len() as u64;
+            limits.check(&name, (value.len() + chunk.len()) as u64, total_size)?;
+            hasher.update(&chunk);
+            value.extend_from_slice(&chunk);
+        }
+
+        if name == REQUEST_PART_NAME {
+            request = Some(
+                serde_json::from_slice::<GraphRequest>(&value)
+                    .map_err(|e| error::Error::deserialization_error(Some(e)))?,
+            );
+            continue;
+        }
+        let input = match mime_type.as_deref() {
+            Some("application/json") => serde_json::from_slice::<NodeInput>(&value)
+                .map_err(|e| error::Error::deserialization_error(Some(e)))?,
+            Some("text/plain") => NodeInput::String(String::from_utf8_lossy(&value).to_string()),
+            // Form fields are sent without content type
+            None => serde_json::from_slice::<NodeInput>(&value)
+                .unwrap_or_else(|_| NodeInput::String(String::from_utf8_lossy(&value).to_string())),
+            Some(other) => {
+                return Err(error::Error::unsupported_content_type(
+                    &format!("{other} (part '{name}')"),
+                    &[
+                        "application/json",
+                        "text/plain",
+                        "a file part with filename",
+                    ],
+                ))
+            }
+        };
+        inputs.insert(name, input);
+    }
+
+    let Some(mut request) = request else {
+        return Err(error::Error::invalid_request(Some(
+            "Multipart run request must have a 'request' part with the run parameters",
+        )));
+    };
+    request.inputs.extend(inputs);
+
+    Ok(RunRequest {
+        request,
+        content_hash: hex::encode(hasher.finalize()),
+    })
+}
+
+async fn write_temp_file(
+    field: &mut Field,
+    name: &str,
+    filename: String,
+    mime_type: Option<String>,
+    limits: &UploadLimits,
+    total_size: &mut u64,
+    hasher: &mut Sha256,
+) -> Result<FileAttachment, error::Error> {
+    let path = new_upload_path();
+    let mut file = tokio::fs::File::create(&path)
+        .await
+        .map_err(anyhow::Error::from)?;
+    // The attachment owns the file from here on, so it's also removed if the upload fails
+    let mut attachment = FileAttachment::new(
+        filename,
+        mime_type.unwrap_or_else(|| DEFAULT_FILE_MIME_TYPE.to_string()),
+        0,
+        path,
+    );
+
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk?;
+        attachment.size += chunk.len() as u64;
+        *total_size += chunk.len() as u64;
+        limits.check(name, attachment.size, *total_size)?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(anyhow::Error::from)?;
+    }
+    file.flush().await.map_err(anyhow::Error::from)?;
+
+    Ok(attachment)
+}
@@ -1,136 +1,443 @@
 use std::{collections::HashMap, sync::Arc};
 
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
 use serde::Deserialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    api::utils::query_target_pipeline_version,
+    api::{
+        utils::{query_pipeline_version, require_api_key_scope, PRODUCTION_PIPELINE_VERSION_ALIAS},
+        v1::multipart::RunRequest,
+    },
+    auth::rate_limit::ApiKeyRateLimiter,
     cache::Cache,
-    db::{api_keys::ProjectApiKey, DB},
+    db::{
+        self,
+        api_keys::{ApiKeyScope, ProjectApiKey},
+        runs::{NewRun, RunStatus},
+        DB,
+    },
     pipeline::{
-        nodes::{GraphOutput, GraphRunOutput, NodeInput, RunEndpointEventError, StreamChunk},
+        credentials::Credentials,
+        nodes::{GraphRunOutput, NodeInput, RunEndpointEventError, StreamChunk},
         runner::{PipelineRunner, PipelineRunnerError},
-        Graph, RunType,
     },
     routes::{
         error::{self, pipeline_runner_to_http_error},
         types::ResponseResult,
     },
+    runs::{
+        execute_run,
+        idempotency::{release_on_error, IdempotencyKey},
+        queue::{self, RunEnd, RunEvent, RunExecution, RunJob, RunQueue},
+        setup_graph, InterruptSenders, PreparedRun, TraceMode,
+    },
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct CurrentTraceAndSpan {
-    trace_id: Uuid,
+pub struct CurrentTraceAndSpan {
+    pub trace_id: Uuid,
     #[serde(default)]
-    parent_span_id: Option<Uuid>,
+    pub parent_span_id: Option<Uuid>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GraphRequest {
+pub struct GraphRequest {
     /// Name of the pipeline to run
-    pipeline: String,
-    inputs: HashMap<String, NodeInput>,
+    pub pipeline: String,
+    /// Id or content hash of a COMMIT version to run, or `production` alias.
+    /// If None, the version promoted to `production` is run.
+    #[serde(default)]
+    pub pipeline_version: Option<String>,
+    /// Multipart requests add inputs from the other parts, see `RunRequest`
+    #[serde(default)]
+    pub inputs: HashMap<String, NodeInput>,
     /// If None, new trace will be generated
     #[serde(default, flatten)]
-    current_trace_and_span: Option<CurrentTraceAndSpan>,
-    env: HashMap<String, String>,
+    pub current_trace_and_span: Option<CurrentTraceAndSpan>,
+    pub env: HashMap<String, String>,
+    /// Provider API keys by the aliases which LLM nodes reference as their `credential`. Aliases
+    /// which aren't set here are taken from the project secrets. Runs with credentials are
+    /// executed by the instance which receives them, and the keys are never stored.
     #[serde(default)]
-    metadata: HashMap<String, String>,
+    #[schema(value_type = HashMap<String, String>)]
+    pub credentials: Credentials,
     #[serde(default)]
-    stream: bool,
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub stream: bool,
+    /// Alternative to the `Idempotency-Key` header, see `IdempotencyKey`
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Record inputs and outputs of the run's nodes, by default if the pipeline has
+    /// `recordNodeIo` set. See `GET runs/{run_id}/nodes/{node_id}/io`.
+    #[serde(default)]
+    pub record_node_io: Option<bool>,
+    /// Persist the trace before responding, so that it can be queried right away, see
+    /// `TraceMode`
+    #[serde(default)]
+    pub trace_mode: TraceMode,
 }
 
-#[post("pipeline/run")]
-async fn run_pipeline_graph(
-    pipeline_runner: web::Data<Arc<PipelineRunner>>,
-    params: web::Json<GraphRequest>,
-    db: web::Data<DB>,
-    project_api_key: ProjectApiKey,
-    cache: web::Data<Cache>,
-) -> ResponseResult {
-    let req = params.into_inner();
-    let db = db.into_inner();
-    let cache = cache.into_inner();
+/// Resolve the pipeline version and set up its graph, everything that can fail before the run starts
+///
+/// Fails if the workspace of the key is already executing its maximum of concurrent runs.
+pub async fn prepare_run(
+    req: GraphRequest,
+    pipeline_runner: &PipelineRunner,
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    rate_limiter: &ApiKeyRateLimiter,
+    project_api_key: &ProjectApiKey,
+) -> Result<PreparedRun, error::Error> {
     let project_id = project_api_key.project_id;
     let inputs = req.inputs;
     let mut env = req.env;
     let metadata = req.metadata;
+    let credentials = req.credentials;
+    let trace_mode = req.trace_mode;
     let parent_span_id = req
         .current_trace_and_span
         .as_ref()
         .and_then(|t| t.parent_span_id);
-    let trace_id = req.current_trace_and_span.map(|t| t.trace_id);
+    let trace_id = req
+        .current_trace_and_span
+        .map(|t| t.trace_id)
+        .unwrap_or_else(Uuid::new_v4);
     env.insert("collection_name".to_string(), project_id.to_string());
 
-    let pipeline_version =
-        query_target_pipeline_version(db.clone(), cache.clone(), project_id, req.pipeline.clone())
-            .await?;
+    let pipeline_version = query_pipeline_version(
+        db.clone(),
+        cache.clone(),
+        project_id,
+        req.pipeline.clone(),
+        req.pipeline_version.clone(),
+    )
+    .await?;
 
     let Some(pipeline_version) = pipeline_version else {
-        return Err(error::Error::no_target_pipeline(&req.pipeline));
+        return Err(match &req.pipeline_version {
+            Some(version) if version != PRODUCTION_PIPELINE_VERSION_ALIAS => {
+                error::Error::no_pipeline_version(&req.pipeline, version)
+            }
+            _ => error::Error::no_target_pipeline(&req.pipeline),
+        });
+    };
+    if !project_api_key.can_run_pipeline(&pipeline_version.pipeline_id) {
+        return Err(error::Error::pipeline_not_allowed(&req.pipeline));
+    }
+    rate_limiter.check_run(project_api_key).map_err(|e| {
+        error::Error::limit_error(&format!(
+            "Workspace is already executing its maximum of {} concurrent runs",
+            e.limit
+        ))
+    })?;
+
+    let record_node_io = match req.record_node_io {
+        Some(record_node_io) => record_node_io,
+        None => {
+            db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_version.pipeline_id)
+                .await?
+                .record_node_io
+        }
     };
-    let pipeline_version_name = pipeline_version.name.clone();
 
     let run_id = Uuid::new_v4(); // used to uniquely identify the related log or run trace
-    let run_type = RunType::Endpoint;
-    let mut graph = serde_json::from_value::<Graph>(pipeline_version.runnable_graph)
-        .map_err(|e| error::Error::deserialization_error(Some(e)))?;
-    graph
-        .setup(&inputs, &env, &metadata, &run_type)
-        .map_err(error::graph_error_to_http_error)?;
-
-    if req.stream {
+    let mut graph = setup_graph(
+        pipeline_runner,
+        &db,
+        &pipeline_version,
+        &inputs,
+        &env,
+        &metadata,
+        &project_id,
+    )
+    .await
+    .map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
+    graph.record_node_io = record_node_io;
+    graph.credentials = credentials;
+    // with the resolved prompt versions, which queued runs are set up with too
+    let metadata = graph.metadata.clone();
+
+    Ok(PreparedRun {
+        run_id,
+        project_id,
+        pipeline_version,
+        secrets: graph.secrets.clone(),
+        graph,
+        inputs,
+        env,
+        metadata,
+        parent_span_id,
+        trace_id,
+        replay: None,
+        trace_mode,
+        leased_by: None,
+        checkpoints: None,
+    })
+}
+
+pub async fn create_run(db: &DB, run: &PreparedRun, status: RunStatus) -> Result<(), error::Error> {
+    db::runs::create_run(
+        &db.pool,
+        &NewRun {
+            id: run.run_id,
+            project_id: run.project_id,
+            pipeline_id: run.pipeline_version.pipeline_id,
+            pipeline_version_id: run.pipeline_version.id,
+            status,
+            trace_id: run.trace_id,
+            metadata: &serde_json::to_value(&run.metadata).unwrap_or_default(),
+            replay_of: run.replay.as_ref().map(|plan| plan.replay_of),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Push the run to the run queue, and respond with its events relayed from the worker
+///
+/// Streamed runs respond with the same events, and sync runs with the same response, as if
+/// they were executed locally.
+pub async fn relay_queued_run(
+    queue: &dyn RunQueue,
+    run: PreparedRun,
+    project_api_key: &ProjectApiKey,
+    stream: bool,
+    db: Arc<DB>,
+    http_client: &reqwest::Client,
+    idempotency_key: Option<IdempotencyKey>,
+) -> ResponseResult {
+    let run_id = run.run_id;
+    let job = RunJob::new(&run, project_api_key, stream).map_err(error::Error::from);
+    let job = release_on_error(idempotency_key.as_ref(), &db, job).await?;
+    // Subscribed before the job is pushed, so that no events are missed
+    let events = queue.events(&run_id).await.map_err(error::Error::from);
+    let mut events = release_on_error(idempotency_key.as_ref(), &db, events).await?;
+
+    let created = create_run(&db, &run, RunStatus::Queued).await;
+    release_on_error(idempotency_key.as_ref(), &db, created).await?;
+    if let Some(idempotency_key) = &idempotency_key {
+        idempotency_key.set_run(&db, &run_id).await;
+    }
+    let submitted = queue::submit_job(queue, &db, http_client, &job)
+        .await
+        .map_err(error::Error::from);
+    release_on_error(idempotency_key.as_ref(), &db, submitted).await?;
+
+    if stream {
+        let stream = async_stream::stream! {
+            while let Some(event) = events.next().await {
+                match event {
+                    RunEvent::Chunk(chunk) => {
+                        yield Ok::<_, actix_web::Error>(format!("data: {}\n\n", chunk).into_bytes().into());
+                    }
+                    RunEvent::Finished(end) => {
+                        complete_queued_run(idempotency_key.as_ref(), &db, &end, true).await;
+                        yield Ok(format!("data: {}\n\n", end.chunk).into_bytes().into());
+                        break;
+                    }
+                }
+            }
+        };
+
+        return Ok(HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .streaming(stream));
+    }
+
+    let end = loop {
+        match events.next().await {
+            Some(RunEvent::Finished(end)) => break end,
+            Some(RunEvent::Chunk(_)) => {}
+            None => {
+                if let Some(idempotency_key) = &idempotency_key {
+                    idempotency_key.release(&db).await;
+                }
+                return Err(
+                    anyhow::anyhow!("Lost events of run {} from the run queue", run_id).into(),
+                );
+            }
+        }
+    };
+    complete_queued_run(idempotency_key.as_ref(), &db, &end, false).await;
+
+    let status = StatusCode::from_u16(end.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(match end.response {
+        Some(response) => HttpResponse::build(status).json(response),
+        None => HttpResponse::build(status).finish(),
+    })
+}
+
+/// Store the response of the successful run for the idempotency key, or release it
+async fn complete_queued_run(
+    idempotency_key: Option<&IdempotencyKey>,
+    db: &DB,
+    end: &RunEnd,
+    stream: bool,
+) {
+    let Some(idempotency_key) = idempotency_key else {
+        return;
+    };
+    match (end.status, &end.response) {
+        (200, _) if stream => {
+            idempotency_key
+                .complete(db, StatusCode::OK, &end.chunk)
+                .await
+        }
+        (200, Some(response)) => idempotency_key.complete(db, StatusCode::OK, response).await,
+        _ => idempotency_key.release(db).await,
+    }
+}
+
+/// Run a pipeline, accepts JSON or multipart/form-data with file inputs
+///
+/// Retries with the same `Idempotency-Key` get the response of the first request, see
+/// `IdempotencyKey`.
+#[utoipa::path(
+    post,
+    path = "/v1/pipeline/run",
+    tag = "runs",
+    request_body(
+        content = GraphRequest,
+        description = "JSON, or multipart/form-data with the JSON in a `request` part and an input per other part",
+    ),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the response of the first request"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Outputs of the run, or node chunks followed by the outputs as server-sent events if `stream` is set",
+            content(("application/json" = GraphRunOutput), ("text/event-stream" = StreamChunk)),
+        ),
+        (status = 400, description = "Invalid request, or the run failed"),
+        (status = 409, description = "Idempotency key was used for a different request"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("pipeline/run")]
+#[allow(clippy::too_many_arguments)]
+async fn run_pipeline_graph(
+    request: HttpRequest,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    params: RunRequest,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+    cache: web::Data<Cache>,
+    rate_limiter: web::Data<Arc<ApiKeyRateLimiter>>,
+    interrupt_senders: web::Data<Arc<InterruptSenders>>,
+    run_execution: web::Data<RunExecution>,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let RunRequest {
+        request: mut req,
+        content_hash,
+    } = params;
+    let stream = req.stream;
+    let pipeline_runner = pipeline_runner.into_inner();
+    let rate_limiter = rate_limiter.into_inner();
+    let interrupt_senders = interrupt_senders.into_inner();
+    let db = db.into_inner();
+    let cache = cache.into_inner();
+
+    let idempotency_key = IdempotencyKey::from_request(
+        &request,
+        req.idempotency_key.take(),
+        project_api_key.id,
+        &content_hash,
+    )?;
+    if let Some(idempotency_key) = &idempotency_key {
+        if let Some(res) = idempotency_key.claim(&db).await?.replay_response(stream)? {
+            return Ok(res);
+        }
+    }
+
+    let run = prepare_run(
+        req,
+        &pipeline_runner,
+        db.clone(),
+        cache,
+        &rate_limiter,
+        &project_api_key,
+    )
+    .await;
+    let run = release_on_error(idempotency_key.as_ref(), &db, run).await?;
+    if let Some(queue) = run_execution.queue_for(&run) {
+        return relay_queued_run(
+            queue,
+            run,
+            &project_api_key,
+            stream,
+            db,
+            pipeline_runner.http_client(),
+            idempotency_key,
+        )
+        .await;
+    }
+    let created = create_run(&db, &run, RunStatus::Running).await;
+    release_on_error(idempotency_key.as_ref(), &db, created).await?;
+    let run_id = run.run_id;
+    let pipeline_version_id = run.pipeline_version.id;
+    let pipeline_version_hash = run.pipeline_version.content_hash.clone();
+    if let Some(idempotency_key) = &idempotency_key {
+        idempotency_key.set_run(&db, &run_id).await;
+    }
+
+    if stream {
         let stream = async_stream::stream! {
 
             let (tx, mut rx) = tokio::sync::mpsc::channel::<StreamChunk>(8);
 
             tokio::spawn(async move {
-                let run_result = pipeline_runner.run(graph, Some(tx.clone())).await;
-                // write the trace
-                pipeline_runner.record_observations(
-                    &run_result,
-                    &project_id,
-                    &pipeline_version_name,
-                    parent_span_id,
-                    trace_id
+                let run_result = execute_run(
+                    run,
+                    Some(tx.clone()),
+                    &pipeline_runner,
+                    &db,
+                    &rate_limiter,
+                    &project_api_key,
+                    &interrupt_senders,
                 )
-                .await
-                .expect("Failed to record observations from pipeline output");
-
+                .await;
 
                 // communicate the end result to the client
                 match run_result {
                     Ok(outputs) => {
-                        let outputs = outputs
-                            .output_values()
-                            .into_iter()
-                            .map(|(node_name, value)| {
-                            (
-                                node_name,
-                                GraphOutput {
-                                    value,
-                                },
-                            )
-                            })
-                            .collect();
-                        let output_chunk = StreamChunk::GraphRunOutput(GraphRunOutput { outputs, run_id });
+                        let output_chunk = StreamChunk::GraphRunOutput(GraphRunOutput {
+                            outputs: outputs.graph_outputs(),
+                            run_id,
+                            pipeline_version_id,
+                            pipeline_version_hash,
+                        });
+                        if let Some(idempotency_key) = &idempotency_key {
+                            let response = serde_json::to_value(&output_chunk).unwrap_or_default();
+                            idempotency_key.complete(&db, StatusCode::OK, &response).await;
+                        }
 
                         let _ = tx.send(output_chunk).await;
                     }
                     Err(error) => {
+                        if let Some(idempotency_key) = &idempotency_key {
+                            idempotency_key.release(&db).await;
+                        }
                         let run_id: Option<Uuid> = match error {
                             PipelineRunnerError::RunningError(_) => Some(run_id),
                             PipelineRunnerError::GraphError(_)
                             | PipelineRunnerError::DeserializationError(_)
                             | PipelineRunnerError::MissingEnvVarsError(_)
+                            | PipelineRunnerError::MissingSecretsError(_)
+                            | PipelineRunnerError::MissingCredentialsError(_)
                             | PipelineRunnerError::TraceWritingError(_)
                             | PipelineRunnerError::UnhandledError(_)
                             | PipelineRunnerError::InvalidSchemasError(_) => None,
                         };
-                        let chunk = StreamChunk::RunEndpointEventError(RunEndpointEventError {error, run_id});
+                        let chunk = StreamChunk::RunEndpointEventError(RunEndpointEventError::new(error, run_id));
 
                         let _ = tx.send(chunk).await;
                     }
@@ -150,30 +457,44 @@ async fn run_pipeline_graph(
             .content_type("text/event-stream")
             .streaming(stream))
     } else {
-        let run_result = pipeline_runner.run(graph, None).await;
-
-        pipeline_runner
-            .record_observations(
-                &run_result,
-                &project_id,
-                &pipeline_version.name,
-                parent_span_id,
-                trace_id,
-            )
-            .await?;
-
-        let run_result = run_result.map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
-        let outputs = run_result
-            .output_values()
-            .into_iter()
-            .map(|(node_name, value)| (node_name, GraphOutput { value }))
-            .collect();
-        let res = GraphRunOutput { outputs, run_id };
+        let run_result = execute_run(
+            run,
+            None,
+            &pipeline_runner,
+            &db,
+            &rate_limiter,
+            &project_api_key,
+            &interrupt_senders,
+        )
+        .await;
+
+        let run_result = release_on_error(idempotency_key.as_ref(), &db, run_result)
+            .await
+            .map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
+        let res = GraphRunOutput {
+            outputs: run_result.graph_outputs(),
+            run_id,
+            pipeline_version_id,
+            pipeline_version_hash,
+        };
+        if let Some(idempotency_key) = &idempotency_key {
+            let response = serde_json::to_value(&res).unwrap_or_default();
+            idempotency_key
+                .complete(&db, StatusCode::OK, &response)
+                .await;
+        }
 
         Ok(HttpResponse::Ok().json(res))
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/healthcheck",
+    tag = "pipelines",
+    responses((status = 200, description = "API key is valid")),
+    security(("project_api_key" = [])),
+)]
 #[get("healthcheck")]
 async fn ping_healthcheck() -> ResponseResult {
     Ok(HttpResponse::Ok().finish())
@@ -0,0 +1,609 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        utils::require_api_key_scope,
+        v1::{
+            multipart::RunRequest,
+            pipelines::{create_run, prepare_run, relay_queued_run},
+        },
+    },
+    auth::rate_limit::ApiKeyRateLimiter,
+    cache::Cache,
+    db::{
+        self,
+        api_keys::{ApiKeyScope, ProjectApiKey},
+        runs::{Run, RunStatus},
+        DB,
+    },
+    pipeline::{
+        nodes::GraphRunOutput,
+        prompts,
+        runner::{PipelineRunner, PipelineRunnerError},
+        trace::NodeRunStats,
+        utils::parse_graph,
+        RunType,
+    },
+    routes::{
+        error::{self, pipeline_runner_to_http_error},
+        types::ResponseResult,
+    },
+    runs::{
+        self, execute_run,
+        idempotency::{release_on_error, IdempotencyKey},
+        node_io::NodeIoRecord,
+        queue::{self, RunExecution, RunJob},
+        replay, EngineStats, InterruptSenders, PreparedRun,
+    },
+    secrets,
+};
+
+#[derive(Deserialize, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum RunMode {
+    #[default]
+    Sync,
+    Async,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SubmitRunQuery {
+    #[serde(default)]
+    #[param(inline)]
+    mode: RunMode,
+}
+
+/// Submit a pipeline run
+///
+/// In sync mode, the run is executed and its outputs are returned, same as `pipeline/run`.
+/// In async mode, the request is validated, the run is queued and 202 is returned with its id,
+/// while the run is executed in the background, or by a worker in queue mode. Its status and
+/// result can be polled with `GET runs/{run_id}`. Streaming is not supported here, use
+/// `pipeline/run` for it.
+///
+/// Like `pipeline/run`, accepts JSON or multipart/form-data with file inputs, and an
+/// `Idempotency-Key`. Retries of async submissions get the id of the first run.
+#[utoipa::path(
+    post,
+    path = "/v1/runs",
+    tag = "runs",
+    request_body(
+        content = GraphRequest,
+        description = "JSON, or multipart/form-data with the JSON in a `request` part and an input per other part",
+    ),
+    params(
+        SubmitRunQuery,
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the response of the first request"),
+    ),
+    responses(
+        (status = 200, description = "Outputs of the run, in sync mode", body = GraphRunOutput),
+        (status = 202, description = "Run is queued, in async mode. Body is `{runId, status}`"),
+        (status = 400, description = "Invalid request, or the run failed"),
+        (status = 409, description = "Idempotency key was used for a different request"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("runs")]
+#[allow(clippy::too_many_arguments)]
+async fn submit_run(
+    request: HttpRequest,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    params: RunRequest,
+    query: web::Query<SubmitRunQuery>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+    cache: web::Data<Cache>,
+    rate_limiter: web::Data<Arc<ApiKeyRateLimiter>>,
+    interrupt_senders: web::Data<Arc<InterruptSenders>>,
+    engine_stats: web::Data<Arc<EngineStats>>,
+    run_execution: web::Data<RunExecution>,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let pipeline_runner = pipeline_runner.into_inner();
+    let rate_limiter = rate_limiter.into_inner();
+    let interrupt_senders = interrupt_senders.into_inner();
+    let db = db.into_inner();
+
+    let RunRequest {
+        request: mut req,
+        content_hash,
+    } = params;
+    let idempotency_key = IdempotencyKey::from_request(
+        &request,
+        req.idempotency_key.take(),
+        project_api_key.id,
+        &content_hash,
+    )?;
+    if let Some(idempotency_key) = &idempotency_key {
+        if let Some(res) = idempotency_key.claim(&db).await?.replay_response(false)? {
+            return Ok(res);
+        }
+    }
+
+    let run = prepare_run(
+        req,
+        &pipeline_runner,
+        db.clone(),
+        cache.into_inner(),
+        &rate_limiter,
+        &project_api_key,
+    )
+    .await;
+    let run = release_on_error(idempotency_key.as_ref(), &db, run).await?;
+    let run_id = run.run_id;
+    let pipeline_version_id = run.pipeline_version.id;
+    let pipeline_version_hash = run.pipeline_version.content_hash.clone();
+
+    if query.mode == RunMode::Async {
+        let checked = pipeline_runner
+            .check_graph_values(&run.graph)
+            .map_err(|e| pipeline_runner_to_http_error(e, run_id));
+        release_on_error(idempotency_key.as_ref(), &db, checked).await?;
+
+        let queue = run_execution.queue_for(&run);
+        let job = queue
+            .map(|_| RunJob::new(&run, &project_api_key, false))
+            .transpose()
+            .map_err(error::Error::from);
+        let job = release_on_error(idempotency_key.as_ref(), &db, job).await?;
+
+        let created = create_run(&db, &run, RunStatus::Queued).await;
+        release_on_error(idempotency_key.as_ref(), &db, created).await?;
+        if let (Some(queue), Some(job)) = (queue, &job) {
+            let submitted = queue::submit_job(queue, &db, pipeline_runner.http_client(), job)
+                .await
+                .map_err(error::Error::from);
+            release_on_error(idempotency_key.as_ref(), &db, submitted).await?;
+        }
+        let response = serde_json::json!({
+            "runId": run_id,
+            "status": RunStatus::Queued,
+        });
+        if let Some(idempotency_key) = &idempotency_key {
+            idempotency_key.set_run(&db, &run_id).await;
+            idempotency_key
+                .complete(&db, StatusCode::ACCEPTED, &response)
+                .await;
+        }
+        if job.is_some() {
+            return Ok(HttpResponse::Accepted().json(response));
+        }
+        let engine_stats = engine_stats.into_inner();
+        engine_stats.run_queued();
+        tokio::spawn(async move {
+            let started = db::runs::start_run(&db.pool, &run_id).await;
+            engine_stats.run_dequeued();
+            match started {
+                Ok(true) => {}
+                Ok(false) => return, // cancelled while queued
+                Err(e) => {
+                    log::error!("Failed to start run {}: {}", run_id, e);
+                    return;
+                }
+            }
+            let _ = execute_run(
+                run,
+                None,
+                &pipeline_runner,
+                &db,
+                &rate_limiter,
+                &project_api_key,
+                &interrupt_senders,
+            )
+            .await;
+        });
+
+        return Ok(HttpResponse::Accepted().json(response));
+    }
+
+    if let Some(queue) = run_execution.queue_for(&run) {
+        return relay_queued_run(
+            queue,
+            run,
+            &project_api_key,
+            false,
+            db,
+            pipeline_runner.http_client(),
+            idempotency_key,
+        )
+        .await;
+    }
+    let created = create_run(&db, &run, RunStatus::Running).await;
+    release_on_error(idempotency_key.as_ref(), &db, created).await?;
+    if let Some(idempotency_key) = &idempotency_key {
+        idempotency_key.set_run(&db, &run_id).await;
+    }
+    let run_result = execute_run(
+        run,
+        None,
+        &pipeline_runner,
+        &db,
+        &rate_limiter,
+        &project_api_key,
+        &interrupt_senders,
+    )
+    .await;
+    let run_result = release_on_error(idempotency_key.as_ref(), &db, run_result)
+        .await
+        .map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
+
+    let res = GraphRunOutput {
+        outputs: run_result.graph_outputs(),
+        run_id,
+        pipeline_version_id,
+        pipeline_version_hash,
+    };
+    if let Some(idempotency_key) = &idempotency_key {
+        let response = serde_json::to_value(&res).unwrap_or_default();
+        idempotency_key
+            .complete(&db, StatusCode::OK, &response)
+            .await;
+    }
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Status of the run, with outputs and node stats once it's finished
+#[utoipa::path(
+    get,
+    path = "/v1/runs/{run_id}",
+    tag = "runs",
+    params(("run_id" = Uuid, Path, description = "Id of the run")),
+    responses(
+        (status = 200, body = Run),
+        (status = 400, description = "Run not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("runs/{run_id}")]
+async fn get_run(
+    run_id: web::Path<Uuid>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    if !project_api_key.has_scope(ApiKeyScope::ReadTraces) {
+        require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    }
+
+    let run = db::runs::get_run(&db.pool, &run_id, &project_api_key.project_id)
+        .await?
+        .ok_or_else(|| error::Error::invalid_request(Some("Run not found")))?;
+
+    Ok(HttpResponse::Ok().json(run))
+}
+
+/// Cancel a queued or running run
+#[utoipa::path(
+    post,
+    path = "/v1/runs/{run_id}/cancel",
+    tag = "runs",
+    params(("run_id" = Uuid, Path, description = "Id of the run")),
+    responses(
+        (status = 200, description = "Run is cancelled"),
+        (status = 400, description = "Run not found or already finished"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("runs/{run_id}/cancel")]
+async fn cancel_run(
+    run_id: web::Path<Uuid>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    interrupt_senders: web::Data<Arc<InterruptSenders>>,
+    run_execution: web::Data<RunExecution>,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let run_id = run_id.into_inner();
+
+    let cancelled = runs::cancel_run(
+        &db,
+        pipeline_runner.http_client(),
+        &interrupt_senders,
+        &run_execution,
+        run_id,
+        &project_api_key.project_id,
+    )
+    .await?;
+    if !cancelled {
+        return Err(error::Error::invalid_request(Some(
+            "Run not found or already finished",
+        )));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Inputs and outputs of the node's executions in the run, in order of execution
+///
+/// Recorded for runs of pipelines with `recordNodeIo` set, or requested with `recordNodeIo`.
+/// Values dropped to fit a record into `NODE_IO_MAX_RECORD_BYTES` are null.
+#[utoipa::path(
+    get,
+    path = "/v1/runs/{run_id}/nodes/{node_id}/io",
+    tag = "runs",
+    params(
+        ("run_id" = Uuid, Path, description = "Id of the run"),
+        ("node_id" = Uuid, Path, description = "Id of the node"),
+    ),
+    responses(
+        (status = 200, body = [NodeIoRecord]),
+        (status = 400, description = "Run not found, or no I/O of the node is recorded"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("runs/{run_id}/nodes/{node_id}/io")]
+async fn get_node_io(
+    path: web::Path<(Uuid, Uuid)>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    if !project_api_key.has_scope(ApiKeyScope::ReadTraces) {
+        require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    }
+    let (run_id, node_id) = path.into_inner();
+
+    db::runs::get_run(&db.pool, &run_id, &project_api_key.project_id)
+        .await?
+        .ok_or_else(|| error::Error::invalid_request(Some("Run not found")))?;
+    let records = pipeline_runner
+        .node_io_store()
+        .get(&run_id, &node_id)
+        .await?;
+    if records.is_empty() {
+        return Err(error::Error::invalid_request(Some(
+            "No I/O of the node is recorded in the run",
+        )));
+    }
+
+    Ok(HttpResponse::Ok().json(records))
+}
+
+/// Graph of the run's pipeline version in Graphviz DOT
+///
+/// Once the run is finished, nodes are colored by their outcome and annotated with their total
+/// duration and token count.
+#[utoipa::path(
+    get,
+    path = "/v1/runs/{run_id}/graph.dot",
+    tag = "runs",
+    params(("run_id" = Uuid, Path, description = "Id of the run")),
+    responses(
+        (status = 200, description = "DOT of the graph", content_type = "text/vnd.graphviz", body = String),
+        (status = 400, description = "Run not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("runs/{run_id}/graph.dot")]
+async fn get_run_graph_dot(
+    run_id: web::Path<Uuid>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    project_api_key: ProjectApiKey,
+) -> ResponseResult {
+    if !project_api_key.has_scope(ApiKeyScope::ReadTraces) {
+        require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    }
+    let run_id = run_id.into_inner();
+
+    let run = db::runs::get_run(&db.pool, &run_id, &project_api_key.project_id)
+        .await?
+        .ok_or_else(|| error::Error::invalid_request(Some("Run not found")))?;
+    let pipeline_version =
+        db::pipelines::get_pipeline_version(&db.pool, &run.pipeline_version_id).await?;
+    let node_stats = match (run.finished_at, run.node_stats) {
+        (Some(_), Some(node_stats)) => serde_json::from_value::<Vec<NodeRunStats>>(node_stats).ok(),
+        _ => None,
+    };
+    let dot = pipeline_runner
+        .version_dot(&pipeline_version, node_stats.as_deref())
+        .map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/vnd.graphviz")
+        .body(dot))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayRequest {
+    /// Config overrides by node name, merged into the node's config, e.g. `{"prompt": "..."}`
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    overrides: HashMap<String, Value>,
+    /// Names of nodes to re-execute with their recorded configs, overridden nodes are
+    /// re-executed too
+    #[serde(default)]
+    nodes: Vec<String>,
+    /// Env of the replay, env isn't recorded with the run
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+/// Replay the run with modified node configs
+///
+/// Re-executes the nodes in `nodes` and `overrides`, with the overrides merged into their
+/// configs, and all nodes downstream of them. The other nodes aren't executed, and what they
+/// produced in the run is taken from its recorded node I/O, so the run must be recorded with
+/// `recordNodeIo`. Nodes whose recorded inputs are missing, or were dropped by the size cap,
+/// can't be replayed and fail the request with their names.
+///
+/// The replay is a new run of the same pipeline version with its own trace, and `replayOf` set
+/// to the run. It's executed in sync mode on the instance which receives the request.
+#[utoipa::path(
+    post,
+    path = "/v1/runs/{run_id}/replay",
+    tag = "runs",
+    params(("run_id" = Uuid, Path, description = "Id of the run")),
+    request_body = ReplayRequest,
+    responses(
+        (status = 200, description = "Outputs of the replay", body = GraphRunOutput),
+        (status = 400, description = "Run not found, its node I/O isn't recorded, nodes can't be replayed, or the replay failed"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[post("runs/{run_id}/replay")]
+async fn replay_run(
+    run_id: web::Path<Uuid>,
+    req: web::Json<ReplayRequest>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    db: web::Data<DB>,
+    project_api_key: ProjectApiKey,
+    rate_limiter: web::Data<Arc<ApiKeyRateLimiter>>,
+    interrupt_senders: web::Data<Arc<InterruptSenders>>,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
+    let run_id = run_id.into_inner();
+
+    let recorded_run = db::runs::get_run(&db.pool, &run_id, &project_api_key.project_id)
+        .await?
+        .ok_or_else(|| error::Error::invalid_request(Some("Run not found")))?;
+    if !project_api_key.can_run_pipeline(&recorded_run.pipeline_id) {
+        return Err(error::Error::Forbidden(
+            "API key is not allowed to run the pipeline of the run".to_string(),
+        ));
+    }
+    let records = pipeline_runner.node_io_store().get_run(&run_id).await?;
+    if records.is_empty() {
+        return Err(error::Error::invalid_request(Some(
+            "No node I/O is recorded for the run",
+        )));
+    }
+
+    let run = prepare_replay(
+        req.into_inner(),
+        recorded_run,
+        &records,
+        &pipeline_runner,
+        &db,
+    )
+    .await?;
+    let replay_id = run.run_id;
+    let pipeline_version_id = run.pipeline_version.id;
+    let pipeline_version_hash = run.pipeline_version.content_hash.clone();
+    create_run(&db, &run, RunStatus::Running).await?;
+
+    let run_result = execute_run(
+        run,
+        None,
+        &pipeline_runner,
+        &db,
+        &rate_limiter,
+        &project_api_key,
+        &interrupt_senders,
+    )
+    .await
+    .map_err(|e| pipeline_runner_to_http_error(e, replay_id))?;
+
+    Ok(HttpResponse::Ok().json(GraphRunOutput {
+        outputs: run_result.graph_outputs(),
+        run_id: replay_id,
+        pipeline_version_id,
+        pipeline_version_hash,
+    }))
+}
+
+/// Replay with the recorded run's pipeline version, inputs and metadata, and the nodes to
+/// re-execute seeded from its records
+async fn prepare_replay(
+    req: ReplayRequest,
+    recorded_run: Run,
+    records: &[NodeIoRecord],
+    pipeline_runner: &PipelineRunner,
+    db: &DB,
+) -> Result<PreparedRun, error::Error> {
+    let run_id = Uuid::new_v4();
+    let project_id = recorded_run.project_id;
+    let pipeline_version =
+        db::pipelines::get_pipeline_version(&db.pool, &recorded_run.pipeline_version_id).await?;
+    let mut graph = pipeline_runner
+        .get_version_graph(&pipeline_version)
+        .map_err(|e| error::Error::deserialization_error(Some(e)))?;
+    replay::apply_overrides(&mut graph, &req.overrides)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+
+    let mut node_ids = HashSet::new();
+    let mut unknown_nodes = Vec::new();
+    for node_name in req.nodes.iter().chain(req.overrides.keys()) {
+        match graph.nodes.values().find(|node| node.name() == *node_name) {
+            Some(node) => {
+                node_ids.insert(node.id());
+            }
+            None => unknown_nodes.push(node_name.as_str()),
+        }
+    }
+    if !unknown_nodes.is_empty() {
+        return Err(error::Error::invalid_request(Some(&format!(
+            "Nodes are not in the pipeline version: {}",
+            unknown_nodes.join(", ")
+        ))));
+    }
+    if node_ids.is_empty() {
+        return Err(error::Error::invalid_request(Some(
+            "Set nodes or overrides to re-execute",
+        )));
+    }
+
+    let secrets = secrets::get_project_secrets(&db.pool, &project_id).await?;
+    let missing_inputs =
+        |e: replay::MissingInputsError| error::Error::invalid_request(Some(&e.to_string()));
+    let inputs =
+        replay::recorded_inputs(&graph, records, &node_ids, &secrets).map_err(missing_inputs)?;
+    let mut env = req.env;
+    env.insert("collection_name".to_string(), project_id.to_string());
+    let metadata = serde_json::from_value::<HashMap<String, String>>(recorded_run.metadata)
+        .unwrap_or_default();
+    graph
+        .setup(&inputs, &env, &metadata, &RunType::Endpoint)
+        .map_err(|e| pipeline_runner_to_http_error(e.into(), run_id))?;
+    graph.secrets = secrets;
+    graph.workspace_model_defaults =
+        db::model_defaults::get_project_model_defaults(&db.pool, &project_id).await?;
+    graph.workspace_id =
+        Some(db::workspace::get_workspace_id_of_project(&db.pool, &project_id).await?);
+    // with the prompt versions the recorded run ran with
+    prompts::load_prompts(
+        &db.pool,
+        &project_id,
+        &mut graph,
+        &prompts::recorded_versions(&metadata),
+    )
+    .await
+    .map_err(|e| pipeline_runner_to_http_error(e, run_id))?;
+    // replays are recorded too, so that they can be inspected and replayed
+    graph.record_node_io = true;
+
+    let tasks = parse_graph(graph.clone()).map_err(|e| {
+        pipeline_runner_to_http_error(PipelineRunnerError::UnhandledError(e), run_id)
+    })?;
+    let plan = replay::plan(recorded_run.id, &tasks, &node_ids, records, &graph.secrets)
+        .map_err(missing_inputs)?;
+
+    Ok(PreparedRun {
+        run_id,
+        project_id,
+        pipeline_version,
+        secrets: graph.secrets.clone(),
+        graph,
+        inputs,
+        env,
+        metadata,
+        parent_span_id: None,
+        trace_id: Uuid::new_v4(),
+        replay: Some(plan),
+        trace_mode: runs::TraceMode::default(),
+        leased_by: None,
+        checkpoints: None,
+    })
+}
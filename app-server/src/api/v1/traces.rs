@@ -4,19 +4,25 @@ use actix_web::{get, post, web, HttpResponse};
 use bytes::Bytes;
 use lapin::{options::BasicPublishOptions, BasicProperties, Connection};
 use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
 use uuid::Uuid;
 
 use crate::{
+    api::utils::require_api_key_scope,
     db::{
-        api_keys::ProjectApiKey,
+        self,
+        api_keys::{ApiKeyScope, ProjectApiKey},
         events::{self, EvaluateEventRequest, EventObservation},
         trace::Span,
         utils::convert_any_value_to_json_value,
         DB,
     },
     opentelemetry::opentelemetry_collector_trace_v1::ExportTraceServiceRequest,
-    routes::types::ResponseResult,
-    traces::{OBSERVATIONS_EXCHANGE, OBSERVATIONS_ROUTING_KEY},
+    routes::{error, types::ResponseResult},
+    traces::{
+        openinference::{export_request, ExportFormat},
+        OBSERVATIONS_EXCHANGE, OBSERVATIONS_ROUTING_KEY,
+    },
 };
 use prost::Message;
 
@@ -28,12 +34,22 @@ pub struct RabbitMqSpanMessage {
     pub evaluate_events: Vec<EvaluateEventRequest>,
 }
 
+/// Export spans, as an OTLP `ExportTraceServiceRequest`
+#[utoipa::path(
+    post,
+    path = "/v1/traces",
+    tag = "traces",
+    request_body(content = String, content_type = "application/x-protobuf", description = "Protobuf encoded OTLP export request"),
+    responses((status = 200)),
+    security(("project_api_key" = [])),
+)]
 #[post("traces")]
 pub async fn process_traces(
     body: Bytes,
     project_api_key: ProjectApiKey,
     rabbitmq_connection: web::Data<Arc<Connection>>,
 ) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::Run)?;
     let channel = rabbitmq_connection
         .create_channel()
         .await
@@ -101,18 +117,29 @@ pub async fn process_traces(
     Ok(HttpResponse::Ok().finish())
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 #[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
 struct GetEventsForSessionRequest {
     session_id: String,
 }
 
+/// Events of the spans in the session
+#[utoipa::path(
+    get,
+    path = "/v1/session-events",
+    tag = "traces",
+    params(GetEventsForSessionRequest),
+    responses((status = 200, body = [EventWithTemplateName])),
+    security(("project_api_key" = [])),
+)]
 #[get("session-events")]
 pub async fn get_events_for_session(
     request: web::Query<GetEventsForSessionRequest>,
     project_api_key: ProjectApiKey,
     db: web::Data<DB>,
 ) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::ReadTraces)?;
     let project_id = project_api_key.project_id;
     let session_id = request.session_id.clone();
     let events = events::get_events_for_session(&db.pool, &session_id, &project_id)
@@ -120,3 +147,49 @@ pub async fn get_events_for_session(
         .map_err(|e| anyhow::anyhow!("Failed to get events for session: {}", e))?;
     Ok(HttpResponse::Ok().json(events))
 }
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportTraceQuery {
+    #[serde(default)]
+    #[param(inline)]
+    format: ExportFormat,
+}
+
+/// Spans of the trace, as an OTLP `ExportTraceServiceRequest`
+///
+/// In the `laminar` format, spans have the attributes they were recorded with. In the
+/// `openinference` format, they have the attributes of the OpenInference semantic conventions,
+/// e.g. to import the trace into a tool which reads them.
+#[utoipa::path(
+    get,
+    path = "/v1/traces/{trace_id}/otlp",
+    tag = "traces",
+    params(("trace_id" = Uuid, Path, description = "Id of the trace"), ExportTraceQuery),
+    responses(
+        (status = 200, description = "Protobuf encoded OTLP export request", content_type = "application/x-protobuf", body = String),
+        (status = 400, description = "Trace not found"),
+    ),
+    security(("project_api_key" = [])),
+)]
+#[get("traces/{trace_id}/otlp")]
+pub async fn export_trace(
+    trace_id: web::Path<Uuid>,
+    query: web::Query<ExportTraceQuery>,
+    project_api_key: ProjectApiKey,
+    db: web::Data<DB>,
+) -> ResponseResult {
+    require_api_key_scope(&project_api_key, ApiKeyScope::ReadTraces)?;
+    let trace_id = trace_id.into_inner();
+
+    let trace = db::trace::get_single_trace(&db.pool, trace_id)
+        .await?
+        .filter(|trace| trace.project_id == project_api_key.project_id)
+        .ok_or_else(|| error::Error::invalid_request(Some("Trace not found")))?;
+    let spans = db::trace::get_trace_spans(&db.pool, trace_id).await?;
+    let request = export_request(&trace, &spans, query.format);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-protobuf")
+        .body(request.encode_to_vec()))
+}
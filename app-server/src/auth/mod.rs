@@ -1,3 +1,5 @@
+pub mod rate_limit;
+
 use anyhow::Result;
 use std::env;
 use std::future::{ready, Ready};
@@ -5,16 +7,26 @@ use std::sync::Arc;
 
 use actix_web::dev::Payload;
 use actix_web::dev::ServiceRequest;
+use actix_web::error::{ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized, InternalError};
+use actix_web::http::Method;
 use actix_web::web;
 use actix_web::Error;
+use actix_web::HttpResponse;
 use actix_web::{FromRequest, HttpMessage, HttpRequest};
 use actix_web_httpauth::extractors::bearer::{BearerAuth, Config};
 use actix_web_httpauth::extractors::AuthenticationError;
+use uuid::Uuid;
 
 use crate::cache::Cache;
-use crate::db::api_keys::{get_api_key, ProjectApiKey};
+use crate::db::api_keys::{
+    get_api_key, get_api_key_token_count_today, get_workspace_token_count_today,
+    record_api_key_usage, ProjectApiKey,
+};
+use crate::db::limits::{get_workspace_run_limits, WorkspaceRunLimits};
+use crate::db::records::{ProjectRecord, RecordOwner, RecordOwners};
 use crate::db::user::{get_user_from_api_key, User};
 use crate::db::DB;
+use rate_limit::{ApiKeyRateLimiter, RateLimitExceeded};
 
 impl FromRequest for User {
     type Error = Error;
@@ -76,6 +88,103 @@ async fn validate_token(db: &DB, cache: Arc<Cache>, token: String) -> Result<Use
     get_user_from_api_key(&db.pool, token, cache).await
 }
 
+/// Reject requests to `workspaces/{workspace_id}/...` of users who aren't members of it
+///
+/// Runs after `validator`, which sets the user of the request.
+pub async fn workspace_member_validator(
+    req: ServiceRequest,
+    _credentials: BearerAuth,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let user = req.extensions().get::<User>().cloned();
+    let Some(user) = user else {
+        return Err((ErrorUnauthorized(""), req));
+    };
+    let workspace_id = req
+        .match_info()
+        .unprocessed()
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .and_then(|segment| Uuid::parse_str(segment).ok());
+
+    match workspace_id {
+        Some(workspace_id) if !user.is_member_of_workspace(&workspace_id) => {
+            log::error!(
+                "Unauthorized, user {} is not part of workspace {}",
+                user.id,
+                workspace_id
+            );
+            Err((ErrorUnauthorized(""), req))
+        }
+        _ => Ok(req),
+    }
+}
+
+/// Reject requests to `projects/{project_id}/...` of users who aren't members of the project,
+/// or which address records of other projects
+///
+/// Runs after `validator`, which sets the user of the request. Records of other projects are
+/// responded as not found, so that their ids can't be probed.
+pub async fn project_member_validator(
+    req: ServiceRequest,
+    _credentials: BearerAuth,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let user = req.extensions().get::<User>().cloned();
+    let Some(user) = user else {
+        return Err((ErrorUnauthorized(""), req));
+    };
+    let Some(project_id) = req
+        .match_info()
+        .get("project_id")
+        .and_then(|project_id| Uuid::parse_str(project_id).ok())
+    else {
+        return Err((ErrorNotFound(""), req));
+    };
+    if !user.is_member_of_project(&project_id) {
+        log::error!(
+            "Unauthorized, user {} is not part of project {}",
+            user.id,
+            project_id
+        );
+        return Err((ErrorUnauthorized(""), req));
+    }
+
+    let record_owners = req
+        .app_data::<web::Data<dyn RecordOwners>>()
+        .cloned()
+        .unwrap()
+        .into_inner();
+    let read_only = req.method() == Method::GET;
+    for record in ProjectRecord::from_path(req.match_info().unprocessed()) {
+        match record_owners.get_owner(&record).await {
+            Ok(owner) if can_access_record(owner.as_ref(), &project_id, read_only) => {}
+            Ok(_) => {
+                log::error!(
+                    "Unauthorized, {:?} is not part of project {}",
+                    record,
+                    project_id
+                );
+                return Err((ErrorNotFound(""), req));
+            }
+            Err(e) => {
+                log::error!("Error getting project of {:?}: {}", record, e);
+                return Err((ErrorInternalServerError(""), req));
+            }
+        }
+    }
+
+    Ok(req)
+}
+
+/// Whether a request through the project can access the record. Missing records are left to
+/// the route, and public pipelines can be read, but not changed or run, from any project.
+fn can_access_record(owner: Option<&RecordOwner>, project_id: &Uuid, read_only: bool) -> bool {
+    match owner {
+        None => true,
+        Some(owner) => owner.project_id == *project_id || (owner.public && read_only),
+    }
+}
+
 pub async fn project_validator(
     req: ServiceRequest,
     credentials: BearerAuth,
@@ -96,16 +205,99 @@ pub async fn project_validator(
         .unwrap()
         .into_inner();
 
-    match get_api_key(&db.pool, &credentials.token().to_string(), cache.clone()).await {
-        Ok(api_key) => {
-            req.extensions_mut().insert(api_key);
-            Ok(req)
+    let rate_limiter = req
+        .app_data::<web::Data<Arc<ApiKeyRateLimiter>>>()
+        .cloned()
+        .unwrap()
+        .into_inner();
+
+    let api_key =
+        match authenticate_project_api_key(db, cache, &rate_limiter, credentials.token()).await {
+            Ok(api_key) => api_key,
+            Err(ProjectAuthError::InvalidApiKey(e)) => {
+                log::error!("Error validating project_token: {}", e);
+                return Err((AuthenticationError::from(config).into(), req));
+            }
+            Err(ProjectAuthError::RateLimited(e)) => {
+                return Err((rate_limit_error(e, rate_limiter.now()), req))
+            }
+        };
+
+    req.extensions_mut().insert(api_key);
+    Ok(req)
+}
+
+pub enum ProjectAuthError {
+    InvalidApiKey(anyhow::Error),
+    RateLimited(RateLimitExceeded),
+}
+
+/// Resolve the project API key and count the request towards its limits
+///
+/// Shared by the HTTP API and the gRPC service, so that both enforce the same limits.
+pub async fn authenticate_project_api_key(
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    rate_limiter: &ApiKeyRateLimiter,
+    token: &str,
+) -> Result<ProjectApiKey, ProjectAuthError> {
+    let api_key = get_api_key(&db.pool, &token.to_string(), cache)
+        .await
+        .map_err(ProjectAuthError::InvalidApiKey)?;
+
+    if rate_limiter.needs_daily_tokens(&api_key) {
+        match get_api_key_token_count_today(&db.pool, &api_key.id).await {
+            Ok(token_count) => rate_limiter.set_daily_tokens(api_key.id, token_count),
+            Err(e) => log::error!("Error getting token usage of API key: {}", e),
         }
-        Err(e) => {
-            log::error!("Error validating project_token: {}", e);
-            Err((AuthenticationError::from(config).into(), req))
+    }
+    if rate_limiter.needs_workspace_limits(&api_key.workspace_id) {
+        match load_workspace_limits(&db, &api_key).await {
+            Ok((limits, token_count)) => {
+                rate_limiter.set_workspace_limits(api_key.workspace_id, limits, token_count)
+            }
+            Err(e) => log::error!("Error getting limits of workspace: {}", e),
         }
     }
+    rate_limiter
+        .check_request(&api_key)
+        .map_err(ProjectAuthError::RateLimited)?;
+
+    let api_key_id = api_key.id;
+    tokio::spawn(async move {
+        if let Err(e) = record_api_key_usage(&db.pool, &api_key_id, 1, 0).await {
+            log::error!("Error recording API key usage: {}", e);
+        }
+    });
+
+    Ok(api_key)
+}
+
+/// Limits of the key's workspace, with its tokens of today if they're limited
+async fn load_workspace_limits(
+    db: &DB,
+    api_key: &ProjectApiKey,
+) -> Result<(WorkspaceRunLimits, i64)> {
+    let limits = get_workspace_run_limits(&db.pool, &api_key.workspace_id).await?;
+    let token_count = match limits.tokens_per_day {
+        Some(_) => get_workspace_token_count_today(&db.pool, &api_key.workspace_id).await?,
+        None => 0,
+    };
+    Ok((limits, token_count))
+}
+
+fn rate_limit_error(e: RateLimitExceeded, now: chrono::DateTime<chrono::Utc>) -> Error {
+    let retry_after = (e.reset_at - now).num_seconds().max(0);
+    let response = HttpResponse::TooManyRequests()
+        .insert_header(("X-RateLimit-Limit", e.limit.to_string()))
+        .insert_header(("X-RateLimit-Remaining", "0"))
+        .insert_header(("X-RateLimit-Reset", e.reset_at.timestamp().to_string()))
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(serde_json::json!({
+            "error_code": "api.RateLimitExceeded",
+            "error_message": format!("Rate limit exceeded, resets at {}", e.reset_at.to_rfc3339()),
+        }));
+    InternalError::from_response("Rate limit exceeded", response).into()
 }
 
 pub async fn shared_secret_validator(
@@ -125,3 +317,156 @@ pub async fn shared_secret_validator(
         Err((AuthenticationError::from(config).into(), req))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use std::collections::HashMap;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use actix_web_httpauth::middleware::HttpAuthentication;
+    use async_trait::async_trait;
+    use dashmap::DashMap;
+    use tokio::sync::mpsc::Sender;
+
+    use crate::cache::cache::CacheTrait;
+    use crate::routes::{self, pipelines::GraphInterruptMessage};
+    use crate::testing::OfflineServices;
+
+    use super::*;
+
+    /// Owners of the records of a test, records it doesn't know don't exist
+    struct KnownOwners(HashMap<ProjectRecord, RecordOwner>);
+
+    #[async_trait]
+    impl RecordOwners for KnownOwners {
+        async fn get_owner(&self, record: &ProjectRecord) -> Result<Option<RecordOwner>> {
+            Ok(self.0.get(record).copied())
+        }
+    }
+
+    fn member_of(workspace_id: Uuid, project_id: Uuid) -> User {
+        User {
+            id: Uuid::new_v4(),
+            workspace_ids: Some(vec![workspace_id]),
+            project_ids: Some(vec![project_id]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_workspace_cannot_access_other_workspace() {
+        let (workspace_a, project_a) = (Uuid::new_v4(), Uuid::new_v4());
+        let (workspace_b, project_b) = (Uuid::new_v4(), Uuid::new_v4());
+        let user = member_of(workspace_a, project_a);
+
+        assert!(user.is_member_of_workspace(&workspace_a));
+        assert!(user.is_member_of_project(&project_a));
+        assert!(!user.is_member_of_workspace(&workspace_b));
+        assert!(!user.is_member_of_project(&project_b));
+    }
+
+    #[test]
+    fn test_records_of_other_projects_are_rejected() {
+        let (project_a, project_b) = (Uuid::new_v4(), Uuid::new_v4());
+        let of_a = RecordOwner {
+            project_id: project_a,
+            public: false,
+        };
+        let of_b = RecordOwner {
+            project_id: project_b,
+            public: false,
+        };
+
+        // e.g. B's trace, or B's pipeline version run through A's project
+        assert!(can_access_record(Some(&of_a), &project_a, false));
+        assert!(!can_access_record(Some(&of_b), &project_a, true));
+        assert!(!can_access_record(Some(&of_b), &project_a, false));
+
+        let public_of_b = RecordOwner {
+            public: true,
+            ..of_b
+        };
+        assert!(can_access_record(Some(&public_of_b), &project_a, true));
+        assert!(!can_access_record(Some(&public_of_b), &project_a, false));
+    }
+
+    /// A user of workspace A reading B's trace and running B's pipeline version through A's
+    /// project, with its API key. The routes run behind the same middleware as in the server,
+    /// whose checks reject the requests before they reach the database.
+    #[actix_web::test]
+    async fn test_routes_reject_records_of_other_workspace() {
+        let (workspace_a, project_a) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_b = Uuid::new_v4();
+        let (trace_b, version_b) = (Uuid::new_v4(), Uuid::new_v4());
+        let of_b = RecordOwner {
+            project_id: project_b,
+            public: false,
+        };
+        let record_owners: Arc<dyn RecordOwners> = Arc::new(KnownOwners(HashMap::from([
+            (ProjectRecord::Trace(trace_b), of_b),
+            (ProjectRecord::PipelineVersion(version_b), of_b),
+        ])));
+
+        let token = "workspace-a-key";
+        let mut caches: HashMap<TypeId, Arc<dyn CacheTrait>> = HashMap::new();
+        caches.insert(
+            TypeId::of::<User>(),
+            Arc::new(moka::future::Cache::<String, User>::new(1)),
+        );
+        let cache = Arc::new(Cache::new(caches));
+        cache
+            .insert(token.to_string(), &member_of(workspace_a, project_a))
+            .await
+            .unwrap();
+
+        let services = OfflineServices::default();
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/laminar")
+            .unwrap();
+        let interrupt_senders: Arc<DashMap<Uuid, Sender<GraphInterruptMessage>>> =
+            Arc::new(DashMap::new());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::from(cache))
+                .app_data(web::Data::new(DB::new(pool)))
+                .app_data(web::Data::from(record_owners))
+                .app_data(web::Data::new(Arc::new(services.context().pipeline_runner)))
+                .app_data(web::Data::new(interrupt_senders))
+                .service(
+                    web::scope("/api/v1/projects")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .service(
+                            web::scope("/{project_id}")
+                                .wrap(HttpAuthentication::bearer(project_member_validator))
+                                .service(routes::traces::get_single_trace)
+                                .service(routes::pipelines::run_pipeline_graph),
+                        ),
+                ),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri(&format!("/api/v1/projects/{project_a}/traces/{trace_b}"))
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = TestRequest::post()
+            .uri(&format!("/api/v1/projects/{project_a}/pipelines/run/graph"))
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .set_json(serde_json::json!({
+                "runId": Uuid::new_v4(),
+                "graph": {"nodes": {}, "pred": {}},
+                "inputs": {},
+                "env": {},
+                "pipelineVersionId": version_b,
+            }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,385 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    db::{api_keys::ProjectApiKey, limits::WorkspaceRunLimits, workspace::WorkspaceId},
+};
+
+/// Limits of workspaces are reloaded after this long, so that updates apply on all instances
+const WORKSPACE_LIMITS_TTL_SECONDS: i64 = 60;
+
+#[derive(Debug)]
+pub struct RateLimitExceeded {
+    pub limit: i64,
+    pub reset_at: DateTime<Utc>,
+}
+
+/// The workspace is executing its maximum of concurrent runs
+#[derive(Debug)]
+pub struct RunLimitReached {
+    pub limit: i64,
+}
+
+struct RequestWindow {
+    /// Start of the current minute, in seconds since epoch
+    start: i64,
+    count: i64,
+}
+
+struct TokenWindow {
+    date: NaiveDate,
+    count: i64,
+}
+
+impl TokenWindow {
+    fn add(&mut self, today: NaiveDate, token_count: i64) {
+        if self.date != today {
+            self.date = today;
+            self.count = 0;
+        }
+        self.count += token_count;
+    }
+}
+
+struct WorkspaceWindow {
+    limits: WorkspaceRunLimits,
+    loaded_at: DateTime<Utc>,
+    tokens: TokenWindow,
+}
+
+/// Fixed window limiter for project API key quotas
+///
+/// Requests are counted per minute and tokens per UTC day. Counters are kept in memory of
+/// this instance, token counters are seeded from `project_api_key_usage` on the first request
+/// of the day, so restarts don't reset daily quotas.
+///
+/// Keys of all projects of a workspace also count towards the limits of the workspace, which
+/// are loaded with the workspace's tokens of the day on its first request, and reloaded every
+/// minute. Runs are counted while they execute, on the instance executing them.
+pub struct ApiKeyRateLimiter {
+    requests: DashMap<Uuid, RequestWindow>,
+    tokens: DashMap<Uuid, TokenWindow>,
+    workspaces: DashMap<WorkspaceId, WorkspaceWindow>,
+    running: DashMap<WorkspaceId, i64>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ApiKeyRateLimiter {
+    fn default() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+}
+
+impl ApiKeyRateLimiter {
+    /// Limiter whose windows start and end on the clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            requests: DashMap::new(),
+            tokens: DashMap::new(),
+            workspaces: DashMap::new(),
+            running: DashMap::new(),
+            clock,
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Whether today's token count of the key must be loaded with `set_daily_tokens`
+    pub fn needs_daily_tokens(&self, api_key: &ProjectApiKey) -> bool {
+        api_key.tokens_per_day.is_some()
+            && !self
+                .tokens
+                .get(&api_key.id)
+                .is_some_and(|window| window.date == self.now().date_naive())
+    }
+
+    pub fn set_daily_tokens(&self, api_key_id: Uuid, count: i64) {
+        self.tokens.insert(
+            api_key_id,
+            TokenWindow {
+                date: self.now().date_naive(),
+                count,
+            },
+        );
+    }
+
+    /// Whether the limits of the workspace must be loaded with `set_workspace_limits`
+    pub fn needs_workspace_limits(&self, workspace_id: &WorkspaceId) -> bool {
+        let now = self.now();
+        !self.workspaces.get(workspace_id).is_some_and(|window| {
+            window.tokens.date == now.date_naive()
+                && now - window.loaded_at < Duration::seconds(WORKSPACE_LIMITS_TTL_SECONDS)
+        })
+    }
+
+    /// Set the limits of the workspace and its token count of today
+    ///
+    /// Tokens this instance counted since the last load are kept if the stored count doesn't
+    /// include them yet.
+    pub fn set_workspace_limits(
+        &self,
+        workspace_id: WorkspaceId,
+        limits: WorkspaceRunLimits,
+        token_count: i64,
+    ) {
+        let now = self.now();
+        let today = now.date_naive();
+        let count = match self.workspaces.get(&workspace_id) {
+            Some(window) if window.tokens.date == today => window.tokens.count.max(token_count),
+            _ => token_count,
+        };
+        self.workspaces.insert(
+            workspace_id,
+            WorkspaceWindow {
+                limits,
+                loaded_at: now,
+                tokens: TokenWindow { date: today, count },
+            },
+        );
+    }
+
+    /// Count the request, fails if the key or its workspace is out of requests for this minute
+    /// or tokens for today
+    pub fn check_request(&self, api_key: &ProjectApiKey) -> Result<(), RateLimitExceeded> {
+        let now = self.now();
+        let today = now.date_naive();
+
+        if let Some(tokens_per_day) = api_key.tokens_per_day {
+            let used = self
+                .tokens
+                .get(&api_key.id)
+                .filter(|window| window.date == today)
+                .map(|window| window.count)
+                .unwrap_or(0);
+            if used >= tokens_per_day {
+                return Err(RateLimitExceeded {
+                    limit: tokens_per_day,
+                    reset_at: start_of_next_day(today),
+                });
+            }
+        }
+
+        if let Some(window) = self.workspaces.get(&api_key.workspace_id) {
+            if let Some(tokens_per_day) = window.limits.tokens_per_day {
+                if window.tokens.date == today && window.tokens.count >= tokens_per_day {
+                    return Err(RateLimitExceeded {
+                        limit: tokens_per_day,
+                        reset_at: start_of_next_day(today),
+                    });
+                }
+            }
+        }
+
+        if let Some(requests_per_minute) = api_key.requests_per_minute {
+            let start = now.timestamp() - now.timestamp() % 60;
+            let mut window = self
+                .requests
+                .entry(api_key.id)
+                .or_insert(RequestWindow { start, count: 0 });
+            if window.start != start {
+                window.start = start;
+                window.count = 0;
+            }
+            if window.count >= requests_per_minute {
+                return Err(RateLimitExceeded {
+                    limit: requests_per_minute,
+                    reset_at: Utc.timestamp_opt(start + 60, 0).unwrap(),
+                });
+            }
+            window.count += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the workspace of the key is already executing its maximum of concurrent runs
+    pub fn check_run(&self, api_key: &ProjectApiKey) -> Result<(), RunLimitReached> {
+        let Some(limit) = self
+            .workspaces
+            .get(&api_key.workspace_id)
+            .and_then(|window| window.limits.max_concurrent_runs)
+        else {
+            return Ok(());
+        };
+        let running = self
+            .running
+            .get(&api_key.workspace_id)
+            .map(|running| *running)
+            .unwrap_or(0);
+        if running >= limit {
+            return Err(RunLimitReached { limit });
+        }
+        Ok(())
+    }
+
+    /// Count a run of the workspace as executing until the slot is dropped
+    pub fn start_run(&self, workspace_id: WorkspaceId) -> RunSlot<'_> {
+        *self.running.entry(workspace_id).or_insert(0) += 1;
+        RunSlot {
+            limiter: self,
+            workspace_id,
+        }
+    }
+
+    /// Count the tokens towards the daily quotas of the key and its workspace
+    pub fn record_tokens(&self, api_key: &ProjectApiKey, token_count: i64) {
+        let today = self.now().date_naive();
+        self.tokens
+            .entry(api_key.id)
+            .or_insert(TokenWindow {
+                date: today,
+                count: 0,
+            })
+            .add(today, token_count);
+        if let Some(mut window) = self.workspaces.get_mut(&api_key.workspace_id) {
+            window.tokens.add(today, token_count);
+        }
+    }
+}
+
+/// Run counted by `ApiKeyRateLimiter::start_run`
+pub struct RunSlot<'a> {
+    limiter: &'a ApiKeyRateLimiter,
+    workspace_id: WorkspaceId,
+}
+
+impl Drop for RunSlot<'_> {
+    fn drop(&mut self) {
+        if let Some(mut running) = self.limiter.running.get_mut(&self.workspace_id) {
+            *running -= 1;
+        }
+        self.limiter
+            .running
+            .remove_if(&self.workspace_id, |_, running| *running <= 0);
+    }
+}
+
+fn start_of_next_day(today: NaiveDate) -> DateTime<Utc> {
+    let tomorrow = today + Duration::days(1);
+    Utc.from_utc_datetime(&tomorrow.and_hms_opt(0, 0, 0).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use crate::clock::ManualClock;
+
+    use super::*;
+
+    fn api_key(requests_per_minute: Option<i64>, tokens_per_day: Option<i64>) -> ProjectApiKey {
+        ProjectApiKey {
+            id: Uuid::new_v4(),
+            project_id: Uuid::new_v4(),
+            workspace_id: WorkspaceId(Uuid::new_v4()),
+            name: None,
+            shorthand: "lm...key".to_string(),
+            scopes: vec![],
+            pipeline_ids: None,
+            requests_per_minute,
+            tokens_per_day,
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn test_request_window_resets_each_minute() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 30).unwrap());
+        let limiter = ApiKeyRateLimiter::with_clock(Arc::new(clock.clone()));
+        let api_key = api_key(Some(2), None);
+
+        assert!(limiter.check_request(&api_key).is_ok());
+        assert!(limiter.check_request(&api_key).is_ok());
+        let e = limiter.check_request(&api_key).unwrap_err();
+        assert_eq!(e.limit, 2);
+        assert_eq!(
+            e.reset_at,
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 1, 0).unwrap()
+        );
+
+        clock.advance(StdDuration::from_secs(29));
+        assert!(limiter.check_request(&api_key).is_err());
+        clock.advance(StdDuration::from_secs(1));
+        assert!(limiter.check_request(&api_key).is_ok());
+    }
+
+    #[test]
+    fn test_token_window_resets_at_midnight() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 5, 1, 23, 0, 0).unwrap());
+        let limiter = ApiKeyRateLimiter::with_clock(Arc::new(clock.clone()));
+        let api_key = api_key(None, Some(100));
+
+        assert!(limiter.needs_daily_tokens(&api_key));
+        limiter.set_daily_tokens(api_key.id, 60);
+        assert!(!limiter.needs_daily_tokens(&api_key));
+        assert!(limiter.check_request(&api_key).is_ok());
+        limiter.record_tokens(&api_key, 40);
+        let e = limiter.check_request(&api_key).unwrap_err();
+        assert_eq!(
+            e.reset_at,
+            Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap()
+        );
+
+        clock.advance(StdDuration::from_secs(3600));
+        assert!(limiter.needs_daily_tokens(&api_key));
+        assert!(limiter.check_request(&api_key).is_ok());
+    }
+
+    #[test]
+    fn test_workspace_tokens_count_keys_of_all_projects() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap());
+        let limiter = ApiKeyRateLimiter::with_clock(Arc::new(clock.clone()));
+        let limits = WorkspaceRunLimits {
+            max_concurrent_runs: None,
+            tokens_per_day: Some(100),
+        };
+        let first = api_key(None, None);
+        let second = ProjectApiKey {
+            workspace_id: first.workspace_id,
+            ..api_key(None, None)
+        };
+        let other_workspace = api_key(None, None);
+
+        assert!(limiter.needs_workspace_limits(&first.workspace_id));
+        limiter.set_workspace_limits(first.workspace_id, limits, 30);
+        limiter.set_workspace_limits(other_workspace.workspace_id, limits, 0);
+        assert!(!limiter.needs_workspace_limits(&first.workspace_id));
+        limiter.record_tokens(&first, 40);
+        assert!(limiter.check_request(&second).is_ok());
+        limiter.record_tokens(&second, 30);
+        assert!(limiter.check_request(&first).is_err());
+        assert!(limiter.check_request(&second).is_err());
+        assert!(limiter.check_request(&other_workspace).is_ok());
+
+        // a stale stored count doesn't drop the tokens counted since the last load
+        clock.advance(StdDuration::from_secs(60));
+        assert!(limiter.needs_workspace_limits(&first.workspace_id));
+        limiter.set_workspace_limits(first.workspace_id, limits, 30);
+        assert!(limiter.check_request(&first).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_runs_per_workspace() {
+        let limiter = ApiKeyRateLimiter::with_clock(Arc::new(ManualClock::default()));
+        let limits = WorkspaceRunLimits {
+            max_concurrent_runs: Some(1),
+            tokens_per_day: None,
+        };
+        let key = api_key(None, None);
+        let other_workspace = api_key(None, None);
+        limiter.set_workspace_limits(key.workspace_id, limits, 0);
+        limiter.set_workspace_limits(other_workspace.workspace_id, limits, 0);
+
+        let slot = limiter.start_run(key.workspace_id);
+        assert_eq!(limiter.check_run(&key).unwrap_err().limit, 1);
+        assert!(limiter.check_run(&other_workspace).is_ok());
+        drop(slot);
+        assert!(limiter.check_run(&key).is_ok());
+    }
+}
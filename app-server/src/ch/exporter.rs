@@ -0,0 +1,219 @@
+//! Batched export of spans to ClickHouse
+//!
+//! Spans are sent to a single writer task, which inserts them in batches of up to
+//! `MAX_BATCH_SIZE`, or every `FLUSH_INTERVAL` if fewer arrived. A batch which still fails after
+//! `EXPORT_ATTEMPTS` attempts is captured as a dead letter, instead of being dropped.
+//!
+//! Runs in strict trace mode wait on a flush barrier, which writes the spans queued before it
+//! right away, and is acknowledged once they're inserted, see `runs::TraceMode`.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{db::DB, dead_letters};
+
+use super::spans::{insert_spans, CHSpan};
+
+const MAX_BATCH_SIZE: usize = 1000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const EXPORT_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// Table the exported batches are inserted into, ClickHouse's `spans` outside of tests
+#[async_trait]
+pub trait SpanSink: Send + Sync {
+    async fn insert(&self, spans: &[CHSpan]) -> Result<()>;
+}
+
+#[async_trait]
+impl SpanSink for clickhouse::Client {
+    async fn insert(&self, spans: &[CHSpan]) -> Result<()> {
+        insert_spans(self, spans).await
+    }
+}
+
+enum Export {
+    Span(Box<CHSpan>),
+    /// Batch of a dead letter, `retry_count` is recorded if it's captured again
+    Retry {
+        spans: Vec<CHSpan>,
+        retry_count: i32,
+    },
+    /// Write the pending batch, and acknowledge whether it was inserted
+    Flush(oneshot::Sender<bool>),
+}
+
+#[derive(Debug)]
+pub struct SpanExporter {
+    sender: mpsc::Sender<Export>,
+}
+
+impl SpanExporter {
+    /// Start the writer task, which runs until the exporter is dropped
+    pub fn start(clickhouse: clickhouse::Client, db: Arc<DB>) -> Self {
+        Self::start_with_sink(Arc::new(clickhouse), db)
+    }
+
+    pub fn start_with_sink(sink: Arc<dyn SpanSink>, db: Arc<DB>) -> Self {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(write_batches(sink, db, receiver));
+        Self { sender }
+    }
+
+    /// Queue the span to be exported with the next batch, waits if the queue is full
+    pub async fn export(&self, span: CHSpan) -> Result<()> {
+        self.send(Export::Span(Box::new(span))).await
+    }
+
+    /// Export the spans of a dead letter as a batch of their own
+    pub async fn retry(&self, spans: Vec<CHSpan>, retry_count: i32) -> Result<()> {
+        self.send(Export::Retry { spans, retry_count }).await
+    }
+
+    /// Insert all spans queued so far, returns once they're inserted
+    ///
+    /// Fails if they're captured as a dead letter instead. Spans queued after the call may be
+    /// inserted with them.
+    pub async fn flush(&self) -> Result<()> {
+        let (ack, acked) = oneshot::channel();
+        self.send(Export::Flush(ack)).await?;
+        match acked.await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(anyhow::anyhow!(
+                "Spans failed to be exported, they're captured as a dead letter"
+            )),
+            Err(_) => Err(anyhow::anyhow!("Span exporter is stopped")),
+        }
+    }
+
+    async fn send(&self, export: Export) -> Result<()> {
+        self.sender
+            .send(export)
+            .await
+            .map_err(|_| anyhow::anyhow!("Span exporter is stopped"))
+    }
+}
+
+async fn write_batches(sink: Arc<dyn SpanSink>, db: Arc<DB>, mut receiver: mpsc::Receiver<Export>) {
+    let mut batch = Vec::new();
+    let mut flush_interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            export = receiver.recv() => match export {
+                Some(Export::Span(span)) => {
+                    batch.push(*span);
+                    if batch.len() >= MAX_BATCH_SIZE {
+                        write(sink.as_ref(), &db, std::mem::take(&mut batch), 0).await;
+                    }
+                }
+                Some(Export::Retry { spans, retry_count }) => {
+                    write(sink.as_ref(), &db, spans, retry_count).await;
+                }
+                Some(Export::Flush(ack)) => {
+                    let written = batch.is_empty()
+                        || write(sink.as_ref(), &db, std::mem::take(&mut batch), 0).await;
+                    let _ = ack.send(written);
+                }
+                None => {
+                    if !batch.is_empty() {
+                        write(sink.as_ref(), &db, batch, 0).await;
+                    }
+                    return;
+                }
+            },
+            _ = flush_interval.tick() => {
+                if !batch.is_empty() {
+                    write(sink.as_ref(), &db, std::mem::take(&mut batch), 0).await;
+                }
+            }
+        }
+    }
+}
+
+/// Insert the batch, returns false if it's captured as a dead letter instead
+async fn write(sink: &dyn SpanSink, db: &DB, spans: Vec<CHSpan>, retry_count: i32) -> bool {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=EXPORT_ATTEMPTS {
+        let Err(e) = sink.insert(&spans).await else {
+            return true;
+        };
+        if attempt < EXPORT_ATTEMPTS {
+            log::warn!(
+                "Failed to export {} spans to ClickHouse, attempt {}: {}",
+                spans.len(),
+                attempt,
+                e
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        } else {
+            log::error!(
+                "Failed to export {} spans to ClickHouse after {} attempts, capturing them: {}",
+                spans.len(),
+                EXPORT_ATTEMPTS,
+                e
+            );
+            dead_letters::capture_span_batch(db, &spans, &e.to_string(), retry_count).await;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::testing::MemorySink;
+
+    use super::*;
+
+    fn span() -> CHSpan {
+        CHSpan {
+            span_id: Uuid::new_v4(),
+            name: String::from("run"),
+            span_type: 0,
+            start_time: 0,
+            end_time: 0,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+            input_cost: 0.0,
+            output_cost: 0.0,
+            total_cost: 0.0,
+            model: String::from("<null>"),
+            session_id: String::from("<null>"),
+            project_id: Uuid::new_v4(),
+            trace_id: Uuid::new_v4(),
+            provider: String::from("<null>"),
+            user_id: String::from("<null>"),
+            pipeline_id: Uuid::nil(),
+            node_id: Uuid::nil(),
+            route: String::from("<null>"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_inserts_queued_spans() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/laminar")
+            .unwrap();
+        let sink = Arc::new(MemorySink::default());
+        let exporter = SpanExporter::start_with_sink(sink.clone(), Arc::new(DB::new(pool)));
+
+        let spans = [span(), span()];
+        let span_ids = spans.iter().map(|span| span.span_id).collect::<Vec<_>>();
+        for span in spans {
+            exporter.export(span).await.unwrap();
+        }
+        exporter.flush().await.unwrap();
+
+        assert_eq!(sink.span_ids(), span_ids);
+        exporter.flush().await.unwrap();
+        assert_eq!(sink.span_ids().len(), 2);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod exporter;
+pub mod routes;
 pub mod spans;
 pub mod utils;
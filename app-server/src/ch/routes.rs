@@ -0,0 +1,383 @@
+//! Executions of each route of router nodes, i.e. switches, counted from their spans
+//!
+//! Route labels are truncated when the spans are created, see `ConditionedValue::route_label`,
+//! and the routes of a node are capped to the `MAX_ROUTES` most executed ones when they're
+//! counted, the rest are merged into `OTHER_ROUTE`.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::modifiers::GroupByInterval;
+
+/// Most routes of a node which are counted on their own
+pub const MAX_ROUTES: usize = 20;
+pub const OTHER_ROUTE: &str = "<other>";
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteCount {
+    pub route: String,
+    pub count: u64,
+    /// Percentage of the node's executions which took the route
+    pub percentage: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteBucket {
+    /// Start of the bucket, in seconds since the epoch
+    pub time: u32,
+    pub routes: Vec<RouteCount>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteStats {
+    /// Executions of each route over the whole window, most executed first, `OTHER_ROUTE` last
+    pub routes: Vec<RouteCount>,
+    /// Buckets with executions, oldest first
+    pub buckets: Vec<RouteBucket>,
+}
+
+/// Executions of a route of the node in the evaluation and in its baseline
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteShift {
+    pub route: String,
+    pub baseline_count: u64,
+    pub baseline_percentage: f64,
+    pub count: u64,
+    pub percentage: f64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteDistribution {
+    pub node_id: Uuid,
+    pub node_name: String,
+    pub routes: Vec<RouteShift>,
+}
+
+#[derive(Row, Deserialize)]
+struct BucketRouteCount {
+    time: u32,
+    label: String,
+    count: u64,
+}
+
+#[derive(Row, Deserialize)]
+pub struct NodeRouteCount {
+    #[serde(with = "clickhouse::serde::uuid")]
+    pub node_id: Uuid,
+    pub node_name: String,
+    pub label: String,
+    pub count: u64,
+}
+
+/// Longest window routes are counted over, in hours
+pub const MAX_WINDOW_HOURS: i64 = 90 * 24;
+
+/// Hours of a window such as `7d` or `24h`, if it's valid and at most `MAX_WINDOW_HOURS`
+pub fn parse_window(window: &str) -> Option<i64> {
+    let (count, hours_per_unit) = match window.trim() {
+        window if window.ends_with('d') => (&window[..window.len() - 1], 24),
+        window if window.ends_with('h') => (&window[..window.len() - 1], 1),
+        _ => return None,
+    };
+    let hours = count.parse::<i64>().ok()?.checked_mul(hours_per_unit)?;
+    (1..=MAX_WINDOW_HOURS).contains(&hours).then_some(hours)
+}
+
+/// Executions of each route of the node in the past hours, in buckets of the interval
+pub async fn get_route_stats(
+    clickhouse: clickhouse::Client,
+    project_id: Uuid,
+    pipeline_id: Uuid,
+    node_id: Uuid,
+    past_hours: i64,
+    group_by_interval: GroupByInterval,
+) -> Result<RouteStats> {
+    let query_string = format!(
+        "SELECT
+            {}(start_time) AS time,
+            route AS label,
+            count() AS count
+        FROM spans
+        WHERE
+            project_id = '{}'
+            AND pipeline_id = '{}'
+            AND node_id = '{}'
+            AND route != '<null>'
+            AND start_time >= now() - INTERVAL {} HOUR
+        GROUP BY time, label
+        ORDER BY time",
+        group_by_interval.to_ch_round_time(),
+        project_id,
+        pipeline_id,
+        node_id,
+        past_hours,
+    );
+
+    let mut cursor = clickhouse
+        .query(&query_string)
+        .fetch::<BucketRouteCount>()?;
+
+    let mut rows = Vec::new();
+    while let Some(row) = cursor.next().await? {
+        rows.push(row);
+    }
+
+    Ok(route_stats(rows))
+}
+
+/// Executions of each route of the router nodes in the traces, by node
+pub async fn get_trace_route_counts(
+    clickhouse: clickhouse::Client,
+    project_id: Uuid,
+    trace_ids: &[Uuid],
+) -> Result<Vec<NodeRouteCount>> {
+    if trace_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let trace_ids = trace_ids
+        .iter()
+        .map(|trace_id| format!("'{}'", trace_id))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let query_string = format!(
+        "SELECT
+            node_id,
+            any(name) AS node_name,
+            route AS label,
+            count() AS count
+        FROM spans
+        WHERE
+            project_id = '{}'
+            AND trace_id IN ({})
+            AND route != '<null>'
+        GROUP BY node_id, label",
+        project_id, trace_ids,
+    );
+
+    let mut cursor = clickhouse.query(&query_string).fetch::<NodeRouteCount>()?;
+
+    let mut counts = Vec::new();
+    while let Some(row) = cursor.next().await? {
+        counts.push(row);
+    }
+
+    Ok(counts)
+}
+
+/// Routes kept on their own, the `MAX_ROUTES` most executed ones
+fn top_routes<'a>(counts: impl Iterator<Item = (&'a str, u64)>) -> Vec<(String, u64)> {
+    let mut totals = HashMap::<&str, u64>::new();
+    for (route, count) in counts {
+        *totals.entry(route).or_default() += count;
+    }
+    let mut totals = totals
+        .into_iter()
+        .map(|(route, count)| (route.to_string(), count))
+        .collect::<Vec<_>>();
+    totals.sort_by(|(a_route, a), (b_route, b)| b.cmp(a).then_with(|| a_route.cmp(b_route)));
+    totals.truncate(MAX_ROUTES);
+    totals
+}
+
+/// Counts of the routes, most executed first, the ones which aren't kept merged into
+/// `OTHER_ROUTE`, which comes last
+fn route_counts<'a>(
+    counts: impl Iterator<Item = (&'a str, u64)>,
+    kept: &[(String, u64)],
+) -> Vec<RouteCount> {
+    let mut merged = Vec::<(String, u64)>::new();
+    for (route, count) in counts {
+        let route = if kept.iter().any(|(kept_route, _)| kept_route == route) {
+            route
+        } else {
+            OTHER_ROUTE
+        };
+        match merged
+            .iter_mut()
+            .find(|(merged_route, _)| *merged_route == route)
+        {
+            Some((_, merged_count)) => *merged_count += count,
+            None => merged.push((route.to_string(), count)),
+        }
+    }
+    let total = merged.iter().map(|(_, count)| count).sum::<u64>();
+    let mut counts = merged
+        .into_iter()
+        .map(|(route, count)| RouteCount {
+            route,
+            count,
+            percentage: percentage(count, total),
+        })
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| {
+        (a.route == OTHER_ROUTE)
+            .cmp(&(b.route == OTHER_ROUTE))
+            .then_with(|| b.count.cmp(&a.count))
+            .then_with(|| a.route.cmp(&b.route))
+    });
+    counts
+}
+
+fn percentage(count: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        total => count as f64 * 100.0 / total as f64,
+    }
+}
+
+fn route_stats(rows: Vec<BucketRouteCount>) -> RouteStats {
+    let kept = top_routes(rows.iter().map(|row| (row.label.as_str(), row.count)));
+    let routes = route_counts(
+        rows.iter().map(|row| (row.label.as_str(), row.count)),
+        &kept,
+    );
+
+    // rows are ordered by time
+    let mut buckets = Vec::<RouteBucket>::new();
+    let mut start = 0;
+    while start < rows.len() {
+        let time = rows[start].time;
+        let end = rows[start..]
+            .iter()
+            .position(|row| row.time != time)
+            .map_or(rows.len(), |len| start + len);
+        buckets.push(RouteBucket {
+            time,
+            routes: route_counts(
+                rows[start..end]
+                    .iter()
+                    .map(|row| (row.label.as_str(), row.count)),
+                &kept,
+            ),
+        });
+        start = end;
+    }
+
+    RouteStats { routes, buckets }
+}
+
+/// Distribution of the routes of each router node in the evaluation and in its baseline
+pub fn compare_route_counts(
+    baseline: &[NodeRouteCount],
+    evaluation: &[NodeRouteCount],
+) -> Vec<RouteDistribution> {
+    let mut node_names = HashMap::<Uuid, &str>::new();
+    for count in baseline.iter().chain(evaluation) {
+        node_names.entry(count.node_id).or_insert(&count.node_name);
+    }
+    fn node_counts(counts: &[NodeRouteCount], node_id: Uuid) -> Vec<(&str, u64)> {
+        counts
+            .iter()
+            .filter(|count| count.node_id == node_id)
+            .map(|count| (count.label.as_str(), count.count))
+            .collect()
+    }
+
+    let mut distributions = node_names
+        .into_iter()
+        .map(|(node_id, node_name)| {
+            let baseline = node_counts(baseline, node_id);
+            let evaluation = node_counts(evaluation, node_id);
+            let kept = top_routes(baseline.iter().chain(&evaluation).copied());
+            let baseline = route_counts(baseline.into_iter(), &kept);
+            let evaluation = route_counts(evaluation.into_iter(), &kept);
+
+            let mut routes = Vec::<RouteShift>::new();
+            for route in baseline.iter().chain(&evaluation) {
+                if routes.iter().any(|shift| shift.route == route.route) {
+                    continue;
+                }
+                let find = |counts: &[RouteCount]| {
+                    counts
+                        .iter()
+                        .find(|count| count.route == route.route)
+                        .map(|count| (count.count, count.percentage))
+                        .unwrap_or_default()
+                };
+                let (baseline_count, baseline_percentage) = find(&baseline);
+                let (count, percentage) = find(&evaluation);
+                routes.push(RouteShift {
+                    route: route.route.clone(),
+                    baseline_count,
+                    baseline_percentage,
+                    count,
+                    percentage,
+                });
+            }
+
+            RouteDistribution {
+                node_id,
+                node_name: node_name.to_string(),
+                routes,
+            }
+        })
+        .collect::<Vec<_>>();
+    distributions.sort_by(|a, b| a.node_name.cmp(&b.node_name));
+    distributions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("7d"), Some(168));
+        assert_eq!(parse_window("24h"), Some(24));
+        assert_eq!(parse_window("0h"), None);
+        assert_eq!(parse_window("-1d"), None);
+        assert_eq!(parse_window("91d"), None);
+        assert_eq!(parse_window("7w"), None);
+        assert_eq!(parse_window("d"), None);
+    }
+
+    #[test]
+    fn test_route_stats_caps_routes() {
+        let mut rows = (0..MAX_ROUTES + 2)
+            .map(|i| BucketRouteCount {
+                time: 0,
+                label: format!("route {}", i),
+                count: 100 - i as u64,
+            })
+            .collect::<Vec<_>>();
+        rows.push(BucketRouteCount {
+            time: 3600,
+            label: String::from("route 0"),
+            count: 1,
+        });
+
+        let stats = route_stats(rows);
+
+        assert_eq!(stats.routes.len(), MAX_ROUTES + 1);
+        assert_eq!(stats.routes[0].route, "route 0");
+        assert_eq!(stats.routes[0].count, 101);
+        let other = stats
+            .routes
+            .iter()
+            .find(|count| count.route == OTHER_ROUTE)
+            .unwrap();
+        assert_eq!(other.count, 80 + 79);
+        let total = stats.routes.iter().map(|count| count.count).sum::<u64>();
+        assert_eq!(total, (79..=100).sum::<u64>() + 1);
+
+        assert_eq!(stats.buckets.len(), 2);
+        assert_eq!(
+            stats.buckets[1].routes,
+            vec![RouteCount {
+                route: String::from("route 0"),
+                count: 1,
+                percentage: 100.0,
+            }]
+        );
+    }
+}
@@ -1,11 +1,14 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use clickhouse::Row;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
     ch::utils::round_small_values_to_zero,
-    db::{self, modifiers::GroupByInterval},
+    db::{self, modifiers::GroupByInterval, trace::SpanType},
     traces::SpanUsage,
 };
 
@@ -35,6 +38,17 @@ pub struct CHSpan {
     pub trace_id: Uuid,
     pub provider: String,
     pub user_id: String,
+    /// Pipeline, node and route of the span of a router node, defaults otherwise
+    #[serde(default, with = "clickhouse::serde::uuid")]
+    pub pipeline_id: Uuid,
+    #[serde(default, with = "clickhouse::serde::uuid")]
+    pub node_id: Uuid,
+    #[serde(default = "null_value")]
+    pub route: String,
+}
+
+fn null_value() -> String {
+    String::from("<null>")
 }
 
 impl CHSpan {
@@ -61,28 +75,22 @@ impl CHSpan {
             trace_id: span.trace_id,
             provider: usage.provider_name.unwrap_or(String::from("<null>")),
             user_id: span_attributes.user_id().unwrap_or(String::from("<null>")),
+            pipeline_id: span_attributes.pipeline_id().unwrap_or_default(),
+            node_id: span_attributes.route_node_id().unwrap_or_default(),
+            route: span_attributes
+                .route_name()
+                .unwrap_or(String::from("<null>")),
         }
     }
 }
 
-pub async fn insert_span(clickhouse: clickhouse::Client, span: &CHSpan) -> Result<()> {
-    let ch_insert = clickhouse.insert("spans");
-    match ch_insert {
-        Ok(mut ch_insert) => {
-            let write_res = ch_insert.write(span).await;
-            if let Err(e) = write_res {
-                log::error!("Failed to write span into Clickhouse: {:?}", e);
-            }
-
-            let ch_insert_end_res = ch_insert.end().await;
-            if let Err(e) = ch_insert_end_res {
-                log::error!("Failed to insert span into Clickhouse: {:?}", e);
-            }
-        }
-        Err(e) => {
-            log::error!("Failed to insert span into Clickhouse: {:?}", e);
-        }
+/// Insert the spans in one batch, fails if any of them isn't inserted
+pub async fn insert_spans(clickhouse: &clickhouse::Client, spans: &[CHSpan]) -> Result<()> {
+    let mut ch_insert = clickhouse.insert("spans")?;
+    for span in spans {
+        ch_insert.write(span).await?;
     }
+    ch_insert.end().await?;
     Ok(())
 }
 
@@ -370,3 +378,70 @@ pub async fn get_total_cost_usd_metrics(
 
     Ok(res)
 }
+
+/// Delete spans of the project which started before the cutoff, returns the number of deleted
+/// spans. The delete is a mutation, applied by ClickHouse in the background.
+pub async fn delete_spans_before(
+    clickhouse: clickhouse::Client,
+    project_id: Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<u64> {
+    let condition = format!(
+        "project_id = '{}' AND start_time < fromUnixTimestamp64Nano({})",
+        project_id,
+        chrono_to_timestamp(cutoff)
+    );
+
+    let count = clickhouse
+        .query(&format!("SELECT count() FROM spans WHERE {}", condition))
+        .fetch_one::<u64>()
+        .await?;
+    if count > 0 {
+        clickhouse
+            .query(&format!("ALTER TABLE spans DELETE WHERE {}", condition))
+            .execute()
+            .await?;
+    }
+
+    Ok(count)
+}
+
+#[derive(Deserialize, Row)]
+struct ModelLatency {
+    model: String,
+    /// Nanoseconds
+    p50: f64,
+}
+
+/// Median latency in seconds of the project's LLM spans of the past hours, by model
+pub async fn get_model_latency_p50s(
+    clickhouse: clickhouse::Client,
+    project_id: Uuid,
+    past_hours: i64,
+) -> Result<HashMap<String, f64>> {
+    let query_string = format!(
+        "SELECT
+            model,
+            quantile(0.5)(toUnixTimestamp64Nano(end_time) - toUnixTimestamp64Nano(start_time))
+                as p50
+        FROM spans
+        WHERE
+            project_id = '{}'
+            AND span_type = {}
+            AND model != '<null>'
+            AND start_time >= now() - INTERVAL {} HOUR
+        GROUP BY model",
+        project_id,
+        Into::<u8>::into(SpanType::LLM),
+        past_hours,
+    );
+
+    let mut cursor = clickhouse.query(&query_string).fetch::<ModelLatency>()?;
+
+    let mut latencies = HashMap::new();
+    while let Some(row) = cursor.next().await? {
+        latencies.insert(row.model, row.p50 / 1_000_000_000.0);
+    }
+
+    Ok(latencies)
+}
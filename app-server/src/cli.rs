@@ -0,0 +1,294 @@
+//! Command line mode of the server binary, to run a pipeline locally without the server
+//!
+//! `app-server run --pipeline pipeline.json --input '{"question": "..."}' --env .env` compiles
+//! the graph exported from the workshop, runs it with the engine the server runs, and streams
+//! node completions and LLM tokens to stdout. Values of the env file are both the env of the run,
+//! e.g. API keys of the providers, and its secrets. Runs aren't recorded, and nodes which need
+//! the database or semantic search fail unless `DATABASE_URL` and `SEMANTIC_SEARCH_URL` are set.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{Context as _, Result};
+use tokio::sync::mpsc;
+
+use crate::{
+    chunk::{
+        character_split::CharacterSplitChunker,
+        runner::{Chunker, ChunkerRunner, ChunkerType},
+    },
+    db::DB,
+    engine, http_client,
+    language_model::LanguageModelRunner,
+    pipeline::{
+        nodes::{NodeInput, RunEndpointEventError, StreamChunk},
+        runner::{PipelineRunner, PipelineRunnerError},
+        trace::RunTraceStats,
+        validation::validate_graph,
+        Graph, RunType,
+    },
+    runs::{checkpoints::PostgresCheckpointStore, node_io},
+    semantic_search::{
+        semantic_search_grpc::semantic_search_client::SemanticSearchClient, SemanticSearch,
+    },
+};
+
+pub const USAGE: &str = "Usage: app-server run --pipeline <FILE> [--input <JSON>] [--env <FILE>] \
+[--validate-only]";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunArgs),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RunArgs {
+    /// JSON of the runnable graph, as exported from the workshop
+    pub pipeline: PathBuf,
+    /// JSON object of the run inputs by input node name
+    pub input: Option<String>,
+    pub env_file: Option<PathBuf>,
+    /// Only print the diagnostics of the graph
+    pub validate_only: bool,
+}
+
+/// Command of the arguments after the binary name, unset if they don't start with one, so that
+/// the binary starts the server
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Command>, String> {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("run") => {}
+        _ => return Ok(None),
+    }
+
+    let mut pipeline = None;
+    let mut run_args = RunArgs::default();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or(format!("{} requires a value", flag));
+        match arg.as_str() {
+            "--pipeline" => pipeline = Some(PathBuf::from(value("--pipeline")?)),
+            "--input" => run_args.input = Some(value("--input")?),
+            "--env" => run_args.env_file = Some(PathBuf::from(value("--env")?)),
+            "--validate-only" => run_args.validate_only = true,
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    run_args.pipeline = pipeline.ok_or("--pipeline is required".to_string())?;
+    Ok(Some(Command::Run(run_args)))
+}
+
+/// Run the command, returning the exit code of the process
+pub async fn run(command: Command) -> i32 {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    engine::trace::init_from_env();
+
+    let Command::Run(args) = command;
+    match run_pipeline(args).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            1
+        }
+    }
+}
+
+async fn run_pipeline(args: RunArgs) -> Result<i32> {
+    let pipeline = std::fs::read_to_string(&args.pipeline)
+        .with_context(|| format!("Failed to read {}", args.pipeline.display()))?;
+    let mut graph = serde_json::from_str::<Graph>(&pipeline)
+        .with_context(|| format!("Failed to parse graph of {}", args.pipeline.display()))?;
+
+    let diagnostics = validate_graph(&graph);
+    for diagnostic in diagnostics.iter() {
+        match &diagnostic.node_name {
+            Some(node_name) => eprintln!("{}: {}", node_name, diagnostic.message),
+            None => eprintln!("{}", diagnostic.message),
+        }
+    }
+    if args.validate_only {
+        if diagnostics.is_empty() {
+            println!("Graph is valid");
+        }
+        return Ok(if diagnostics.is_empty() { 0 } else { 1 });
+    }
+
+    let env = match &args.env_file {
+        Some(path) => read_env_file(path)?,
+        None => HashMap::new(),
+    };
+    let inputs = match &args.input {
+        Some(input) => serde_json::from_str::<HashMap<String, NodeInput>>(input)
+            .context("--input must be a JSON object of inputs by input node name")?,
+        None => HashMap::new(),
+    };
+
+    graph.setup(&inputs, &env, &HashMap::new(), &RunType::Workshop)?;
+    graph.secrets = env;
+
+    let pipeline_runner = local_pipeline_runner().await?;
+    if let Err(e) = pipeline_runner.check_graph_values(&graph) {
+        print_error(e)?;
+        return Ok(1);
+    }
+
+    let (tx, mut rx) = mpsc::channel(100);
+    let run = pipeline_runner.run(graph, Some(tx));
+    tokio::pin!(run);
+    let result = loop {
+        tokio::select! {
+            Some(chunk) = rx.recv() => print_chunk(chunk),
+            result = &mut run => break result,
+        }
+    };
+    while let Ok(chunk) = rx.try_recv() {
+        print_chunk(chunk);
+    }
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            print_error(e)?;
+            return Ok(1);
+        }
+    };
+
+    println!();
+    let mut outputs = output.output_values().into_iter().collect::<Vec<_>>();
+    outputs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (name, value) in outputs {
+        println!("{}: {}", name, display_value(&value));
+    }
+    let stats = RunTraceStats::from_messages(&output.messages);
+    println!(
+        "Tokens: {}, cost: {}, time: {}ms",
+        stats.total_token_count,
+        stats
+            .approximate_cost
+            .map_or("unknown".to_string(), |cost| format!("${:.6}", cost)),
+        (stats.end_time - stats.start_time).num_milliseconds()
+    );
+    Ok(0)
+}
+
+fn read_env_file(path: &PathBuf) -> Result<HashMap<String, String>> {
+    let mut env = HashMap::new();
+    // Deprecated, but the only function of dotenv which reads a file without setting its
+    // variables in the process environment
+    #[allow(deprecated)]
+    let entries = dotenv::from_path_iter(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    for entry in entries {
+        let (key, value) = entry.with_context(|| format!("Failed to parse {}", path.display()))?;
+        env.insert(key, value);
+    }
+    Ok(env)
+}
+
+/// Runner without a message queue, whose database and semantic search connect on first use
+async fn local_pipeline_runner() -> Result<PipelineRunner> {
+    let db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/laminar".to_string());
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .connect_lazy(&db_url)
+        .context("Invalid DATABASE_URL")?;
+    let db = Arc::new(DB::new(pool));
+
+    let semantic_search_url =
+        env::var("SEMANTIC_SEARCH_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+    let channel = tonic::transport::Endpoint::from_shared(semantic_search_url)
+        .context("Invalid SEMANTIC_SEARCH_URL")?
+        .connect_lazy();
+    let semantic_search = Arc::new(SemanticSearch::new(
+        Arc::new(SemanticSearchClient::new(channel)),
+        db.clone(),
+    ));
+
+    let chunker_runner = Arc::new(ChunkerRunner::new(HashMap::from([(
+        ChunkerType::CharacterSplit,
+        Chunker::CharacterSplit(CharacterSplitChunker {}),
+    )])));
+    let client = http_client::build_client();
+    let language_model =
+        Arc::new(LanguageModelRunner::with_default_providers(client.clone()).await);
+
+    Ok(PipelineRunner::new(
+        language_model,
+        chunker_runner,
+        semantic_search,
+        client,
+        None,
+        node_io::store_from_env(db.clone()),
+        Arc::new(PostgresCheckpointStore::new(db)),
+    ))
+}
+
+/// Print the error as the run endpoint reports it, with the nodes the run stopped at
+fn print_error(e: PipelineRunnerError) -> Result<()> {
+    let error = RunEndpointEventError::new(e, None);
+    eprintln!("{}", serde_json::to_string_pretty(&error)?);
+    Ok(())
+}
+
+fn print_chunk(chunk: StreamChunk) {
+    match chunk {
+        StreamChunk::NodeChunk(chunk) => {
+            print!("{}", display_value(&chunk.content));
+            let _ = io::stdout().flush();
+        }
+        StreamChunk::NodeEnd(end) => {
+            let message = end.message;
+            println!(
+                "\n[{}] {} finished in {}ms",
+                message.node_type,
+                message.node_name,
+                (message.end_time - message.start_time).num_milliseconds()
+            );
+        }
+        _ => {}
+    }
+}
+
+fn display_value(value: &NodeInput) -> String {
+    match value {
+        NodeInput::String(s) => s.clone(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(args(&[])), Ok(None));
+        assert_eq!(parse_args(args(&["--port", "8000"])), Ok(None));
+        assert_eq!(
+            parse_args(args(&[
+                "run",
+                "--pipeline",
+                "pipeline.json",
+                "--input",
+                "{}",
+                "--validate-only"
+            ])),
+            Ok(Some(Command::Run(RunArgs {
+                pipeline: PathBuf::from("pipeline.json"),
+                input: Some("{}".to_string()),
+                env_file: None,
+                validate_only: true,
+            })))
+        );
+        assert!(parse_args(args(&["run", "--input", "{}"])).is_err());
+        assert!(parse_args(args(&["run", "--pipeline"])).is_err());
+    }
+}
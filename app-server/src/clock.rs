@@ -0,0 +1,188 @@
+//! Source of the current time and of sleeps, so that timing logic can run on virtual time
+//!
+//! Code which waits or compares against the current time takes an `Arc<dyn Clock>`, which is
+//! [`SystemClock`] outside tests. Runs get it from their
+//! [`Context`](crate::pipeline::context::Context). Tests use a [`ManualClock`], whose time only
+//! moves when the test advances it, so that a retry backoff of minutes or a rate limit window of
+//! a day is checked without sleeping.
+
+#[cfg(any(test, feature = "testing"))]
+use std::sync::{Mutex, Weak};
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Future which resolves once the duration passed on this clock
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Error of a [`timeout`] which elapsed before the future finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Timed out after {0:?}")]
+pub struct Elapsed(pub Duration);
+
+/// Await the future for at most the duration on the clock
+pub async fn timeout<F: Future>(
+    clock: &dyn Clock,
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    tokio::select! {
+        output = future => Ok(output),
+        _ = clock.sleep(duration) => Err(Elapsed(duration)),
+    }
+}
+
+/// Wall clock time, with sleeps of the Tokio timer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Deadline of a sleep of a [`ManualClock`], unset for sleeps too long to ever end
+#[cfg(any(test, feature = "testing"))]
+type Deadline = Option<DateTime<Utc>>;
+
+/// Clock which stands still until [`ManualClock::advance`] is called
+///
+/// Sleeps end once the clock is advanced to their deadline. Clones share the time, so that a
+/// test keeps a clone to advance the clock it passed on.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<tokio::sync::watch::Sender<DateTime<Utc>>>,
+    /// Deadlines of the sleeps, held by the sleeps so that dropped ones aren't counted
+    deadlines: Arc<Mutex<Vec<Weak<Deadline>>>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        let (now, _) = tokio::sync::watch::channel(start);
+        Self {
+            now: Arc::new(now),
+            deadlines: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let duration = chrono::Duration::from_std(duration).expect("Duration out of range");
+        self.now.send_modify(|now| *now += duration);
+    }
+
+    /// Number of sleeps whose deadline wasn't reached yet
+    pub fn sleepers(&self) -> usize {
+        let now = self.now();
+        let mut deadlines = self.deadlines.lock().unwrap_or_else(|e| e.into_inner());
+        deadlines.retain(|deadline| deadline.strong_count() > 0);
+        deadlines
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|deadline| deadline.map_or(true, |deadline| deadline > now))
+            .count()
+    }
+
+    /// Wait until at least `count` sleeps are pending, e.g. until a spawned task sleeps before
+    /// its retry, so that advancing the clock wakes it
+    pub async fn wait_for_sleepers(&self, count: usize) {
+        while self.sleepers() < count {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Default for ManualClock {
+    /// Clock starting at the Unix epoch
+    fn default() -> Self {
+        Self::new(DateTime::<Utc>::UNIX_EPOCH)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let deadline = Arc::new(
+            chrono::Duration::from_std(duration)
+                .ok()
+                .and_then(|duration| self.now().checked_add_signed(duration)),
+        );
+        // registered before the future is polled, so that the sleep counts as pending at once
+        self.deadlines
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::downgrade(&deadline));
+        let mut now = self.now.subscribe();
+        Box::pin(async move {
+            let _ = now
+                .wait_for(|now| deadline.is_some_and(|deadline| *now >= deadline))
+                .await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manual_clock_sleeps_until_advanced() {
+        let clock = ManualClock::default();
+        let sleeper = {
+            let clock = clock.clone();
+            tokio::spawn(async move { clock.sleep(Duration::from_secs(60)).await })
+        };
+        clock.wait_for_sleepers(1).await;
+
+        clock.advance(Duration::from_secs(59));
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+        assert_eq!(clock.sleepers(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        sleeper.await.unwrap();
+        assert_eq!(clock.sleepers(), 0);
+        assert_eq!(clock.now().timestamp(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let clock = ManualClock::default();
+        assert_eq!(
+            timeout(&clock, Duration::from_secs(1), async { 42 }).await,
+            Ok(42)
+        );
+
+        let pending = {
+            let clock = clock.clone();
+            tokio::spawn(async move {
+                timeout(&clock, Duration::from_secs(5), std::future::pending::<()>()).await
+            })
+        };
+        clock.wait_for_sleepers(1).await;
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(pending.await.unwrap(), Err(Elapsed(Duration::from_secs(5))));
+    }
+}
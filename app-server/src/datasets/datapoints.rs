@@ -1,14 +1,10 @@
-use std::{
-    collections::HashMap,
-    io::{BufReader, Cursor},
-    sync::Arc,
-};
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
-use csv;
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
@@ -19,7 +15,7 @@ use crate::{
     },
 };
 
-#[derive(Serialize, FromRow, Clone, Debug)]
+#[derive(Serialize, FromRow, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Datapoint {
     pub id: Uuid,
@@ -28,40 +24,39 @@ pub struct Datapoint {
     pub target: Value,
 }
 
-impl Datapoint {
-    pub fn try_from_raw_value(dataset_id: Uuid, raw: &Value) -> Option<Self> {
-        match raw {
-            Value::Object(raw_obj) => {
-                // Checks that the object has a `data` field and optionally a `target` field
-                // and no other fields
-                let data = raw_obj.get("data");
-                let target = raw_obj.get("target");
-                if matches!(data, Some(Value::Object(_)))
-                    && ((raw_obj.len() == 2 && matches!(target, Some(Value::Object(_))))
-                        || raw_obj.len() == 1)
+/// Data and target of a raw datapoint, None if it's not an object
+pub fn split_raw_value(raw: &Value) -> Option<(Map<String, Value>, Value)> {
+    match raw {
+        Value::Object(raw_obj) => {
+            // Checks that the object has a `data` field and optionally a `target` field
+            // and no other fields
+            let data = raw_obj.get("data");
+            let target = raw_obj.get("target");
+            match data {
+                Some(Value::Object(data))
+                    if (raw_obj.len() == 2 && matches!(target, Some(Value::Object(_))))
+                        || raw_obj.len() == 1 =>
                 {
-                    Some(Datapoint {
-                        id: Uuid::new_v4(),
-                        dataset_id,
-                        data: data.unwrap().to_owned(),
-                        target: target.cloned().unwrap_or(Value::Object(Default::default())),
-                    })
-                } else {
-                    // Otherwise, dump all the fields into the `data` field
-                    Some(Datapoint {
-                        id: Uuid::new_v4(),
-                        dataset_id,
-                        data: raw.to_owned(),
-                        target: Value::Object(Default::default()),
-                    })
+                    Some((
+                        data.to_owned(),
+                        target.cloned().unwrap_or(Value::Object(Default::default())),
+                    ))
                 }
+                // Otherwise, dump all the fields into the `data` field
+                _ => Some((raw_obj.to_owned(), Value::Object(Default::default()))),
             }
-            _ => None,
         }
+        _ => None,
     }
 }
 
 impl Datapoint {
+    /// Whether the datapoint has the index column, and can be indexed on it
+    pub fn is_indexable(&self, index_column: &String) -> bool {
+        serde_json::from_value::<HashMap<String, NodeInput>>(self.data.clone())
+            .is_ok_and(|data| data.contains_key(index_column))
+    }
+
     /// Turns a datapoint into protobuf datapoint for indexing in semantic search service
     ///
     /// Assumes column_name is there in `data`, so it unwraps the field
@@ -103,16 +98,6 @@ impl From<VectorDBDatapoint> for Datapoint {
     }
 }
 
-pub fn read_bytes_jsonl(bytes: &Vec<u8>) -> Result<Vec<Value>> {
-    let buf = BufReader::new(Cursor::new(bytes.as_slice()));
-    let reader = serde_jsonlines::JsonLinesReader::new(buf);
-
-    reader
-        .read_all::<Value>()
-        .collect::<std::io::Result<Vec<_>>>()
-        .map_err(|e| anyhow::anyhow!("error parsing jsonlines: {}", e))
-}
-
 pub fn read_bytes_json(bytes: &Vec<u8>) -> Result<Vec<Value>> {
     let content = serde_json::from_slice::<Value>(bytes.as_slice())?;
     match content {
@@ -123,74 +108,22 @@ pub fn read_bytes_json(bytes: &Vec<u8>) -> Result<Vec<Value>> {
     }
 }
 
-pub fn read_bytes_csv(bytes: &Vec<u8>) -> Result<Vec<Value>> {
-    let mut reader = csv::Reader::from_reader(bytes.as_slice());
-    let headers = reader.headers()?.clone();
-    let mut result = Vec::new();
-    for record in reader.records() {
-        let record = match record {
-            Ok(r) => r,
-            Err(e) => {
-                log::error!("couldn't read line in CSV, {}", e);
-                continue;
-            }
-        };
-        let mut row = HashMap::new();
-        for i in 0..headers.len() {
-            let header = headers
-                .get(i)
-                .ok_or(anyhow::anyhow!("can't read header at position {}", i))?;
-            let value = record.get(i).unwrap_or_default();
-            row.insert(header.to_string(), value.to_string());
-        }
-        let row_json = match serde_json::to_value(row) {
-            Ok(v) => v,
-            Err(e) => {
-                log::error!("couldn't convert csv row to serde_json::Value, {}", e);
-                continue;
-            }
-        };
-        result.push(row_json);
-    }
-
-    Ok(result)
-}
-
+/// Insert the chunks of an unstructured file as datapoints, structured files are imported
+/// with `import::DatasetImporter`
 pub async fn insert_datapoints_from_file(
     file_bytes: &Vec<u8>,
     filename: &String,
-    is_unstructured_file: bool,
     dataset_id: Uuid,
     db: Arc<DB>,
     file_manager: Arc<crate::files::FileManager>,
 ) -> Result<Vec<Datapoint>> {
-    if !is_unstructured_file {
-        let mut records = None;
-        let extension = filename.split(".").last().unwrap_or_default();
-        if extension == "jsonl" {
-            records = Some(read_bytes_jsonl(&file_bytes)?);
-        } else if extension == "json" {
-            records = Some(read_bytes_json(&file_bytes)?);
-        } else if extension == "csv" {
-            records = Some(read_bytes_csv(&file_bytes)?);
-        }
-
-        if let Some(data) = records {
-            Ok(db::datapoints::insert_raw_data(&db.pool, &dataset_id, &data).await?)
-        } else {
-            Err(anyhow::anyhow!(
-                "Attempting to process file as unstructured even though requested as structured"
-            ))
-        }
-    } else {
-        let vector_db_datapoints = file_manager
-            .chunk_doc(&file_bytes, &filename, dataset_id)
-            .await?;
-        let datapoints = vector_db_datapoints
-            .into_iter()
-            .map(|dp| Datapoint::from(dp))
-            .collect::<Vec<_>>();
-
-        Ok(db::datapoints::insert_datapoints(&db.pool, &dataset_id, datapoints).await?)
-    }
+    let vector_db_datapoints = file_manager
+        .chunk_doc(file_bytes, filename, dataset_id)
+        .await?;
+    let datapoints = vector_db_datapoints
+        .into_iter()
+        .map(Datapoint::from)
+        .collect::<Vec<_>>();
+
+    db::datapoints::insert_datapoints(&db.pool, &dataset_id, datapoints).await
 }
@@ -0,0 +1,466 @@
+//! Import of datapoints from CSV and JSONL files, parsed while they're uploaded
+//!
+//! Rows are typed with the dataset schema, which is inferred from the first
+//! `SCHEMA_SAMPLE_ROWS` rows if the dataset has no columns yet. Malformed rows are skipped and
+//! reported, and the import stops adding rows once the dataset reaches its `DatasetLimits`.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    db::{self, DB},
+    semantic_search::SemanticSearch,
+};
+
+use super::{
+    datapoints::{read_bytes_json, split_raw_value, Datapoint},
+    schema::{DatasetSchema, RowFields},
+    Dataset, DatasetLimits, DatasetUsage,
+};
+
+const SCHEMA_SAMPLE_ROWS: usize = 100;
+const INSERT_BATCH_SIZE: usize = 500;
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ImportFormat {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = filename.rsplit('.').next()?.to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(ImportFormat::Csv),
+            "jsonl" => Some(ImportFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Row of an imported file, with the data fields still to be typed with the schema
+pub struct ImportRow {
+    pub fields: RowFields,
+    pub target: Value,
+}
+
+impl ImportRow {
+    /// Row from a JSON object, split into data and target like other raw datapoints
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let (data, target) =
+            split_raw_value(value).ok_or_else(|| "Row must be a JSON object".to_string())?;
+        Ok(Self {
+            fields: RowFields::Json(data),
+            target,
+        })
+    }
+}
+
+/// Parses rows of a file fed chunk by chunk, each row with its line number in the file
+///
+/// Chunks are split after the last complete row, the rest is kept until the next chunk. CSV
+/// rows end at newlines outside of quoted fields, quotes in fields are escaped by doubling
+/// them, so counting quotes is enough to tell which newlines end rows.
+pub struct RowParser {
+    format: ImportFormat,
+    buffer: Vec<u8>,
+    /// Bytes of the buffer scanned for row ends, and whether they end in a quoted field
+    scanned: usize,
+    in_quotes: bool,
+    /// Lines of the file before the buffer
+    lines: usize,
+    headers: Option<Vec<String>>,
+}
+
+pub type ParsedRow = (usize, Result<ImportRow, String>);
+
+impl RowParser {
+    pub fn new(format: ImportFormat) -> Self {
+        Self {
+            format,
+            buffer: Vec::new(),
+            scanned: 0,
+            in_quotes: false,
+            lines: 0,
+            headers: None,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<ParsedRow> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut end = 0;
+        for (i, byte) in self.buffer.iter().enumerate().skip(self.scanned) {
+            match byte {
+                b'"' if self.format == ImportFormat::Csv => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => end = i + 1,
+                _ => {}
+            }
+        }
+        self.scanned = self.buffer.len() - end;
+
+        let complete = self.buffer.drain(..end).collect::<Vec<_>>();
+        self.parse(&complete)
+    }
+
+    /// Parse the last row, which may not end with a newline
+    pub fn finish(mut self) -> Vec<ParsedRow> {
+        let rest = std::mem::take(&mut self.buffer);
+        self.parse(&rest)
+    }
+
+    fn parse(&mut self, bytes: &[u8]) -> Vec<ParsedRow> {
+        let rows = match self.format {
+            ImportFormat::Csv => self.parse_csv(bytes),
+            ImportFormat::Jsonl => self.parse_jsonl(bytes),
+        };
+        self.lines += bytes.iter().filter(|byte| **byte == b'\n').count();
+        rows
+    }
+
+    fn parse_jsonl(&self, bytes: &[u8]) -> Vec<ParsedRow> {
+        bytes
+            .split(|byte| *byte == b'\n')
+            .enumerate()
+            .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+            .map(|(i, line)| {
+                let row = serde_json::from_slice::<Value>(line)
+                    .map_err(|e| format!("Invalid JSON: {}", e))
+                    .and_then(|value| ImportRow::from_json(&value));
+                (self.lines + i + 1, row)
+            })
+            .collect()
+    }
+
+    fn parse_csv(&mut self, bytes: &[u8]) -> Vec<ParsedRow> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(bytes);
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    let line = e.position().map(|position| position.line()).unwrap_or(0);
+                    rows.push((self.lines + line as usize, Err(e.to_string())));
+                    continue;
+                }
+            };
+            let line = self.lines + record.position().map_or(0, |p| p.line() as usize);
+
+            let Some(headers) = &self.headers else {
+                self.headers = Some(record.iter().map(|header| header.to_string()).collect());
+                continue;
+            };
+            if record.len() != headers.len() {
+                rows.push((
+                    line,
+                    Err(format!(
+                        "Expected {} fields, found {}",
+                        headers.len(),
+                        record.len()
+                    )),
+                ));
+                continue;
+            }
+            let fields = headers
+                .iter()
+                .cloned()
+                .zip(record.iter().map(|field| field.to_string()))
+                .collect();
+            rows.push((
+                line,
+                Ok(ImportRow {
+                    fields: RowFields::Text(fields),
+                    target: Value::Object(Default::default()),
+                }),
+            ));
+        }
+        rows
+    }
+}
+
+/// Rows of a whole structured file, JSON files must contain an array of rows
+pub fn parse_file(filename: &str, bytes: &Vec<u8>) -> Result<Vec<ParsedRow>> {
+    if filename.to_lowercase().ends_with(".json") {
+        let rows = read_bytes_json(bytes)?
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (i + 1, ImportRow::from_json(value)))
+            .collect();
+        return Ok(rows);
+    }
+
+    let format = ImportFormat::from_filename(filename)
+        .ok_or_else(|| anyhow::anyhow!("Structured files must be CSV, JSON or JSONL"))?;
+    let mut parser = RowParser::new(format);
+    let mut rows = parser.feed(bytes);
+    rows.extend(parser.finish());
+    Ok(rows)
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RowError {
+    /// Line of the row in the file starting from 1, or its position in the array of JSON files
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported_rows: usize,
+    pub failed_rows: usize,
+    /// Errors of the first failed rows, up to 100
+    pub errors: Vec<RowError>,
+    /// The dataset reached its row or size limit, the rows after that were not imported
+    pub truncated: bool,
+    /// Schema of the dataset after the import
+    pub schema: DatasetSchema,
+}
+
+/// Adds imported rows to the dataset in batches, indexing them if the dataset is indexed
+pub struct DatasetImporter {
+    dataset: Dataset,
+    db: Arc<DB>,
+    semantic_search: Arc<SemanticSearch>,
+    limits: DatasetLimits,
+    usage: DatasetUsage,
+    /// Rows buffered to infer the schema from, None once the dataset has columns
+    sample: Option<Vec<(usize, ImportRow)>>,
+    batch: Vec<Datapoint>,
+    created_at: DateTime<Utc>,
+    /// Datapoints inserted so far, only kept if they're returned
+    inserted: Option<Vec<Datapoint>>,
+    report: ImportReport,
+}
+
+impl DatasetImporter {
+    /// `keep_datapoints` to return the inserted datapoints from `finish`
+    pub async fn new(
+        dataset: Dataset,
+        db: Arc<DB>,
+        semantic_search: Arc<SemanticSearch>,
+        keep_datapoints: bool,
+    ) -> Result<Self> {
+        let usage = db::datapoints::get_dataset_usage(&db.pool, dataset.id).await?;
+        let sample = dataset.schema.columns.is_empty().then(Vec::new);
+        let schema = dataset.schema.clone();
+
+        Ok(Self {
+            dataset,
+            db,
+            semantic_search,
+            limits: DatasetLimits::from_env(),
+            usage,
+            sample,
+            batch: Vec::new(),
+            created_at: Utc::now(),
+            inserted: keep_datapoints.then(Vec::new),
+            report: ImportReport {
+                imported_rows: 0,
+                failed_rows: 0,
+                errors: Vec::new(),
+                truncated: false,
+                schema,
+            },
+        })
+    }
+
+    /// Add columns to the schema before the rows are imported, instead of inferring them
+    pub fn set_schema(&mut self, schema: &DatasetSchema) -> Result<(), String> {
+        self.report.schema.merge(schema)?;
+        if !self.report.schema.columns.is_empty() {
+            self.sample = None;
+        }
+        Ok(())
+    }
+
+    /// The dataset reached its limits, no more rows are added
+    pub fn is_full(&self) -> bool {
+        self.report.truncated
+    }
+
+    pub async fn push(&mut self, (line, row): ParsedRow) -> Result<()> {
+        if self.report.truncated {
+            return Ok(());
+        }
+        let row = match row {
+            Ok(row) => row,
+            Err(message) => {
+                self.fail(line, message);
+                return Ok(());
+            }
+        };
+
+        match &mut self.sample {
+            Some(sample) => {
+                sample.push((line, row));
+                if sample.len() >= SCHEMA_SAMPLE_ROWS {
+                    self.add_sample().await?;
+                }
+                Ok(())
+            }
+            None => self.add(line, row).await,
+        }
+    }
+
+    /// Insert the remaining rows and store the schema
+    pub async fn finish(mut self) -> Result<(ImportReport, Vec<Datapoint>)> {
+        self.add_sample().await?;
+        self.insert_batch().await?;
+        if self.report.schema != self.dataset.schema {
+            db::datasets::update_dataset_schema(
+                &self.db.pool,
+                self.dataset.id,
+                &self.report.schema,
+            )
+            .await?;
+        }
+
+        Ok((self.report, self.inserted.unwrap_or_default()))
+    }
+
+    /// Infer the schema from the sample, then add its rows
+    async fn add_sample(&mut self) -> Result<()> {
+        let Some(sample) = self.sample.take() else {
+            return Ok(());
+        };
+        self.report.schema = DatasetSchema::infer(sample.iter().map(|(_, row)| &row.fields));
+        for (line, row) in sample {
+            self.add(line, row).await?;
+        }
+        Ok(())
+    }
+
+    async fn add(&mut self, line: usize, row: ImportRow) -> Result<()> {
+        if self.report.truncated {
+            return Ok(());
+        }
+        let data = match self.report.schema.apply(row.fields) {
+            Ok(data) => data,
+            Err(message) => {
+                self.fail(line, message);
+                return Ok(());
+            }
+        };
+        let datapoint = Datapoint {
+            id: Uuid::new_v4(),
+            dataset_id: self.dataset.id,
+            data: Value::Object(data),
+            target: row.target,
+        };
+
+        let size = datapoint_size(&datapoint);
+        if !self.limits.allows(&self.usage, 1, size) {
+            self.report.truncated = true;
+            return Ok(());
+        }
+        self.usage.rows += 1;
+        self.usage.size += size as i64;
+        self.batch.push(datapoint);
+
+        if self.batch.len() >= INSERT_BATCH_SIZE {
+            self.insert_batch().await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_batch(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let datapoints = db::datapoints::insert_datapoint_batch(
+            &self.db.pool,
+            &self.dataset.id,
+            batch,
+            self.created_at,
+            self.report.imported_rows as i64,
+        )
+        .await?;
+        self.report.imported_rows += datapoints.len();
+
+        if self.dataset.indexed_on.is_some() {
+            self.dataset
+                .index_new_points(
+                    datapoints.clone(),
+                    self.semantic_search.clone(),
+                    self.dataset.project_id.to_string(),
+                    self.dataset.indexed_on.clone(),
+                )
+                .await?;
+        }
+        if let Some(inserted) = &mut self.inserted {
+            inserted.extend(datapoints);
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, line: usize, message: String) {
+        self.report.failed_rows += 1;
+        if self.report.errors.len() < MAX_REPORTED_ERRORS {
+            self.report.errors.push(RowError { line, message });
+        }
+    }
+}
+
+/// Size of the datapoint's data and target as JSON, counted towards the dataset size limit
+pub fn datapoint_size(datapoint: &Datapoint) -> u64 {
+    (datapoint.data.to_string().len() + datapoint.target.to_string().len()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv_in_chunks() {
+        let file = b"name,note\nalice,\"multi\nline, with \"\"quotes\"\"\"\nbob\ncarol,done";
+        let mut parser = RowParser::new(ImportFormat::Csv);
+        let mut rows = Vec::new();
+        for chunk in file.chunks(7) {
+            rows.extend(parser.feed(chunk));
+        }
+        rows.extend(parser.finish());
+
+        let lines = rows.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 4, 5]);
+        let Ok(ImportRow {
+            fields: RowFields::Text(fields),
+            ..
+        }) = &rows[0].1
+        else {
+            panic!("Expected a CSV row");
+        };
+        assert_eq!(fields[1].1, "multi\nline, with \"quotes\"");
+        assert!(rows[1].1.is_err()); // bob has one field
+        assert!(rows[2].1.is_ok());
+    }
+
+    #[test]
+    fn test_parse_jsonl_in_chunks() {
+        let file = b"{\"data\": {\"a\": 1}, \"target\": {\"b\": 2}}\n\nnot json\n[1]\n{\"a\": 3}";
+        let mut parser = RowParser::new(ImportFormat::Jsonl);
+        let mut rows = parser.feed(&file[..20]);
+        rows.extend(parser.feed(&file[20..]));
+        rows.extend(parser.finish());
+
+        let lines = rows.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 3, 4, 5]);
+        assert_eq!(rows[0].1.as_ref().unwrap().target["b"], 2);
+        assert!(rows[1].1.is_err());
+        assert!(rows[2].1.is_err()); // not an object
+        assert!(rows[3].1.is_ok());
+    }
+}
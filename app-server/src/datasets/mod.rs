@@ -1,16 +1,25 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{env, sync::Arc};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::{pipeline::nodes::NodeInput, semantic_search::SemanticSearch};
+use crate::semantic_search::SemanticSearch;
+
+use self::schema::DatasetSchema;
 
 pub mod datapoints;
+pub mod import;
+pub mod sampling;
+pub mod schema;
 pub mod utils;
 
-#[derive(Debug, Clone, Deserialize, Serialize, FromRow)]
+const DEFAULT_MAX_ROWS: u64 = 100_000;
+const DEFAULT_MAX_SIZE_BYTES: u64 = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, Deserialize, Serialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Dataset {
     pub id: Uuid,
@@ -19,6 +28,43 @@ pub struct Dataset {
     pub project_id: Uuid,
     #[serde(default)]
     pub indexed_on: Option<String>,
+    #[serde(default)]
+    #[sqlx(json)]
+    pub schema: DatasetSchema,
+}
+
+/// Number of datapoints of a dataset, and the size of their data and targets as JSON
+#[derive(Debug, Clone, Copy, Default, FromRow)]
+pub struct DatasetUsage {
+    pub rows: i64,
+    pub size: i64,
+}
+
+/// Limits on datapoints per dataset, set with `DATASET_MAX_ROWS` and `DATASET_MAX_SIZE_BYTES`
+#[derive(Debug, Clone, Copy)]
+pub struct DatasetLimits {
+    pub max_rows: u64,
+    pub max_size: u64,
+}
+
+impl DatasetLimits {
+    pub fn from_env() -> Self {
+        Self {
+            max_rows: env::var("DATASET_MAX_ROWS")
+                .ok()
+                .and_then(|rows| rows.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_MAX_ROWS),
+            max_size: env::var("DATASET_MAX_SIZE_BYTES")
+                .ok()
+                .and_then(|size| size.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_MAX_SIZE_BYTES),
+        }
+    }
+
+    /// Whether the datapoints can be added to a dataset with the usage
+    pub fn allows(&self, usage: &DatasetUsage, rows: u64, size: u64) -> bool {
+        usage.rows as u64 + rows <= self.max_rows && usage.size as u64 + size <= self.max_size
+    }
 }
 
 impl Dataset {
@@ -30,10 +76,9 @@ impl Dataset {
         new_index_column: Option<String>,
     ) -> anyhow::Result<()> {
         if let Some(index_column) = &new_index_column {
-            let indexable_datapoints = datapoints.iter().filter(|datapoint| {
-                serde_json::from_value::<HashMap<String, NodeInput>>(datapoint.data.clone())
-                    .is_ok_and(|data| data.contains_key(index_column))
-            });
+            let indexable_datapoints = datapoints
+                .iter()
+                .filter(|datapoint| datapoint.is_indexable(index_column));
 
             let vector_db_datapoints = indexable_datapoints
                 .clone()
@@ -0,0 +1,181 @@
+//! Subsets of datasets for runs over them, e.g. evaluations
+//!
+//! Samples are read without loading the whole dataset: the first rows with a limit, random
+//! samples by reservoir sampling over a stream of datapoint ids, and stratified samples with a
+//! query ranking the rows of each distinct column value. Random and stratified samples are
+//! reproducible with the same seed, as long as the dataset doesn't change.
+
+use anyhow::Result;
+use futures_util::TryStreamExt;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db;
+
+use super::datapoints::Datapoint;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "strategy", rename_all = "camelCase")]
+pub enum Sampling {
+    /// First `n` rows, in the order of the dataset
+    Head { n: usize },
+    /// `n` rows picked uniformly at random
+    Random {
+        n: usize,
+        /// Generated if not set, so that the sample can be reproduced
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    /// Up to `n` random rows per distinct value of the data column, rows without the column
+    /// are a group of their own
+    Stratified {
+        column: String,
+        n: usize,
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+}
+
+impl Sampling {
+    /// Check the sample size, which is at most `max_rows`, the row limit of datasets
+    pub fn validate(&self, max_rows: u64) -> Result<()> {
+        let n = match self {
+            Self::Head { n } | Self::Random { n, .. } | Self::Stratified { n, .. } => *n,
+        };
+        if n == 0 {
+            return Err(anyhow::anyhow!("Sample size must be positive"));
+        }
+        if n as u64 > max_rows {
+            return Err(anyhow::anyhow!(
+                "Sample size must be at most {}, the row limit of datasets",
+                max_rows
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set the seed if it's not set, seeds fit into 32 bits to survive JSON numbers of any client
+    pub fn resolve_seed(&mut self) {
+        if let Self::Random { seed, .. } | Self::Stratified { seed, .. } = self {
+            seed.get_or_insert_with(|| rand::random::<u32>() as u64);
+        }
+    }
+}
+
+/// Items a reservoir allocates memory for upfront, it grows past them as the stream fills it
+const PREALLOCATED_ITEMS: usize = 1024;
+
+/// Uniform sample of a stream of unknown length, keeping only the sampled items in memory
+pub struct Reservoir<T> {
+    size: usize,
+    seen: usize,
+    items: Vec<T>,
+    rng: StdRng,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(size: usize, seed: u64) -> Self {
+        Self {
+            size,
+            seen: 0,
+            items: Vec::with_capacity(size.min(PREALLOCATED_ITEMS)),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() < self.size {
+            self.items.push(item);
+        } else {
+            let index = self.rng.gen_range(0..=self.seen);
+            if index < self.size {
+                self.items[index] = item;
+            }
+        }
+        self.seen += 1;
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+/// Datapoints of the sample, in the order of the dataset
+pub async fn sample_datapoints(
+    pool: &PgPool,
+    dataset_id: Uuid,
+    sampling: &Sampling,
+) -> Result<Vec<Datapoint>> {
+    match sampling {
+        Sampling::Head { n } => {
+            db::datapoints::get_datapoints_paginated(pool, dataset_id, i64::try_from(*n)?, 0).await
+        }
+        Sampling::Random { n, seed } => {
+            let mut reservoir = Reservoir::new(*n, seed.unwrap_or_default());
+            let mut ids = db::datapoints::stream_datapoint_ids(pool, dataset_id);
+            while let Some(id) = ids.try_next().await? {
+                reservoir.push(id);
+            }
+            drop(ids);
+            db::datapoints::get_datapoints_by_ids(pool, dataset_id, &reservoir.into_items()).await
+        }
+        Sampling::Stratified { column, n, seed } => {
+            db::datapoints::get_stratified_datapoints(
+                pool,
+                dataset_id,
+                column,
+                i64::try_from(*n)?,
+                seed.unwrap_or_default(),
+            )
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(size: usize, seed: u64, items: usize) -> Vec<usize> {
+        let mut reservoir = Reservoir::new(size, seed);
+        (0..items).for_each(|item| reservoir.push(item));
+        reservoir.into_items()
+    }
+
+    #[test]
+    fn test_reservoir_is_reproducible() {
+        let first = sample(10, 42, 1000);
+        assert_eq!(first.len(), 10);
+        assert_eq!(first, sample(10, 42, 1000));
+        assert_ne!(first, sample(10, 43, 1000));
+        assert!(first.iter().any(|item| *item >= 10));
+
+        // streams shorter than the sample are kept whole
+        assert_eq!(sample(10, 42, 3), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_resolve_seed() {
+        let mut sampling =
+            serde_json::from_value::<Sampling>(serde_json::json!({"strategy": "random", "n": 5}))
+                .unwrap();
+        sampling.resolve_seed();
+        let Sampling::Random {
+            seed: Some(seed), ..
+        } = sampling
+        else {
+            panic!("seed is not resolved");
+        };
+        assert!(seed <= u32::MAX as u64);
+
+        let mut head = Sampling::Head { n: 5 };
+        head.resolve_seed();
+        assert_eq!(head, Sampling::Head { n: 5 });
+        assert!(Sampling::Head { n: 0 }.validate(100).is_err());
+        assert!(Sampling::Head { n: 100 }.validate(100).is_ok());
+        assert!(Sampling::Head { n: usize::MAX }.validate(100).is_err());
+    }
+}
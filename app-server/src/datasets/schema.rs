@@ -0,0 +1,316 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+/// Type of the values of a dataset column, null values are allowed in any column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    String,
+    Number,
+    Boolean,
+    /// Any JSON value, e.g. objects, arrays, or values of mixed types
+    Json,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColumnType::String => "string",
+            ColumnType::Number => "number",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ColumnType {
+    fn of_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => None,
+            Value::String(_) => Some(ColumnType::String),
+            Value::Number(_) => Some(ColumnType::Number),
+            Value::Bool(_) => Some(ColumnType::Boolean),
+            Value::Array(_) | Value::Object(_) => Some(ColumnType::Json),
+        }
+    }
+
+    /// Type of a CSV field, empty fields are null. Numbers are parsed as JSON numbers, so that
+    /// e.g. zip codes with leading zeros stay strings.
+    fn of_text(text: &str) -> Option<Self> {
+        if text.is_empty() {
+            None
+        } else if serde_json::from_str::<serde_json::Number>(text).is_ok() {
+            Some(ColumnType::Number)
+        } else if text == "true" || text == "false" {
+            Some(ColumnType::Boolean)
+        } else {
+            Some(ColumnType::String)
+        }
+    }
+
+    pub fn accepts(&self, value: &Value) -> bool {
+        match ColumnType::of_value(value) {
+            None => true,
+            Some(value_type) => *self == ColumnType::Json || *self == value_type,
+        }
+    }
+
+    fn parse_text(&self, text: &str) -> Result<Value, String> {
+        if text.is_empty() && *self != ColumnType::String {
+            return Ok(Value::Null);
+        }
+        match self {
+            ColumnType::String => Ok(Value::String(text.to_string())),
+            ColumnType::Number => serde_json::from_str::<serde_json::Number>(text)
+                .map(Value::Number)
+                .map_err(|_| format!("'{}' is not a number", text)),
+            ColumnType::Boolean => match text {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(format!("'{}' is not a boolean", text)),
+            },
+            ColumnType::Json => {
+                serde_json::from_str::<Value>(text).map_err(|e| format!("Invalid JSON: {}", e))
+            }
+        }
+    }
+}
+
+/// Fields of an imported row, CSV fields are typed with the schema
+pub enum RowFields {
+    Text(Vec<(String, String)>),
+    Json(Map<String, Value>),
+}
+
+impl RowFields {
+    fn types(&self) -> Vec<(&str, Option<ColumnType>)> {
+        match self {
+            RowFields::Text(fields) => fields
+                .iter()
+                .map(|(name, text)| (name.as_str(), ColumnType::of_text(text)))
+                .collect(),
+            RowFields::Json(fields) => fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), ColumnType::of_value(value)))
+                .collect(),
+        }
+    }
+
+    /// Type of columns with values of different types
+    fn mixed_type(&self) -> ColumnType {
+        match self {
+            RowFields::Text(_) => ColumnType::String,
+            RowFields::Json(_) => ColumnType::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DatasetColumn {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: ColumnType,
+}
+
+/// Columns of the `data` of the dataset's datapoints
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DatasetSchema {
+    pub columns: Vec<DatasetColumn>,
+}
+
+impl DatasetSchema {
+    pub fn column_type(&self, name: &str) -> Option<ColumnType> {
+        self.columns
+            .iter()
+            .find(|column| column.name == name)
+            .map(|column| column.column_type)
+    }
+
+    /// Schema of the sample rows, a column has the type all its values share, or the mixed type
+    /// of its rows if they differ. Columns with only nulls are left out until they have values.
+    pub fn infer<'a>(rows: impl Iterator<Item = &'a RowFields>) -> Self {
+        let mut schema = DatasetSchema::default();
+        for row in rows {
+            for (name, value_type) in row.types() {
+                let Some(value_type) = value_type else {
+                    continue;
+                };
+                match schema.columns.iter_mut().find(|column| column.name == name) {
+                    Some(column) if column.column_type != value_type => {
+                        column.column_type = row.mixed_type();
+                    }
+                    Some(_) => {}
+                    None => schema.columns.push(DatasetColumn {
+                        name: name.to_string(),
+                        column_type: value_type,
+                    }),
+                }
+            }
+        }
+        schema
+    }
+
+    /// Add the columns of the other schema, fails if a column has a different type here
+    pub fn merge(&mut self, other: &DatasetSchema) -> Result<(), String> {
+        for column in &other.columns {
+            match self.column_type(&column.name) {
+                Some(column_type) if column_type != column.column_type => {
+                    return Err(format!(
+                        "Column '{}' is already of type {}",
+                        column.name, column_type
+                    ));
+                }
+                Some(_) => {}
+                None => self.columns.push(column.clone()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Typed data of the row, columns which aren't in the schema yet are added with the type
+    /// of their value
+    pub fn apply(&mut self, fields: RowFields) -> Result<Map<String, Value>, String> {
+        let mut new_columns = Vec::new();
+        let data = match fields {
+            RowFields::Text(fields) => {
+                let mut data = Map::new();
+                for (name, text) in fields {
+                    let column_type = match self.column_type(&name) {
+                        Some(column_type) => column_type,
+                        None => {
+                            let column_type = ColumnType::of_text(&text);
+                            if let Some(column_type) = column_type {
+                                new_columns.push(DatasetColumn {
+                                    name: name.clone(),
+                                    column_type,
+                                });
+                            }
+                            column_type.unwrap_or(ColumnType::String)
+                        }
+                    };
+                    let value = column_type
+                        .parse_text(&text)
+                        .map_err(|e| format!("Column '{}': {}", name, e))?;
+                    data.insert(name, value);
+                }
+                data
+            }
+            RowFields::Json(data) => {
+                for (name, value) in &data {
+                    match self.column_type(name) {
+                        Some(column_type) if !column_type.accepts(value) => {
+                            return Err(format!(
+                                "Column '{}' must be of type {}",
+                                name, column_type
+                            ));
+                        }
+                        Some(_) => {}
+                        None => {
+                            if let Some(column_type) = ColumnType::of_value(value) {
+                                new_columns.push(DatasetColumn {
+                                    name: name.clone(),
+                                    column_type,
+                                });
+                            }
+                        }
+                    }
+                }
+                data
+            }
+        };
+
+        self.columns.extend(new_columns);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn text_row(fields: &[(&str, &str)]) -> RowFields {
+        RowFields::Text(
+            fields
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_infer_text_columns() {
+        let rows = [
+            text_row(&[("id", "1"), ("zip", "01234"), ("ok", "true"), ("note", "")]),
+            text_row(&[
+                ("id", "2.5"),
+                ("zip", "5678"),
+                ("ok", "false"),
+                ("note", ""),
+            ]),
+        ];
+        let schema = DatasetSchema::infer(rows.iter());
+
+        assert_eq!(schema.column_type("id"), Some(ColumnType::Number));
+        assert_eq!(schema.column_type("zip"), Some(ColumnType::String));
+        assert_eq!(schema.column_type("ok"), Some(ColumnType::Boolean));
+        assert_eq!(schema.column_type("note"), None);
+    }
+
+    #[test]
+    fn test_apply_json_rows() {
+        let rows = [RowFields::Json(
+            json!({"question": "hi", "score": 1, "tags": ["a"]})
+                .as_object()
+                .unwrap()
+                .clone(),
+        )];
+        let mut schema = DatasetSchema::infer(rows.iter());
+        assert_eq!(schema.column_type("tags"), Some(ColumnType::Json));
+
+        let invalid = json!({"question": "hi", "score": "high"});
+        assert!(schema
+            .apply(RowFields::Json(invalid.as_object().unwrap().clone()))
+            .is_err());
+
+        let valid = json!({"question": null, "score": 2.5, "answer": "hello"});
+        assert!(schema
+            .apply(RowFields::Json(valid.as_object().unwrap().clone()))
+            .is_ok());
+        assert_eq!(schema.column_type("answer"), Some(ColumnType::String));
+    }
+
+    #[test]
+    fn test_apply_text_rows() {
+        let mut schema = DatasetSchema {
+            columns: vec![
+                DatasetColumn {
+                    name: "count".to_string(),
+                    column_type: ColumnType::Number,
+                },
+                DatasetColumn {
+                    name: "meta".to_string(),
+                    column_type: ColumnType::Json,
+                },
+            ],
+        };
+
+        let data = schema
+            .apply(text_row(&[
+                ("count", "3"),
+                ("meta", r#"{"a": 1}"#),
+                ("name", "x"),
+            ]))
+            .unwrap();
+        assert_eq!(
+            Value::Object(data),
+            json!({"count": 3, "meta": {"a": 1}, "name": "x"})
+        );
+        assert!(schema.apply(text_row(&[("count", "three")])).is_err());
+    }
+}
@@ -1,37 +1,167 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
 
 use crate::cache::Cache;
 
+use super::workspace::WorkspaceId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// Run pipelines and report traces and evaluations
+    Run,
+    /// Read traces and events
+    ReadTraces,
+    /// Full access, includes all other scopes
+    Admin,
+}
+
+impl std::fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyScope::Run => write!(f, "run"),
+            ApiKeyScope::ReadTraces => write!(f, "read_traces"),
+            ApiKeyScope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Project API key without the key itself, only its hash is stored
 #[derive(Debug, Clone, Deserialize, Serialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct ProjectApiKey {
-    pub value: String,
+    pub id: Uuid,
     pub project_id: Uuid,
+    /// Workspace of the project, whose limits the key's requests and runs count towards
+    pub workspace_id: WorkspaceId,
     pub name: Option<String>,
+    /// first and last characters of the key to tell keys apart
+    pub shorthand: String,
+    pub scopes: Vec<String>,
+    /// If None, the key can run all pipelines of the project
+    pub pipeline_ids: Option<Vec<Uuid>>,
+    pub requests_per_minute: Option<i64>,
+    pub tokens_per_day: Option<i64>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ProjectApiKey {
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        self.scopes
+            .iter()
+            .any(|s| *s == scope.to_string() || *s == ApiKeyScope::Admin.to_string())
+    }
+
+    pub fn can_run_pipeline(&self, pipeline_id: &Uuid) -> bool {
+        match &self.pipeline_ids {
+            Some(pipeline_ids) => pipeline_ids.contains(pipeline_id),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectApiKeyUsage {
+    pub api_key_id: Uuid,
+    pub date: NaiveDate,
+    pub request_count: i64,
+    pub token_count: i64,
+}
+
+/// Settings of a new key, the key value itself is generated
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeySettings {
+    pub name: Option<String>,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<ApiKeyScope>,
+    #[serde(default)]
+    pub pipeline_ids: Option<Vec<Uuid>>,
+    #[serde(default)]
+    pub requests_per_minute: Option<i64>,
+    #[serde(default)]
+    pub tokens_per_day: Option<i64>,
+}
+
+fn default_scopes() -> Vec<ApiKeyScope> {
+    vec![ApiKeyScope::Admin]
+}
+
+pub fn hash_api_key(value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(value.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn api_key_shorthand(value: &str) -> String {
+    format!("{}...{}", &value[..4], &value[value.len() - 4..])
 }
 
 pub async fn create_project_api_key(
     db: &PgPool,
-    api_key: &ProjectApiKey,
+    value: &str,
+    project_id: Uuid,
+    settings: &ApiKeySettings,
     cache: Arc<Cache>,
-) -> Result<()> {
-    sqlx::query("INSERT INTO project_api_keys (value, project_id, name) VALUES ($1, $2, $3);")
-        .bind(&api_key.value)
-        .bind(api_key.project_id)
-        .bind(&api_key.name)
-        .execute(db)
-        .await?;
+) -> Result<ProjectApiKey> {
+    let hash = hash_api_key(value);
+    let api_key = sqlx::query_as::<_, ProjectApiKey>(
+        "WITH inserted AS (
+            INSERT INTO project_api_keys (
+                hash,
+                shorthand,
+                project_id,
+                name,
+                scopes,
+                pipeline_ids,
+                requests_per_minute,
+                tokens_per_day
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+        )
+        SELECT
+            inserted.id,
+            inserted.project_id,
+            projects.workspace_id,
+            inserted.name,
+            inserted.shorthand,
+            inserted.scopes,
+            inserted.pipeline_ids,
+            inserted.requests_per_minute,
+            inserted.tokens_per_day,
+            inserted.last_used_at
+        FROM
+            inserted
+            JOIN projects ON projects.id = inserted.project_id",
+    )
+    .bind(&hash)
+    .bind(api_key_shorthand(value))
+    .bind(project_id)
+    .bind(&settings.name)
+    .bind(
+        settings
+            .scopes
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>(),
+    )
+    .bind(&settings.pipeline_ids)
+    .bind(settings.requests_per_minute)
+    .bind(settings.tokens_per_day)
+    .fetch_one(db)
+    .await?;
 
-    let _ = cache
-        .insert::<ProjectApiKey>(api_key.value.clone(), api_key)
-        .await;
+    let _ = cache.insert::<ProjectApiKey>(hash, &api_key).await;
 
-    Ok(())
+    Ok(api_key)
 }
 
 pub async fn get_api_keys_for_project(
@@ -40,13 +170,23 @@ pub async fn get_api_keys_for_project(
 ) -> Result<Vec<ProjectApiKey>> {
     let api_keys = sqlx::query_as::<_, ProjectApiKey>(
         "SELECT
-            project_api_keys.value,
+            project_api_keys.id,
             project_api_keys.project_id,
-            project_api_keys.name
+            projects.workspace_id,
+            project_api_keys.name,
+            project_api_keys.shorthand,
+            project_api_keys.scopes,
+            project_api_keys.pipeline_ids,
+            project_api_keys.requests_per_minute,
+            project_api_keys.tokens_per_day,
+            project_api_keys.last_used_at
         FROM
             project_api_keys
+            JOIN projects ON projects.id = project_api_keys.project_id
         WHERE
-            project_api_keys.project_id = $1",
+            project_api_keys.project_id = $1
+        ORDER BY
+            project_api_keys.created_at",
     )
     .bind(project_id)
     .fetch_all(db)
@@ -60,7 +200,8 @@ pub async fn get_api_key(
     api_key: &String,
     cache: Arc<Cache>,
 ) -> Result<ProjectApiKey> {
-    let cache_res = cache.get::<ProjectApiKey>(api_key).await;
+    let hash = hash_api_key(api_key);
+    let cache_res = cache.get::<ProjectApiKey>(&hash).await;
     match cache_res {
         Ok(Some(api_key)) => return Ok(api_key),
         Ok(None) => {}
@@ -69,23 +210,29 @@ pub async fn get_api_key(
 
     let api_key = match sqlx::query_as::<_, ProjectApiKey>(
         "SELECT
-            project_api_keys.value,
+            project_api_keys.id,
             project_api_keys.project_id,
-            project_api_keys.name
+            projects.workspace_id,
+            project_api_keys.name,
+            project_api_keys.shorthand,
+            project_api_keys.scopes,
+            project_api_keys.pipeline_ids,
+            project_api_keys.requests_per_minute,
+            project_api_keys.tokens_per_day,
+            project_api_keys.last_used_at
         FROM
             project_api_keys
+            JOIN projects ON projects.id = project_api_keys.project_id
         WHERE
-            project_api_keys.value = $1",
+            project_api_keys.hash = $1",
     )
-    .bind(api_key)
+    .bind(&hash)
     .fetch_optional(db)
     .await
     {
         Ok(None) => Err(anyhow::anyhow!("invalid project API key")),
         Ok(Some(api_key_meta)) => {
-            let _ = cache
-                .insert::<ProjectApiKey>(api_key.clone(), &api_key_meta)
-                .await;
+            let _ = cache.insert::<ProjectApiKey>(hash, &api_key_meta).await;
             Ok(api_key_meta)
         }
         Err(e) => Err(e.into()),
@@ -94,11 +241,114 @@ pub async fn get_api_key(
     Ok(api_key)
 }
 
-pub async fn delete_api_key(pool: &PgPool, api_key: &String, project_id: &Uuid) -> Result<()> {
-    sqlx::query("DELETE FROM project_api_keys WHERE value = $1 AND project_id = $2")
-        .bind(api_key)
-        .bind(project_id)
-        .execute(pool)
-        .await?;
+/// Delete the API key, returns its hash to invalidate the cache
+pub async fn delete_api_key(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    project_id: &Uuid,
+) -> Result<Option<String>> {
+    let hash = sqlx::query_scalar::<_, String>(
+        "DELETE FROM project_api_keys WHERE id = $1 AND project_id = $2 RETURNING hash",
+    )
+    .bind(api_key_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(hash)
+}
+
+/// Add a request and the tokens it used to today's usage of the key
+pub async fn record_api_key_usage(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    request_count: i64,
+    token_count: i64,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO project_api_key_usage (api_key_id, request_count, token_count)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (api_key_id, date) DO UPDATE SET
+            request_count = project_api_key_usage.request_count + $2,
+            token_count = project_api_key_usage.token_count + $3",
+    )
+    .bind(api_key_id)
+    .bind(request_count)
+    .bind(token_count)
+    .execute(pool)
+    .await?;
+
+    if request_count > 0 {
+        sqlx::query("UPDATE project_api_keys SET last_used_at = now() WHERE id = $1")
+            .bind(api_key_id)
+            .execute(pool)
+            .await?;
+    }
+
     Ok(())
 }
+
+pub async fn get_api_key_usage(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    project_id: &Uuid,
+    past_days: i64,
+) -> Result<Vec<ProjectApiKeyUsage>> {
+    let usage = sqlx::query_as::<_, ProjectApiKeyUsage>(
+        "SELECT
+            project_api_key_usage.api_key_id,
+            project_api_key_usage.date,
+            project_api_key_usage.request_count,
+            project_api_key_usage.token_count
+        FROM
+            project_api_key_usage
+        JOIN
+            project_api_keys ON project_api_keys.id = project_api_key_usage.api_key_id
+        WHERE
+            project_api_key_usage.api_key_id = $1
+            AND project_api_keys.project_id = $2
+            AND project_api_key_usage.date > CURRENT_DATE - $3::integer
+        ORDER BY
+            project_api_key_usage.date DESC",
+    )
+    .bind(api_key_id)
+    .bind(project_id)
+    .bind(past_days)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(usage)
+}
+
+pub async fn get_api_key_token_count_today(pool: &PgPool, api_key_id: &Uuid) -> Result<i64> {
+    let token_count = sqlx::query_scalar::<_, i64>(
+        "SELECT token_count FROM project_api_key_usage
+        WHERE api_key_id = $1 AND date = CURRENT_DATE",
+    )
+    .bind(api_key_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(token_count.unwrap_or(0))
+}
+
+/// Tokens used today by the keys of all projects of the workspace
+pub async fn get_workspace_token_count_today(
+    pool: &PgPool,
+    workspace_id: &WorkspaceId,
+) -> Result<i64> {
+    let token_count = sqlx::query_scalar::<_, i64>(
+        "SELECT COALESCE(SUM(project_api_key_usage.token_count), 0)::bigint
+        FROM
+            project_api_key_usage
+            JOIN project_api_keys ON project_api_keys.id = project_api_key_usage.api_key_id
+            JOIN projects ON projects.id = project_api_keys.project_id
+        WHERE
+            projects.workspace_id = $1
+            AND project_api_key_usage.date = CURRENT_DATE",
+    )
+    .bind(workspace_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(token_count)
+}
@@ -0,0 +1,183 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+pub struct ClaimedCheckpoint {
+    pub generation: i32,
+    pub paused_task_id: Option<Uuid>,
+    pub run_store: Option<Value>,
+}
+
+/// Start checkpoints of the run, dropping snapshots of an earlier run with the id. Returns the
+/// generation snapshots are written with.
+pub async fn start_checkpoints(pool: &PgPool, run_id: &Uuid, graph_hash: &str) -> Result<i32> {
+    let mut tx = pool.begin().await?;
+    let generation = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO run_checkpoints (run_id, graph_hash)
+        VALUES ($1, $2)
+        ON CONFLICT (run_id) DO UPDATE SET
+            graph_hash = EXCLUDED.graph_hash,
+            status = 'running',
+            paused_task_id = NULL,
+            run_store = NULL,
+            generation = run_checkpoints.generation + 1,
+            updated_at = now()
+        RETURNING generation",
+    )
+    .bind(run_id)
+    .bind(graph_hash)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM run_checkpoint_states WHERE run_id = $1")
+        .bind(run_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(generation)
+}
+
+/// Write snapshots of tasks, and the run store if it's set, in a transaction, unless the run was
+/// claimed by a later generation. A task's snapshot only replaces one with a lower seq.
+pub async fn write_snapshots(
+    pool: &PgPool,
+    run_id: &Uuid,
+    generation: i32,
+    task_ids: Vec<Uuid>,
+    seqs: Vec<i64>,
+    snapshots: Vec<Value>,
+    run_store: Option<Value>,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let current_generation = sqlx::query_scalar::<_, i32>(
+        "SELECT generation FROM run_checkpoints WHERE run_id = $1 FOR UPDATE",
+    )
+    .bind(run_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if current_generation != Some(generation) {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        "INSERT INTO run_checkpoint_states (run_id, task_id, seq, snapshot)
+        SELECT $1, task_id, seq, snapshot
+        FROM UNNEST($2::uuid[], $3::int8[], $4::jsonb[]) AS t(task_id, seq, snapshot)
+        ON CONFLICT (run_id, task_id) DO UPDATE SET
+            seq = EXCLUDED.seq,
+            snapshot = EXCLUDED.snapshot
+        WHERE run_checkpoint_states.seq < EXCLUDED.seq",
+    )
+    .bind(run_id)
+    .bind(&task_ids)
+    .bind(&seqs)
+    .bind(&snapshots)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE run_checkpoints
+        SET run_store = COALESCE($2, run_store), updated_at = now()
+        WHERE run_id = $1",
+    )
+    .bind(run_id)
+    .bind(&run_store)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Set the status of the run's checkpoints, unless the run was claimed by a later generation
+pub async fn set_checkpoint_status(
+    pool: &PgPool,
+    run_id: &Uuid,
+    generation: i32,
+    status: &str,
+    paused_task_id: Option<Uuid>,
+) -> Result<bool> {
+    let res = sqlx::query(
+        "UPDATE run_checkpoints
+        SET status = $3, paused_task_id = $4, updated_at = now()
+        WHERE run_id = $1 AND generation = $2",
+    )
+    .bind(run_id)
+    .bind(generation)
+    .bind(status)
+    .bind(paused_task_id)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Claim the run paused with the graph, so that no other instance resumes it
+pub async fn claim_paused_checkpoint(
+    pool: &PgPool,
+    run_id: &Uuid,
+    graph_hash: &str,
+) -> Result<Option<ClaimedCheckpoint>> {
+    let checkpoint = sqlx::query_as::<_, ClaimedCheckpoint>(
+        "UPDATE run_checkpoints
+        SET status = 'running', generation = generation + 1, updated_at = now()
+        WHERE run_id = $1 AND status = 'paused' AND graph_hash = $2
+        RETURNING generation, paused_task_id, run_store",
+    )
+    .bind(run_id)
+    .bind(graph_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(checkpoint)
+}
+
+/// Claim the checkpoint of the run with the graph from the engine which wrote it, e.g. of a
+/// worker whose lease of the run expired, unless the run is paused
+pub async fn claim_running_checkpoint(
+    pool: &PgPool,
+    run_id: &Uuid,
+    graph_hash: &str,
+) -> Result<Option<ClaimedCheckpoint>> {
+    let checkpoint = sqlx::query_as::<_, ClaimedCheckpoint>(
+        "UPDATE run_checkpoints
+        SET generation = generation + 1, updated_at = now()
+        WHERE run_id = $1 AND status IN ('running', 'completed') AND graph_hash = $2
+        RETURNING generation, paused_task_id, run_store",
+    )
+    .bind(run_id)
+    .bind(graph_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(checkpoint)
+}
+
+pub async fn get_snapshots(pool: &PgPool, run_id: &Uuid) -> Result<Vec<Value>> {
+    let snapshots = sqlx::query_scalar::<_, Value>(
+        "SELECT snapshot FROM run_checkpoint_states WHERE run_id = $1 ORDER BY seq",
+    )
+    .bind(run_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(snapshots)
+}
+
+/// Delete checkpoints of runs which completed before `completed_before`
+pub async fn delete_completed_checkpoints(
+    pool: &PgPool,
+    completed_before: DateTime<Utc>,
+) -> Result<u64> {
+    let res =
+        sqlx::query("DELETE FROM run_checkpoints WHERE status = 'completed' AND updated_at < $1")
+            .bind(completed_before)
+            .execute(pool)
+            .await?;
+
+    Ok(res.rows_affected())
+}
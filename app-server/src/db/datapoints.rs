@@ -1,19 +1,33 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures_core::stream::BoxStream;
 use serde_json::Value;
 use sqlx::{prelude::FromRow, PgPool};
 use uuid::Uuid;
 
-use crate::datasets::datapoints::Datapoint;
+use crate::datasets::{datapoints::Datapoint, DatasetUsage};
 
 pub async fn insert_datapoints(
     pool: &PgPool,
     dataset_id: &Uuid,
     datapoints: Vec<Datapoint>,
 ) -> Result<Vec<Datapoint>> {
-    let size = datapoints.len();
+    insert_datapoint_batch(pool, dataset_id, datapoints, Utc::now(), 0).await
+}
+
+/// Insert a batch of a larger import, batches of an import share `created_at` and continue
+/// `index_in_batch` from `first_index`, so that datapoints are listed in the order of the import
+pub async fn insert_datapoint_batch(
+    pool: &PgPool,
+    dataset_id: &Uuid,
+    datapoints: Vec<Datapoint>,
+    created_at: DateTime<Utc>,
+    first_index: i64,
+) -> Result<Vec<Datapoint>> {
+    let size = datapoints.len() as i64;
     let datapoints = sqlx::query_as::<_, Datapoint>(
-        "INSERT INTO dataset_datapoints (dataset_id, id, data, target, index_in_batch)
-        SELECT $1 as dataset_id, id, data, target, index_in_batch
+        "INSERT INTO dataset_datapoints (dataset_id, id, data, target, index_in_batch, created_at)
+        SELECT $1 as dataset_id, id, data, target, index_in_batch, $6 as created_at
         FROM UNNEST($2::uuid[], $3::jsonb[], $4::jsonb[], $5::int8[])
         AS tmp_table(id, data, target, index_in_batch)
         RETURNING id, dataset_id, data, target",
@@ -32,26 +46,14 @@ pub async fn insert_datapoints(
             .map(|dp| dp.target)
             .collect::<Vec<_>>(),
     )
-    .bind(&Vec::from_iter(0..size as i64))
+    .bind(Vec::from_iter(first_index..first_index + size))
+    .bind(created_at)
     .fetch_all(pool)
     .await?;
 
     Ok(datapoints)
 }
 
-pub async fn insert_raw_data(
-    pool: &PgPool,
-    dataset_id: &Uuid,
-    data: &Vec<Value>,
-) -> Result<Vec<Datapoint>> {
-    let valid_datapoints = data
-        .iter()
-        .filter_map(|value| Datapoint::try_from_raw_value(dataset_id.to_owned(), value))
-        .collect();
-
-    insert_datapoints(pool, dataset_id, valid_datapoints).await
-}
-
 pub async fn get_all_datapoints(pool: &PgPool, dataset_id: Uuid) -> Result<Vec<Datapoint>> {
     let datapoints = sqlx::query_as::<_, Datapoint>(
         "SELECT id, dataset_id, data, target
@@ -93,6 +95,83 @@ pub async fn get_datapoints_paginated(
     Ok(datapoints)
 }
 
+/// Ids of the dataset's datapoints, streamed so that large datasets aren't loaded into memory.
+/// The order is stable, so that reservoir samples with the same seed pick the same rows.
+pub fn stream_datapoint_ids(pool: &PgPool, dataset_id: Uuid) -> BoxStream<'_, sqlx::Result<Uuid>> {
+    sqlx::query_scalar::<_, Uuid>(
+        "SELECT id
+        FROM dataset_datapoints
+        WHERE dataset_id = $1
+        ORDER BY created_at, index_in_batch, id",
+    )
+    .bind(dataset_id)
+    .fetch(pool)
+}
+
+/// Datapoints with the ids, in the order of the dataset
+pub async fn get_datapoints_by_ids(
+    pool: &PgPool,
+    dataset_id: Uuid,
+    ids: &[Uuid],
+) -> Result<Vec<Datapoint>> {
+    let datapoints = sqlx::query_as::<_, Datapoint>(
+        "SELECT id, dataset_id, data, target
+        FROM dataset_datapoints
+        WHERE dataset_id = $1 AND id = ANY($2)
+        ORDER BY
+            created_at DESC,
+            index_in_batch ASC NULLS FIRST",
+    )
+    .bind(dataset_id)
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(datapoints)
+}
+
+/// Up to `per_value` datapoints for each distinct value of the data column, in the order of the
+/// dataset. Rows of each value are picked by the hash of their id with the seed, so the same
+/// seed picks the same rows.
+pub async fn get_stratified_datapoints(
+    pool: &PgPool,
+    dataset_id: Uuid,
+    column: &str,
+    per_value: i64,
+    seed: u64,
+) -> Result<Vec<Datapoint>> {
+    let datapoints = sqlx::query_as::<_, Datapoint>(
+        "SELECT id, dataset_id, data, target
+        FROM (
+            SELECT
+                id,
+                dataset_id,
+                data,
+                target,
+                created_at,
+                index_in_batch,
+                row_number() OVER (
+                    PARTITION BY data->$2
+                    ORDER BY md5(id::text || $4)
+                ) as value_rank
+            FROM dataset_datapoints
+            WHERE dataset_id = $1
+        ) ranked
+        WHERE value_rank <= $3
+        ORDER BY
+            created_at DESC,
+            index_in_batch ASC NULLS FIRST",
+    )
+    .bind(dataset_id)
+    .bind(column)
+    .bind(per_value)
+    .bind(seed.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(datapoints)
+}
+
 pub async fn update_datapoint(
     pool: &PgPool,
     datapoint_id: &Uuid,
@@ -157,3 +236,18 @@ pub async fn count_datapoints(pool: &PgPool, dataset_id: Uuid) -> Result<u64> {
 
     Ok(count.count as u64)
 }
+
+pub async fn get_dataset_usage(pool: &PgPool, dataset_id: Uuid) -> Result<DatasetUsage> {
+    let usage = sqlx::query_as::<_, DatasetUsage>(
+        "SELECT
+            COUNT(*) as rows,
+            COALESCE(SUM(octet_length(data::text) + octet_length(target::text)), 0)::int8 as size
+        FROM dataset_datapoints
+        WHERE dataset_id = $1",
+    )
+    .bind(dataset_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(usage)
+}
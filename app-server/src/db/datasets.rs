@@ -2,17 +2,23 @@ use anyhow::{Context, Result};
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::datasets::Dataset;
+use crate::datasets::{schema::DatasetSchema, Dataset};
 
-pub async fn create_dataset(pool: &PgPool, name: &String, project_id: Uuid) -> Result<Dataset> {
+pub async fn create_dataset(
+    pool: &PgPool,
+    name: &String,
+    project_id: Uuid,
+    schema: &DatasetSchema,
+) -> Result<Dataset> {
     let dataset = sqlx::query_as::<_, Dataset>(
-        "INSERT INTO datasets (name, project_id)
-        VALUES ($1, $2)
-        RETURNING id, created_at, name, project_id, indexed_on
+        "INSERT INTO datasets (name, project_id, schema)
+        VALUES ($1, $2, $3)
+        RETURNING id, created_at, name, project_id, indexed_on, schema
         ",
     )
     .bind(name)
     .bind(project_id)
+    .bind(serde_json::to_value(schema)?)
     .fetch_one(pool)
     .await?;
 
@@ -21,7 +27,7 @@ pub async fn create_dataset(pool: &PgPool, name: &String, project_id: Uuid) -> R
 
 pub async fn get_datasets(pool: &PgPool, project_id: Uuid) -> Result<Vec<Dataset>> {
     let datasets = sqlx::query_as::<_, Dataset>(
-        "SELECT id, created_at, name, project_id, indexed_on FROM datasets WHERE project_id = $1
+        "SELECT id, created_at, name, project_id, indexed_on, schema FROM datasets WHERE project_id = $1
         ORDER BY created_at DESC",
     )
     .bind(project_id)
@@ -33,7 +39,7 @@ pub async fn get_datasets(pool: &PgPool, project_id: Uuid) -> Result<Vec<Dataset
 
 pub async fn get_dataset(pool: &PgPool, project_id: Uuid, dataset_id: Uuid) -> Result<Dataset> {
     let dataset = sqlx::query_as::<_, Dataset>(
-        "SELECT id, created_at, name, project_id, indexed_on FROM datasets WHERE id = $1 AND project_id = $2",
+        "SELECT id, created_at, name, project_id, indexed_on, schema FROM datasets WHERE id = $1 AND project_id = $2",
     )
     .bind(dataset_id)
     .bind(project_id)
@@ -51,7 +57,7 @@ pub async fn rename_dataset(
 ) -> Result<Dataset> {
     let dataset = sqlx::query_as::<_, Dataset>(
         "UPDATE datasets SET name = $3 WHERE id = $1 AND project_id = $2
-        RETURNING id, created_at, name, project_id, indexed_on",
+        RETURNING id, created_at, name, project_id, indexed_on, schema",
     )
     .bind(id)
     .bind(project_id)
@@ -78,7 +84,7 @@ pub async fn update_index_column(
 ) -> Result<Dataset> {
     let dataset = sqlx::query_as::<_, Dataset>(
         "UPDATE datasets SET indexed_on = $2 WHERE id = $1
-        RETURNING id, created_at, name, project_id, indexed_on",
+        RETURNING id, created_at, name, project_id, indexed_on, schema",
     )
     .bind(dataset_id)
     .bind(index_column)
@@ -87,3 +93,17 @@ pub async fn update_index_column(
 
     Ok(dataset)
 }
+
+pub async fn update_dataset_schema(
+    pool: &PgPool,
+    dataset_id: Uuid,
+    schema: &DatasetSchema,
+) -> Result<()> {
+    sqlx::query("UPDATE datasets SET schema = $2 WHERE id = $1")
+        .bind(dataset_id)
+        .bind(serde_json::to_value(schema)?)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
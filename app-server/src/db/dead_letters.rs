@@ -0,0 +1,162 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::dead_letters::DeadLetterKind;
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub kind: DeadLetterKind,
+    pub payload: Value,
+    pub error: String,
+    pub retry_count: i32,
+}
+
+/// Dead letter without its payload, which can be large for span batches
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterInfo {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub kind: DeadLetterKind,
+    pub error: String,
+    pub retry_count: i32,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterStats {
+    pub kind: DeadLetterKind,
+    pub count: i64,
+    pub oldest_created_at: DateTime<Utc>,
+    pub newest_created_at: DateTime<Utc>,
+}
+
+pub async fn insert_dead_letter(
+    pool: &PgPool,
+    kind: DeadLetterKind,
+    payload: &Value,
+    error: &str,
+    retry_count: i32,
+) -> Result<Uuid> {
+    let id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO dead_letters (kind, payload, error, retry_count)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id",
+    )
+    .bind(kind)
+    .bind(payload)
+    .bind(error)
+    .bind(retry_count)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Dead letters of the kind, or of all kinds, latest first
+pub async fn get_dead_letters(
+    pool: &PgPool,
+    kind: Option<DeadLetterKind>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<DeadLetterInfo>> {
+    let dead_letters = sqlx::query_as::<_, DeadLetterInfo>(
+        "SELECT id, created_at, updated_at, kind, error, retry_count
+        FROM dead_letters
+        WHERE ($1::dead_letter_kind IS NULL OR kind = $1)
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3",
+    )
+    .bind(kind)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(dead_letters)
+}
+
+pub async fn get_dead_letter(pool: &PgPool, id: &Uuid) -> Result<Option<DeadLetter>> {
+    let dead_letter = sqlx::query_as::<_, DeadLetter>(
+        "SELECT id, created_at, updated_at, kind, payload, error, retry_count
+        FROM dead_letters
+        WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(dead_letter)
+}
+
+/// Record that retrying the dead letter failed again, with the error of the retry
+pub async fn record_retry_failure(pool: &PgPool, id: &Uuid, error: &str) -> Result<()> {
+    sqlx::query(
+        "UPDATE dead_letters
+        SET error = $2, retry_count = retry_count + 1, updated_at = now()
+        WHERE id = $1",
+    )
+    .bind(id)
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete the dead letter, returns whether it existed
+pub async fn delete_dead_letter(pool: &PgPool, id: &Uuid) -> Result<bool> {
+    let res = sqlx::query("DELETE FROM dead_letters WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Delete dead letters of the kind created before the cutoff, any kind or time if not set
+pub async fn purge_dead_letters(
+    pool: &PgPool,
+    kind: Option<DeadLetterKind>,
+    created_before: Option<DateTime<Utc>>,
+) -> Result<u64> {
+    let res = sqlx::query(
+        "DELETE FROM dead_letters
+        WHERE ($1::dead_letter_kind IS NULL OR kind = $1)
+        AND ($2::timestamptz IS NULL OR created_at < $2)",
+    )
+    .bind(kind)
+    .bind(created_before)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Number of dead letters of each kind which has any
+pub async fn get_dead_letter_stats(pool: &PgPool) -> Result<Vec<DeadLetterStats>> {
+    let stats = sqlx::query_as::<_, DeadLetterStats>(
+        "SELECT
+            kind,
+            COUNT(*) AS count,
+            MIN(created_at) AS oldest_created_at,
+            MAX(created_at) AS newest_created_at
+        FROM dead_letters
+        GROUP BY kind
+        ORDER BY kind",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(stats)
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::evaluations::backfill::{BackfillConfig, RunScore};
+
+use super::runs::RunStatus;
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationBackfill {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub pipeline_id: Uuid,
+    /// running, failed or succeeded
+    pub status: String,
+    #[sqlx(json)]
+    pub config: BackfillConfig,
+    /// Runs in the time range when the backfill was started
+    pub total_runs: i64,
+    pub scored_runs: i64,
+    pub skipped_runs: i64,
+    pub failed_runs: i64,
+    /// Number of skipped runs by reason
+    #[sqlx(json)]
+    pub skip_reasons: HashMap<String, i64>,
+    #[serde(skip)]
+    pub cursor_created_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub cursor_run_id: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+const BACKFILL_COLUMNS: &str = "id, created_at, updated_at, project_id, pipeline_id, status,
+    config, total_runs, scored_runs, skipped_runs, failed_runs, skip_reasons, cursor_created_at,
+    cursor_run_id, error";
+
+/// Run scored by a backfill
+#[derive(FromRow)]
+pub struct BackfillRun {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// Whether the content of the run's trace was purged by retention
+    pub content_purged: bool,
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackfillRunScore {
+    pub run_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// scored, skipped or failed
+    pub status: String,
+    #[schema(value_type = Object)]
+    pub scores: Value, // HashMap<String, f64>
+    pub passed: Option<bool>,
+    pub skip_reason: Option<String>,
+    pub error: Option<String>,
+    pub evaluator_trace_id: Option<Uuid>,
+}
+
+/// Create a running backfill, counting the runs in its time range
+pub async fn create_backfill(
+    pool: &PgPool,
+    backfill_id: &Uuid,
+    project_id: &Uuid,
+    pipeline_id: &Uuid,
+    config: &BackfillConfig,
+) -> Result<EvaluationBackfill> {
+    let backfill = sqlx::query_as::<_, EvaluationBackfill>(&format!(
+        "INSERT INTO evaluation_backfills (id, project_id, pipeline_id, config, total_runs)
+        VALUES ($1, $2, $3, $4, (
+            SELECT COUNT(*) FROM runs
+            WHERE project_id = $2 AND pipeline_id = $3 AND status = $5
+            AND created_at >= $6 AND created_at < $7
+        ))
+        RETURNING {BACKFILL_COLUMNS}"
+    ))
+    .bind(backfill_id)
+    .bind(project_id)
+    .bind(pipeline_id)
+    .bind(serde_json::to_value(config)?)
+    .bind(RunStatus::Succeeded)
+    .bind(config.start_time)
+    .bind(config.end_time)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(backfill)
+}
+
+pub async fn get_backfill(
+    pool: &PgPool,
+    project_id: &Uuid,
+    backfill_id: &Uuid,
+) -> Result<Option<EvaluationBackfill>> {
+    let backfill = sqlx::query_as::<_, EvaluationBackfill>(&format!(
+        "SELECT {BACKFILL_COLUMNS} FROM evaluation_backfills WHERE id = $1 AND project_id = $2"
+    ))
+    .bind(backfill_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(backfill)
+}
+
+/// Next batch of the succeeded runs in the backfill's time range, in order of creation time and
+/// id, after the backfill's cursor
+pub async fn get_backfill_runs(
+    pool: &PgPool,
+    backfill: &EvaluationBackfill,
+    limit: i64,
+) -> Result<Vec<BackfillRun>> {
+    let runs = sqlx::query_as::<_, BackfillRun>(
+        "SELECT
+            runs.id,
+            runs.created_at,
+            EXISTS (
+                SELECT 1 FROM purged_traces WHERE purged_traces.trace_id = runs.trace_id
+            ) AS content_purged
+        FROM runs
+        WHERE runs.project_id = $1 AND runs.pipeline_id = $2 AND runs.status = $3
+            AND runs.created_at >= $4 AND runs.created_at < $5
+            AND ($6::timestamptz IS NULL OR (runs.created_at, runs.id) > ($6, $7::uuid))
+        ORDER BY runs.created_at, runs.id
+        LIMIT $8",
+    )
+    .bind(backfill.project_id)
+    .bind(backfill.pipeline_id)
+    .bind(RunStatus::Succeeded)
+    .bind(backfill.config.start_time)
+    .bind(backfill.config.end_time)
+    .bind(backfill.cursor_created_at)
+    .bind(backfill.cursor_run_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(runs)
+}
+
+pub async fn insert_run_scores(
+    pool: &PgPool,
+    backfill_id: &Uuid,
+    scores: &[RunScore],
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO evaluation_backfill_scores (
+            backfill_id,
+            run_id,
+            status,
+            scores,
+            passed,
+            skip_reason,
+            error,
+            evaluator_trace_id
+        )
+        SELECT $1 as backfill_id, *
+        FROM UNNEST (
+            $2::uuid[],
+            $3::text[],
+            $4::jsonb[],
+            $5::bool[],
+            $6::text[],
+            $7::text[],
+            $8::uuid[]
+        )",
+    )
+    .bind(backfill_id)
+    .bind(scores.iter().map(|s| s.run_id).collect::<Vec<_>>())
+    .bind(scores.iter().map(|s| s.status.as_str()).collect::<Vec<_>>())
+    .bind(
+        scores
+            .iter()
+            .map(|s| serde_json::to_value(&s.scores).unwrap_or_default())
+            .collect::<Vec<_>>(),
+    )
+    .bind(scores.iter().map(|s| s.passed).collect::<Vec<_>>())
+    .bind(
+        scores
+            .iter()
+            .map(|s| s.skip_reason.map(|reason| reason.as_str()))
+            .collect::<Vec<_>>(),
+    )
+    .bind(scores.iter().map(|s| s.error.clone()).collect::<Vec<_>>())
+    .bind(
+        scores
+            .iter()
+            .map(|s| s.evaluator_trace_id)
+            .collect::<Vec<_>>(),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Record the backfill's counts and cursor, unless it's no longer running. Returns whether it's
+/// running.
+pub async fn advance_backfill(pool: &PgPool, backfill: &EvaluationBackfill) -> Result<bool> {
+    let res = sqlx::query(
+        "UPDATE evaluation_backfills SET
+            scored_runs = $2,
+            skipped_runs = $3,
+            failed_runs = $4,
+            skip_reasons = $5,
+            cursor_created_at = $6,
+            cursor_run_id = $7,
+            updated_at = now()
+        WHERE id = $1 AND status = 'running'",
+    )
+    .bind(backfill.id)
+    .bind(backfill.scored_runs)
+    .bind(backfill.skipped_runs)
+    .bind(backfill.failed_runs)
+    .bind(serde_json::to_value(&backfill.skip_reasons)?)
+    .bind(backfill.cursor_created_at)
+    .bind(backfill.cursor_run_id)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn finish_backfill(pool: &PgPool, backfill_id: &Uuid) -> Result<()> {
+    sqlx::query(
+        "UPDATE evaluation_backfills SET status = 'succeeded', updated_at = now()
+        WHERE id = $1 AND status = 'running'",
+    )
+    .bind(backfill_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn fail_backfill(pool: &PgPool, backfill_id: &Uuid, error: &str) -> Result<()> {
+    sqlx::query(
+        "UPDATE evaluation_backfills SET status = 'failed', error = $2, updated_at = now()
+        WHERE id = $1 AND status = 'running'",
+    )
+    .bind(backfill_id)
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Results of the backfill's runs, in order of the runs' creation
+pub async fn get_run_scores(
+    pool: &PgPool,
+    backfill_id: &Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<BackfillRunScore>> {
+    let scores = sqlx::query_as::<_, BackfillRunScore>(
+        "SELECT
+            evaluation_backfill_scores.run_id,
+            runs.created_at,
+            evaluation_backfill_scores.status,
+            evaluation_backfill_scores.scores,
+            evaluation_backfill_scores.passed,
+            evaluation_backfill_scores.skip_reason,
+            evaluation_backfill_scores.error,
+            evaluation_backfill_scores.evaluator_trace_id
+        FROM evaluation_backfill_scores
+        JOIN runs ON runs.id = evaluation_backfill_scores.run_id
+        WHERE evaluation_backfill_scores.backfill_id = $1
+        ORDER BY runs.created_at, runs.id
+        LIMIT $2 OFFSET $3",
+    )
+    .bind(backfill_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(scores)
+}
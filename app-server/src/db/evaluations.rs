@@ -5,6 +5,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::{postgres::PgHasArrayType, prelude::FromRow, PgPool};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::DB;
@@ -17,7 +18,7 @@ pub async fn delete_evaluation(pool: &PgPool, evaluation_id: &Uuid) -> Result<()
     Ok(())
 }
 
-#[derive(sqlx::Type, Deserialize, Serialize)]
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
 #[sqlx(type_name = "evaluation_job_status")]
 pub enum EvaluationStatus {
     Started,
@@ -25,7 +26,7 @@ pub enum EvaluationStatus {
     Error,
 }
 
-#[derive(sqlx::Type, Serialize, Clone, Deserialize)]
+#[derive(sqlx::Type, Serialize, Clone, Copy, Debug, PartialEq, Deserialize)]
 #[sqlx(type_name = "evaluation_status")]
 pub enum EvaluationDatapointStatus {
     Success,
@@ -38,7 +39,7 @@ impl PgHasArrayType for EvaluationDatapointStatus {
     }
 }
 
-#[derive(Deserialize, Serialize, FromRow)]
+#[derive(Deserialize, Serialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Evaluation {
     pub id: Uuid,
@@ -47,6 +48,10 @@ pub struct Evaluation {
     pub status: EvaluationStatus,
     pub project_id: Uuid,
     pub metadata: Option<Value>,
+    /// `evaluations::EvaluationConfig` of evaluations run by the server
+    pub config: Option<Value>,
+    /// `evaluations::EvaluationStats` of the last finished run
+    pub stats: Option<Value>,
 }
 
 #[derive(Serialize, FromRow)]
@@ -61,25 +66,41 @@ pub struct EvaluationDatapointPreview {
     pub status: EvaluationDatapointStatus,
     pub executor_output: Option<Value>,
     pub error: Option<Value>,
+    pub datapoint_id: Option<Uuid>,
+    /// Whether all evaluators passed, set for rows of evaluations run by the server
+    pub passed: Option<bool>,
+    pub executor_trace_id: Option<Uuid>,
+    pub evaluator_trace_id: Option<Uuid>,
+    /// Whether the content of the row's traces was purged by retention, the traces may be deleted
+    pub trace_content_purged: bool,
 }
 
+/// Column of `EvaluationDatapointPreview::trace_content_purged`
+const TRACE_CONTENT_PURGED_COLUMN: &str = "EXISTS (
+        SELECT 1 FROM purged_traces
+        WHERE purged_traces.trace_id IN (executor_trace_id, evaluator_trace_id)
+    ) AS trace_content_purged";
+
 pub async fn create_evaluation(
     pool: &PgPool,
     name: &String,
     status: EvaluationStatus,
     project_id: Uuid,
     metadata: Option<Value>,
+    config: Option<Value>,
 ) -> Result<Evaluation> {
     let evaluation = sqlx::query_as::<_, Evaluation>(
-        "INSERT INTO evaluations (name, status, project_id, metadata)
-        VALUES ($1, $2::evaluation_job_status, $3, $4)
-        ON CONFLICT (name, project_id) DO UPDATE set metadata = $4
-        RETURNING id, created_at, name, status, project_id, metadata",
+        "INSERT INTO evaluations (name, status, project_id, metadata, config)
+        VALUES ($1, $2::evaluation_job_status, $3, $4, $5)
+        ON CONFLICT (name, project_id) DO UPDATE
+        SET metadata = $4, config = COALESCE($5, evaluations.config)
+        RETURNING id, created_at, name, status, project_id, metadata, config, stats",
     )
     .bind(name)
     .bind(status)
     .bind(project_id)
     .bind(metadata)
+    .bind(config)
     .fetch_one(pool)
     .await?;
 
@@ -88,7 +109,7 @@ pub async fn create_evaluation(
 
 pub async fn get_evaluation(db: Arc<DB>, evaluation_id: Uuid) -> Result<Evaluation> {
     let evaluation = sqlx::query_as::<_, Evaluation>(
-        "SELECT id, name, status, project_id, created_at, metadata
+        "SELECT id, name, status, project_id, created_at, metadata, config, stats
         FROM evaluations WHERE id = $1",
     )
     .bind(evaluation_id)
@@ -98,13 +119,28 @@ pub async fn get_evaluation(db: Arc<DB>, evaluation_id: Uuid) -> Result<Evaluati
     Ok(evaluation)
 }
 
+pub async fn get_evaluation_by_id(
+    pool: &PgPool,
+    evaluation_id: Uuid,
+) -> Result<Option<Evaluation>> {
+    let evaluation = sqlx::query_as::<_, Evaluation>(
+        "SELECT id, name, status, project_id, created_at, metadata, config, stats
+        FROM evaluations WHERE id = $1",
+    )
+    .bind(evaluation_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(evaluation)
+}
+
 pub async fn get_evaluation_by_name(
     pool: &PgPool,
     project_id: Uuid,
     name: &str,
 ) -> Result<Evaluation> {
     let evaluation = sqlx::query_as::<_, Evaluation>(
-        "SELECT id, name, status, project_id, created_at, metadata
+        "SELECT id, name, status, project_id, created_at, metadata, config, stats
         FROM evaluations WHERE project_id = $1 AND name = $2",
     )
     .bind(project_id)
@@ -117,7 +153,7 @@ pub async fn get_evaluation_by_name(
 
 pub async fn get_evaluations(pool: &PgPool, project_id: Uuid) -> Result<Vec<Evaluation>> {
     let evaluations = sqlx::query_as::<_, Evaluation>(
-        "SELECT id, name, status, project_id, created_at, metadata
+        "SELECT id, name, status, project_id, created_at, metadata, config, stats
         FROM evaluations WHERE project_id = $1",
     )
     .bind(project_id)
@@ -133,7 +169,7 @@ pub async fn get_finished_evaluation_infos(
     exclude_id: Uuid,
 ) -> Result<Vec<Evaluation>> {
     let evaluations = sqlx::query_as::<_, Evaluation>(
-        "SELECT id, name, status, project_id, created_at, metadata
+        "SELECT id, name, status, project_id, created_at, metadata, config, stats
         FROM evaluations
         WHERE project_id = $1 AND status = 'Finished'::evaluation_job_status AND id != $2
         ORDER BY created_at DESC",
@@ -238,7 +274,7 @@ pub async fn get_evaluation_results(
     pool: &PgPool,
     evaluation_id: Uuid,
 ) -> Result<Vec<EvaluationDatapointPreview>> {
-    let results = sqlx::query_as::<_, EvaluationDatapointPreview>(
+    let results = sqlx::query_as::<_, EvaluationDatapointPreview>(&format!(
         "SELECT
             id,
             created_at,
@@ -248,11 +284,16 @@ pub async fn get_evaluation_results(
             target,
             scores,
             executor_output,
-            error
+            error,
+            datapoint_id,
+            passed,
+            executor_trace_id,
+            evaluator_trace_id,
+            {TRACE_CONTENT_PURGED_COLUMN}
         FROM evaluation_results
         WHERE evaluation_id = $1
-        ORDER BY created_at ASC, index_in_batch ASC NULLS FIRST",
-    )
+        ORDER BY created_at ASC, index_in_batch ASC NULLS FIRST"
+    ))
     .bind(evaluation_id)
     .fetch_all(pool)
     .await?;
@@ -264,7 +305,7 @@ pub async fn get_evaluation_datapoint(
     pool: &PgPool,
     evaluation_result_id: Uuid,
 ) -> Result<EvaluationDatapointPreview> {
-    let preview = sqlx::query_as::<_, EvaluationDatapointPreview>(
+    let preview = sqlx::query_as::<_, EvaluationDatapointPreview>(&format!(
         "SELECT
             id,
             created_at,
@@ -274,13 +315,145 @@ pub async fn get_evaluation_datapoint(
             data,
             target,
             executor_output,
-            error
+            error,
+            datapoint_id,
+            passed,
+            executor_trace_id,
+            evaluator_trace_id,
+            {TRACE_CONTENT_PURGED_COLUMN}
         FROM evaluation_results
-        WHERE id = $1",
-    )
+        WHERE id = $1"
+    ))
     .bind(evaluation_result_id)
     .fetch_one(pool)
     .await?;
 
     Ok(preview)
 }
+
+/// Result of a row of an evaluation run by the server
+pub struct NewEvaluationResult {
+    pub datapoint_id: Uuid,
+    pub status: EvaluationDatapointStatus,
+    pub scores: HashMap<String, f64>,
+    pub passed: bool,
+    pub data: Value,
+    pub target: Value,
+    pub executor_output: Option<Value>,
+    pub executor_trace_id: Option<Uuid>,
+    pub evaluator_trace_id: Option<Uuid>,
+    pub error: Option<Value>,
+}
+
+/// Insert results of a batch of rows, `first_index` is the index of the batch's first row
+pub async fn insert_evaluation_results(
+    pool: &PgPool,
+    evaluation_id: Uuid,
+    results: &[NewEvaluationResult],
+    first_index: i64,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO evaluation_results (
+            evaluation_id,
+            datapoint_id,
+            status,
+            scores,
+            passed,
+            data,
+            target,
+            executor_output,
+            executor_trace_id,
+            evaluator_trace_id,
+            error,
+            index_in_batch
+        )
+        SELECT $1 as evaluation_id, *
+        FROM UNNEST (
+            $2::uuid[],
+            $3::evaluation_status[],
+            $4::jsonb[],
+            $5::bool[],
+            $6::jsonb[],
+            $7::jsonb[],
+            $8::jsonb[],
+            $9::uuid[],
+            $10::uuid[],
+            $11::jsonb[],
+            $12::int8[]
+        )",
+    )
+    .bind(evaluation_id)
+    .bind(results.iter().map(|r| r.datapoint_id).collect::<Vec<_>>())
+    .bind(results.iter().map(|r| r.status).collect::<Vec<_>>())
+    .bind(
+        results
+            .iter()
+            .map(|r| serde_json::to_value(&r.scores).unwrap_or_default())
+            .collect::<Vec<_>>(),
+    )
+    .bind(results.iter().map(|r| r.passed).collect::<Vec<_>>())
+    .bind(results.iter().map(|r| r.data.clone()).collect::<Vec<_>>())
+    .bind(results.iter().map(|r| r.target.clone()).collect::<Vec<_>>())
+    .bind(
+        results
+            .iter()
+            .map(|r| r.executor_output.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        results
+            .iter()
+            .map(|r| r.executor_trace_id)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        results
+            .iter()
+            .map(|r| r.evaluator_trace_id)
+            .collect::<Vec<_>>(),
+    )
+    .bind(results.iter().map(|r| r.error.clone()).collect::<Vec<_>>())
+    .bind(Vec::from_iter(
+        first_index..first_index + results.len() as i64,
+    ))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Clear results and stats of the previous run, and mark the evaluation as started
+pub async fn start_evaluation_run(pool: &PgPool, evaluation_id: Uuid) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM evaluation_results WHERE evaluation_id = $1")
+        .bind(evaluation_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE evaluations
+        SET status = 'Started'::evaluation_job_status, stats = NULL
+        WHERE id = $1",
+    )
+    .bind(evaluation_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn finish_evaluation_run(
+    pool: &PgPool,
+    evaluation_id: Uuid,
+    status: EvaluationStatus,
+    stats: Option<Value>,
+) -> Result<()> {
+    sqlx::query("UPDATE evaluations SET status = $2, stats = $3 WHERE id = $1")
+        .bind(evaluation_id)
+        .bind(status)
+        .bind(stats)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
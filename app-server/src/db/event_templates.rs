@@ -4,9 +4,10 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(sqlx::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[derive(sqlx::Type, Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
 #[sqlx(type_name = "event_type")]
 pub enum EventType {
     BOOLEAN,
@@ -5,6 +5,7 @@ use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::{PgPool, Postgres, QueryBuilder};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
@@ -19,7 +20,7 @@ use super::{
     DB,
 };
 
-#[derive(sqlx::Type, Deserialize, Serialize)]
+#[derive(sqlx::Type, Deserialize, Serialize, ToSchema)]
 #[sqlx(type_name = "event_source")]
 pub enum EventSource {
     AUTO,
@@ -117,7 +118,7 @@ pub struct Event {
     pub value: Option<Value>,
 }
 
-#[derive(sqlx::FromRow, Serialize)]
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EventWithTemplateName {
     pub id: Uuid,
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, FromRow)]
+pub struct IdempotencyKey {
+    pub request_hash: String,
+    pub run_id: Option<Uuid>,
+    pub response_status: Option<i16>,
+    pub response: Option<Value>,
+}
+
+/// Insert the key for a new request, or take over an expired one
+///
+/// Returns false if the key is already used by a request which hasn't expired.
+pub async fn claim_idempotency_key(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    key: &str,
+    request_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<bool> {
+    let res = sqlx::query(
+        "INSERT INTO idempotency_keys (api_key_id, key, request_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (api_key_id, key) DO UPDATE SET
+            created_at = now(),
+            expires_at = EXCLUDED.expires_at,
+            request_hash = EXCLUDED.request_hash,
+            run_id = NULL,
+            response_status = NULL,
+            response = NULL
+        WHERE idempotency_keys.expires_at < now()",
+    )
+    .bind(api_key_id)
+    .bind(key)
+    .bind(request_hash)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn get_idempotency_key(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    key: &str,
+) -> Result<Option<IdempotencyKey>> {
+    let key = sqlx::query_as::<_, IdempotencyKey>(
+        "SELECT request_hash, run_id, response_status, response
+        FROM idempotency_keys
+        WHERE api_key_id = $1 AND key = $2",
+    )
+    .bind(api_key_id)
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(key)
+}
+
+pub async fn set_idempotency_key_run(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    key: &str,
+    run_id: &Uuid,
+) -> Result<()> {
+    sqlx::query("UPDATE idempotency_keys SET run_id = $3 WHERE api_key_id = $1 AND key = $2")
+        .bind(api_key_id)
+        .bind(key)
+        .bind(run_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn set_idempotency_key_response(
+    pool: &PgPool,
+    api_key_id: &Uuid,
+    key: &str,
+    response_status: i16,
+    response: &Value,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE idempotency_keys SET response_status = $3, response = $4
+        WHERE api_key_id = $1 AND key = $2",
+    )
+    .bind(api_key_id)
+    .bind(key)
+    .bind(response_status)
+    .bind(response)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_idempotency_key(pool: &PgPool, api_key_id: &Uuid, key: &str) -> Result<()> {
+    sqlx::query("DELETE FROM idempotency_keys WHERE api_key_id = $1 AND key = $2")
+        .bind(api_key_id)
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the number of deleted keys
+pub async fn delete_expired_idempotency_keys(pool: &PgPool) -> Result<u64> {
+    let res = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at < now()")
+        .execute(pool)
+        .await?;
+
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,305 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::labeling::{LabelSchema, QueueFilter};
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LabelingQueue {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub name: String,
+    #[sqlx(json)]
+    pub filter: QueueFilter,
+    #[sqlx(json)]
+    pub label_schema: LabelSchema,
+    pub lease_seconds: i32,
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunLabel {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub queue_id: Uuid,
+    pub run_id: Uuid,
+    pub labeled_by: Uuid,
+    #[sqlx(rename = "label_values")]
+    pub values: Value,
+}
+
+pub async fn create_queue(
+    pool: &PgPool,
+    project_id: &Uuid,
+    name: &str,
+    filter: &QueueFilter,
+    label_schema: &LabelSchema,
+    lease_seconds: i32,
+) -> Result<LabelingQueue> {
+    let queue = sqlx::query_as::<_, LabelingQueue>(
+        "INSERT INTO labeling_queues (project_id, name, filter, label_schema, lease_seconds)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, created_at, project_id, name, filter, label_schema, lease_seconds",
+    )
+    .bind(project_id)
+    .bind(name)
+    .bind(serde_json::to_value(filter)?)
+    .bind(serde_json::to_value(label_schema)?)
+    .bind(lease_seconds)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(queue)
+}
+
+pub async fn get_queues(pool: &PgPool, project_id: &Uuid) -> Result<Vec<LabelingQueue>> {
+    let queues = sqlx::query_as::<_, LabelingQueue>(
+        "SELECT id, created_at, project_id, name, filter, label_schema, lease_seconds
+        FROM labeling_queues
+        WHERE project_id = $1
+        ORDER BY created_at DESC",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(queues)
+}
+
+pub async fn get_queue(
+    pool: &PgPool,
+    project_id: &Uuid,
+    queue_id: &Uuid,
+) -> Result<Option<LabelingQueue>> {
+    let queue = sqlx::query_as::<_, LabelingQueue>(
+        "SELECT id, created_at, project_id, name, filter, label_schema, lease_seconds
+        FROM labeling_queues
+        WHERE id = $1 AND project_id = $2",
+    )
+    .bind(queue_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(queue)
+}
+
+/// Conditions on `runs` of the queue's project which match the queue's filter
+fn push_queue_filter(query: &mut QueryBuilder<'_, Postgres>, queue: &LabelingQueue) {
+    let filter = &queue.filter;
+    query
+        .push(" runs.project_id = ")
+        .push_bind(queue.project_id);
+    if let Some(pipeline_id) = filter.pipeline_id {
+        query
+            .push(" AND runs.pipeline_id = ")
+            .push_bind(pipeline_id);
+    }
+    if let Some(error_kind) = filter.error_kind {
+        query
+            .push(" AND runs.status = ")
+            .push_bind(error_kind.status());
+    }
+    if let Some(error_contains) = &filter.error_contains {
+        query
+            .push(" AND runs.error ILIKE '%' || ")
+            .push_bind(error_contains.clone())
+            .push(" || '%'");
+    }
+    if let Some(score) = &filter.score {
+        query
+            .push(
+                " AND EXISTS (
+                SELECT 1 FROM events
+                JOIN spans ON spans.span_id = events.span_id
+                JOIN event_templates ON event_templates.id = events.template_id
+                WHERE spans.trace_id = runs.trace_id
+                    AND event_templates.project_id = runs.project_id
+                    AND jsonb_typeof(events.value) = 'number'
+                    AND event_templates.name = ",
+            )
+            .push_bind(score.event.clone());
+        if let Some(min) = score.min {
+            query
+                .push(" AND (events.value #>> '{}')::float8 >= ")
+                .push_bind(min);
+        }
+        if let Some(max) = score.max {
+            query
+                .push(" AND (events.value #>> '{}')::float8 <= ")
+                .push_bind(max);
+        }
+        query.push(")");
+    }
+    if let Some(flag_event) = &filter.flag_event {
+        query
+            .push(
+                " AND EXISTS (
+                SELECT 1 FROM events
+                JOIN spans ON spans.span_id = events.span_id
+                JOIN event_templates ON event_templates.id = events.template_id
+                WHERE spans.trace_id = runs.trace_id
+                    AND event_templates.project_id = runs.project_id
+                    AND events.value = 'true'::jsonb
+                    AND event_templates.name = ",
+            )
+            .push_bind(flag_event.clone())
+            .push(")");
+    }
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunLease {
+    pub run_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Lease the oldest unlabeled run of the queue which isn't leased by another reviewer. A
+/// reviewer's unexpired lease is renewed and returned before other runs.
+pub async fn lease_next_run(
+    pool: &PgPool,
+    queue: &LabelingQueue,
+    user_id: &Uuid,
+) -> Result<Option<RunLease>> {
+    let mut tx = pool.begin().await?;
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT runs.id FROM runs WHERE");
+    push_queue_filter(&mut query, queue);
+    query
+        .push(
+            " AND NOT EXISTS (
+            SELECT 1 FROM run_labels
+            WHERE run_labels.queue_id = ",
+        )
+        .push_bind(queue.id)
+        .push(
+            " AND run_labels.run_id = runs.id)
+            AND NOT EXISTS (
+            SELECT 1 FROM labeling_queue_leases leases
+            WHERE leases.queue_id = ",
+        )
+        .push_bind(queue.id)
+        .push(" AND leases.run_id = runs.id AND leases.expires_at > now() AND leases.leased_by <> ")
+        .push_bind(user_id)
+        .push(
+            ")
+            ORDER BY EXISTS (
+            SELECT 1 FROM labeling_queue_leases leases
+            WHERE leases.queue_id = ",
+        )
+        .push_bind(queue.id)
+        .push(" AND leases.run_id = runs.id AND leases.leased_by = ")
+        .push_bind(user_id)
+        // runs being leased by a concurrent request are skipped
+        .push(") DESC, runs.created_at, runs.id LIMIT 1 FOR UPDATE OF runs SKIP LOCKED");
+    let run_id = query
+        .build_query_scalar::<Uuid>()
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(run_id) = run_id else {
+        return Ok(None);
+    };
+
+    // The lease of another reviewer may have been committed after the run was selected, it's
+    // only replaced if it expired
+    let lease = sqlx::query_as::<_, RunLease>(
+        "INSERT INTO labeling_queue_leases (queue_id, run_id, leased_by, expires_at)
+        VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+        ON CONFLICT (queue_id, run_id) DO UPDATE SET
+            leased_by = EXCLUDED.leased_by,
+            expires_at = EXCLUDED.expires_at
+        WHERE labeling_queue_leases.expires_at <= now()
+            OR labeling_queue_leases.leased_by = EXCLUDED.leased_by
+        RETURNING run_id, expires_at",
+    )
+    .bind(queue.id)
+    .bind(run_id)
+    .bind(user_id)
+    .bind(queue.lease_seconds as f64)
+    .fetch_optional(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(lease)
+}
+
+/// Whether the run is an item of the queue
+pub async fn is_queue_run(pool: &PgPool, queue: &LabelingQueue, run_id: &Uuid) -> Result<bool> {
+    let mut query = QueryBuilder::<Postgres>::new("SELECT EXISTS (SELECT 1 FROM runs WHERE");
+    push_queue_filter(&mut query, queue);
+    query.push(" AND runs.id = ").push_bind(run_id).push(")");
+
+    let exists = query.build_query_scalar::<bool>().fetch_one(pool).await?;
+
+    Ok(exists)
+}
+
+/// Store the label of the run and release its lease. None if another reviewer holds an
+/// unexpired lease of the run.
+pub async fn submit_label(
+    pool: &PgPool,
+    queue_id: &Uuid,
+    run_id: &Uuid,
+    user_id: &Uuid,
+    values: &Value,
+) -> Result<Option<RunLabel>> {
+    let mut tx = pool.begin().await?;
+    let leased_by = sqlx::query_scalar::<_, Uuid>(
+        "SELECT leased_by FROM labeling_queue_leases
+        WHERE queue_id = $1 AND run_id = $2 AND expires_at > now()
+        FOR UPDATE",
+    )
+    .bind(queue_id)
+    .bind(run_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if leased_by.is_some_and(|leased_by| &leased_by != user_id) {
+        return Ok(None);
+    }
+
+    let label = sqlx::query_as::<_, RunLabel>(
+        "INSERT INTO run_labels (queue_id, run_id, labeled_by, label_values)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (queue_id, run_id) DO UPDATE SET
+            labeled_by = EXCLUDED.labeled_by,
+            label_values = EXCLUDED.label_values,
+            created_at = now()
+        RETURNING id, created_at, queue_id, run_id, labeled_by, label_values",
+    )
+    .bind(queue_id)
+    .bind(run_id)
+    .bind(user_id)
+    .bind(values)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM labeling_queue_leases WHERE queue_id = $1 AND run_id = $2")
+        .bind(queue_id)
+        .bind(run_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(Some(label))
+}
+
+pub async fn get_labels(pool: &PgPool, queue_id: &Uuid) -> Result<Vec<RunLabel>> {
+    let labels = sqlx::query_as::<_, RunLabel>(
+        "SELECT id, created_at, queue_id, run_id, labeled_by, label_values
+        FROM run_labels
+        WHERE queue_id = $1
+        ORDER BY created_at",
+    )
+    .bind(queue_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(labels)
+}
@@ -1,8 +1,10 @@
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, PgPool};
 use uuid::Uuid;
 
+use super::workspace::WorkspaceId;
+
 /// Combination of subscription tier and user-specific limits
 #[derive(Debug, FromRow, Clone)]
 pub struct SubscriptionLimits {
@@ -86,6 +88,27 @@ pub async fn get_limits_for_workspace(
     Ok(tier.into())
 }
 
+/// Log retention of the project's tier, i.e. the tier of its workspace's owner
+pub async fn get_log_retention_days(pool: &PgPool, project_id: &Uuid) -> anyhow::Result<i64> {
+    let log_retention_days = sqlx::query_scalar::<_, i64>(
+        "SELECT
+            subscription_tiers.log_retention_days
+        FROM
+            projects
+        JOIN members_of_workspaces ON members_of_workspaces.workspace_id = projects.workspace_id
+            AND members_of_workspaces.member_role = 'owner'::workspace_role
+        JOIN users ON users.id = members_of_workspaces.user_id
+        JOIN subscription_tiers ON subscription_tiers.id = users.tier_id
+        WHERE projects.id = $1
+        LIMIT 1",
+    )
+    .bind(project_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(log_retention_days)
+}
+
 #[derive(Debug, FromRow, Clone)]
 pub struct RunCount {
     pub _workspace_id: Uuid,
@@ -243,3 +266,63 @@ pub async fn get_workspace_stats(
 
     Ok(stats)
 }
+
+/// Limits of the runs of all projects of a workspace, unset limits don't apply
+#[derive(Debug, Clone, Copy, Default, PartialEq, FromRow, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceRunLimits {
+    /// Runs executing at once, counted on each instance
+    pub max_concurrent_runs: Option<i64>,
+    /// Tokens used by the runs of all API keys of the workspace per UTC day
+    pub tokens_per_day: Option<i64>,
+}
+
+impl WorkspaceRunLimits {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_concurrent_runs.is_some_and(|limit| limit < 1) {
+            return Err(anyhow::anyhow!("maxConcurrentRuns must be at least 1"));
+        }
+        if self.tokens_per_day.is_some_and(|limit| limit < 0) {
+            return Err(anyhow::anyhow!("tokensPerDay must not be negative"));
+        }
+        Ok(())
+    }
+}
+
+/// Limits of the workspace, unset if it has none
+pub async fn get_workspace_run_limits(
+    pool: &PgPool,
+    workspace_id: &WorkspaceId,
+) -> anyhow::Result<WorkspaceRunLimits> {
+    let limits = sqlx::query_as::<_, WorkspaceRunLimits>(
+        "SELECT max_concurrent_runs, tokens_per_day FROM workspace_run_limits
+        WHERE workspace_id = $1",
+    )
+    .bind(workspace_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(limits.unwrap_or_default())
+}
+
+pub async fn set_workspace_run_limits(
+    pool: &PgPool,
+    workspace_id: &WorkspaceId,
+    limits: &WorkspaceRunLimits,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO workspace_run_limits (workspace_id, max_concurrent_runs, tokens_per_day)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (workspace_id) DO UPDATE SET
+            max_concurrent_runs = EXCLUDED.max_concurrent_runs,
+            tokens_per_day = EXCLUDED.tokens_per_day,
+            updated_at = now()",
+    )
+    .bind(workspace_id)
+    .bind(limits.max_concurrent_runs)
+    .bind(limits.tokens_per_day)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -1,20 +1,34 @@
 use sqlx::PgPool;
 
 pub mod api_keys;
+pub mod checkpoints;
 pub mod datapoints;
 pub mod datasets;
+pub mod dead_letters;
+pub mod evaluation_backfills;
 pub mod evaluations;
 pub mod event_templates;
 pub mod events;
+pub mod idempotency_keys;
+pub mod labeling_queues;
 pub mod limits;
 pub mod metrics;
+pub mod model_defaults;
 pub mod modifiers;
+pub mod node_io;
 pub mod pipelines;
 pub mod projects;
+pub mod prompts;
+pub mod records;
+pub mod retention;
+pub mod runs;
+pub mod secrets;
+pub mod semantic_indexes;
 pub mod stats;
 pub mod trace;
 pub mod user;
 pub mod utils;
+pub mod webhooks;
 pub mod workspace;
 
 #[derive(Clone, Debug)]
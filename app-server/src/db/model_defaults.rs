@@ -0,0 +1,76 @@
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::pipeline::model_defaults::ModelDefaults;
+
+#[derive(FromRow)]
+struct ModelDefaultsRow {
+    model: Option<String>,
+    params: Value,
+}
+
+impl From<ModelDefaultsRow> for ModelDefaults {
+    fn from(row: ModelDefaultsRow) -> Self {
+        ModelDefaults {
+            model: row.model,
+            params: match row.params {
+                Value::Object(params) => params,
+                _ => Default::default(),
+            },
+        }
+    }
+}
+
+/// Defaults of the workspace, empty if it has none
+pub async fn get_workspace_model_defaults(
+    pool: &PgPool,
+    workspace_id: &Uuid,
+) -> Result<ModelDefaults> {
+    let row = sqlx::query_as::<_, ModelDefaultsRow>(
+        "SELECT model, params FROM workspace_model_defaults WHERE workspace_id = $1",
+    )
+    .bind(workspace_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(ModelDefaults::from).unwrap_or_default())
+}
+
+/// Defaults of the workspace of the project, which its runs are resolved with
+pub async fn get_project_model_defaults(pool: &PgPool, project_id: &Uuid) -> Result<ModelDefaults> {
+    let row = sqlx::query_as::<_, ModelDefaultsRow>(
+        "SELECT workspace_model_defaults.model, workspace_model_defaults.params
+        FROM workspace_model_defaults
+        JOIN projects ON projects.workspace_id = workspace_model_defaults.workspace_id
+        WHERE projects.id = $1",
+    )
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(ModelDefaults::from).unwrap_or_default())
+}
+
+pub async fn set_workspace_model_defaults(
+    pool: &PgPool,
+    workspace_id: &Uuid,
+    defaults: &ModelDefaults,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO workspace_model_defaults (workspace_id, model, params)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (workspace_id) DO UPDATE SET
+            model = EXCLUDED.model,
+            params = EXCLUDED.params,
+            updated_at = now()",
+    )
+    .bind(workspace_id)
+    .bind(&defaults.model)
+    .bind(Value::Object(defaults.params.clone()))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
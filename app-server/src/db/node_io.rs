@@ -0,0 +1,80 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Compressed record of a node execution, see `runs::node_io::NodeIoRecord`
+pub struct NewNodeIo {
+    pub node_id: Uuid,
+    pub execution: i32,
+    pub data: Vec<u8>,
+}
+
+pub async fn insert_node_io(
+    pool: &PgPool,
+    run_id: &Uuid,
+    records: Vec<NewNodeIo>,
+    expires_at: DateTime<Utc>,
+) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let node_ids = records.iter().map(|r| r.node_id).collect::<Vec<_>>();
+    let executions = records.iter().map(|r| r.execution).collect::<Vec<_>>();
+    let data = records.into_iter().map(|r| r.data).collect::<Vec<_>>();
+
+    sqlx::query(
+        "INSERT INTO run_node_io (run_id, node_id, execution, data, expires_at)
+        SELECT $1, node_id, execution, data, $5
+        FROM UNNEST($2::uuid[], $3::int4[], $4::bytea[]) AS t(node_id, execution, data)
+        ON CONFLICT (run_id, node_id, execution) DO UPDATE SET
+            data = EXCLUDED.data,
+            expires_at = EXCLUDED.expires_at",
+    )
+    .bind(run_id)
+    .bind(&node_ids)
+    .bind(&executions)
+    .bind(&data)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records of the node's executions in the run, or of all nodes if `node_id` is None
+pub async fn get_node_io(
+    pool: &PgPool,
+    run_id: &Uuid,
+    node_id: Option<&Uuid>,
+) -> Result<Vec<Vec<u8>>> {
+    let data = sqlx::query_scalar::<_, Vec<u8>>(
+        "SELECT data
+        FROM run_node_io
+        WHERE run_id = $1 AND ($2::uuid IS NULL OR node_id = $2) AND expires_at > now()
+        ORDER BY node_id, execution",
+    )
+    .bind(run_id)
+    .bind(node_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(data)
+}
+
+pub async fn delete_expired_node_io(pool: &PgPool) -> Result<u64> {
+    let res = sqlx::query("DELETE FROM run_node_io WHERE expires_at < now()")
+        .execute(pool)
+        .await?;
+
+    Ok(res.rows_affected())
+}
+
+pub async fn delete_node_io_of_runs(pool: &PgPool, run_ids: &[Uuid]) -> Result<()> {
+    sqlx::query("DELETE FROM run_node_io WHERE run_id = ANY($1)")
+        .bind(run_ids)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, PgPool};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Deserialize, Serialize, FromRow)]
+#[derive(Debug, Clone, Deserialize, Serialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Pipeline {
     pub id: Option<Uuid>,
@@ -11,9 +12,12 @@ pub struct Pipeline {
     pub project_id: Uuid,
     pub name: String,
     pub visibility: String,
+    /// Record inputs and outputs of the nodes of every run, None keeps the setting on update
+    #[serde(default)]
+    pub record_node_io: Option<bool>,
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(Serialize, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PipelineWithTargetVersion {
     pub id: Uuid,
@@ -21,6 +25,7 @@ pub struct PipelineWithTargetVersion {
     pub project_id: Uuid,
     pub name: String,
     pub visibility: String,
+    pub record_node_io: bool,
     pub target_version_id: Option<Uuid>,
 }
 
@@ -33,7 +38,7 @@ pub async fn write_pipeline(
 ) -> Result<Pipeline> {
     let pipeline = sqlx::query_as::<_, Pipeline>(
         "INSERT INTO pipelines (id, project_id, name, visibility) values ($1, $2, $3, $4)
-        RETURNING id, created_at, project_id, name, visibility",
+        RETURNING id, created_at, project_id, name, visibility, record_node_io",
     )
     .bind(&id)
     .bind(&project_id)
@@ -56,6 +61,7 @@ pub async fn get_pipeline_by_id(
             pipelines.name,
             pipelines.project_id,
             pipelines.visibility,
+            pipelines.record_node_io,
             target_pipeline_versions.pipeline_version_id as target_version_id
         FROM
             pipelines
@@ -81,12 +87,15 @@ pub async fn delete_pipeline(pool: &PgPool, pipeline_id: &Uuid) -> Result<()> {
 
 pub async fn update_pipeline(pool: &PgPool, pipeline: &Pipeline) -> Result<Pipeline> {
     let updated_pipeline = sqlx::query_as::<_, Pipeline>(
-        "UPDATE pipelines SET name = $2, visibility = $3 WHERE id = $1
-        RETURNING id, created_at, project_id, name, visibility",
+        "UPDATE pipelines
+        SET name = $2, visibility = $3, record_node_io = COALESCE($4, record_node_io)
+        WHERE id = $1
+        RETURNING id, created_at, project_id, name, visibility, record_node_io",
     )
     .bind(pipeline.id)
     .bind(&pipeline.name)
     .bind(&pipeline.visibility)
+    .bind(pipeline.record_node_io)
     .fetch_optional(pool)
     .await?;
 
@@ -95,7 +104,7 @@ pub async fn update_pipeline(pool: &PgPool, pipeline: &Pipeline) -> Result<Pipel
 
 pub async fn get_pipeline_by_version_id(pool: &PgPool, version_id: &Uuid) -> Result<Pipeline> {
     let pipeline = sqlx::query_as::<_, Pipeline>(
-        "SELECT id, created_at, project_id, name, visibility
+        "SELECT id, created_at, project_id, name, visibility, record_node_io
         FROM pipelines
         WHERE id = (SELECT pipeline_id FROM pipeline_versions WHERE id = $1)",
     )
@@ -106,6 +115,35 @@ pub async fn get_pipeline_by_version_id(pool: &PgPool, version_id: &Uuid) -> Res
     pipeline.context("pipeline not found")
 }
 
+pub async fn get_pipeline_by_name(
+    pool: &PgPool,
+    project_id: &Uuid,
+    name: &str,
+) -> Result<Option<PipelineWithTargetVersion>> {
+    let pipeline = sqlx::query_as::<_, PipelineWithTargetVersion>(
+        "SELECT
+            pipelines.id,
+            pipelines.created_at,
+            pipelines.name,
+            pipelines.project_id,
+            pipelines.visibility,
+            pipelines.record_node_io,
+            target_pipeline_versions.pipeline_version_id as target_version_id
+        FROM
+            pipelines
+        LEFT JOIN target_pipeline_versions ON target_pipeline_versions.pipeline_id = pipelines.id
+        WHERE
+            pipelines.project_id = $1
+            AND pipelines.name = $2",
+    )
+    .bind(project_id)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(pipeline)
+}
+
 pub async fn get_pipelines_of_project(
     pool: &PgPool,
     project_id: &Uuid,
@@ -117,6 +155,7 @@ pub async fn get_pipelines_of_project(
           pipelines.name,
           pipelines.project_id,
           pipelines.visibility,
+          pipelines.record_node_io,
           target_pipeline_versions.pipeline_version_id as target_version_id
       FROM
           pipelines
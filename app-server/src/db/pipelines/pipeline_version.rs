@@ -18,6 +18,9 @@ pub struct PipelineVersion {
     pub runnable_graph: Value,
     #[serde(default)]
     pub created_at: DateTime<Utc>,
+    /// Hash of the runnable graph, set for immutable COMMIT versions only
+    #[serde(default)]
+    pub content_hash: Option<String>,
 }
 
 #[derive(FromRow)]
@@ -29,6 +32,7 @@ pub struct PipelineVersionWithPipelineName {
     pub displayable_graph: Value,
     pub runnable_graph: Value,
     pub created_at: DateTime<Utc>,
+    pub content_hash: Option<String>,
     pub pipeline_name: String,
 }
 
@@ -49,6 +53,7 @@ pub struct PipelineVersionInfo {
     pub pipeline_type: String,
     pub name: String,
     pub created_at: DateTime<Utc>,
+    pub content_hash: Option<String>,
 }
 
 pub async fn get_pipeline_versions(
@@ -63,7 +68,8 @@ pub async fn get_pipeline_versions(
             name,
             displayable_graph,
             runnable_graph,
-            created_at
+            created_at,
+            content_hash
         FROM
             pipeline_versions
         WHERE
@@ -86,7 +92,8 @@ pub async fn get_commit_pipeline_versions_info(
                 pipeline_versions.name,
                 pipeline_versions.pipeline_id,
                 pipeline_versions.pipeline_type,
-                pipeline_versions.created_at
+                pipeline_versions.created_at,
+                pipeline_versions.content_hash
             FROM
                 pipeline_versions
             WHERE
@@ -111,7 +118,8 @@ pub async fn get_pipeline_versions_info(
                 pipeline_versions.name,
                 pipeline_versions.pipeline_id,
                 pipeline_versions.pipeline_type,
-                pipeline_versions.created_at
+                pipeline_versions.created_at,
+                pipeline_versions.content_hash
             FROM
                 pipeline_versions
             WHERE
@@ -124,6 +132,7 @@ pub async fn get_pipeline_versions_info(
     .await?)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_pipeline_version(
     pool: &PgPool,
     id: Uuid,
@@ -132,11 +141,12 @@ pub async fn create_pipeline_version(
     name: &str,
     displayable_graph: &Value,
     runnable_graph: &Value,
+    content_hash: Option<&str>,
 ) -> Result<PipelineVersion> {
     let pipeline_version = sqlx::query_as::<_, PipelineVersion>(
-        "INSERT INTO pipeline_versions (id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph) 
-        VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, created_at",
+        "INSERT INTO pipeline_versions (id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, content_hash) 
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, created_at, content_hash",
     )
     .bind(id)
     .bind(pipeline_id)
@@ -144,6 +154,7 @@ pub async fn create_pipeline_version(
     .bind(name)
     .bind(displayable_graph)
     .bind(runnable_graph)
+    .bind(content_hash)
     .fetch_one(pool)
     .await?;
 
@@ -155,15 +166,17 @@ pub async fn clone_pipeline_version(
     ref_pipeline_version_id: Uuid,
     new_pipeline_version_name: &str,
     new_pipeline_version_type: &str,
+    content_hash: Option<&str>,
 ) -> Result<Uuid> {
     let pipeline_version = sqlx::query_as::<_, PipelineVersion>(
-        "INSERT INTO pipeline_versions (pipeline_id, pipeline_type, name, displayable_graph, runnable_graph) 
-        SELECT pipeline_id, $1, $2, displayable_graph, runnable_graph FROM pipeline_versions WHERE id = $3
-        RETURNING id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, created_at",
+        "INSERT INTO pipeline_versions (pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, content_hash) 
+        SELECT pipeline_id, $1, $2, displayable_graph, runnable_graph, $4 FROM pipeline_versions WHERE id = $3
+        RETURNING id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, created_at, content_hash",
     )
     .bind(new_pipeline_version_type)
     .bind(new_pipeline_version_name)
     .bind(ref_pipeline_version_id)
+    .bind(content_hash)
     .fetch_one(pool)
     .await?;
 
@@ -180,7 +193,7 @@ pub async fn clone_pipeline_version_to_pipeline(
     let pipeline_version = sqlx::query_as::<_, PipelineVersion>(
         "INSERT INTO pipeline_versions (pipeline_id, pipeline_type, name, displayable_graph, runnable_graph) 
         SELECT $2, $3, $4, displayable_graph, runnable_graph FROM pipeline_versions WHERE id = $1
-        RETURNING id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, created_at",
+        RETURNING id, pipeline_id, pipeline_type, name, displayable_graph, runnable_graph, created_at, content_hash",
     )
     .bind(ref_pipeline_version_id)
     .bind(pipeline_id)
@@ -236,7 +249,8 @@ pub async fn get_pipeline_version(pool: &PgPool, version_id: &Uuid) -> Result<Pi
             pipeline_versions.name,
             pipeline_versions.displayable_graph,
             pipeline_versions.runnable_graph,
-            pipeline_versions.created_at
+            pipeline_versions.created_at,
+            pipeline_versions.content_hash
         FROM
             pipeline_versions
         WHERE
@@ -262,6 +276,7 @@ pub async fn get_pipeline_version_with_pipeline_name(
             pipeline_versions.displayable_graph,
             pipeline_versions.runnable_graph,
             pipeline_versions.created_at,
+            pipeline_versions.content_hash,
             pipelines.name as pipeline_name
         FROM
             pipeline_versions
@@ -310,7 +325,8 @@ pub async fn get_target_pipeline_version_by_pipeline_name(
             pipeline_versions.name,
             pipeline_versions.displayable_graph,
             pipeline_versions.runnable_graph,
-            pipeline_versions.created_at
+            pipeline_versions.created_at,
+            pipeline_versions.content_hash
         FROM
             pipeline_versions
         WHERE
@@ -348,3 +364,109 @@ pub async fn update_pipeline_version(pool: &PgPool, version: &PipelineVersion) -
 
     Ok(())
 }
+
+/// Find a COMMIT version of the pipeline with the given content hash
+pub async fn get_commit_pipeline_version_by_hash(
+    pool: &PgPool,
+    pipeline_id: &Uuid,
+    content_hash: &str,
+) -> Result<Option<PipelineVersion>> {
+    let version = sqlx::query_as::<_, PipelineVersion>(
+        "SELECT
+            pipeline_versions.id,
+            pipeline_versions.pipeline_id,
+            pipeline_versions.pipeline_type,
+            pipeline_versions.name,
+            pipeline_versions.displayable_graph,
+            pipeline_versions.runnable_graph,
+            pipeline_versions.created_at,
+            pipeline_versions.content_hash
+        FROM
+            pipeline_versions
+        WHERE
+            pipeline_versions.pipeline_id = $1
+            AND pipeline_versions.pipeline_type = 'COMMIT'
+            AND pipeline_versions.content_hash = $2",
+    )
+    .bind(pipeline_id)
+    .bind(content_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(version)
+}
+
+/// Resolve a pinned COMMIT version of a pipeline by its id or content hash
+pub async fn get_commit_pipeline_version_by_pipeline_name(
+    pool: &PgPool,
+    project_id: Uuid,
+    pipeline_name: &str,
+    version: &str,
+) -> Result<Option<PipelineVersion>> {
+    let version = sqlx::query_as::<_, PipelineVersion>(
+        "SELECT
+            pipeline_versions.id,
+            pipeline_versions.pipeline_id,
+            pipeline_versions.pipeline_type,
+            pipeline_versions.name,
+            pipeline_versions.displayable_graph,
+            pipeline_versions.runnable_graph,
+            pipeline_versions.created_at,
+            pipeline_versions.content_hash
+        FROM
+            pipeline_versions
+        JOIN
+            pipelines ON pipeline_versions.pipeline_id = pipelines.id
+        WHERE
+            pipelines.project_id = $1
+            AND pipelines.name = $2
+            AND pipeline_versions.pipeline_type = 'COMMIT'
+            AND (pipeline_versions.id::text = $3 OR pipeline_versions.content_hash = $3)",
+    )
+    .bind(project_id)
+    .bind(pipeline_name)
+    .bind(version)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(version)
+}
+
+/// Check if any recorded run was executed with the pipeline version, or any evaluation or
+/// backfill runs it, as the evaluated pipeline or as a judge
+pub async fn is_pipeline_version_referenced(pool: &PgPool, version_id: &Uuid) -> Result<bool> {
+    let referenced = sqlx::query_scalar::<_, bool>(
+        "SELECT
+            EXISTS(SELECT 1 FROM runs WHERE pipeline_version_id = $1)
+            OR EXISTS(
+                SELECT 1 FROM spans WHERE attributes ->> 'lmnr.pipeline.version_id' = $1::text
+            )
+            OR EXISTS(
+                SELECT 1 FROM evaluations
+                WHERE config ->> 'pipelineVersionId' = $1::text
+                    OR config -> 'evaluators' @> jsonb_build_array(
+                        jsonb_build_object('pipelineVersionId', $1::text)
+                    )
+            )
+            OR EXISTS(
+                SELECT 1 FROM evaluation_backfills
+                WHERE config -> 'evaluators' @> jsonb_build_array(
+                    jsonb_build_object('pipelineVersionId', $1::text)
+                )
+            )",
+    )
+    .bind(version_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(referenced)
+}
+
+pub async fn delete_pipeline_version(pool: &PgPool, version_id: &Uuid) -> Result<()> {
+    sqlx::query("DELETE FROM pipeline_versions WHERE id = $1")
+        .bind(version_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::pipeline::prompts::PromptSelector;
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Prompt {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub name: String,
+    pub latest_version: i32,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptVersion {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub prompt_id: Uuid,
+    pub version: i32,
+    pub template: String,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptLabel {
+    pub label: String,
+    pub version: i32,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Add a version of the prompt with the name, creating the prompt if the project has none
+pub async fn create_prompt_version(
+    pool: &PgPool,
+    project_id: &Uuid,
+    name: &str,
+    template: &str,
+) -> Result<PromptVersion> {
+    let mut tx = pool.begin().await?;
+
+    // the upsert locks the prompt, so that concurrent updates get consecutive versions
+    let prompt_id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO prompts (project_id, name) VALUES ($1, $2)
+        ON CONFLICT (project_id, name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id",
+    )
+    .bind(project_id)
+    .bind(name)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let version = sqlx::query_as::<_, PromptVersion>(
+        "INSERT INTO prompt_versions (prompt_id, version, template)
+        SELECT $1, COALESCE(MAX(version), 0) + 1, $2 FROM prompt_versions WHERE prompt_id = $1
+        RETURNING id, created_at, prompt_id, version, template",
+    )
+    .bind(prompt_id)
+    .bind(template)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(version)
+}
+
+pub async fn get_prompts(pool: &PgPool, project_id: &Uuid) -> Result<Vec<Prompt>> {
+    let prompts = sqlx::query_as::<_, Prompt>(
+        "SELECT prompts.id, prompts.created_at, prompts.project_id, prompts.name,
+            (SELECT MAX(version) FROM prompt_versions WHERE prompt_id = prompts.id)
+            as latest_version
+        FROM prompts
+        WHERE project_id = $1
+        ORDER BY name",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(prompts)
+}
+
+pub async fn get_prompt(pool: &PgPool, project_id: &Uuid, name: &str) -> Result<Option<Prompt>> {
+    let prompt = sqlx::query_as::<_, Prompt>(
+        "SELECT prompts.id, prompts.created_at, prompts.project_id, prompts.name,
+            (SELECT MAX(version) FROM prompt_versions WHERE prompt_id = prompts.id)
+            as latest_version
+        FROM prompts
+        WHERE project_id = $1 AND name = $2",
+    )
+    .bind(project_id)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(prompt)
+}
+
+/// Versions of the prompt, latest first
+pub async fn get_prompt_versions(pool: &PgPool, prompt_id: &Uuid) -> Result<Vec<PromptVersion>> {
+    let versions = sqlx::query_as::<_, PromptVersion>(
+        "SELECT id, created_at, prompt_id, version, template
+        FROM prompt_versions
+        WHERE prompt_id = $1
+        ORDER BY version DESC",
+    )
+    .bind(prompt_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(versions)
+}
+
+pub async fn get_prompt_labels(pool: &PgPool, prompt_id: &Uuid) -> Result<Vec<PromptLabel>> {
+    let labels = sqlx::query_as::<_, PromptLabel>(
+        "SELECT prompt_labels.label, prompt_versions.version, prompt_labels.updated_at
+        FROM prompt_labels
+        JOIN prompt_versions ON prompt_versions.id = prompt_labels.version_id
+        WHERE prompt_labels.prompt_id = $1
+        ORDER BY prompt_labels.label",
+    )
+    .bind(prompt_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(labels)
+}
+
+/// Point the label of the prompt to the version, unset if the prompt has no such version
+pub async fn set_prompt_label(
+    pool: &PgPool,
+    prompt_id: &Uuid,
+    label: &str,
+    version: i32,
+) -> Result<Option<PromptLabel>> {
+    let label = sqlx::query_as::<_, PromptLabel>(
+        "INSERT INTO prompt_labels (prompt_id, label, version_id)
+        SELECT prompt_id, $2, id FROM prompt_versions WHERE prompt_id = $1 AND version = $3
+        ON CONFLICT (prompt_id, label) DO UPDATE SET
+            version_id = EXCLUDED.version_id,
+            updated_at = now()
+        RETURNING label, $3 as version, updated_at",
+    )
+    .bind(prompt_id)
+    .bind(label)
+    .bind(version)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(label)
+}
+
+/// Version of the project's prompt with the name which the selector selects, if any
+pub async fn get_selected_prompt_version(
+    pool: &PgPool,
+    project_id: &Uuid,
+    name: &str,
+    selector: &PromptSelector,
+) -> Result<Option<PromptVersion>> {
+    let condition = match selector {
+        PromptSelector::Latest => "ORDER BY prompt_versions.version DESC LIMIT 1",
+        PromptSelector::Label(_) => {
+            "AND EXISTS (
+            SELECT 1 FROM prompt_labels
+            WHERE prompt_labels.version_id = prompt_versions.id AND prompt_labels.label = $3)"
+        }
+        PromptSelector::Version(_) => "AND prompt_versions.version = $3",
+    };
+    let sql = format!(
+        "SELECT prompt_versions.id, prompt_versions.created_at, prompt_versions.prompt_id,
+            prompt_versions.version, prompt_versions.template
+        FROM prompt_versions
+        JOIN prompts ON prompts.id = prompt_versions.prompt_id
+        WHERE prompts.project_id = $1 AND prompts.name = $2 {condition}"
+    );
+    let query = sqlx::query_as::<_, PromptVersion>(&sql)
+        .bind(project_id)
+        .bind(name);
+    let query = match selector {
+        PromptSelector::Latest => query,
+        PromptSelector::Label(label) => query.bind(label),
+        PromptSelector::Version(version) => query.bind(version),
+    };
+    let version = query.fetch_optional(pool).await?;
+
+    Ok(version)
+}
@@ -0,0 +1,184 @@
+//! Projects of the records which requests address by id
+//!
+//! Routes of a project look up most of their records by id alone, so a request is checked
+//! before it's routed: every record its path addresses must belong to the project of the path,
+//! which the user is a member of, and so to a workspace of the user. Stores which query by
+//! project already, e.g. API keys, secrets and webhooks, have no records here.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use super::DB;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProjectRecord {
+    Pipeline(Uuid),
+    PipelineVersion(Uuid),
+    Dataset(Uuid),
+    Datapoint(Uuid),
+    Evaluation(Uuid),
+    /// Result of an evaluation, addressed as a datapoint of the evaluation
+    EvaluationDatapoint(Uuid),
+    EventTemplate(Uuid),
+    Trace(Uuid),
+    Span(Uuid),
+}
+
+/// Project of a record, and whether it's of a public pipeline
+#[derive(Debug, Clone, Copy, PartialEq, FromRow)]
+pub struct RecordOwner {
+    pub project_id: Uuid,
+    pub public: bool,
+}
+
+impl ProjectRecord {
+    /// Records addressed by the path within a project, e.g. `pipelines/{id}/versions/{id}`
+    pub fn from_path(path: &str) -> Vec<Self> {
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let mut records = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            let Some(id) = segments.get(i + 1).and_then(|id| Uuid::parse_str(id).ok()) else {
+                continue;
+            };
+            let record = match *segment {
+                "pipelines" => Self::Pipeline(id),
+                "versions" | "pipeline-versions" => Self::PipelineVersion(id),
+                "datasets" => Self::Dataset(id),
+                "datapoints" => match records.last() {
+                    Some(Self::Evaluation(_)) => Self::EvaluationDatapoint(id),
+                    _ => Self::Datapoint(id),
+                },
+                "evaluations" => Self::Evaluation(id),
+                "event-templates" => Self::EventTemplate(id),
+                "traces" => Self::Trace(id),
+                "spans" => Self::Span(id),
+                _ => continue,
+            };
+            records.push(record);
+        }
+        records
+    }
+
+    /// Query of the owner of the record, by its id
+    fn owner_query(&self) -> (&'static str, Uuid) {
+        match *self {
+            Self::Pipeline(id) => (
+                "SELECT project_id, visibility = 'PUBLIC' as public FROM pipelines WHERE id = $1",
+                id,
+            ),
+            Self::PipelineVersion(id) => (
+                "SELECT pipelines.project_id, pipelines.visibility = 'PUBLIC' as public
+                FROM pipeline_versions
+                JOIN pipelines ON pipelines.id = pipeline_versions.pipeline_id
+                WHERE pipeline_versions.id = $1",
+                id,
+            ),
+            Self::Dataset(id) => (
+                "SELECT project_id, false as public FROM datasets WHERE id = $1",
+                id,
+            ),
+            Self::Datapoint(id) => (
+                "SELECT datasets.project_id, false as public
+                FROM dataset_datapoints
+                JOIN datasets ON datasets.id = dataset_datapoints.dataset_id
+                WHERE dataset_datapoints.id = $1",
+                id,
+            ),
+            Self::Evaluation(id) => (
+                "SELECT project_id, false as public FROM evaluations WHERE id = $1",
+                id,
+            ),
+            Self::EvaluationDatapoint(id) => (
+                "SELECT evaluations.project_id, false as public
+                FROM evaluation_results
+                JOIN evaluations ON evaluations.id = evaluation_results.evaluation_id
+                WHERE evaluation_results.id = $1",
+                id,
+            ),
+            Self::EventTemplate(id) => (
+                "SELECT project_id, false as public FROM event_templates WHERE id = $1",
+                id,
+            ),
+            Self::Trace(id) => (
+                "SELECT project_id, false as public FROM traces WHERE id = $1",
+                id,
+            ),
+            Self::Span(id) => (
+                "SELECT traces.project_id, false as public
+                FROM spans
+                JOIN traces ON traces.id = spans.trace_id
+                WHERE spans.span_id = $1",
+                id,
+            ),
+        }
+    }
+}
+
+/// Owner of the record, unset if it doesn't exist
+pub async fn get_record_owner(
+    pool: &PgPool,
+    record: &ProjectRecord,
+) -> Result<Option<RecordOwner>> {
+    let (query, id) = record.owner_query();
+    let owner = sqlx::query_as::<_, RecordOwner>(query)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(owner)
+}
+
+/// Lookup of the owners of records, which requests are checked with
+#[async_trait]
+pub trait RecordOwners: Send + Sync {
+    /// Owner of the record, unset if it doesn't exist
+    async fn get_owner(&self, record: &ProjectRecord) -> Result<Option<RecordOwner>>;
+}
+
+#[async_trait]
+impl RecordOwners for DB {
+    async fn get_owner(&self, record: &ProjectRecord) -> Result<Option<RecordOwner>> {
+        get_record_owner(&self.pool, record).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_from_path() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(
+            ProjectRecord::from_path(&format!(
+                "/pipelines/{a}/versions/{b}/nodes/{a}/model-config"
+            )),
+            vec![
+                ProjectRecord::Pipeline(a),
+                ProjectRecord::PipelineVersion(b)
+            ]
+        );
+        assert_eq!(
+            ProjectRecord::from_path(&format!("/evaluations/{a}/datapoints/{b}")),
+            vec![
+                ProjectRecord::Evaluation(a),
+                ProjectRecord::EvaluationDatapoint(b)
+            ]
+        );
+        assert_eq!(
+            ProjectRecord::from_path(&format!("/datasets/{a}/datapoints/{b}")),
+            vec![ProjectRecord::Dataset(a), ProjectRecord::Datapoint(b)]
+        );
+        assert_eq!(
+            ProjectRecord::from_path(&format!("/traces/{a}")),
+            vec![ProjectRecord::Trace(a)]
+        );
+        assert!(ProjectRecord::from_path("/pipelines/run/graph").is_empty());
+        assert!(ProjectRecord::from_path(&format!("/secrets/{a}")).is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    retention::{PurgeKind, RetentionPolicy},
+    traces::attributes::LMNR_RETRIEVAL_DOCUMENTS,
+};
+
+/// Policy of the workspace, all TTLs are unset if it has none
+pub async fn get_retention_policy(pool: &PgPool, workspace_id: &Uuid) -> Result<RetentionPolicy> {
+    let policy = sqlx::query_as::<_, RetentionPolicy>(
+        "SELECT content_ttl_days, span_metadata_ttl_days, rollup_ttl_days
+        FROM workspace_retention_policies
+        WHERE workspace_id = $1",
+    )
+    .bind(workspace_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(policy.unwrap_or_default())
+}
+
+pub async fn set_retention_policy(
+    pool: &PgPool,
+    workspace_id: &Uuid,
+    policy: &RetentionPolicy,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO workspace_retention_policies
+            (workspace_id, content_ttl_days, span_metadata_ttl_days, rollup_ttl_days)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (workspace_id) DO UPDATE SET
+            content_ttl_days = EXCLUDED.content_ttl_days,
+            span_metadata_ttl_days = EXCLUDED.span_metadata_ttl_days,
+            rollup_ttl_days = EXCLUDED.rollup_ttl_days,
+            updated_at = now()",
+    )
+    .bind(workspace_id)
+    .bind(policy.content_ttl_days)
+    .bind(policy.span_metadata_ttl_days)
+    .bind(policy.rollup_ttl_days)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(FromRow)]
+pub struct ProjectRetention {
+    pub project_id: Uuid,
+    #[sqlx(flatten)]
+    pub policy: RetentionPolicy,
+}
+
+/// Projects of workspaces with a retention policy
+pub async fn get_project_retentions(pool: &PgPool) -> Result<Vec<ProjectRetention>> {
+    let projects = sqlx::query_as::<_, ProjectRetention>(
+        "SELECT
+            projects.id AS project_id,
+            workspace_retention_policies.content_ttl_days,
+            workspace_retention_policies.span_metadata_ttl_days,
+            workspace_retention_policies.rollup_ttl_days
+        FROM workspace_retention_policies
+        JOIN projects ON projects.workspace_id = workspace_retention_policies.workspace_id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(projects)
+}
+
+/// Traces of a project whose data is purged
+pub enum TraceSelector {
+    /// Traces which started before the cutoff
+    StartedBefore(DateTime<Utc>),
+    /// Traces of an end user of the project, by the user id set in tracing
+    EndUser(String),
+}
+
+/// Next batch of traces of the selector whose content isn't purged yet
+pub async fn get_traces_to_purge(
+    pool: &PgPool,
+    project_id: &Uuid,
+    selector: &TraceSelector,
+    limit: i64,
+) -> Result<Vec<Uuid>> {
+    let (cutoff, end_user_id) = match selector {
+        TraceSelector::StartedBefore(cutoff) => (Some(cutoff), None),
+        TraceSelector::EndUser(user_id) => (None, Some(user_id)),
+    };
+    let trace_ids = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM traces
+        WHERE project_id = $1
+        AND ($2::timestamptz IS NULL OR start_time < $2)
+        AND ($3::text IS NULL OR user_id = $3)
+        AND NOT EXISTS (SELECT 1 FROM purged_traces WHERE purged_traces.trace_id = traces.id)
+        LIMIT $4",
+    )
+    .bind(project_id)
+    .bind(cutoff)
+    .bind(end_user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(trace_ids)
+}
+
+/// Next batch of traces of the project which started before the cutoff
+pub async fn get_traces_to_delete(
+    pool: &PgPool,
+    project_id: &Uuid,
+    cutoff: &DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<Uuid>> {
+    let trace_ids = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM traces WHERE project_id = $1 AND start_time < $2 LIMIT $3",
+    )
+    .bind(project_id)
+    .bind(cutoff)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(trace_ids)
+}
+
+/// Runs whose trace is one of the traces
+pub async fn get_runs_of_traces(pool: &PgPool, trace_ids: &[Uuid]) -> Result<Vec<Uuid>> {
+    let run_ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM runs WHERE trace_id = ANY($1)")
+        .bind(trace_ids)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(run_ids)
+}
+
+/// Clear span inputs, outputs and retrieved documents, event inputs and run outputs of the
+/// traces, and mark them as purged
+pub async fn purge_trace_content(
+    pool: &PgPool,
+    project_id: &Uuid,
+    trace_ids: &[Uuid],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE events SET data = NULL, inputs = NULL
+        WHERE span_id IN (SELECT span_id FROM spans WHERE trace_id = ANY($1))",
+    )
+    .bind(trace_ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE spans SET input = NULL, output = NULL, attributes = attributes - $2
+        WHERE trace_id = ANY($1)",
+    )
+    .bind(trace_ids)
+    .bind(LMNR_RETRIEVAL_DOCUMENTS)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE runs SET outputs = NULL WHERE trace_id = ANY($1)")
+        .bind(trace_ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO purged_traces (trace_id, project_id)
+        SELECT trace_id, $2 FROM UNNEST($1::uuid[]) AS t(trace_id)
+        ON CONFLICT (trace_id) DO NOTHING",
+    )
+    .bind(trace_ids)
+    .bind(project_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Delete the traces with their spans and events, their content must be purged already
+pub async fn delete_traces(pool: &PgPool, trace_ids: &[Uuid]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    // events don't reference spans, so they aren't deleted with them
+    sqlx::query(
+        "DELETE FROM events
+        WHERE span_id IN (SELECT span_id FROM spans WHERE trace_id = ANY($1))",
+    )
+    .bind(trace_ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM traces WHERE id = ANY($1)")
+        .bind(trace_ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE purged_traces SET deleted_at = now() WHERE trace_id = ANY($1)")
+        .bind(trace_ids)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgedTrace {
+    pub content_purged_at: DateTime<Utc>,
+    /// Set if the trace was deleted, after the span metadata TTL
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+pub async fn get_purged_trace(pool: &PgPool, trace_id: &Uuid) -> Result<Option<PurgedTrace>> {
+    let purged = sqlx::query_as::<_, PurgedTrace>(
+        "SELECT content_purged_at, deleted_at FROM purged_traces WHERE trace_id = $1",
+    )
+    .bind(trace_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(purged)
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPurge {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub kind: PurgeKind,
+    /// Data older than the cutoff was purged, unset for erasures of an end user
+    pub cutoff: Option<DateTime<Utc>>,
+    pub end_user_id: Option<String>,
+    /// Traces whose content was purged or which were deleted, or spans of rollups
+    pub purged_rows: i64,
+}
+
+pub async fn record_purge(
+    pool: &PgPool,
+    project_id: &Uuid,
+    kind: PurgeKind,
+    cutoff: Option<DateTime<Utc>>,
+    end_user_id: Option<&str>,
+    purged_rows: i64,
+) -> Result<RetentionPurge> {
+    let purge = sqlx::query_as::<_, RetentionPurge>(
+        "INSERT INTO retention_purges (project_id, kind, cutoff, end_user_id, purged_rows)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, created_at, project_id, kind, cutoff, end_user_id, purged_rows",
+    )
+    .bind(project_id)
+    .bind(kind)
+    .bind(cutoff)
+    .bind(end_user_id)
+    .bind(purged_rows)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(purge)
+}
+
+/// Latest purges of the projects of the workspace
+pub async fn get_purges_of_workspace(
+    pool: &PgPool,
+    workspace_id: &Uuid,
+    limit: i64,
+) -> Result<Vec<RetentionPurge>> {
+    let purges = sqlx::query_as::<_, RetentionPurge>(
+        "SELECT
+            retention_purges.id,
+            retention_purges.created_at,
+            retention_purges.project_id,
+            retention_purges.kind,
+            retention_purges.cutoff,
+            retention_purges.end_user_id,
+            retention_purges.purged_rows
+        FROM retention_purges
+        JOIN projects ON projects.id = retention_purges.project_id
+        WHERE projects.workspace_id = $1
+        ORDER BY retention_purges.created_at DESC
+        LIMIT $2",
+    )
+    .bind(workspace_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(purges)
+}
@@ -0,0 +1,442 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(sqlx::Type, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[sqlx(type_name = "run_status")]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// Stopped by worker crashes or restarts too many times, see `RUN_QUEUE_MAX_ATTEMPTS`
+    Interrupted,
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub pipeline_id: Uuid,
+    pub pipeline_version_id: Uuid,
+    pub status: RunStatus,
+    pub trace_id: Uuid,
+    pub metadata: Value,
+    /// None until the run is finished, and after the result has expired
+    pub outputs: Option<Value>,
+    pub node_stats: Option<Value>,
+    pub error: Option<String>,
+    pub total_token_count: i64,
+    pub approximate_cost: Option<f64>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result_expires_at: Option<DateTime<Utc>>,
+    /// Id of the run this run is a replay of
+    pub replay_of: Option<Uuid>,
+    /// Why the trace of the run in strict trace mode wasn't persisted before it responded
+    pub trace_warning: Option<String>,
+}
+
+pub struct NewRun<'a> {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub pipeline_id: Uuid,
+    pub pipeline_version_id: Uuid,
+    pub status: RunStatus,
+    pub trace_id: Uuid,
+    pub metadata: &'a Value,
+    pub replay_of: Option<Uuid>,
+}
+
+pub struct RunResult {
+    pub status: RunStatus,
+    pub outputs: Option<Value>,
+    pub node_stats: Value,
+    pub error: Option<String>,
+    pub total_token_count: i64,
+    pub approximate_cost: Option<f64>,
+    pub result_expires_at: DateTime<Utc>,
+    pub trace_warning: Option<String>,
+}
+
+pub async fn create_run(pool: &PgPool, run: &NewRun<'_>) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO runs (
+            id,
+            project_id,
+            pipeline_id,
+            pipeline_version_id,
+            status,
+            trace_id,
+            metadata,
+            replay_of,
+            started_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CASE WHEN $5 = 'Running'::run_status THEN now() END)",
+    )
+    .bind(run.id)
+    .bind(run.project_id)
+    .bind(run.pipeline_id)
+    .bind(run.pipeline_version_id)
+    .bind(run.status)
+    .bind(run.trace_id)
+    .bind(run.metadata)
+    .bind(run.replay_of)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark queued run as running, returns false if the run was cancelled before it started
+pub async fn start_run(pool: &PgPool, run_id: &Uuid) -> Result<bool> {
+    let res = sqlx::query(
+        "UPDATE runs SET status = 'Running', started_at = now()
+        WHERE id = $1 AND status = 'Queued'",
+    )
+    .bind(run_id)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Outcome of a worker's claim of a run from the run queue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunClaim {
+    /// The run is leased to the worker, `attempt` counts from 1
+    Claimed { attempt: i32 },
+    /// Another worker runs the run, and its lease hasn't expired
+    Leased,
+    /// The run was cancelled, is finished, or has no attempts left
+    Unavailable,
+}
+
+/// Mark the run from the run queue as running, leased to the worker, counting the attempt
+///
+/// Redelivered runs are claimed even if they're already running, once the lease of the worker
+/// which started them expired, since that worker stopped before finishing them.
+pub async fn claim_queued_run(
+    pool: &PgPool,
+    run_id: &Uuid,
+    worker_id: &Uuid,
+    lease_seconds: i64,
+    redelivered: bool,
+    max_attempts: i32,
+) -> Result<RunClaim> {
+    let attempt = sqlx::query_scalar::<_, i32>(
+        "UPDATE runs SET
+            status = 'Running',
+            started_at = now(),
+            attempts = attempts + 1,
+            leased_by = $4,
+            lease_expires_at = now() + make_interval(secs => $5)
+        WHERE id = $1
+            AND (status = 'Queued' OR (
+                status = 'Running'
+                AND $2
+                AND (lease_expires_at IS NULL OR lease_expires_at < now())
+            ))
+            AND attempts < $3
+        RETURNING attempts",
+    )
+    .bind(run_id)
+    .bind(redelivered)
+    .bind(max_attempts)
+    .bind(worker_id)
+    .bind(lease_seconds as f64)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(attempt) = attempt {
+        return Ok(RunClaim::Claimed { attempt });
+    }
+
+    let leased = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (
+            SELECT 1 FROM runs
+            WHERE id = $1 AND status = 'Running' AND lease_expires_at >= now()
+        )",
+    )
+    .bind(run_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(if leased {
+        RunClaim::Leased
+    } else {
+        RunClaim::Unavailable
+    })
+}
+
+/// Extend the lease of the worker on the running run, returns false if the worker lost it, e.g.
+/// another worker took the run over, or the run is no longer running
+pub async fn renew_run_lease(
+    pool: &PgPool,
+    run_id: &Uuid,
+    worker_id: &Uuid,
+    lease_seconds: i64,
+) -> Result<bool> {
+    let res = sqlx::query(
+        "UPDATE runs SET lease_expires_at = now() + make_interval(secs => $3)
+        WHERE id = $1 AND leased_by = $2 AND status = 'Running'",
+    )
+    .bind(run_id)
+    .bind(worker_id)
+    .bind(lease_seconds as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Mark the running run as interrupted if it has no attempts left and its lease expired, returns
+/// whether it was marked
+pub async fn interrupt_run(
+    pool: &PgPool,
+    run_id: &Uuid,
+    max_attempts: i32,
+    error: &str,
+) -> Result<bool> {
+    let res = sqlx::query(
+        "UPDATE runs SET status = 'Interrupted', error = $3, finished_at = now()
+        WHERE id = $1
+            AND status = 'Running'
+            AND attempts >= $2
+            AND (lease_expires_at IS NULL OR lease_expires_at < now())",
+    )
+    .bind(run_id)
+    .bind(max_attempts)
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Write the result of the run, unless it has already been finished, e.g. cancelled
+///
+/// Results of runs from the run queue are only written by the worker `leased_by`, so that a
+/// worker whose run was taken over doesn't finish it.
+pub async fn finish_run(
+    pool: &PgPool,
+    run_id: &Uuid,
+    result: &RunResult,
+    leased_by: Option<&Uuid>,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE runs SET
+            status = $2,
+            outputs = $3,
+            node_stats = $4,
+            error = $5,
+            total_token_count = $6,
+            approximate_cost = $7,
+            result_expires_at = $8,
+            trace_warning = $9,
+            finished_at = now()
+        WHERE id = $1
+            AND status IN ('Queued', 'Running')
+            AND ($10::uuid IS NULL OR leased_by = $10)",
+    )
+    .bind(run_id)
+    .bind(result.status)
+    .bind(&result.outputs)
+    .bind(&result.node_stats)
+    .bind(&result.error)
+    .bind(result.total_token_count)
+    .bind(result.approximate_cost)
+    .bind(result.result_expires_at)
+    .bind(&result.trace_warning)
+    .bind(leased_by)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark the run as cancelled, returns its status before, or None if it's already finished
+pub async fn cancel_run(
+    pool: &PgPool,
+    run_id: &Uuid,
+    project_id: &Uuid,
+) -> Result<Option<RunStatus>> {
+    let status = sqlx::query_scalar::<_, RunStatus>(
+        "WITH old AS (
+            SELECT id, status FROM runs
+            WHERE id = $1 AND project_id = $2 AND status IN ('Queued', 'Running')
+            FOR UPDATE
+        )
+        UPDATE runs SET status = 'Cancelled', finished_at = now()
+        FROM old
+        WHERE runs.id = old.id
+        RETURNING old.status",
+    )
+    .bind(run_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(status)
+}
+
+pub async fn get_run(pool: &PgPool, run_id: &Uuid, project_id: &Uuid) -> Result<Option<Run>> {
+    let run = sqlx::query_as::<_, Run>(
+        "SELECT
+            id,
+            created_at,
+            project_id,
+            pipeline_id,
+            pipeline_version_id,
+            status,
+            trace_id,
+            metadata,
+            outputs,
+            node_stats,
+            error,
+            total_token_count,
+            approximate_cost,
+            started_at,
+            finished_at,
+            result_expires_at,
+            replay_of,
+            trace_warning
+        FROM runs
+        WHERE id = $1 AND project_id = $2",
+    )
+    .bind(run_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(run)
+}
+
+/// Clear outputs of runs whose results have expired, returns the number of cleared runs
+pub async fn clear_expired_run_results(pool: &PgPool) -> Result<u64> {
+    let res = sqlx::query(
+        "UPDATE runs SET outputs = NULL
+        WHERE outputs IS NOT NULL AND result_expires_at < now()",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub pipeline_version_id: Uuid,
+    pub pipeline_version_name: String,
+    pub status: RunStatus,
+    pub trace_id: Uuid,
+    pub metadata: Value,
+    /// `user_id` from run metadata, if set by the caller
+    pub user_id: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// in seconds
+    pub duration: Option<f64>,
+    pub total_token_count: i64,
+    pub approximate_cost: Option<f64>,
+    pub error: Option<String>,
+    /// Beginning of serialized outputs, while the result is retained
+    pub output_preview: Option<String>,
+}
+
+#[derive(Default)]
+pub struct RunFilters {
+    pub status: Option<RunStatus>,
+    pub start_date: Option<DateTime<Utc>>,
+    pub end_date: Option<DateTime<Utc>>,
+    /// Runs whose metadata contains all of these key-value pairs
+    pub metadata: Option<Value>,
+}
+
+/// Position after the last run of the previous page, runs are ordered by (created_at, id) descending
+pub struct RunCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+const OUTPUT_PREVIEW_LENGTH: i32 = 200;
+
+pub async fn get_pipeline_runs(
+    pool: &PgPool,
+    project_id: &Uuid,
+    pipeline_id: &Uuid,
+    filters: &RunFilters,
+    cursor: Option<&RunCursor>,
+    limit: i64,
+) -> Result<Vec<RunSummary>> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "SELECT
+            runs.id,
+            runs.created_at,
+            runs.pipeline_version_id,
+            pipeline_versions.name as pipeline_version_name,
+            runs.status,
+            runs.trace_id,
+            runs.metadata,
+            runs.metadata->>'user_id' as user_id,
+            runs.started_at,
+            runs.finished_at,
+            EXTRACT(EPOCH FROM (runs.finished_at - runs.started_at))::float8 as duration,
+            runs.total_token_count,
+            runs.approximate_cost,
+            runs.error,
+            left(runs.outputs::text, ",
+    );
+    query
+        .push_bind(OUTPUT_PREVIEW_LENGTH)
+        .push(
+            ") as output_preview
+        FROM runs
+        JOIN pipeline_versions ON pipeline_versions.id = runs.pipeline_version_id
+        WHERE runs.project_id = ",
+        )
+        .push_bind(project_id)
+        .push(" AND runs.pipeline_id = ")
+        .push_bind(pipeline_id);
+
+    if let Some(status) = filters.status {
+        query.push(" AND runs.status = ").push_bind(status);
+    }
+    if let Some(start_date) = filters.start_date {
+        query.push(" AND runs.created_at >= ").push_bind(start_date);
+    }
+    if let Some(end_date) = filters.end_date {
+        query.push(" AND runs.created_at <= ").push_bind(end_date);
+    }
+    if let Some(metadata) = &filters.metadata {
+        query.push(" AND runs.metadata @> ").push_bind(metadata);
+    }
+    if let Some(cursor) = cursor {
+        query
+            .push(" AND (runs.created_at, runs.id) < (")
+            .push_bind(cursor.created_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    query
+        .push(" ORDER BY runs.created_at DESC, runs.id DESC LIMIT ")
+        .push_bind(limit);
+
+    let runs = query
+        .build_query_as::<'_, RunSummary>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(runs)
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Secret without its value, the value is never returned by the API
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretInfo {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub name: String,
+}
+
+#[derive(FromRow)]
+pub struct EncryptedSecret {
+    pub name: String,
+    /// hex encoded ciphertext with authentication tag
+    pub value: String,
+    /// hex encoded nonce
+    pub nonce: String,
+}
+
+pub async fn create_or_update_secret(
+    pool: &PgPool,
+    project_id: &Uuid,
+    name: &str,
+    value: &str,
+    nonce: &str,
+) -> Result<SecretInfo> {
+    let secret = sqlx::query_as::<_, SecretInfo>(
+        "INSERT INTO project_secrets (project_id, name, value, nonce)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (project_id, name) DO UPDATE SET value = $3, nonce = $4
+        RETURNING id, created_at, project_id, name",
+    )
+    .bind(project_id)
+    .bind(name)
+    .bind(value)
+    .bind(nonce)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(secret)
+}
+
+pub async fn get_secrets(pool: &PgPool, project_id: &Uuid) -> Result<Vec<SecretInfo>> {
+    let secrets = sqlx::query_as::<_, SecretInfo>(
+        "SELECT id, created_at, project_id, name
+        FROM project_secrets
+        WHERE project_id = $1
+        ORDER BY name",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(secrets)
+}
+
+pub async fn get_encrypted_secrets(
+    pool: &PgPool,
+    project_id: &Uuid,
+) -> Result<Vec<EncryptedSecret>> {
+    let secrets = sqlx::query_as::<_, EncryptedSecret>(
+        "SELECT name, value, nonce FROM project_secrets WHERE project_id = $1",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(secrets)
+}
+
+pub async fn delete_secret(pool: &PgPool, project_id: &Uuid, secret_id: &Uuid) -> Result<()> {
+    sqlx::query("DELETE FROM project_secrets WHERE id = $1 AND project_id = $2")
+        .bind(secret_id)
+        .bind(project_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    datasets::datapoints::Datapoint,
+    semantic_search::index::{IndexCollection, IndexConfig},
+};
+
+#[derive(FromRow)]
+struct IndexCollectionRow {
+    collection_name: String,
+    #[sqlx(json)]
+    config: IndexConfig,
+}
+
+impl From<IndexCollectionRow> for IndexCollection {
+    fn from(row: IndexCollectionRow) -> Self {
+        Self {
+            collection_name: row.collection_name,
+            config: row.config,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReindexJob {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    /// running, failed, succeeded or superseded
+    pub status: String,
+    #[sqlx(json)]
+    pub config: IndexConfig,
+    /// Shadow collection the index is rebuilt in
+    pub collection_name: String,
+    pub total_datapoints: i64,
+    pub reindexed_datapoints: i64,
+    #[serde(skip)]
+    pub cursor_dataset_id: Option<Uuid>,
+    #[serde(skip)]
+    pub cursor_datapoint_id: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+const JOB_COLUMNS: &str = "id, created_at, updated_at, project_id, status, config, collection_name,
+    total_datapoints, reindexed_datapoints, cursor_dataset_id, cursor_datapoint_id, error";
+
+/// Live collection of the index
+pub async fn get_live_collection(pool: &PgPool, index_name: &str) -> Result<IndexCollection> {
+    let collection = sqlx::query_as::<_, IndexCollectionRow>(
+        "SELECT collection_name, config FROM semantic_indexes WHERE name = $1",
+    )
+    .bind(index_name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(collection
+        .map(IndexCollection::from)
+        .unwrap_or_else(|| IndexCollection::default_of(index_name)))
+}
+
+/// Collections writes to the index go to, the live one and the shadow collection of an
+/// unfinished reindex job
+pub async fn get_write_collections(
+    pool: &PgPool,
+    index_name: &str,
+) -> Result<Vec<IndexCollection>> {
+    let mut collections = vec![get_live_collection(pool, index_name).await?];
+    let shadow = sqlx::query_as::<_, IndexCollectionRow>(
+        "SELECT collection_name, config FROM semantic_reindex_jobs
+        WHERE project_id::text = $1 AND status IN ('running', 'failed')",
+    )
+    .bind(index_name)
+    .fetch_optional(pool)
+    .await?;
+    collections.extend(shadow.map(IndexCollection::from));
+
+    Ok(collections)
+}
+
+/// Collections of the index and its reindex jobs, to delete with the index
+pub async fn get_all_collections(pool: &PgPool, index_name: &str) -> Result<Vec<String>> {
+    let mut collections = vec![get_live_collection(pool, index_name).await?.collection_name];
+    let shadows = sqlx::query_scalar::<_, String>(
+        "SELECT collection_name FROM semantic_reindex_jobs
+        WHERE project_id::text = $1 AND status IN ('running', 'failed')",
+    )
+    .bind(index_name)
+    .fetch_all(pool)
+    .await?;
+    collections.extend(shadows);
+
+    Ok(collections)
+}
+
+pub async fn delete_index(pool: &PgPool, index_name: &str) -> Result<()> {
+    sqlx::query("DELETE FROM semantic_indexes WHERE name = $1")
+        .bind(index_name)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub struct StartedReindexJob {
+    pub job: ReindexJob,
+    /// Failed job of the project, whose shadow collection is no longer used
+    pub superseded: Option<ReindexJob>,
+}
+
+/// Start a reindex job of the project, superseding its failed job. None if a job of the project
+/// is running.
+pub async fn start_reindex_job(
+    pool: &PgPool,
+    project_id: &Uuid,
+    job_id: &Uuid,
+    config: &IndexConfig,
+    collection_name: &str,
+) -> Result<Option<StartedReindexJob>> {
+    let mut tx = pool.begin().await?;
+    let unfinished = sqlx::query_as::<_, ReindexJob>(&format!(
+        "SELECT {JOB_COLUMNS} FROM semantic_reindex_jobs
+        WHERE project_id = $1 AND status IN ('running', 'failed')
+        FOR UPDATE"
+    ))
+    .bind(project_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if unfinished
+        .as_ref()
+        .is_some_and(|job| job.status == "running")
+    {
+        return Ok(None);
+    }
+    if let Some(superseded) = &unfinished {
+        sqlx::query(
+            "UPDATE semantic_reindex_jobs SET status = 'superseded', updated_at = now()
+            WHERE id = $1",
+        )
+        .bind(superseded.id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let total_datapoints = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM dataset_datapoints
+        JOIN datasets ON datasets.id = dataset_datapoints.dataset_id
+        WHERE datasets.project_id = $1 AND datasets.indexed_on IS NOT NULL",
+    )
+    .bind(project_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    let job = sqlx::query_as::<_, ReindexJob>(&format!(
+        "INSERT INTO semantic_reindex_jobs (id, project_id, config, collection_name, total_datapoints)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {JOB_COLUMNS}"
+    ))
+    .bind(job_id)
+    .bind(project_id)
+    .bind(serde_json::to_value(config)?)
+    .bind(collection_name)
+    .bind(total_datapoints)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Some(StartedReindexJob {
+        job,
+        superseded: unfinished,
+    }))
+}
+
+pub async fn get_reindex_job(
+    pool: &PgPool,
+    project_id: &Uuid,
+    job_id: &Uuid,
+) -> Result<Option<ReindexJob>> {
+    let job = sqlx::query_as::<_, ReindexJob>(&format!(
+        "SELECT {JOB_COLUMNS} FROM semantic_reindex_jobs WHERE id = $1 AND project_id = $2"
+    ))
+    .bind(job_id)
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(job)
+}
+
+pub async fn get_latest_reindex_job(
+    pool: &PgPool,
+    project_id: &Uuid,
+) -> Result<Option<ReindexJob>> {
+    let job = sqlx::query_as::<_, ReindexJob>(&format!(
+        "SELECT {JOB_COLUMNS} FROM semantic_reindex_jobs
+        WHERE project_id = $1
+        ORDER BY created_at DESC
+        LIMIT 1"
+    ))
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(job)
+}
+
+/// Claim the job to resume it, if it failed, or it's running but wasn't updated for
+/// `stale_seconds`, e.g. its instance stopped
+pub async fn claim_reindex_job(
+    pool: &PgPool,
+    project_id: &Uuid,
+    job_id: &Uuid,
+    stale_seconds: f64,
+) -> Result<Option<ReindexJob>> {
+    let job = sqlx::query_as::<_, ReindexJob>(&format!(
+        "UPDATE semantic_reindex_jobs
+        SET status = 'running', error = NULL, updated_at = now()
+        WHERE id = $1 AND project_id = $2 AND (
+            status = 'failed'
+            OR (status = 'running' AND updated_at < now() - make_interval(secs => $3))
+        )
+        RETURNING {JOB_COLUMNS}"
+    ))
+    .bind(job_id)
+    .bind(project_id)
+    .bind(stale_seconds)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(job)
+}
+
+/// Datasets of the project which are indexed, from the dataset id on, with their index column
+pub async fn get_indexed_datasets(
+    pool: &PgPool,
+    project_id: &Uuid,
+    from_dataset_id: Option<Uuid>,
+) -> Result<Vec<(Uuid, String)>> {
+    let datasets = sqlx::query_as::<_, (Uuid, String)>(
+        "SELECT id, indexed_on FROM datasets
+        WHERE project_id = $1 AND indexed_on IS NOT NULL AND ($2::uuid IS NULL OR id >= $2)
+        ORDER BY id",
+    )
+    .bind(project_id)
+    .bind(from_dataset_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(datasets)
+}
+
+/// Next batch of the dataset's datapoints in order of ids, after the datapoint id
+pub async fn get_datapoints_after(
+    pool: &PgPool,
+    dataset_id: &Uuid,
+    after_id: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<Datapoint>> {
+    let datapoints = sqlx::query_as::<_, Datapoint>(
+        "SELECT id, dataset_id, data, target FROM dataset_datapoints
+        WHERE dataset_id = $1 AND ($2::uuid IS NULL OR id > $2)
+        ORDER BY id
+        LIMIT $3",
+    )
+    .bind(dataset_id)
+    .bind(after_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(datapoints)
+}
+
+/// Record a reindexed batch, unless the job is no longer running. Returns whether it's running.
+pub async fn advance_reindex_job(
+    pool: &PgPool,
+    job_id: &Uuid,
+    dataset_id: &Uuid,
+    datapoint_id: &Uuid,
+    reindexed: i64,
+) -> Result<bool> {
+    let res = sqlx::query(
+        "UPDATE semantic_reindex_jobs SET
+            cursor_dataset_id = $2,
+            cursor_datapoint_id = $3,
+            reindexed_datapoints = reindexed_datapoints + $4,
+            updated_at = now()
+        WHERE id = $1 AND status = 'running'",
+    )
+    .bind(job_id)
+    .bind(dataset_id)
+    .bind(datapoint_id)
+    .bind(reindexed)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn fail_reindex_job(pool: &PgPool, job_id: &Uuid, error: &str) -> Result<()> {
+    sqlx::query(
+        "UPDATE semantic_reindex_jobs SET status = 'failed', error = $2, updated_at = now()
+        WHERE id = $1 AND status = 'running'",
+    )
+    .bind(job_id)
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Make the job's collection the live collection of the index, and finish the job. Returns the
+/// replaced collection, or None if the job is no longer running.
+pub async fn swap_live_collection(
+    pool: &PgPool,
+    job: &ReindexJob,
+    index_name: &str,
+) -> Result<Option<String>> {
+    let mut tx = pool.begin().await?;
+    let res = sqlx::query(
+        "UPDATE semantic_reindex_jobs SET status = 'succeeded', updated_at = now()
+        WHERE id = $1 AND status = 'running'",
+    )
+    .bind(job.id)
+    .execute(&mut *tx)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let replaced = sqlx::query_scalar::<_, String>(
+        "SELECT collection_name FROM semantic_indexes WHERE name = $1 FOR UPDATE",
+    )
+    .bind(index_name)
+    .fetch_optional(&mut *tx)
+    .await?
+    .unwrap_or_else(|| index_name.to_string());
+    sqlx::query(
+        "INSERT INTO semantic_indexes (name, collection_name, config)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO UPDATE SET
+            collection_name = EXCLUDED.collection_name,
+            config = EXCLUDED.config,
+            updated_at = now()",
+    )
+    .bind(index_name)
+    .bind(&job.collection_name)
+    .bind(serde_json::to_value(job.config)?)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Some(replaced))
+}
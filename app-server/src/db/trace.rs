@@ -5,6 +5,7 @@ use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sqlx::{postgres::PgHasArrayType, FromRow, PgPool, Postgres, QueryBuilder};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
@@ -13,10 +14,19 @@ use crate::{
         providers::anthropic::OtelChatMessageContentPart, ChatMessage, ChatMessageContent,
     },
     opentelemetry::opentelemetry_proto_trace_v1::Span as OtelSpan,
-    pipeline::{nodes::Message, trace::MetaLog},
+    pipeline::{
+        nodes::{semantic_search_utils::RetrievedDocument, Message, NodeInput},
+        trace::MetaLog,
+    },
+    secrets::scrub_secrets,
     traces::attributes::{
-        GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_TOKENS, GEN_AI_REQUEST_MODEL, GEN_AI_RESPONSE_MODEL,
-        GEN_AI_SYSTEM,
+        GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_TOKENS, GEN_AI_REQUEST_MAX_TOKENS, GEN_AI_REQUEST_MODEL,
+        GEN_AI_REQUEST_TEMPERATURE, GEN_AI_RESPONSE_MODEL, GEN_AI_SYSTEM,
+        LMNR_LLM_CONTEXT_EVICTED_MESSAGES, LMNR_LLM_CONTEXT_EVICTED_TOKENS,
+        LMNR_LLM_CONTEXT_STRATEGY, LMNR_LLM_CREDENTIAL, LMNR_LLM_MODEL_CONFIG,
+        LMNR_LLM_PROMPT_LABEL, LMNR_LLM_PROMPT_NAME, LMNR_LLM_PROMPT_VERSION,
+        LMNR_NODE_POST_PROCESSING, LMNR_NODE_TYPE, LMNR_PIPELINE_ID, LMNR_RETRIEVAL_DOCUMENTS,
+        LMNR_ROUTE_NAME, LMNR_ROUTE_NODE_ID,
     },
 };
 
@@ -26,6 +36,8 @@ use super::{
 };
 
 const DEFAULT_VERSION: &str = "0.1.0";
+/// Types of the nodes whose messages are the spans of a run's trace, see `Span::from_messages`
+pub const TRACED_NODE_TYPES: [&str; 4] = ["LLM", "SemanticSearch", "Switch", "SemanticSwitch"];
 
 // TODO: add_X_to_query functions don't need to return the query builder
 // they can just modify the query builder in place, and return nothing.
@@ -162,7 +174,7 @@ pub struct DBMessage {
     pub meta_log: Value,
 }
 
-#[derive(sqlx::Type, Deserialize, Serialize, PartialEq, Clone, Debug, Default)]
+#[derive(sqlx::Type, Deserialize, Serialize, PartialEq, Clone, Debug, Default, ToSchema)]
 #[sqlx(type_name = "span_type")]
 pub enum SpanType {
     #[default]
@@ -193,7 +205,7 @@ fn default_true() -> bool {
     true
 }
 
-#[derive(Deserialize, Serialize, sqlx::FromRow, Clone, Debug)]
+#[derive(Deserialize, Serialize, sqlx::FromRow, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Trace {
     pub id: Uuid,
@@ -206,8 +218,8 @@ pub struct Trace {
     // Laminar customers' release version
     release: Option<String>,
     // User id of Laminar customers' user
-    user_id: Option<String>,
-    session_id: Option<String>,
+    pub user_id: Option<String>,
+    pub session_id: Option<String>,
     metadata: Option<Value>,
     #[serde(default)]
     total_token_count: i64,
@@ -239,7 +251,7 @@ pub struct Trace {
 //     }
 // }
 
-#[derive(Serialize, sqlx::FromRow)]
+#[derive(Serialize, sqlx::FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TraceWithEvents {
     id: Uuid,
@@ -323,7 +335,7 @@ impl TraceAttributes {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default, FromRow)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Span {
     pub version: String,
@@ -404,6 +416,41 @@ impl SpanAttributes {
             _ => None,
         }
     }
+
+    pub fn pipeline_id(&self) -> Option<Uuid> {
+        match self.attributes.get(LMNR_PIPELINE_ID) {
+            Some(Value::String(s)) => Uuid::parse_str(s).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn route_name(&self) -> Option<String> {
+        match self.attributes.get(LMNR_ROUTE_NAME) {
+            Some(Value::String(s)) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn route_node_id(&self) -> Option<Uuid> {
+        match self.attributes.get(LMNR_ROUTE_NODE_ID) {
+            Some(Value::String(s)) => Uuid::parse_str(s).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn node_type(&self) -> Option<String> {
+        match self.attributes.get(LMNR_NODE_TYPE) {
+            Some(Value::String(s)) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn retrieved_documents(&self) -> Vec<RetrievedDocument> {
+        self.attributes
+            .get(LMNR_RETRIEVAL_DOCUMENTS)
+            .and_then(|documents| serde_json::from_value(documents.clone()).ok())
+            .unwrap_or_default()
+    }
 }
 
 impl Span {
@@ -530,6 +577,20 @@ impl Span {
         }
     }
 
+    /// Replace values of known secrets in the captured span data with references to them
+    pub fn scrub_secrets(&mut self, secrets: &HashMap<String, String>) {
+        if secrets.is_empty() {
+            return;
+        }
+        scrub_secrets(&mut self.attributes, secrets);
+        if let Some(input) = self.input.as_mut() {
+            scrub_secrets(input, secrets);
+        }
+        if let Some(output) = self.output.as_mut() {
+            scrub_secrets(output, secrets);
+        }
+    }
+
     pub fn from_messages(
         messages: &HashMap<Uuid, Message>,
         trace_id: Uuid,
@@ -538,18 +599,20 @@ impl Span {
         messages
             .iter()
             .filter_map(|(msg_id, message)| {
+                // empty inputs, and inputs seeded from the recorded I/O of a replayed run,
+                // weren't produced by the run
                 let input_values = message
                     .input_message_ids
                     .iter()
-                    .map(|input_id| {
-                        let input_message = messages.get(input_id).unwrap();
-                        (
+                    .filter_map(|input_id| {
+                        let input_message = messages.get(input_id)?;
+                        Some((
                             input_message.node_name.clone(),
                             input_message.value.clone().into(),
-                        )
+                        ))
                     })
                     .collect::<HashMap<String, Value>>();
-                let span = Span {
+                let mut span = Span {
                     span_id: *msg_id,
                     start_time: message.start_time,
                     end_time: message.end_time,
@@ -566,10 +629,18 @@ impl Span {
                     },
                     events: None,
                 };
-                match message.node_type.as_str() {
-                    "LLM" | "SemanticSearch" => Some(span),
-                    _ => None,
+                span.attributes[LMNR_NODE_TYPE] = json!(message.node_type);
+                // routers output the value with the condition of the route they took
+                if let NodeInput::ConditionedValue(conditioned_value) = &message.value {
+                    span.attributes[LMNR_ROUTE_NAME] = json!(conditioned_value.route_label());
+                    span.attributes[LMNR_ROUTE_NODE_ID] = json!(message.node_id);
+                }
+                if !message.post_processing.is_empty() {
+                    span.attributes[LMNR_NODE_POST_PROCESSING] = json!(message.post_processing);
                 }
+                TRACED_NODE_TYPES
+                    .contains(&message.node_type.as_str())
+                    .then_some(span)
             })
             .collect()
     }
@@ -577,12 +648,58 @@ impl Span {
 
 fn span_attributes_from_meta_log(meta_log: Option<MetaLog>) -> Value {
     match meta_log {
-        Some(MetaLog::LLM(llm_log)) => serde_json::json!({
-            GEN_AI_INPUT_TOKENS: llm_log.input_token_count,
-            GEN_AI_OUTPUT_TOKENS: llm_log.output_token_count,
-            GEN_AI_RESPONSE_MODEL: llm_log.model,
-            GEN_AI_SYSTEM: llm_log.provider,
-        }),
+        Some(MetaLog::LLM(llm_log)) => {
+            let mut attributes = serde_json::json!({
+                GEN_AI_INPUT_TOKENS: llm_log.input_token_count,
+                GEN_AI_OUTPUT_TOKENS: llm_log.output_token_count,
+                GEN_AI_RESPONSE_MODEL: llm_log.model,
+                GEN_AI_SYSTEM: llm_log.provider,
+            });
+            if let Some(credential) = llm_log.credential {
+                attributes[LMNR_LLM_CREDENTIAL] = json!(credential);
+            }
+            if let Some(context_window) = llm_log.context_window {
+                attributes[LMNR_LLM_CONTEXT_STRATEGY] = json!(context_window.strategy);
+                attributes[LMNR_LLM_CONTEXT_EVICTED_TOKENS] = json!(context_window.evicted_tokens);
+                attributes[LMNR_LLM_CONTEXT_EVICTED_MESSAGES] =
+                    json!(context_window.evicted_messages);
+            }
+            if let Some(prompt) = llm_log.prompt_version {
+                attributes[LMNR_LLM_PROMPT_NAME] = json!(prompt.name);
+                attributes[LMNR_LLM_PROMPT_VERSION] = json!(prompt.version);
+                if let Some(label) = prompt.label {
+                    attributes[LMNR_LLM_PROMPT_LABEL] = json!(label);
+                }
+            }
+            if let Some(config) = llm_log.model_config {
+                let attributes = attributes.as_object_mut().unwrap();
+                if let Some(model) = &config.model {
+                    attributes.insert(GEN_AI_REQUEST_MODEL.to_string(), json!(model));
+                }
+                for (key, param) in [
+                    (GEN_AI_REQUEST_TEMPERATURE, "temperature"),
+                    (GEN_AI_REQUEST_MAX_TOKENS, "max_tokens"),
+                ] {
+                    if let Some(value) = config.params.get(param) {
+                        attributes.insert(key.to_string(), value.clone());
+                    }
+                }
+                attributes.insert(
+                    LMNR_LLM_MODEL_CONFIG.to_string(),
+                    serde_json::to_value(&config).unwrap_or_default(),
+                );
+            }
+            attributes
+        }
+        Some(MetaLog::Embedding(embedding_log)) => {
+            let mut attributes = serde_json::json!({
+                GEN_AI_INPUT_TOKENS: embedding_log.input_token_count,
+            });
+            if !embedding_log.documents.is_empty() {
+                attributes[LMNR_RETRIEVAL_DOCUMENTS] = json!(embedding_log.documents);
+            }
+            attributes
+        }
         _ => serde_json::json!({}),
     }
 }
@@ -941,7 +1058,7 @@ pub async fn count_all_traces_in_project(pool: &PgPool, project_id: Uuid) -> Res
     Ok(count.total_count)
 }
 
-pub async fn get_single_trace(pool: &PgPool, id: Uuid) -> Result<Trace> {
+pub async fn get_single_trace(pool: &PgPool, id: Uuid) -> Result<Option<Trace>> {
     let trace = sqlx::query_as::<_, Trace>(
         "SELECT
             id,
@@ -961,7 +1078,7 @@ pub async fn get_single_trace(pool: &PgPool, id: Uuid) -> Result<Trace> {
         AND start_time IS NOT NULL AND end_time IS NOT NULL",
     )
     .bind(id)
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await?;
 
     Ok(trace)
@@ -1012,7 +1129,7 @@ pub async fn get_span_previews(pool: &PgPool, trace_id: Uuid) -> Result<Vec<Span
     Ok(spans)
 }
 
-pub async fn get_span(pool: &PgPool, id: Uuid) -> Result<Span> {
+pub async fn get_span(pool: &PgPool, id: Uuid) -> Result<Option<Span>> {
     let span = sqlx::query_as::<_, Span>(
         "SELECT
             span_id,
@@ -1031,8 +1148,35 @@ pub async fn get_span(pool: &PgPool, id: Uuid) -> Result<Span> {
         WHERE span_id = $1",
     )
     .bind(id)
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await?;
 
     Ok(span)
 }
+
+/// Spans of the trace with their input, output and attributes, in order of their start
+pub async fn get_trace_spans(pool: &PgPool, trace_id: Uuid) -> Result<Vec<Span>> {
+    let spans = sqlx::query_as::<_, Span>(
+        "SELECT
+            span_id,
+            start_time,
+            end_time,
+            version,
+            trace_id,
+            parent_span_id,
+            name,
+            attributes,
+            input,
+            output,
+            span_type,
+            '[]'::jsonb as events
+        FROM spans
+        WHERE trace_id = $1
+        ORDER BY start_time ASC",
+    )
+    .bind(trace_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(spans)
+}
@@ -29,6 +29,20 @@ pub struct User {
     pub project_ids: Option<Vec<Uuid>>,
 }
 
+impl User {
+    pub fn is_member_of_workspace(&self, workspace_id: &Uuid) -> bool {
+        self.workspace_ids
+            .as_ref()
+            .is_some_and(|workspace_ids| workspace_ids.contains(workspace_id))
+    }
+
+    pub fn is_member_of_project(&self, project_id: &Uuid) -> bool {
+        self.project_ids
+            .as_ref()
+            .is_some_and(|project_ids| project_ids.contains(project_id))
+    }
+}
+
 pub async fn get_by_email(pool: &PgPool, email: &str) -> Result<Option<User>> {
     sqlx::query_as::<_, User>(
         "SELECT 
@@ -65,3 +65,8 @@ pub fn span_id_to_uuid(span_id: &[u8]) -> Uuid {
     padded_vec.extend_from_slice(&span_id.to_vec());
     Uuid::from_slice(&padded_vec).unwrap()
 }
+
+/// Inverse of `span_id_to_uuid`, the OTel span id of a span
+pub fn uuid_to_span_id(uuid: &Uuid) -> Vec<u8> {
+    uuid.as_bytes()[8..].to_vec()
+}
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Webhook without its secret, the secret is only returned once when the webhook is created
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookInfo {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub pipeline_id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub include_output: bool,
+    pub consecutive_failures: i32,
+    pub last_error: Option<String>,
+    pub last_delivered_at: Option<DateTime<Utc>>,
+    pub last_failed_at: Option<DateTime<Utc>>,
+    /// Set when the webhook was disabled after too many failed deliveries
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    /// hex encoded nonce
+    pub secret_nonce: String,
+    /// hex encoded ciphertext with authentication tag
+    pub secret_value: String,
+    pub include_output: bool,
+}
+
+pub struct NewWebhook<'a> {
+    pub id: Uuid,
+    pub pipeline_id: Uuid,
+    pub url: &'a str,
+    pub secret_nonce: &'a str,
+    pub secret_value: &'a str,
+    pub event_types: &'a [String],
+    pub include_output: bool,
+}
+
+const WEBHOOK_INFO_COLUMNS: &str = "id,
+    created_at,
+    pipeline_id,
+    url,
+    event_types,
+    include_output,
+    consecutive_failures,
+    last_error,
+    last_delivered_at,
+    last_failed_at,
+    disabled_at";
+
+pub async fn create_webhook(pool: &PgPool, webhook: &NewWebhook<'_>) -> Result<WebhookInfo> {
+    let webhook = sqlx::query_as::<_, WebhookInfo>(&format!(
+        "INSERT INTO pipeline_webhooks (
+            id,
+            pipeline_id,
+            url,
+            secret_nonce,
+            secret_value,
+            event_types,
+            include_output
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING {WEBHOOK_INFO_COLUMNS}"
+    ))
+    .bind(webhook.id)
+    .bind(webhook.pipeline_id)
+    .bind(webhook.url)
+    .bind(webhook.secret_nonce)
+    .bind(webhook.secret_value)
+    .bind(webhook.event_types)
+    .bind(webhook.include_output)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+pub async fn get_webhooks(
+    pool: &PgPool,
+    project_id: &Uuid,
+    pipeline_id: &Uuid,
+) -> Result<Vec<WebhookInfo>> {
+    let webhooks = sqlx::query_as::<_, WebhookInfo>(&format!(
+        "SELECT {WEBHOOK_INFO_COLUMNS}
+        FROM pipeline_webhooks
+        WHERE pipeline_id = $2
+            AND pipeline_id IN (SELECT id FROM pipelines WHERE project_id = $1)
+        ORDER BY created_at"
+    ))
+    .bind(project_id)
+    .bind(pipeline_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(webhooks)
+}
+
+/// Enabled webhooks of the pipeline which are subscribed to the event type
+pub async fn get_webhook_endpoints(
+    pool: &PgPool,
+    pipeline_id: &Uuid,
+    event_type: &str,
+) -> Result<Vec<WebhookEndpoint>> {
+    let endpoints = sqlx::query_as::<_, WebhookEndpoint>(
+        "SELECT id, url, secret_nonce, secret_value, include_output
+        FROM pipeline_webhooks
+        WHERE pipeline_id = $1 AND $2 = ANY(event_types) AND disabled_at IS NULL",
+    )
+    .bind(pipeline_id)
+    .bind(event_type)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(endpoints)
+}
+
+pub async fn delete_webhook(
+    pool: &PgPool,
+    project_id: &Uuid,
+    pipeline_id: &Uuid,
+    webhook_id: &Uuid,
+) -> Result<bool> {
+    let res = sqlx::query(
+        "DELETE FROM pipeline_webhooks
+        WHERE id = $3 AND pipeline_id = $2
+            AND pipeline_id IN (SELECT id FROM pipelines WHERE project_id = $1)",
+    )
+    .bind(project_id)
+    .bind(pipeline_id)
+    .bind(webhook_id)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Enable the webhook again after it was disabled, and reset its failure counter
+pub async fn enable_webhook(
+    pool: &PgPool,
+    project_id: &Uuid,
+    pipeline_id: &Uuid,
+    webhook_id: &Uuid,
+) -> Result<Option<WebhookInfo>> {
+    let webhook = sqlx::query_as::<_, WebhookInfo>(&format!(
+        "UPDATE pipeline_webhooks SET consecutive_failures = 0, disabled_at = NULL
+        WHERE id = $3 AND pipeline_id = $2
+            AND pipeline_id IN (SELECT id FROM pipelines WHERE project_id = $1)
+        RETURNING {WEBHOOK_INFO_COLUMNS}"
+    ))
+    .bind(project_id)
+    .bind(pipeline_id)
+    .bind(webhook_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+pub async fn record_webhook_delivery(pool: &PgPool, webhook_id: &Uuid) -> Result<()> {
+    sqlx::query(
+        "UPDATE pipeline_webhooks SET consecutive_failures = 0, last_delivered_at = now()
+        WHERE id = $1",
+    )
+    .bind(webhook_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Count the failed delivery, and disable the webhook once it reaches `max_failures` in a row
+///
+/// Returns true if the webhook got disabled.
+pub async fn record_webhook_failure(
+    pool: &PgPool,
+    webhook_id: &Uuid,
+    error: &str,
+    max_failures: i32,
+) -> Result<bool> {
+    let disabled = sqlx::query_scalar::<_, bool>(
+        "UPDATE pipeline_webhooks SET
+            consecutive_failures = consecutive_failures + 1,
+            last_error = $2,
+            last_failed_at = now(),
+            disabled_at = CASE
+                WHEN consecutive_failures + 1 >= $3 THEN COALESCE(disabled_at, now())
+                ELSE disabled_at
+            END
+        WHERE id = $1
+        RETURNING disabled_at IS NOT NULL",
+    )
+    .bind(webhook_id)
+    .bind(error)
+    .bind(max_failures)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(disabled.unwrap_or(false))
+}
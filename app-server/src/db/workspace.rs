@@ -1,3 +1,5 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
@@ -5,6 +7,27 @@ use uuid::Uuid;
 use super::projects::Project;
 use super::stats::create_run_count_for_workspace;
 
+/// Id of a workspace, which tenants are isolated by
+///
+/// Every project belongs to one workspace, so the workspace of a request or a run is the one of
+/// its project. Runs carry it in their context, and limits of the workspace are counted by it.
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct WorkspaceId(pub Uuid);
+
+impl fmt::Display for WorkspaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Uuid> for WorkspaceId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, FromRow)]
 pub struct Workspace {
     pub id: Uuid,
@@ -99,6 +122,19 @@ pub async fn get_owned_workspaces(pool: &PgPool, user_id: &Uuid) -> anyhow::Resu
     Ok(workspaces)
 }
 
+pub async fn get_workspace_id_of_project(
+    pool: &PgPool,
+    project_id: &Uuid,
+) -> anyhow::Result<WorkspaceId> {
+    let workspace_id =
+        sqlx::query_scalar::<_, WorkspaceId>("SELECT workspace_id FROM projects WHERE id = $1")
+            .bind(project_id)
+            .fetch_one(pool)
+            .await?;
+
+    Ok(workspace_id)
+}
+
 pub async fn create_new_workspace(pool: &PgPool, workspace: &Workspace) -> anyhow::Result<()> {
     sqlx::query("INSERT INTO workspaces (id, name) VALUES ($1, $2)")
         .bind(workspace.id)
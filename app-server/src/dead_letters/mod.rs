@@ -0,0 +1,142 @@
+//! Dead letters, i.e. data which couldn't be processed and would otherwise be dropped
+//!
+//! Span batches which still fail to be exported to ClickHouse after the exporter's retries, and
+//! run jobs whose payload can't be parsed, e.g. jobs pushed before a deploy which changed
+//! `RunJob`, are kept with their error and the number of times they were retried. Admins list,
+//! inspect, retry or purge them, and alert on their growth with the stats of each kind.
+//!
+//! A retried span batch goes back through the exporter, and a new dead letter is captured if it
+//! fails again. A retried run job is parsed again and pushed to the run queue.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{
+    ch::{exporter::SpanExporter, spans::CHSpan},
+    db::{self, dead_letters::DeadLetter, DB},
+    runs::queue::{RunExecution, RunJob},
+};
+
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[sqlx(type_name = "dead_letter_kind", rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub enum DeadLetterKind {
+    /// Batch of spans which failed to be exported to ClickHouse
+    SpanExport,
+    /// Job from the run queue which couldn't be parsed
+    RunJob,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RetryError {
+    /// The dead letter still can't be processed, the error is recorded on it
+    #[error("{0}")]
+    Failed(String),
+    #[error("Run jobs can't be retried, since runs aren't executed with the run queue")]
+    NoRunQueue,
+    #[error("{0}")]
+    UnhandledError(#[from] anyhow::Error),
+}
+
+/// Capture spans which failed to be exported, logging if they can't be captured either
+pub async fn capture_span_batch(db: &DB, spans: &[CHSpan], error: &str, retry_count: i32) {
+    let captured = match serde_json::to_value(spans) {
+        Ok(payload) => {
+            db::dead_letters::insert_dead_letter(
+                &db.pool,
+                DeadLetterKind::SpanExport,
+                &payload,
+                error,
+                retry_count,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = captured {
+        log::error!(
+            "Failed to capture {} spans as a dead letter, dropping them: {}",
+            spans.len(),
+            e
+        );
+    }
+}
+
+/// Capture the payload of a run job which couldn't be parsed
+pub async fn capture_run_job(db: &DB, payload: &[u8], error: &str) -> Result<()> {
+    db::dead_letters::insert_dead_letter(
+        &db.pool,
+        DeadLetterKind::RunJob,
+        &job_payload(payload),
+        error,
+        0,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Payload as JSON, or as a string if it's not valid JSON, so that it can still be inspected
+fn job_payload(payload: &[u8]) -> Value {
+    serde_json::from_slice::<Value>(payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()))
+}
+
+/// Process the dead letter again, deleting it once it's handed back to the exporter or queue
+pub async fn retry(
+    db: &DB,
+    dead_letter: DeadLetter,
+    exporter: &SpanExporter,
+    run_execution: &RunExecution,
+) -> Result<(), RetryError> {
+    match dead_letter.kind {
+        DeadLetterKind::SpanExport => {
+            let spans = parse_payload::<Vec<CHSpan>>(db, &dead_letter).await?;
+            db::dead_letters::delete_dead_letter(&db.pool, &dead_letter.id).await?;
+            exporter.retry(spans, dead_letter.retry_count + 1).await?;
+        }
+        DeadLetterKind::RunJob => {
+            let queue = run_execution.queue().ok_or(RetryError::NoRunQueue)?;
+            let job = parse_payload::<RunJob>(db, &dead_letter).await?;
+            if let Err(e) = queue.push_job(&job).await {
+                let error = format!("Failed to push run job to the run queue: {}", e);
+                db::dead_letters::record_retry_failure(&db.pool, &dead_letter.id, &error).await?;
+                return Err(RetryError::Failed(error));
+            }
+            db::dead_letters::delete_dead_letter(&db.pool, &dead_letter.id).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn parse_payload<T: serde::de::DeserializeOwned>(
+    db: &DB,
+    dead_letter: &DeadLetter,
+) -> Result<T, RetryError> {
+    match serde_json::from_value::<T>(dead_letter.payload.clone()) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            let error = format!("Failed to parse payload: {}", e);
+            db::dead_letters::record_retry_failure(&db.pool, &dead_letter.id, &error).await?;
+            Err(RetryError::Failed(error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_payload() {
+        assert_eq!(
+            job_payload(br#"{"runId": "1"}"#),
+            serde_json::json!({"runId": "1"})
+        );
+        assert_eq!(
+            job_payload(b"{\"runId\": \xff"),
+            Value::String("{\"runId\": \u{fffd}".to_string())
+        );
+    }
+}
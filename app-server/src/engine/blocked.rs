@@ -0,0 +1,388 @@
+//! Breakdowns of the inputs of nodes a run stopped at
+//!
+//! When a node fails, or the run finishes while a scheduled node still waits for some of its
+//! inputs, the run reports the node with each of its input handles: the predecessor feeding it,
+//! the predecessor's final state, and how long the node waited for it.
+//!
+//! When a node finishes the run, the nodes which were still scheduled or running are reported as
+//! cancelled. Inputs of the running ones aren't broken down, the engine released them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, DashSet};
+use log::{debug, warn};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{clock::Clock, pipeline::nodes::Message};
+
+use super::task::{InputHandle, State, Task};
+
+/// Final state of the predecessor feeding an input handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum SourceState {
+    Success,
+    /// The handle was set without a message, e.g. a cyclic input before its loop ran
+    Empty,
+    Terminated,
+    Error,
+    NeverRan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum BlockReason {
+    /// The node ran and failed
+    Failed,
+    /// The node waited for an input whose predecessor terminated its branch, e.g. after a
+    /// condition which didn't match
+    Skipped,
+    /// The node waited for an input whose predecessor never produced it
+    Stuck,
+    /// Another node finished the run while the node waited or ran, see `RunOutput::Finish`
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockedInput {
+    /// `node_id.handle_name` of the input handle
+    pub handle: String,
+    pub handle_name: String,
+    /// Unset if no predecessor is connected to the handle
+    pub source_node_id: Option<Uuid>,
+    pub source_node_name: Option<String>,
+    pub source_state: SourceState,
+    /// Time from when the node started waiting until the input arrived, or until the run stopped
+    /// if it never did
+    pub waited_ms: i64,
+    pub arrived: bool,
+}
+
+/// Node a run stopped at, with the breakdown of its inputs
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockedNode {
+    pub node_id: Uuid,
+    pub node_name: String,
+    pub node_type: String,
+    pub reason: BlockReason,
+    pub inputs: Vec<BlockedInput>,
+}
+
+impl fmt::Display for BlockedNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Node {} ({}) {:?}:",
+            self.node_name, self.node_id, self.reason
+        )?;
+        for input in self.inputs.iter() {
+            let source = input
+                .source_node_name
+                .as_deref()
+                .unwrap_or("no predecessor");
+            write!(
+                f,
+                " input {} from {} is {:?}, {} after {}ms;",
+                input.handle,
+                source,
+                input.source_state,
+                if input.arrived { "arrived" } else { "missing" },
+                input.waited_ms
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the engine knows of the tasks of a run to break their inputs down
+#[derive(Debug)]
+pub(crate) struct BlockTracker {
+    clock: Arc<dyn Clock>,
+    /// Latest outcome of each task which ran
+    outcomes: DashMap<Uuid, SourceState>,
+    /// When each task last started waiting for its inputs and its turn to run
+    wait_starts: DashMap<Uuid, DateTime<Utc>>,
+    failed: DashMap<Uuid, BlockedNode>,
+}
+
+impl BlockTracker {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            outcomes: DashMap::new(),
+            wait_starts: DashMap::new(),
+            failed: DashMap::new(),
+        }
+    }
+
+    pub fn waiting(&self, task_id: Uuid) {
+        self.wait_starts.insert(task_id, self.clock.now());
+    }
+
+    pub fn finished(&self, task_id: Uuid, state: &State) {
+        let outcome = match state {
+            State::Success(_) => SourceState::Success,
+            State::Empty(_) => SourceState::Empty,
+            State::Termination => SourceState::Terminated,
+            State::Finish(_) => SourceState::Success,
+        };
+        self.outcomes.insert(task_id, outcome);
+    }
+
+    /// Record the failure of the task, which ran with the inputs
+    pub fn failed(
+        &self,
+        tasks: &DashMap<Uuid, Arc<Task>>,
+        task: &Task,
+        inputs: &HashMap<InputHandle, Arc<Message>>,
+    ) {
+        self.outcomes.insert(task.id, SourceState::Error);
+        let now = self.clock.now();
+        let blocked = self.breakdown(tasks, task, BlockReason::Failed, now, |handle| {
+            inputs
+                .get(handle)
+                .map(|message| State::Success(message.clone()))
+        });
+        warn!("{}", blocked);
+        self.failed.insert(task.id, blocked);
+    }
+
+    /// Tasks whose latest run terminated their branch
+    pub fn terminated(&self) -> HashSet<Uuid> {
+        self.outcomes
+            .iter()
+            .filter(|entry| *entry.value() == SourceState::Terminated)
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    /// Nodes which failed, and scheduled nodes which are still waiting for their inputs. If the
+    /// run was `finished` by a node, the scheduled and running nodes are cancelled instead.
+    pub fn blocked_nodes(
+        &self,
+        tasks: &DashMap<Uuid, Arc<Task>>,
+        idle_tasks: &DashSet<Uuid>,
+        active_tasks: &DashSet<Uuid>,
+        finished: bool,
+    ) -> Vec<BlockedNode> {
+        let now = self.clock.now();
+        let mut blocked = self
+            .failed
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        for task_id in idle_tasks.iter() {
+            if active_tasks.contains(task_id.key()) || self.failed.contains_key(task_id.key()) {
+                continue;
+            }
+            let Some(task) = tasks.get(task_id.key()).map(|task| task.clone()) else {
+                continue;
+            };
+            let mut node = self.breakdown(tasks, &task, BlockReason::Stuck, now, |handle| {
+                task.input_states[handle]
+                    .get_completed_state()
+                    .map(|(state, _)| state)
+            });
+            let skipped = node
+                .inputs
+                .iter()
+                .any(|input| !input.arrived && input.source_state == SourceState::Terminated);
+            if skipped {
+                node.reason = BlockReason::Skipped;
+                debug!("{}", node);
+            } else if finished {
+                node.reason = BlockReason::Cancelled;
+                debug!("{}", node);
+            } else if node.inputs.iter().all(|input| input.arrived) {
+                // waits for its turn to run, not for an input
+                continue;
+            } else {
+                warn!("{}", node);
+            }
+            blocked.push(node);
+        }
+        if finished {
+            for task_id in active_tasks.iter() {
+                if self.failed.contains_key(task_id.key()) {
+                    continue;
+                }
+                let Some(task) = tasks.get(task_id.key()).map(|task| task.clone()) else {
+                    continue;
+                };
+                let node = BlockedNode {
+                    node_id: task.id,
+                    node_name: task.action.node_name(),
+                    node_type: task.action.node_type(),
+                    reason: BlockReason::Cancelled,
+                    inputs: Vec::new(),
+                };
+                debug!("{}", node);
+                blocked.push(node);
+            }
+        }
+        blocked.sort_by(|a, b| a.node_name.cmp(&b.node_name));
+        blocked
+    }
+
+    fn breakdown(
+        &self,
+        tasks: &DashMap<Uuid, Arc<Task>>,
+        task: &Task,
+        reason: BlockReason,
+        now: DateTime<Utc>,
+        received: impl Fn(&InputHandle) -> Option<State>,
+    ) -> BlockedNode {
+        let wait_start = self.wait_starts.get(&task.id).map(|start| *start);
+        let waited_until = |until: DateTime<Utc>| {
+            wait_start.map_or(0, |start| (until - start).num_milliseconds().max(0))
+        };
+
+        let mut handles = task.input_states.keys().collect::<Vec<_>>();
+        handles.sort();
+        let mut inputs = Vec::new();
+        for handle in handles {
+            let state = received(handle);
+            let sources = tasks
+                .iter()
+                .filter(|source| source.routes.contains(handle))
+                .map(|source| (source.id, source.action.node_name()))
+                .collect::<Vec<_>>();
+            let sources = if sources.is_empty() {
+                vec![None]
+            } else {
+                sources.into_iter().map(Some).collect()
+            };
+
+            for source in sources {
+                let source_id = source.as_ref().map(|(source_id, _)| *source_id);
+                // a merged handle is set by the one of its sources which arrived
+                let from_source = |message: &Message| {
+                    source_id.map_or(true, |source_id| message.node_id == source_id)
+                };
+                let (source_state, arrival) = match &state {
+                    Some(State::Empty(message)) => (SourceState::Empty, Some(message.end_time)),
+                    Some(State::Success(message)) if from_source(message) => {
+                        (SourceState::Success, Some(message.end_time))
+                    }
+                    _ => (
+                        source_id
+                            .and_then(|source_id| self.outcomes.get(&source_id))
+                            .map_or(SourceState::NeverRan, |outcome| *outcome),
+                        None,
+                    ),
+                };
+                let (source_node_id, source_node_name) = source.unzip();
+                inputs.push(BlockedInput {
+                    handle: handle.to_string(),
+                    handle_name: handle.name().to_string(),
+                    source_node_id,
+                    source_node_name,
+                    source_state,
+                    waited_ms: waited_until(arrival.unwrap_or(now)),
+                    arrived: state.is_some(),
+                });
+            }
+        }
+
+        BlockedNode {
+            node_id: task.id,
+            node_name: task.action.node_name(),
+            node_type: task.action.node_type(),
+            reason,
+            inputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        clock::ManualClock,
+        testing::{NoopBehavior, NoopGraph},
+    };
+
+    use super::*;
+
+    fn task_named(tasks: &DashMap<Uuid, Arc<Task>>, name: &str) -> Arc<Task> {
+        tasks
+            .iter()
+            .find(|task| task.action.node_name() == name)
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_inputs_of_waiting_node_are_broken_down() {
+        let mut graph = NoopGraph::default();
+        let first = graph.node(NoopBehavior::Forward);
+        let second = graph.node(NoopBehavior::Below(1.0));
+        let join = graph.node(NoopBehavior::Forward);
+        graph.edge(first, join);
+        graph.edge(second, join);
+        let tasks = graph
+            .tasks()
+            .into_iter()
+            .map(|(id, task)| (id, Arc::new(task)))
+            .collect::<DashMap<_, _>>();
+        let first = task_named(&tasks, "node0");
+        let second = task_named(&tasks, "node1");
+        let join = task_named(&tasks, "node2");
+        let idle_tasks = DashSet::from_iter([join.id]);
+
+        let clock = ManualClock::default();
+        let tracker = BlockTracker::new(Arc::new(clock.clone()));
+        tracker.waiting(join.id);
+        clock.advance(Duration::from_millis(1500));
+        let state = State::new(Message {
+            node_id: first.id,
+            node_name: "node0".to_string(),
+            ..Message::empty()
+        });
+        tracker.finished(first.id, &state);
+        join.input_state("node0").unwrap().set_state(state);
+
+        let blocked = tracker.blocked_nodes(&tasks, &idle_tasks, &DashSet::new(), false);
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].reason, BlockReason::Stuck);
+        let inputs = &blocked[0].inputs;
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].handle, format!("{}.node0", join.id));
+        assert_eq!(inputs[0].source_node_id, Some(first.id));
+        assert_eq!(inputs[0].source_state, SourceState::Success);
+        assert!(inputs[0].arrived);
+        assert_eq!(inputs[1].source_node_id, Some(second.id));
+        assert_eq!(inputs[1].source_state, SourceState::NeverRan);
+        assert!(!inputs[1].arrived);
+        assert_eq!(inputs[1].waited_ms, 1500);
+
+        tracker.finished(second.id, &State::termination());
+        let blocked = tracker.blocked_nodes(&tasks, &idle_tasks, &DashSet::new(), false);
+        assert_eq!(blocked[0].reason, BlockReason::Skipped);
+        assert_eq!(blocked[0].inputs[1].source_state, SourceState::Terminated);
+
+        // a node waiting for its turn to run isn't blocked
+        join.input_state("node1").unwrap().set_state(State::empty());
+        assert!(tracker
+            .blocked_nodes(&tasks, &idle_tasks, &DashSet::new(), false)
+            .is_empty());
+
+        // unless another node finished the run, which cancels it, as it cancels a running one
+        let active_tasks = DashSet::from_iter([first.id]);
+        let blocked = tracker.blocked_nodes(&tasks, &idle_tasks, &active_tasks, true);
+        assert_eq!(blocked.len(), 2);
+        assert!(blocked
+            .iter()
+            .all(|node| node.reason == BlockReason::Cancelled));
+        assert!(blocked[0].inputs.is_empty());
+        assert_eq!(blocked[1].inputs.len(), 2);
+    }
+}
@@ -1,29 +1,41 @@
 use crate::{
     engine::{
-        task::{State, Task},
-        RunOutput,
+        blocked::{BlockTracker, BlockedNode},
+        snapshot::{self, CheckpointEvent, SnapshotSender, StateSnapshot},
+        task::{ExecState, Input, InputHandle, State, Task},
+        trace::{self, Instrument},
+        NodeError, RunOutput,
     },
     pipeline::{
         context::Context,
-        nodes::{BreakpointChunk, Message, NodeInput, NodeStreamChunk, NodeStreamEnd, StreamChunk},
+        nodes::{
+            BreakpointChunk, GraphOutput, Message, NodeInput, NodeStreamChunk, NodeStreamEnd,
+            ParsedJson, StreamChunk,
+        },
+        outputs::OutputBindings,
+        run_store::RunStore,
         trace::MetaLog,
     },
     routes::pipelines::GraphInterruptMessage,
 };
-use chrono::Utc;
 use dashmap::{DashMap, DashSet};
 use futures::FutureExt;
 use log::{debug, error};
 use serde::Serialize;
+use serde_json::Value;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     panic::AssertUnwindSafe,
-    sync::Arc,
+    sync::{Arc, OnceLock},
+    time::Instant,
 };
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
 use tokio::task::JoinHandle;
 use uuid::Uuid;
 
+/// Executions of a task after which the run fails, e.g. of a loop which never exits
+const MAX_RECURSION_DEPTH: usize = 10;
+
 pub struct Engine {
     /// Store all tasks.
     tasks: Arc<DashMap<Uuid, Arc<Task>>>,
@@ -36,7 +48,7 @@ pub struct Engine {
     /// Store the execution depth of each task in the graph.
     depths: Arc<DashMap<Uuid, usize>>,
     /// Store all messages generated by the tasks.
-    node_messages: Arc<DashMap<Uuid, Message>>,
+    node_messages: Arc<DashMap<Uuid, Arc<Message>>>,
     /// Store the ids of the output messages.
     output_ids: Arc<DashSet<Uuid>>,
     /// Store the handles of the tasks that are currently being executed.
@@ -45,6 +57,48 @@ pub struct Engine {
     control_semaphore: Arc<tokio::sync::Semaphore>,
     /// Tasks which will stop the execution of the graph and wait until continue signal is received.
     breakpoint_task_ids: Arc<DashSet<Uuid>>,
+    /// Inputs of executed tasks by the id of the message they produced, if recording is enabled.
+    task_inputs: Option<Arc<DashMap<Uuid, HashMap<String, TaskInput>>>>,
+    /// Sends snapshots of tasks as they finish, if the run is checkpointed.
+    checkpoints: Option<SnapshotSender>,
+    /// Tasks on a cycle of the graph, which read their non-cyclic inputs again in each iteration.
+    cyclic_tasks: Arc<HashSet<Uuid>>,
+    /// Outcomes and wait times of the tasks, to report the nodes the run stopped at.
+    blocks: Arc<BlockTracker>,
+    /// Named outputs of the graph, returned in `EngineOutput::bound_outputs`.
+    output_bindings: Option<Arc<OutputBindings>>,
+    /// Id of the message of the task which finished the run, see `State::Finish`.
+    finish_message_id: Arc<OnceLock<Uuid>>,
+    /// Record the run store on the output, see `Engine::trace_run_store`.
+    trace_run_store: bool,
+    /// Tasks whose messages are stored with their values, if messages are compacted, see
+    /// `Engine::compact_messages`.
+    kept_message_tasks: Option<Arc<HashSet<Uuid>>>,
+}
+
+/// Input of a task as the task received it
+#[derive(Debug, Clone)]
+pub struct TaskInput {
+    pub message: Arc<Message>,
+    /// The handle had no message, and the task received the value of an empty message
+    pub empty: bool,
+}
+
+impl TaskInput {
+    pub fn from_state(state: &State) -> Self {
+        Self {
+            message: state.get_out(),
+            empty: matches!(state, State::Empty(_)),
+        }
+    }
+
+    pub fn to_state(&self) -> State {
+        if self.empty {
+            State::Empty(self.message.clone())
+        } else {
+            State::Success(self.message.clone())
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -52,6 +106,8 @@ enum ScheduledTask {
     Task(Uuid),
     // id of the task that failed
     Err,
+    // a task finished the run
+    Finish,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -59,9 +115,35 @@ enum ScheduledTask {
 pub struct EngineOutput {
     pub output_message_ids: Vec<Uuid>,
     pub messages: HashMap<Uuid, Message>,
+    /// Inputs of the nodes by the id of the message they produced, see `Engine::record_task_inputs`
+    #[serde(skip)]
+    pub task_inputs: HashMap<Uuid, HashMap<String, TaskInput>>,
+    /// Nodes which failed, or waited for inputs which never arrived
+    pub blocked_nodes: Vec<BlockedNode>,
+    /// Outputs by the names of the graph's output bindings, unset if it declares none
+    #[serde(skip)]
+    pub bound_outputs: Option<BTreeMap<String, GraphOutput>>,
+    /// Message a task finished the run with, the only output of the run, see `State::Finish`
+    pub finish_message_id: Option<Uuid>,
+    /// Values of the run store as of the end of the run, if the engine records them
+    #[serde(skip)]
+    pub run_store: Option<BTreeMap<String, Value>>,
 }
 
 impl EngineOutput {
+    /// Outputs of the run as returned and stored, by binding name if the graph declares output
+    /// bindings, and by output node name otherwise
+    pub fn graph_outputs(&self) -> BTreeMap<String, GraphOutput> {
+        match &self.bound_outputs {
+            Some(outputs) => outputs.clone(),
+            None => self
+                .output_values()
+                .into_iter()
+                .map(|(node_name, value)| (node_name, GraphOutput::new(value)))
+                .collect(),
+        }
+    }
+
     pub fn output_values(&self) -> HashMap<String, NodeInput> {
         self.output_message_ids
             .iter()
@@ -75,6 +157,7 @@ impl EngineOutput {
 
 impl Engine {
     fn new(context: Context) -> Engine {
+        let blocks = Arc::new(BlockTracker::new(context.clock.clone()));
         Engine {
             tasks: Arc::new(DashMap::new()),
             active_tasks: Arc::new(DashSet::new()),
@@ -86,6 +169,99 @@ impl Engine {
             handles: Arc::new(DashMap::new()),
             control_semaphore: Arc::new(tokio::sync::Semaphore::new(20)),
             breakpoint_task_ids: Arc::new(DashSet::new()),
+            task_inputs: None,
+            checkpoints: None,
+            cyclic_tasks: Arc::new(HashSet::new()),
+            blocks,
+            output_bindings: None,
+            finish_message_id: Arc::new(OnceLock::new()),
+            trace_run_store: false,
+            kept_message_tasks: None,
+        }
+    }
+
+    /// Record the inputs of each executed task, they're returned in `EngineOutput::task_inputs`
+    pub fn record_task_inputs(&mut self) {
+        self.task_inputs = Some(Arc::new(DashMap::new()));
+    }
+
+    /// Return the outputs of the bound nodes by binding name, see `pipeline::outputs`
+    pub fn bind_outputs(&mut self, output_bindings: Option<Arc<OutputBindings>>) {
+        self.output_bindings = output_bindings;
+    }
+
+    /// Send snapshots of tasks to checkpoint the run, see `engine::snapshot`. Seqs of the
+    /// snapshots start from `first_seq`, past the seqs of the checkpoint a run is resumed from.
+    pub fn record_checkpoints(&mut self, events: UnboundedSender<CheckpointEvent>, first_seq: u64) {
+        self.checkpoints = Some(SnapshotSender::new(
+            events,
+            first_seq,
+            self.context.run_store.clone(),
+        ));
+    }
+
+    /// Record the run store on the output once the run ends, see `EngineOutput::run_store`
+    pub fn trace_run_store(&mut self) {
+        self.trace_run_store = true;
+    }
+
+    /// Store the messages of tasks other than the output tasks and `kept_task_ids` without their
+    /// values, so that a message is dropped once its successors have read it and the memory of
+    /// the run scales with the graph's width rather than its size. Only for runs whose output
+    /// isn't returned or persisted with the values of all messages, e.g. not for workshop runs.
+    pub fn compact_messages(&mut self, kept_task_ids: HashSet<Uuid>) {
+        self.kept_message_tasks = Some(Arc::new(kept_task_ids));
+    }
+
+    /// Key/value store the nodes of the run share, see `pipeline::run_store`
+    pub fn run_store(&self) -> &RunStore {
+        &self.context.run_store
+    }
+
+    /// Restore the state of a run paused at `paused_task_id` from the latest snapshots of its
+    /// tasks, returns the tasks to continue from. The run is finished if there are none, e.g. if a
+    /// task finished it, and the scheduled tasks are then cancelled.
+    pub fn restore(
+        &self,
+        snapshots: Vec<StateSnapshot>,
+        paused_task_id: Option<Uuid>,
+    ) -> anyhow::Result<Vec<Uuid>> {
+        let restored = snapshot::restore(&self.tasks, snapshots, paused_task_id)?;
+        for (task_id, depth) in restored.depths {
+            self.depths.insert(task_id, depth);
+        }
+        for message in restored.messages {
+            self.node_messages.insert(message.id, Arc::new(message));
+        }
+        for message_id in restored.output_ids {
+            self.output_ids.insert(message_id);
+        }
+        // as if they were scheduled by their predecessors
+        for task_id in restored.start_task_ids.iter() {
+            self.idle_tasks.insert(*task_id);
+        }
+        if let Some(message_id) = restored.finish_message_id {
+            let _ = self.finish_message_id.set(message_id);
+            return Ok(Vec::new());
+        }
+        Ok(restored.start_task_ids)
+    }
+
+    /// Set inputs of tasks as if their predecessors had produced the messages, and add messages of
+    /// output tasks which won't be executed to the outputs, e.g. from the recorded I/O of a run
+    /// which is replayed.
+    pub fn seed(&self, inputs: HashMap<Uuid, HashMap<String, TaskInput>>, outputs: Vec<Message>) {
+        for (task_id, task_inputs) in inputs {
+            let task = self.tasks.get(&task_id).unwrap().clone();
+            for (handle_name, input) in task_inputs {
+                if let Some(input_state) = task.input_state(&handle_name) {
+                    input_state.set_state(input.to_state());
+                }
+            }
+        }
+        for message in outputs {
+            self.output_ids.insert(message.id);
+            self.node_messages.insert(message.id, Arc::new(message));
         }
     }
 
@@ -97,7 +273,8 @@ impl Engine {
         start_task_id: Option<Uuid>,
         breakpoint_task_ids: Option<Vec<Uuid>>,
     ) -> Engine {
-        let engine = Engine::new(context);
+        let mut engine = Engine::new(context);
+        engine.cyclic_tasks = Arc::new(tasks_on_cycles(&tasks));
 
         if let Some(breakpoint_task_ids) = breakpoint_task_ids {
             for task_id in breakpoint_task_ids {
@@ -116,25 +293,10 @@ impl Engine {
                 let task = engine.tasks.get(&message.node_id).unwrap().clone();
 
                 // prefill next tasks with the message
-                for next_task_id in task.next.iter() {
-                    let next_task = engine.tasks.get(next_task_id).unwrap().clone();
-
-                    let handle_names = next_task
-                        .action
-                        .handles_mapping()
-                        .iter()
-                        .filter(|(k, _)| *k == task.action.output_handle_id())
-                        .map(|(_, v)| v.clone())
-                        .collect::<Vec<_>>();
-
-                    for handle in handle_names.iter() {
-                        let next_state = next_task
-                            .input_states
-                            .get(&handle.name_force())
-                            .unwrap()
-                            .clone();
-                        next_state.set_state_and_permits(State::new(message.clone()), 1);
-                    }
+                for route in task.routes.iter() {
+                    engine
+                        .input_state(route)
+                        .set_state(State::new(message.clone()));
                 }
             }
             // reset all input_states
@@ -145,6 +307,11 @@ impl Engine {
         engine
     }
 
+    /// State of the input handle of its task
+    fn input_state(&self, handle: &InputHandle) -> Arc<ExecState> {
+        self.tasks.get(&handle.node_id()).unwrap().input_states[handle].clone()
+    }
+
     fn propagate_reset_input_states(
         &self,
         task_id: Uuid,
@@ -162,23 +329,12 @@ impl Engine {
                 continue;
             }
 
-            let next_task = self.tasks.get(next_task_id).unwrap().clone();
-
-            let handle_names = next_task
-                .action
-                .handles_mapping()
+            for route in task
+                .routes
                 .iter()
-                .filter(|(k, _)| *k == task.action.output_handle_id())
-                .map(|(_, v)| v.clone())
-                .collect::<Vec<_>>();
-
-            for handle in handle_names.iter() {
-                let next_state = next_task
-                    .input_states
-                    .get(&handle.name_force())
-                    .unwrap()
-                    .clone();
-                next_state.set_state_and_permits(State::empty(), 1);
+                .filter(|route| route.node_id() == *next_task_id)
+            {
+                self.input_state(route).set_state(State::empty());
             }
 
             visited.insert(next_task_id.clone());
@@ -186,17 +342,33 @@ impl Engine {
         }
     }
 
-    /// Start task scheduler and execute tasks.
+    /// Start task scheduler and execute tasks, starting from the given tasks, or the tasks without
+    /// predecessors if none are given.
     pub async fn run(
         &mut self,
         stream_send: Option<Sender<StreamChunk>>,
         interrupt_recv: Option<Receiver<GraphInterruptMessage>>,
-        start_task_id: Option<Uuid>,
+        start_task_ids: Vec<Uuid>,
+    ) -> Result<EngineOutput, EngineOutput> {
+        let span = trace::run_span(Uuid::new_v4(), self.tasks.len());
+        let result = self
+            .schedule(stream_send, interrupt_recv, start_task_ids)
+            .instrument(span.clone())
+            .await;
+        span.in_scope(|| trace::run_finished(result.is_ok()));
+        result
+    }
+
+    async fn schedule(
+        &mut self,
+        stream_send: Option<Sender<StreamChunk>>,
+        interrupt_recv: Option<Receiver<GraphInterruptMessage>>,
+        start_task_ids: Vec<Uuid>,
     ) -> Result<EngineOutput, EngineOutput> {
         let (task_send, mut task_recv) = tokio::sync::mpsc::channel::<ScheduledTask>(10);
 
-        let input_tasks = if let Some(start_task_id) = start_task_id {
-            vec![start_task_id]
+        let input_tasks = if !start_task_ids.is_empty() {
+            start_task_ids
         } else {
             self.tasks
                 .iter()
@@ -205,6 +377,8 @@ impl Engine {
                 .collect::<Vec<_>>()
         };
 
+        trace::run_started(&input_tasks);
+
         // push input tasks to the channel
         let tx = task_send.clone();
         tokio::spawn(async move {
@@ -218,19 +392,22 @@ impl Engine {
             let active_tasks = self.active_tasks.clone();
             let handles = self.handles.clone();
             let control_semaphore = self.control_semaphore.clone();
-            tokio::spawn(async move {
+            let interrupts = async move {
                 while let Some(interrupt) = interrupt_recv.recv().await {
                     if matches!(interrupt, GraphInterruptMessage::Cancel) {
+                        trace::cancelled(active_tasks.len());
                         active_tasks.clear();
                         handles.iter().for_each(|handle| handle.abort());
 
                         tx.send(ScheduledTask::Err).await.unwrap();
                     } else if matches!(interrupt, GraphInterruptMessage::Continue) {
                         // continue execution
+                        trace::permits_added(20);
                         control_semaphore.add_permits(20);
                     }
                 }
-            });
+            };
+            tokio::spawn(interrupts.instrument(trace::Span::current()));
         }
 
         loop {
@@ -240,6 +417,7 @@ impl Engine {
                         // awaiting only output nodes to check if we can stop execution
                         // if task doesn't have any next tasks it means it's an output node
                         let task = self.tasks.get(&task_id).unwrap().clone();
+                        trace::task_received(task_id, task.next.is_empty());
 
                         if task.next.is_empty() {
                             match self
@@ -286,6 +464,14 @@ impl Engine {
 
                         return Err(self.get_outputs());
                     }
+                    ScheduledTask::Finish => {
+                        // the other tasks are cancelled, including streaming ones, whose
+                        // chunks so far stay sent
+                        trace::cancelled(self.active_tasks.len());
+                        self.handles.iter().for_each(|handle| handle.abort());
+
+                        return Ok(self.get_outputs());
+                    }
                 }
             }
         }
@@ -299,6 +485,7 @@ impl Engine {
         stream_send: Option<Sender<StreamChunk>>,
     ) -> JoinHandle<()> {
         let context = self.context.clone();
+        let clock = context.clock.clone();
         let task_id = task.id;
         let action = task.action.clone();
         let next = task.next.clone();
@@ -312,36 +499,84 @@ impl Engine {
         let node_messages = self.node_messages.clone();
         let control_semaphore = self.control_semaphore.clone();
         let breakpoint_task_ids = self.breakpoint_task_ids.clone();
+        let task_inputs = self.task_inputs.clone();
+        let checkpoints = self.checkpoints.clone();
+        let cyclic_tasks = self.cyclic_tasks.clone();
+        let blocks = self.blocks.clone();
+        let finish_message_id = self.finish_message_id.clone();
+        let keeps_messages = match &self.kept_message_tasks {
+            Some(kept_message_tasks) => next.is_empty() || kept_message_tasks.contains(&task_id),
+            None => true,
+        };
+        let span = trace::task_span(&task, depth);
 
-        tokio::spawn(async move {
+        let execution = async move {
+            blocks.waiting(task_id);
             // acquire semaphore to control the number of active tasks
-            let control_permit = control_semaphore.acquire().await.unwrap();
+            let control_permit = control_semaphore
+                .acquire()
+                .instrument(trace::queue_wait_span())
+                .await
+                .unwrap();
 
             let mut inputs = HashMap::new();
             let mut input_message_ids = Vec::new();
+            let mut recorded_inputs = HashMap::new();
+            let mut input_generations = HashMap::new();
 
             // Wait for inputs for this task to be set
-            for (handle_name, input_state) in input_states.iter() {
-                let _permit = input_state.semaphore().acquire().await.unwrap();
-
-                // Set the outputs of predecessors as inputs of the current
-                let output = input_state.get_state();
-                let message = output.get_out();
-
-                inputs.insert(handle_name.clone(), message.value.clone());
-                input_message_ids.push(message.id);
+            async {
+                for (handle, input_state) in input_states.iter() {
+                    let waiting_since = Instant::now();
+                    let (output, generation) = input_state.wait_for_state().await;
+                    trace::input_received(
+                        handle,
+                        generation,
+                        matches!(output, State::Empty(_)),
+                        waiting_since.elapsed(),
+                    );
+
+                    // Set the outputs of predecessors as inputs of the current
+                    input_generations.insert(handle.clone(), generation);
+                    let message = output.get_out();
+
+                    input_message_ids.push(message.id);
+                    inputs.insert(handle.clone(), message);
+                    if task_inputs.is_some() {
+                        recorded_inputs
+                            .insert(handle.name().to_string(), TaskInput::from_state(&output));
+                    }
+                }
             }
+            .instrument(trace::inputs_span())
+            .await;
+            // inputs are recorded under the id of the message the task produces, even a failed one
+            let record_inputs = |message_id: Uuid| {
+                if let Some(task_inputs) = &task_inputs {
+                    task_inputs.insert(message_id, recorded_inputs.clone());
+                }
+            };
 
             // once the task has collected all inputs, we remove it from idle tasks and push to active tasks
             active_tasks.insert(task_id);
 
+            // release the consumed inputs, so that messages don't outlive their successors' reads,
+            // unless the task reads them again in the next iteration of a cycle or they are
+            // recorded as its I/O. Inputs of output tasks are kept with the outputs of the run.
+            // Released after the task is active, so that the run isn't considered finished.
+            if task_inputs.is_none() && !next.is_empty() && !cyclic_tasks.contains(&task_id) {
+                task.release_inputs(&input_generations);
+            }
+
             // if task is a breakpoint task, we first remove all permits from the semaphore
             // to stop the execution of the graph
             if breakpoint_task_ids.contains(&task_id) {
-                control_semaphore.forget_permits(control_semaphore.available_permits());
+                let permits = control_semaphore.available_permits();
+                trace::permits_forgotten(permits);
+                control_semaphore.forget_permits(permits);
             }
 
-            let start_time = Utc::now();
+            let start_time = clock.now();
 
             let mut id = Uuid::new_v4();
 
@@ -358,12 +593,16 @@ impl Engine {
                 stream_send.send(stream_chunk).await.unwrap();
             }
 
-            match AssertUnwindSafe(action.run(inputs, context))
+            // kept to break down the inputs of the task if it fails
+            let received = inputs.clone();
+            match AssertUnwindSafe(action.run(Input::new(task_id, inputs), context))
                 .catch_unwind()
+                .instrument(trace::execute_span())
                 .await
             {
                 Err(_) => {
                     debug!("Execution failed [id: {}]", task_id);
+                    trace::task_failed(&"panicked");
                     let msg_id = Uuid::new_v4();
                     let error = Message {
                         id: msg_id,
@@ -373,13 +612,17 @@ impl Engine {
                         node_type: action.node_type(),
                         input_message_ids,
                         meta_log: None,
+                        parsed_json: ParsedJson::default(),
+                        post_processing: Vec::new(),
                         start_time,
-                        end_time: Utc::now(),
+                        end_time: clock.now(),
                     };
 
+                    record_inputs(msg_id);
                     output_ids.insert(msg_id);
-                    node_messages.insert(msg_id, error);
+                    node_messages.insert(msg_id, Arc::new(error));
                     idle_tasks.remove(&task_id);
+                    blocks.failed(&tasks, &task, &received);
 
                     task_send.send(ScheduledTask::Err).await.unwrap();
 
@@ -387,10 +630,36 @@ impl Engine {
                     drop(control_permit);
                 }
                 Ok(out) => {
+                    // applied before the message is passed on, so that a failing post-processor
+                    // fails the node which declares it
+                    let mut post_processing = Vec::new();
+                    let out = match (out, &task.post_processors) {
+                        (Ok(RunOutput::Success((value, meta_log))), Some(post_processors)) => {
+                            post_processors
+                                .apply(value)
+                                .map(|(value, effects)| {
+                                    post_processing = effects;
+                                    RunOutput::Success((value, meta_log))
+                                })
+                                .map_err(NodeError::Failed)
+                        }
+                        (Ok(RunOutput::Finish((value, meta_log))), Some(post_processors)) => {
+                            post_processors
+                                .apply(value)
+                                .map(|(value, effects)| {
+                                    post_processing = effects;
+                                    RunOutput::Finish((value, meta_log))
+                                })
+                                .map_err(NodeError::Failed)
+                        }
+                        (out, _) => out,
+                    };
                     match out {
                         Ok(run_output) => {
+                            let finishes = matches!(run_output, RunOutput::Finish(_));
                             let state = match run_output {
-                                RunOutput::Success((value, meta_log)) => {
+                                RunOutput::Success((value, meta_log))
+                                | RunOutput::Finish((value, meta_log)) => {
                                     if let Some(meta_log) = meta_log.clone() {
                                         match meta_log {
                                             MetaLog::LLM(llm_meta_log) => {
@@ -400,7 +669,8 @@ impl Engine {
                                             }
                                             MetaLog::Zenguard(_)
                                             | MetaLog::Subpipeline(_)
-                                            | MetaLog::Map(_) => {}
+                                            | MetaLog::Map(_)
+                                            | MetaLog::Embedding(_) => {}
                                         }
                                     }
                                     let message = Message {
@@ -411,21 +681,37 @@ impl Engine {
                                         node_type: action.node_type(),
                                         input_message_ids: input_message_ids.clone(),
                                         meta_log,
+                                        parsed_json: ParsedJson::default(),
+                                        post_processing,
                                         start_time,
-                                        end_time: Utc::now(),
+                                        end_time: clock.now(),
+                                    };
+                                    record_inputs(id);
+                                    let message = Arc::new(message);
+                                    let stored = match keeps_messages {
+                                        true => message.clone(),
+                                        false => Arc::new(message.without_value()),
                                     };
-                                    node_messages.insert(id, message.clone());
+                                    node_messages.insert(id, stored);
 
-                                    State::new(message)
+                                    if finishes {
+                                        State::Finish(message)
+                                    } else {
+                                        State::Success(message)
+                                    }
                                 }
                                 RunOutput::Termination => State::termination(),
                             };
+                            blocks.finished(task_id, &state);
+                            // set before the task stops being active, so that the run isn't
+                            // considered finished without it. The first task to finish wins.
+                            let finishes = finishes && finish_message_id.set(id).is_ok();
 
                             // send to the stream before scheduling next tasks
                             // to ensure the order of the stream
                             if let Some(stream_send) = stream_send.clone() {
                                 // check if success, because it can be a termination
-                                if state.is_success() {
+                                if state.is_success() || state.is_finish() {
                                     let stream_chunk = StreamChunk::NodeEnd(NodeStreamEnd {
                                         message: (*state.get_out()).clone(),
                                     });
@@ -445,18 +731,33 @@ impl Engine {
                                             .await
                                             .unwrap();
 
+                                        // the run can be resumed from the checkpoint by
+                                        // another engine, which passes on the task's output
+                                        if let Some(checkpoints) = &checkpoints {
+                                            checkpoints.send_task(
+                                                &task,
+                                                depth + 1,
+                                                task_messages(&node_messages, task_id),
+                                                false,
+                                                true,
+                                            );
+                                            checkpoints.send_paused(task_id);
+                                        }
+
                                         let _ = control_semaphore.acquire().await.unwrap();
                                     }
                                 }
                             }
 
-                            let is_termination = state.is_termination();
+                            // a finish isn't passed on either
+                            let is_termination = state.is_termination() || state.is_finish();
                             debug!("Task {} executed", task_id);
 
                             idle_tasks.remove(&task_id);
 
-                            // terminate graph on recursion depth exceeding 10
-                            if depth == 10 {
+                            // terminate graph on recursion depth exceeding the limit
+                            trace::depth_checked(depth, MAX_RECURSION_DEPTH);
+                            if depth == MAX_RECURSION_DEPTH {
                                 debug!("Max recursion depth exceeded, terminating graph");
 
                                 let msg_id = Uuid::new_v4();
@@ -469,8 +770,10 @@ impl Engine {
                                     node_type: action.node_type(),
                                     input_message_ids: input_message_ids.clone(),
                                     meta_log: None,
+                                    parsed_json: ParsedJson::default(),
+                                    post_processing: Vec::new(),
                                     start_time,
-                                    end_time: Utc::now(),
+                                    end_time: clock.now(),
                                 };
 
                                 if let Some(stream_send) = stream_send.clone() {
@@ -482,65 +785,61 @@ impl Engine {
                                 }
 
                                 output_ids.insert(msg_id);
-                                node_messages.insert(msg_id, error);
+                                node_messages.insert(msg_id, Arc::new(error));
 
                                 task_send.send(ScheduledTask::Err).await.unwrap();
                             }
 
                             if next.is_empty() {
                                 // if there are no next tasks, we can terminate the graph
-                                let message = state.get_out().as_ref().clone();
+                                let message = state.get_out();
                                 output_ids.insert(message.id);
                                 node_messages.insert(message.id, message);
                             }
 
-                            // push next tasks to the channel only if the current task is not a termination
-                            for next_task_id in next.iter() {
-                                if is_termination {
-                                    break;
-                                }
+                            async {
+                                // push next tasks to the channel only if the current task is not a termination
+                                for next_task_id in next.iter() {
+                                    if is_termination {
+                                        break;
+                                    }
 
-                                // we set the inputs of the next tasks to the outputs of the current task
-                                let next_task = tasks.get(next_task_id).unwrap().clone();
-
-                                // in majority of cases there will be only one handle name
-                                // however we need to handle the case when single output is mapped to multiple inputs on the next node
-                                let handle_names = next_task
-                                    .action
-                                    .handles_mapping()
-                                    .iter()
-                                    .filter(|(k, _)| *k == action.output_handle_id())
-                                    .map(|(_, v)| v.clone())
-                                    .collect::<Vec<_>>();
-
-                                for handle in handle_names.iter() {
-                                    let next_state = next_task
-                                        .input_states
-                                        .get(&handle.name_force())
-                                        .unwrap()
-                                        .clone();
-                                    next_state.set_state_and_permits(state.clone(), 1);
-                                }
+                                    // we set the inputs of the next tasks to the outputs of the current task.
+                                    // In majority of cases there will be only one route to the next task,
+                                    // however a single output can be mapped to multiple inputs on the next node
+                                    for route in task
+                                        .routes
+                                        .iter()
+                                        .filter(|route| route.node_id() == *next_task_id)
+                                    {
+                                        tasks.get(next_task_id).unwrap().input_states[route]
+                                            .set_state(state.clone());
+                                    }
 
-                                // push next tasks to the channel only if the task is not active and current task is not a termination
-                                if !idle_tasks.contains(next_task_id) {
-                                    idle_tasks.insert(*next_task_id);
-                                    task_send
-                                        .send(ScheduledTask::Task(*next_task_id))
-                                        .await
-                                        .unwrap();
+                                    // push next tasks to the channel only if the task is not active and current task is not a termination
+                                    let scheduled = !idle_tasks.contains(next_task_id);
+                                    trace::successor(*next_task_id, scheduled);
+                                    if scheduled {
+                                        idle_tasks.insert(*next_task_id);
+                                        task_send
+                                            .send(ScheduledTask::Task(*next_task_id))
+                                            .await
+                                            .unwrap();
+                                    }
                                 }
-                            }
 
-                            // reset the inputs of the current task if they are resettable.
-                            // This prevents the task from being executed again with the same inputs
-                            // instead of waiting for new inputs
-                            for input_state in input_states.values() {
-                                if input_state.is_resettable() {
-                                    input_state.set_state(State::empty());
-                                    input_state.semaphore().forget_permits(1);
+                                // reset the inputs of the current task if they are resettable.
+                                // This prevents the task from being executed again with the same inputs
+                                // instead of waiting for new inputs
+                                for (handle, input_state) in input_states.iter() {
+                                    if input_state.is_resettable() {
+                                        trace::input_reset(handle, input_generations[handle]);
+                                        input_state.reset_consumed(input_generations[handle]);
+                                    }
                                 }
                             }
+                            .instrument(trace::publish_span())
+                            .await;
 
                             // remove the task from active tasks once it's done, and has pushed next tasks to idle tasks and the channel
                             active_tasks.remove(&task_id);
@@ -548,11 +847,50 @@ impl Engine {
                             // increment depth of the finished task
                             depths.insert(task_id, depth + 1);
 
+                            if let Some(checkpoints) = &checkpoints {
+                                if finishes {
+                                    // the checkpoint is completed once the engine is dropped
+                                    checkpoints.send_finished(
+                                        &task,
+                                        depth + 1,
+                                        task_messages(&node_messages, task_id),
+                                    );
+                                } else {
+                                    checkpoints.send_task(
+                                        &task,
+                                        depth + 1,
+                                        task_messages(&node_messages, task_id),
+                                        idle_tasks.contains(&task_id),
+                                        false,
+                                    );
+                                }
+                                for next_task_id in next.iter() {
+                                    if is_termination {
+                                        break;
+                                    }
+                                    let next_task = tasks.get(next_task_id).unwrap().clone();
+                                    let executions =
+                                        depths.get(next_task_id).map(|depth| *depth).unwrap_or(0);
+                                    checkpoints.send_task(
+                                        &next_task,
+                                        executions,
+                                        task_messages(&node_messages, *next_task_id),
+                                        true,
+                                        false,
+                                    );
+                                }
+                            }
+
                             // release semaphore
                             drop(control_permit);
+
+                            if finishes {
+                                task_send.send(ScheduledTask::Finish).await.unwrap();
+                            }
                         }
                         Err(err) => {
                             debug!("Execution failed [id: {}], err: {}", task_id, err);
+                            trace::task_failed(&err);
 
                             let msg_id = Uuid::new_v4();
 
@@ -564,8 +902,10 @@ impl Engine {
                                 node_type: action.node_type(),
                                 input_message_ids,
                                 meta_log: None,
+                                parsed_json: ParsedJson::default(),
+                                post_processing: Vec::new(),
                                 start_time,
-                                end_time: Utc::now(),
+                                end_time: clock.now(),
                             };
 
                             if let Some(stream_send) = stream_send {
@@ -576,8 +916,10 @@ impl Engine {
                                 stream_send.send(stream_chunk).await.unwrap();
                             }
 
+                            record_inputs(msg_id);
                             output_ids.insert(msg_id);
-                            node_messages.insert(msg_id, error);
+                            node_messages.insert(msg_id, Arc::new(error));
+                            blocks.failed(&tasks, &task, &received);
 
                             // terminate entire graph by sending err task
                             task_send.send(ScheduledTask::Err).await.unwrap();
@@ -589,17 +931,301 @@ impl Engine {
                     }
                 }
             }
-        })
+        };
+        tokio::spawn(execution.instrument(span))
     }
 
-    fn get_outputs(&self) -> EngineOutput {
+    pub fn get_outputs(&self) -> EngineOutput {
+        let messages = self
+            .node_messages
+            .iter()
+            .map(|entry| (entry.key().to_owned(), entry.value().as_ref().clone()))
+            .collect::<HashMap<_, _>>();
+        let finish_message_id = self.finish_message_id.get().copied();
+        let blocked_nodes = self.blocks.blocked_nodes(
+            &self.tasks,
+            &self.idle_tasks,
+            &self.active_tasks,
+            finish_message_id.is_some(),
+        );
+        let finished_by = finish_message_id
+            .and_then(|message_id| messages.get(&message_id))
+            .map(|message| message.node_id);
+        let bound_outputs = self.output_bindings.as_ref().map(|output_bindings| {
+            output_bindings.bind(
+                &messages,
+                &blocked_nodes,
+                &self.blocks.terminated(),
+                finished_by,
+            )
+        });
+        let output_message_ids = match finish_message_id {
+            Some(message_id) => vec![message_id],
+            None => self.output_ids.as_ref().clone().into_iter().collect(),
+        };
         EngineOutput {
-            messages: self
-                .node_messages
+            messages,
+            output_message_ids,
+            task_inputs: self
+                .task_inputs
+                .as_ref()
+                .map(|task_inputs| {
+                    task_inputs
+                        .iter()
+                        .map(|entry| (entry.key().to_owned(), entry.value().to_owned()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            blocked_nodes,
+            bound_outputs,
+            finish_message_id,
+            run_store: self
+                .trace_run_store
+                .then(|| self.context.run_store.snapshot().values),
+        }
+    }
+}
+
+/// Tasks which can reach themselves through their successors
+fn tasks_on_cycles(tasks: &HashMap<Uuid, Task>) -> HashSet<Uuid> {
+    tasks
+        .values()
+        .filter(|task| {
+            let mut visited = HashSet::new();
+            let mut stack = task.next.clone();
+            while let Some(task_id) = stack.pop() {
+                if task_id == task.id {
+                    return true;
+                }
+                if visited.insert(task_id) {
+                    if let Some(next_task) = tasks.get(&task_id) {
+                        stack.extend(next_task.next.iter().copied());
+                    }
+                }
+            }
+            false
+        })
+        .map(|task| task.id)
+        .collect()
+}
+
+/// Messages the task produced, in order of execution
+fn task_messages(node_messages: &DashMap<Uuid, Arc<Message>>, task_id: Uuid) -> Vec<Message> {
+    let mut messages = node_messages
+        .iter()
+        .filter(|entry| entry.node_id == task_id)
+        .map(|entry| entry.value().as_ref().clone())
+        .collect::<Vec<_>>();
+    messages.sort_by_key(|message| message.start_time);
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, Weak};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        engine::{task::Action, NodeImpl},
+        pipeline::nodes::Handle,
+        testing::{NoopBehavior, NoopGraph, OfflineServices},
+    };
+
+    use super::*;
+
+    /// Runs the wrapped node after recording the messages of its inputs
+    struct Probe {
+        node: Action,
+        inputs: Arc<Mutex<Vec<Weak<Message>>>>,
+    }
+
+    #[async_trait]
+    impl NodeImpl for Probe {
+        fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+            self.node.handles_mapping()
+        }
+
+        fn output_handle_id(&self) -> Uuid {
+            self.node.output_handle_id()
+        }
+
+        fn node_name(&self) -> String {
+            self.node.node_name()
+        }
+
+        fn node_id(&self) -> Uuid {
+            self.node.node_id()
+        }
+
+        fn node_type(&self) -> String {
+            self.node.node_type()
+        }
+
+        async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+            self.inputs
+                .lock()
+                .unwrap()
+                .extend(input.messages().values().map(Arc::downgrade));
+            self.node.run(input, context).await
+        }
+    }
+
+    const MESSAGE_SIZE: usize = 5 * 1024 * 1024;
+
+    /// Resident memory of the process, where it's known
+    fn rss_bytes() -> Option<usize> {
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages = statm.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+        Some(pages * 4096)
+    }
+
+    /// Outputs a new 5 MB message in place of the wrapped node's output, recording the peak RSS
+    struct Large {
+        node: Action,
+        peak_rss: Arc<Mutex<Option<usize>>>,
+    }
+
+    #[async_trait]
+    impl NodeImpl for Large {
+        fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+            self.node.handles_mapping()
+        }
+
+        fn output_handle_id(&self) -> Uuid {
+            self.node.output_handle_id()
+        }
+
+        fn node_name(&self) -> String {
+            self.node.node_name()
+        }
+
+        fn node_id(&self) -> Uuid {
+            self.node.node_id()
+        }
+
+        fn node_type(&self) -> String {
+            self.node.node_type()
+        }
+
+        async fn run(&self, _input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+            let value = "x".repeat(MESSAGE_SIZE);
+            let mut peak_rss = self.peak_rss.lock().unwrap();
+            *peak_rss = (*peak_rss).max(rss_bytes());
+            Ok(RunOutput::Success((value.into(), None)))
+        }
+    }
+
+    /// 100 sequential nodes, each producing a 5 MB message. With compacted messages, only the
+    /// messages which are yet to be read and the output stay alive.
+    #[tokio::test]
+    async fn test_compacted_messages_bound_memory() {
+        const NODES: usize = 100;
+
+        let mut graph = NoopGraph::default();
+        let nodes = (0..NODES)
+            .map(|_| graph.node(NoopBehavior::Forward))
+            .collect::<Vec<_>>();
+        for pair in nodes.windows(2) {
+            graph.edge(pair[0], pair[1]);
+        }
+        let mut tasks = graph.tasks();
+        let peak_rss = Arc::new(Mutex::new(None));
+        for task in tasks.values_mut() {
+            task.action = Arc::new(Large {
+                node: task.action.clone(),
+                peak_rss: peak_rss.clone(),
+            });
+        }
+
+        let services = OfflineServices::default();
+        let mut engine =
+            Engine::with_tasks_and_context(tasks, services.context(), None, None, None);
+        engine.compact_messages(HashSet::new());
+        let baseline = rss_bytes();
+        let outputs = engine.run(None, None, vec![]).await.unwrap();
+
+        assert_eq!(outputs.messages.len(), NODES);
+        let output_id = graph.id(nodes[NODES - 1]);
+        for message in outputs.messages.values() {
+            let size = match &message.value {
+                NodeInput::String(value) => value.len(),
+                value => panic!("unexpected value {:?}", value),
+            };
+            let expected = if message.node_id == output_id {
+                MESSAGE_SIZE
+            } else {
+                0
+            };
+            assert_eq!(size, expected);
+        }
+        let peak = *peak_rss.lock().unwrap();
+        if let (Some(baseline), Some(peak)) = (baseline, peak) {
+            // all 100 messages take 500 MB
+            assert!(
+                peak.saturating_sub(baseline) < 20 * MESSAGE_SIZE,
+                "peak RSS grew by {} bytes",
+                peak.saturating_sub(baseline)
+            );
+        }
+    }
+
+    /// An input fanned out to 8 nodes, joined by one node before the output. Once the run with
+    /// compacted messages ends, only the message of the output's input, which the output task
+    /// doesn't release, is alive.
+    #[tokio::test]
+    async fn test_released_inputs_are_dropped() {
+        const BRANCHES: usize = 8;
+
+        let mut graph = NoopGraph::default();
+        let input = graph.node(NoopBehavior::Forward);
+        let join = graph.node(NoopBehavior::Forward);
+        let branches = (0..BRANCHES)
+            .map(|_| {
+                let branch = graph.node(NoopBehavior::Forward);
+                graph.edge(input, branch);
+                graph.edge(branch, join);
+                branch
+            })
+            .collect::<Vec<_>>();
+        let output = graph.node(NoopBehavior::Forward);
+        graph.edge(join, output);
+
+        let mut tasks = graph.tasks();
+        let mut probe = |node: usize| {
+            let inputs = Arc::new(Mutex::new(Vec::new()));
+            let task = tasks.get_mut(&graph.id(node)).unwrap();
+            task.action = Arc::new(Probe {
+                node: task.action.clone(),
+                inputs: inputs.clone(),
+            });
+            inputs
+        };
+        let branch_inputs = branches
+            .iter()
+            .map(|branch| probe(*branch))
+            .collect::<Vec<_>>();
+        let join_inputs = probe(join);
+        let output_inputs = probe(output);
+
+        let services = OfflineServices::default();
+        let mut engine =
+            Engine::with_tasks_and_context(tasks, services.context(), None, None, None);
+        engine.compact_messages(HashSet::new());
+        engine.run(None, None, vec![]).await.unwrap();
+
+        let live = |inputs: &Arc<Mutex<Vec<Weak<Message>>>>| {
+            let inputs = inputs.lock().unwrap();
+            assert!(!inputs.is_empty());
+            inputs
                 .iter()
-                .map(|entry| (entry.key().to_owned(), entry.value().to_owned()))
-                .collect(),
-            output_message_ids: self.output_ids.as_ref().clone().into_iter().collect(),
+                .filter(|message| message.strong_count() > 0)
+                .count()
+        };
+        for inputs in branch_inputs.iter() {
+            assert_eq!(live(inputs), 0);
         }
+        assert_eq!(live(&join_inputs), 0);
+        assert_eq!(live(&output_inputs), 1);
     }
 }
@@ -2,7 +2,10 @@ extern crate bimap;
 extern crate tokio;
 
 pub use engine::Engine;
-pub use task::{RunOutput, RunnableNode, Task};
+pub use task::{Input, NodeError, NodeImpl, RunOutput, State, Task};
 
+pub mod blocked;
 pub mod engine;
+pub mod snapshot;
 pub mod task;
+pub mod trace;
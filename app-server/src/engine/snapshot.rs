@@ -0,0 +1,407 @@
+//! Snapshots of the state of tasks, to checkpoint runs and resume them on another engine
+//!
+//! The state of a task is the inputs set on its handles and the messages it produced. The engine
+//! sends a snapshot of each task once it finishes, and of each successor it set inputs of. When
+//! paused at a breakpoint, it sends a snapshot of the breakpoint task before its successors get
+//! its output. An engine restored from the latest snapshot of each task continues the run from
+//! where it was paused, or ends it right away if a task finished the run, see `State::Finish`.
+//!
+//! The run store is snapshotted along with the tasks whenever a task wrote to it, before the
+//! task's snapshot, so that a task which is restored as finished had its writes checkpointed.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+use crate::pipeline::{
+    nodes::Message,
+    run_store::{RunStore, RunStoreSnapshot},
+};
+
+use super::task::{State, Task};
+
+/// Input set on a handle of a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputSnapshot {
+    pub message: Message,
+    /// The handle had no message, and the task receives the value of an empty message
+    pub empty: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateSnapshot {
+    pub task_id: Uuid,
+    /// Order of the snapshots of a run, the snapshot with the highest seq of a task is its latest
+    pub seq: u64,
+    /// Inputs set on the task's handles, by handle name
+    pub inputs: HashMap<String, InputSnapshot>,
+    /// Number of executions of the task, the depth of cycles
+    pub executions: usize,
+    /// Messages the task produced, in order of execution
+    pub messages: Vec<Message>,
+    /// The task is scheduled, and waits for its inputs or its turn to run
+    pub scheduled: bool,
+    /// The task finished the run with its latest message
+    #[serde(default)]
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum CheckpointEvent {
+    Snapshot(StateSnapshot),
+    /// The run is paused at the breakpoint task, after the task's snapshot with its output
+    Paused {
+        task_id: Uuid,
+    },
+    /// The run store after writes of tasks, a snapshot with a higher version is later
+    RunStore(RunStoreSnapshot),
+}
+
+impl StateSnapshot {
+    pub(crate) fn of_task(
+        task: &Task,
+        seq: u64,
+        executions: usize,
+        messages: Vec<Message>,
+        scheduled: bool,
+    ) -> Self {
+        let inputs = task
+            .input_states
+            .iter()
+            .filter(|(_, input_state)| input_state.is_completed())
+            .filter_map(|(handle, input_state)| {
+                let input = match input_state.get_state() {
+                    State::Success(message) => InputSnapshot {
+                        message: (*message).clone(),
+                        empty: false,
+                    },
+                    State::Empty(message) => InputSnapshot {
+                        message: (*message).clone(),
+                        empty: true,
+                    },
+                    // a finish isn't passed on to successors
+                    State::Termination | State::Finish(_) => return None,
+                };
+                Some((handle.name().to_string(), input))
+            })
+            .collect();
+
+        Self {
+            task_id: task.id,
+            seq,
+            inputs,
+            executions,
+            messages,
+            scheduled,
+            finished: false,
+        }
+    }
+}
+
+/// Snapshots tasks for the checkpoints of a run
+#[derive(Debug, Clone)]
+pub(crate) struct SnapshotSender {
+    events: UnboundedSender<CheckpointEvent>,
+    seq: Arc<AtomicU64>,
+    run_store: Arc<RunStore>,
+    /// Latest version of the run store which was sent
+    run_store_version: Arc<AtomicU64>,
+}
+
+impl SnapshotSender {
+    pub fn new(
+        events: UnboundedSender<CheckpointEvent>,
+        first_seq: u64,
+        run_store: Arc<RunStore>,
+    ) -> Self {
+        Self {
+            events,
+            seq: Arc::new(AtomicU64::new(first_seq)),
+            run_store_version: Arc::new(AtomicU64::new(run_store.version())),
+            run_store,
+        }
+    }
+
+    /// Send a snapshot of the task, without inputs of resettable handles if `consumed`, i.e. the
+    /// task ran but didn't reset its inputs yet
+    pub fn send_task(
+        &self,
+        task: &Task,
+        executions: usize,
+        messages: Vec<Message>,
+        scheduled: bool,
+        consumed: bool,
+    ) {
+        self.send_run_store();
+        // seq is taken before reading the state, so that a snapshot with a later seq has all
+        // inputs which were set when the earlier one was taken
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let mut snapshot = StateSnapshot::of_task(task, seq, executions, messages, scheduled);
+        if consumed {
+            snapshot.inputs.retain(|handle_name, _| {
+                task.input_state(handle_name)
+                    .map_or(true, |input_state| !input_state.is_resettable())
+            });
+        }
+        // the run continues without checkpoints if they're no longer written
+        let _ = self.events.send(CheckpointEvent::Snapshot(snapshot));
+    }
+
+    /// Send a snapshot of the task which finished the run with its latest message
+    pub fn send_finished(&self, task: &Task, executions: usize, messages: Vec<Message>) {
+        self.send_run_store();
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let mut snapshot = StateSnapshot::of_task(task, seq, executions, messages, false);
+        snapshot.finished = true;
+        let _ = self.events.send(CheckpointEvent::Snapshot(snapshot));
+    }
+
+    /// Send a snapshot of the run store if it was written since the last one
+    fn send_run_store(&self) {
+        let version = self.run_store.version();
+        if self.run_store_version.fetch_max(version, Ordering::SeqCst) < version {
+            let snapshot = self.run_store.snapshot();
+            let _ = self.events.send(CheckpointEvent::RunStore(snapshot));
+        }
+    }
+
+    pub fn send_paused(&self, task_id: Uuid) {
+        let _ = self.events.send(CheckpointEvent::Paused { task_id });
+    }
+}
+
+/// State of an engine restored from snapshots
+#[derive(Debug, Default)]
+pub struct RestoredRun {
+    /// Tasks which were scheduled, and successors of the task the run was paused at
+    pub start_task_ids: Vec<Uuid>,
+    pub depths: HashMap<Uuid, usize>,
+    pub messages: Vec<Message>,
+    /// Ids of messages of output tasks
+    pub output_ids: Vec<Uuid>,
+    /// Id of the message a task finished the run with
+    pub finish_message_id: Option<Uuid>,
+}
+
+/// Set the input states of the tasks from their latest snapshots, and pass the output of the
+/// task the run was paused at to its successors
+pub(crate) fn restore(
+    tasks: &DashMap<Uuid, Arc<Task>>,
+    snapshots: Vec<StateSnapshot>,
+    paused_task_id: Option<Uuid>,
+) -> Result<RestoredRun> {
+    let mut latest = HashMap::<Uuid, StateSnapshot>::new();
+    for snapshot in snapshots {
+        if latest
+            .get(&snapshot.task_id)
+            .map_or(true, |current| current.seq < snapshot.seq)
+        {
+            latest.insert(snapshot.task_id, snapshot);
+        }
+    }
+
+    let mut restored = RestoredRun::default();
+    let mut paused_message = None;
+    for snapshot in latest.into_values() {
+        let Some(task) = tasks.get(&snapshot.task_id).map(|task| task.clone()) else {
+            return Err(anyhow::anyhow!(
+                "Task {} of the checkpoint is not in the graph",
+                snapshot.task_id
+            ));
+        };
+        for (handle_name, input) in snapshot.inputs {
+            let Some(input_state) = task.input_state(&handle_name) else {
+                return Err(anyhow::anyhow!(
+                    "Handle {}.{} of the checkpoint is not an input of its task",
+                    task.id,
+                    handle_name
+                ));
+            };
+            let state = if input.empty {
+                State::Empty(Arc::new(input.message))
+            } else {
+                State::new(input.message)
+            };
+            input_state.set_state(state);
+        }
+
+        if Some(task.id) == paused_task_id {
+            paused_message = snapshot.messages.last().cloned();
+        } else if snapshot.scheduled {
+            restored.start_task_ids.push(task.id);
+        }
+        if snapshot.finished {
+            restored.finish_message_id = snapshot.messages.last().map(|message| message.id);
+        }
+        if task.next.is_empty() {
+            restored
+                .output_ids
+                .extend(snapshot.messages.iter().map(|message| message.id));
+        }
+        restored.depths.insert(task.id, snapshot.executions);
+        restored.messages.extend(snapshot.messages);
+    }
+
+    if let (Some(task_id), Some(message)) = (paused_task_id, paused_message) {
+        let task = tasks.get(&task_id).unwrap().clone();
+        for route in task.routes.iter() {
+            tasks.get(&route.node_id()).unwrap().input_states[route]
+                .set_state(State::new(message.clone()));
+        }
+        for next_task_id in task.next.iter() {
+            if !restored.start_task_ids.contains(next_task_id) {
+                restored.start_task_ids.push(*next_task_id);
+            }
+        }
+    }
+
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::{nodes::NodeInput, utils::parse_graph, Graph};
+
+    use super::*;
+
+    struct Ids {
+        question: Uuid,
+        template: Uuid,
+        answer: Uuid,
+    }
+
+    /// question -> template (breakpoint) -> answer
+    fn graph_tasks() -> (DashMap<Uuid, Arc<Task>>, Ids) {
+        let ids = Ids {
+            question: Uuid::from_u128(1),
+            template: Uuid::from_u128(2),
+            answer: Uuid::from_u128(3),
+        };
+        let (question_output, template_input, template_output, answer_input) = (
+            Uuid::from_u128(11),
+            Uuid::from_u128(12),
+            Uuid::from_u128(13),
+            Uuid::from_u128(14),
+        );
+        let graph = serde_json::json!({
+            "nodes": {
+                "question": {
+                    "type": "Input",
+                    "id": ids.question,
+                    "name": "question",
+                    "outputs": [{"id": question_output, "name": "output", "type": "String"}],
+                    "inputType": "String",
+                },
+                "template": {
+                    "type": "StringTemplate",
+                    "id": ids.template,
+                    "name": "template",
+                    "inputs": [{"id": template_input, "name": "question", "type": "String"}],
+                    "outputs": [{"id": template_output, "name": "output", "type": "String"}],
+                    "inputsMappings": {template_input.to_string(): question_output},
+                    "text": "{{question}}?",
+                },
+                "answer": {
+                    "type": "Output",
+                    "id": ids.answer,
+                    "name": "answer",
+                    "inputs": [{"id": answer_input, "name": "output", "type": "String"}],
+                    "inputsMappings": {answer_input.to_string(): template_output},
+                },
+            },
+            "pred": {
+                ids.template.to_string(): [ids.question],
+                ids.answer.to_string(): [ids.template],
+            },
+        });
+        let graph = serde_json::from_value::<Graph>(graph).unwrap();
+        let tasks = parse_graph(graph)
+            .unwrap()
+            .into_iter()
+            .map(|(id, task)| (id, Arc::new(task)))
+            .collect();
+        (tasks, ids)
+    }
+
+    fn message(task: &Task, value: &str) -> Message {
+        Message {
+            value: NodeInput::String(value.to_string()),
+            node_id: task.id,
+            node_name: task.action.node_name(),
+            node_type: task.action.node_type(),
+            ..Message::empty()
+        }
+    }
+
+    #[test]
+    fn test_paused_run_resumes_on_fresh_tasks() {
+        // the run is paused after the template, as the engine pauses at a breakpoint
+        let (tasks, ids) = graph_tasks();
+        let question = tasks.get(&ids.question).unwrap().clone();
+        let template = tasks.get(&ids.template).unwrap().clone();
+        let question_message = message(&question, "why");
+        let template_message = message(&template, "why?");
+        template
+            .input_state("question")
+            .unwrap()
+            .set_state(State::new(question_message.clone()));
+        let snapshots = vec![
+            StateSnapshot::of_task(&question, 0, 1, vec![question_message.clone()], false),
+            StateSnapshot::of_task(&template, 1, 0, vec![], true),
+            StateSnapshot::of_task(&template, 2, 1, vec![template_message.clone()], false),
+        ];
+        // snapshots are stored as JSON
+        let snapshots =
+            serde_json::from_value::<Vec<StateSnapshot>>(serde_json::to_value(&snapshots).unwrap())
+                .unwrap();
+
+        let (fresh_tasks, _) = graph_tasks();
+        let restored = restore(&fresh_tasks, snapshots, Some(ids.template)).unwrap();
+
+        assert_eq!(restored.start_task_ids, vec![ids.answer]);
+        assert_eq!(restored.depths[&ids.template], 1);
+        assert_eq!(restored.messages.len(), 2);
+        assert!(restored.output_ids.is_empty());
+        let answer = fresh_tasks.get(&ids.answer).unwrap().clone();
+        let input = answer.input_state("output").unwrap();
+        assert!(input.is_completed());
+        assert_eq!(input.get_state().get_out().id, template_message.id);
+        let template_input = fresh_tasks
+            .get(&ids.template)
+            .unwrap()
+            .input_state("question")
+            .unwrap()
+            .clone();
+        assert_eq!(
+            template_input.get_state().get_out().value,
+            question_message.value
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_tasks_not_in_graph() {
+        let (tasks, _) = graph_tasks();
+        let snapshot = StateSnapshot {
+            task_id: Uuid::new_v4(),
+            seq: 0,
+            inputs: HashMap::new(),
+            executions: 0,
+            messages: vec![],
+            scheduled: true,
+            finished: false,
+        };
+        assert!(restore(&tasks, vec![snapshot], None).is_err());
+    }
+}
@@ -1,20 +1,33 @@
+use crate::language_model::ChatMessage;
 use crate::pipeline::{
     context::Context,
-    nodes::{Handle, NodeInput},
+    nodes::{Handle, Message, NodeInput},
     trace::MetaLog,
+    validation::GraphDiagnostic,
 };
-use anyhow::Result;
 use async_trait::async_trait;
-use std::{collections::HashMap, sync::Arc};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use std::{collections::HashMap, fmt, sync::Arc};
 use uuid::Uuid;
 
+use super::{InputHandle, InputTypeError, InputValue};
+
 pub enum RunOutput {
     Success((NodeInput, Option<MetaLog>)),
+    /// Stops the node's branch, its successors don't run
     Termination,
+    /// Ends the whole run with the output, e.g. of a guardrail rejecting the input. Other tasks
+    /// are cancelled, and the output is the run's output, see `State::Finish`.
+    Finish((NodeInput, Option<MetaLog>)),
 }
 
+/// Node of a pipeline graph, run by a task of the engine
+///
+/// Built-in nodes and custom node types registered in the
+/// [`NodeRegistry`](crate::pipeline::nodes::registry::NodeRegistry) implement it alike.
 #[async_trait]
-pub trait RunnableNode {
+pub trait NodeImpl {
     /// Mapping from prev node's output handle id to current node's corresponding input handle
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)>;
 
@@ -26,11 +39,146 @@ pub trait RunnableNode {
 
     fn node_type(&self) -> String;
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput>;
+    /// Problems of the node's config, reported by graph validation. Nodes may compile their
+    /// templates or regexes here, so that runs reuse them.
+    fn validate_config(&self) -> Vec<GraphDiagnostic> {
+        Vec::new()
+    }
+
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError>;
+}
+
+pub type Action = Arc<dyn NodeImpl + Send + Sync>;
+
+/// Messages of the predecessors of a node run, by the input handle they're mapped to
+///
+/// Messages are shared by all successors of a node, so nodes reading the JSON of their inputs
+/// share its parse through the message.
+#[derive(Debug, Clone, Default)]
+pub struct Input {
+    node_id: Uuid,
+    messages: HashMap<InputHandle, Arc<Message>>,
+}
+
+impl Input {
+    /// Input of the node, whose messages are mapped to its input handles
+    pub(crate) fn new(node_id: Uuid, messages: HashMap<InputHandle, Arc<Message>>) -> Self {
+        Self { node_id, messages }
+    }
+
+    pub fn message(&self, handle_name: &str) -> Result<&Arc<Message>, NodeError> {
+        let handle = InputHandle::new(self.node_id, handle_name);
+        self.messages
+            .get(&handle)
+            .ok_or(NodeError::MissingInput(handle))
+    }
+
+    pub fn value(&self, handle_name: &str) -> Result<&NodeInput, NodeError> {
+        self.message(handle_name).map(|message| &message.value)
+    }
+
+    /// Value of the input of a node with a single input handle, whatever its name
+    pub fn single_value(&self) -> Result<&NodeInput, NodeError> {
+        self.single_message().map(|message| &message.value)
+    }
+
+    pub fn single_message(&self) -> Result<&Arc<Message>, NodeError> {
+        self.messages
+            .values()
+            .next()
+            .ok_or_else(|| NodeError::MissingInput(InputHandle::new(self.node_id, "input")))
+    }
+
+    /// Message of the input handle, to read its value as a kind
+    pub fn get(&self, handle_name: &str) -> Result<InputValue<'_>, NodeError> {
+        let handle = InputHandle::new(self.node_id, handle_name);
+        match self.messages.get_key_value(&handle) {
+            Some((handle, message)) => Ok(InputValue::new(handle, message)),
+            None => Err(NodeError::MissingInput(handle)),
+        }
+    }
+
+    /// Message of the input of a node with a single input handle, whatever its name
+    pub fn single(&self) -> Result<InputValue<'_>, NodeError> {
+        match self.messages.iter().next() {
+            Some((handle, message)) => Ok(InputValue::new(handle, message)),
+            None => Err(NodeError::MissingInput(InputHandle::new(
+                self.node_id,
+                "input",
+            ))),
+        }
+    }
+
+    pub fn has(&self, handle_name: &str) -> bool {
+        self.messages
+            .contains_key(&InputHandle::new(self.node_id, handle_name))
+    }
+
+    pub fn get_text(&self, handle_name: &str) -> Result<String, NodeError> {
+        self.get(handle_name)?.text()
+    }
+
+    pub fn get_json<T: DeserializeOwned>(&self, handle_name: &str) -> Result<T, NodeError> {
+        self.get(handle_name)?.json()
+    }
+
+    pub fn get_chat(&self, handle_name: &str) -> Result<Vec<ChatMessage>, NodeError> {
+        self.get(handle_name)?.chat()
+    }
+
+    pub async fn get_bytes(&self, handle_name: &str) -> Result<Bytes, NodeError> {
+        self.get(handle_name)?.bytes().await
+    }
+
+    /// Values by handle name, e.g. to render templates with
+    pub fn values(&self) -> HashMap<String, NodeInput> {
+        self.messages
+            .iter()
+            .map(|(handle, message)| (handle.name().to_string(), message.value.clone()))
+            .collect()
+    }
+
+    pub fn messages(&self) -> &HashMap<InputHandle, Arc<Message>> {
+        &self.messages
+    }
+
+    /// The same messages, as the input of another node, e.g. of a node run on its own
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn of_node(self, node_id: Uuid) -> Self {
+        let messages = self
+            .messages
+            .into_iter()
+            .map(|(handle, message)| (InputHandle::new(node_id, handle.name()), message))
+            .collect();
+        Self { node_id, messages }
+    }
+}
+
+/// Error of a node run, which fails the run with its message
+///
+/// Like `anyhow::Error`, it doesn't implement `std::error::Error`, so that `?` converts any error
+/// into it.
+#[derive(Debug)]
+pub enum NodeError {
+    /// The node has no message at the input handle
+    MissingInput(InputHandle),
+    /// The message at the input handle isn't of the kind the node reads it as
+    InvalidInput(InputTypeError),
+    Failed(anyhow::Error),
 }
 
-pub type Action = Arc<dyn RunnableNode + Send + Sync>;
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingInput(handle) => write!(f, "Input {} is missing", handle),
+            Self::InvalidInput(e) => write!(f, "{}", e),
+            Self::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for NodeError {
+    fn from(e: E) -> Self {
+        Self::Failed(e.into())
+    }
+}
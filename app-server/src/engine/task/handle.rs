@@ -0,0 +1,117 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+use super::Action;
+
+/// Input handle of a node
+///
+/// Created by the graph compiler from the handles the node declares, so that a handle a task is
+/// keyed or routed by always exists on its node. Input handles of a node with the same name are
+/// one handle, whose messages are merged.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InputHandle {
+    node_id: Uuid,
+    name: String,
+}
+
+impl InputHandle {
+    pub(crate) fn new(node_id: Uuid, name: impl Into<String>) -> Self {
+        Self {
+            node_id,
+            name: name.into(),
+        }
+    }
+
+    pub fn node_id(&self) -> Uuid {
+        self.node_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for InputHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.node_id, self.name)
+    }
+}
+
+/// Output handle of a node, whose message is routed to the input handles of its successors
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutputHandle {
+    node_id: Uuid,
+    name: String,
+}
+
+impl OutputHandle {
+    pub(crate) fn new(node_id: Uuid, name: impl Into<String>) -> Self {
+        Self {
+            node_id,
+            name: name.into(),
+        }
+    }
+
+    pub fn node_id(&self) -> Uuid {
+        self.node_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for OutputHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.node_id, self.name)
+    }
+}
+
+/// Handles of a task, resolved when the graph is compiled
+#[derive(Debug, Clone)]
+pub struct TaskHandles {
+    /// Input handles, and whether they are cyclic
+    pub inputs: Vec<(InputHandle, bool)>,
+    pub output: OutputHandle,
+    /// Input handles of the successors the output is routed to. In most cases a successor has
+    /// a single one, but an output can be mapped to several inputs of the same node.
+    pub routes: Vec<InputHandle>,
+}
+
+impl TaskHandles {
+    /// Handles of the action, routed to the inputs of the successors mapped to its output
+    pub(crate) fn resolve<'a>(
+        action: &Action,
+        output_name: &str,
+        successors: impl IntoIterator<Item = &'a Action>,
+    ) -> Self {
+        let inputs = action
+            .handles_mapping()
+            .into_iter()
+            .map(|(_, handle)| {
+                (
+                    InputHandle::new(action.node_id(), handle.name_force()),
+                    handle.is_cyclic,
+                )
+            })
+            .collect();
+
+        let output_handle_id = action.output_handle_id();
+        let mut routes = Vec::new();
+        for successor in successors {
+            for (from, handle) in successor.handles_mapping() {
+                let route = InputHandle::new(successor.node_id(), handle.name_force());
+                if from == output_handle_id && !routes.contains(&route) {
+                    routes.push(route);
+                }
+            }
+        }
+
+        Self {
+            inputs,
+            output: OutputHandle::new(action.node_id(), output_name),
+            routes,
+        }
+    }
+}
@@ -26,13 +26,19 @@
 //! to provide users with the output of the predecessor task.
 use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
-pub use self::action::{Action, RunOutput, RunnableNode};
-pub(crate) use self::state::ExecState;
+pub use self::action::{Action, Input, NodeError, NodeImpl, RunOutput};
+pub use self::handle::{InputHandle, OutputHandle, TaskHandles};
+pub use self::state::ExecState;
 pub use self::state::State;
+pub use self::value::{InputKind, InputTypeError, InputValue};
 use uuid::Uuid;
 
+use crate::pipeline::nodes::post_processors::PostProcessors;
+
 mod action;
+mod handle;
 mod state;
+mod value;
 /// The Task trait
 ///
 /// Tasks can have many attributes, among which `id`, `name`, `predecessor_tasks`, and
@@ -52,37 +58,63 @@ pub struct Task {
     pub prev: Vec<Uuid>,
     /// Task ids of the tasks that must be executed after this task.
     pub next: Vec<Uuid>,
-    /// Map from input handle name to input state.
-    pub input_states: HashMap<String, Arc<ExecState>>,
+    /// Map from input handle to input state.
+    pub input_states: HashMap<InputHandle, Arc<ExecState>>,
+    pub output: OutputHandle,
+    /// Input handles of the next tasks which the output of this task is passed to.
+    pub routes: Vec<InputHandle>,
+    /// Post-processors the output of the task is transformed with before it's passed on.
+    pub post_processors: Option<Arc<PostProcessors>>,
 }
 
 impl Task {
-    pub fn with_action(id: Uuid, action: Action) -> Self {
-        let handles_mapping = action.handles_mapping();
-
-        let mut inputs = HashMap::new();
-        for (_, handle) in handles_mapping.iter() {
-            inputs.insert(
-                handle.name_force(),
-                Arc::new(ExecState::new_with_resettable(handle.is_cyclic)),
-            );
-        }
+    /// Task whose handles are already resolved, e.g. by a compiled graph. Its input states are
+    /// fresh.
+    pub fn with_inputs(
+        id: Uuid,
+        action: Action,
+        handles: &TaskHandles,
+        prev: Vec<Uuid>,
+        next: Vec<Uuid>,
+    ) -> Self {
+        let input_states = handles
+            .inputs
+            .iter()
+            .map(|(handle, is_cyclic)| {
+                (
+                    handle.clone(),
+                    Arc::new(ExecState::new_with_resettable(*is_cyclic)),
+                )
+            })
+            .collect();
 
         Self {
-            id: id,
+            id,
             action,
-            prev: Vec::new(),
-            next: Vec::new(),
-            input_states: inputs,
+            prev,
+            next,
+            input_states,
+            output: handles.output.clone(),
+            routes: handles.routes.clone(),
+            post_processors: None,
         }
     }
 
-    pub fn add_prev(&mut self, id: Uuid) {
-        self.prev.push(id);
+    /// State of the input handle with the name, e.g. of an input recorded or snapshotted by name
+    pub fn input_state(&self, handle_name: &str) -> Option<&Arc<ExecState>> {
+        self.input_states
+            .get(&InputHandle::new(self.id, handle_name))
     }
 
-    pub fn add_next(&mut self, id: Uuid) {
-        self.next.push(id);
+    /// Drop the consumed states of the task's non-cyclic inputs, so that the messages of its
+    /// predecessors are freed once nothing else holds them. `generations` are the generations of
+    /// the states the task read.
+    pub fn release_inputs(&self, generations: &HashMap<InputHandle, u64>) {
+        for (handle, input_state) in self.input_states.iter() {
+            if !input_state.is_resettable() {
+                input_state.reset_consumed(generations[handle]);
+            }
+        }
     }
 }
 
@@ -36,76 +36,122 @@
 //! to implement the logic of the program.
 
 use crate::pipeline::nodes::Message;
+use arc_swap::ArcSwap;
 use core::panic;
-use std::{
-    fmt::Debug,
-    sync::{Arc, Mutex},
-};
-use tokio::sync::Semaphore;
+use std::{fmt::Debug, sync::Arc};
+use tokio::sync::Notify;
 
 /// [`ExeState`] internally stores [`Output`], which represents whether the execution of
-/// the task is successful, and its internal semaphore is used to synchronously obtain
-/// the output of the predecessor task as the input of this task.
+/// the task is successful, and signals its completion to the successor task waiting for it
+/// as its input.
 #[derive(Debug)]
-pub(crate) struct ExecState {
-    /// Output produced by a task.
-    output: Arc<Mutex<State>>,
-    /// The semaphore is used to control the synchronous blocking of subsequent tasks to obtain the
-    /// execution results of this task.
-    /// When a task is successfully executed, the permits inside the semaphore will be increased to
-    /// n (n represents the number of successor tasks of this task or can also be called the output
-    /// of the node), which means that the output of the task is available, and then each successor
-    /// The task will obtain a permits synchronously (the permit will not be returned), which means
-    /// that the subsequent task has obtained the execution result of this task.
-    semaphore: Semaphore,
+pub struct ExecState {
+    /// Output produced by a task, read without locking, so that successors woken at the same time
+    /// don't contend for it.
+    output: ArcSwap<Written>,
+    /// Wakes all tasks waiting for the state when it's completed. Waiters check the completion
+    /// flag of the output, so they don't need to know how many others wait, and a waiter which is
+    /// cancelled doesn't affect the others.
+    completion: Notify,
     /// Exec state output is resettable if the corresponding input handle is cyclic. This is used to
     /// make sure the node in a cyclic flow does not take the input from the previous iteration.
     resettable: bool,
 }
 
+/// State with the number of writes before it, so that a reset of a consumed state doesn't drop a
+/// state of the next loop iteration written in the meantime
+#[derive(Debug)]
+struct Written {
+    state: State,
+    generation: u64,
+    /// The state was set and not reset since
+    completed: bool,
+}
+
 /// Output produced by a task.
 #[derive(Debug, Clone)]
 pub enum State {
     Success(Arc<Message>),
     Empty(Arc<Message>),
     Termination,
+    /// Output of a task which finished the run. It isn't passed on, the engine cancels the
+    /// other tasks and returns it as the output of the run.
+    Finish(Arc<Message>),
 }
 
 impl ExecState {
     /// Construct a new [`ExeState`].
     pub fn new_with_resettable(resettable: bool) -> Self {
         Self {
-            output: Arc::new(Mutex::new(State::empty())),
-            semaphore: Semaphore::new(0),
+            output: ArcSwap::from_pointee(Written {
+                state: State::empty(),
+                generation: 0,
+                completed: false,
+            }),
+            completion: Notify::new(),
             resettable,
         }
     }
 
-    /// After the task is successfully executed, set the execution result.
+    /// Set the output of the predecessor task, and wake the tasks waiting for it.
     pub fn set_state(&self, output: State) {
-        *self.output.lock().unwrap() = output;
+        self.output.rcu(|written| Written {
+            state: output.clone(),
+            generation: written.generation + 1,
+            completed: true,
+        });
+        self.completion.notify_waiters();
+    }
+
+    /// Wait until the state is completed, and return it with its generation, to reset it with
+    /// `reset_consumed` once it's consumed. Cancelling the wait has no effect on the state.
+    pub async fn wait_for_state(&self) -> (State, u64) {
+        loop {
+            let notified = self.completion.notified();
+            tokio::pin!(notified);
+            // registered before the check, so that a completion right after it isn't missed
+            notified.as_mut().enable();
+            if let Some(completed) = self.get_completed_state() {
+                return completed;
+            }
+            notified.await;
+        }
     }
 
     /// [`Output`] for fetching internal storage.
-    /// This function is generally not called directly, but first uses the semaphore for synchronization control.
+    /// This function is generally not called directly, but after waiting for the completion.
     pub fn get_state(&self) -> State {
-        self.output.lock().unwrap().to_owned()
+        self.output.load().state.clone()
     }
 
-    /// A utility function to set the successful output and propagate down the line
-    /// by adding permits.
-    pub fn set_state_and_permits(&self, state: State, n_permits_to_add: usize) {
-        self.set_state(state);
-        self.semaphore().add_permits(n_permits_to_add);
+    /// The state with its generation, if it's completed
+    pub fn get_completed_state(&self) -> Option<(State, u64)> {
+        let written = self.output.load();
+        written
+            .completed
+            .then(|| (written.state.clone(), written.generation))
     }
 
-    /// The semaphore is used to control the synchronous acquisition of task output results.
-    /// Under normal circumstances, first use the semaphore to obtain a permit, and then call
-    /// the `get_output` function to obtain the output. If the current task is not completed
-    /// (no output is generated), the subsequent task will be blocked until the current task
-    /// is completed and output is generated.
-    pub fn semaphore(&self) -> &Semaphore {
-        &self.semaphore
+    pub fn is_completed(&self) -> bool {
+        self.output.load().completed
+    }
+
+    /// Reset a state consumed by its task, so that the task waits for the state to be set again.
+    /// The state is kept if it was written again since the task read `generation`, i.e. the next
+    /// loop iteration already set it.
+    pub fn reset_consumed(&self, generation: u64) {
+        let written = self.output.load();
+        if written.generation == generation {
+            // fails if the state is written between the load and the swap, which keeps it
+            self.output.compare_and_swap(
+                &written,
+                Arc::new(Written {
+                    state: State::empty(),
+                    generation: generation + 1,
+                    completed: false,
+                }),
+            );
+        }
     }
 
     pub fn is_resettable(&self) -> bool {
@@ -130,7 +176,7 @@ impl State {
     pub fn is_success(&self) -> bool {
         match self {
             Self::Success(_) => true,
-            Self::Termination | Self::Empty(_) => false,
+            Self::Termination | Self::Empty(_) | Self::Finish(_) => false,
         }
     }
 
@@ -138,12 +184,84 @@ impl State {
         matches!(self, Self::Termination)
     }
 
+    pub fn is_finish(&self) -> bool {
+        matches!(self, Self::Finish(_))
+    }
+
     /// Get the contents of [`Output`].
     pub fn get_out(&self) -> Arc<Message> {
         match self {
             Self::Success(ref out) => out.clone(),
             Self::Empty(ref out) => out.clone(),
+            Self::Finish(ref out) => out.clone(),
             Self::Termination => panic!("Task is terminated!"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use crate::pipeline::nodes::NodeInput;
+
+    use super::*;
+
+    #[test]
+    fn test_reset_keeps_state_of_next_iteration() {
+        let input = ExecState::new_with_resettable(true);
+        let message = |value: &str| Message {
+            value: NodeInput::String(value.to_string()),
+            ..Message::empty()
+        };
+
+        input.set_state(State::new(message("first")));
+        let (_, generation) = input.get_completed_state().unwrap();
+        input.reset_consumed(generation);
+        assert!(!input.get_state().is_success());
+        assert!(!input.is_completed());
+
+        // the next iteration sets the input before the task resets the consumed one
+        input.set_state(State::new(message("second")));
+        let (_, generation) = input.get_completed_state().unwrap();
+        input.set_state(State::new(message("third")));
+        input.reset_consumed(generation);
+        assert_eq!(
+            input.get_state().get_out().value,
+            NodeInput::String("third".to_string())
+        );
+        assert!(input.is_completed());
+    }
+
+    #[tokio::test]
+    async fn test_completion_wakes_all_waiters() {
+        let input = Arc::new(ExecState::new_with_resettable(true));
+        let state = State::new(Message {
+            value: NodeInput::String("why".to_string()),
+            ..Message::empty()
+        });
+        let wait =
+            |input: Arc<ExecState>| tokio::spawn(async move { input.wait_for_state().await });
+
+        let waiters = (0..3).map(|_| wait(input.clone())).collect::<Vec<_>>();
+        // waits cancelled before the completion, e.g. by aborted tasks, don't affect the others
+        let cancelled = wait(input.clone());
+        cancelled.abort();
+        assert!(input.wait_for_state().now_or_never().is_none());
+
+        input.set_state(state);
+        for waiter in waiters {
+            let (state, generation) = waiter.await.unwrap();
+            assert_eq!(state.get_out().value, NodeInput::String("why".to_string()));
+            assert_eq!(generation, 1);
+        }
+
+        // the next iteration waits for the state to be set again
+        input.reset_consumed(1);
+        let next_iteration = wait(input.clone());
+        tokio::task::yield_now().await;
+        assert!(!next_iteration.is_finished());
+        input.set_state(State::empty());
+        assert_eq!(next_iteration.await.unwrap().1, 3);
+    }
+}
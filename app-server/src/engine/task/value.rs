@@ -0,0 +1,467 @@
+//! Typed reads of the values of a node's input handles
+//!
+//! Nodes read an input as the kind of value they need, and the value is coerced if it sensibly
+//! converts to it:
+//! - text: strings as they are, other values as their text, e.g. numbers or chat messages
+//! - JSON: strings are parsed, other values are read as their JSON, and strings which aren't
+//!   JSON are read as JSON strings, e.g. by nodes expecting a string field
+//! - chat messages: lists of chat messages, or JSON text of one
+//! - bytes: content of files, or the UTF-8 of strings
+//!
+//! Values which don't convert fail the node with an [`InputTypeError`], which names the handle,
+//! the kind expected and the kind found.
+
+use std::{fmt, sync::Arc};
+
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+    language_model::{ChatMessage, ChatMessageContent, ChatMessageContentPart},
+    pipeline::nodes::{Message, NodeInput},
+};
+
+use super::{InputHandle, NodeError};
+
+/// Kind of an input value, as nodes expect it or as it was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Text,
+    Json,
+    Boolean,
+    Number,
+    StringList,
+    Chat,
+    ConditionedValue,
+    File,
+    Bytes,
+}
+
+impl InputKind {
+    pub fn of(value: &NodeInput) -> Self {
+        match value {
+            NodeInput::Boolean(_) => Self::Boolean,
+            NodeInput::String(_) => Self::Text,
+            NodeInput::StringList(_) => Self::StringList,
+            NodeInput::ChatMessageList(_) => Self::Chat,
+            NodeInput::Float(_) => Self::Number,
+            NodeInput::ConditionedValue(_) => Self::ConditionedValue,
+            NodeInput::File(_) => Self::File,
+        }
+    }
+}
+
+impl fmt::Display for InputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Text => "text",
+            Self::Json => "JSON",
+            Self::Boolean => "a boolean",
+            Self::Number => "a number",
+            Self::StringList => "a list of strings",
+            Self::Chat => "chat messages",
+            Self::ConditionedValue => "a conditioned value",
+            Self::File => "a file",
+            Self::Bytes => "bytes",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// Value of an input handle which doesn't convert to the kind the node reads it as
+#[derive(Debug)]
+pub struct InputTypeError {
+    pub handle: InputHandle,
+    pub expected: InputKind,
+    pub found: InputKind,
+    /// Why the value didn't convert, e.g. the deserialization error and the path of its field
+    pub cause: Option<String>,
+}
+
+impl fmt::Display for InputTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Input {}: expected {}, found {}",
+            self.handle, self.expected, self.found
+        )?;
+        if let Some(cause) = &self.cause {
+            write!(f, " ({})", cause)?;
+        }
+        Ok(())
+    }
+}
+
+/// Message of an input handle, to read its value as a kind
+#[derive(Debug, Clone, Copy)]
+pub struct InputValue<'a> {
+    handle: &'a InputHandle,
+    message: &'a Arc<Message>,
+}
+
+impl<'a> InputValue<'a> {
+    pub(super) fn new(handle: &'a InputHandle, message: &'a Arc<Message>) -> Self {
+        Self { handle, message }
+    }
+
+    pub fn handle(&self) -> &'a InputHandle {
+        self.handle
+    }
+
+    pub fn message(&self) -> &'a Arc<Message> {
+        self.message
+    }
+
+    pub fn value(&self) -> &'a NodeInput {
+        &self.message.value
+    }
+
+    pub fn text(&self) -> Result<String, NodeError> {
+        text_of(self.value()).ok_or_else(|| {
+            self.error(
+                InputKind::Text,
+                Some("only the text parts of chat messages are read as text".to_string()),
+            )
+        })
+    }
+
+    /// JSON of the value, parsed once for all successors of the message. Strings which aren't
+    /// JSON fail, unlike with [`Self::json`].
+    pub fn json_value(&self) -> Result<&'a Value, NodeError> {
+        self.message
+            .json()
+            .ok_or_else(|| self.error(InputKind::Json, None))
+    }
+
+    /// Value deserialized from its JSON
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, NodeError> {
+        let as_string = |text: &str| deserialize::<T>(&Value::String(text.to_string()));
+        match (self.message.json(), self.value()) {
+            (Some(json), value) => deserialize(json).or_else(|cause| {
+                // e.g. a string field whose text happens to be a number
+                match value {
+                    NodeInput::String(text) => as_string(text).map_err(|_| cause),
+                    _ => Err(cause),
+                }
+                .map_err(|cause| self.error_found(InputKind::Json, InputKind::Json, Some(cause)))
+            }),
+            (None, NodeInput::String(text)) => {
+                as_string(text).map_err(|cause| self.error(InputKind::Json, Some(cause)))
+            }
+            (None, _) => Err(self.error(InputKind::Json, None)),
+        }
+    }
+
+    pub fn chat(&self) -> Result<Vec<ChatMessage>, NodeError> {
+        let mut value = self.value();
+        while let NodeInput::ConditionedValue(conditioned) = value {
+            value = &conditioned.value;
+        }
+        match value {
+            NodeInput::ChatMessageList(messages) => Ok(messages.clone()),
+            NodeInput::String(_) => match self.message.json() {
+                Some(json) => {
+                    deserialize(json).map_err(|cause| self.error(InputKind::Chat, Some(cause)))
+                }
+                None => Err(self.error(InputKind::Chat, None)),
+            },
+            _ => Err(self.error(InputKind::Chat, None)),
+        }
+    }
+
+    /// Content of the file, or the UTF-8 of the string
+    pub async fn bytes(&self) -> Result<Bytes, NodeError> {
+        match self.value() {
+            NodeInput::File(file) => file.read().await.map_err(|e| {
+                self.error_found(InputKind::Bytes, InputKind::File, Some(e.to_string()))
+            }),
+            NodeInput::String(text) => Ok(Bytes::from(text.clone())),
+            _ => Err(self.error(InputKind::Bytes, None)),
+        }
+    }
+
+    fn error(&self, expected: InputKind, cause: Option<String>) -> NodeError {
+        self.error_found(expected, InputKind::of(self.value()), cause)
+    }
+
+    fn error_found(
+        &self,
+        expected: InputKind,
+        found: InputKind,
+        cause: Option<String>,
+    ) -> NodeError {
+        NodeError::InvalidInput(InputTypeError {
+            handle: self.handle.clone(),
+            expected,
+            found,
+            cause,
+        })
+    }
+}
+
+/// Text of the value, `None` for chat messages with parts other than text, e.g. images
+fn text_of(value: &NodeInput) -> Option<String> {
+    match value {
+        NodeInput::ChatMessageList(messages) => {
+            let text_only = messages.iter().all(|message| match &message.content {
+                ChatMessageContent::Text(_) => true,
+                ChatMessageContent::ContentPartList(parts) => parts
+                    .iter()
+                    .all(|part| matches!(part, ChatMessageContentPart::Text(_))),
+            });
+            text_only.then(|| value.clone().into())
+        }
+        NodeInput::ConditionedValue(conditioned) => text_of(&conditioned.value),
+        value => Some(value.clone().into()),
+    }
+}
+
+/// The value as `T`, or the error with the path of the field which didn't deserialize
+fn deserialize<T: DeserializeOwned>(value: &Value) -> Result<T, String> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        if path == "." {
+            e.into_inner().to_string()
+        } else {
+            format!("at {}: {}", path, e.into_inner())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        files::attachment::FileAttachment,
+        language_model::{ChatMessageImageUrl, ChatMessageText},
+        pipeline::nodes::ConditionedValue,
+    };
+
+    use super::*;
+    use crate::engine::Input;
+
+    fn input(values: Vec<(&str, NodeInput)>) -> Input {
+        let node_id = Uuid::new_v4();
+        let messages = values
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    InputHandle::new(node_id, name),
+                    Arc::new(Message {
+                        value,
+                        ..Message::empty()
+                    }),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        Input::new(node_id, messages)
+    }
+
+    fn type_error(error: NodeError) -> InputTypeError {
+        match error {
+            NodeError::InvalidInput(error) => error,
+            error => panic!("Unexpected error: {}", error),
+        }
+    }
+
+    fn chat(content: ChatMessageContent) -> Vec<ChatMessage> {
+        vec![ChatMessage {
+            role: "user".to_string(),
+            content,
+        }]
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Answer {
+        text: String,
+        sources: Vec<Source>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Source {
+        page: u32,
+    }
+
+    #[test]
+    fn test_text() {
+        let input = input(vec![
+            ("text", NodeInput::String("hello".to_string())),
+            ("number", NodeInput::Float(1.5)),
+            (
+                "list",
+                NodeInput::StringList(vec!["a".to_string(), "b".to_string()]),
+            ),
+            (
+                "chat",
+                NodeInput::ChatMessageList(chat(ChatMessageContent::Text("hi".to_string()))),
+            ),
+            (
+                "condition",
+                NodeInput::ConditionedValue(ConditionedValue {
+                    condition: "yes".to_string(),
+                    value: Box::new(NodeInput::Boolean(true)),
+                }),
+            ),
+            (
+                "image",
+                NodeInput::ChatMessageList(chat(ChatMessageContent::ContentPartList(vec![
+                    ChatMessageContentPart::Text(ChatMessageText {
+                        text: "look".to_string(),
+                    }),
+                    ChatMessageContentPart::ImageUrl(ChatMessageImageUrl {
+                        url: "https://example.com/cat.png".to_string(),
+                        detail: None,
+                    }),
+                ]))),
+            ),
+        ]);
+        assert_eq!(input.get_text("text").unwrap(), "hello");
+        assert_eq!(input.get_text("number").unwrap(), "1.5");
+        assert_eq!(input.get_text("list").unwrap(), "[a, b]");
+        assert_eq!(input.get_text("chat").unwrap(), "user:\nhi");
+        assert_eq!(input.get_text("condition").unwrap(), "true");
+
+        let error = type_error(input.get_text("image").unwrap_err());
+        assert_eq!(error.handle.name(), "image");
+        assert_eq!(
+            (error.expected, error.found),
+            (InputKind::Text, InputKind::Chat)
+        );
+        assert!(matches!(
+            input.get_text("missing"),
+            Err(NodeError::MissingInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_json() {
+        let answer = json!({"text": "42", "sources": [{"page": 1}, {"page": 7}]});
+        let input = input(vec![
+            ("answer", NodeInput::String(answer.to_string())),
+            (
+                "wrong",
+                NodeInput::String(json!({"text": "42", "sources": [{"page": "one"}]}).to_string()),
+            ),
+            ("plain", NodeInput::String("not json".to_string())),
+            ("digits", NodeInput::String("42".to_string())),
+            (
+                "list",
+                NodeInput::StringList(vec!["a".to_string(), "b".to_string()]),
+            ),
+            ("number", NodeInput::Float(2.0)),
+        ]);
+        assert_eq!(
+            input.get_json::<Answer>("answer").unwrap(),
+            Answer {
+                text: "42".to_string(),
+                sources: vec![Source { page: 1 }, Source { page: 7 }],
+            }
+        );
+        // the parse is shared with the other readers of the message
+        let value = input.get("answer").unwrap();
+        assert!(std::ptr::eq(
+            value.json_value().unwrap(),
+            value.message().json().unwrap()
+        ));
+        assert_eq!(input.get_json::<String>("plain").unwrap(), "not json");
+        assert_eq!(input.get_json::<String>("digits").unwrap(), "42");
+        assert_eq!(input.get_json::<u32>("digits").unwrap(), 42);
+        assert_eq!(
+            input.get_json::<Vec<String>>("list").unwrap(),
+            vec!["a".to_string(), "b".to_string()]
+        );
+        assert_eq!(input.get_json::<f64>("number").unwrap(), 2.0);
+
+        let error = type_error(input.get_json::<Answer>("wrong").unwrap_err());
+        assert_eq!(
+            (error.expected, error.found),
+            (InputKind::Json, InputKind::Json)
+        );
+        let cause = error.cause.unwrap();
+        assert!(
+            cause.starts_with("at sources[0].page: invalid type"),
+            "{}",
+            cause
+        );
+
+        let error = type_error(input.get_json::<Answer>("plain").unwrap_err());
+        assert_eq!(
+            (error.expected, error.found),
+            (InputKind::Json, InputKind::Text)
+        );
+        assert!(
+            type_error(input.get("plain").unwrap().json_value().unwrap_err())
+                .cause
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_chat() {
+        let messages = chat(ChatMessageContent::Text("hi".to_string()));
+        let input = input(vec![
+            ("chat", NodeInput::ChatMessageList(messages.clone())),
+            (
+                "json",
+                NodeInput::String(serde_json::to_string(&messages).unwrap()),
+            ),
+            ("plain", NodeInput::String("hi".to_string())),
+            (
+                "object",
+                NodeInput::String(json!([{"role": "user"}]).to_string()),
+            ),
+            ("number", NodeInput::Float(1.0)),
+        ]);
+        assert_eq!(input.get_chat("chat").unwrap(), messages);
+        assert_eq!(input.get_chat("json").unwrap(), messages);
+
+        let error = type_error(input.get_chat("plain").unwrap_err());
+        assert_eq!(
+            (error.expected, error.found),
+            (InputKind::Chat, InputKind::Text)
+        );
+        let error = type_error(input.get_chat("object").unwrap_err());
+        assert!(error.cause.unwrap().contains("missing field `content`"));
+        let error = type_error(input.get_chat("number").unwrap_err());
+        assert_eq!(error.found, InputKind::Number);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Input {}: expected chat messages, found a number",
+                error.handle
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bytes() {
+        let file = FileAttachment::from_bytes(
+            "data.bin".to_string(),
+            "application/octet-stream".to_string(),
+            &[0, 159, 146, 150],
+        )
+        .await
+        .unwrap();
+        let input = input(vec![
+            ("file", NodeInput::File(file)),
+            ("text", NodeInput::String("hi".to_string())),
+            ("flag", NodeInput::Boolean(false)),
+        ]);
+        assert_eq!(
+            input.get_bytes("file").await.unwrap().as_ref(),
+            &[0, 159, 146, 150]
+        );
+        assert_eq!(input.get_bytes("text").await.unwrap().as_ref(), b"hi");
+        let error = type_error(input.get_bytes("flag").await.unwrap_err());
+        assert_eq!(
+            (error.expected, error.found),
+            (InputKind::Bytes, InputKind::Boolean)
+        );
+    }
+}
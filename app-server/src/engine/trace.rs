@@ -0,0 +1,434 @@
+//! Spans and events of the scheduler, to follow how a run was scheduled
+//!
+//! Built with the `engine-trace` feature. Without it the functions of this module do nothing, and
+//! the engine pays for little more than reading the clock around input waits.
+//!
+//! Each run is a `run` span, with a `task` span per execution of a task. The phases of an
+//! execution are its children:
+//! - `queue_wait`, until the task gets a permit of the run's semaphore
+//! - `inputs`, until all input states of the task are completed, with an `input` event per
+//!   handle and how long it was waited for
+//! - `execute`, running the node
+//! - `publish`, setting the input states of the successors, with a `successor` event per
+//!   successor and whether it was scheduled, and an `input_reset` event per resettable input
+//!
+//! Events of the run record the tasks it was scheduled with, permits added on continue and
+//! forgotten at breakpoints, cancellation, and the check of the recursion depth limit.
+//!
+//! The subscriber is configured by `ENGINE_TRACE`, which takes the directives of `RUST_LOG`.
+//! Runs log their id as they start, so that the scheduling decisions of a single run are dumped
+//! with
+//!
+//! ```text
+//! ENGINE_TRACE='engine[run{run_id=0b0f1c5e-...}]=trace' \
+//!     cargo run --features engine-trace -- run --pipeline pipeline.json
+//! ```
+//!
+//! and those of every execution of a node with `engine[task{node_name=summarize}]=trace`.
+
+#![cfg_attr(not(feature = "engine-trace"), allow(unused_variables))]
+
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::task::InputHandle;
+
+pub use spans::*;
+
+/// Env variable of the filter directives of the subscriber
+pub const FILTER_ENV: &str = "ENGINE_TRACE";
+
+#[cfg(feature = "engine-trace")]
+const TARGET: &str = "engine";
+
+/// Install a subscriber printing the spans and events of the engine to stderr, if `ENGINE_TRACE`
+/// is set
+pub fn init_from_env() {
+    #[cfg(feature = "engine-trace")]
+    if let Ok(directives) = std::env::var(FILTER_ENV) {
+        // `log` records are already printed by env_logger, so no `LogTracer` is installed
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new(directives))
+            .with_writer(std::io::stderr)
+            .finish();
+        if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+            log::warn!("Failed to install the engine trace subscriber: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "engine-trace")]
+mod spans {
+    use uuid::Uuid;
+
+    use super::TARGET;
+    use crate::engine::task::Task;
+
+    pub use tracing::{Instrument, Span};
+
+    pub fn run_span(run_id: Uuid, tasks: usize) -> Span {
+        tracing::debug_span!(target: TARGET, "run", %run_id, tasks)
+    }
+
+    /// Span of an execution of the task, `execution` counts from zero
+    pub fn task_span(task: &Task, execution: usize) -> Span {
+        tracing::debug_span!(
+            target: TARGET,
+            "task",
+            task_id = %task.id,
+            node_name = %task.action.node_name(),
+            node_type = %task.action.node_type(),
+            execution,
+        )
+    }
+
+    pub fn queue_wait_span() -> Span {
+        tracing::trace_span!(target: TARGET, "queue_wait")
+    }
+
+    pub fn inputs_span() -> Span {
+        tracing::trace_span!(target: TARGET, "inputs")
+    }
+
+    pub fn execute_span() -> Span {
+        tracing::trace_span!(target: TARGET, "execute")
+    }
+
+    pub fn publish_span() -> Span {
+        tracing::trace_span!(target: TARGET, "publish")
+    }
+}
+
+/// Spans which are never entered, and futures run as they are
+#[cfg(not(feature = "engine-trace"))]
+mod spans {
+    use uuid::Uuid;
+
+    use crate::engine::task::Task;
+
+    #[derive(Debug, Clone, Default)]
+    pub struct Span;
+
+    impl Span {
+        pub fn current() -> Self {
+            Self
+        }
+
+        pub fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+            f()
+        }
+    }
+
+    pub trait Instrument: Sized {
+        fn instrument(self, _span: Span) -> Self {
+            self
+        }
+    }
+
+    impl<F: std::future::Future> Instrument for F {}
+
+    pub fn run_span(_run_id: Uuid, _tasks: usize) -> Span {
+        Span
+    }
+
+    pub fn task_span(_task: &Task, _execution: usize) -> Span {
+        Span
+    }
+
+    pub fn queue_wait_span() -> Span {
+        Span
+    }
+
+    pub fn inputs_span() -> Span {
+        Span
+    }
+
+    pub fn execute_span() -> Span {
+        Span
+    }
+
+    pub fn publish_span() -> Span {
+        Span
+    }
+}
+
+pub fn run_started(start_task_ids: &[Uuid]) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, start_tasks = ?start_task_ids, "run_started");
+}
+
+/// The scheduler received the task. Tasks without successors are awaited, to check whether the
+/// run is finished once they are.
+pub fn task_received(task_id: Uuid, awaited: bool) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, %task_id, awaited, "task_received");
+}
+
+pub fn input_received(handle: &InputHandle, generation: u64, empty: bool, waited: Duration) {
+    #[cfg(feature = "engine-trace")]
+    tracing::trace!(
+        target: TARGET,
+        handle = handle.name(),
+        generation,
+        empty,
+        waited_us = waited.as_micros() as u64,
+        "input",
+    );
+}
+
+/// The task set the input state of its successor, which is scheduled unless it's already
+/// waiting for its inputs
+pub fn successor(next_task_id: Uuid, scheduled: bool) {
+    #[cfg(feature = "engine-trace")]
+    tracing::trace!(target: TARGET, %next_task_id, scheduled, "successor");
+}
+
+/// A resettable input state was reset once the task consumed it
+pub fn input_reset(handle: &InputHandle, generation: u64) {
+    #[cfg(feature = "engine-trace")]
+    tracing::trace!(target: TARGET, handle = handle.name(), generation, "input_reset");
+}
+
+pub fn permits_added(permits: usize) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, permits, "permits_added");
+}
+
+/// Permits were forgotten at a breakpoint, so that no other task starts until the run continues
+pub fn permits_forgotten(permits: usize) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, permits, "permits_forgotten");
+}
+
+pub fn cancelled(active_tasks: usize) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, active_tasks, "cancelled");
+}
+
+pub fn depth_checked(depth: usize, limit: usize) {
+    #[cfg(feature = "engine-trace")]
+    tracing::trace!(
+        target: TARGET,
+        depth,
+        limit,
+        exceeded = depth >= limit,
+        "depth_checked"
+    );
+}
+
+pub fn task_failed(error: &dyn std::fmt::Display) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, %error, "task_failed");
+}
+
+pub fn run_finished(success: bool) {
+    #[cfg(feature = "engine-trace")]
+    tracing::debug!(target: TARGET, success, "run_finished");
+}
+
+#[cfg(all(test, feature = "engine-trace"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        registry::LookupSpan,
+        Layer,
+    };
+
+    use crate::{
+        engine::Engine,
+        testing::{NoopBehavior, NoopGraph, OfflineServices},
+    };
+
+    /// Span or event, with the names and node names of its ancestors from the closest
+    #[derive(Debug, Clone)]
+    struct Recorded {
+        name: String,
+        fields: HashMap<String, String>,
+        ancestors: Vec<(String, Option<String>)>,
+    }
+
+    #[derive(Default)]
+    struct Fields(HashMap<String, String>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        spans: Arc<Mutex<Vec<Recorded>>>,
+        events: Arc<Mutex<Vec<Recorded>>>,
+    }
+
+    fn ancestors<'a, S: LookupSpan<'a>>(
+        scope: impl Iterator<Item = tracing_subscriber::registry::SpanRef<'a, S>>,
+    ) -> Vec<(String, Option<String>)> {
+        scope
+            .map(|span| {
+                let node_name = span
+                    .extensions()
+                    .get::<Fields>()
+                    .and_then(|fields| fields.0.get("node_name").cloned());
+                (span.name().to_string(), node_name)
+            })
+            .collect()
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            let ancestors = span
+                .parent()
+                .map(|parent| ancestors(parent.scope()))
+                .unwrap_or_default();
+            self.spans.lock().unwrap().push(Recorded {
+                name: span.name().to_string(),
+                fields: fields.0.clone(),
+                ancestors,
+            });
+            span.extensions_mut().insert(fields);
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let ancestors = ctx.event_scope(event).map(ancestors).unwrap_or_default();
+            self.events.lock().unwrap().push(Recorded {
+                name: fields.0.get("message").cloned().unwrap_or_default(),
+                fields: fields.0,
+                ancestors,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diamond_graph_spans() {
+        // node0 -> node1, node2 -> node3 -> node4
+        let mut graph = NoopGraph::default();
+        let input = graph.node(NoopBehavior::Forward);
+        let left = graph.node(NoopBehavior::Forward);
+        let right = graph.node(NoopBehavior::Forward);
+        let join = graph.node(NoopBehavior::Forward);
+        let output = graph.node(NoopBehavior::Forward);
+        graph.edge(input, left);
+        graph.edge(input, right);
+        graph.edge(left, join);
+        graph.edge(right, join);
+        graph.edge(join, output);
+
+        let recorder = Recorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+        let services = OfflineServices::default();
+        let mut engine =
+            Engine::with_tasks_and_context(graph.tasks(), services.context(), None, None, None);
+        engine.run(None, None, vec![]).await.unwrap();
+
+        let spans = recorder.spans.lock().unwrap().clone();
+        let runs = spans
+            .iter()
+            .filter(|span| span.name == "run")
+            .collect::<Vec<_>>();
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].ancestors.is_empty());
+        assert_eq!(runs[0].fields["tasks"], "5");
+
+        let mut tasks = spans
+            .iter()
+            .filter(|span| span.name == "task")
+            .map(|span| {
+                assert_eq!(span.ancestors, vec![("run".to_string(), None)]);
+                span.fields["node_name"].clone()
+            })
+            .collect::<Vec<_>>();
+        tasks.sort();
+        assert_eq!(tasks, ["node0", "node1", "node2", "node3", "node4"]);
+
+        // each execution goes through the phases once, under its task span
+        for phase in ["queue_wait", "inputs", "execute", "publish"] {
+            let mut parents = spans
+                .iter()
+                .filter(|span| span.name == phase)
+                .map(|span| {
+                    assert_eq!(span.ancestors[0].0, "task");
+                    assert_eq!(span.ancestors[1].0, "run");
+                    span.ancestors[0].1.clone().unwrap()
+                })
+                .collect::<Vec<_>>();
+            parents.sort();
+            assert_eq!(parents, tasks, "phase {}", phase);
+        }
+
+        let events = recorder.events.lock().unwrap().clone();
+        let of_node = |name: &str, node_name: &str| {
+            events
+                .iter()
+                .filter(|event| {
+                    event.name == name
+                        && event
+                            .ancestors
+                            .iter()
+                            .any(|(_, node)| node.as_deref() == Some(node_name))
+                })
+                .collect::<Vec<_>>()
+        };
+        // inputs are received under the `inputs` span, one event per handle
+        let join_inputs = of_node("input", "node3");
+        assert_eq!(join_inputs.len(), 2);
+        assert!(join_inputs
+            .iter()
+            .all(|event| event.ancestors[0].0 == "inputs"));
+        assert!(of_node("input", "node0").is_empty());
+
+        // the join is scheduled by the first of its predecessors to publish only
+        let successors = ["node1", "node2"]
+            .iter()
+            .flat_map(|node_name| of_node("successor", node_name))
+            .collect::<Vec<_>>();
+        assert_eq!(successors.len(), 2);
+        assert!(successors
+            .iter()
+            .all(|event| event.ancestors[0].0 == "publish"));
+        assert_eq!(
+            successors
+                .iter()
+                .filter(|event| event.fields["scheduled"] == "true")
+                .count(),
+            1
+        );
+
+        let depth_checks = events
+            .iter()
+            .filter(|event| event.name == "depth_checked")
+            .count();
+        assert_eq!(depth_checks, 5);
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| event.name == "run_finished")
+                .map(|event| event.fields["success"].as_str())
+                .collect::<Vec<_>>(),
+            ["true"]
+        );
+    }
+}
@@ -0,0 +1,588 @@
+//! Backfills, scoring persisted runs of a pipeline with evaluators, see `run_backfill`
+//!
+//! A backfill scores the succeeded runs of a pipeline created in a time range, e.g. last week's
+//! production traffic with a newly added judge. The pipeline isn't run again: the scored output
+//! and the target are taken from the node I/O recorded for each run, see `runs::node_io`. Runs
+//! whose content was purged by retention, which weren't recorded, or whose recorded values don't
+//! have what's scored, are skipped with the reason.
+//!
+//! Runs are scored in batches in order of creation time and id, and the backfill's counts and
+//! the last scored run are updated after each batch, for `GET evaluation-backfills/{id}`. Judge
+//! runs are throttled per provider of their models to `EVALUATION_BACKFILL_JUDGE_RUNS_PER_MINUTE`
+//! across the backfills of the instance, so that a backfill doesn't use up the provider limits
+//! which live runs need.
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    auth::rate_limit::ApiKeyRateLimiter,
+    db::{
+        self,
+        api_keys::ProjectApiKey,
+        evaluation_backfills::{BackfillRun, EvaluationBackfill},
+        pipelines::PipelineVersion,
+        DB,
+    },
+    language_model::providers::utils::get_provider,
+    pipeline::{model_defaults, runner::PipelineRunner},
+    runs::node_io::NodeIoRecord,
+};
+
+use super::{
+    evaluation_concurrency,
+    evaluators::{EvaluatorConfig, JudgeContext},
+    load_judges,
+};
+
+const DEFAULT_BATCH_SIZE: i64 = 100;
+const DEFAULT_JUDGE_RUNS_PER_MINUTE: u32 = 60;
+/// Throttle key of judges whose provider can't be told from their graph
+const UNKNOWN_PROVIDER: &str = "unknown";
+
+lazy_static::lazy_static! {
+    static ref JUDGE_THROTTLE: JudgeThrottle = JudgeThrottle::from_env();
+}
+
+/// Which runs a backfill scores, and how
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackfillConfig {
+    /// Name of the pipeline whose runs are scored
+    pub pipeline: String,
+    /// Runs created from this time on are scored
+    pub start_time: DateTime<Utc>,
+    /// Runs created before this time are scored
+    pub end_time: DateTime<Utc>,
+    /// Output node whose recorded value is scored, can be omitted if the runs have a single output
+    #[serde(default)]
+    pub output_node: Option<String>,
+    /// Graph input compared against the output, the object of all inputs by name if not set
+    #[serde(default)]
+    pub target_input: Option<String>,
+    #[schema(inline)]
+    pub evaluators: Vec<EvaluatorConfig>,
+}
+
+/// Why a run wasn't scored
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+    /// Content of the run's trace was purged by retention, with its node I/O
+    ContentPurged,
+    /// No node I/O was recorded for the run, or it has expired
+    NotRecorded,
+    /// Scored values were dropped from the records to fit their size cap
+    Truncated,
+    /// The run has no record of the output node, or has several outputs and none is configured
+    OutputMissing,
+    /// The run has no record of the target input
+    InputMissing,
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::ContentPurged => "contentPurged",
+            SkipReason::NotRecorded => "notRecorded",
+            SkipReason::Truncated => "truncated",
+            SkipReason::OutputMissing => "outputMissing",
+            SkipReason::InputMissing => "inputMissing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunScoreStatus {
+    Scored,
+    Skipped,
+    /// The records couldn't be read, or an evaluator failed
+    Failed,
+}
+
+impl RunScoreStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunScoreStatus::Scored => "scored",
+            RunScoreStatus::Skipped => "skipped",
+            RunScoreStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Result of a run of the backfill, as written to `evaluation_backfill_scores`
+pub struct RunScore {
+    pub run_id: Uuid,
+    pub status: RunScoreStatus,
+    pub scores: HashMap<String, f64>,
+    /// Whether all evaluators passed, None for skipped runs
+    pub passed: Option<bool>,
+    pub skip_reason: Option<SkipReason>,
+    pub error: Option<String>,
+    pub evaluator_trace_id: Option<Uuid>,
+}
+
+impl RunScore {
+    fn skipped(run_id: Uuid, reason: SkipReason) -> Self {
+        Self {
+            run_id,
+            status: RunScoreStatus::Skipped,
+            scores: HashMap::new(),
+            passed: None,
+            skip_reason: Some(reason),
+            error: None,
+            evaluator_trace_id: None,
+        }
+    }
+
+    fn failed(run_id: Uuid, error: String) -> Self {
+        Self {
+            run_id,
+            status: RunScoreStatus::Failed,
+            scores: HashMap::new(),
+            passed: Some(false),
+            skip_reason: None,
+            error: Some(error),
+            evaluator_trace_id: None,
+        }
+    }
+}
+
+/// Limits the judge runs started per provider in each minute
+pub struct JudgeThrottle {
+    runs_per_minute: u32,
+    /// Minute since the epoch and runs started in it, by provider
+    windows: Mutex<HashMap<String, (i64, u32)>>,
+}
+
+impl JudgeThrottle {
+    pub fn new(runs_per_minute: u32) -> Self {
+        Self {
+            runs_per_minute,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn from_env() -> Self {
+        let runs_per_minute = env::var("EVALUATION_BACKFILL_JUDGE_RUNS_PER_MINUTE")
+            .ok()
+            .and_then(|runs| runs.parse::<u32>().ok())
+            .filter(|runs| *runs > 0)
+            .unwrap_or(DEFAULT_JUDGE_RUNS_PER_MINUTE);
+        Self::new(runs_per_minute)
+    }
+
+    /// Wait until a run of the judge can be started with each of its providers
+    pub async fn acquire(&self, judge: &PipelineVersion) {
+        for provider in judge_providers(&judge.runnable_graph) {
+            while let Err(wait) = self.try_acquire(&provider, Utc::now()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    /// Count a run of the provider in the minute of `now`, or the time until the next minute if
+    /// the provider has no runs left in it
+    fn try_acquire(&self, provider: &str, now: DateTime<Utc>) -> Result<(), Duration> {
+        let minute = now.timestamp().div_euclid(60);
+        let mut windows = self.windows.lock().unwrap();
+        let (window, runs) = windows.entry(provider.to_string()).or_insert((minute, 0));
+        if *window != minute {
+            *window = minute;
+            *runs = 0;
+        }
+        if *runs < self.runs_per_minute {
+            *runs += 1;
+            return Ok(());
+        }
+        let next_minute = (minute + 1) * 60 * 1000;
+        let wait = (next_minute - now.timestamp_millis()).max(1) as u64;
+        Err(Duration::from_millis(wait))
+    }
+}
+
+/// Providers of the models of the graph's LLM nodes, of the graph's or the server's default
+/// model for nodes without one
+fn judge_providers(runnable_graph: &Value) -> Vec<String> {
+    let default_model = runnable_graph
+        .pointer("/modelDefaults/model")
+        .and_then(Value::as_str)
+        .or(model_defaults::server_defaults().model.as_deref());
+    let mut providers = runnable_graph
+        .get("nodes")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|nodes| nodes.values())
+        .filter(|node| node.get("type").and_then(Value::as_str) == Some("LLM"))
+        .map(|node| {
+            node.get("model")
+                .and_then(Value::as_str)
+                .filter(|model| !model.is_empty())
+                .or(default_model)
+                .and_then(get_provider)
+                .unwrap_or(UNKNOWN_PROVIDER)
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    providers.sort();
+    providers.dedup();
+    providers
+}
+
+/// Everything runs of a backfill are scored with
+pub struct BackfillContext {
+    pub db: Arc<DB>,
+    pub pipeline_runner: Arc<PipelineRunner>,
+    pub rate_limiter: Arc<ApiKeyRateLimiter>,
+    pub project_api_key: ProjectApiKey,
+    pub config: BackfillConfig,
+    /// Versions of the judge pipelines, by id
+    pub judges: HashMap<Uuid, PipelineVersion>,
+    pub env: HashMap<String, String>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl BackfillContext {
+    pub async fn new(
+        db: Arc<DB>,
+        pipeline_runner: Arc<PipelineRunner>,
+        rate_limiter: Arc<ApiKeyRateLimiter>,
+        project_api_key: ProjectApiKey,
+        backfill_id: Uuid,
+        config: BackfillConfig,
+        mut env: HashMap<String, String>,
+    ) -> Result<Self> {
+        let judges = load_judges(&db, &config.evaluators).await?;
+        env.insert(
+            "collection_name".to_string(),
+            project_api_key.project_id.to_string(),
+        );
+        let metadata = HashMap::from([(
+            "evaluation_backfill_id".to_string(),
+            backfill_id.to_string(),
+        )]);
+
+        Ok(Self {
+            db,
+            pipeline_runner,
+            rate_limiter,
+            project_api_key,
+            config,
+            judges,
+            env,
+            metadata,
+        })
+    }
+
+    fn judge_context(&self) -> JudgeContext<'_> {
+        JudgeContext {
+            db: &self.db,
+            pipeline_runner: &self.pipeline_runner,
+            rate_limiter: &self.rate_limiter,
+            project_api_key: &self.project_api_key,
+            judges: &self.judges,
+            env: &self.env,
+            metadata: &self.metadata,
+            throttle: Some(&JUDGE_THROTTLE),
+        }
+    }
+}
+
+fn batch_size() -> i64 {
+    env::var("EVALUATION_BACKFILL_BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+/// Score the runs of the backfill to completion or failure, recording the failure on the
+/// backfill
+pub async fn run_backfill(context: BackfillContext, backfill: EvaluationBackfill) {
+    let backfill_id = backfill.id;
+    let db = context.db.clone();
+    match score_runs(&context, backfill).await {
+        Ok(()) => log::info!("Evaluation backfill {} finished", backfill_id),
+        Err(e) => {
+            log::error!("Evaluation backfill {} failed: {}", backfill_id, e);
+            if let Err(e) =
+                db::evaluation_backfills::fail_backfill(&db.pool, &backfill_id, &e.to_string())
+                    .await
+            {
+                log::error!(
+                    "Failed to record failure of evaluation backfill {}: {}",
+                    backfill_id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn score_runs(context: &BackfillContext, mut backfill: EvaluationBackfill) -> Result<()> {
+    let batch_size = batch_size();
+    loop {
+        let runs =
+            db::evaluation_backfills::get_backfill_runs(&context.db.pool, &backfill, batch_size)
+                .await?;
+        let Some(last) = runs.last() else {
+            break;
+        };
+        backfill.cursor_created_at = Some(last.created_at);
+        backfill.cursor_run_id = Some(last.id);
+
+        // Collected first, as in `evaluations::evaluate_rows`
+        let scores = runs
+            .iter()
+            .map(|run| score_run(context, run))
+            .collect::<Vec<_>>();
+        let scores = stream::iter(scores)
+            .buffered(evaluation_concurrency())
+            .collect::<Vec<_>>()
+            .await;
+        db::evaluation_backfills::insert_run_scores(&context.db.pool, &backfill.id, &scores)
+            .await?;
+        for score in &scores {
+            match score.status {
+                RunScoreStatus::Scored => backfill.scored_runs += 1,
+                RunScoreStatus::Skipped => backfill.skipped_runs += 1,
+                RunScoreStatus::Failed => backfill.failed_runs += 1,
+            }
+            if let Some(reason) = score.skip_reason {
+                *backfill
+                    .skip_reasons
+                    .entry(reason.as_str().to_string())
+                    .or_default() += 1;
+            }
+        }
+        if !db::evaluation_backfills::advance_backfill(&context.db.pool, &backfill).await? {
+            log::info!("Evaluation backfill {} was stopped", backfill.id);
+            return Ok(());
+        }
+        if (runs.len() as i64) < batch_size {
+            break;
+        }
+    }
+
+    db::evaluation_backfills::finish_backfill(&context.db.pool, &backfill.id).await
+}
+
+async fn score_run(context: &BackfillContext, run: &BackfillRun) -> RunScore {
+    if run.content_purged {
+        return RunScore::skipped(run.id, SkipReason::ContentPurged);
+    }
+    let records = match context
+        .pipeline_runner
+        .node_io_store()
+        .get_run(&run.id)
+        .await
+    {
+        Ok(records) => records,
+        Err(e) => return RunScore::failed(run.id, format!("Failed to read node I/O: {}", e)),
+    };
+    let (output, target) = match recorded_values(&context.config, &records) {
+        Ok(values) => values,
+        Err(reason) => return RunScore::skipped(run.id, reason),
+    };
+
+    let judge_context = context.judge_context();
+    let mut result = RunScore {
+        run_id: run.id,
+        status: RunScoreStatus::Scored,
+        scores: HashMap::new(),
+        passed: Some(true),
+        skip_reason: None,
+        error: None,
+        evaluator_trace_id: None,
+    };
+    let mut errors = Vec::new();
+    for evaluator in &context.config.evaluators {
+        let name = evaluator.name();
+        match evaluator.score(&output, &target, &judge_context).await {
+            Ok(score) => {
+                result.passed = Some(result.passed == Some(true) && evaluator.passes(score.value));
+                result.scores.insert(name, score.value);
+                result.evaluator_trace_id = result.evaluator_trace_id.or(score.trace_id);
+            }
+            Err(e) => errors.push(format!("Evaluator '{}' failed: {}", name, e)),
+        }
+    }
+    if !errors.is_empty() {
+        result.status = RunScoreStatus::Failed;
+        result.passed = Some(false);
+        result.error = Some(errors.join("\n"));
+    }
+    result
+}
+
+/// Scored output and target of a run from its node I/O records, or why the run is skipped
+///
+/// The output is the value of the last execution of the output node, and the inputs are the
+/// values of the input nodes, in the same form as outputs and inputs of evaluation runs.
+fn recorded_values(
+    config: &BackfillConfig,
+    records: &[NodeIoRecord],
+) -> Result<(Value, Value), SkipReason> {
+    if records.is_empty() {
+        return Err(SkipReason::NotRecorded);
+    }
+    let mut outputs = HashMap::<&str, &NodeIoRecord>::new();
+    for record in records.iter().filter(|record| record.node_type == "Output") {
+        let output = outputs.entry(record.node_name.as_str()).or_insert(record);
+        if record.execution > output.execution {
+            *output = record;
+        }
+    }
+    let output = match &config.output_node {
+        Some(node) => outputs.get(node.as_str()),
+        None if outputs.len() == 1 => outputs.values().next(),
+        None => None,
+    }
+    .ok_or(SkipReason::OutputMissing)?;
+    let output = output.output.value.clone().ok_or(SkipReason::Truncated)?;
+
+    let mut inputs = serde_json::Map::new();
+    for record in records.iter().filter(|record| record.node_type == "Input") {
+        let value = record.output.value.clone().ok_or(SkipReason::Truncated)?;
+        inputs.insert(record.node_name.clone(), value);
+    }
+    let target = match &config.target_input {
+        Some(input) => inputs.remove(input).ok_or(SkipReason::InputMissing)?,
+        None => Value::Object(inputs),
+    };
+    Ok((output, target))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use crate::runs::node_io::RecordedOutput;
+
+    use super::*;
+
+    fn record(
+        node_name: &str,
+        node_type: &str,
+        execution: i32,
+        value: Option<Value>,
+    ) -> NodeIoRecord {
+        NodeIoRecord {
+            run_id: Uuid::nil(),
+            node_id: Uuid::new_v4(),
+            node_name: node_name.to_string(),
+            node_type: node_type.to_string(),
+            execution,
+            inputs: HashMap::new(),
+            output: RecordedOutput {
+                message_id: Uuid::new_v4(),
+                value,
+                scrubbed_secrets: Vec::new(),
+                meta_log: None,
+                start_time: Utc::now(),
+                end_time: Utc::now(),
+            },
+        }
+    }
+
+    fn config(output_node: Option<&str>, target_input: Option<&str>) -> BackfillConfig {
+        serde_json::from_value(json!({
+            "pipeline": "pipeline",
+            "startTime": "2024-01-01T00:00:00Z",
+            "endTime": "2024-01-08T00:00:00Z",
+            "outputNode": output_node,
+            "targetInput": target_input,
+            "evaluators": [{"type": "exactMatch"}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_recorded_values() {
+        let records = vec![
+            record("question", "Input", 0, Some(json!("2 + 2?"))),
+            record("answer", "Output", 0, Some(json!("3"))),
+            record("answer", "Output", 1, Some(json!("4"))),
+        ];
+        assert_eq!(
+            recorded_values(&config(None, None), &records),
+            Ok((json!("4"), json!({"question": "2 + 2?"})))
+        );
+        assert_eq!(
+            recorded_values(&config(Some("answer"), Some("question")), &records),
+            Ok((json!("4"), json!("2 + 2?")))
+        );
+        assert_eq!(
+            recorded_values(&config(Some("other"), None), &records),
+            Err(SkipReason::OutputMissing)
+        );
+        assert_eq!(
+            recorded_values(&config(None, Some("context")), &records),
+            Err(SkipReason::InputMissing)
+        );
+        assert_eq!(
+            recorded_values(&config(None, None), &[]),
+            Err(SkipReason::NotRecorded)
+        );
+
+        let truncated = vec![
+            record("question", "Input", 0, None),
+            record("answer", "Output", 0, Some(json!("4"))),
+        ];
+        assert_eq!(
+            recorded_values(&config(None, None), &truncated),
+            Err(SkipReason::Truncated)
+        );
+        let several_outputs = vec![
+            record("answer", "Output", 0, Some(json!("4"))),
+            record("reasoning", "Output", 0, Some(json!("..."))),
+        ];
+        assert_eq!(
+            recorded_values(&config(None, None), &several_outputs),
+            Err(SkipReason::OutputMissing)
+        );
+    }
+
+    #[test]
+    fn test_throttle_waits_for_next_minute() {
+        let throttle = JudgeThrottle::new(2);
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 45).unwrap();
+        assert!(throttle.try_acquire("openai", now).is_ok());
+        assert!(throttle.try_acquire("openai", now).is_ok());
+        assert_eq!(
+            throttle.try_acquire("openai", now),
+            Err(Duration::from_secs(15))
+        );
+        // providers are throttled separately
+        assert!(throttle.try_acquire("anthropic", now).is_ok());
+
+        let next_minute = now + chrono::Duration::seconds(15);
+        assert!(throttle.try_acquire("openai", next_minute).is_ok());
+    }
+
+    #[test]
+    fn test_judge_providers() {
+        let graph = json!({
+            "nodes": {
+                "a": {"type": "LLM", "model": "openai:gpt-4o-mini"},
+                "b": {"type": "LLM", "model": "anthropic:claude-3-haiku"},
+                "c": {"type": "LLM"},
+                "d": {"type": "Input"},
+            },
+            "modelDefaults": {"model": "openai:gpt-4o"},
+        });
+        assert_eq!(judge_providers(&graph), vec!["anthropic", "openai"]);
+    }
+}
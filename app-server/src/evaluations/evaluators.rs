@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    auth::rate_limit::ApiKeyRateLimiter,
+    db::{api_keys::ProjectApiKey, pipelines::PipelineVersion, DB},
+    pipeline::{nodes::NodeInput, runner::PipelineRunner},
+    runs::{record_token_usage, setup_graph},
+};
+
+use super::backfill::JudgeThrottle;
+
+pub const DEFAULT_THRESHOLD: f64 = 1.0;
+const DEFAULT_JUDGE_THRESHOLD: f64 = 0.5;
+
+/// Scores the output of a row from 0 to 1, against the target of the row
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Evaluator {
+    /// 1 if the output is equal to the target, ignoring surrounding whitespace of strings
+    ExactMatch,
+    /// Share of the target's JSON values which are in the output at the same paths, strings are
+    /// parsed as JSON
+    JsonMatch,
+    /// 1 if the output matches the pattern
+    Regex { pattern: String },
+    /// 1 if the output is a number within the tolerance from the target
+    NumericTolerance { tolerance: f64 },
+    /// Score output by a judge pipeline, run with `output` and `target` string inputs
+    ///
+    /// The judge's version is resolved from the pipeline name and version when the evaluation
+    /// is created, so that runs of the evaluation are scored by the same version.
+    LlmJudge {
+        pipeline: String,
+        #[serde(default, rename = "pipelineVersion")]
+        pipeline_version: Option<String>,
+        #[serde(default, rename = "pipelineVersionId")]
+        pipeline_version_id: Option<Uuid>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluatorConfig {
+    /// Key of the evaluator's scores, the evaluator type by default
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Minimum score for the evaluator to pass, 1 by default, 0.5 for `llmJudge`
+    #[serde(default)]
+    pub threshold: Option<f64>,
+    #[serde(flatten)]
+    pub evaluator: Evaluator,
+}
+
+/// Everything judge pipelines are run with
+pub struct JudgeContext<'a> {
+    pub db: &'a DB,
+    pub pipeline_runner: &'a PipelineRunner,
+    pub rate_limiter: &'a ApiKeyRateLimiter,
+    pub project_api_key: &'a ProjectApiKey,
+    /// Versions of the judge pipelines, by id
+    pub judges: &'a HashMap<Uuid, PipelineVersion>,
+    pub env: &'a HashMap<String, String>,
+    pub metadata: &'a HashMap<String, String>,
+    /// Limits the judge runs started per provider, None to start them right away
+    pub throttle: Option<&'a JudgeThrottle>,
+}
+
+/// Score of an evaluator, with the trace of the judge pipeline if it was run
+pub struct Score {
+    pub value: f64,
+    pub trace_id: Option<Uuid>,
+}
+
+impl Score {
+    fn of(value: f64) -> Self {
+        Self {
+            value,
+            trace_id: None,
+        }
+    }
+
+    fn of_bool(passed: bool) -> Self {
+        Self::of(if passed { 1.0 } else { 0.0 })
+    }
+}
+
+impl EvaluatorConfig {
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let name = match self.evaluator {
+            Evaluator::ExactMatch => "exactMatch",
+            Evaluator::JsonMatch => "jsonMatch",
+            Evaluator::Regex { .. } => "regex",
+            Evaluator::NumericTolerance { .. } => "numericTolerance",
+            Evaluator::LlmJudge { .. } => "llmJudge",
+        };
+        name.to_string()
+    }
+
+    /// Score at which the row passes
+    pub fn threshold(&self) -> f64 {
+        self.threshold.unwrap_or(match self.evaluator {
+            Evaluator::LlmJudge { .. } => DEFAULT_JUDGE_THRESHOLD,
+            _ => DEFAULT_THRESHOLD,
+        })
+    }
+
+    pub fn passes(&self, score: f64) -> bool {
+        score >= self.threshold()
+    }
+
+    /// Fails for invalid patterns, so that they're reported when the evaluation is created
+    pub fn validate(&self) -> Result<()> {
+        if let Evaluator::Regex { pattern } = &self.evaluator {
+            Regex::new(pattern)?;
+        }
+        Ok(())
+    }
+
+    pub async fn score(
+        &self,
+        output: &Value,
+        target: &Value,
+        context: &JudgeContext<'_>,
+    ) -> Result<Score> {
+        let score = match &self.evaluator {
+            Evaluator::ExactMatch => {
+                Score::of_bool(as_text(output).trim() == as_text(target).trim())
+            }
+            Evaluator::JsonMatch => Score::of(json_match(&parse_json(target), &parse_json(output))),
+            Evaluator::Regex { pattern } => {
+                Score::of_bool(Regex::new(pattern)?.is_match(&as_text(output)))
+            }
+            Evaluator::NumericTolerance { tolerance } => {
+                let matches = match (as_number(output), as_number(target)) {
+                    (Some(output), Some(target)) => (output - target).abs() <= *tolerance,
+                    _ => false,
+                };
+                Score::of_bool(matches)
+            }
+            Evaluator::LlmJudge {
+                pipeline_version_id,
+                ..
+            } => {
+                let pipeline_version_id = pipeline_version_id
+                    .ok_or_else(|| anyhow::anyhow!("Judge pipeline version is not resolved"))?;
+                judge(output, target, pipeline_version_id, context).await?
+            }
+        };
+        Ok(score)
+    }
+}
+
+/// Run the judge pipeline, its output is the score
+async fn judge(
+    output: &Value,
+    target: &Value,
+    pipeline_version_id: Uuid,
+    context: &JudgeContext<'_>,
+) -> Result<Score> {
+    let pipeline_version = context.judges.get(&pipeline_version_id).ok_or_else(|| {
+        anyhow::anyhow!("Judge pipeline version {} not found", pipeline_version_id)
+    })?;
+    if let Some(throttle) = context.throttle {
+        throttle.acquire(pipeline_version).await;
+    }
+    let inputs = HashMap::from([
+        ("output".to_string(), NodeInput::String(as_text(output))),
+        ("target".to_string(), NodeInput::String(as_text(target))),
+    ]);
+    let graph = setup_graph(
+        context.pipeline_runner,
+        context.db,
+        pipeline_version,
+        &inputs,
+        context.env,
+        context.metadata,
+        &context.project_api_key.project_id,
+    )
+    .await?;
+    let secrets = graph.secrets.clone();
+
+    let trace_id = Uuid::new_v4();
+    let run_result = context.pipeline_runner.run(graph, None).await;
+    record_token_usage(
+        context.db,
+        context.rate_limiter,
+        context.project_api_key,
+        &run_result,
+    )
+    .await;
+    if let Err(e) = context
+        .pipeline_runner
+        .record_observations(
+            &run_result,
+            &context.project_api_key.project_id,
+            Some(context.project_api_key.workspace_id),
+            pipeline_version,
+            None,
+            Some(trace_id),
+            None,
+            &secrets,
+        )
+        .await
+    {
+        log::error!("Failed to record observations of judge run: {}", e);
+    }
+
+    let outputs = run_result?.output_values();
+    let [(_, output)] = <[_; 1]>::try_from(outputs.into_iter().collect::<Vec<_>>())
+        .map_err(|_| anyhow::anyhow!("Judge pipeline must have a single output"))?;
+    let value = serde_json::to_value(output)?;
+    let score = as_number(&value)
+        .ok_or_else(|| anyhow::anyhow!("Judge output '{}' is not a number", as_text(&value)))?;
+
+    Ok(Score {
+        value: score.clamp(0.0, 1.0),
+        trace_id: Some(trace_id),
+    })
+}
+
+/// Strings as they are, other values as JSON
+pub fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse_json(value: &Value) -> Value {
+    match value {
+        Value::String(s) => serde_json::from_str(s).unwrap_or_else(|_| value.clone()),
+        _ => value.clone(),
+    }
+}
+
+/// Share of the expected value's leaves which are equal in the actual value
+fn json_match(expected: &Value, actual: &Value) -> f64 {
+    let (matched, total) = count_matching_leaves(expected, actual);
+    if total == 0 {
+        return 1.0;
+    }
+    matched as f64 / total as f64
+}
+
+fn count_matching_leaves(expected: &Value, actual: &Value) -> (usize, usize) {
+    match expected {
+        Value::Object(fields) if !fields.is_empty() => fields
+            .iter()
+            .map(|(key, value)| {
+                count_matching_leaves(value, actual.get(key).unwrap_or(&Value::Null))
+            })
+            .fold((0, 0), |(m, t), (matched, total)| (m + matched, t + total)),
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .enumerate()
+            .map(|(i, value)| count_matching_leaves(value, actual.get(i).unwrap_or(&Value::Null)))
+            .fold((0, 0), |(m, t), (matched, total)| (m + matched, t + total)),
+        _ => ((expected == actual) as usize, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_json_match() {
+        let expected = json!({"name": "a", "tags": ["x", "y"], "meta": {"n": 1}});
+        assert_eq!(json_match(&expected, &expected), 1.0);
+        assert_eq!(
+            json_match(
+                &expected,
+                &json!({"name": "a", "tags": ["x"], "meta": {"n": 2}})
+            ),
+            0.5
+        );
+        assert_eq!(json_match(&expected, &json!("not an object")), 0.0);
+        assert_eq!(
+            json_match(
+                &json!({"a": 1}),
+                &parse_json(&json!("{\"a\": 1, \"b\": 2}"))
+            ),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_evaluator_config() {
+        let config = serde_json::from_value::<EvaluatorConfig>(json!({
+            "type": "numericTolerance",
+            "tolerance": 0.1,
+            "threshold": 0.5,
+        }))
+        .unwrap();
+        assert!(matches!(
+            config.evaluator,
+            Evaluator::NumericTolerance { tolerance } if tolerance == 0.1
+        ));
+        assert_eq!(config.name(), "numericTolerance");
+        assert!(config.passes(0.5));
+
+        let judge = serde_json::from_value::<EvaluatorConfig>(json!({
+            "type": "llmJudge",
+            "name": "helpfulness",
+            "pipeline": "judge",
+        }))
+        .unwrap();
+        assert_eq!(judge.name(), "helpfulness");
+        assert!(judge.passes(0.5) && !judge.passes(0.4));
+    }
+}
@@ -0,0 +1,758 @@
+//! Evaluations run by the server, see `run_evaluation`
+//!
+//! An evaluation runs a pipeline version on each row of a dataset, with graph inputs taken from
+//! the row's data columns, and scores the output with the configured evaluators against the
+//! row's target. Rows which fail to run score 0 with every evaluator, so that they count as
+//! failures in the stats. Backfills score recorded production runs with the same evaluators,
+//! see `backfill`.
+
+use std::{collections::HashMap, env, sync::Arc};
+
+use anyhow::Result;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    auth::rate_limit::ApiKeyRateLimiter,
+    datasets::{
+        datapoints::Datapoint,
+        sampling::{sample_datapoints, Sampling},
+    },
+    db::{
+        self,
+        api_keys::ProjectApiKey,
+        evaluations::{
+            EvaluationDatapointPreview, EvaluationDatapointStatus, EvaluationStatus,
+            NewEvaluationResult,
+        },
+        pipelines::PipelineVersion,
+        DB,
+    },
+    pipeline::{nodes::NodeInput, prompts, runner::PipelineRunner},
+    runs::{record_token_usage, setup_graph},
+};
+
+pub mod backfill;
+pub mod evaluators;
+pub mod stats;
+
+use evaluators::{as_text, EvaluatorConfig, JudgeContext};
+use stats::{HistogramBucket, PairedComparison, ScoreStats};
+
+const DEFAULT_EVALUATION_CONCURRENCY: usize = 5;
+const RESULTS_BATCH_SIZE: usize = 50;
+
+/// What an evaluation runs, and how it scores the outputs
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationConfig {
+    /// Name of the evaluated pipeline
+    pub pipeline: String,
+    /// Id or content hash of a COMMIT version, or `production` alias. The version is resolved
+    /// when the evaluation is created, and the same one is run by each run of the evaluation.
+    #[serde(default)]
+    pub pipeline_version: Option<String>,
+    #[serde(default)]
+    pub pipeline_version_id: Option<Uuid>,
+    pub dataset_id: Uuid,
+    /// Data column of each graph input
+    pub column_mapping: HashMap<String, String>,
+    /// Column compared against the output, looked up in the row's target, then in its data
+    pub target_column: String,
+    /// Output node whose value is scored, can be omitted if the graph has a single output
+    #[serde(default)]
+    pub output_node: Option<String>,
+    #[schema(inline)]
+    pub evaluators: Vec<EvaluatorConfig>,
+    /// Rows of the dataset to run on, all rows if not set. The seed of random samples is set
+    /// when the evaluation is created, so that each run is on the same rows.
+    #[serde(default)]
+    pub sampling: Option<Sampling>,
+    /// Versions of registry prompts by name which the runs of the evaluation are pinned to, over
+    /// the labels their nodes reference, to compare candidate versions of a prompt
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub prompt_versions: HashMap<String, i32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluatorStats {
+    pub mean_score: f64,
+    /// Share of rows scoring at least the evaluator's threshold
+    pub pass_rate: f64,
+    #[serde(default)]
+    pub median_score: f64,
+    #[serde(default)]
+    pub stddev: f64,
+    /// Counts of scores in equal width buckets
+    #[serde(default)]
+    pub histogram: Vec<HistogramBucket>,
+}
+
+/// Aggregate metrics of a run of an evaluation
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationStats {
+    pub rows: usize,
+    /// Rows which failed to run, or which an evaluator failed to score
+    pub error_rows: usize,
+    /// Mean of the rows' mean scores
+    pub mean_score: f64,
+    /// Share of rows which passed all evaluators
+    pub pass_rate: f64,
+    #[schema(inline)]
+    pub evaluators: HashMap<String, EvaluatorStats>,
+    /// Ids of the rows of the sample, if the evaluation is run on a sample of the dataset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampled_datapoint_ids: Option<Vec<Uuid>>,
+}
+
+/// Scores and result of a row, as needed for the stats
+pub struct RowScores<'a> {
+    pub scores: &'a HashMap<String, f64>,
+    pub passed: bool,
+    pub error: bool,
+}
+
+impl EvaluationStats {
+    pub fn from_rows<'a>(
+        evaluators: &[EvaluatorConfig],
+        rows: impl IntoIterator<Item = RowScores<'a>>,
+    ) -> Self {
+        let mut stats = EvaluationStats::default();
+        let mut score_sum = 0.0;
+        let mut passed_rows = 0;
+        // Scores of all rows of each evaluator, missing scores are 0
+        let mut evaluator_scores = HashMap::<String, Vec<f64>>::new();
+
+        for row in rows {
+            stats.rows += 1;
+            stats.error_rows += row.error as usize;
+            passed_rows += row.passed as usize;
+            if !evaluators.is_empty() {
+                score_sum += row.scores.values().sum::<f64>() / evaluators.len() as f64;
+            }
+            for evaluator in evaluators {
+                let name = evaluator.name();
+                let score = row.scores.get(&name).copied().unwrap_or(0.0);
+                evaluator_scores.entry(name).or_default().push(score);
+            }
+        }
+
+        if stats.rows > 0 {
+            let rows = stats.rows as f64;
+            stats.mean_score = score_sum / rows;
+            stats.pass_rate = passed_rows as f64 / rows;
+            stats.evaluators = evaluators
+                .iter()
+                .filter_map(|evaluator| {
+                    let name = evaluator.name();
+                    let scores =
+                        ScoreStats::new(evaluator_scores.get(&name)?, evaluator.threshold());
+                    let evaluator_stats = EvaluatorStats {
+                        mean_score: scores.mean,
+                        pass_rate: scores.pass_rate,
+                        median_score: scores.median,
+                        stddev: scores.stddev,
+                        histogram: scores.histogram,
+                    };
+                    Some((name, evaluator_stats))
+                })
+                .collect();
+        }
+        stats
+    }
+}
+
+/// Everything rows of a run are executed and scored with
+pub struct EvaluationContext {
+    pub db: Arc<DB>,
+    pub pipeline_runner: Arc<PipelineRunner>,
+    pub rate_limiter: Arc<ApiKeyRateLimiter>,
+    pub project_api_key: ProjectApiKey,
+    pub config: EvaluationConfig,
+    pub pipeline_version: PipelineVersion,
+    /// Versions of the judge pipelines, by id
+    pub judges: HashMap<Uuid, PipelineVersion>,
+    pub env: HashMap<String, String>,
+    pub metadata: HashMap<String, String>,
+}
+
+fn evaluation_concurrency() -> usize {
+    env::var("EVALUATION_CONCURRENCY")
+        .ok()
+        .and_then(|concurrency| concurrency.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_EVALUATION_CONCURRENCY)
+}
+
+/// Versions of the judge pipelines of the evaluators, by id
+async fn load_judges(
+    db: &DB,
+    evaluators: &[EvaluatorConfig],
+) -> Result<HashMap<Uuid, PipelineVersion>> {
+    let mut judges = HashMap::new();
+    for evaluator in evaluators {
+        if let evaluators::Evaluator::LlmJudge {
+            pipeline_version_id: Some(id),
+            ..
+        } = &evaluator.evaluator
+        {
+            judges.insert(
+                *id,
+                db::pipelines::get_pipeline_version(&db.pool, id).await?,
+            );
+        }
+    }
+    Ok(judges)
+}
+
+impl EvaluationContext {
+    pub async fn new(
+        db: Arc<DB>,
+        pipeline_runner: Arc<PipelineRunner>,
+        rate_limiter: Arc<ApiKeyRateLimiter>,
+        project_api_key: ProjectApiKey,
+        evaluation_id: Uuid,
+        config: EvaluationConfig,
+        mut env: HashMap<String, String>,
+    ) -> Result<Self> {
+        let pipeline_version_id = config
+            .pipeline_version_id
+            .ok_or_else(|| anyhow::anyhow!("Pipeline version is not resolved"))?;
+        let pipeline_version =
+            db::pipelines::get_pipeline_version(&db.pool, &pipeline_version_id).await?;
+
+        let judges = load_judges(&db, &config.evaluators).await?;
+
+        env.insert(
+            "collection_name".to_string(),
+            project_api_key.project_id.to_string(),
+        );
+        let mut metadata =
+            HashMap::from([("evaluation_id".to_string(), evaluation_id.to_string())]);
+        prompts::pin_versions(&mut metadata, &config.prompt_versions);
+
+        Ok(Self {
+            db,
+            pipeline_runner,
+            rate_limiter,
+            project_api_key,
+            config,
+            pipeline_version,
+            judges,
+            env,
+            metadata,
+        })
+    }
+
+    pub fn judge_context(&self) -> JudgeContext<'_> {
+        JudgeContext {
+            db: &self.db,
+            pipeline_runner: &self.pipeline_runner,
+            rate_limiter: &self.rate_limiter,
+            project_api_key: &self.project_api_key,
+            judges: &self.judges,
+            env: &self.env,
+            metadata: &self.metadata,
+            throttle: None,
+        }
+    }
+}
+
+/// Run the evaluation on the rows of its dataset, or its sample, replacing the results of
+/// previous runs
+///
+/// Results are written in batches while the rows are run, and the stats once all rows are
+/// scored. The evaluation is marked as `Error` if the dataset can't be read.
+pub async fn run_evaluation(context: EvaluationContext, evaluation_id: Uuid) {
+    let db = context.db.clone();
+    let status = match evaluate_rows(&context, evaluation_id).await {
+        Ok(stats) => {
+            let stats = serde_json::to_value(stats).ok();
+            db::evaluations::finish_evaluation_run(
+                &db.pool,
+                evaluation_id,
+                EvaluationStatus::Finished,
+                stats,
+            )
+            .await
+        }
+        Err(e) => {
+            log::error!("Evaluation {} failed: {}", evaluation_id, e);
+            db::evaluations::finish_evaluation_run(
+                &db.pool,
+                evaluation_id,
+                EvaluationStatus::Error,
+                None,
+            )
+            .await
+        }
+    };
+    if let Err(e) = status {
+        log::error!("Failed to finish evaluation {}: {}", evaluation_id, e);
+    }
+}
+
+async fn evaluate_rows(
+    context: &EvaluationContext,
+    evaluation_id: Uuid,
+) -> Result<EvaluationStats> {
+    let datapoints = match &context.config.sampling {
+        Some(sampling) => {
+            sample_datapoints(&context.db.pool, context.config.dataset_id, sampling).await?
+        }
+        None => {
+            db::datapoints::get_all_datapoints(&context.db.pool, context.config.dataset_id).await?
+        }
+    };
+
+    // Collected first, a stream mapping borrowed datapoints with a closure isn't `Send` for
+    // `tokio::spawn`
+    let rows = datapoints
+        .iter()
+        .map(|datapoint| evaluate_row(context, datapoint))
+        .collect::<Vec<_>>();
+    let mut results = stream::iter(rows)
+        .buffered(evaluation_concurrency())
+        .chunks(RESULTS_BATCH_SIZE);
+
+    let mut all_scores = Vec::with_capacity(datapoints.len());
+    let mut first_index = 0;
+    while let Some(batch) = results.next().await {
+        db::evaluations::insert_evaluation_results(
+            &context.db.pool,
+            evaluation_id,
+            &batch,
+            first_index,
+        )
+        .await?;
+        first_index += batch.len() as i64;
+        all_scores.extend(batch.into_iter().map(|result| {
+            let error = result.status == EvaluationDatapointStatus::Error;
+            (result.scores, result.passed, error)
+        }));
+    }
+
+    let rows = all_scores.iter().map(|(scores, passed, error)| RowScores {
+        scores,
+        passed: *passed,
+        error: *error,
+    });
+    let mut stats = EvaluationStats::from_rows(&context.config.evaluators, rows);
+    if context.config.sampling.is_some() {
+        stats.sampled_datapoint_ids = Some(datapoints.iter().map(|dp| dp.id).collect());
+    }
+    Ok(stats)
+}
+
+async fn evaluate_row(context: &EvaluationContext, datapoint: &Datapoint) -> NewEvaluationResult {
+    let config = &context.config;
+    let target = datapoint
+        .target
+        .get(&config.target_column)
+        .or_else(|| datapoint.data.get(&config.target_column))
+        .cloned()
+        .unwrap_or(Value::Null);
+    let trace_id = Uuid::new_v4();
+
+    let mut result = NewEvaluationResult {
+        datapoint_id: datapoint.id,
+        status: EvaluationDatapointStatus::Success,
+        scores: config
+            .evaluators
+            .iter()
+            .map(|evaluator| (evaluator.name(), 0.0))
+            .collect(),
+        passed: false,
+        data: datapoint.data.clone(),
+        target: datapoint.target.clone(),
+        executor_output: None,
+        executor_trace_id: None,
+        evaluator_trace_id: None,
+        error: None,
+    };
+
+    let output = match run_row(context, datapoint, trace_id).await {
+        Ok(output) => output,
+        Err(e) => {
+            result.status = EvaluationDatapointStatus::Error;
+            result.executor_trace_id = Some(trace_id);
+            result.error = Some(Value::String(e.to_string()));
+            return result;
+        }
+    };
+    result.executor_output = Some(output.clone());
+    result.executor_trace_id = Some(trace_id);
+
+    let judge_context = context.judge_context();
+    let mut passed = true;
+    let mut errors = Vec::new();
+    for evaluator in &config.evaluators {
+        let name = evaluator.name();
+        match evaluator.score(&output, &target, &judge_context).await {
+            Ok(score) => {
+                passed &= evaluator.passes(score.value);
+                result.scores.insert(name, score.value);
+                result.evaluator_trace_id = result.evaluator_trace_id.or(score.trace_id);
+            }
+            Err(e) => {
+                passed = false;
+                errors.push(format!("Evaluator '{}' failed: {}", name, e));
+            }
+        }
+    }
+    result.passed = passed;
+    if !errors.is_empty() {
+        result.status = EvaluationDatapointStatus::Error;
+        result.error = Some(Value::String(errors.join("\n")));
+    }
+    result
+}
+
+/// Run the pipeline on the row, returns the value of the scored output
+async fn run_row(
+    context: &EvaluationContext,
+    datapoint: &Datapoint,
+    trace_id: Uuid,
+) -> Result<Value> {
+    let config = &context.config;
+    let mut inputs = HashMap::new();
+    for (input, column) in &config.column_mapping {
+        let value = datapoint
+            .data
+            .get(column)
+            .ok_or_else(|| anyhow::anyhow!("Column '{}' is missing", column))?;
+        inputs.insert(input.clone(), to_node_input(value));
+    }
+
+    let graph = setup_graph(
+        &context.pipeline_runner,
+        &context.db,
+        &context.pipeline_version,
+        &inputs,
+        &context.env,
+        &context.metadata,
+        &context.project_api_key.project_id,
+    )
+    .await?;
+    let secrets = graph.secrets.clone();
+
+    let run_result = context.pipeline_runner.run(graph, None).await;
+    record_token_usage(
+        &context.db,
+        &context.rate_limiter,
+        &context.project_api_key,
+        &run_result,
+    )
+    .await;
+    if let Err(e) = context
+        .pipeline_runner
+        .record_observations(
+            &run_result,
+            &context.project_api_key.project_id,
+            Some(context.project_api_key.workspace_id),
+            &context.pipeline_version,
+            None,
+            Some(trace_id),
+            None,
+            &secrets,
+        )
+        .await
+    {
+        log::error!("Failed to record observations of evaluation run: {}", e);
+    }
+
+    let mut outputs = run_result?.output_values();
+    let output = match &config.output_node {
+        Some(node) => outputs
+            .remove(node)
+            .ok_or_else(|| anyhow::anyhow!("Output '{}' not found", node))?,
+        None if outputs.len() == 1 => outputs.into_values().next().unwrap(),
+        None => {
+            return Err(anyhow::anyhow!(
+                "Graph has {} outputs, set the output node to score",
+                outputs.len()
+            ))
+        }
+    };
+    Ok(serde_json::to_value(output)?)
+}
+
+/// Column values which aren't node inputs, e.g. JSON objects, are passed as JSON strings
+fn to_node_input(value: &Value) -> NodeInput {
+    serde_json::from_value::<NodeInput>(value.clone())
+        .unwrap_or_else(|_| NodeInput::String(as_text(value)))
+}
+
+/// Row whose scores differ between two runs of evaluations of the same dataset
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RowDiff {
+    pub datapoint_id: Uuid,
+    pub data: Value,
+    pub target: Value,
+    /// Scores in the baseline evaluation, None if the row wasn't evaluated there
+    pub baseline_scores: Option<Value>,
+    pub scores: Option<Value>,
+    pub baseline_passed: Option<bool>,
+    pub passed: Option<bool>,
+    pub baseline_output: Option<Value>,
+    pub output: Option<Value>,
+}
+
+/// Rows whose scores changed from the baseline results, in the order of the results
+pub fn diff_results(
+    baseline: Vec<EvaluationDatapointPreview>,
+    results: Vec<EvaluationDatapointPreview>,
+) -> Vec<RowDiff> {
+    let mut baseline = baseline
+        .into_iter()
+        .filter_map(|row| Some((row.datapoint_id?, row)))
+        .collect::<HashMap<_, _>>();
+
+    let mut diffs = Vec::new();
+    for row in results {
+        let Some(datapoint_id) = row.datapoint_id else {
+            continue;
+        };
+        let baseline_row = baseline.remove(&datapoint_id);
+        if baseline_row
+            .as_ref()
+            .is_some_and(|baseline_row| baseline_row.scores == row.scores)
+        {
+            continue;
+        }
+        diffs.push(RowDiff {
+            datapoint_id,
+            data: row.data,
+            target: row.target,
+            baseline_scores: baseline_row.as_ref().map(|r| r.scores.clone()),
+            scores: Some(row.scores),
+            baseline_passed: baseline_row.as_ref().and_then(|r| r.passed),
+            passed: row.passed,
+            baseline_output: baseline_row.and_then(|r| r.executor_output),
+            output: row.executor_output,
+        });
+    }
+    // Rows which were only evaluated in the baseline
+    diffs.extend(baseline.into_values().map(|row| RowDiff {
+        datapoint_id: row.datapoint_id.unwrap_or_default(),
+        data: row.data,
+        target: row.target,
+        baseline_scores: Some(row.scores),
+        scores: None,
+        baseline_passed: row.passed,
+        passed: None,
+        baseline_output: row.executor_output,
+        output: None,
+    }));
+    diffs
+}
+
+/// Row which scored lower than in the baseline
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegressedRow {
+    pub datapoint_id: Uuid,
+    pub baseline_score: f64,
+    pub score: f64,
+    pub baseline_executor_trace_id: Option<Uuid>,
+    pub executor_trace_id: Option<Uuid>,
+}
+
+/// Scores of an evaluator in two evaluations of the same dataset
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluatorComparison {
+    /// Stats of the baseline rows scored by the evaluator
+    pub baseline: ScoreStats,
+    pub stats: ScoreStats,
+    /// Rows scored by the evaluator in both evaluations
+    pub paired: PairedComparison,
+    /// Largest regressions first
+    pub regressed_rows: Vec<RegressedRow>,
+}
+
+fn scores_of(row: &EvaluationDatapointPreview) -> HashMap<String, f64> {
+    row.scores
+        .as_object()
+        .map(|scores| {
+            scores
+                .iter()
+                .filter_map(|(name, score)| Some((name.clone(), score.as_f64()?)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Compare the scores of each evaluator with the baseline, rows are paired by datapoint.
+/// Rows without the evaluator's score, e.g. failed ones, are left out of its stats.
+pub fn compare_results(
+    baseline: &[EvaluationDatapointPreview],
+    results: &[EvaluationDatapointPreview],
+    threshold: impl Fn(&str) -> f64,
+) -> HashMap<String, EvaluatorComparison> {
+    let baseline_scores = baseline.iter().map(scores_of).collect::<Vec<_>>();
+    let result_scores = results.iter().map(scores_of).collect::<Vec<_>>();
+    let baseline_rows = baseline
+        .iter()
+        .zip(&baseline_scores)
+        .filter_map(|(row, scores)| Some((row.datapoint_id?, (row, scores))))
+        .collect::<HashMap<_, _>>();
+
+    let mut names = baseline_scores
+        .iter()
+        .chain(&result_scores)
+        .flat_map(|scores| scores.keys().cloned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let threshold = threshold(&name);
+            let scores_of_evaluator = |scores: &[HashMap<String, f64>]| {
+                scores
+                    .iter()
+                    .filter_map(|scores| scores.get(&name).copied())
+                    .collect::<Vec<_>>()
+            };
+
+            let mut pairs = Vec::new();
+            let mut regressed_rows = Vec::new();
+            for (row, scores) in results.iter().zip(&result_scores) {
+                let Some(datapoint_id) = row.datapoint_id else {
+                    continue;
+                };
+                let Some((baseline_row, baseline_scores)) = baseline_rows.get(&datapoint_id) else {
+                    continue;
+                };
+                let (Some(baseline_score), Some(score)) =
+                    (baseline_scores.get(&name), scores.get(&name))
+                else {
+                    continue;
+                };
+                pairs.push((*baseline_score, *score));
+                if stats::is_regression(*baseline_score, *score) {
+                    regressed_rows.push(RegressedRow {
+                        datapoint_id,
+                        baseline_score: *baseline_score,
+                        score: *score,
+                        baseline_executor_trace_id: baseline_row.executor_trace_id,
+                        executor_trace_id: row.executor_trace_id,
+                    });
+                }
+            }
+            regressed_rows.sort_by(|a, b| {
+                (a.score - a.baseline_score).total_cmp(&(b.score - b.baseline_score))
+            });
+
+            let comparison = EvaluatorComparison {
+                baseline: ScoreStats::new(&scores_of_evaluator(&baseline_scores), threshold),
+                stats: ScoreStats::new(&scores_of_evaluator(&result_scores), threshold),
+                paired: PairedComparison::new(&pairs),
+                regressed_rows,
+            };
+            (name, comparison)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_stats_count_failed_rows() {
+        let evaluators = serde_json::from_value::<Vec<EvaluatorConfig>>(json!([
+            {"type": "exactMatch"},
+            {"type": "jsonMatch", "threshold": 0.5},
+        ]))
+        .unwrap();
+        let passed = HashMap::from([
+            ("exactMatch".to_string(), 1.0),
+            ("jsonMatch".to_string(), 0.5),
+        ]);
+        let failed = HashMap::from([
+            ("exactMatch".to_string(), 0.0),
+            ("jsonMatch".to_string(), 0.0),
+        ]);
+
+        let stats = EvaluationStats::from_rows(
+            &evaluators,
+            [
+                RowScores {
+                    scores: &passed,
+                    passed: true,
+                    error: false,
+                },
+                RowScores {
+                    scores: &failed,
+                    passed: false,
+                    error: true,
+                },
+            ],
+        );
+
+        assert_eq!(stats.rows, 2);
+        assert_eq!(stats.error_rows, 1);
+        assert_eq!(stats.pass_rate, 0.5);
+        assert_eq!(stats.mean_score, 0.375);
+        assert_eq!(stats.evaluators["exactMatch"].mean_score, 0.5);
+        assert_eq!(stats.evaluators["jsonMatch"].pass_rate, 0.5);
+    }
+
+    fn result_row(datapoint_id: Uuid, scores: Value) -> EvaluationDatapointPreview {
+        EvaluationDatapointPreview {
+            id: Uuid::new_v4(),
+            created_at: chrono::Utc::now(),
+            evaluation_id: Uuid::new_v4(),
+            data: json!({}),
+            target: json!({}),
+            scores,
+            status: EvaluationDatapointStatus::Success,
+            executor_output: None,
+            error: None,
+            datapoint_id: Some(datapoint_id),
+            passed: None,
+            executor_trace_id: Some(Uuid::new_v4()),
+            evaluator_trace_id: None,
+            trace_content_purged: false,
+        }
+    }
+
+    #[test]
+    fn test_compare_results_pairs_rows_by_datapoint() {
+        let (improved, regressed, failed) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let baseline = vec![
+            result_row(improved, json!({"exactMatch": 0.0})),
+            result_row(regressed, json!({"exactMatch": 1.0})),
+            result_row(failed, json!({"exactMatch": 1.0})),
+        ];
+        let results = vec![
+            result_row(regressed, json!({"exactMatch": 0.0})),
+            result_row(improved, json!({"exactMatch": 1.0})),
+            result_row(failed, json!({})),
+        ];
+
+        let comparison = compare_results(&baseline, &results, |_| 1.0);
+        let exact_match = &comparison["exactMatch"];
+        assert_eq!(exact_match.paired.rows, 2);
+        assert_eq!(exact_match.paired.improved, 1);
+        assert_eq!(exact_match.paired.regressed, 1);
+        assert_eq!(exact_match.baseline.rows, 3);
+        assert_eq!(exact_match.stats.rows, 2);
+        assert_eq!(exact_match.regressed_rows.len(), 1);
+        assert_eq!(exact_match.regressed_rows[0].datapoint_id, regressed);
+        assert_eq!(
+            exact_match.regressed_rows[0].executor_trace_id,
+            results[0].executor_trace_id
+        );
+    }
+}
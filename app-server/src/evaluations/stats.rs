@@ -0,0 +1,261 @@
+//! Statistics of evaluator scores, and paired comparisons of scores of the same rows
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const HISTOGRAM_BUCKETS: usize = 10;
+const BOOTSTRAP_RESAMPLES: usize = 1000;
+/// Fixed, so that comparisons of the same evaluations report the same interval
+const BOOTSTRAP_SEED: u64 = 0;
+const CONFIDENCE_LEVEL: f64 = 0.95;
+/// Score differences within this are unchanged
+const SCORE_EPSILON: f64 = 1e-9;
+
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+/// Sample standard deviation, 0 for less than two values
+pub fn stddev(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(values);
+    let sum_of_squares = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>();
+    (sum_of_squares / (values.len() - 1) as f64).sqrt()
+}
+
+/// Share of values which are at least the threshold
+pub fn pass_rate(values: &[f64], threshold: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().filter(|value| **value >= threshold).count() as f64 / values.len() as f64
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBucket {
+    pub lower: f64,
+    /// Exclusive, except for the last bucket
+    pub upper: f64,
+    pub count: usize,
+}
+
+/// Equal width buckets over 0 to 1, widened to include scores outside of it
+pub fn histogram(values: &[f64], buckets: usize) -> Vec<HistogramBucket> {
+    let lower = values.iter().copied().fold(0.0, f64::min);
+    let upper = values.iter().copied().fold(1.0, f64::max);
+    let width = (upper - lower) / buckets as f64;
+
+    let mut histogram = (0..buckets)
+        .map(|bucket| HistogramBucket {
+            lower: lower + width * bucket as f64,
+            upper: lower + width * (bucket + 1) as f64,
+            count: 0,
+        })
+        .collect::<Vec<_>>();
+    for value in values {
+        let bucket = (((value - lower) / width) as usize).min(buckets - 1);
+        histogram[bucket].count += 1;
+    }
+    histogram
+}
+
+/// Aggregates of an evaluator's scores of all rows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreStats {
+    pub rows: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    pub threshold: f64,
+    /// Share of rows scoring at least the threshold
+    pub pass_rate: f64,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+impl ScoreStats {
+    pub fn new(scores: &[f64], threshold: f64) -> Self {
+        Self {
+            rows: scores.len(),
+            mean: mean(scores),
+            median: median(scores),
+            stddev: stddev(scores),
+            threshold,
+            pass_rate: pass_rate(scores, threshold),
+            histogram: histogram(scores, HISTOGRAM_BUCKETS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct ConfidenceInterval {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Whether the score is lower than the baseline by more than float noise
+pub fn is_regression(baseline: f64, score: f64) -> bool {
+    score - baseline < -SCORE_EPSILON
+}
+
+/// Comparison of scores of the same rows in a baseline and another evaluation
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PairedComparison {
+    pub rows: usize,
+    pub improved: usize,
+    pub regressed: usize,
+    pub unchanged: usize,
+    /// Mean of the score differences from the baseline
+    pub mean_difference: f64,
+    /// Bootstrap 95% confidence interval of the mean difference, None without rows
+    pub confidence_interval: Option<ConfidenceInterval>,
+}
+
+impl PairedComparison {
+    /// Compare (baseline, score) pairs of the rows
+    pub fn new(pairs: &[(f64, f64)]) -> Self {
+        let differences = pairs
+            .iter()
+            .map(|(baseline, score)| score - baseline)
+            .collect::<Vec<_>>();
+        let count = |predicate: fn(f64) -> bool| {
+            differences
+                .iter()
+                .filter(|difference| predicate(**difference))
+                .count()
+        };
+
+        Self {
+            rows: differences.len(),
+            improved: count(|difference| difference > SCORE_EPSILON),
+            regressed: count(|difference| is_regression(0.0, difference)),
+            unchanged: count(|difference| difference.abs() <= SCORE_EPSILON),
+            mean_difference: mean(&differences),
+            confidence_interval: bootstrap_mean_interval(
+                &differences,
+                BOOTSTRAP_RESAMPLES,
+                CONFIDENCE_LEVEL,
+                BOOTSTRAP_SEED,
+            ),
+        }
+    }
+}
+
+/// Percentile bootstrap confidence interval of the mean
+pub fn bootstrap_mean_interval(
+    values: &[f64],
+    resamples: usize,
+    confidence_level: f64,
+    seed: u64,
+) -> Option<ConfidenceInterval> {
+    if values.is_empty() || resamples == 0 {
+        return None;
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut means = (0..resamples)
+        .map(|_| {
+            let sum = (0..values.len())
+                .map(|_| values[rng.gen_range(0..values.len())])
+                .sum::<f64>();
+            sum / values.len() as f64
+        })
+        .collect::<Vec<_>>();
+    means.sort_by(f64::total_cmp);
+
+    let tail = (1.0 - confidence_level) / 2.0;
+    let index = |quantile: f64| (quantile * (resamples - 1) as f64).round() as usize;
+    Some(ConfidenceInterval {
+        lower: means[index(tail)],
+        upper: means[index(1.0 - tail)],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_summary_stats() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_close(mean(&values), 5.0);
+        assert_close(median(&values), 4.5);
+        assert_close(median(&[3.0, 1.0, 2.0]), 2.0);
+        // sample variance of the values is 32 / 7
+        assert_close(stddev(&values), (32.0_f64 / 7.0).sqrt());
+        assert_close(stddev(&[1.0]), 0.0);
+        assert_close(pass_rate(&values, 5.0), 0.5);
+        assert_close(mean(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = histogram(&[0.0, 0.05, 0.5, 1.0], 10);
+        assert_eq!(histogram.len(), 10);
+        assert_eq!(histogram[0].count, 2);
+        assert_eq!(histogram[5].count, 1);
+        // the maximum is in the last bucket
+        assert_eq!(histogram[9].count, 1);
+        assert_close(histogram[9].upper, 1.0);
+
+        let widened = super::histogram(&[-1.0, 3.0], 4);
+        assert_close(widened[0].lower, -1.0);
+        assert_close(widened[3].upper, 3.0);
+        assert_eq!(widened.iter().map(|b| b.count).sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn test_paired_comparison() {
+        let comparison = PairedComparison::new(&[(0.0, 1.0), (1.0, 0.0), (0.5, 0.5), (0.0, 1.0)]);
+        assert_eq!(comparison.rows, 4);
+        assert_eq!(comparison.improved, 2);
+        assert_eq!(comparison.regressed, 1);
+        assert_eq!(comparison.unchanged, 1);
+        assert_close(comparison.mean_difference, 0.25);
+        let interval = comparison.confidence_interval.unwrap();
+        assert!(interval.lower <= 0.25 && 0.25 <= interval.upper);
+        assert!(interval.lower >= -1.0 && interval.upper <= 1.0);
+
+        assert_eq!(PairedComparison::new(&[]).confidence_interval, None);
+    }
+
+    #[test]
+    fn test_bootstrap_of_constant_values() {
+        let interval = bootstrap_mean_interval(&[0.5; 20], 100, 0.95, 1).unwrap();
+        assert_close(interval.lower, 0.5);
+        assert_close(interval.upper, 0.5);
+    }
+}
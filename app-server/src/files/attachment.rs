@@ -0,0 +1,101 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Mime types whose content is passed to nodes as text when they expect a string input
+const TEXT_MIME_TYPES: &[&str] = &["application/json", "application/xml", "application/yaml"];
+
+/// Temp file removed once the last attachment referencing it is dropped, i.e. after the run
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Failed to remove uploaded file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// File uploaded as a pipeline run input
+///
+/// The content is kept on disk rather than in memory, and is read by nodes on demand.
+/// Only the metadata is serialized, e.g. into run traces and outputs.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FileAttachment {
+    pub filename: String,
+    pub mime_type: String,
+    /// in bytes
+    pub size: u64,
+    #[serde(skip)]
+    file: Arc<TempFile>,
+}
+
+impl PartialEq for FileAttachment {
+    fn eq(&self, other: &Self) -> bool {
+        self.file.path == other.file.path
+    }
+}
+
+impl FileAttachment {
+    /// Take ownership of the file at `path`, it is removed when the attachment is dropped
+    pub fn new(filename: String, mime_type: String, size: u64, path: PathBuf) -> Self {
+        Self {
+            filename,
+            mime_type,
+            size,
+            file: Arc::new(TempFile { path }),
+        }
+    }
+
+    /// Write the content to a new upload file, for inputs which are not streamed
+    pub async fn from_bytes(filename: String, mime_type: String, data: &[u8]) -> Result<Self> {
+        let path = new_upload_path();
+        tokio::fs::write(&path, data).await?;
+        Ok(Self::new(filename, mime_type, data.len() as u64, path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file.path
+    }
+
+    pub async fn read(&self) -> Result<Bytes> {
+        Ok(tokio::fs::read(self.path()).await?.into())
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.mime_type.starts_with("text/") || TEXT_MIME_TYPES.contains(&self.mime_type.as_str())
+    }
+
+    /// Content of text files, or a description of the file for binary ones
+    pub fn to_text(&self) -> String {
+        if self.is_text() {
+            if let Ok(content) = std::fs::read(self.path()) {
+                return String::from_utf8_lossy(&content).to_string();
+            }
+        }
+        format!(
+            "[file {} ({}, {} bytes)]",
+            self.filename, self.mime_type, self.size
+        )
+    }
+}
+
+/// Path for a new uploaded file, in `RUN_UPLOAD_DIR` or the system temp dir
+pub fn new_upload_path() -> PathBuf {
+    let upload_dir = env::var("RUN_UPLOAD_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir());
+    upload_dir.join(format!("lmnr-upload-{}", Uuid::new_v4()))
+}
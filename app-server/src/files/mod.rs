@@ -10,6 +10,8 @@ use reqwest::{
 use std::{collections::HashMap, env, sync::Arc};
 use uuid::Uuid;
 
+pub mod attachment;
+
 use crate::chunk::{
     character_split::CharacterSplitParams,
     runner::{ChunkParams, ChunkerRunner, ChunkerType},
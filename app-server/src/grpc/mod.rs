@@ -0,0 +1,924 @@
+//! gRPC API for pipeline runs, for clients which prefer it over the JSON endpoints in `api::v1`
+//!
+//! Runs go through the same preparation, execution and tracing as `POST /v1/pipeline/run`, and
+//! requests are authenticated with project API keys and count towards the same rate limits.
+
+use std::{collections::HashMap, sync::Arc};
+
+use actix_web::http::StatusCode;
+use prost::Message;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        utils::require_api_key_scope,
+        v1::pipelines::{create_run, prepare_run, CurrentTraceAndSpan, GraphRequest},
+    },
+    auth::{authenticate_project_api_key, rate_limit::ApiKeyRateLimiter, ProjectAuthError},
+    cache::Cache,
+    db::{
+        api_keys::{ApiKeyScope, ProjectApiKey},
+        runs::RunStatus,
+        DB,
+    },
+    engine::engine::EngineOutput,
+    files::attachment::FileAttachment,
+    language_model::{
+        ChatMessage, ChatMessageContent, ChatMessageContentPart, ChatMessageImage,
+        ChatMessageImageUrl, ChatMessageText,
+    },
+    pipeline::{
+        nodes::{NodeInput, StreamChunk},
+        runner::{PipelineRunner, PipelineRunnerError},
+        trace::RunTraceStats,
+    },
+    routes::error,
+    runs::{
+        self, execute_run,
+        idempotency::{release_on_error, IdempotencyClaim, IdempotencyKey},
+        queue::RunExecution,
+        InterruptSenders,
+    },
+};
+
+use self::pipeline_runner_grpc::{
+    chat_message, chat_message_content_part, node_value,
+    pipeline_run_service_server::PipelineRunService, run_pipeline_stream_chunk, Attachment,
+    CancelRunRequest, CancelRunResponse, ChatMessageContentPartList, ChatMessageList, NodeChunk,
+    NodeEnd, NodeValue, RunPipelineRequest, RunPipelineResponse, RunPipelineStreamChunk,
+    StringList, Usage,
+};
+
+pub mod pipeline_runner_grpc;
+
+const DEFAULT_ATTACHMENT_MIME_TYPE: &str = "application/octet-stream";
+
+pub struct PipelineRunGrpcService {
+    pipeline_runner: Arc<PipelineRunner>,
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    rate_limiter: Arc<ApiKeyRateLimiter>,
+    interrupt_senders: Arc<InterruptSenders>,
+    /// gRPC runs are executed locally, this is only to cancel runs executed by workers
+    run_execution: RunExecution,
+}
+
+impl PipelineRunGrpcService {
+    pub fn new(
+        pipeline_runner: Arc<PipelineRunner>,
+        db: Arc<DB>,
+        cache: Arc<Cache>,
+        rate_limiter: Arc<ApiKeyRateLimiter>,
+        interrupt_senders: Arc<InterruptSenders>,
+        run_execution: RunExecution,
+    ) -> Self {
+        Self {
+            pipeline_runner,
+            db,
+            cache,
+            rate_limiter,
+            interrupt_senders,
+            run_execution,
+        }
+    }
+
+    /// Same as `auth::project_validator`, with the API key in `authorization: Bearer` metadata
+    async fn authenticate<T>(&self, request: &Request<T>) -> Result<ProjectApiKey, Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing project API key"))?;
+
+        authenticate_project_api_key(
+            self.db.clone(),
+            self.cache.clone(),
+            &self.rate_limiter,
+            token,
+        )
+        .await
+        .map_err(|e| match e {
+            ProjectAuthError::InvalidApiKey(e) => {
+                log::error!("Project API key authentication failed: {}", e);
+                Status::unauthenticated("Invalid project API key")
+            }
+            ProjectAuthError::RateLimited(e) => Status::resource_exhausted(format!(
+                "Rate limit of {} exceeded, resets at {}",
+                e.limit, e.reset_at
+            )),
+        })
+    }
+
+    async fn authenticate_for_run<T>(&self, request: &Request<T>) -> Result<ProjectApiKey, Status> {
+        let project_api_key = self.authenticate(request).await?;
+        require_api_key_scope(&project_api_key, ApiKeyScope::Run).map_err(error_to_status)?;
+        Ok(project_api_key)
+    }
+
+    /// Response of the first request with the idempotency key, None if this one should be
+    /// executed
+    async fn replay(
+        &self,
+        idempotency_key: &IdempotencyKey,
+    ) -> Result<Option<RunPipelineResponse>, Status> {
+        match idempotency_key
+            .claim(&self.db)
+            .await
+            .map_err(error_to_status)?
+        {
+            IdempotencyClaim::New => Ok(None),
+            IdempotencyClaim::Completed { response, .. } => decode_response(&response)
+                .map(Some)
+                .ok_or_else(|| Status::internal("Failed to decode the stored response")),
+            IdempotencyClaim::InProgress {
+                run_id: Some(run_id),
+            } => Err(Status::already_exists(format!(
+                "Run {} of a request with this idempotency key is still running",
+                run_id
+            ))),
+            IdempotencyClaim::InProgress { run_id: None } => Err(Status::already_exists(
+                "A request with this idempotency key is being processed, retry later",
+            )),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl PipelineRunService for PipelineRunGrpcService {
+    async fn run_pipeline(
+        &self,
+        request: Request<RunPipelineRequest>,
+    ) -> Result<Response<RunPipelineResponse>, Status> {
+        let project_api_key = self.authenticate_for_run(&request).await?;
+        let (req, idempotency_key) =
+            run_request(request.into_inner(), "RunPipeline", &project_api_key).await?;
+        if let Some(idempotency_key) = &idempotency_key {
+            if let Some(res) = self.replay(idempotency_key).await? {
+                return Ok(Response::new(res));
+            }
+        }
+
+        let run = prepare_run(
+            req,
+            &self.pipeline_runner,
+            self.db.clone(),
+            self.cache.clone(),
+            &self.rate_limiter,
+            &project_api_key,
+        )
+        .await
+        .map_err(error_to_status);
+        let run = release_on_error(idempotency_key.as_ref(), &self.db, run).await?;
+        let created = create_run(&self.db, &run, RunStatus::Running)
+            .await
+            .map_err(error_to_status);
+        release_on_error(idempotency_key.as_ref(), &self.db, created).await?;
+        let run_info = RunInfo::new(&run);
+        if let Some(idempotency_key) = &idempotency_key {
+            idempotency_key.set_run(&self.db, &run_info.run_id).await;
+        }
+
+        let engine_output = execute_run(
+            run,
+            None,
+            &self.pipeline_runner,
+            &self.db,
+            &self.rate_limiter,
+            &project_api_key,
+            &self.interrupt_senders,
+        )
+        .await
+        .map_err(|e| runner_error_to_status(e, run_info.run_id));
+        let engine_output =
+            release_on_error(idempotency_key.as_ref(), &self.db, engine_output).await?;
+
+        let res = run_info.into_response(&engine_output).await;
+        if let Some(idempotency_key) = &idempotency_key {
+            idempotency_key
+                .complete(&self.db, StatusCode::OK, &encode_response(&res))
+                .await;
+        }
+        Ok(Response::new(res))
+    }
+
+    type RunPipelineStreamStream = ReceiverStream<Result<RunPipelineStreamChunk, Status>>;
+
+    async fn run_pipeline_stream(
+        &self,
+        request: Request<RunPipelineRequest>,
+    ) -> Result<Response<Self::RunPipelineStreamStream>, Status> {
+        let project_api_key = self.authenticate_for_run(&request).await?;
+        let (req, idempotency_key) =
+            run_request(request.into_inner(), "RunPipelineStream", &project_api_key).await?;
+        let (tx, rx) = mpsc::channel(8);
+        if let Some(idempotency_key) = &idempotency_key {
+            // Replayed as a stream of only the output
+            if let Some(res) = self.replay(idempotency_key).await? {
+                let chunk = RunPipelineStreamChunk {
+                    chunk: Some(run_pipeline_stream_chunk::Chunk::Output(res)),
+                };
+                let _ = tx.send(Ok(chunk)).await;
+                return Ok(Response::new(ReceiverStream::new(rx)));
+            }
+        }
+
+        // Errors before the run starts are returned as the status of the call itself
+        let run = prepare_run(
+            req,
+            &self.pipeline_runner,
+            self.db.clone(),
+            self.cache.clone(),
+            &self.rate_limiter,
+            &project_api_key,
+        )
+        .await
+        .map_err(error_to_status);
+        let run = release_on_error(idempotency_key.as_ref(), &self.db, run).await?;
+        let created = create_run(&self.db, &run, RunStatus::Running)
+            .await
+            .map_err(error_to_status);
+        release_on_error(idempotency_key.as_ref(), &self.db, created).await?;
+        let run_info = RunInfo::new(&run);
+        if let Some(idempotency_key) = &idempotency_key {
+            idempotency_key.set_run(&self.db, &run_info.run_id).await;
+        }
+
+        let pipeline_runner = self.pipeline_runner.clone();
+        let db = self.db.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let interrupt_senders = self.interrupt_senders.clone();
+
+        tokio::spawn(async move {
+            let (chunk_tx, mut chunk_rx) = mpsc::channel::<StreamChunk>(8);
+            let forward_tx = tx.clone();
+            let forward = async move {
+                while let Some(chunk) = chunk_rx.recv().await {
+                    let Some(chunk) = stream_chunk_to_proto(chunk).await else {
+                        continue;
+                    };
+                    if forward_tx.send(Ok(chunk)).await.is_err() {
+                        // The client went away, the run still finishes and is recorded
+                        break;
+                    }
+                }
+            };
+            let run = execute_run(
+                run,
+                Some(chunk_tx),
+                &pipeline_runner,
+                &db,
+                &rate_limiter,
+                &project_api_key,
+                &interrupt_senders,
+            );
+            let (run_result, _) = tokio::join!(run, forward);
+
+            let last_chunk = match run_result {
+                Ok(engine_output) => {
+                    let res = run_info.into_response(&engine_output).await;
+                    if let Some(idempotency_key) = &idempotency_key {
+                        idempotency_key
+                            .complete(&db, StatusCode::OK, &encode_response(&res))
+                            .await;
+                    }
+                    Ok(RunPipelineStreamChunk {
+                        chunk: Some(run_pipeline_stream_chunk::Chunk::Output(res)),
+                    })
+                }
+                Err(e) => {
+                    if let Some(idempotency_key) = &idempotency_key {
+                        idempotency_key.release(&db).await;
+                    }
+                    Err(runner_error_to_status(e, run_info.run_id))
+                }
+            };
+            let _ = tx.send(last_chunk).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn cancel_run(
+        &self,
+        request: Request<CancelRunRequest>,
+    ) -> Result<Response<CancelRunResponse>, Status> {
+        let project_api_key = self.authenticate_for_run(&request).await?;
+        let run_id = parse_uuid(&request.get_ref().run_id, "run_id")?;
+
+        let cancelled = runs::cancel_run(
+            &self.db,
+            self.pipeline_runner.http_client(),
+            &self.interrupt_senders,
+            &self.run_execution,
+            run_id,
+            &project_api_key.project_id,
+        )
+        .await
+        .map_err(|e| error_to_status(e.into()))?;
+        if !cancelled {
+            return Err(Status::not_found(format!(
+                "No queued or running run with id {}",
+                run_id
+            )));
+        }
+
+        Ok(Response::new(CancelRunResponse {}))
+    }
+}
+
+/// Run identifiers known before execution, returned along with the outputs
+struct RunInfo {
+    run_id: Uuid,
+    pipeline_version_id: Uuid,
+    pipeline_version_hash: Option<String>,
+}
+
+impl RunInfo {
+    fn new(run: &runs::PreparedRun) -> Self {
+        Self {
+            run_id: run.run_id,
+            pipeline_version_id: run.pipeline_version.id,
+            pipeline_version_hash: run.pipeline_version.content_hash.clone(),
+        }
+    }
+
+    async fn into_response(self, engine_output: &EngineOutput) -> RunPipelineResponse {
+        let mut outputs = HashMap::new();
+        // null outputs of declared bindings have no proto value
+        for (name, output) in engine_output.graph_outputs() {
+            if let Some(value) = output.value {
+                outputs.insert(name, node_input_to_proto(value).await);
+            }
+        }
+        let stats = RunTraceStats::from_messages(&engine_output.messages);
+
+        RunPipelineResponse {
+            run_id: self.run_id.to_string(),
+            pipeline_version_id: self.pipeline_version_id.to_string(),
+            pipeline_version_hash: self.pipeline_version_hash,
+            outputs,
+            usage: Some(Usage {
+                total_token_count: stats.total_token_count,
+                approximate_cost: stats.approximate_cost,
+            }),
+        }
+    }
+}
+
+/// Graph request of a run request and its idempotency key, `method` is the name of the called
+/// method
+async fn run_request(
+    req: RunPipelineRequest,
+    method: &str,
+    project_api_key: &ProjectApiKey,
+) -> Result<(GraphRequest, Option<IdempotencyKey>), Status> {
+    let content_hash = content_hash(&req);
+    let mut req = graph_request(req).await?;
+    let idempotency_key = IdempotencyKey::new(
+        req.idempotency_key.take(),
+        project_api_key.id,
+        &[method, &content_hash],
+    )
+    .map_err(error_to_status)?;
+    Ok((req, idempotency_key))
+}
+
+/// Hash of the request content for its idempotency key
+///
+/// Map fields are hashed in the order of their keys, since they're encoded in the iteration
+/// order of the `HashMap`s.
+fn content_hash(req: &RunPipelineRequest) -> String {
+    let fields = RunPipelineRequest {
+        pipeline: req.pipeline.clone(),
+        pipeline_version: req.pipeline_version.clone(),
+        trace_id: req.trace_id.clone(),
+        parent_span_id: req.parent_span_id.clone(),
+        idempotency_key: req.idempotency_key.clone(),
+        record_node_io: req.record_node_io,
+        ..Default::default()
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(fields.encode_to_vec());
+
+    let mut inputs = req.inputs.iter().collect::<Vec<_>>();
+    inputs.sort_by_key(|(name, _)| *name);
+    for (name, value) in inputs {
+        let value = value.encode_to_vec();
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update((value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    }
+    for map in [&req.env, &req.metadata, &req.credentials] {
+        hasher.update([1]);
+        let mut entries = map.iter().collect::<Vec<_>>();
+        entries.sort();
+        for (key, value) in entries {
+            hasher.update(key.as_bytes());
+            hasher.update([0]);
+            hasher.update(value.as_bytes());
+            hasher.update([0]);
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Responses are stored for idempotency keys as their hex encoded protobuf
+fn encode_response(res: &RunPipelineResponse) -> Value {
+    Value::String(hex::encode(res.encode_to_vec()))
+}
+
+fn decode_response(value: &Value) -> Option<RunPipelineResponse> {
+    let bytes = hex::decode(value.as_str()?).ok()?;
+    RunPipelineResponse::decode(bytes.as_slice()).ok()
+}
+
+async fn graph_request(req: RunPipelineRequest) -> Result<GraphRequest, Status> {
+    let current_trace_and_span = match (req.trace_id, req.parent_span_id) {
+        (Some(trace_id), parent_span_id) => Some(CurrentTraceAndSpan {
+            trace_id: parse_uuid(&trace_id, "trace_id")?,
+            parent_span_id: parent_span_id
+                .map(|id| parse_uuid(&id, "parent_span_id"))
+                .transpose()?,
+        }),
+        (None, Some(_)) => {
+            return Err(Status::invalid_argument(
+                "parent_span_id requires trace_id to be set",
+            ))
+        }
+        (None, None) => None,
+    };
+
+    let mut inputs = HashMap::new();
+    for (name, value) in req.inputs {
+        let input = node_input_from_proto(value)
+            .await
+            .map_err(|e| Status::invalid_argument(format!("Invalid input '{name}': {e}")))?;
+        inputs.insert(name, input);
+    }
+
+    Ok(GraphRequest {
+        pipeline: req.pipeline,
+        pipeline_version: req.pipeline_version,
+        inputs,
+        current_trace_and_span,
+        env: req.env,
+        credentials: req.credentials.into(),
+        metadata: req.metadata,
+        // Streaming is chosen by the method
+        stream: false,
+        idempotency_key: req.idempotency_key,
+        record_node_io: req.record_node_io,
+        trace_mode: Default::default(),
+    })
+}
+
+fn parse_uuid(value: &str, field: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(value)
+        .map_err(|e| Status::invalid_argument(format!("Invalid {field} '{value}': {e}")))
+}
+
+async fn stream_chunk_to_proto(chunk: StreamChunk) -> Option<RunPipelineStreamChunk> {
+    let chunk = match chunk {
+        StreamChunk::NodeChunk(chunk) => run_pipeline_stream_chunk::Chunk::NodeChunk(NodeChunk {
+            node_id: chunk.node_id.to_string(),
+            node_name: chunk.node_name,
+            node_type: chunk.node_type,
+            content: Some(node_input_to_proto(chunk.content).await),
+        }),
+        StreamChunk::NodeEnd(end) => run_pipeline_stream_chunk::Chunk::NodeEnd(NodeEnd {
+            node_id: end.message.node_id.to_string(),
+            node_name: end.message.node_name,
+            node_type: end.message.node_type,
+            output: Some(node_input_to_proto(end.message.value).await),
+        }),
+        // Run output and errors are sent once the run finishes
+        _ => return None,
+    };
+
+    Some(RunPipelineStreamChunk { chunk: Some(chunk) })
+}
+
+async fn node_input_from_proto(value: NodeValue) -> anyhow::Result<NodeInput> {
+    let input = match value.value {
+        Some(node_value::Value::Boolean(value)) => NodeInput::Boolean(value),
+        Some(node_value::Value::String(value)) => NodeInput::String(value),
+        Some(node_value::Value::StringList(list)) => NodeInput::StringList(list.values),
+        Some(node_value::Value::ChatMessageList(list)) => NodeInput::ChatMessageList(
+            list.messages
+                .into_iter()
+                .map(chat_message_from_proto)
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Some(node_value::Value::Float(value)) => NodeInput::Float(value),
+        Some(node_value::Value::Attachment(attachment)) => {
+            let mime_type = if attachment.mime_type.is_empty() {
+                DEFAULT_ATTACHMENT_MIME_TYPE.to_string()
+            } else {
+                attachment.mime_type
+            };
+            NodeInput::File(
+                FileAttachment::from_bytes(attachment.filename, mime_type, &attachment.data)
+                    .await?,
+            )
+        }
+        None => return Err(anyhow::anyhow!("Value is not set")),
+    };
+
+    Ok(input)
+}
+
+async fn node_input_to_proto(input: NodeInput) -> NodeValue {
+    let mut input = input;
+    while let NodeInput::ConditionedValue(conditioned) = input {
+        input = *conditioned.value;
+    }
+
+    let value = match input {
+        NodeInput::Boolean(value) => node_value::Value::Boolean(value),
+        NodeInput::String(value) => node_value::Value::String(value),
+        NodeInput::StringList(values) => node_value::Value::StringList(StringList { values }),
+        NodeInput::ChatMessageList(messages) => {
+            node_value::Value::ChatMessageList(ChatMessageList {
+                messages: messages.into_iter().map(chat_message_to_proto).collect(),
+            })
+        }
+        NodeInput::Float(value) => node_value::Value::Float(value),
+        NodeInput::File(file) => {
+            let data = match file.read().await {
+                Ok(data) => data.to_vec(),
+                Err(e) => {
+                    log::error!("Failed to read output file {:?}: {}", file.path(), e);
+                    vec![]
+                }
+            };
+            node_value::Value::Attachment(Attachment {
+                filename: file.filename,
+                mime_type: file.mime_type,
+                data,
+            })
+        }
+        NodeInput::ConditionedValue(_) => unreachable!("conditioned values are unwrapped above"),
+    };
+
+    NodeValue { value: Some(value) }
+}
+
+fn chat_message_from_proto(
+    message: pipeline_runner_grpc::ChatMessage,
+) -> anyhow::Result<ChatMessage> {
+    let content = match message.content {
+        Some(chat_message::Content::Text(text)) => ChatMessageContent::Text(text),
+        Some(chat_message::Content::Parts(parts)) => ChatMessageContent::ContentPartList(
+            parts
+                .parts
+                .into_iter()
+                .map(|part| match part.part {
+                    Some(chat_message_content_part::Part::Text(text)) => {
+                        Ok(ChatMessageContentPart::Text(ChatMessageText { text }))
+                    }
+                    Some(chat_message_content_part::Part::ImageUrl(image_url)) => {
+                        Ok(ChatMessageContentPart::ImageUrl(ChatMessageImageUrl {
+                            url: image_url.url,
+                            detail: image_url.detail,
+                        }))
+                    }
+                    Some(chat_message_content_part::Part::Image(image)) => {
+                        Ok(ChatMessageContentPart::Image(ChatMessageImage {
+                            media_type: image.media_type,
+                            data: image.data,
+                        }))
+                    }
+                    None => Err(anyhow::anyhow!("Chat message content part is not set")),
+                })
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        None => ChatMessageContent::Text(String::new()),
+    };
+
+    Ok(ChatMessage {
+        role: message.role,
+        content,
+    })
+}
+
+fn chat_message_to_proto(message: ChatMessage) -> pipeline_runner_grpc::ChatMessage {
+    let content = match message.content {
+        ChatMessageContent::Text(text) => chat_message::Content::Text(text),
+        ChatMessageContent::ContentPartList(parts) => {
+            chat_message::Content::Parts(ChatMessageContentPartList {
+                parts: parts
+                    .into_iter()
+                    .map(|part| {
+                        let part = match part {
+                            ChatMessageContentPart::Text(text) => {
+                                chat_message_content_part::Part::Text(text.text)
+                            }
+                            ChatMessageContentPart::ImageUrl(image_url) => {
+                                chat_message_content_part::Part::ImageUrl(
+                                    chat_message_content_part::ImageUrl {
+                                        url: image_url.url,
+                                        detail: image_url.detail,
+                                    },
+                                )
+                            }
+                            ChatMessageContentPart::Image(image) => {
+                                chat_message_content_part::Part::Image(
+                                    chat_message_content_part::Image {
+                                        media_type: image.media_type,
+                                        data: image.data,
+                                    },
+                                )
+                            }
+                        };
+                        pipeline_runner_grpc::ChatMessageContentPart { part: Some(part) }
+                    })
+                    .collect(),
+            })
+        }
+    };
+
+    pipeline_runner_grpc::ChatMessage {
+        role: message.role,
+        content: Some(content),
+    }
+}
+
+fn error_message(error_message: Option<serde_json::Value>) -> String {
+    match error_message {
+        Some(serde_json::Value::String(message)) => message,
+        Some(message) => message.to_string(),
+        None => String::new(),
+    }
+}
+
+fn error_to_status(e: error::Error) -> Status {
+    match e {
+        error::Error::RequestError {
+            error_code,
+            error_message: message,
+        } => Status::invalid_argument(format!("{}: {}", error_code, error_message(message))),
+        error::Error::Forbidden(message) => Status::permission_denied(message),
+        error::Error::Conflict(message) => Status::already_exists(message),
+        e => {
+            log::error!("Internal error in gRPC run: {}", e);
+            Status::internal(e.to_string())
+        }
+    }
+}
+
+fn runner_error_to_status(e: PipelineRunnerError, run_id: Uuid) -> Status {
+    match e {
+        // The partial trace is recorded, and can be looked up by the run id
+        PipelineRunnerError::RunningError(e) => {
+            Status::aborted(format!("Run {} failed: {}", run_id, e))
+        }
+        PipelineRunnerError::UnhandledError(_) | PipelineRunnerError::TraceWritingError(_) => {
+            log::error!("Internal error in gRPC run {}: {}", run_id, e);
+            Status::internal(e.to_string())
+        }
+        e => Status::invalid_argument(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+
+    use super::pipeline_runner_grpc::{
+        pipeline_run_service_client::PipelineRunServiceClient,
+        pipeline_run_service_server::PipelineRunServiceServer,
+    };
+    use super::*;
+
+    /// Runs "echo" pipelines, which output their inputs, to test the wire format without
+    /// the database and engine
+    struct EchoService;
+
+    #[tonic::async_trait]
+    impl PipelineRunService for EchoService {
+        async fn run_pipeline(
+            &self,
+            request: Request<RunPipelineRequest>,
+        ) -> Result<Response<RunPipelineResponse>, Status> {
+            let req = graph_request(request.into_inner()).await?;
+            let mut outputs = HashMap::new();
+            for (name, input) in req.inputs {
+                outputs.insert(name, node_input_to_proto(input).await);
+            }
+            Ok(Response::new(RunPipelineResponse {
+                run_id: Uuid::new_v4().to_string(),
+                pipeline_version_id: Uuid::new_v4().to_string(),
+                pipeline_version_hash: None,
+                outputs,
+                usage: Some(Usage::default()),
+            }))
+        }
+
+        type RunPipelineStreamStream = ReceiverStream<Result<RunPipelineStreamChunk, Status>>;
+
+        async fn run_pipeline_stream(
+            &self,
+            request: Request<RunPipelineRequest>,
+        ) -> Result<Response<Self::RunPipelineStreamStream>, Status> {
+            let req = graph_request(request.into_inner()).await?;
+            let (tx, rx) = mpsc::channel(8);
+            for (name, input) in req.inputs {
+                let chunk = RunPipelineStreamChunk {
+                    chunk: Some(run_pipeline_stream_chunk::Chunk::NodeEnd(NodeEnd {
+                        node_id: Uuid::new_v4().to_string(),
+                        node_name: name,
+                        node_type: "Output".to_string(),
+                        output: Some(node_input_to_proto(input).await),
+                    })),
+                };
+                tx.send(Ok(chunk)).await.unwrap();
+            }
+            tx.send(Err(Status::aborted("done"))).await.unwrap();
+            Ok(Response::new(ReceiverStream::new(rx)))
+        }
+
+        async fn cancel_run(
+            &self,
+            request: Request<CancelRunRequest>,
+        ) -> Result<Response<CancelRunResponse>, Status> {
+            parse_uuid(&request.get_ref().run_id, "run_id")?;
+            Ok(Response::new(CancelRunResponse {}))
+        }
+    }
+
+    async fn echo_client() -> PipelineRunServiceClient<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(PipelineRunServiceServer::new(EchoService))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        PipelineRunServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+
+    fn request_with_inputs(inputs: HashMap<String, NodeValue>) -> RunPipelineRequest {
+        RunPipelineRequest {
+            pipeline: "echo".to_string(),
+            inputs,
+            ..Default::default()
+        }
+    }
+
+    fn chat_messages() -> NodeValue {
+        NodeValue {
+            value: Some(node_value::Value::ChatMessageList(ChatMessageList {
+                messages: vec![
+                    chat_message_to_proto(ChatMessage {
+                        role: "system".to_string(),
+                        content: ChatMessageContent::Text("Be brief".to_string()),
+                    }),
+                    chat_message_to_proto(ChatMessage {
+                        role: "user".to_string(),
+                        content: ChatMessageContent::ContentPartList(vec![
+                            ChatMessageContentPart::Text(ChatMessageText {
+                                text: "What is this?".to_string(),
+                            }),
+                            ChatMessageContentPart::ImageUrl(ChatMessageImageUrl {
+                                url: "https://example.com/cat.png".to_string(),
+                                detail: Some("low".to_string()),
+                            }),
+                        ]),
+                    }),
+                ],
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_pipeline_round_trips_inputs() {
+        let mut client = echo_client().await;
+        let inputs = HashMap::from([
+            (
+                "question".to_string(),
+                NodeValue {
+                    value: Some(node_value::Value::String("hi".to_string())),
+                },
+            ),
+            (
+                "temperature".to_string(),
+                NodeValue {
+                    value: Some(node_value::Value::Float(0.5)),
+                },
+            ),
+            ("messages".to_string(), chat_messages()),
+            (
+                "document".to_string(),
+                NodeValue {
+                    value: Some(node_value::Value::Attachment(Attachment {
+                        filename: "notes.txt".to_string(),
+                        mime_type: "text/plain".to_string(),
+                        data: b"some notes".to_vec(),
+                    })),
+                },
+            ),
+        ]);
+
+        let res = client
+            .run_pipeline(request_with_inputs(inputs.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(res.outputs, inputs);
+    }
+
+    #[tokio::test]
+    async fn test_run_pipeline_rejects_invalid_requests() {
+        let mut client = echo_client().await;
+
+        let unset_input = HashMap::from([("question".to_string(), NodeValue { value: None })]);
+        let status = client
+            .run_pipeline(request_with_inputs(unset_input))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut req = request_with_inputs(HashMap::new());
+        req.parent_span_id = Some(Uuid::new_v4().to_string());
+        let status = client.run_pipeline(req).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = client
+            .cancel_run(CancelRunRequest {
+                run_id: "not-a-uuid".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_run_pipeline_stream() {
+        let mut client = echo_client().await;
+        let inputs = HashMap::from([("messages".to_string(), chat_messages())]);
+
+        let mut stream = client
+            .run_pipeline_stream(request_with_inputs(inputs))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let chunk = stream.next().await.unwrap().unwrap();
+        let Some(run_pipeline_stream_chunk::Chunk::NodeEnd(end)) = chunk.chunk else {
+            panic!("Expected node end chunk, got {:?}", chunk);
+        };
+        assert_eq!(end.node_name, "messages");
+        assert_eq!(end.output, Some(chat_messages()));
+
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_graph_request_maps_run_options() {
+        let mut req = request_with_inputs(HashMap::new());
+        req.credentials = HashMap::from([("openai".to_string(), "sk-test".to_string())]);
+        req.idempotency_key = Some("retry-1".to_string());
+        req.record_node_io = Some(true);
+
+        let graph_req = graph_request(req).await.unwrap();
+        assert_eq!(graph_req.credentials.get("openai"), Some("sk-test"));
+        assert_eq!(graph_req.idempotency_key.as_deref(), Some("retry-1"));
+        assert_eq!(graph_req.record_node_io, Some(true));
+    }
+
+    #[test]
+    fn test_idempotency_content_hash_and_response() {
+        let entries = (0..20).map(|i| (format!("key{i}"), i.to_string()));
+        let mut req =
+            request_with_inputs(HashMap::from([("messages".to_string(), chat_messages())]));
+        req.env = entries.clone().collect();
+        let mut reordered = req.clone();
+        reordered.env = entries.rev().collect();
+        assert_eq!(content_hash(&req), content_hash(&reordered));
+        reordered
+            .credentials
+            .insert("openai".to_string(), "sk-test".to_string());
+        assert_ne!(content_hash(&req), content_hash(&reordered));
+
+        let res = RunPipelineResponse {
+            run_id: Uuid::new_v4().to_string(),
+            outputs: HashMap::from([("messages".to_string(), chat_messages())]),
+            ..Default::default()
+        };
+        assert_eq!(decode_response(&encode_response(&res)), Some(res));
+    }
+}
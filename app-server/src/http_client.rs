@@ -0,0 +1,90 @@
+//! HTTP client shared by language model providers, nodes calling external APIs and file
+//! downloads
+//!
+//! Requests reuse pooled connections to the same host instead of paying for a TLS handshake per
+//! call, and hosts negotiating HTTP/2 multiplex requests over one connection. Proxies are taken
+//! from `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`.
+
+use std::{env, time::Duration};
+
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 256;
+const DEFAULT_POOL_IDLE_TIMEOUT_SECONDS: u64 = 90;
+const DEFAULT_CONNECT_TIMEOUT_SECONDS: u64 = 10;
+/// LLM calls can take minutes to respond, but streams send chunks far more often
+const DEFAULT_READ_TIMEOUT_SECONDS: u64 = 300;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn build_client() -> reqwest::Client {
+    build_client_with_pool(env_or(
+        "HTTP_POOL_MAX_IDLE_PER_HOST",
+        DEFAULT_POOL_MAX_IDLE_PER_HOST,
+    ))
+}
+
+/// Client keeping at most `pool_max_idle_per_host` idle connections to each host
+fn build_client_with_pool(pool_max_idle_per_host: usize) -> reqwest::Client {
+    reqwest::Client::builder()
+        .pool_max_idle_per_host(pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(env_or(
+            "HTTP_POOL_IDLE_TIMEOUT_SECONDS",
+            DEFAULT_POOL_IDLE_TIMEOUT_SECONDS,
+        )))
+        .connect_timeout(Duration::from_secs(env_or(
+            "HTTP_CONNECT_TIMEOUT_SECONDS",
+            DEFAULT_CONNECT_TIMEOUT_SECONDS,
+        )))
+        .read_timeout(Duration::from_secs(env_or(
+            "HTTP_READ_TIMEOUT_SECONDS",
+            DEFAULT_READ_TIMEOUT_SECONDS,
+        )))
+        .tcp_keepalive(Duration::from_secs(60))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::testing::mock_http_provider;
+
+    use super::*;
+
+    async fn call_concurrently(client: &reqwest::Client, url: &str, calls: usize) {
+        let calls = (0..calls)
+            .map(|_| {
+                let (client, url) = (client.clone(), url.to_string());
+                tokio::spawn(async move { client.get(url).send().await?.bytes().await })
+            })
+            .collect::<Vec<_>>();
+        for call in calls {
+            call.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shared_client_reuses_connections() {
+        const POOL_SIZE: usize = 4;
+        const ROUNDS: usize = 10;
+
+        let (url, connections) = mock_http_provider().await;
+        let client = build_client_with_pool(POOL_SIZE);
+        for _ in 0..ROUNDS {
+            call_concurrently(&client, &url, POOL_SIZE).await;
+        }
+        let opened = connections.load(Ordering::SeqCst);
+        assert!(
+            opened <= POOL_SIZE,
+            "{} connections for {} calls with a pool of {}",
+            opened,
+            ROUNDS * POOL_SIZE,
+            POOL_SIZE
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Labeling queues of runs for human review
+//!
+//! A queue is a filter over the runs of a project, e.g. failed runs of a pipeline or runs with a
+//! low score, and a schema of the labels reviewers give them. Reviewers lease the next unlabeled
+//! run of the queue, so that two reviewers don't label the same run, and submit its labels before
+//! the lease expires. Runs whose lease expired without labels return to the queue. Labels can be
+//! exported to a dataset, with the inputs of the runs as data and the labels as targets.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{db::runs::RunStatus, runs::node_io::NodeIoRecord};
+
+pub const DEFAULT_LEASE_SECONDS: i32 = 600;
+
+/// How a run of the queue ended
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    Failed,
+    Cancelled,
+    Interrupted,
+}
+
+impl ErrorKind {
+    pub fn status(&self) -> RunStatus {
+        match self {
+            ErrorKind::Failed => RunStatus::Failed,
+            ErrorKind::Cancelled => RunStatus::Cancelled,
+            ErrorKind::Interrupted => RunStatus::Interrupted,
+        }
+    }
+}
+
+/// Runs with a numeric event of the template, with a value within the bounds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreRange {
+    /// Name of the event template
+    pub event: String,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+/// Filter of the runs of a project which are items of a queue, runs match all set conditions
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueFilter {
+    #[serde(default)]
+    pub pipeline_id: Option<Uuid>,
+    #[serde(default)]
+    pub score: Option<ScoreRange>,
+    /// Name of a boolean event template, e.g. of a moderation check. Runs with the event set to
+    /// true are flagged.
+    #[serde(default)]
+    pub flag_event: Option<String>,
+    #[serde(default)]
+    pub error_kind: Option<ErrorKind>,
+    /// Case-insensitive text of the run's error
+    #[serde(default)]
+    pub error_contains: Option<String>,
+}
+
+impl QueueFilter {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(score) = &self.score {
+            if score.event.is_empty() {
+                return Err(anyhow::anyhow!("Score event must not be empty"));
+            }
+            if let (Some(min), Some(max)) = (score.min, score.max) {
+                if min > max {
+                    return Err(anyhow::anyhow!("Score range is empty"));
+                }
+            }
+        }
+        if self
+            .flag_event
+            .as_ref()
+            .is_some_and(|event| event.is_empty())
+        {
+            return Err(anyhow::anyhow!("Flag event must not be empty"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LabelKind {
+    /// One of the options
+    Categorical { options: Vec<String> },
+    Numeric {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    Text {
+        #[serde(default, rename = "maxLength")]
+        max_length: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LabelField {
+    pub name: String,
+    pub kind: LabelKind,
+    /// Labels without the field, or with it set to null, are rejected
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LabelSchema {
+    #[serde(default)]
+    pub fields: Vec<LabelField>,
+}
+
+impl LabelSchema {
+    pub fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            if field.name.is_empty() {
+                return Err(anyhow::anyhow!("Label field names must not be empty"));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(anyhow::anyhow!("Label field {} is repeated", field.name));
+            }
+            match &field.kind {
+                LabelKind::Categorical { options } if options.is_empty() => {
+                    return Err(anyhow::anyhow!(
+                        "Categorical label field {} has no options",
+                        field.name
+                    ));
+                }
+                LabelKind::Numeric {
+                    min: Some(min),
+                    max: Some(max),
+                } if min > max => {
+                    return Err(anyhow::anyhow!(
+                        "Range of numeric label field {} is empty",
+                        field.name
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the values of a label against the schema
+    pub fn check_values(&self, values: &Map<String, Value>) -> Result<(), String> {
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.fields.iter().any(|field| &field.name == *name))
+        {
+            return Err(format!("Label field {} is not in the schema", name));
+        }
+
+        for field in &self.fields {
+            let value = match values.get(&field.name) {
+                None | Some(Value::Null) if field.required => {
+                    return Err(format!("Label field {} is required", field.name));
+                }
+                None | Some(Value::Null) => continue,
+                Some(value) => value,
+            };
+            match &field.kind {
+                LabelKind::Categorical { options } => {
+                    let is_option = value
+                        .as_str()
+                        .is_some_and(|value| options.iter().any(|option| option == value));
+                    if !is_option {
+                        return Err(format!(
+                            "Label field {} must be one of: {}",
+                            field.name,
+                            options.join(", ")
+                        ));
+                    }
+                }
+                LabelKind::Numeric { min, max } => {
+                    let Some(number) = value.as_f64() else {
+                        return Err(format!("Label field {} must be a number", field.name));
+                    };
+                    if min.is_some_and(|min| number < min) || max.is_some_and(|max| number > max) {
+                        return Err(format!("Label field {} is out of range", field.name));
+                    }
+                }
+                LabelKind::Text { max_length } => {
+                    let Some(text) = value.as_str() else {
+                        return Err(format!("Label field {} must be a string", field.name));
+                    };
+                    if let Some(max_length) =
+                        max_length.filter(|max_length| text.chars().count() > *max_length)
+                    {
+                        return Err(format!(
+                            "Label field {} must be at most {} characters",
+                            field.name, max_length
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Inputs of a run by input node name, from its recorded node inputs and outputs
+pub fn run_inputs(records: &[NodeIoRecord]) -> Map<String, Value> {
+    records
+        .iter()
+        .filter(|record| record.node_type == "Input" && record.execution == 0)
+        .filter_map(|record| {
+            let value = record.output.value.clone()?;
+            Some((record.node_name.clone(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schema() -> LabelSchema {
+        serde_json::from_value(json!({
+            "fields": [
+                {"name": "verdict", "kind": {"type": "categorical", "options": ["good", "bad"]}, "required": true},
+                {"name": "quality", "kind": {"type": "numeric", "min": 0, "max": 5}},
+                {"name": "comment", "kind": {"type": "text", "maxLength": 10}},
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn check(values: Value) -> Result<(), String> {
+        schema().check_values(values.as_object().unwrap())
+    }
+
+    #[test]
+    fn test_label_values_are_checked_against_schema() {
+        assert!(schema().validate().is_ok());
+        assert!(check(json!({"verdict": "good", "quality": 4.5, "comment": "fine"})).is_ok());
+        assert!(check(json!({"verdict": "bad", "quality": null})).is_ok());
+
+        assert!(check(json!({"quality": 1})).is_err());
+        assert!(check(json!({"verdict": "ok"})).is_err());
+        assert!(check(json!({"verdict": "good", "quality": 6})).is_err());
+        assert!(check(json!({"verdict": "good", "quality": "high"})).is_err());
+        assert!(check(json!({"verdict": "good", "comment": "far too long"})).is_err());
+        assert!(check(json!({"verdict": "good", "other": 1})).is_err());
+    }
+
+    #[test]
+    fn test_invalid_schemas() {
+        let repeated = LabelSchema {
+            fields: vec![schema().fields[0].clone(), schema().fields[0].clone()],
+        };
+        assert!(repeated.validate().is_err());
+        let no_options = LabelSchema {
+            fields: vec![LabelField {
+                name: "verdict".to_string(),
+                kind: LabelKind::Categorical { options: vec![] },
+                required: false,
+            }],
+        };
+        assert!(no_options.validate().is_err());
+
+        let filter = QueueFilter {
+            score: Some(ScoreRange {
+                event: "relevance".to_string(),
+                min: Some(0.8),
+                max: Some(0.2),
+            }),
+            ..Default::default()
+        };
+        assert!(filter.validate().is_err());
+    }
+}
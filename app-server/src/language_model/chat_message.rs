@@ -1,11 +1,12 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, PartialEq, ToSchema)]
 pub struct ChatMessageText {
     pub text: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ChatMessageImageUrl {
     pub url: String,
@@ -13,14 +14,14 @@ pub struct ChatMessageImageUrl {
     pub detail: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ChatMessageImage {
     pub media_type: String, // e.g. "image/jpeg"
     pub data: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 #[serde(tag = "type")]
 pub enum ChatMessageContentPart {
     #[serde(rename = "text")]
@@ -31,14 +32,14 @@ pub enum ChatMessageContentPart {
     Image(ChatMessageImage),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 #[serde(untagged)]
 pub enum ChatMessageContent {
     Text(String),
     ContentPartList(Vec<ChatMessageContentPart>),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, PartialEq, ToSchema)]
 pub struct ChatMessage {
     pub role: String,
     pub content: ChatMessageContent,
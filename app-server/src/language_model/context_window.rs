@@ -0,0 +1,460 @@
+//! Fitting the messages of a chat into the context window of its model
+//!
+//! Chats of long sessions eventually exceed the context of their model, and the provider rejects
+//! them. An LLM node with a `contextWindow` counts the tokens of its messages before each call,
+//! and if they don't fit the model's context, less the tokens left for the completion, evicts
+//! messages by its strategy:
+//!
+//! - `dropOldest` evicts the oldest turns until the rest fit
+//! - `keepLastTurns` keeps at most the last `turns` turns, fewer if they don't fit
+//! - `summarize` evicts like `dropOldest`, and has a cheaper `model` summarize the evicted turns
+//!   into a system message after the prompt
+//!
+//! Messages are evicted by turns, a user message and the messages which answer it, so a tool call
+//! is never evicted without its results. System messages are never evicted, and neither is the
+//! last turn, so a chat whose last turn doesn't fit is still sent. The applied strategy and the
+//! evicted tokens are recorded in the meta log and on the span of the node.
+//!
+//! Tokens are counted with the tokenizer of the model if tiktoken knows it, and with the one of
+//! `gpt-4` otherwise, which approximates the counts of other providers' models.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiktoken_rs::{
+    get_bpe_from_tokenizer,
+    tokenizer::{get_tokenizer, Tokenizer},
+    CoreBPE,
+};
+
+use crate::secrets::ResolvedEnv;
+
+use super::{
+    ChatMessage, ChatMessageContent, ChatMessageContentPart, LanguageModelRunner, NodeInfo,
+};
+
+/// Tokens left for the completion if the params of the call don't set `max_tokens`
+const DEFAULT_COMPLETION_TOKENS: u32 = 1024;
+/// Tokens the summary of the evicted turns may take
+const SUMMARY_MAX_TOKENS: u32 = 512;
+/// Every reply is primed with `<|start|>assistant<|message|>`
+const REPLY_TOKENS: u32 = 3;
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below for an assistant which will \
+continue it without seeing it. Keep the facts, names, decisions and open questions, and leave \
+out greetings and small talk. Answer with the summary only.";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "camelCase")]
+pub enum ContextStrategy {
+    DropOldest,
+    KeepLastTurns {
+        turns: usize,
+    },
+    /// Evict the oldest turns, and keep their summary by the `provider:model`
+    Summarize {
+        model: String,
+    },
+}
+
+impl ContextStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DropOldest => "dropOldest",
+            Self::KeepLastTurns { .. } => "keepLastTurns",
+            Self::Summarize { .. } => "summarize",
+        }
+    }
+}
+
+/// Context management of an LLM node, e.g. `{"strategy": "keepLastTurns", "turns": 10}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextWindow {
+    #[serde(flatten)]
+    pub strategy: ContextStrategy,
+    /// Context size of the model in tokens, for models whose size isn't known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_size: Option<u32>,
+}
+
+/// Strategy which was applied to the messages of a call, and what it evicted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextWindowLog {
+    pub strategy: String,
+    pub context_size: u32,
+    /// Tokens of the messages before eviction
+    pub input_tokens: u32,
+    pub evicted_messages: usize,
+    pub evicted_tokens: u32,
+    /// Tokens of the summary of the evicted turns, if they were summarized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_tokens: Option<u32>,
+}
+
+/// Context size in tokens of the `provider:model`, if it's known
+pub fn context_size(model: &str) -> Option<u32> {
+    let name = model.split_once(':').map_or(model, |(_, name)| name);
+    let name = name.to_lowercase();
+    if name.starts_with("gpt-4o") || name.starts_with("gpt-4-turbo") {
+        Some(128_000)
+    } else if name.starts_with("gpt-4-32k") {
+        Some(32_768)
+    } else if name.starts_with("gpt-4") {
+        Some(8_192)
+    } else if name.starts_with("gpt-3.5-turbo") {
+        Some(16_385)
+    } else if name.contains("claude-3") {
+        Some(200_000)
+    } else if name.starts_with("gemini-1.5") {
+        Some(1_048_576)
+    } else if name.starts_with("gemini") {
+        Some(32_760)
+    } else if name.starts_with("mistral-large") || name.starts_with("open-mistral-nemo") {
+        Some(128_000)
+    } else if name.starts_with("mistral") || name.starts_with("open-mixtral") {
+        Some(32_000)
+    } else if name.starts_with("llama-3.1") {
+        Some(131_072)
+    } else if name.starts_with("llama3") || name.starts_with("llama-3") {
+        Some(8_192)
+    } else if name.starts_with("mixtral-8x7b") {
+        Some(32_768)
+    } else {
+        None
+    }
+}
+
+pub struct TokenCounter {
+    bpe: CoreBPE,
+}
+
+impl TokenCounter {
+    pub fn for_model(model: &str) -> Result<Self> {
+        let name = model.split_once(':').map_or(model, |(_, name)| name);
+        let tokenizer = get_tokenizer(name).unwrap_or(Tokenizer::Cl100kBase);
+        Ok(Self {
+            bpe: get_bpe_from_tokenizer(tokenizer)?,
+        })
+    }
+
+    pub fn count(&self, message: &ChatMessage) -> u32 {
+        // every message follows <im_start>{role/name}\n{content}<im_end>\n
+        let mut tokens = 3 + self.count_text(&message.role);
+        match &message.content {
+            ChatMessageContent::Text(text) => tokens += self.count_text(text),
+            ChatMessageContent::ContentPartList(parts) => {
+                for part in parts {
+                    tokens += match part {
+                        ChatMessageContentPart::Text(text) => self.count_text(&text.text),
+                        // start and end tags
+                        ChatMessageContentPart::Image(image) => 2 + self.count_text(&image.data),
+                        ChatMessageContentPart::ImageUrl(image_url) => {
+                            2 + self.count_text(&image_url.url)
+                        }
+                    };
+                }
+            }
+        }
+        tokens
+    }
+
+    pub fn count_text(&self, text: &str) -> u32 {
+        self.bpe.encode_with_special_tokens(text).len() as u32
+    }
+
+    /// Longest prefix of the text with at most the tokens
+    pub fn truncate_text(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.bpe.encode_with_special_tokens(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        // a token may end inside a multi-byte character, which doesn't decode on its own
+        (0..=max_tokens)
+            .rev()
+            .find_map(|len| self.bpe.decode(tokens[..len].to_vec()).ok())
+            .unwrap_or_default()
+    }
+
+    /// Prompt tokens of a call with the messages, including the priming of the reply
+    pub fn count_chat(&self, messages: &[ChatMessage]) -> u32 {
+        REPLY_TOKENS
+            + messages
+                .iter()
+                .map(|message| self.count(message))
+                .sum::<u32>()
+    }
+}
+
+/// Messages of the chat which fit the budget, and the ones evicted, each in order
+struct Fitted {
+    kept: Vec<ChatMessage>,
+    evicted: Vec<ChatMessage>,
+    evicted_tokens: u32,
+}
+
+/// Evict the oldest turns, past the last `max_turns` if set, until the rest fit the budget
+fn fit(
+    messages: Vec<ChatMessage>,
+    budget: u32,
+    max_turns: Option<usize>,
+    tokens: &[u32],
+) -> Fitted {
+    // turn of each message, unset for system messages
+    let mut turn_of = Vec::with_capacity(messages.len());
+    let mut turn_tokens = Vec::<u32>::new();
+    for (message, message_tokens) in messages.iter().zip(tokens) {
+        if message.role == "system" {
+            turn_of.push(None);
+            continue;
+        }
+        if message.role == "user" || turn_tokens.is_empty() {
+            turn_tokens.push(0);
+        }
+        *turn_tokens.last_mut().unwrap() += message_tokens;
+        turn_of.push(Some(turn_tokens.len() - 1));
+    }
+
+    let mut total = REPLY_TOKENS + tokens.iter().sum::<u32>();
+    let mut first_kept = 0;
+    let last_turn = turn_tokens.len().saturating_sub(1);
+    if let Some(max_turns) = max_turns {
+        while first_kept < last_turn && turn_tokens.len() - first_kept > max_turns.max(1) {
+            total -= turn_tokens[first_kept];
+            first_kept += 1;
+        }
+    }
+    while first_kept < last_turn && total > budget {
+        total -= turn_tokens[first_kept];
+        first_kept += 1;
+    }
+
+    let mut fitted = Fitted {
+        kept: Vec::new(),
+        evicted: Vec::new(),
+        evicted_tokens: turn_tokens[..first_kept].iter().sum(),
+    };
+    for (message, turn) in messages.into_iter().zip(turn_of) {
+        match turn {
+            Some(turn) if turn < first_kept => fitted.evicted.push(message),
+            _ => fitted.kept.push(message),
+        }
+    }
+    fitted
+}
+
+impl ContextWindow {
+    /// Messages of the call which fit the context of the model, leaving the tokens of
+    /// `max_tokens` of the params for the completion, with the log of what was evicted. Messages
+    /// of models whose context size isn't known are sent as they are, without a log.
+    pub async fn apply(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: &str,
+        params: &Value,
+        language_model: &LanguageModelRunner,
+        env: &ResolvedEnv,
+        node_info: &NodeInfo,
+    ) -> Result<(Vec<ChatMessage>, Option<ContextWindowLog>)> {
+        let Some(context_size) = self.context_size.or_else(|| context_size(model)) else {
+            log::warn!(
+                "Context size of {} is unknown, messages of LLM node {} are sent as they are",
+                model,
+                node_info.node_name
+            );
+            return Ok((messages, None));
+        };
+        let completion_tokens = params
+            .get("max_tokens")
+            .and_then(Value::as_u64)
+            .map_or(DEFAULT_COMPLETION_TOKENS, |max_tokens| max_tokens as u32);
+        let counter = TokenCounter::for_model(model)?;
+        let tokens = messages
+            .iter()
+            .map(|message| counter.count(message))
+            .collect::<Vec<_>>();
+        let input_tokens = REPLY_TOKENS + tokens.iter().sum::<u32>();
+
+        let mut budget = context_size.saturating_sub(completion_tokens);
+        let max_turns = match &self.strategy {
+            ContextStrategy::DropOldest => None,
+            ContextStrategy::KeepLastTurns { turns } => Some(*turns),
+            ContextStrategy::Summarize { .. } => {
+                if input_tokens > budget {
+                    budget = budget.saturating_sub(SUMMARY_MAX_TOKENS);
+                }
+                None
+            }
+        };
+        let mut fitted = fit(messages, budget, max_turns, &tokens);
+        let mut log = ContextWindowLog {
+            strategy: self.strategy.name().to_string(),
+            context_size,
+            input_tokens,
+            evicted_messages: fitted.evicted.len(),
+            evicted_tokens: fitted.evicted_tokens,
+            summary_tokens: None,
+        };
+
+        if let ContextStrategy::Summarize { model } = &self.strategy {
+            if !fitted.evicted.is_empty() {
+                let summary =
+                    summarize(&fitted.evicted, model, language_model, env, node_info).await?;
+                log.summary_tokens = Some(counter.count(&summary));
+                let position = fitted
+                    .kept
+                    .iter()
+                    .position(|message| message.role != "system")
+                    .unwrap_or(fitted.kept.len());
+                fitted.kept.insert(position, summary);
+            }
+        }
+        if input_tokens - fitted.evicted_tokens > budget {
+            log::warn!(
+                "Last turn of LLM node {} doesn't fit the context of {}",
+                node_info.node_name,
+                model
+            );
+        }
+        Ok((fitted.kept, Some(log)))
+    }
+}
+
+/// System message with the summary of the evicted messages by the model
+async fn summarize(
+    evicted: &[ChatMessage],
+    model: &str,
+    language_model: &LanguageModelRunner,
+    env: &ResolvedEnv,
+    node_info: &NodeInfo,
+) -> Result<ChatMessage> {
+    let transcript = evicted
+        .iter()
+        .map(|message| format!("{}: {}", message.role, message_text(message)))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let messages = vec![
+        ChatMessage {
+            role: "system".to_string(),
+            content: ChatMessageContent::Text(SUMMARY_PROMPT.to_string()),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: ChatMessageContent::Text(transcript),
+        },
+    ];
+    let params = json!({"max_tokens": SUMMARY_MAX_TOKENS});
+    let completion = language_model
+        .chat_completion(model, &messages, &params, env, None, node_info)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to summarize evicted turns: {}", e))?;
+
+    Ok(ChatMessage {
+        role: "system".to_string(),
+        content: ChatMessageContent::Text(format!(
+            "Summary of the earlier conversation:\n{}",
+            completion.text_message()
+        )),
+    })
+}
+
+/// Text parts of the message, images are left out of summaries
+fn message_text(message: &ChatMessage) -> String {
+    match &message.content {
+        ChatMessageContent::Text(text) => text.clone(),
+        ChatMessageContent::ContentPartList(parts) => parts
+            .iter()
+            .filter_map(|part| match part {
+                ChatMessageContentPart::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, text: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: ChatMessageContent::Text(text.to_string()),
+        }
+    }
+
+    fn roles(messages: &[ChatMessage]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| format!("{}:{}", message.role, message_text(message)))
+            .collect()
+    }
+
+    fn chat() -> Vec<ChatMessage> {
+        vec![
+            message("system", "prompt"),
+            message("user", "1"),
+            message("assistant", "call"),
+            message("tool", "result"),
+            message("assistant", "1"),
+            message("user", "2"),
+            message("assistant", "2"),
+            message("user", "3"),
+        ]
+    }
+
+    #[test]
+    fn test_oldest_turns_are_evicted_whole() {
+        let tokens = vec![10; 8];
+        // 3 reply tokens, the prompt and the last two turns
+        let fitted = fit(chat(), 3 + 10 + 30, None, &tokens);
+        assert_eq!(
+            roles(&fitted.kept),
+            vec!["system:prompt", "user:2", "assistant:2", "user:3"]
+        );
+        assert_eq!(
+            roles(&fitted.evicted),
+            vec!["user:1", "assistant:call", "tool:result", "assistant:1"]
+        );
+        assert_eq!(fitted.evicted_tokens, 40);
+
+        // the prompt and the last turn are kept even if they don't fit
+        let fitted = fit(chat(), 0, None, &tokens);
+        assert_eq!(roles(&fitted.kept), vec!["system:prompt", "user:3"]);
+    }
+
+    #[test]
+    fn test_last_turns_are_kept() {
+        let tokens = vec![10; 8];
+        let fitted = fit(chat(), u32::MAX, Some(2), &tokens);
+        assert_eq!(
+            roles(&fitted.kept),
+            vec!["system:prompt", "user:2", "assistant:2", "user:3"]
+        );
+        let fitted = fit(chat(), u32::MAX, Some(5), &tokens);
+        assert!(fitted.evicted.is_empty());
+    }
+
+    #[test]
+    fn test_strategy_config() {
+        let window = serde_json::from_value::<ContextWindow>(json!({
+            "strategy": "summarize",
+            "model": "openai:gpt-4o-mini",
+        }))
+        .unwrap();
+        assert_eq!(
+            window.strategy,
+            ContextStrategy::Summarize {
+                model: "openai:gpt-4o-mini".to_string()
+            }
+        );
+        assert_eq!(
+            context_size("anthropic:claude-3-haiku-20240307"),
+            Some(200_000)
+        );
+        assert_eq!(context_size("openai:gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_size("mock:model"), None);
+    }
+}
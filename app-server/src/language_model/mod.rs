@@ -1,4 +1,5 @@
 mod chat_message;
+pub mod context_window;
 pub mod providers;
 mod runner;
 
@@ -8,6 +9,7 @@ pub use providers::anthropic_bedrock::AnthropicBedrock;
 pub use providers::gemini::Gemini;
 pub use providers::groq::Groq;
 pub use providers::mistral::Mistral;
+pub use providers::mock::{MockFailure, MockLlmProvider};
 pub use providers::openai::OpenAI;
 pub use providers::openai_azure::OpenAIAzure;
 pub use runner::*;
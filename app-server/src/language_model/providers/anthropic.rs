@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use crate::language_model::chat_message::{ChatChoice, ChatCompletion, ChatMessage, ChatUsage};
 use crate::language_model::providers::utils::calculate_cost;
 use crate::language_model::runner::ExecuteChatCompletion;
@@ -8,6 +6,7 @@ use crate::language_model::{
     ChatMessageText, LanguageModelProviderName, NodeInfo,
 };
 use crate::pipeline::nodes::{NodeStreamChunk, StreamChunk};
+use crate::secrets::ResolvedEnv;
 use anyhow::Result;
 use futures::stream::StreamExt;
 use json_value_merge::Merge;
@@ -197,7 +196,7 @@ impl ExecuteChatCompletion for Anthropic {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
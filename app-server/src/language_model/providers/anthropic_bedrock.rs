@@ -6,6 +6,7 @@ use crate::language_model::{
     ChatChoice, ChatMessageContent, ChatUsage, LanguageModelProviderName, NodeInfo,
 };
 use crate::pipeline::nodes::{NodeStreamChunk, StreamChunk};
+use crate::secrets::ResolvedEnv;
 use anyhow::Result;
 use aws_config::Region;
 use aws_credential_types::Credentials;
@@ -40,7 +41,7 @@ impl ExecuteChatCompletion for AnthropicBedrock {
         _provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -134,10 +135,7 @@ impl ExecuteChatCompletion for AnthropicBedrock {
                 .collect::<Vec<_>>()
         };
 
-        let region = env
-            .get(AWS_REGION)
-            .cloned()
-            .unwrap_or("us-east-1".to_string());
+        let region = env.get(AWS_REGION).unwrap_or("us-east-1").to_string();
 
         let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
             .credentials_provider(Credentials::new(
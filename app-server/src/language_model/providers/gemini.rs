@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use anyhow::Result;
 use futures::stream::StreamExt;
 use reqwest_eventsource::{Event, EventSource};
@@ -14,6 +12,7 @@ use crate::{
         LanguageModelProviderName, NodeInfo,
     },
     pipeline::nodes::{NodeStreamChunk, StreamChunk},
+    secrets::ResolvedEnv,
 };
 
 use crate::language_model::providers::utils::calculate_cost;
@@ -140,7 +139,7 @@ impl ExecuteChatCompletion for Gemini {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use futures::StreamExt;
 use json_value_merge::Merge;
 
@@ -16,6 +14,7 @@ use crate::{
         LanguageModelProviderName, NodeInfo,
     },
     pipeline::nodes::{NodeStreamChunk, StreamChunk},
+    secrets::ResolvedEnv,
 };
 
 use crate::language_model::providers::utils::calculate_cost;
@@ -62,7 +61,7 @@ impl ExecuteChatCompletion for Groq {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -1,9 +1,8 @@
-use std::collections::HashMap;
-
 use crate::language_model::chat_message::{ChatCompletion, ChatMessage};
 use crate::language_model::runner::ExecuteChatCompletion;
 use crate::language_model::{LanguageModelProviderName, NodeInfo};
 use crate::pipeline::nodes::StreamChunk;
+use crate::secrets::ResolvedEnv;
 use anyhow::Result;
 use json_value_merge::Merge;
 use serde_json::{json, Value};
@@ -29,7 +28,7 @@ impl ExecuteChatCompletion for Mistral {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         _tx: Option<Sender<StreamChunk>>,
         _node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -0,0 +1,409 @@
+//! Provider of canned completions, for tests of pipelines with LLM nodes
+//!
+//! Nodes select it with `mock:<any model>` models, if the runner has it, or all models are
+//! served by it with [`LanguageModelRunner::mocked`](crate::language_model::LanguageModelRunner::mocked).
+//! Completions are matched by a substring of the prompt, in the order the matches were added,
+//! or else taken from the sequence of responses. Calls are recorded, and clones of the provider
+//! share the responses and calls, so a test keeps a clone to inspect them.
+//!
+//! ```ignore
+//! let mock = MockLlmProvider::new()
+//!     .respond_to("Translate", "Bonjour")
+//!     .then_fail(MockFailure::RateLimited)
+//!     .then_respond("{\"answer\": 42}")
+//!     .streaming(4, Duration::from_millis(10));
+//! let services = OfflineServices::with_language_model(LanguageModelRunner::mocked(mock.clone()));
+//! ```
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    language_model::{
+        ChatChoice, ChatCompletion, ChatMessage, ChatMessageContent, ChatMessageContentPart,
+        ChatUsage, ExecuteChatCompletion, LanguageModelProviderName, NodeInfo,
+    },
+    pipeline::nodes::{NodeStreamChunk, StreamChunk},
+    secrets::ResolvedEnv,
+};
+
+use super::utils::calculate_cost;
+
+/// Failure of a mocked call, as the provider's API would fail
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockFailure {
+    /// 429 Too Many Requests
+    RateLimited,
+    /// No response within the duration
+    Timeout(Duration),
+    /// A response body which isn't a valid completion
+    MalformedJson,
+}
+
+#[derive(Debug, Clone)]
+enum MockResponse {
+    Text(String),
+    Failure(MockFailure),
+}
+
+/// Call the mock received
+#[derive(Debug, Clone)]
+pub struct MockCall {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub params: Value,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    matches: Vec<(String, MockResponse)>,
+    sequence: VecDeque<MockResponse>,
+    calls: Vec<MockCall>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockLlmProvider {
+    state: Arc<Mutex<MockState>>,
+    /// Characters per chunk, and the delay before each chunk, of streamed completions
+    stream: (usize, Duration),
+    /// Prompt and completion tokens of every completion, counted by words if unset
+    usage: Option<(u32, u32)>,
+    /// Prices per million prompt and completion tokens
+    prices: (f64, f64),
+    /// Stream delays and timeouts sleep on it
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MockLlmProvider {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState::default())),
+            stream: (usize::MAX, Duration::ZERO),
+            usage: None,
+            prices: (1.0, 2.0),
+            clock: SystemClock::shared(),
+        }
+    }
+}
+
+impl MockLlmProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Complete any prompt containing `substring` with `text`, every time it's called with one
+    pub fn respond_to(self, substring: &str, text: &str) -> Self {
+        self.lock()
+            .matches
+            .push((substring.to_string(), MockResponse::Text(text.to_string())));
+        self
+    }
+
+    /// Fail calls with prompts containing `substring`
+    pub fn fail_on(self, substring: &str, failure: MockFailure) -> Self {
+        self.lock()
+            .matches
+            .push((substring.to_string(), MockResponse::Failure(failure)));
+        self
+    }
+
+    /// Complete the next call no match is found for with `text`
+    pub fn then_respond(self, text: &str) -> Self {
+        self.lock()
+            .sequence
+            .push_back(MockResponse::Text(text.to_string()));
+        self
+    }
+
+    /// Fail the next call no match is found for
+    pub fn then_fail(self, failure: MockFailure) -> Self {
+        self.lock()
+            .sequence
+            .push_back(MockResponse::Failure(failure));
+        self
+    }
+
+    /// Stream completions in chunks of `chunk_chars` characters, waiting `delay` before each
+    pub fn streaming(mut self, chunk_chars: usize, delay: Duration) -> Self {
+        self.stream = (chunk_chars.max(1), delay);
+        self
+    }
+
+    /// Report the same usage for every completion
+    pub fn usage(mut self, prompt_tokens: u32, completion_tokens: u32) -> Self {
+        self.usage = Some((prompt_tokens, completion_tokens));
+        self
+    }
+
+    /// Prices per million prompt and completion tokens, which costs are estimated with
+    pub fn prices(mut self, input_price: f64, output_price: f64) -> Self {
+        self.prices = (input_price, output_price);
+        self
+    }
+
+    /// Sleep on the clock, e.g. a `ManualClock` so that timeouts don't take real time
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Calls received so far, in order
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.lock().calls.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        // a test panicking while holding the lock doesn't make the responses invalid
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn next_response(&self, call: MockCall) -> Result<MockResponse> {
+        let prompt = prompt_text(&call.messages);
+        let mut state = self.lock();
+        state.calls.push(call);
+        let matched = state
+            .matches
+            .iter()
+            .find(|(substring, _)| prompt.contains(substring.as_str()))
+            .map(|(_, response)| response.clone());
+        matched
+            .or_else(|| state.sequence.pop_front())
+            .ok_or_else(|| anyhow::anyhow!("Mock has no response for prompt: {}", prompt))
+    }
+}
+
+/// Text of the messages, which matches are looked up in
+fn prompt_text(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| match &message.content {
+            ChatMessageContent::Text(text) => text.clone(),
+            ChatMessageContent::ContentPartList(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ChatMessageContentPart::Text(text) => Some(text.text.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join(""),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn count_tokens(text: &str) -> u32 {
+    text.split_whitespace().count() as u32
+}
+
+impl ExecuteChatCompletion for MockLlmProvider {
+    async fn chat_completion(
+        &self,
+        model: &str,
+        _provider_name: LanguageModelProviderName,
+        messages: &Vec<ChatMessage>,
+        params: &Value,
+        _env: &ResolvedEnv,
+        tx: Option<Sender<StreamChunk>>,
+        node_info: &NodeInfo,
+    ) -> Result<ChatCompletion> {
+        let call = MockCall {
+            model: model.to_string(),
+            messages: messages.clone(),
+            params: params.clone(),
+        };
+        let text = match self.next_response(call)? {
+            MockResponse::Text(text) => text,
+            MockResponse::Failure(MockFailure::RateLimited) => {
+                return Err(anyhow::anyhow!(
+                    "Error. Status code: {}",
+                    reqwest::StatusCode::TOO_MANY_REQUESTS
+                ));
+            }
+            MockResponse::Failure(MockFailure::Timeout(duration)) => {
+                self.clock.sleep(duration).await;
+                return Err(anyhow::anyhow!("Request timed out after {:?}", duration));
+            }
+            MockResponse::Failure(MockFailure::MalformedJson) => {
+                let body = r#"{"choices": [{"message": {"role": "assistant", "content": "#;
+                return Err(serde_json::from_str::<ChatCompletion>(body)
+                    .err()
+                    .unwrap()
+                    .into());
+            }
+        };
+
+        if let Some(tx) = tx {
+            let (chunk_chars, delay) = self.stream;
+            let chars = text.chars().collect::<Vec<_>>();
+            for chunk in chars.chunks(chunk_chars) {
+                self.clock.sleep(delay).await;
+                let stream_chunk = StreamChunk::NodeChunk(NodeStreamChunk {
+                    id: node_info.id,
+                    node_id: node_info.node_id,
+                    node_name: node_info.node_name.clone(),
+                    node_type: node_info.node_type.clone(),
+                    content: chunk.iter().collect::<String>().into(),
+                });
+                tx.send(stream_chunk)
+                    .await
+                    .map_err(|_| anyhow::anyhow!("Stream of the completion is closed"))?;
+            }
+        }
+
+        let (prompt_tokens, completion_tokens) = self
+            .usage
+            .unwrap_or_else(|| (count_tokens(&prompt_text(messages)), count_tokens(&text)));
+        Ok(ChatCompletion::new(
+            vec![ChatChoice::new(ChatMessage {
+                role: "assistant".to_string(),
+                content: ChatMessageContent::Text(text),
+            })],
+            ChatUsage {
+                completion_tokens,
+                prompt_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+                approximate_cost: self.estimate_cost(model, completion_tokens, prompt_tokens),
+            },
+            model.to_string(),
+        ))
+    }
+
+    fn estimate_input_cost(&self, _model: &str, prompt_tokens: u32) -> Option<f64> {
+        Some(calculate_cost(prompt_tokens, self.prices.0))
+    }
+
+    fn estimate_output_cost(&self, _model: &str, completion_tokens: u32) -> Option<f64> {
+        Some(calculate_cost(completion_tokens, self.prices.1))
+    }
+
+    fn estimate_cost(
+        &self,
+        model: &str,
+        completion_tokens: u32,
+        prompt_tokens: u32,
+    ) -> Option<f64> {
+        let input_cost = self.estimate_input_cost(model, prompt_tokens)?;
+        let output_cost = self.estimate_output_cost(model, completion_tokens)?;
+        Some(input_cost + output_cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::clock::ManualClock;
+
+    use super::*;
+
+    fn node_info() -> NodeInfo {
+        NodeInfo {
+            id: Uuid::new_v4(),
+            node_id: Uuid::new_v4(),
+            node_name: "llm".to_string(),
+            node_type: "LLM".to_string(),
+        }
+    }
+
+    async fn complete(
+        mock: &MockLlmProvider,
+        prompt: &str,
+        tx: Option<Sender<StreamChunk>>,
+    ) -> Result<ChatCompletion> {
+        let messages = vec![ChatMessage {
+            role: "system".to_string(),
+            content: ChatMessageContent::Text(prompt.to_string()),
+        }];
+        mock.chat_completion(
+            "test-model",
+            LanguageModelProviderName::Mock,
+            &messages,
+            &Value::Null,
+            &ResolvedEnv::default(),
+            tx,
+            &node_info(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_matches_come_before_sequence() {
+        let mock = MockLlmProvider::new()
+            .respond_to("French", "Bonjour")
+            .then_respond("first")
+            .then_respond("second answer");
+
+        let completion = complete(&mock, "Say hi in French", None).await.unwrap();
+        assert_eq!(completion.text_message(), "Bonjour");
+        let completion = complete(&mock, "Say hi", None).await.unwrap();
+        assert_eq!(completion.text_message(), "first");
+        let completion = complete(&mock, "Say hi twice", None).await.unwrap();
+        assert_eq!(completion.text_message(), "second answer");
+        assert_eq!(completion.usage.prompt_tokens, 3);
+        assert_eq!(completion.usage.completion_tokens, 2);
+        let cost = completion.usage.approximate_cost.unwrap();
+        assert!((cost - 7.0 / 1_000_000.0).abs() < 1e-12);
+
+        assert!(complete(&mock, "Say hi again", None).await.is_err());
+        // the same French prompt matches every time
+        assert!(complete(&mock, "French, again", None).await.is_ok());
+        assert_eq!(mock.calls().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_streams_chunks() {
+        let mock = MockLlmProvider::new()
+            .then_respond("Hello")
+            .streaming(2, Duration::from_millis(1))
+            .usage(100, 10);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        let completion = complete(&mock, "Greet", Some(tx)).await.unwrap();
+        let mut chunks = Vec::new();
+        while let Some(StreamChunk::NodeChunk(chunk)) = rx.recv().await {
+            let content: String = chunk.content.into();
+            chunks.push(content);
+        }
+        assert_eq!(chunks, vec!["He", "ll", "o"]);
+        assert_eq!(completion.usage.total_tokens, 110);
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let clock = ManualClock::default();
+        let mock = MockLlmProvider::new()
+            .then_fail(MockFailure::RateLimited)
+            .then_fail(MockFailure::MalformedJson)
+            .then_fail(MockFailure::Timeout(Duration::from_secs(30)))
+            .then_respond("recovered")
+            .clock(Arc::new(clock.clone()));
+
+        let e = complete(&mock, "prompt", None).await.unwrap_err();
+        assert!(e.to_string().contains("429"));
+        let e = complete(&mock, "prompt", None).await.unwrap_err();
+        assert!(e.downcast_ref::<serde_json::Error>().is_some());
+
+        let timed_out = {
+            let mock = mock.clone();
+            tokio::spawn(async move { complete(&mock, "prompt", None).await })
+        };
+        clock.wait_for_sleepers(1).await;
+        clock.advance(Duration::from_secs(29));
+        tokio::task::yield_now().await;
+        assert!(!timed_out.is_finished());
+        clock.advance(Duration::from_secs(1));
+        let e = timed_out.await.unwrap().unwrap_err();
+        assert!(e.to_string().contains("timed out"));
+
+        let completion = complete(&mock, "prompt", None).await.unwrap();
+        assert_eq!(completion.text_message(), "recovered");
+    }
+}
@@ -3,6 +3,7 @@ pub mod anthropic_bedrock;
 pub mod gemini;
 pub mod groq;
 pub mod mistral;
+pub mod mock;
 pub mod openai;
 pub mod openai_azure;
 pub mod utils;
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use futures::stream::StreamExt;
 use json_value_merge::Merge;
 
@@ -16,6 +14,7 @@ use crate::language_model::{
     ChatChoice, ChatCompletion, ChatMessage, ChatMessageContent, ChatMessageContentPart, ChatUsage,
     LanguageModelProviderName, NodeInfo,
 };
+use crate::secrets::ResolvedEnv;
 
 use crate::language_model::runner::ExecuteChatCompletion;
 use crate::pipeline::nodes::{NodeStreamChunk, StreamChunk};
@@ -195,7 +194,7 @@ impl ExecuteChatCompletion for OpenAI {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use futures::StreamExt;
 use json_value_merge::Merge;
 
@@ -10,6 +8,7 @@ use serde_json::{json, Value};
 use tokio::sync::mpsc::Sender;
 
 use crate::language_model::chat_message::{ChatCompletion, ChatMessage};
+use crate::secrets::ResolvedEnv;
 
 use crate::language_model::runner::ExecuteChatCompletion;
 use crate::language_model::{
@@ -50,7 +49,7 @@ impl ExecuteChatCompletion for OpenAIAzure {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -257,7 +256,7 @@ impl ExecuteChatCompletion for OpenAIAzure {
     }
 }
 
-fn build_endpoint_name(env: &HashMap<String, String>) -> Result<String> {
+fn build_endpoint_name(env: &ResolvedEnv) -> Result<String> {
     let resource_id = env
         .get(&String::from(OPENAI_AZURE_RESOURCE_ID))
         .map(ToOwned::to_owned)
@@ -6,6 +6,7 @@ use anyhow::Result;
 use serde_json::{json, Value};
 use tokio::sync::mpsc::Sender;
 
+use crate::secrets::ResolvedEnv;
 use crate::language_model::chat_message::{ChatCompletion, ChatMessage};
 
 use crate::language_model::runner::ExecuteChatCompletion;
@@ -37,7 +38,7 @@ impl ExecuteChatCompletion for Unify {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         _tx: Option<Sender<StreamChunk>>,
         _node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -19,6 +19,15 @@ pub fn get_required_env_vars_for_model(model: &str) -> HashSet<String> {
     }
 }
 
+/// Env var of the API key of the model's provider, unset for unknown providers and mocks
+pub fn get_api_key_env_var_for_model(model: &str) -> Option<&'static str> {
+    let provider = LanguageModelProviderName::from_str(get_provider(model)?).ok()?;
+    provider
+        .required_env_vars()
+        .contains(provider.api_key_name())
+        .then(|| provider.api_key_name())
+}
+
 pub fn calculate_cost(tokens: u32, price_per_million_tokens: f64) -> f64 {
     (tokens as f64 / 1_000_000.0) * price_per_million_tokens
 }
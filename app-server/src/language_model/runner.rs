@@ -8,6 +8,7 @@ use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
 use crate::pipeline::nodes::StreamChunk;
+use crate::secrets::ResolvedEnv;
 
 use super::{
     chat_message::ChatCompletion,
@@ -16,7 +17,8 @@ use super::{
         openai_azure::{OPENAI_AZURE_DEPLOYMENT_NAME, OPENAI_AZURE_RESOURCE_ID},
         utils::get_provider,
     },
-    Anthropic, AnthropicBedrock, ChatMessage, Gemini, Groq, Mistral, OpenAI, OpenAIAzure,
+    Anthropic, AnthropicBedrock, ChatMessage, Gemini, Groq, Mistral, MockLlmProvider, OpenAI,
+    OpenAIAzure,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -28,6 +30,7 @@ pub enum LanguageModelProviderName {
     Gemini,
     Groq,
     Bedrock,
+    Mock,
 }
 
 #[derive(Clone, Debug)]
@@ -40,8 +43,11 @@ pub enum LanguageModelProvider {
     OpenAI(OpenAI),
     OpenAIAzure(OpenAIAzure),
     Bedrock(AnthropicBedrock),
+    Mock(MockLlmProvider),
 }
 
+// implemented by the providers of this crate only, which are all `Send`
+#[allow(async_fn_in_trait)]
 #[enum_dispatch(LanguageModelProvider)]
 pub trait ExecuteChatCompletion {
     async fn chat_completion(
@@ -50,7 +56,7 @@ pub trait ExecuteChatCompletion {
         provider_name: LanguageModelProviderName,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion>;
@@ -73,6 +79,7 @@ impl LanguageModelProviderName {
             "gemini" => Ok(Self::Gemini),
             "groq" => Ok(Self::Groq),
             "bedrock" => Ok(Self::Bedrock),
+            "mock" => Ok(Self::Mock),
             _ => Err(anyhow::anyhow!("Invalid language model provider: {}", s)),
         }
     }
@@ -86,17 +93,17 @@ impl LanguageModelProviderName {
             Self::Gemini => "gemini",
             Self::Groq => "groq",
             Self::Bedrock => "bedrock",
+            Self::Mock => "mock",
         }
     }
 
-    pub fn api_key(&self, env: &HashMap<String, String>) -> Result<String> {
+    pub fn api_key<'a>(&self, env: &'a ResolvedEnv) -> Result<&'a str> {
         let name = self.api_key_name();
         env.get(name)
-            .cloned()
             .ok_or(anyhow::anyhow!("Env variables don't contain: {}", name))
     }
 
-    fn api_key_name(&self) -> &str {
+    pub fn api_key_name(&self) -> &'static str {
         match self {
             LanguageModelProviderName::Anthropic => "ANTHROPIC_API_KEY",
             LanguageModelProviderName::Mistral => "MISTRAL_API_KEY",
@@ -105,11 +112,16 @@ impl LanguageModelProviderName {
             LanguageModelProviderName::Gemini => "GEMINI_API_KEY",
             LanguageModelProviderName::Groq => "GROQ_API_KEY",
             LanguageModelProviderName::Bedrock => AWS_SECRET_ACCESS_KEY,
+            // never read, mocks don't need one
+            LanguageModelProviderName::Mock => "MOCK_API_KEY",
         }
     }
 
     pub fn required_env_vars(&self) -> HashSet<String> {
         let mut env_vars = HashSet::new();
+        if matches!(self, Self::Mock) {
+            return env_vars;
+        }
         env_vars.insert(self.api_key_name().to_string());
 
         if matches!(self, Self::Bedrock) {
@@ -134,6 +146,65 @@ impl LanguageModelRunner {
         Self { models }
     }
 
+    /// Runner whose models of any provider are all served by the mock, for tests
+    pub fn mocked(provider: MockLlmProvider) -> Self {
+        let models = [
+            LanguageModelProviderName::Anthropic,
+            LanguageModelProviderName::Mistral,
+            LanguageModelProviderName::OpenAI,
+            LanguageModelProviderName::OpenAIAzure,
+            LanguageModelProviderName::Gemini,
+            LanguageModelProviderName::Groq,
+            LanguageModelProviderName::Bedrock,
+            LanguageModelProviderName::Mock,
+        ]
+        .into_iter()
+        .map(|name| (name, LanguageModelProvider::Mock(provider.clone())))
+        .collect();
+        Self { models }
+    }
+
+    /// Runner of the providers the server calls, sharing the HTTP client
+    pub async fn with_default_providers(client: reqwest::Client) -> Self {
+        let bedrock_client = aws_sdk_bedrockruntime::Client::new(
+            &aws_config::defaults(aws_config::BehaviorVersion::latest())
+                .region(aws_config::Region::new("us-east-1"))
+                .load()
+                .await,
+        );
+        let models = HashMap::from([
+            (
+                LanguageModelProviderName::Anthropic,
+                LanguageModelProvider::Anthropic(Anthropic::new(client.clone())),
+            ),
+            (
+                LanguageModelProviderName::OpenAI,
+                LanguageModelProvider::OpenAI(OpenAI::new(client.clone())),
+            ),
+            (
+                LanguageModelProviderName::OpenAIAzure,
+                LanguageModelProvider::OpenAIAzure(OpenAIAzure::new(client.clone())),
+            ),
+            (
+                LanguageModelProviderName::Gemini,
+                LanguageModelProvider::Gemini(Gemini::new(client.clone())),
+            ),
+            (
+                LanguageModelProviderName::Groq,
+                LanguageModelProvider::Groq(Groq::new(client.clone())),
+            ),
+            (
+                LanguageModelProviderName::Mistral,
+                LanguageModelProvider::Mistral(Mistral::new(client)),
+            ),
+            (
+                LanguageModelProviderName::Bedrock,
+                LanguageModelProvider::Bedrock(AnthropicBedrock::new(bedrock_client)),
+            ),
+        ]);
+        Self { models }
+    }
+
     /// Completes the chat by calling model's executor
     ///
     /// # Arguments
@@ -150,7 +221,7 @@ impl LanguageModelRunner {
         model: &str,
         messages: &Vec<ChatMessage>,
         params: &Value,
-        env: &HashMap<String, String>,
+        env: &ResolvedEnv,
         tx: Option<Sender<StreamChunk>>,
         node_info: &NodeInfo,
     ) -> Result<ChatCompletion> {
@@ -161,7 +232,10 @@ impl LanguageModelRunner {
         }
         let provider_name = LanguageModelProviderName::from_str(provider)?;
 
-        let executor = self.models.get(&provider_name).unwrap();
+        let executor = self
+            .models
+            .get(&provider_name)
+            .ok_or_else(|| anyhow::anyhow!("Language model provider {} is not set up", provider))?;
         executor
             .chat_completion(
                 model_name.as_str(),
@@ -0,0 +1,35 @@
+//! Modules of the app server
+//!
+//! The server itself is `main.rs`, which also runs the commands of `cli`. The modules are built
+//! as a library so that benchmarks in `benches/` can drive the engine with the same code the
+//! server runs.
+
+pub mod api;
+pub mod auth;
+pub mod cache;
+pub mod ch;
+pub mod chunk;
+pub mod cli;
+pub mod clock;
+pub mod datasets;
+pub mod db;
+pub mod dead_letters;
+pub mod engine;
+pub mod evaluations;
+pub mod files;
+pub mod grpc;
+pub mod http_client;
+pub mod labeling;
+pub mod language_model;
+pub mod opentelemetry;
+pub mod pipeline;
+pub mod retention;
+pub mod routes;
+pub mod runs;
+pub mod secrets;
+pub mod semantic_search;
+pub mod traces;
+pub mod webhooks;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
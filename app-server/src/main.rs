@@ -1,13 +1,17 @@
-use actix_service::Service;
 use actix_web::{
     middleware::{Logger, NormalizePath},
     web::{self, PayloadConfig},
-    App, HttpMessage, HttpServer,
+    App, HttpServer,
 };
 use actix_web_httpauth::middleware::HttpAuthentication;
+use app_server::{
+    api, auth, cache, ch, chunk, cli, db, engine, files, grpc, http_client, language_model,
+    pipeline, retention, routes, runs, semantic_search, traces,
+};
 use dashmap::DashMap;
 use db::{api_keys::ProjectApiKey, pipelines::PipelineVersion, user::User};
 use files::FileManager;
+use grpc::pipeline_runner_grpc::pipeline_run_service_server::PipelineRunServiceServer;
 use traces::{observation_collector, OBSERVATIONS_EXCHANGE, OBSERVATIONS_QUEUE};
 
 use cache::{cache::CacheTrait, Cache};
@@ -15,7 +19,6 @@ use chunk::{
     character_split::CharacterSplitChunker,
     runner::{Chunker, ChunkerRunner, ChunkerType},
 };
-use language_model::{LanguageModelProvider, LanguageModelProviderName};
 use lapin::{
     options::{ExchangeDeclareOptions, QueueDeclareOptions},
     types::FieldTable,
@@ -28,22 +31,6 @@ use std::{any::TypeId, collections::HashMap, env, sync::Arc};
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-mod api;
-mod auth;
-mod cache;
-mod ch;
-mod chunk;
-mod datasets;
-mod db;
-mod engine;
-mod files;
-mod language_model;
-mod opentelemetry;
-mod pipeline;
-mod routes;
-mod semantic_search;
-mod traces;
-
 const DEFAULT_CACHE_SIZE: u64 = 100; // entries
 
 #[tokio::main]
@@ -52,15 +39,29 @@ async fn main() -> std::io::Result<()> {
         .install_default()
         .expect("Failed to install rustls crypto provider");
 
+    match cli::parse_args(env::args().skip(1)) {
+        Ok(Some(command)) => std::process::exit(cli::run(command).await),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    }
+
     dotenv::dotenv().ok();
 
     std::env::set_var("RUST_LOG", "info");
     env_logger::init();
+    engine::trace::init_from_env();
 
     let port = env::var("PORT")
         .unwrap_or(String::from("8000"))
         .parse()
         .unwrap();
+    let grpc_port: u16 = env::var("GRPC_PORT")
+        .unwrap_or(String::from("8001"))
+        .parse()
+        .unwrap();
 
     let semantic_search_url =
         env::var("SEMANTIC_SEARCH_URL").expect("SEMANTIC_SEARCH_URL must be set");
@@ -70,7 +71,6 @@ async fn main() -> std::io::Result<()> {
             .await
             .unwrap(),
     );
-    let semantic_search = Arc::new(semantic_search::SemanticSearch::new(semantic_search_client));
 
     let mut caches: HashMap<TypeId, Arc<dyn CacheTrait>> = HashMap::new();
     let auth_cache: Arc<MokaCache<String, User>> = Arc::new(MokaCache::new(DEFAULT_CACHE_SIZE));
@@ -90,56 +90,17 @@ async fn main() -> std::io::Result<()> {
     let pool = sqlx::postgres::PgPool::connect(&db_url).await.unwrap();
 
     let db = Arc::new(db::DB::new(pool));
+    let record_owners: Arc<dyn db::records::RecordOwners> = db.clone();
 
-    let client = reqwest::Client::new();
-    let anthropic = language_model::Anthropic::new(client.clone());
-    let openai = language_model::OpenAI::new(client.clone());
-    let openai_azure = language_model::OpenAIAzure::new(client.clone());
-    let gemini = language_model::Gemini::new(client.clone());
-    let groq = language_model::Groq::new(client.clone());
-    let mistral = language_model::Mistral::new(client.clone());
-
-    let mut language_models: HashMap<LanguageModelProviderName, LanguageModelProvider> =
-        HashMap::new();
-    language_models.insert(
-        LanguageModelProviderName::Anthropic,
-        LanguageModelProvider::Anthropic(anthropic),
-    );
-    language_models.insert(
-        LanguageModelProviderName::OpenAI,
-        LanguageModelProvider::OpenAI(openai),
-    );
-    language_models.insert(
-        LanguageModelProviderName::OpenAIAzure,
-        LanguageModelProvider::OpenAIAzure(openai_azure),
-    );
-    language_models.insert(
-        LanguageModelProviderName::Gemini,
-        LanguageModelProvider::Gemini(gemini),
-    );
-    language_models.insert(
-        LanguageModelProviderName::Groq,
-        LanguageModelProvider::Groq(groq),
-    );
-    language_models.insert(
-        LanguageModelProviderName::Mistral,
-        LanguageModelProvider::Mistral(mistral),
-    );
-    language_models.insert(
-        LanguageModelProviderName::Bedrock,
-        LanguageModelProvider::Bedrock(language_model::AnthropicBedrock::new(
-            aws_sdk_bedrockruntime::Client::new(
-                &aws_config::defaults(aws_config::BehaviorVersion::latest())
-                    .region(aws_config::Region::new("us-east-1"))
-                    .load()
-                    .await,
-            ),
-        )),
-    );
+    let semantic_search = Arc::new(semantic_search::SemanticSearch::new(
+        semantic_search_client,
+        db.clone(),
+    ));
 
-    let language_model_runner = Arc::new(language_model::LanguageModelRunner::new(language_models));
+    let client = http_client::build_client();
+    let language_model_runner =
+        Arc::new(language_model::LanguageModelRunner::with_default_providers(client.clone()).await);
 
-    let document_client = reqwest::Client::new();
     let mut chunkers = HashMap::new();
     let character_split_chunker = CharacterSplitChunker {};
     chunkers.insert(
@@ -147,9 +108,12 @@ async fn main() -> std::io::Result<()> {
         Chunker::CharacterSplit(character_split_chunker),
     );
     let chunker_runner = Arc::new(ChunkerRunner::new(chunkers));
-    let file_manager = Arc::new(FileManager::new(document_client, chunker_runner.clone()));
+    let file_manager = Arc::new(FileManager::new(client.clone(), chunker_runner.clone()));
+
+    let api_key_rate_limiter = Arc::new(auth::rate_limit::ApiKeyRateLimiter::default());
 
     let interrupt_senders = Arc::new(DashMap::<Uuid, mpsc::Sender<GraphInterruptMessage>>::new());
+    let engine_stats = Arc::new(runs::EngineStats::default());
 
     let rabbitmq_url = env::var("RABBITMQ_URL").expect("RABBITMQ_URL must be set");
     let rabbitmq_connection = Arc::new(
@@ -196,17 +160,118 @@ async fn main() -> std::io::Result<()> {
         .await
         .unwrap();
 
-    HttpServer::new(move || {
-        let auth = HttpAuthentication::bearer(auth::validator);
-        let project_auth = HttpAuthentication::bearer(auth::project_validator);
-        let shared_secret_auth = HttpAuthentication::bearer(auth::shared_secret_validator);
+    let node_io_store = runs::node_io::store_from_env(db.clone());
+    let checkpoint_store: Arc<dyn runs::checkpoints::CheckpointStore> =
+        Arc::new(runs::checkpoints::PostgresCheckpointStore::new(db.clone()));
+    tokio::task::spawn(runs::sweep_expired_run_results(
+        db.clone(),
+        node_io_store.clone(),
+        checkpoint_store.clone(),
+    ));
+    tokio::task::spawn(retention::sweep_expired_data(
+        db.clone(),
+        clickhouse.clone(),
+        node_io_store.clone(),
+    ));
+    let span_exporter = Arc::new(ch::exporter::SpanExporter::start(
+        clickhouse.clone(),
+        db.clone(),
+    ));
 
-        let pipeline_runner = Arc::new(pipeline::runner::PipelineRunner::new(
+    let file_pipelines = Arc::new(pipeline::file_source::FilePipelines::default());
+    if let Ok(pipelines_dir) = env::var("PIPELINES_DIR") {
+        file_pipelines
+            .watch(pipelines_dir.into())
+            .expect("Failed to watch PIPELINES_DIR");
+    }
+
+    let run_execution = runs::queue::RunExecution::from_env(rabbitmq_connection.clone()).await;
+    if let runs::queue::RunExecution::Queue(queue) = &run_execution {
+        tokio::task::spawn(runs::queue::listen_for_cancellations(
+            queue.clone(),
+            interrupt_senders.clone(),
+        ));
+        let worker_concurrency = runs::queue::worker_concurrency();
+        if worker_concurrency > 0 {
+            let worker = Arc::new(runs::queue::RunWorker::new(
+                queue.clone(),
+                Arc::new(
+                    pipeline::runner::PipelineRunner::new(
+                        language_model_runner.clone(),
+                        chunker_runner.clone(),
+                        semantic_search.clone(),
+                        client.clone(),
+                        Some(rabbitmq_connection.clone()),
+                        node_io_store.clone(),
+                        checkpoint_store.clone(),
+                    )
+                    .with_span_exporter(span_exporter.clone()),
+                ),
+                db.clone(),
+                api_key_rate_limiter.clone(),
+                interrupt_senders.clone(),
+            ));
+            log::info!(
+                "Executing runs from the run queue, {} at a time",
+                worker_concurrency
+            );
+            tokio::task::spawn(worker.run(worker_concurrency));
+        }
+    }
+
+    let grpc_pipeline_runner = Arc::new(
+        pipeline::runner::PipelineRunner::new(
             language_model_runner.clone(),
             chunker_runner.clone(),
             semantic_search.clone(),
-            rabbitmq_connection.clone(),
-        ));
+            client.clone(),
+            Some(rabbitmq_connection.clone()),
+            node_io_store.clone(),
+            checkpoint_store.clone(),
+        )
+        .with_span_exporter(span_exporter.clone()),
+    );
+    let grpc_service = grpc::PipelineRunGrpcService::new(
+        grpc_pipeline_runner,
+        db.clone(),
+        cache.clone(),
+        api_key_rate_limiter.clone(),
+        interrupt_senders.clone(),
+        run_execution.clone(),
+    );
+    let grpc_addr = std::net::SocketAddr::from(([0, 0, 0, 0], grpc_port));
+    tokio::task::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(PipelineRunServiceServer::new(grpc_service))
+            .serve(grpc_addr)
+            .await
+        {
+            log::error!("gRPC server error: {}", e);
+        }
+    });
+
+    let shutdown_engine_stats = engine_stats.clone();
+    let shutdown_interrupt_senders = interrupt_senders.clone();
+
+    let server = HttpServer::new(move || {
+        let auth = HttpAuthentication::bearer(auth::validator);
+        let project_auth = HttpAuthentication::bearer(auth::project_validator);
+        let shared_secret_auth = HttpAuthentication::bearer(auth::shared_secret_validator);
+        let workspace_member_auth = HttpAuthentication::bearer(auth::workspace_member_validator);
+        let project_member_auth = HttpAuthentication::bearer(auth::project_member_validator);
+
+        let pipeline_runner = Arc::new(
+            pipeline::runner::PipelineRunner::new(
+                language_model_runner.clone(),
+                chunker_runner.clone(),
+                semantic_search.clone(),
+                client.clone(),
+                Some(rabbitmq_connection.clone()),
+                node_io_store.clone(),
+                checkpoint_store.clone(),
+            )
+            .with_span_exporter(span_exporter.clone()),
+        );
 
         tokio::task::spawn(observation_collector(
             pipeline_runner.clone(),
@@ -214,7 +279,7 @@ async fn main() -> std::io::Result<()> {
             cache.clone(),
             language_model_runner.clone(),
             rabbitmq_connection.clone(),
-            clickhouse.clone(),
+            span_exporter.clone(),
         ));
 
         App::new()
@@ -222,28 +287,66 @@ async fn main() -> std::io::Result<()> {
             .wrap(NormalizePath::trim())
             .app_data(web::Data::from(cache.clone()))
             .app_data(web::Data::from(db.clone()))
+            .app_data(web::Data::from(record_owners.clone()))
             .app_data(web::Data::new(pipeline_runner.clone()))
             .app_data(web::Data::new(file_manager.clone()))
             .app_data(web::Data::new(semantic_search.clone()))
             .app_data(web::Data::new(interrupt_senders.clone()))
+            .app_data(web::Data::new(engine_stats.clone()))
+            .app_data(web::Data::new(run_execution.clone()))
+            .app_data(web::Data::new(file_pipelines.clone()))
+            .app_data(web::Data::new(api_key_rate_limiter.clone()))
             .app_data(web::Data::new(language_model_runner.clone()))
             .app_data(web::Data::new(rabbitmq_connection.clone()))
             .app_data(web::Data::new(clickhouse.clone()))
+            .app_data(web::Data::new(span_exporter.clone()))
             // Scopes with specific auth or no auth
+            .configure(api::openapi::configure)
+            .service(
+                web::scope("/health")
+                    .service(routes::health::live)
+                    .service(routes::health::ready),
+            )
             .service(
                 web::scope("api/v1/auth")
-                    .wrap(shared_secret_auth)
+                    .wrap(shared_secret_auth.clone())
                     .service(routes::auth::signin),
             )
+            .service(
+                web::scope("api/v1/admin")
+                    .wrap(shared_secret_auth)
+                    .service(routes::dead_letters::get_dead_letter_stats)
+                    .service(routes::dead_letters::get_dead_letters)
+                    .service(routes::dead_letters::purge_dead_letters)
+                    .service(routes::dead_letters::get_dead_letter)
+                    .service(routes::dead_letters::retry_dead_letter)
+                    .service(routes::dead_letters::delete_dead_letter),
+            )
             .service(
                 web::scope("/v1")
                     .wrap(project_auth.clone())
                     .service(api::v1::pipelines::run_pipeline_graph)
                     .service(api::v1::pipelines::ping_healthcheck)
+                    .service(api::v1::runs::submit_run)
+                    .service(api::v1::runs::get_run)
+                    .service(api::v1::runs::cancel_run)
+                    .service(api::v1::runs::get_node_io)
+                    .service(api::v1::runs::get_run_graph_dot)
+                    .service(api::v1::runs::replay_run)
+                    .service(api::v1::file_pipelines::get_file_pipelines)
+                    .service(api::v1::file_pipelines::run_file_pipeline)
                     .service(api::v1::traces::get_events_for_session)
+                    .service(api::v1::traces::export_trace)
                     .service(api::v1::evaluations::create_evaluation)
                     .service(api::v1::evaluations::upload_evaluation_datapoints)
                     .service(api::v1::evaluations::update_evaluation)
+                    .service(api::v1::evaluations::run_evaluation)
+                    .service(api::v1::evaluations::get_evaluation)
+                    .service(api::v1::evaluations::diff_evaluations)
+                    .service(api::v1::evaluations::compare_evaluations)
+                    .service(api::v1::evaluations::create_evaluation_backfill)
+                    .service(api::v1::evaluations::get_evaluation_backfill)
+                    .service(api::v1::evaluations::get_evaluation_backfill_scores)
                     .service(api::v1::traces::process_traces)
                     .service(api::v1::metrics::process_metrics)
                     .app_data(PayloadConfig::new(10 * 1024 * 1024)),
@@ -251,12 +354,21 @@ async fn main() -> std::io::Result<()> {
             // Scopes with generic auth
             .service(
                 web::scope("/api/v1/workspaces")
+                    // registered first to run after `auth`, which sets the user
+                    .wrap(workspace_member_auth)
                     .wrap(auth.clone())
                     .service(routes::workspace::get_all_workspaces_of_user)
                     .service(routes::workspace::get_workspace)
                     .service(routes::workspace::create_workspace)
                     .service(routes::workspace::can_add_users_to_workspace)
-                    .service(routes::workspace::add_user_to_workspace),
+                    .service(routes::workspace::add_user_to_workspace)
+                    .service(routes::workspace::get_retention_policy)
+                    .service(routes::workspace::update_retention_policy)
+                    .service(routes::workspace::get_retention_purges)
+                    .service(routes::workspace::get_model_defaults)
+                    .service(routes::workspace::update_model_defaults)
+                    .service(routes::workspace::get_run_limits)
+                    .service(routes::workspace::update_run_limits),
             )
             .service(
                 web::scope("/api/v1/limits")
@@ -265,6 +377,11 @@ async fn main() -> std::io::Result<()> {
                     .service(routes::limits::get_workspace_stats)
                     .service(routes::limits::get_user_storage_stats),
             )
+            .service(
+                web::scope("/api/v1/node_types")
+                    .wrap(auth.clone())
+                    .service(routes::node_types::get_node_types),
+            )
             .service(
                 web::scope("/api/v1/projects")
                     .wrap(auth)
@@ -272,35 +389,14 @@ async fn main() -> std::io::Result<()> {
                     .service(routes::projects::get_projects)
                     .service(
                         web::scope("/{project_id}")
-                            .wrap_fn(|req: actix_web::dev::ServiceRequest, srv| {
-                                let project_id =
-                                    Uuid::parse_str(req.match_info().get("project_id").unwrap())
-                                        .unwrap();
-                                let user: User;
-                                {
-                                    let binding = req.extensions();
-                                    // it is safe to unwrap here because if this middle runs user is present in the request
-                                    user = binding.get::<User>().cloned().unwrap();
-                                }
-                                if user.project_ids.as_ref().unwrap().contains(&project_id) {
-                                    srv.call(req)
-                                } else {
-                                    // return unauthorized
-                                    log::error!(
-                                        "Unauthorized, user {:} is not part of project {}",
-                                        user.id,
-                                        project_id
-                                    );
-                                    Box::pin(futures_util::future::err(
-                                        actix_web::error::ErrorUnauthorized(""),
-                                    ))
-                                }
-                            })
+                            .wrap(project_member_auth)
                             .service(routes::projects::get_project)
                             .service(routes::projects::delete_project)
                             .service(routes::pipelines::run_pipeline_graph)
                             .service(routes::pipelines::get_pipelines)
                             .service(routes::pipelines::create_pipeline)
+                            .service(routes::pipelines::import_pipeline)
+                            .service(routes::pipelines::validate_pipeline_graph)
                             .service(routes::pipelines::update_pipeline)
                             .service(routes::pipelines::get_pipeline_by_id)
                             .service(routes::pipelines::delete_pipeline)
@@ -316,9 +412,27 @@ async fn main() -> std::io::Result<()> {
                             .service(routes::pipelines::create_template)
                             .service(routes::pipelines::run_pipeline_interrupt_graph)
                             .service(routes::pipelines::update_target_pipeline_version)
+                            .service(routes::pipelines::promote_pipeline_version)
+                            .service(routes::pipelines::delete_pipeline_version)
+                            .service(routes::pipelines::get_pipeline_runs)
+                            .service(routes::pipelines::export_pipeline)
+                            .service(routes::pipelines::get_pipeline_graph_dot)
+                            .service(routes::pipelines::get_pipeline_diff)
+                            .service(routes::pipelines::get_pipeline_input_schema)
+                            .service(routes::pipelines::get_node_model_config)
+                            .service(routes::pipelines::simulate_pipeline)
+                            .service(routes::pipelines::get_node_routes)
+                            .service(routes::webhooks::create_webhook)
+                            .service(routes::webhooks::get_webhooks)
+                            .service(routes::webhooks::enable_webhook)
+                            .service(routes::webhooks::delete_webhook)
+                            .service(routes::secrets::create_secret)
+                            .service(routes::secrets::get_secrets)
+                            .service(routes::secrets::delete_secret)
                             .service(routes::api_keys::create_project_api_key)
                             .service(routes::api_keys::get_api_keys_for_project)
                             .service(routes::api_keys::revoke_project_api_key)
+                            .service(routes::api_keys::get_api_key_usage)
                             .service(routes::evaluations::get_evaluation)
                             .service(routes::evaluations::delete_evaluation)
                             .service(routes::evaluations::get_evaluation_datapoint)
@@ -328,6 +442,7 @@ async fn main() -> std::io::Result<()> {
                             .service(routes::datasets::rename_dataset)
                             .service(routes::datasets::delete_dataset)
                             .service(routes::datasets::upload_datapoint_file)
+                            .service(routes::datasets::import_datapoints)
                             .service(routes::datasets::create_datapoints)
                             .service(routes::datasets::get_datapoints)
                             .service(routes::datasets::update_datapoint_data)
@@ -340,6 +455,7 @@ async fn main() -> std::io::Result<()> {
                             .service(routes::evaluations::get_evaluation_datapoint)
                             .service(routes::traces::get_traces)
                             .service(routes::traces::get_single_trace)
+                            .service(routes::traces::delete_user_traces)
                             .service(routes::traces::get_single_span)
                             .service(routes::events::get_event_templates)
                             .service(routes::events::get_event_template)
@@ -348,11 +464,35 @@ async fn main() -> std::io::Result<()> {
                             .service(routes::events::get_events_by_template_id)
                             .service(routes::events::get_events_metrics)
                             .service(routes::events::get_events)
-                            .service(routes::traces::get_traces_metrics),
+                            .service(routes::traces::get_traces_metrics)
+                            .service(routes::labeling_queues::create_queue)
+                            .service(routes::labeling_queues::get_queues)
+                            .service(routes::labeling_queues::lease_next_run)
+                            .service(routes::labeling_queues::submit_label)
+                            .service(routes::labeling_queues::get_labels)
+                            .service(routes::labeling_queues::export_labels)
+                            .service(routes::prompts::create_prompt_version)
+                            .service(routes::prompts::get_prompts)
+                            .service(routes::prompts::get_prompt)
+                            .service(routes::prompts::set_prompt_label)
+                            .service(routes::semantic_index::get_semantic_index)
+                            .service(routes::semantic_index::update_semantic_index)
+                            .service(routes::semantic_index::reindex_semantic_index)
+                            .service(routes::semantic_index::get_reindex_job)
+                            .service(routes::semantic_index::resume_reindex_job),
                     ),
             )
     })
     .bind(("0.0.0.0", port))?
-    .run()
-    .await
+    // Signals are handled by `drain_on_shutdown`, so that the server keeps running while draining
+    .disable_signals()
+    .run();
+
+    tokio::task::spawn(runs::drain_on_shutdown(
+        server.handle(),
+        shutdown_engine_stats,
+        shutdown_interrupt_senders,
+    ));
+
+    server.await
 }
@@ -0,0 +1,637 @@
+//! Building pipeline graphs in Rust
+//!
+//! Nodes are added by name with their config, and edges connect them by `node.handle` names, so
+//! graphs are written without ids or handle mappings. The builder checks the names when the
+//! graph is built, and then validates the graph like graphs saved from the workshop.
+//!
+//! A router, whose branches merge into the same output:
+//!
+//! ```ignore
+//! let template = |text| NodeConfig::string_template(text).input("question", HandleType::String);
+//! let graph = GraphBuilder::new()
+//!     .node("question", NodeConfig::graph_input(HandleType::String))
+//!     .node("topic", NodeConfig::graph_input(HandleType::String))
+//!     .node("router", NodeConfig::switch(&["math", "chat"]))
+//!     .node("math", NodeConfig::condition("math"))
+//!     .node("chat", NodeConfig::condition("chat"))
+//!     .node("solve", template("Solve {{question}}"))
+//!     .node("reply", template("Reply to {{question}}"))
+//!     .node("answer", NodeConfig::graph_output())
+//!     .edge("topic.output", "router.condition")
+//!     .edge("question.output", "router.input")
+//!     .edge("router", "math")
+//!     .edge("router", "chat")
+//!     .edge("math", "solve.question")
+//!     .edge("chat", "reply.question")
+//!     // both branches feed the same input, whichever one runs
+//!     .edge("solve", "answer")
+//!     .edge("reply", "answer")
+//!     .build()?;
+//! ```
+//!
+//! A generation validated by its format, and retried while it doesn't match. The model's input
+//! is cyclic: it receives the question first, then the rejected generation.
+//!
+//! ```ignore
+//! let llm = NodeConfig::llm("openai:gpt-4o-mini", "Answer in one word: {{input}}")
+//!     .input("input", HandleType::String);
+//! let graph = GraphBuilder::new()
+//!     .node("question", NodeConfig::graph_input(HandleType::String))
+//!     .node("llm1", llm)
+//!     .node("validator", NodeConfig::format_validator(r"^\w+$"))
+//!     .node("retry", NodeConfig::condition("incorrect"))
+//!     .node("valid", NodeConfig::condition("correct"))
+//!     .node("answer", NodeConfig::graph_output())
+//!     .cycle("question.output", "llm1.input")
+//!     .edge("llm1", "validator")
+//!     .edge("validator", "retry")
+//!     .edge("validator", "valid")
+//!     .cycle("retry.output", "llm1.input")
+//!     .edge("valid", "answer")
+//!     .build()?;
+//! ```
+//!
+//! Nodes have a single output, named `output`, so sources may be given by node name alone, as
+//! may targets with a single input. Nodes of custom types registered in the
+//! [`NodeRegistry`](super::nodes::registry::NodeRegistry) are added with [`NodeConfig::new`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::{
+    credentials::Credentials,
+    model_defaults::ModelDefaults,
+    nodes::{
+        post_processors::{self, POST_PROCESSORS_FIELD},
+        registry::registry,
+        Handle, HandleType,
+    },
+    validation::{validate_graph, GraphDiagnostic},
+    Graph, RunType,
+};
+
+const OUTPUT_HANDLE_NAME: &str = "output";
+
+/// Config of a node to build, besides its id, name and handle mappings
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    node_type: String,
+    config: Value,
+    inputs: Vec<(String, HandleType)>,
+    output_type: HandleType,
+}
+
+impl NodeConfig {
+    /// Node of any registered type. `config` has the fields of the node's JSON other than
+    /// `type`, `id`, `name`, `inputs`, `outputs` and `inputsMappings`, which the builder sets.
+    pub fn new(node_type: &str, config: Value) -> Self {
+        Self {
+            node_type: node_type.to_string(),
+            config,
+            inputs: Vec::new(),
+            output_type: HandleType::String,
+        }
+    }
+
+    /// Declare an input handle, which an edge must connect
+    pub fn input(mut self, name: &str, handle_type: HandleType) -> Self {
+        self.inputs.push((name.to_string(), handle_type));
+        self
+    }
+
+    pub fn output_type(mut self, handle_type: HandleType) -> Self {
+        self.output_type = handle_type;
+        self
+    }
+
+    /// Post-processors the node's output is transformed with, see `nodes::post_processors`
+    pub fn post_processors(mut self, references: &[&str]) -> Self {
+        self.config[POST_PROCESSORS_FIELD] = json!(references);
+        self
+    }
+
+    /// Input of the graph, set by the inputs of a run
+    pub fn graph_input(input_type: HandleType) -> Self {
+        Self::new("Input", json!({"inputType": input_type.clone()})).output_type(input_type)
+    }
+
+    /// JSON schema of the value of a [`NodeConfig::graph_input`], which run inputs must match
+    pub fn input_schema(mut self, schema: Value) -> Self {
+        self.config["inputSchema"] = schema;
+        self
+    }
+
+    /// Output of the graph. Its input is named `output`.
+    pub fn graph_output() -> Self {
+        Self::new("Output", json!({})).input(OUTPUT_HANDLE_NAME, HandleType::Any)
+    }
+
+    /// Template rendered with its inputs, which are declared with [`NodeConfig::input`]
+    pub fn string_template(text: &str) -> Self {
+        Self::new("StringTemplate", json!({"text": text}))
+    }
+
+    /// Completion of the prompt, rendered with the inputs declared with [`NodeConfig::input`].
+    /// `model` is `provider:model`.
+    pub fn llm(model: &str, prompt: &str) -> Self {
+        Self::new(
+            "LLM",
+            json!({"model": model, "prompt": prompt, "dynamicInputs": []}),
+        )
+    }
+
+    /// Routes its `input` by its `condition` input, to the [`NodeConfig::condition`] nodes
+    /// following it
+    pub fn switch(routes: &[&str]) -> Self {
+        let routes = routes
+            .iter()
+            .map(|route| json!({"name": route}))
+            .collect::<Vec<_>>();
+        Self::new("Switch", json!({"routes": routes}))
+            .input("condition", HandleType::String)
+            .input("input", HandleType::Any)
+            .output_type(HandleType::Any)
+    }
+
+    /// Branch of a switch or validator, which runs if its input was routed to `condition`
+    pub fn condition(condition: &str) -> Self {
+        Self::new("Condition", json!({"condition": condition}))
+            .input("input", HandleType::Any)
+            .output_type(HandleType::Any)
+    }
+
+    /// Branch of a switch or validator, which finishes the run with its input if it was routed to
+    /// `condition`
+    pub fn finish_condition(condition: &str) -> Self {
+        Self::new("Condition", json!({"condition": condition, "finish": true}))
+            .input("input", HandleType::Any)
+            .output_type(HandleType::Any)
+    }
+
+    /// Routes its input to the `correct` or `incorrect` condition by whether it matches the regex
+    pub fn format_validator(format: &str) -> Self {
+        Self::new("FormatValidator", json!({"format": format}))
+            .input("input", HandleType::String)
+            .output_type(HandleType::Any)
+    }
+}
+
+struct BuilderNode {
+    id: Uuid,
+    name: String,
+    config: NodeConfig,
+    output_handle_id: Uuid,
+}
+
+struct Edge {
+    from: String,
+    to: String,
+    is_cyclic: bool,
+}
+
+/// Builder of a [`Graph`], see the module docs
+#[derive(Default)]
+pub struct GraphBuilder {
+    nodes: Vec<BuilderNode>,
+    edges: Vec<Edge>,
+    model_defaults: ModelDefaults,
+    strict_inputs: bool,
+    output_bindings: BTreeMap<String, String>,
+    trace_run_store: bool,
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node(mut self, name: &str, config: NodeConfig) -> Self {
+        self.nodes.push(BuilderNode {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            config,
+            output_handle_id: Uuid::new_v4(),
+        });
+        self
+    }
+
+    /// Connect the output `from`, e.g. `llm1.output` or `llm1`, to the input `to`, e.g.
+    /// `judge.input`
+    pub fn edge(mut self, from: &str, to: &str) -> Self {
+        self.edges.push(Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+            is_cyclic: false,
+        });
+        self
+    }
+
+    /// Connect the output `from` to the cyclic input `to`, which receives a message from any of
+    /// its edges in each iteration. The edge entering a loop and the edge closing it are both
+    /// connected with `cycle`.
+    pub fn cycle(mut self, from: &str, to: &str) -> Self {
+        self.edges.push(Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+            is_cyclic: true,
+        });
+        self
+    }
+
+    /// Defaults of the graph's LLM nodes, as `modelDefaults` of the graph JSON
+    pub fn model_defaults(mut self, model_defaults: ModelDefaults) -> Self {
+        self.model_defaults = model_defaults;
+        self
+    }
+
+    /// Reject run inputs which no input node takes, as `strictInputs` of the graph JSON
+    pub fn strict_inputs(mut self, strict_inputs: bool) -> Self {
+        self.strict_inputs = strict_inputs;
+        self
+    }
+
+    /// Record the run store on the run's span, as `traceRunStore` of the graph JSON
+    pub fn trace_run_store(mut self, trace_run_store: bool) -> Self {
+        self.trace_run_store = trace_run_store;
+        self
+    }
+
+    /// Bind the run output `name` to the output `from`, e.g. `llm1.output` or `llm1`, as
+    /// `outputBindings` of the graph JSON
+    pub fn output(mut self, name: &str, from: &str) -> Self {
+        self.output_bindings
+            .insert(name.to_string(), from.to_string());
+        self
+    }
+
+    /// The graph, if its names resolve and it passes validation. Otherwise all the problems
+    /// found, of the names first.
+    pub fn build(self) -> Result<Graph, Vec<GraphDiagnostic>> {
+        let mut diagnostics = Vec::new();
+        let mut nodes_by_name = HashMap::new();
+        for node in self.nodes.iter() {
+            if nodes_by_name.insert(node.name.as_str(), node).is_some() {
+                diagnostics.push(node_diagnostic(node, "Node name is used twice".to_string()));
+            }
+        }
+
+        // input handles by node id, with the output handle ids they're mapped to
+        let mut inputs = HashMap::<Uuid, Vec<(Handle, Uuid)>>::new();
+        let mut pred = HashMap::<Uuid, Vec<Uuid>>::new();
+        let mut cyclic_inputs = HashSet::new();
+        for edge in self.edges.iter() {
+            let from = resolve_output(&nodes_by_name, &edge.from);
+            let to = resolve_input(&nodes_by_name, &edge.to);
+            let (from, (to, (handle_name, handle_type))) = match (from, to) {
+                (Ok(from), Ok(to)) => (from, to),
+                (from, to) => {
+                    diagnostics.extend(from.err());
+                    diagnostics.extend(to.err());
+                    continue;
+                }
+            };
+            if edge.is_cyclic {
+                cyclic_inputs.insert((to.id, handle_name.clone()));
+            }
+            let handle = Handle {
+                id: Uuid::new_v4(),
+                name: Some(handle_name),
+                handle_type,
+                is_cyclic: false,
+            };
+            inputs
+                .entry(to.id)
+                .or_default()
+                .push((handle, from.output_handle_id));
+            let predecessors = pred.entry(to.id).or_default();
+            if !predecessors.contains(&from.id) {
+                predecessors.push(from.id);
+            }
+        }
+
+        let mut graph_nodes = HashMap::new();
+        let mut post_processors = HashMap::new();
+        for node in self.nodes.iter() {
+            let mut handles = inputs.remove(&node.id).unwrap_or_default();
+            for (handle, _) in handles.iter_mut() {
+                handle.is_cyclic = cyclic_inputs.contains(&(node.id, handle.name_force()));
+            }
+            for (name, _) in node.config.inputs.iter() {
+                if !handles
+                    .iter()
+                    .any(|(handle, _)| handle.name.as_ref() == Some(name))
+                {
+                    diagnostics.push(node_diagnostic(
+                        node,
+                        format!("Input {} is not connected", name),
+                    ));
+                }
+            }
+
+            let Value::Object(mut config) = node.config.config.clone() else {
+                diagnostics.push(node_diagnostic(
+                    node,
+                    "Config must be a JSON object".to_string(),
+                ));
+                continue;
+            };
+            let output = Handle {
+                id: node.output_handle_id,
+                name: Some(OUTPUT_HANDLE_NAME.to_string()),
+                handle_type: node.config.output_type.clone(),
+                is_cyclic: false,
+            };
+            let inputs_mappings = handles
+                .iter()
+                .map(|(handle, from)| (handle.id.to_string(), json!(from)))
+                .collect::<serde_json::Map<_, _>>();
+            let handles = handles
+                .into_iter()
+                .map(|(handle, _)| handle)
+                .collect::<Vec<_>>();
+            config.insert("type".to_string(), json!(node.config.node_type));
+            config.insert("id".to_string(), json!(node.id));
+            config.insert("name".to_string(), json!(node.name));
+            config.insert("inputs".to_string(), json!(handles));
+            config.insert("outputs".to_string(), json!([output]));
+            config.insert("inputsMappings".to_string(), Value::Object(inputs_mappings));
+
+            let config = Value::Object(config);
+            match post_processors::declared(&config) {
+                Ok(references) if !references.is_empty() => {
+                    post_processors.insert(node.id, references);
+                }
+                Ok(_) => {}
+                Err(e) => diagnostics.push(node_diagnostic(
+                    node,
+                    format!("Invalid post-processors: {}", e),
+                )),
+            }
+            match registry().load(config) {
+                Ok(loaded) => {
+                    graph_nodes.insert(node.name.clone(), loaded);
+                }
+                Err(e) => diagnostics.push(node_diagnostic(node, e.to_string())),
+            }
+        }
+        if !diagnostics.is_empty() {
+            diagnostics.sort();
+            return Err(diagnostics);
+        }
+
+        let graph = Graph {
+            nodes: graph_nodes,
+            pred,
+            model_defaults: self.model_defaults,
+            strict_inputs: self.strict_inputs,
+            output_bindings: self.output_bindings,
+            post_processors,
+            trace_run_store: self.trace_run_store,
+            workspace_model_defaults: ModelDefaults::default(),
+            workspace_id: None,
+            prompts: None,
+            env: HashMap::new(),
+            secrets: HashMap::new(),
+            credentials: Credentials::default(),
+            metadata: HashMap::new(),
+            run_type: RunType::default(),
+            record_node_io: false,
+            content_hash: None,
+            config_diagnostics: Vec::new(),
+        };
+        let diagnostics = validate_graph(&graph);
+        if diagnostics.is_empty() {
+            Ok(graph)
+        } else {
+            Err(diagnostics)
+        }
+    }
+}
+
+fn node_diagnostic(node: &BuilderNode, message: String) -> GraphDiagnostic {
+    GraphDiagnostic::of_config(node.id, &node.name, message)
+}
+
+fn name_diagnostic(endpoint: &str, message: String) -> GraphDiagnostic {
+    GraphDiagnostic {
+        node_id: None,
+        node_name: Some(endpoint.to_string()),
+        message,
+        pointer: None,
+    }
+}
+
+/// Node of an endpoint, e.g. `llm1.output` or `llm1`, and its handle name if given
+fn split_endpoint<'a, 'n>(
+    nodes: &HashMap<&str, &'n BuilderNode>,
+    endpoint: &'a str,
+) -> Result<(&'n BuilderNode, Option<&'a str>), GraphDiagnostic> {
+    if let Some(node) = nodes.get(endpoint) {
+        return Ok((*node, None));
+    }
+    endpoint
+        .rsplit_once('.')
+        .and_then(|(node_name, handle_name)| Some((*nodes.get(node_name)?, Some(handle_name))))
+        .ok_or_else(|| name_diagnostic(endpoint, format!("No node for {}", endpoint)))
+}
+
+fn resolve_output<'n>(
+    nodes: &HashMap<&str, &'n BuilderNode>,
+    endpoint: &str,
+) -> Result<&'n BuilderNode, GraphDiagnostic> {
+    match split_endpoint(nodes, endpoint)? {
+        (node, Some(handle_name)) if handle_name != OUTPUT_HANDLE_NAME => Err(node_diagnostic(
+            node,
+            format!(
+                "Node has no output {}, its output is {}",
+                handle_name, OUTPUT_HANDLE_NAME
+            ),
+        )),
+        (node, _) => Ok(node),
+    }
+}
+
+fn resolve_input<'n>(
+    nodes: &HashMap<&str, &'n BuilderNode>,
+    endpoint: &str,
+) -> Result<(&'n BuilderNode, (String, HandleType)), GraphDiagnostic> {
+    let (node, handle_name) = split_endpoint(nodes, endpoint)?;
+    let inputs = &node.config.inputs;
+    let input = match handle_name {
+        Some(handle_name) => inputs.iter().find(|(name, _)| name == handle_name),
+        None if inputs.len() == 1 => inputs.first(),
+        None => None,
+    };
+    if let Some(input) = input {
+        return Ok((node, input.clone()));
+    }
+    let names = inputs
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let message = match handle_name {
+        Some(handle_name) => format!(
+            "Node has no input {}, its inputs are: {}",
+            handle_name, names
+        ),
+        None => format!(
+            "Edge into {} must name one of its inputs: {}",
+            node.name, names
+        ),
+    };
+    Err(node_diagnostic(node, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        engine::Engine,
+        pipeline::{
+            nodes::{Node, NodeInput},
+            utils::parse_graph,
+        },
+        testing::OfflineServices,
+    };
+
+    use super::*;
+
+    fn router() -> GraphBuilder {
+        let template =
+            |text: &str| NodeConfig::string_template(text).input("question", HandleType::String);
+        GraphBuilder::new()
+            .node("question", NodeConfig::graph_input(HandleType::String))
+            .node("topic", NodeConfig::graph_input(HandleType::String))
+            .node("router", NodeConfig::switch(&["math", "chat"]))
+            .node("math", NodeConfig::condition("math"))
+            .node("chat", NodeConfig::condition("chat"))
+            .node("solve", template("Solve {{question}}"))
+            .node("reply", template("Reply to {{question}}"))
+            .node("answer", NodeConfig::graph_output())
+            .edge("topic.output", "router.condition")
+            .edge("question.output", "router.input")
+            .edge("router", "math")
+            .edge("router", "chat")
+            .edge("solve", "answer")
+            .edge("reply", "answer")
+    }
+
+    #[tokio::test]
+    async fn test_router_branches_merge() {
+        let mut graph = router()
+            .edge("math", "solve.question")
+            .edge("chat", "reply.question")
+            .build()
+            .unwrap();
+        let inputs = HashMap::from([
+            ("question".to_string(), NodeInput::String("2+2".to_string())),
+            ("topic".to_string(), NodeInput::String("math".to_string())),
+        ]);
+        graph
+            .setup(
+                &inputs,
+                &HashMap::new(),
+                &HashMap::new(),
+                &RunType::Workshop,
+            )
+            .unwrap();
+
+        let services = OfflineServices::default();
+        let mut engine = Engine::with_tasks_and_context(
+            parse_graph(graph).unwrap(),
+            services.context(),
+            None,
+            None,
+            None,
+        );
+        let outputs = engine.run(None, None, vec![]).await.unwrap();
+        assert_eq!(
+            outputs.output_values(),
+            HashMap::from([(
+                "answer".to_string(),
+                NodeInput::String("Solve 2+2".to_string())
+            )])
+        );
+    }
+
+    #[test]
+    fn test_names_are_checked() {
+        let diagnostics = router()
+            .edge("math", "solve.questoin")
+            .edge("chat.out", "reply.question")
+            .edge("chats", "reply.question")
+            .build()
+            .unwrap_err();
+        let messages = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            messages,
+            HashSet::from([
+                "Node has no input questoin, its inputs are: question",
+                "Node has no output out, its output is output",
+                "No node for chats",
+                "Input question is not connected",
+            ])
+        );
+        // the unconnected input is of the node with the typo
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.node_name.as_deref() == Some("solve"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_validated_generation_loop() {
+        let graph = GraphBuilder::new()
+            .node("question", NodeConfig::graph_input(HandleType::String))
+            .node(
+                "llm1",
+                NodeConfig::llm("openai:gpt-4o-mini", "Answer in one word: {{input}}")
+                    .input("input", HandleType::String),
+            )
+            .node("validator", NodeConfig::format_validator(r"^\w+$"))
+            .node("retry", NodeConfig::condition("incorrect"))
+            .node("valid", NodeConfig::condition("correct"))
+            .node("answer", NodeConfig::graph_output())
+            .cycle("question.output", "llm1.input")
+            .edge("llm1", "validator")
+            .edge("validator", "retry")
+            .edge("validator", "valid")
+            .cycle("retry.output", "llm1.input")
+            .edge("valid", "answer")
+            .build()
+            .unwrap();
+
+        let Node::LLM(llm) = &graph.nodes["llm1"] else {
+            panic!("llm1 is not an LLM node");
+        };
+        assert_eq!(llm.inputs.len(), 2);
+        assert!(llm.inputs.iter().all(|handle| handle.is_cyclic));
+        assert_eq!(graph.pred[&llm.id].len(), 2);
+
+        // without the cyclic input, the loop waits for its own output
+        let diagnostics = GraphBuilder::new()
+            .node("question", NodeConfig::graph_input(HandleType::String))
+            .node(
+                "draft",
+                NodeConfig::string_template("{{input}}").input("input", HandleType::String),
+            )
+            .node("answer", NodeConfig::graph_output())
+            .edge("question", "draft")
+            .edge("draft", "draft")
+            .edge("draft", "answer")
+            .build()
+            .unwrap_err();
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.message.contains("cycle")));
+    }
+}
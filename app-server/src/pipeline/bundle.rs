@@ -0,0 +1,410 @@
+//! Portable JSON bundle of a pipeline with its versions, used to move pipelines between
+//! projects and deployments.
+//!
+//! Graphs are exported as-is, so `{{secret:NAME}}` and `{{env:NAME}}` references are kept and
+//! have to be resolvable in the project the bundle is imported to. Subpipeline and map nodes
+//! already inline the runnable graph of the version they use, the bundle additionally lists
+//! these versions by content hash.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::db::pipelines::PipelineVersion;
+use crate::secrets::{get_json_references, Reference};
+
+use super::nodes::{registry::registry, Node};
+use super::utils::get_graph_content_hash;
+use super::Graph;
+
+pub const PIPELINE_BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineBundle {
+    pub bundle_version: u32,
+    pub pipeline: BundlePipeline,
+    /// Versions in the order they were created
+    pub versions: Vec<BundlePipelineVersion>,
+    /// Content hash of the target version, if the pipeline has one
+    #[serde(default)]
+    pub target_version_hash: Option<String>,
+    #[serde(default)]
+    pub subpipelines: Vec<BundleSubpipeline>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundlePipeline {
+    pub name: String,
+    pub visibility: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundlePipelineVersion {
+    pub name: String,
+    pub pipeline_type: String,
+    pub displayable_graph: Value,
+    pub runnable_graph: Value,
+    /// Set for COMMIT versions only
+    #[serde(default)]
+    pub content_hash: Option<String>,
+}
+
+/// Pipeline version inlined into a subpipeline or map node
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleSubpipeline {
+    pub pipeline_name: String,
+    pub pipeline_version_name: String,
+    pub content_hash: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleValidationErrors {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsupported_bundle_version: Option<u32>,
+    pub unknown_node_types: Vec<BundleNodeError>,
+    pub invalid_nodes: Vec<BundleNodeError>,
+    /// Versions whose graph cannot be loaded or does not match its content hash
+    pub invalid_versions: Vec<BundleVersionError>,
+    /// Secrets referenced by the graphs which are not set in the project
+    pub missing_secrets: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleNodeError {
+    pub version_name: String,
+    /// Nodes of inlined graphs are prefixed with the names of their parent nodes, e.g. `map.llm`
+    pub node_name: String,
+    pub node_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleVersionError {
+    pub version_name: String,
+    pub message: String,
+}
+
+impl BundleValidationErrors {
+    fn is_empty(&self) -> bool {
+        self.unsupported_bundle_version.is_none()
+            && self.unknown_node_types.is_empty()
+            && self.invalid_nodes.is_empty()
+            && self.invalid_versions.is_empty()
+            && self.missing_secrets.is_empty()
+    }
+}
+
+impl PipelineBundle {
+    /// `versions` are expected in the order returned by `get_pipeline_versions`, i.e. newest first
+    pub fn new(
+        pipeline: BundlePipeline,
+        versions: &[PipelineVersion],
+        target_version_id: Option<Uuid>,
+    ) -> Self {
+        let target_version_hash = versions
+            .iter()
+            .find(|version| Some(version.id) == target_version_id)
+            .and_then(|version| version.content_hash.clone());
+
+        let mut subpipelines = HashMap::new();
+        for version in versions {
+            collect_subpipelines(&version.runnable_graph, &mut subpipelines);
+        }
+        let mut subpipelines = subpipelines.into_values().collect::<Vec<_>>();
+        subpipelines.sort_by(|a, b| {
+            (&a.pipeline_name, &a.pipeline_version_name)
+                .cmp(&(&b.pipeline_name, &b.pipeline_version_name))
+        });
+
+        Self {
+            bundle_version: PIPELINE_BUNDLE_VERSION,
+            pipeline,
+            versions: versions
+                .iter()
+                .rev()
+                .map(|version| BundlePipelineVersion {
+                    name: version.name.clone(),
+                    pipeline_type: version.pipeline_type.clone(),
+                    displayable_graph: version.displayable_graph.clone(),
+                    runnable_graph: version.runnable_graph.clone(),
+                    content_hash: version.content_hash.clone(),
+                })
+                .collect(),
+            target_version_hash,
+            subpipelines,
+        }
+    }
+
+    pub fn target_version(&self) -> Option<&BundlePipelineVersion> {
+        let target_version_hash = self.target_version_hash.as_ref()?;
+        self.versions
+            .iter()
+            .find(|version| version.content_hash.as_ref() == Some(target_version_hash))
+    }
+
+    /// Check that the bundle can be loaded by this deployment, i.e. all node types are known and
+    /// their configs are valid, and that all referenced secrets are set in the project
+    pub fn validate(&self, secret_names: &HashSet<String>) -> Result<(), BundleValidationErrors> {
+        let mut errors = BundleValidationErrors::default();
+        if self.bundle_version != PIPELINE_BUNDLE_VERSION {
+            errors.unsupported_bundle_version = Some(self.bundle_version);
+            return Err(errors);
+        }
+
+        let mut missing_secrets = HashSet::new();
+        for version in &self.versions {
+            let node_errors_count = errors.unknown_node_types.len() + errors.invalid_nodes.len();
+            validate_graph_nodes(&version.runnable_graph, &version.name, "", &mut errors);
+
+            // Node errors already explain why the graph cannot be loaded
+            if errors.unknown_node_types.len() + errors.invalid_nodes.len() == node_errors_count {
+                if let Err(e) = serde_json::from_value::<Graph>(version.runnable_graph.clone()) {
+                    errors.invalid_versions.push(BundleVersionError {
+                        version_name: version.name.clone(),
+                        message: e.to_string(),
+                    });
+                }
+            }
+            if let Some(content_hash) = &version.content_hash {
+                if get_graph_content_hash(&version.runnable_graph) != *content_hash {
+                    errors.invalid_versions.push(BundleVersionError {
+                        version_name: version.name.clone(),
+                        message: "Runnable graph does not match content hash".to_string(),
+                    });
+                }
+            }
+
+            missing_secrets.extend(
+                get_json_references(&version.runnable_graph)
+                    .into_iter()
+                    .filter_map(|reference| match reference {
+                        Reference::Secret(name) if !secret_names.contains(&name) => Some(name),
+                        _ => None,
+                    }),
+            );
+        }
+        if self.target_version_hash.is_some() && self.target_version().is_none() {
+            errors.invalid_versions.push(BundleVersionError {
+                version_name: String::new(),
+                message: "Target version is not included in the bundle".to_string(),
+            });
+        }
+
+        errors.missing_secrets = missing_secrets.into_iter().collect();
+        errors.missing_secrets.sort();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Node types which inline the runnable graph of another pipeline version
+fn is_subpipeline_node_type(node_type: &str) -> bool {
+    node_type == "Subpipeline" || node_type == "Map"
+}
+
+fn graph_nodes(graph: &Value) -> impl Iterator<Item = (&String, &Value)> {
+    graph
+        .get("nodes")
+        .and_then(|nodes| nodes.as_object())
+        .into_iter()
+        .flatten()
+}
+
+fn collect_subpipelines(graph: &Value, subpipelines: &mut HashMap<String, BundleSubpipeline>) {
+    for (_, node) in graph_nodes(graph) {
+        let Some(node_type) = node.get("type").and_then(|t| t.as_str()) else {
+            continue;
+        };
+        if !is_subpipeline_node_type(node_type) {
+            continue;
+        }
+        let Some(runnable_graph) = node.get("runnableGraph") else {
+            continue;
+        };
+        let content_hash = get_graph_content_hash(runnable_graph);
+        let name = |key: &str| {
+            node.get(key)
+                .and_then(|name| name.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        subpipelines
+            .entry(content_hash.clone())
+            .or_insert_with(|| BundleSubpipeline {
+                pipeline_name: name("pipelineName"),
+                pipeline_version_name: name("pipelineVersionName"),
+                content_hash,
+            });
+        collect_subpipelines(runnable_graph, subpipelines);
+    }
+}
+
+fn validate_graph_nodes(
+    graph: &Value,
+    version_name: &str,
+    prefix: &str,
+    errors: &mut BundleValidationErrors,
+) {
+    let mut nodes = graph_nodes(graph).collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_name, _)| *node_name);
+
+    for (node_name, node) in nodes {
+        let node_path = format!("{prefix}{node_name}");
+        let node_type = node.get("type").and_then(|t| t.as_str());
+        let node_error = |message: Option<String>| BundleNodeError {
+            version_name: version_name.to_string(),
+            node_name: node_path.clone(),
+            node_type: node_type.map(|t| t.to_string()),
+            message,
+        };
+
+        let Some(node_type) = node_type.filter(|t| registry().contains(t)) else {
+            errors.unknown_node_types.push(node_error(None));
+            continue;
+        };
+        if let Err(e) = serde_json::from_value::<Node>(node.clone()) {
+            errors.invalid_nodes.push(node_error(Some(e.to_string())));
+            continue;
+        }
+        if is_subpipeline_node_type(node_type) {
+            if let Some(runnable_graph) = node.get("runnableGraph") {
+                validate_graph_nodes(
+                    runnable_graph,
+                    version_name,
+                    &format!("{node_path}."),
+                    errors,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runnable_graph(output_type: &str, output_name: &str) -> Value {
+        let input_id = Uuid::new_v4();
+        let input_handle_id = Uuid::new_v4();
+        let output_id = Uuid::new_v4();
+        let output_handle_id = Uuid::new_v4();
+        serde_json::json!({
+            "nodes": {
+                "question": {
+                    "type": "Input",
+                    "id": input_id,
+                    "name": "question",
+                    "outputs": [{"id": input_handle_id, "name": "output", "type": "String"}],
+                    "inputType": "String",
+                },
+                output_name: {
+                    "type": output_type,
+                    "id": output_id,
+                    "name": output_name,
+                    "inputs": [{"id": output_handle_id, "name": "output", "type": "String"}],
+                    "inputsMappings": {output_handle_id.to_string(): input_handle_id},
+                    "url": "https://example.com?key={{secret:API_TOKEN}}",
+                },
+            },
+            "pred": {output_id.to_string(): [input_id]},
+        })
+    }
+
+    fn pipeline_version(pipeline_type: &str, runnable_graph: Value) -> PipelineVersion {
+        PipelineVersion {
+            id: Uuid::new_v4(),
+            pipeline_id: Uuid::new_v4(),
+            pipeline_type: pipeline_type.to_string(),
+            name: pipeline_type.to_lowercase(),
+            displayable_graph: serde_json::json!({"nodes": [], "edges": []}),
+            content_hash: (pipeline_type == "COMMIT")
+                .then(|| get_graph_content_hash(&runnable_graph)),
+            runnable_graph,
+            ..Default::default()
+        }
+    }
+
+    fn bundle(output_type: &str) -> (PipelineBundle, Vec<PipelineVersion>) {
+        let versions = vec![
+            pipeline_version("COMMIT", runnable_graph(output_type, "answer")),
+            pipeline_version("WORKSHOP", runnable_graph(output_type, "result")),
+        ];
+        let bundle = PipelineBundle::new(
+            BundlePipeline {
+                name: "qa".to_string(),
+                visibility: "PRIVATE".to_string(),
+            },
+            &versions,
+            Some(versions[0].id),
+        );
+        (bundle, versions)
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let (bundle, versions) = bundle("Output");
+
+        let exported = serde_json::to_string(&bundle).unwrap();
+        let imported = serde_json::from_str::<PipelineBundle>(&exported).unwrap();
+        assert_eq!(imported, bundle);
+
+        // oldest version first
+        assert_eq!(
+            imported.versions[1].runnable_graph,
+            versions[0].runnable_graph
+        );
+        assert_eq!(
+            imported.versions[0].runnable_graph,
+            versions[1].runnable_graph
+        );
+        let target_version = imported.target_version().unwrap();
+        assert_eq!(target_version.content_hash, versions[0].content_hash);
+        assert_eq!(
+            get_graph_content_hash(&target_version.runnable_graph),
+            versions[0].content_hash.clone().unwrap()
+        );
+
+        let secret_names = HashSet::from(["API_TOKEN".to_string()]);
+        assert!(imported.validate(&secret_names).is_ok());
+    }
+
+    #[test]
+    fn test_validate_bundle_errors() {
+        let (bundle, _) = bundle("Http");
+
+        let errors = bundle.validate(&HashSet::new()).unwrap_err();
+        assert_eq!(errors.unknown_node_types.len(), 2);
+        assert_eq!(
+            errors.unknown_node_types[0].node_type.as_deref(),
+            Some("Http")
+        );
+        assert!(errors.invalid_nodes.is_empty());
+        assert_eq!(errors.missing_secrets, vec!["API_TOKEN".to_string()]);
+    }
+
+    #[test]
+    fn test_validate_tampered_graph() {
+        let (mut bundle, _) = bundle("Output");
+        bundle.versions[1].runnable_graph["nodes"]["answer"]["name"] = "changed".into();
+
+        let errors = bundle
+            .validate(&HashSet::from(["API_TOKEN".to_string()]))
+            .unwrap_err();
+        assert_eq!(errors.invalid_versions.len(), 1);
+        assert_eq!(errors.invalid_versions[0].version_name, "commit");
+    }
+}
@@ -0,0 +1,568 @@
+//! Execution plans of pipeline graphs
+//!
+//! Compiling a graph validates it and resolves everything its runs share: the node configs, with
+//! the effective model configs of LLM nodes, and topology of the tasks, which task inputs are
+//! cyclic, the env vars and references the nodes require, and the validated structured output
+//! schemas. Node configs are compiled in parallel and keep their templates and regexes, so runs
+//! of the same plan reuse those too. A run of a plan only allocates the input states of its
+//! tasks.
+//!
+//! Graphs of COMMIT versions are immutable, so the runner caches their plans by content hash.
+//! Plans keep the configs of their nodes, so that two versions are diffed by their plans, see
+//! [`diff`](super::diff).
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use lmnr_baml::BamlContext;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    db::trace::TRACED_NODE_TYPES,
+    engine::{
+        task::{Action, TaskHandles},
+        Task,
+    },
+    secrets::{get_references, Reference},
+};
+
+use super::{
+    credentials::{self, CredentialRequirement, Credentials},
+    diff::{self, DiffNode, GraphDiff},
+    model_defaults,
+    nodes::{
+        post_processors::{PostProcessors, POST_PROCESSORS_FIELD},
+        Node,
+    },
+    outputs::OutputBindings,
+    prompts,
+    runner::{MissingEnvVarsError, MissingSecretsError, PipelineRunnerError},
+    trace::{NodeOutcome, NodeRunStats},
+    utils::action_from_node,
+    validation, Graph, GraphError, InvalidSchemasError,
+};
+
+pub struct CompiledGraph {
+    nodes: HashMap<Uuid, CompiledNode>,
+    required_env_vars: HashSet<String>,
+    config_references: HashSet<Reference>,
+    credential_requirements: Vec<CredentialRequirement>,
+    output_bindings: Option<Arc<OutputBindings>>,
+    baml_schemas: Arc<HashMap<Uuid, Arc<BamlContext>>>,
+}
+
+impl fmt::Debug for CompiledGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledGraph")
+            .field("nodes", &self.nodes.len())
+            .field("required_env_vars", &self.required_env_vars)
+            .finish_non_exhaustive()
+    }
+}
+
+struct CompiledNode {
+    /// Unset for input nodes, which hold the inputs of a run, so their action is taken from the
+    /// run's graph
+    action: Option<Action>,
+    name: String,
+    node_type: String,
+    /// Config of the node, as in the graph JSON, to diff plans with
+    config: Value,
+    prev: Vec<Uuid>,
+    next: Vec<Uuid>,
+    handles: TaskHandles,
+    post_processors: Option<Arc<PostProcessors>>,
+}
+
+impl CompiledGraph {
+    /// Plan of the graph, whose LLM nodes have their effective model configs
+    pub fn compile(graph: &Graph) -> Result<Self, PipelineRunnerError> {
+        let graph = prompts::resolve_graph(graph).map_err(GraphError::UnhandledError)?;
+        let graph = model_defaults::resolve_graph(&graph);
+        let graph = graph.as_ref();
+        if !graph
+            .nodes
+            .values()
+            .any(|node| matches!(node, Node::Output(_) | Node::Error(_)))
+        {
+            return Err(GraphError::UnhandledError(anyhow::anyhow!(
+                "Graph must contain at least one output node"
+            ))
+            .into());
+        }
+        let output_bindings =
+            OutputBindings::of_graph(graph).map_err(GraphError::UnhandledError)?;
+        let compiled_nodes = validation::compile_nodes(graph);
+        if !compiled_nodes.invalid_schemas.is_empty() {
+            return Err(InvalidSchemasError {
+                invalid_schemas: compiled_nodes.invalid_schemas,
+            }
+            .into());
+        }
+        if let Some(node_id) = graph
+            .post_processors
+            .keys()
+            .find(|node_id| !compiled_nodes.post_processors.contains_key(node_id))
+        {
+            return Err(GraphError::UnhandledError(anyhow::anyhow!(
+                "Node {} has invalid post-processors",
+                node_id
+            ))
+            .into());
+        }
+
+        let actions = graph
+            .nodes
+            .values()
+            .map(|node| (node.id(), action_from_node(node.clone())))
+            .collect::<HashMap<_, _>>();
+        let mut edges = HashMap::<Uuid, (Vec<Uuid>, Vec<Uuid>)>::new();
+        for (to, from) in graph.pred.iter() {
+            for from_node in from {
+                if !actions.contains_key(to) || !actions.contains_key(from_node) {
+                    return Err(GraphError::UnhandledError(anyhow::anyhow!(
+                        "Edge from {} to {} has no node",
+                        from_node,
+                        to
+                    ))
+                    .into());
+                }
+                edges.entry(*to).or_default().0.push(*from_node);
+                edges.entry(*from_node).or_default().1.push(*to);
+            }
+        }
+
+        let mut post_processors = compiled_nodes.post_processors;
+        let nodes = graph
+            .nodes
+            .values()
+            .map(|node| {
+                let id = node.id();
+                let action = actions[&id].clone();
+                let (prev, next) = edges.remove(&id).unwrap_or_default();
+                let handles = TaskHandles::resolve(
+                    &action,
+                    &node.output_handle_name(),
+                    next.iter().map(|next_id| &actions[next_id]),
+                );
+                let mut config = serde_json::to_value(node).unwrap_or_default();
+                if let (Some(references), Value::Object(config)) =
+                    (graph.post_processors.get(&id), &mut config)
+                {
+                    config.insert(POST_PROCESSORS_FIELD.to_string(), references.clone().into());
+                }
+                let compiled = CompiledNode {
+                    action: (!matches!(node, Node::Input(_))).then_some(action),
+                    name: node.name(),
+                    node_type: node.node_type(),
+                    config,
+                    prev,
+                    next,
+                    handles,
+                    post_processors: post_processors.remove(&id),
+                };
+                (id, compiled)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let credential_requirements =
+            credentials::credential_requirements(graph).map_err(GraphError::UnhandledError)?;
+        Ok(Self {
+            nodes,
+            required_env_vars: graph.get_required_env_vars(),
+            config_references: graph.get_config_references(),
+            credential_requirements,
+            output_bindings: output_bindings.map(Arc::new),
+            baml_schemas: Arc::new(compiled_nodes.baml_schemas),
+        })
+    }
+
+    /// Check that all env vars and secrets the nodes and the run's env reference are set
+    pub fn check_values(
+        &self,
+        env: &HashMap<String, String>,
+        secrets: &HashMap<String, String>,
+    ) -> Result<(), PipelineRunnerError> {
+        let references = self
+            .config_references
+            .iter()
+            .cloned()
+            .chain(env.values().flat_map(|value| get_references(value)))
+            .collect::<HashSet<_>>();
+
+        let mut missing_env_vars = self
+            .required_env_vars
+            .iter()
+            .filter(|var| !env.contains_key(*var))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut missing_secrets = HashSet::new();
+        for reference in references {
+            match reference {
+                Reference::Env(name) if !env.contains_key(&name) => {
+                    missing_env_vars.insert(name);
+                }
+                Reference::Secret(name) if !secrets.contains_key(&name) => {
+                    missing_secrets.insert(name);
+                }
+                _ => {}
+            }
+        }
+
+        if !missing_env_vars.is_empty() {
+            return Err(PipelineRunnerError::MissingEnvVarsError(
+                MissingEnvVarsError { missing_env_vars },
+            ));
+        }
+        if !missing_secrets.is_empty() {
+            return Err(PipelineRunnerError::MissingSecretsError(
+                MissingSecretsError { missing_secrets },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Credentials of the run's aliases, see [`credentials`](super::credentials)
+    pub fn resolve_credentials(
+        &self,
+        credentials: &Credentials,
+        secrets: &HashMap<String, String>,
+    ) -> Result<Credentials, PipelineRunnerError> {
+        credentials
+            .resolve(&self.credential_requirements, secrets)
+            .map_err(PipelineRunnerError::MissingCredentialsError)
+    }
+
+    /// Named outputs of the graph's runs, see [`outputs`](super::outputs)
+    pub fn output_bindings(&self) -> Option<Arc<OutputBindings>> {
+        self.output_bindings.clone()
+    }
+
+    /// Nodes whose messages the trace or the bound outputs of a run read the values of, i.e. the
+    /// traced nodes and their predecessors, see `Engine::compact_messages`
+    pub fn traced_node_ids(&self) -> HashSet<Uuid> {
+        let traced_nodes = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| TRACED_NODE_TYPES.contains(&node.node_type.as_str()));
+        let bound_node_ids = self
+            .output_bindings
+            .iter()
+            .flat_map(|output_bindings| output_bindings.node_ids());
+        traced_nodes
+            .flat_map(|(id, node)| node.prev.iter().copied().chain([*id]))
+            .chain(bound_node_ids)
+            .collect()
+    }
+
+    /// Whether any task has a cyclic input, i.e. the graph has loops
+    pub fn has_cycles(&self) -> bool {
+        self.nodes
+            .values()
+            .any(|node| node.handles.inputs.iter().any(|(_, is_cyclic)| *is_cyclic))
+    }
+
+    /// Validated structured output schemas by node id
+    pub fn baml_schemas(&self) -> Arc<HashMap<Uuid, Arc<BamlContext>>> {
+        self.baml_schemas.clone()
+    }
+
+    /// Tasks of a run, with fresh input states. The graph is the compiled one, set up with the
+    /// run's inputs.
+    pub fn instantiate(&self, graph: &Graph) -> Result<HashMap<Uuid, Task>> {
+        let mut input_actions = graph
+            .nodes
+            .values()
+            .filter(|node| matches!(node, Node::Input(_)))
+            .map(|node| (node.id(), action_from_node(node.clone())))
+            .collect::<HashMap<_, _>>();
+
+        self.nodes
+            .iter()
+            .map(|(id, node)| {
+                let action = match &node.action {
+                    Some(action) => action.clone(),
+                    None => input_actions
+                        .remove(id)
+                        .ok_or_else(|| anyhow::anyhow!("Input node {} is not in the graph", id))?,
+                };
+                let mut task = Task::with_inputs(
+                    *id,
+                    action,
+                    &node.handles,
+                    node.prev.clone(),
+                    node.next.clone(),
+                );
+                task.post_processors = node.post_processors.clone();
+                Ok((*id, task))
+            })
+            .collect()
+    }
+
+    /// Graphviz DOT of the graph, with edges labeled by the input handles they're mapped to and
+    /// edges into cyclic inputs highlighted. With the node stats of a finished run, nodes are
+    /// colored by their outcome and annotated with their duration and token count. Nodes and
+    /// edges are sorted, so the same graph and run always have the same DOT.
+    pub fn to_dot(&self, node_stats: Option<&[NodeRunStats]>) -> String {
+        let mut ids = self.nodes.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+
+        let mut dot = String::from("digraph pipeline {\n    rankdir=LR;\n    node [shape=box];\n");
+        for id in ids.iter() {
+            let node = &self.nodes[id];
+            let mut label = format!("{}\\n{}\\n{}", escape_dot(&node.name), node.node_type, id);
+            let mut attributes = String::new();
+            if let Some(node_stats) = node_stats {
+                // nodes cancelled by a finish of the run have skipped stats, without executions
+                let cancelled = node_stats
+                    .iter()
+                    .any(|stats| stats.node_id == *id && stats.outcome == NodeOutcome::Skipped);
+                let stats = node_stats
+                    .iter()
+                    .filter(|stats| stats.node_id == *id && stats.outcome != NodeOutcome::Skipped)
+                    .collect::<Vec<_>>();
+                let (outcome, color) = if stats.is_empty() {
+                    if !cancelled && self.received_inputs(*id, node_stats) {
+                        ("terminated", "khaki")
+                    } else {
+                        ("skipped", "lightgray")
+                    }
+                } else if stats
+                    .iter()
+                    .any(|stats| stats.outcome == NodeOutcome::Error)
+                {
+                    ("error", "salmon")
+                } else {
+                    ("success", "palegreen")
+                };
+                label.push_str(&format!("\\n{}", outcome));
+                if !stats.is_empty() {
+                    let duration_ms = stats
+                        .iter()
+                        .map(|stats| (stats.end_time - stats.start_time).num_milliseconds())
+                        .sum::<i64>();
+                    let tokens = stats
+                        .iter()
+                        .map(|stats| stats.total_token_count)
+                        .sum::<i64>();
+                    label.push_str(&format!("\\n{}ms, {} tokens", duration_ms, tokens));
+                }
+                attributes = format!(", style=filled, fillcolor={}", color);
+            }
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\"{}];\n",
+                id, label, attributes
+            ));
+        }
+
+        for id in ids.iter() {
+            let mut routes = self.nodes[id].handles.routes.clone();
+            routes.sort();
+            for route in routes {
+                let is_cyclic = self.nodes.get(&route.node_id()).is_some_and(|next| {
+                    next.handles
+                        .inputs
+                        .iter()
+                        .any(|(handle, is_cyclic)| *handle == route && *is_cyclic)
+                });
+                let attributes = if is_cyclic {
+                    ", color=red, penwidth=2"
+                } else {
+                    ""
+                };
+                dot.push_str(&format!(
+                    "    \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+                    id,
+                    route.node_id(),
+                    escape_dot(route.name()),
+                    attributes
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Changes of the nodes and edges of this plan in the other one, e.g. of a newer version
+    pub fn diff(&self, to: &CompiledGraph) -> GraphDiff {
+        diff::diff_graphs(&self.diff_nodes(), &to.diff_nodes())
+    }
+
+    fn diff_nodes(&self) -> HashMap<Uuid, DiffNode<'_>> {
+        self.nodes
+            .iter()
+            .map(|(id, node)| {
+                let routes = node
+                    .handles
+                    .routes
+                    .iter()
+                    .map(|route| (route.node_id(), route.name()))
+                    .collect();
+                let diff_node = DiffNode {
+                    name: &node.name,
+                    node_type: &node.node_type,
+                    config: &node.config,
+                    routes,
+                };
+                (*id, diff_node)
+            })
+            .collect()
+    }
+
+    /// Whether each input of the node was passed a message by one of its predecessors in the run
+    fn received_inputs(&self, id: Uuid, node_stats: &[NodeRunStats]) -> bool {
+        let node = &self.nodes[&id];
+        !node.prev.is_empty()
+            && node.handles.inputs.iter().all(|(handle, _)| {
+                node.prev.iter().any(|prev_id| {
+                    self.nodes[prev_id].handles.routes.contains(handle)
+                        && node_stats.iter().any(|stats| {
+                            stats.node_id == *prev_id && stats.outcome == NodeOutcome::Success
+                        })
+                })
+            })
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::template_chain_graph;
+
+    use super::*;
+
+    #[test]
+    fn test_instantiate_takes_inputs_of_run() {
+        let graph = serde_json::from_value::<Graph>(template_chain_graph(1)).unwrap();
+        let compiled = CompiledGraph::compile(&graph).unwrap();
+
+        let first = compiled.instantiate(&graph).unwrap();
+        let second = compiled.instantiate(&graph).unwrap();
+        assert_eq!(first.len(), 3);
+        for (id, task) in first.iter() {
+            let other = &second[id];
+            assert_eq!(task.prev, other.prev);
+            assert_eq!(task.next, other.next);
+            // runs share node configs, but not input states
+            match graph.nodes.values().find(|node| node.id() == *id).unwrap() {
+                Node::Input(_) => assert!(!Arc::ptr_eq(&task.action, &other.action)),
+                _ => assert!(Arc::ptr_eq(&task.action, &other.action)),
+            }
+            for (handle, state) in task.input_states.iter() {
+                assert!(!Arc::ptr_eq(state, &other.input_states[handle]));
+            }
+        }
+
+        let mut without_inputs = graph.clone();
+        without_inputs
+            .nodes
+            .retain(|_, node| !matches!(node, Node::Input(_)));
+        assert!(compiled.instantiate(&without_inputs).is_err());
+    }
+
+    #[test]
+    fn test_outputs_are_routed_to_inputs_of_next_tasks() {
+        let graph = serde_json::from_value::<Graph>(template_chain_graph(1)).unwrap();
+        let tasks = CompiledGraph::compile(&graph)
+            .unwrap()
+            .instantiate(&graph)
+            .unwrap();
+
+        for task in tasks.values() {
+            assert_eq!(task.output.node_id(), task.id);
+            let mut routed = task
+                .routes
+                .iter()
+                .map(|route| route.node_id())
+                .collect::<Vec<_>>();
+            routed.sort();
+            let mut next = task.next.clone();
+            next.sort();
+            assert_eq!(routed, next);
+            for route in task.routes.iter() {
+                assert!(tasks[&route.node_id()].input_states.contains_key(route));
+            }
+        }
+        let answer = tasks.values().find(|task| task.next.is_empty()).unwrap();
+        let handle = answer.input_states.keys().next().unwrap();
+        assert_eq!(handle.to_string(), format!("{}.output", answer.id));
+    }
+
+    fn node_stats(id: Uuid, outcome: NodeOutcome) -> NodeRunStats {
+        let start_time = chrono::Utc::now();
+        NodeRunStats {
+            node_id: id,
+            node_name: String::new(),
+            node_type: String::new(),
+            start_time,
+            end_time: start_time + chrono::Duration::milliseconds(15),
+            total_token_count: 7,
+            approximate_cost: None,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_dot_is_sorted_and_overlaid_with_run() {
+        let graph = serde_json::from_value::<Graph>(template_chain_graph(1)).unwrap();
+        let compiled = CompiledGraph::compile(&graph).unwrap();
+        let id = |name: &str| {
+            graph
+                .nodes
+                .values()
+                .find(|node| node.name() == name)
+                .unwrap()
+                .id()
+        };
+        let (question, template, answer) = (id("question"), id("template0"), id("answer"));
+
+        let dot = compiled.to_dot(None);
+        assert_eq!(dot, compiled.to_dot(None));
+        let mut ids = [question, template, answer];
+        ids.sort();
+        let positions = ids
+            .iter()
+            .map(|id| dot.find(&format!("    \"{}\" [label=", id)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(dot.contains(&format!(
+            "    \"{}\" [label=\"template0\\nStringTemplate\\n{}\"];",
+            template, template
+        )));
+        assert!(dot.contains(&format!(
+            "    \"{}\" -> \"{}\" [label=\"text\"];",
+            question, template
+        )));
+        assert!(dot.contains(&format!(
+            "    \"{}\" -> \"{}\" [label=\"output\"];",
+            template, answer
+        )));
+
+        let failed = [
+            node_stats(question, NodeOutcome::Success),
+            node_stats(template, NodeOutcome::Error),
+        ];
+        let dot = compiled.to_dot(Some(&failed[..]));
+        assert!(dot.contains(&format!(
+            "    \"{}\" [label=\"question\\nInput\\n{}\\nsuccess\\n15ms, 7 tokens\", style=filled, fillcolor=palegreen];",
+            question, question
+        )));
+        assert!(dot.contains("\\nerror\\n15ms, 7 tokens\", style=filled, fillcolor=salmon];"));
+        assert!(dot.contains(&format!(
+            "    \"{}\" [label=\"answer\\nOutput\\n{}\\nskipped\", style=filled, fillcolor=lightgray];",
+            answer, answer
+        )));
+
+        let terminated = [node_stats(question, NodeOutcome::Success)];
+        let dot = compiled.to_dot(Some(&terminated[..]));
+        assert!(dot.contains("\\nterminated\", style=filled, fillcolor=khaki];"));
+    }
+}
@@ -5,18 +5,27 @@ use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
 use crate::{
-    chunk::runner::ChunkerRunner, language_model::LanguageModelRunner,
-    semantic_search::SemanticSearch,
+    chunk::runner::ChunkerRunner, clock::Clock, db::workspace::WorkspaceId,
+    language_model::LanguageModelRunner, secrets::ResolvedEnv, semantic_search::SemanticSearch,
 };
 
-use super::{nodes::StreamChunk, runner::PipelineRunner, RunType};
+use super::{
+    credentials::Credentials, model_defaults::ModelDefaults, nodes::StreamChunk,
+    prompts::RegistryPrompts, run_store::RunStore, runner::PipelineRunner, RunType,
+};
 
 #[derive(Debug)]
 pub struct Context {
     pub language_model: Arc<LanguageModelRunner>,
     pub chunker_runner: Arc<ChunkerRunner>,
     pub semantic_search: Arc<SemanticSearch>,
+    /// Client of the server, nodes calling external APIs share its connection pool
+    pub http_client: reqwest::Client,
     pub env: HashMap<String, String>,
+    /// Project secrets by name, nodes resolve `{{secret:NAME}}` references from them
+    pub secrets: HashMap<String, String>,
+    /// Credentials by alias, of the run request and the aliases the graph resolved from secrets
+    pub credentials: Credentials,
     pub tx: Option<Sender<StreamChunk>>,
     pub metadata: HashMap<String, String>,
     pub run_type: RunType,
@@ -24,5 +33,27 @@ pub struct Context {
     /// map from node id to the validated schema.
     /// This is stored in the context before runtime
     /// to avoid the schema being validated on every LLM node run.
-    pub baml_schemas: HashMap<Uuid, BamlContext>,
+    pub baml_schemas: Arc<HashMap<Uuid, Arc<BamlContext>>>,
+    /// Model defaults of the run's workspace, which graphs of subpipelines are resolved with
+    pub workspace_model_defaults: ModelDefaults,
+    /// Workspace of the run, which graphs of subpipelines run in too
+    pub workspace_id: Option<WorkspaceId>,
+    /// Registry prompts of the run, which references of graphs of subpipelines resolve to
+    pub prompts: Option<RegistryPrompts>,
+    /// Nodes sleep and time out on it, and message times are taken from it
+    pub clock: Arc<dyn Clock>,
+    /// Key/value store the nodes of the run share, see `run_store`
+    pub run_store: Arc<RunStore>,
+}
+
+impl Context {
+    /// Resolve `{{secret:NAME}}` and `{{env:NAME}}` references in a node config value
+    pub fn resolve_references(&self, value: &str) -> anyhow::Result<String> {
+        crate::secrets::resolve_references(value, &self.secrets, &self.env)
+    }
+
+    /// Run env with secret references in values resolved
+    pub fn resolved_env(&self) -> anyhow::Result<ResolvedEnv> {
+        crate::secrets::resolve_env(&self.env, &self.secrets)
+    }
 }
@@ -0,0 +1,217 @@
+//! Provider credentials of single nodes, referenced by alias
+//!
+//! An LLM node with a `credential` alias calls its provider with the API key the alias resolves
+//! to, rather than with the one of the run env, so that nodes of one pipeline can call the same
+//! provider with the keys of different customers. Aliases resolve from the `credentials` of the
+//! run request first, then from the project secrets by name. Runs of graphs whose aliases don't
+//! resolve fail before any node runs, with the nodes which need each missing alias.
+//!
+//! Values are only kept in the graph and context of the run, and zeroed once they're dropped.
+//! Runs with request credentials are executed locally rather than pushed to the run queue, and
+//! spans and meta logs record the alias which served a call, never the key.
+
+use std::{collections::HashMap, fmt};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use super::{nodes::Node, Graph};
+
+/// Credential values by alias
+#[derive(Clone, Default, Deserialize)]
+#[serde(from = "HashMap<String, String>")]
+pub struct Credentials(HashMap<String, Zeroizing<String>>);
+
+impl Credentials {
+    pub fn get(&self, alias: &str) -> Option<&str> {
+        self.0.get(alias).map(|value| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// These credentials, with the ones of the aliases they don't have taken from the secrets.
+    /// Otherwise the nodes whose aliases none of them have.
+    pub fn resolve(
+        &self,
+        requirements: &[CredentialRequirement],
+        secrets: &HashMap<String, String>,
+    ) -> Result<Self, MissingCredentialsError> {
+        let mut resolved = self.clone();
+        let mut missing_credentials = Vec::new();
+        for requirement in requirements {
+            if resolved.0.contains_key(&requirement.credential) {
+                continue;
+            }
+            match secrets.get(&requirement.credential) {
+                Some(value) => {
+                    resolved.0.insert(
+                        requirement.credential.clone(),
+                        Zeroizing::new(value.clone()),
+                    );
+                }
+                None => missing_credentials.push(requirement.clone()),
+            }
+        }
+
+        if missing_credentials.is_empty() {
+            Ok(resolved)
+        } else {
+            missing_credentials.sort();
+            Err(MissingCredentialsError {
+                missing_credentials,
+            })
+        }
+    }
+}
+
+impl From<HashMap<String, String>> for Credentials {
+    fn from(values: HashMap<String, String>) -> Self {
+        Self(
+            values
+                .into_iter()
+                .map(|(alias, value)| (alias, Zeroizing::new(value)))
+                .collect(),
+        )
+    }
+}
+
+/// Lists the aliases only, so that values never end up in logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Node which calls its provider with the credential of the alias
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRequirement {
+    pub node_name: String,
+    pub node_id: Uuid,
+    pub credential: String,
+}
+
+/// Nodes with credential aliases, of the graph and of the graphs of its subpipelines and maps.
+/// Fails if the graph of a subpipeline or map is invalid, whose aliases would go unchecked.
+pub fn credential_requirements(graph: &Graph) -> anyhow::Result<Vec<CredentialRequirement>> {
+    let mut requirements = Vec::new();
+    for node in graph.nodes.values() {
+        let subgraph = match node {
+            Node::LLM(llm_node) => {
+                if let Some(credential) = &llm_node.credential {
+                    requirements.push(CredentialRequirement {
+                        node_name: llm_node.name.clone(),
+                        node_id: llm_node.id,
+                        credential: credential.clone(),
+                    });
+                }
+                continue;
+            }
+            Node::Subpipeline(subpipeline_node) => &subpipeline_node.runnable_graph,
+            Node::Map(map_node) => &map_node.runnable_graph,
+            _ => continue,
+        };
+        let subgraph = serde_json::from_value::<Graph>(subgraph.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid graph of node {}: {}", node.name(), e))?;
+        requirements.extend(credential_requirements(&subgraph)?);
+    }
+    requirements.sort();
+    requirements.dedup();
+    Ok(requirements)
+}
+
+#[derive(Debug)]
+pub struct MissingCredentialsError {
+    pub missing_credentials: Vec<CredentialRequirement>,
+}
+
+impl fmt::Display for MissingCredentialsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let missing = self
+            .missing_credentials
+            .iter()
+            .map(|requirement| format!("{} ({})", requirement.credential, requirement.node_name))
+            .collect::<Vec<_>>();
+        write!(f, "{}", missing.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirement(node_name: &str, credential: &str) -> CredentialRequirement {
+        CredentialRequirement {
+            node_name: node_name.to_string(),
+            node_id: Uuid::new_v4(),
+            credential: credential.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_request_credentials_come_before_secrets() {
+        let requested = Credentials::from(HashMap::from([(
+            "customer_a".to_string(),
+            "sk-request".to_string(),
+        )]));
+        let secrets = HashMap::from([
+            ("customer_a".to_string(), "sk-secret-a".to_string()),
+            ("customer_b".to_string(), "sk-secret-b".to_string()),
+        ]);
+
+        let resolved = requested
+            .resolve(
+                &[
+                    requirement("a", "customer_a"),
+                    requirement("b", "customer_b"),
+                ],
+                &secrets,
+            )
+            .unwrap();
+        assert_eq!(resolved.get("customer_a"), Some("sk-request"));
+        assert_eq!(resolved.get("customer_b"), Some("sk-secret-b"));
+        assert_eq!(resolved.get("OPENAI_API_KEY"), None);
+        assert!(!format!("{:?}", resolved).contains("sk-"));
+    }
+
+    #[test]
+    fn test_missing_credentials_list_their_nodes() {
+        let missing = Credentials::default()
+            .resolve(
+                &[
+                    requirement("b", "customer_b"),
+                    requirement("a", "customer_a"),
+                ],
+                &HashMap::new(),
+            )
+            .unwrap_err();
+        assert_eq!(missing.to_string(), "customer_a (a), customer_b (b)");
+    }
+
+    #[test]
+    fn test_invalid_subgraphs_fail() {
+        let graph = serde_json::from_value::<Graph>(serde_json::json!({
+            "nodes": {
+                "sub": {
+                    "type": "Subpipeline",
+                    "id": Uuid::new_v4(),
+                    "name": "sub",
+                    "inputs": [],
+                    "outputs": [],
+                    "inputsMappings": {},
+                    "pipelineName": "sub",
+                    "pipelineVersionName": "v1",
+                    "runnableGraph": {"nodes": "not nodes"},
+                },
+            },
+            "pred": {},
+        }))
+        .unwrap();
+        let error = credential_requirements(&graph).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid graph of node sub"));
+    }
+}
@@ -0,0 +1,304 @@
+//! Semantic diff of two pipeline graphs
+//!
+//! Nodes are matched by id, so a renamed node is changed rather than removed and added. Changed
+//! nodes list the fields of their configs which differ, as JSON pointers, and edges are compared
+//! by the nodes and input handle they connect rather than by the handle ids of
+//! `inputsMappings`. Configs are diffed as compiled, so LLM nodes show their effective model and
+//! params, and `modelParams` are diffed by param.
+//!
+//! Configs hold `{{secret:NAME}}` references rather than secret values, so a diff shows which
+//! secret a field references, and rotating the value of a secret doesn't change the graph.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Fields which only the canvas of the workshop uses, and don't change how a node runs
+const COSMETIC_FIELDS: [&str; 6] = [
+    "position",
+    "positionAbsolute",
+    "width",
+    "height",
+    "selected",
+    "dragging",
+];
+/// Mappings of input handles, which are diffed as edges
+const MAPPING_FIELDS: [&str; 1] = ["inputsMappings"];
+/// JSON string fields diffed as the JSON objects they hold
+const JSON_STRING_FIELDS: [&str; 1] = ["modelParams"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphDiff {
+    pub added_nodes: Vec<NodeSummary>,
+    pub removed_nodes: Vec<NodeSummary>,
+    pub changed_nodes: Vec<NodeChange>,
+    pub added_edges: Vec<EdgeSummary>,
+    pub removed_edges: Vec<EdgeSummary>,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty()
+            && self.removed_nodes.is_empty()
+            && self.changed_nodes.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub node_type: String,
+}
+
+/// Node of both graphs whose config differs, with the name and type it has in the new graph
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeChange {
+    pub id: Uuid,
+    pub name: String,
+    pub node_type: String,
+    pub fields: Vec<FieldChange>,
+}
+
+/// Field which differs, unset on the side which doesn't have it
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    /// JSON pointer of the field in the node config
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Value>,
+}
+
+/// Edge from the output of a node to an input handle of another
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgeSummary {
+    pub from_name: String,
+    pub to_name: String,
+    /// Name of the input handle
+    pub input: String,
+    pub from: Uuid,
+    pub to: Uuid,
+}
+
+/// Node of a graph, as compared by the diff
+pub(super) struct DiffNode<'a> {
+    pub name: &'a str,
+    pub node_type: &'a str,
+    pub config: &'a Value,
+    /// Successors of the node, with the names of their input handles it's routed to
+    pub routes: Vec<(Uuid, &'a str)>,
+}
+
+pub(super) fn diff_graphs(
+    from: &HashMap<Uuid, DiffNode<'_>>,
+    to: &HashMap<Uuid, DiffNode<'_>>,
+) -> GraphDiff {
+    let summary = |id: &Uuid, node: &DiffNode<'_>| NodeSummary {
+        id: *id,
+        name: node.name.to_string(),
+        node_type: node.node_type.to_string(),
+    };
+
+    let mut diff = GraphDiff::default();
+    for (id, node) in to.iter() {
+        let Some(old) = from.get(id) else {
+            diff.added_nodes.push(summary(id, node));
+            continue;
+        };
+        let mut fields = Vec::new();
+        diff_values(
+            &mut fields,
+            String::new(),
+            Some(&normalize(old.config)),
+            Some(&normalize(node.config)),
+        );
+        if !fields.is_empty() {
+            diff.changed_nodes.push(NodeChange {
+                id: *id,
+                name: node.name.to_string(),
+                node_type: node.node_type.to_string(),
+                fields,
+            });
+        }
+    }
+    for (id, node) in from.iter() {
+        if !to.contains_key(id) {
+            diff.removed_nodes.push(summary(id, node));
+        }
+    }
+
+    let (from_edges, to_edges) = (edges(from), edges(to));
+    diff.added_edges = to_edges.difference(&from_edges).cloned().collect();
+    diff.removed_edges = from_edges.difference(&to_edges).cloned().collect();
+
+    let by_name = |a: &NodeSummary, b: &NodeSummary| (&a.name, a.id).cmp(&(&b.name, b.id));
+    diff.added_nodes.sort_by(by_name);
+    diff.removed_nodes.sort_by(by_name);
+    diff.changed_nodes
+        .sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+    diff
+}
+
+/// Edges of the graph, sorted by the names of the nodes they connect
+fn edges(nodes: &HashMap<Uuid, DiffNode<'_>>) -> BTreeSet<EdgeSummary> {
+    nodes
+        .iter()
+        .flat_map(|(id, node)| {
+            node.routes.iter().filter_map(move |(next_id, input)| {
+                Some(EdgeSummary {
+                    from_name: node.name.to_string(),
+                    to_name: nodes.get(next_id)?.name.to_string(),
+                    input: input.to_string(),
+                    from: *id,
+                    to: *next_id,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Config without the fields which aren't diffed, with JSON string fields parsed
+fn normalize(config: &Value) -> Value {
+    let Value::Object(config) = config else {
+        return config.clone();
+    };
+    let config = config
+        .iter()
+        .filter(|(key, _)| {
+            !COSMETIC_FIELDS.contains(&key.as_str()) && !MAPPING_FIELDS.contains(&key.as_str())
+        })
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(json) if JSON_STRING_FIELDS.contains(&key.as_str()) => {
+                    match serde_json::from_str::<Map<String, Value>>(json) {
+                        Ok(object) => Value::Object(object),
+                        Err(_) => value.clone(),
+                    }
+                }
+                _ => value.clone(),
+            };
+            (key.clone(), value)
+        })
+        .collect();
+    Value::Object(config)
+}
+
+/// Push the fields of the values which differ, objects are diffed by key and others as a whole
+fn diff_values(
+    fields: &mut Vec<FieldChange>,
+    path: String,
+    from: Option<&Value>,
+    to: Option<&Value>,
+) {
+    match (from, to) {
+        (Some(Value::Object(from)), Some(Value::Object(to))) => {
+            let keys = from.keys().chain(to.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                diff_values(
+                    fields,
+                    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1")),
+                    from.get(key),
+                    to.get(key),
+                );
+            }
+        }
+        (from, to) if from != to => fields.push(FieldChange {
+            path,
+            from: from.cloned(),
+            to: to.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::pipeline::{compiled::CompiledGraph, Graph};
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/graph_diff")
+            .join(name)
+    }
+
+    fn compile(name: &str) -> CompiledGraph {
+        let json = std::fs::read_to_string(fixture(name)).unwrap();
+        let graph = serde_json::from_str::<Graph>(&json).unwrap();
+        CompiledGraph::compile(&graph).unwrap()
+    }
+
+    /// Diff of the fixture versions, matched against its snapshot. Run with
+    /// `UPDATE_SNAPSHOTS=1` to write the snapshot after changing the output format.
+    #[test]
+    fn test_diff_snapshot() {
+        let diff = compile("from.json").diff(&compile("to.json"));
+        let actual = serde_json::to_value(&diff).unwrap();
+
+        let snapshot = fixture("diff.snap.json");
+        if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+            let json = serde_json::to_string_pretty(&actual).unwrap();
+            std::fs::write(&snapshot, json + "\n").unwrap();
+        }
+        let expected =
+            serde_json::from_str::<Value>(&std::fs::read_to_string(snapshot).unwrap()).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_same_graph_has_empty_diff() {
+        let graph = compile("from.json");
+        assert!(graph.diff(&graph).is_empty());
+    }
+
+    #[test]
+    fn test_diff_values_by_pointer() {
+        let mut fields = Vec::new();
+        diff_values(
+            &mut fields,
+            String::new(),
+            Some(&normalize(&serde_json::json!({
+                "text": "a",
+                "position": {"x": 0},
+                "a/b": 1,
+                "modelParams": "{\"temperature\": 1, \"seed\": 1}",
+            }))),
+            Some(&normalize(&serde_json::json!({
+                "text": "a",
+                "position": {"x": 100},
+                "a/b": 2,
+                "modelParams": "{\"seed\": 1}",
+            }))),
+        );
+        assert_eq!(
+            fields,
+            vec![
+                FieldChange {
+                    path: "/a~1b".to_string(),
+                    from: Some(1.into()),
+                    to: Some(2.into()),
+                },
+                FieldChange {
+                    path: "/modelParams/temperature".to_string(),
+                    from: Some(1.into()),
+                    to: None,
+                },
+            ]
+        );
+    }
+}
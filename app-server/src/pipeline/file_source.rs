@@ -0,0 +1,318 @@
+//! Pipelines loaded from `*.pipeline.json` files of a watched directory, for local development
+//!
+//! Set `PIPELINES_DIR` to a directory of runnable graphs, as exported from the workshop. Each
+//! file is compiled when it changes, and the pipeline is named after the file, e.g.
+//! `qa.pipeline.json` is `qa`. A file which fails to parse or compile keeps the last version of
+//! the pipeline which compiled. Runs hold the version they started with, so that a change of the
+//! file only affects runs started after it.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use log::{error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+
+use super::{
+    compiled::CompiledGraph,
+    validation::{validate_graph, GraphDiagnostic},
+    Graph,
+};
+
+pub const PIPELINE_FILE_SUFFIX: &str = ".pipeline.json";
+/// Events of a file within this time of each other are reloaded once, as editors and `cp`
+/// write a file in several steps
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Version of a pipeline which compiled
+pub struct FilePipeline {
+    pub name: String,
+    pub graph: Graph,
+    pub compiled: Arc<CompiledGraph>,
+    pub loaded_at: DateTime<Utc>,
+}
+
+/// Outcome of the last load of a pipeline file
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePipelineStatus {
+    pub name: String,
+    pub path: String,
+    /// When the version currently run was loaded, unset if the file never compiled
+    pub loaded_at: Option<DateTime<Utc>>,
+    pub diagnostics: Vec<GraphDiagnostic>,
+    /// Why the last change of the file wasn't loaded
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct FilePipelines {
+    /// Swapped as a whole, so that a lookup never sees a pipeline half replaced
+    pipelines: ArcSwap<HashMap<String, Arc<FilePipeline>>>,
+    statuses: DashMap<String, FilePipelineStatus>,
+}
+
+impl FilePipelines {
+    pub fn get(&self, name: &str) -> Option<Arc<FilePipeline>> {
+        self.pipelines.load().get(name).cloned()
+    }
+
+    /// Statuses of the pipeline files, ordered by name
+    pub fn statuses(&self) -> Vec<FilePipelineStatus> {
+        let mut statuses = self
+            .statuses
+            .iter()
+            .map(|status| status.value().clone())
+            .collect::<Vec<_>>();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// Load all pipeline files of the directory, and reload them whenever they change
+    ///
+    /// The watch lasts as long as the process.
+    pub fn watch(self: &Arc<Self>, dir: PathBuf) -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<PathBuf>();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) => {
+                    for path in event.paths {
+                        let _ = tx.send(path);
+                    }
+                }
+                Err(e) => error!("Failed to watch pipeline files: {}", e),
+            })?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+
+        for entry in std::fs::read_dir(&dir)? {
+            self.reload(&entry?.path());
+        }
+        info!("Watching pipeline files of {}", dir.display());
+
+        let pipelines = self.clone();
+        tokio::spawn(async move {
+            // dropping the watcher stops the watch
+            let _watcher = watcher;
+            while let Some(path) = rx.recv().await {
+                let mut paths = BTreeSet::from([path]);
+                while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                    paths.insert(path);
+                }
+                for path in paths {
+                    pipelines.reload(&path);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Load the current content of the file, or unload its pipeline if it was removed
+    pub fn reload(&self, path: &Path) {
+        let Some(name) = pipeline_name(path) else {
+            return;
+        };
+        if !path.exists() {
+            self.pipelines.rcu(|pipelines| {
+                let mut pipelines = HashMap::clone(pipelines);
+                pipelines.remove(&name);
+                pipelines
+            });
+            self.statuses.remove(&name);
+            info!("Unloaded pipeline {}", name);
+            return;
+        }
+
+        let mut diagnostics = Vec::new();
+        let loaded = load_file(path, &mut diagnostics);
+        for diagnostic in diagnostics.iter() {
+            warn!(
+                "Pipeline {}, {}: {}",
+                name,
+                diagnostic.node_name.as_deref().unwrap_or("graph"),
+                diagnostic.message
+            );
+        }
+
+        let previous = self.get(&name);
+        let status = match loaded {
+            Ok((graph, compiled)) => {
+                let pipeline = Arc::new(FilePipeline {
+                    name: name.clone(),
+                    graph,
+                    compiled,
+                    loaded_at: Utc::now(),
+                });
+                let loaded_at = pipeline.loaded_at;
+                self.pipelines.rcu(|pipelines| {
+                    let mut pipelines = HashMap::clone(pipelines);
+                    pipelines.insert(name.clone(), pipeline.clone());
+                    pipelines
+                });
+                info!("Loaded pipeline {} from {}", name, path.display());
+                FilePipelineStatus {
+                    name: name.clone(),
+                    path: path.display().to_string(),
+                    loaded_at: Some(loaded_at),
+                    diagnostics,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to load pipeline {}, keeping the last version which compiled: {:#}",
+                    name, e
+                );
+                FilePipelineStatus {
+                    name: name.clone(),
+                    path: path.display().to_string(),
+                    loaded_at: previous.map(|pipeline| pipeline.loaded_at),
+                    diagnostics,
+                    error: Some(format!("{:#}", e)),
+                }
+            }
+        };
+        self.statuses.insert(name, status);
+    }
+}
+
+/// Name of the pipeline of the file, unset if it isn't a pipeline file
+fn pipeline_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    file_name
+        .strip_suffix(PIPELINE_FILE_SUFFIX)
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+}
+
+fn load_file(
+    path: &Path,
+    diagnostics: &mut Vec<GraphDiagnostic>,
+) -> Result<(Graph, Arc<CompiledGraph>)> {
+    let content = std::fs::read_to_string(path)?;
+    let graph = serde_json::from_str::<Graph>(&content).context("Invalid graph")?;
+    *diagnostics = validate_graph(&graph);
+    let compiled = CompiledGraph::compile(&graph)?;
+    Ok((graph, Arc::new(compiled)))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn graph_json(output_name: &str) -> String {
+        let input_id = Uuid::new_v4();
+        let input_handle_id = Uuid::new_v4();
+        let output_id = Uuid::new_v4();
+        let output_handle_id = Uuid::new_v4();
+        serde_json::json!({
+            "nodes": {
+                "question": {
+                    "type": "Input",
+                    "id": input_id,
+                    "name": "question",
+                    "outputs": [{"id": input_handle_id, "name": "output", "type": "String"}],
+                    "inputType": "String",
+                },
+                output_name: {
+                    "type": "Output",
+                    "id": output_id,
+                    "name": output_name,
+                    "inputs": [{"id": output_handle_id, "name": "output", "type": "String"}],
+                    "inputsMappings": {output_handle_id.to_string(): input_handle_id},
+                },
+            },
+            "pred": {output_id.to_string(): [input_id]},
+        })
+        .to_string()
+    }
+
+    fn output_name(pipelines: &FilePipelines, name: &str) -> Option<String> {
+        let pipeline = pipelines.get(name)?;
+        let output = pipeline
+            .graph
+            .nodes
+            .keys()
+            .find(|node_name| *node_name != "question")?;
+        Some(output.clone())
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pipelines-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_invalid_file_keeps_last_version() {
+        let dir = temp_dir();
+        let path = dir.join("qa.pipeline.json");
+        let pipelines = FilePipelines::default();
+
+        std::fs::write(&path, graph_json("first")).unwrap();
+        pipelines.reload(&path);
+        let first = pipelines.get("qa").unwrap();
+        assert_eq!(output_name(&pipelines, "qa").as_deref(), Some("first"));
+
+        std::fs::write(&path, "{\"nodes\": ").unwrap();
+        pipelines.reload(&path);
+        assert_eq!(output_name(&pipelines, "qa").as_deref(), Some("first"));
+        let status = &pipelines.statuses()[0];
+        assert!(status.error.is_some());
+        assert_eq!(status.loaded_at, Some(first.loaded_at));
+
+        std::fs::write(&path, graph_json("second")).unwrap();
+        pipelines.reload(&path);
+        assert_eq!(output_name(&pipelines, "qa").as_deref(), Some("second"));
+        assert!(pipelines.statuses()[0].error.is_none());
+        // a run which started with the first version keeps it
+        assert!(first.graph.nodes.contains_key("first"));
+
+        std::fs::remove_file(&path).unwrap();
+        pipelines.reload(&path);
+        assert!(pipelines.get("qa").is_none());
+        assert!(pipelines.statuses().is_empty());
+
+        pipelines.reload(&dir.join("notes.json"));
+        assert!(pipelines.statuses().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rapid_writes_load_last_version() {
+        let dir = temp_dir();
+        let path = dir.join("qa.pipeline.json");
+        std::fs::write(&path, graph_json("version0")).unwrap();
+        let pipelines = Arc::new(FilePipelines::default());
+        pipelines.watch(dir.clone()).unwrap();
+        assert_eq!(output_name(&pipelines, "qa").as_deref(), Some("version0"));
+
+        for i in 1..=20 {
+            std::fs::write(&path, graph_json(&format!("version{}", i))).unwrap();
+        }
+        let mut loaded = None;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            loaded = output_name(&pipelines, "qa");
+            if loaded.as_deref() == Some("version20") {
+                break;
+            }
+        }
+        assert_eq!(loaded.as_deref(), Some("version20"));
+        assert!(pipelines.statuses()[0].error.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Schemas of the run inputs of a graph
+//!
+//! Input nodes may declare an `inputSchema`, a JSON schema of the value of their input, which
+//! run inputs are checked against when the graph is set up, before any node runs. Inputs which
+//! no input node takes are rejected if the graph JSON sets `strictInputs`, and logged otherwise.
+//!
+//! The schema of all the inputs of a graph is an object schema, whose properties are the
+//! declared schemas of the input nodes, or the schemas of their input types if they declare
+//! none.
+
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use utoipa::ToSchema;
+
+use super::{
+    nodes::{input::InputNode, schema, HandleType, Node, NodeInput},
+    Graph,
+};
+
+/// Run input which doesn't match the graph's input schema
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+pub struct InputError {
+    /// Name of the input
+    pub input: String,
+    /// JSON pointer to the field in the value of the input, empty for the value itself
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}: {}", self.input, self.pointer, self.message)
+    }
+}
+
+pub(super) fn format_input_errors(errors: &[InputError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Problems of the input of the node against its declared schema
+pub(super) fn validate_input(node: &InputNode, input: &NodeInput) -> Vec<InputError> {
+    let Some(input_schema) = &node.input_schema else {
+        return Vec::new();
+    };
+    let value = serde_json::to_value(input).unwrap_or_default();
+    schema::validate(input_schema, &value)
+        .into_iter()
+        .map(|error| InputError {
+            input: node.name.clone(),
+            pointer: error.pointer,
+            message: error.message,
+        })
+        .collect()
+}
+
+/// Problems of the inputs which no input node takes, with the closest input name if any
+pub(super) fn unknown_inputs(
+    inputs: &HashMap<String, NodeInput>,
+    names: &[&str],
+) -> Vec<InputError> {
+    inputs
+        .keys()
+        .filter(|name| !names.contains(&name.as_str()))
+        .map(|name| InputError {
+            input: name.clone(),
+            pointer: String::new(),
+            message: match schema::closest(name, names) {
+                Some(closest) => format!("unknown input, did you mean {:?}?", closest),
+                None => "unknown input".to_string(),
+            },
+        })
+        .collect()
+}
+
+/// JSON schema of the inputs of a run of the graph
+pub fn graph_input_schema(graph: &Graph) -> Value {
+    let mut input_nodes = graph
+        .nodes
+        .values()
+        .filter_map(|node| match node {
+            Node::Input(node) => Some(node),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    input_nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let properties = input_nodes
+        .iter()
+        .map(|node| {
+            let schema = node
+                .input_schema
+                .clone()
+                .unwrap_or_else(|| handle_type_schema(&node.input_type));
+            (node.name.clone(), schema)
+        })
+        .collect::<Map<_, _>>();
+    let required = input_nodes
+        .iter()
+        .map(|node| node.name.clone())
+        .collect::<Vec<_>>();
+    let mut input_schema = json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    if graph.strict_inputs {
+        input_schema["additionalProperties"] = Value::Bool(false);
+    }
+    input_schema
+}
+
+fn handle_type_schema(handle_type: &HandleType) -> Value {
+    match handle_type {
+        HandleType::String => json!({"type": "string"}),
+        HandleType::StringList => json!({"type": "array", "items": {"type": "string"}}),
+        HandleType::ChatMessageList => json!({"type": "array", "items": {"type": "object"}}),
+        HandleType::Float => json!({"type": "number"}),
+        HandleType::Any => json!({}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::GraphError;
+
+    fn graph(strict_inputs: bool) -> Graph {
+        serde_json::from_value(json!({
+            "nodes": {
+                "a": {
+                    "type": "Input",
+                    "id": uuid::Uuid::new_v4(),
+                    "name": "level",
+                    "outputs": [{"id": uuid::Uuid::new_v4(), "type": "String"}],
+                    "inputType": "String",
+                    "inputSchema": {"enum": ["low", "high"]},
+                },
+                "b": {
+                    "type": "Input",
+                    "id": uuid::Uuid::new_v4(),
+                    "name": "question",
+                    "outputs": [{"id": uuid::Uuid::new_v4(), "type": "String"}],
+                    "inputType": "String",
+                },
+            },
+            "pred": {},
+            "strictInputs": strict_inputs,
+        }))
+        .unwrap()
+    }
+
+    fn inputs(level: &str, extra: Option<&str>) -> HashMap<String, NodeInput> {
+        let mut inputs = HashMap::from([
+            ("level".to_string(), NodeInput::String(level.to_string())),
+            (
+                "question".to_string(),
+                NodeInput::String("Why?".to_string()),
+            ),
+        ]);
+        if let Some(extra) = extra {
+            inputs.insert(extra.to_string(), NodeInput::String(String::new()));
+        }
+        inputs
+    }
+
+    fn setup(graph: &mut Graph, inputs: &HashMap<String, NodeInput>) -> Result<(), GraphError> {
+        graph.setup(
+            inputs,
+            &HashMap::new(),
+            &HashMap::new(),
+            &Default::default(),
+        )
+    }
+
+    #[test]
+    fn test_inputs_are_validated_against_schema() {
+        assert!(setup(&mut graph(false), &inputs("low", None)).is_ok());
+
+        let Err(GraphError::InvalidInputs(errors)) =
+            setup(&mut graph(false), &inputs("medium", None))
+        else {
+            panic!("expected invalid inputs");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].input, "level");
+        assert_eq!(errors[0].pointer, "");
+    }
+
+    #[test]
+    fn test_unknown_inputs_are_rejected_if_strict() {
+        assert!(setup(&mut graph(false), &inputs("low", Some("questoin"))).is_ok());
+
+        let Err(GraphError::InvalidInputs(errors)) =
+            setup(&mut graph(true), &inputs("low", Some("questoin")))
+        else {
+            panic!("expected invalid inputs");
+        };
+        assert_eq!(
+            errors,
+            vec![InputError {
+                input: "questoin".to_string(),
+                pointer: String::new(),
+                message: "unknown input, did you mean \"question\"?".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_graph_input_schema() {
+        assert_eq!(
+            graph_input_schema(&graph(true)),
+            json!({
+                "type": "object",
+                "properties": {
+                    "level": {"enum": ["low", "high"]},
+                    "question": {"type": "string"},
+                },
+                "required": ["level", "question"],
+                "additionalProperties": false,
+            })
+        );
+    }
+}
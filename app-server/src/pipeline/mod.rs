@@ -1,37 +1,168 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::result::Result;
+use std::sync::Arc;
 
 use lmnr_baml::BamlContext;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use thiserror::Error;
 use uuid::Uuid;
 
-use self::nodes::{Node, NodeInput};
-use crate::language_model::providers::utils::get_required_env_vars_for_model;
+use self::credentials::Credentials;
+use self::inputs::InputError;
+use self::model_defaults::ModelDefaults;
+use self::nodes::{post_processors, registry, Node, NodeInput};
+use self::prompts::RegistryPrompts;
+use self::validation::GraphDiagnostic;
+use crate::db::workspace::WorkspaceId;
+use crate::language_model::context_window::{ContextStrategy, ContextWindow};
+use crate::language_model::providers::utils::{
+    get_api_key_env_var_for_model, get_provider, get_required_env_vars_for_model,
+};
+use crate::secrets::{get_json_references, Reference};
 
+pub mod builder;
+pub mod bundle;
+pub mod compiled;
 pub mod context;
+pub mod credentials;
+pub mod diff;
+pub mod file_source;
+pub mod inputs;
+pub mod model_defaults;
 pub mod nodes;
+pub mod outputs;
+pub mod prompts;
+pub mod run_store;
 pub mod runner;
+pub mod simulate;
 pub mod templates;
 pub mod trace;
 pub mod utils;
+pub mod validation;
+
+type BamlSchemaCache =
+    moka::sync::Cache<(String, Option<String>), Result<Arc<BamlContext>, String>>;
+
+lazy_static::lazy_static! {
+    /// Validated structured output schemas by schema and target, shared by nodes of any graph
+    static ref BAML_SCHEMAS: BamlSchemaCache = moka::sync::Cache::new(10_000);
+}
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "GraphJson")]
 pub struct Graph {
     pub nodes: HashMap<String, Node>,
     pub pred: HashMap<Uuid, Vec<Uuid>>,
+    /// Defaults of the LLM nodes of the pipeline, over the workspace's
+    pub model_defaults: ModelDefaults,
+    /// Reject run inputs which no input node takes, rather than log them, see `inputs`
+    pub strict_inputs: bool,
+    /// Names of the run outputs, bound to `node.handle` or `node`, see `outputs`
+    pub output_bindings: BTreeMap<String, String>,
+    /// References to the post-processors of the nodes which declare them in their
+    /// `postProcessors` config, by node id, see `nodes::post_processors`
+    pub post_processors: HashMap<Uuid, Vec<String>>,
+    /// Record the run store on the run's span once the run ends, see `run_store`
+    pub trace_run_store: bool,
+    /// Defaults of the LLM nodes of the workspace the graph runs in, see `model_defaults`
+    #[serde(skip)]
+    pub workspace_model_defaults: ModelDefaults,
+    /// Workspace of the project the graph runs in, unset for graphs which aren't run in one
+    #[serde(skip)]
+    pub workspace_id: Option<WorkspaceId>,
+    /// Versions of the registry prompts the nodes reference, unset until they're resolved for a
+    /// run, see `prompts`
+    #[serde(skip)]
+    pub prompts: Option<RegistryPrompts>,
     #[serde(skip)]
     pub env: HashMap<String, String>,
+    /// Decrypted project secrets, resolved from `{{secret:NAME}}` references at node execution
+    #[serde(skip)]
+    pub secrets: HashMap<String, String>,
+    /// Credentials of the run request by alias, see `credentials`
+    #[serde(skip)]
+    pub credentials: Credentials,
     #[serde(skip)]
     pub metadata: HashMap<String, String>,
     #[serde(skip)]
     pub run_type: RunType,
+    /// Record the inputs of each node, so that the run's node I/O can be persisted
+    #[serde(skip)]
+    pub record_node_io: bool,
+    /// Content hash of the COMMIT version the graph is of, unset once its node configs change
+    #[serde(skip)]
+    pub content_hash: Option<String>,
+    /// Problems of the node configs of the graph JSON, e.g. unknown fields, which serde ignores
+    #[serde(skip)]
+    pub config_diagnostics: Vec<GraphDiagnostic>,
+}
+
+/// Graph JSON, whose node configs are validated against the schemas of their node types
+#[derive(Deserialize)]
+struct GraphJson {
+    nodes: HashMap<String, Value>,
+    pred: HashMap<Uuid, Vec<Uuid>>,
+    #[serde(default, rename = "modelDefaults")]
+    model_defaults: ModelDefaults,
+    #[serde(default, rename = "strictInputs")]
+    strict_inputs: bool,
+    #[serde(default, rename = "outputBindings")]
+    output_bindings: BTreeMap<String, String>,
+    #[serde(default, rename = "traceRunStore")]
+    trace_run_store: bool,
+}
+
+impl TryFrom<GraphJson> for Graph {
+    type Error = serde_json::Error;
+
+    fn try_from(json: GraphJson) -> Result<Self, Self::Error> {
+        let config_diagnostics = validation::config_diagnostics(&json.nodes);
+        let registry = registry::registry();
+        let mut post_processors = HashMap::new();
+        let nodes = json
+            .nodes
+            .into_iter()
+            .map(|(key, config)| {
+                let references = post_processors::declared(&config)?;
+                let node = registry.load(config)?;
+                if !references.is_empty() {
+                    post_processors.insert(node.id(), references);
+                }
+                Ok((key, node))
+            })
+            .collect::<Result<HashMap<_, _>, serde_json::Error>>()?;
+        Ok(Self {
+            nodes,
+            pred: json.pred,
+            model_defaults: json.model_defaults,
+            strict_inputs: json.strict_inputs,
+            output_bindings: json.output_bindings,
+            post_processors,
+            trace_run_store: json.trace_run_store,
+            workspace_model_defaults: ModelDefaults::default(),
+            workspace_id: None,
+            prompts: None,
+            env: HashMap::new(),
+            secrets: HashMap::new(),
+            credentials: Credentials::default(),
+            metadata: HashMap::new(),
+            run_type: RunType::default(),
+            record_node_io: false,
+            content_hash: None,
+            config_diagnostics,
+        })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum GraphError {
     #[error("Graph input is missing: {0}")]
     InputMissing(String),
+    #[error("Graph inputs are invalid: {}", inputs::format_input_errors(.0))]
+    InvalidInputs(Vec<InputError>),
+    #[error("Unknown prompts: {}", .0.join(", "))]
+    UnknownPrompts(Vec<String>),
     #[error("{0}")]
     UnhandledError(#[from] anyhow::Error),
 }
@@ -78,52 +209,51 @@ impl Graph {
         Ok(())
     }
 
-    pub fn get_missing_env_vars(&self) -> HashSet<String> {
-        let required_env_vars = self.get_required_env_vars();
-        required_env_vars
-            .into_iter()
-            .filter(|var| !self.env.contains_key(var))
-            .collect::<HashSet<_>>()
-    }
-
-    pub fn validate_baml_schemas(&self) -> Result<HashMap<Uuid, BamlContext>, InvalidSchemasError> {
-        let mut schemas = HashMap::new();
-        let mut errors = HashMap::new();
+    /// References in node configs, so that missing ones fail before the run
+    fn get_config_references(&self) -> HashSet<Reference> {
+        let mut references = HashSet::new();
         for node in self.nodes.values() {
-            if let Some(res) = self.validate_baml_schemas_for_node(node) {
-                match res {
-                    Ok(schema) => {
-                        schemas.insert(node.id(), schema);
-                    }
-                    Err(e) => {
-                        errors.insert(node.name(), e.to_string());
-                    }
-                }
+            // Input nodes only hold the run inputs, not configs
+            if matches!(node, Node::Input(_)) {
+                continue;
+            }
+            if let Ok(node) = serde_json::to_value(node) {
+                references.extend(get_json_references(&node));
             }
         }
-        if errors.is_empty() {
-            Ok(schemas)
-        } else {
-            Err(InvalidSchemasError {
-                invalid_schemas: errors,
-            })
-        }
+        references
     }
 
+    /// Set the inputs of the input nodes, if they match the input schema of the graph
     fn setup_inputs(&mut self, inputs: &HashMap<String, NodeInput>) -> Result<(), GraphError> {
+        let mut errors = Vec::new();
         for node in self.nodes.values_mut() {
-            match node {
-                Node::Input(input_node) => {
-                    if let Some(input) = inputs.get(&input_node.name) {
-                        input_node.input = Some(input.clone());
-                    } else {
-                        return Err(GraphError::InputMissing(input_node.name.clone()));
-                    }
-                }
-                _ => {}
+            if let Node::Input(input_node) = node {
+                let Some(input) = inputs.get(&input_node.name) else {
+                    return Err(GraphError::InputMissing(input_node.name.clone()));
+                };
+                errors.extend(inputs::validate_input(input_node, input));
+                input_node.input = Some(input.clone());
             }
         }
-        Ok(())
+
+        let names = self.get_input_node_names();
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+        let unknown = inputs::unknown_inputs(inputs, &names);
+        if self.strict_inputs {
+            errors.extend(unknown);
+        } else {
+            for error in unknown {
+                log::warn!("Ignoring graph input: {}", error);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            errors.sort();
+            Err(GraphError::InvalidInputs(errors))
+        }
     }
 
     pub fn get_input_node_names(&self) -> HashSet<String> {
@@ -145,8 +275,22 @@ impl Graph {
                     env_vars.insert("ZENGUARD_API_KEY".to_string());
                 }
                 Node::LLM(llm_node) => {
-                    if let Some(model_name) = &llm_node.model {
-                        let model_env_vars = get_required_env_vars_for_model(model_name);
+                    let summary_model = match &llm_node.context_window {
+                        Some(ContextWindow {
+                            strategy: ContextStrategy::Summarize { model },
+                            ..
+                        }) => Some(model),
+                        _ => None,
+                    };
+                    for model_name in llm_node.model.iter().chain(summary_model) {
+                        let mut model_env_vars = get_required_env_vars_for_model(model_name);
+                        // the node's credential is the API key of its provider
+                        let provider = llm_node.model.as_deref().and_then(get_provider);
+                        if llm_node.credential.is_some() && get_provider(model_name) == provider {
+                            if let Some(api_key) = get_api_key_env_var_for_model(model_name) {
+                                model_env_vars.remove(api_key);
+                            }
+                        }
                         env_vars.extend(model_env_vars);
                     }
                 }
@@ -175,21 +319,36 @@ impl Graph {
                 | Node::SemanticSwitch(_)
                 | Node::SemanticSearch(_)
                 | Node::SemanticSimilarity(_)
-                | Node::StringTemplate(_) => {}
+                | Node::RunStore(_)
+                | Node::StringTemplate(_)
+                | Node::Custom(_) => {}
             }
         }
         env_vars
     }
 
-    fn validate_baml_schemas_for_node(&self, node: &Node) -> Option<anyhow::Result<BamlContext>> {
+    /// Structured output schema of the node, if it has one. Nodes with the same schema share one
+    /// compiled context.
+    fn validate_baml_schemas_for_node(
+        &self,
+        node: &Node,
+    ) -> Option<Result<Arc<BamlContext>, String>> {
         match node {
             Node::LLM(llm_node) => {
                 let params = &llm_node.structured_output_params;
                 if params.structured_output_enabled && params.structured_output_schema.is_some() {
-                    let context = BamlContext::try_from_schema(
-                        &params.structured_output_schema.as_ref().unwrap(),
+                    let key = (
+                        params.structured_output_schema.clone().unwrap(),
                         params.structured_output_schema_target.clone(),
                     );
+                    let context = BAML_SCHEMAS.get_with(key, || {
+                        BamlContext::try_from_schema(
+                            params.structured_output_schema.as_ref().unwrap(),
+                            params.structured_output_schema_target.clone(),
+                        )
+                        .map(Arc::new)
+                        .map_err(|e| e.to_string())
+                    });
                     Some(context)
                 } else {
                     None
@@ -0,0 +1,290 @@
+//! Layered defaults of the model and params of LLM nodes
+//!
+//! The effective config of an LLM node is resolved from, lowest first, the server defaults in
+//! `LLM_NODE_DEFAULTS`, the defaults of the workspace, the `modelDefaults` of the pipeline's
+//! graph, and the node's own `model` and `modelParams`. Params are merged by key, so a node
+//! which only sets `temperature` keeps the default `max_tokens`. Nodes with a `model` input take
+//! the model of the input rather than a default one.
+//!
+//! Graphs are resolved when they're compiled, so that validation sees the effective configs.
+//! Nodes keep the resolved config, with the layer each value comes from, and record it in their
+//! meta log and on their spans. Nodes whose params aren't a JSON object, e.g. a number given as
+//! an `{{env:...}}` reference, keep their params as they are.
+//!
+//! Embedding models are set per semantic search index rather than per node, so they have no
+//! defaults here.
+
+use std::{borrow::Cow, collections::BTreeMap, env};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use crate::language_model::LanguageModelProviderName;
+
+use super::{
+    nodes::{llm::LLMNode, Node},
+    utils::get_graph_content_hash,
+    Graph,
+};
+
+/// JSON of the server defaults, e.g. `{"model": "openai:gpt-4o-mini", "params": {"seed": 1}}`
+pub const SERVER_DEFAULTS_ENV: &str = "LLM_NODE_DEFAULTS";
+
+lazy_static::lazy_static! {
+    static ref SERVER_DEFAULTS: ModelDefaults = load_server_defaults();
+}
+
+/// Model and params of one layer, unset values are taken from the layers below
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelDefaults {
+    /// `provider:model`, e.g. `openai:gpt-4o-mini`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Params of the provider's API, e.g. `temperature` and `max_tokens`
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+}
+
+impl ModelDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.model.is_none() && self.params.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(model) = &self.model {
+            let Some((provider, name)) = model.split_once(':') else {
+                return Err(anyhow::anyhow!(
+                    "Model must be `provider:model`, got {}",
+                    model
+                ));
+            };
+            if name.is_empty() || LanguageModelProviderName::from_str(provider).is_err() {
+                return Err(anyhow::anyhow!("Unknown model {}", model));
+            }
+        }
+        Ok(())
+    }
+
+    /// Layer of the node's own config, unset if its params aren't a JSON object
+    fn of_node(node: &LLMNode) -> Option<Self> {
+        let params = match node.model_params.as_deref().map(str::trim) {
+            None | Some("") => Map::new(),
+            Some(params) => serde_json::from_str::<Map<String, Value>>(params).ok()?,
+        };
+        Some(Self {
+            model: node.model.clone(),
+            params,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigLayer {
+    Server,
+    Workspace,
+    Pipeline,
+    Node,
+}
+
+/// Effective model config of an LLM node, with the layer each value comes from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedModelConfig {
+    /// Unset if no layer sets it, or the node takes it from its `model` input
+    pub model: Option<String>,
+    pub model_layer: Option<ConfigLayer>,
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+    pub param_layers: BTreeMap<String, ConfigLayer>,
+}
+
+impl ResolvedModelConfig {
+    fn resolve(layers: &[(ConfigLayer, &ModelDefaults)], model_from_input: bool) -> Self {
+        let mut resolved = Self::default();
+        for (layer, defaults) in layers {
+            if let Some(model) = &defaults.model {
+                // the node's own model still comes before its input
+                if !model_from_input || *layer == ConfigLayer::Node {
+                    resolved.model = Some(model.clone());
+                    resolved.model_layer = Some(*layer);
+                }
+            }
+            for (key, value) in defaults.params.iter() {
+                resolved.params.insert(key.clone(), value.clone());
+                resolved.param_layers.insert(key.clone(), *layer);
+            }
+        }
+        resolved
+    }
+}
+
+pub fn server_defaults() -> &'static ModelDefaults {
+    &SERVER_DEFAULTS
+}
+
+fn load_server_defaults() -> ModelDefaults {
+    let Ok(json) = env::var(SERVER_DEFAULTS_ENV) else {
+        return ModelDefaults::default();
+    };
+    match serde_json::from_str::<ModelDefaults>(&json)
+        .map_err(anyhow::Error::from)
+        .and_then(|defaults| defaults.validate().map(|_| defaults))
+    {
+        Ok(defaults) => defaults,
+        Err(e) => {
+            log::error!("Ignoring invalid {}: {}", SERVER_DEFAULTS_ENV, e);
+            ModelDefaults::default()
+        }
+    }
+}
+
+/// Graph whose LLM nodes have their effective model and params, and keep the resolved config
+pub fn resolve_graph(graph: &Graph) -> Cow<'_, Graph> {
+    if !graph
+        .nodes
+        .values()
+        .any(|node| matches!(node, Node::LLM(_)))
+    {
+        return Cow::Borrowed(graph);
+    }
+
+    let mut resolved = graph.clone();
+    for node in resolved.nodes.values_mut() {
+        if let Node::LLM(node) = node {
+            resolve_node(
+                node,
+                server_defaults(),
+                &graph.workspace_model_defaults,
+                &graph.model_defaults,
+            );
+        }
+    }
+    Cow::Owned(resolved)
+}
+
+fn resolve_node(
+    node: &mut LLMNode,
+    server: &ModelDefaults,
+    workspace: &ModelDefaults,
+    pipeline: &ModelDefaults,
+) {
+    let Some(own) = ModelDefaults::of_node(node) else {
+        return;
+    };
+    let model_from_input = node
+        .inputs
+        .iter()
+        .chain(node.dynamic_inputs.iter())
+        .any(|handle| handle.name.as_deref() == Some("model"));
+    let resolved = ResolvedModelConfig::resolve(
+        &[
+            (ConfigLayer::Server, server),
+            (ConfigLayer::Workspace, workspace),
+            (ConfigLayer::Pipeline, pipeline),
+            (ConfigLayer::Node, &own),
+        ],
+        model_from_input,
+    );
+
+    node.model = resolved.model.clone();
+    if !resolved.params.is_empty() {
+        node.model_params = Some(Value::Object(resolved.params.clone()).to_string());
+    }
+    node.resolved_config = Some(resolved);
+}
+
+/// Key of the compiled graph of a COMMIT version, which depends on the workspace defaults too
+pub fn compiled_cache_key(content_hash: &str, graph: &Graph) -> String {
+    if graph.workspace_model_defaults.is_empty() {
+        return content_hash.to_string();
+    }
+    let defaults = serde_json::to_value(&graph.workspace_model_defaults).unwrap_or_default();
+    format!("{}:{}", content_hash, get_graph_content_hash(&defaults))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn defaults(value: Value) -> ModelDefaults {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_layers_override_by_key() {
+        let mut node = serde_json::from_value::<LLMNode>(json!({
+            "id": uuid::Uuid::new_v4(),
+            "name": "llm",
+            "inputs": [],
+            "dynamicInputs": [],
+            "outputs": [],
+            "inputsMappings": {},
+            "prompt": "Hi",
+            "modelParams": "{\"temperature\": 0.2}",
+        }))
+        .unwrap();
+        let server = defaults(json!({
+            "model": "openai:gpt-4o-mini",
+            "params": {"temperature": 1, "max_tokens": 256},
+        }));
+        let workspace = defaults(json!({"model": "anthropic:claude-3-haiku"}));
+        let pipeline = defaults(json!({"params": {"max_tokens": 1024}}));
+
+        resolve_node(&mut node, &server, &workspace, &pipeline);
+
+        assert_eq!(node.model.as_deref(), Some("anthropic:claude-3-haiku"));
+        assert_eq!(
+            serde_json::from_str::<Value>(node.model_params.as_deref().unwrap()).unwrap(),
+            json!({"temperature": 0.2, "max_tokens": 1024})
+        );
+        let resolved = node.resolved_config.unwrap();
+        assert_eq!(resolved.model_layer, Some(ConfigLayer::Workspace));
+        assert_eq!(resolved.param_layers["temperature"], ConfigLayer::Node);
+        assert_eq!(resolved.param_layers["max_tokens"], ConfigLayer::Pipeline);
+    }
+
+    #[test]
+    fn test_model_input_is_not_overridden() {
+        let model_input = json!({"id": uuid::Uuid::new_v4(), "name": "model", "type": "String"});
+        let mut node = serde_json::from_value::<LLMNode>(json!({
+            "id": uuid::Uuid::new_v4(),
+            "name": "llm",
+            "inputs": [],
+            "dynamicInputs": [model_input],
+            "outputs": [],
+            "inputsMappings": {},
+            "prompt": "Hi",
+        }))
+        .unwrap();
+        let server = defaults(json!({"model": "openai:gpt-4o-mini"}));
+
+        resolve_node(
+            &mut node,
+            &server,
+            &ModelDefaults::default(),
+            &ModelDefaults::default(),
+        );
+
+        assert_eq!(node.model, None);
+        assert_eq!(node.model_params, None);
+        assert_eq!(node.resolved_config.unwrap().model_layer, None);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(defaults(json!({"model": "openai:gpt-4o"}))
+            .validate()
+            .is_ok());
+        assert!(defaults(json!({"model": "gpt-4o"})).validate().is_err());
+        assert!(defaults(json!({"model": "nobody:gpt-4o"}))
+            .validate()
+            .is_err());
+    }
+}
@@ -1,18 +1,18 @@
 use std::ops::Deref;
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
-use super::NodeInput;
 use super::{ConditionedValue, Handle};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ConditionNode {
     pub id: Uuid,
@@ -21,10 +21,14 @@ pub struct ConditionNode {
     pub outputs: Vec<Handle>,
     pub inputs_mappings: HashMap<Uuid, Uuid>,
     pub condition: String,
+    /// Finish the run with the routed value instead of passing it on, e.g. for the `block` route
+    /// of a guardrail, see `RunOutput::Finish`
+    #[serde(default)]
+    pub finish: bool,
 }
 
 #[async_trait]
-impl RunnableNode for ConditionNode {
+impl NodeImpl for ConditionNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -45,17 +49,94 @@ impl RunnableNode for ConditionNode {
         "Condition".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input: ConditionedValue = inputs.values().next().unwrap().clone().try_into()?;
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input: ConditionedValue = input.single_value()?.clone().try_into()?;
 
-        if input.condition == self.condition {
-            Ok(RunOutput::Success((input.value.deref().clone(), None)))
-        } else {
+        if input.condition != self.condition {
             Ok(RunOutput::Termination)
+        } else if self.finish {
+            Ok(RunOutput::Finish((input.value.deref().clone(), None)))
+        } else {
+            Ok(RunOutput::Success((input.value.deref().clone(), None)))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        language_model::{LanguageModelRunner, MockFailure, MockLlmProvider},
+        pipeline::{
+            builder::{GraphBuilder, NodeConfig},
+            nodes::{HandleType, NodeInput},
+            trace::{NodeOutcome, NodeRunStats},
+            RunType,
+        },
+        testing::OfflineServices,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_finishing_condition_ends_run() {
+        let template =
+            |text: &str| NodeConfig::string_template(text).input("question", HandleType::String);
+        let draft = NodeConfig::llm("openai:gpt-4o-mini", "Draft {{question}}")
+            .input("question", HandleType::String);
+        let mut graph = GraphBuilder::new()
+            .node("question", NodeConfig::graph_input(HandleType::String))
+            .node("topic", NodeConfig::graph_input(HandleType::String))
+            .node("router", NodeConfig::switch(&["refuse", "chat"]))
+            .node("refuse", NodeConfig::finish_condition("refuse"))
+            .node("chat", NodeConfig::condition("chat"))
+            .node("reply", template("Reply to {{question}}"))
+            .node("answer", NodeConfig::graph_output())
+            .node("draft", draft)
+            .node("drafted", NodeConfig::graph_output())
+            .edge("topic.output", "router.condition")
+            .edge("question.output", "router.input")
+            .edge("router", "refuse")
+            .edge("router", "chat")
+            .edge("chat", "reply.question")
+            .edge("reply", "answer")
+            .edge("question", "draft.question")
+            .edge("draft", "drafted")
+            .build()
+            .unwrap();
+        let inputs = HashMap::from([
+            ("question".to_string(), NodeInput::String("why".to_string())),
+            ("topic".to_string(), NodeInput::String("refuse".to_string())),
+        ]);
+        let env = HashMap::from([("OPENAI_API_KEY".to_string(), "key".to_string())]);
+        graph
+            .setup(&inputs, &env, &HashMap::new(), &RunType::Workshop)
+            .unwrap();
+
+        // the draft is still being generated when the run finishes
+        let mock = MockLlmProvider::new()
+            .fail_on("Draft", MockFailure::Timeout(Duration::from_secs(3600)));
+        let services = OfflineServices::with_language_model(LanguageModelRunner::mocked(mock));
+        let outputs = services
+            .context()
+            .pipeline_runner
+            .run(graph, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outputs.output_values(),
+            HashMap::from([("refuse".to_string(), NodeInput::String("why".to_string()))])
+        );
+        let node_stats = NodeRunStats::from_engine_output(&outputs);
+        let outcomes = node_stats
+            .iter()
+            .map(|stats| (stats.node_name.as_str(), stats.outcome))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(outcomes["refuse"], NodeOutcome::Success);
+        assert_eq!(outcomes["draft"], NodeOutcome::Skipped);
+        // the other route terminated its branch
+        assert!(!outcomes.contains_key("reply"));
+    }
+}
@@ -1,16 +1,17 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
-use super::{Handle, NodeInput};
+use super::Handle;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorNode {
     pub id: Uuid,
@@ -21,7 +22,7 @@ pub struct ErrorNode {
 }
 
 #[async_trait]
-impl RunnableNode for ErrorNode {
+impl NodeImpl for ErrorNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -42,14 +43,9 @@ impl RunnableNode for ErrorNode {
         "Error".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input = inputs.values().next().unwrap();
-        let input: String = input.clone().into();
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input = input.single()?.text()?;
 
-        Err(anyhow::anyhow!(input))
+        Err(anyhow::anyhow!(input).into())
     }
 }
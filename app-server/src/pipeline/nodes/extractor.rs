@@ -1,17 +1,17 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
-use crate::pipeline::context::Context;
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
+use crate::pipeline::{context::Context, validation::GraphDiagnostic};
 use anyhow::Result;
 use async_trait::async_trait;
-use fancy_regex::Regex;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use super::utils::map_handles;
-use super::{Handle, NodeInput};
+use super::utils::{map_handles, CompiledRegex};
+use super::Handle;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ExtractorNode {
     pub id: Uuid,
@@ -20,10 +20,12 @@ pub struct ExtractorNode {
     pub outputs: Vec<Handle>,
     pub inputs_mappings: HashMap<Uuid, Uuid>,
     pub format: String,
+    #[serde(skip)]
+    pub compiled_format: CompiledRegex,
 }
 
 #[async_trait]
-impl RunnableNode for ExtractorNode {
+impl NodeImpl for ExtractorNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -44,22 +46,21 @@ impl RunnableNode for ExtractorNode {
         "Extractor".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input: String = inputs.values().next().unwrap().clone().try_into()?;
+    fn validate_config(&self) -> Vec<GraphDiagnostic> {
+        match self.compiled_format.get(&self.format) {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![GraphDiagnostic::of_config(
+                self.id,
+                &self.name,
+                e.to_string(),
+            )],
+        }
+    }
 
-        let re = match Regex::new(&self.format) {
-            Ok(re) => re,
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "Failed to compile regex: {}",
-                    e.to_string()
-                ));
-            }
-        };
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input = input.single()?.text()?;
+
+        let re = self.compiled_format.get(&self.format)?;
 
         let output = match re.captures(&input) {
             Ok(Some(captures)) => captures.get(1).map_or("", |m| m.as_str()),
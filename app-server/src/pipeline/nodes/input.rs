@@ -1,15 +1,17 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::{Handle, HandleType, NodeInput};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct InputNode {
     pub id: Uuid,
     pub name: String,
@@ -18,10 +20,18 @@ pub struct InputNode {
     pub input: Option<NodeInput>,
     #[serde(rename = "inputType")]
     pub input_type: HandleType,
+    /// JSON schema of the value of the input, which run inputs are checked against
+    #[serde(
+        default,
+        rename = "inputSchema",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<Object>)]
+    pub input_schema: Option<Value>,
 }
 
 #[async_trait]
-impl RunnableNode for InputNode {
+impl NodeImpl for InputNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         Vec::new()
     }
@@ -42,11 +52,7 @@ impl RunnableNode for InputNode {
         "Input".to_string()
     }
 
-    async fn run(
-        &self,
-        _inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
+    async fn run(&self, _input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
         Ok(RunOutput::Success((self.input.clone().unwrap(), None)))
     }
 }
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -8,12 +8,13 @@ use handlebars::Handlebars;
 use handlebars_misc_helpers::json_helpers::json_to_str_fct;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
-use super::{Handle, NodeInput};
+use super::Handle;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JsonExtractorNode {
     pub id: Uuid,
@@ -25,7 +26,7 @@ pub struct JsonExtractorNode {
 }
 
 #[async_trait]
-impl RunnableNode for JsonExtractorNode {
+impl NodeImpl for JsonExtractorNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -46,19 +47,21 @@ impl RunnableNode for JsonExtractorNode {
         "JsonExtractor".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input: String = inputs.values().next().unwrap().clone().try_into()?;
-
-        let input: HashMap<String, Value> = serde_json::from_str(&input)?;
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        // the parse of the message is shared with its other successors
+        match input.single()?.json_value()? {
+            input @ Value::Object(_) => self.render(input),
+            _ => Err(anyhow::anyhow!("Input is not a JSON object").into()),
+        }
+    }
+}
 
+impl JsonExtractorNode {
+    fn render<T: Serialize>(&self, input: &T) -> Result<RunOutput, NodeError> {
         let mut hb = Handlebars::new();
         hb.register_escape_fn(handlebars::no_escape);
         hb.register_helper("json", Box::new(json_to_str_fct));
-        let output = hb.render_template(&self.template, &input)?;
+        let output = hb.render_template(&self.template, input)?;
 
         Ok(RunOutput::Success((output.into(), None)))
     }
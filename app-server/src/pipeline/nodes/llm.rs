@@ -1,25 +1,33 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
+use crate::language_model::context_window::{ContextWindow, ContextWindowLog};
 use crate::language_model::providers::utils::get_provider;
-use crate::language_model::{ChatCompletion, ChatMessageContent, NodeInfo};
+use crate::language_model::{
+    ChatCompletion, ChatMessageContent, LanguageModelProviderName, NodeInfo,
+};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::prelude::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
 use crate::{
     language_model::ChatMessage,
-    pipeline::{context::Context, trace::MetaLog},
+    pipeline::{
+        context::Context, model_defaults::ResolvedModelConfig, prompts::ResolvedPrompt,
+        trace::MetaLog, validation::GraphDiagnostic,
+    },
 };
 
 use super::utils::map_handles;
 use super::HandleType;
-use super::{utils::render_template, Handle, NodeInput};
+use super::{utils::CompiledTemplate, Handle};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct LLMNode {
     pub id: Uuid,
@@ -33,6 +41,15 @@ pub struct LLMNode {
     pub model: Option<String>,
     #[serde(default)]
     pub model_params: Option<String>,
+    /// Alias of the credential which is the API key of the node's provider, rather than the one
+    /// of the run env
+    #[serde(default)]
+    pub credential: Option<String>,
+    /// Eviction of the oldest turns of chats which don't fit the context of the model, see
+    /// `language_model::context_window`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub context_window: Option<ContextWindow>,
     #[serde(default)]
     pub semantic_cache_enabled: bool,
     #[serde(default)]
@@ -46,9 +63,18 @@ pub struct LLMNode {
     pub stream: bool,
     #[serde(flatten)]
     pub structured_output_params: StructuredOutputParams,
+    #[serde(skip)]
+    pub compiled_prompt: CompiledTemplate,
+    /// Effective model and params of the node, set when its graph is compiled
+    #[serde(skip)]
+    pub resolved_config: Option<ResolvedModelConfig>,
+    /// Version of the registry prompt the node's prompt references, set when its graph is
+    /// compiled, see `pipeline::prompts`
+    #[serde(skip)]
+    pub prompt_version: Option<ResolvedPrompt>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StructuredOutputParams {
     #[serde(default)]
@@ -80,10 +106,26 @@ pub struct LLMNodeMetaLog {
     #[serde(default)]
     pub approximate_cost: Option<f64>,
     pub provider: String,
+    /// Model config the node resolved from the default layers and its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub model_config: Option<ResolvedModelConfig>,
+    /// Alias of the credential which served the call, unset for the API key of the run env
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub credential: Option<String>,
+    /// Strategy which fit the messages into the context of the model, if the node has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub context_window: Option<ContextWindowLog>,
+    /// Version of the registry prompt the node ran with, if its prompt references one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub prompt_version: Option<ResolvedPrompt>,
 }
 
 #[async_trait]
-impl RunnableNode for LLMNode {
+impl NodeImpl for LLMNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         let combined_inputs = self
             .inputs
@@ -111,16 +153,21 @@ impl RunnableNode for LLMNode {
         "LLM".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input_chat_messages = match inputs.get("chat_messages") {
-            Some(messages) => messages.clone().try_into()?,
-            None => vec![],
+    fn validate_config(&self) -> Vec<GraphDiagnostic> {
+        match self.compiled_prompt.compile(&self.prompt) {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![GraphDiagnostic::of_config(self.id, &self.name, e)],
+        }
+    }
+
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input_chat_messages = if input.has("chat_messages") {
+            input.get_chat("chat_messages")?
+        } else {
+            vec![]
         };
-        let rendered_prompt = render_template(&self.prompt, &inputs);
+        let inputs = input.values();
+        let rendered_prompt = self.compiled_prompt.render(&self.prompt, &inputs);
 
         let enable_structured_output = self.structured_output_params.structured_output_enabled
             && self
@@ -147,18 +194,17 @@ impl RunnableNode for LLMNode {
 
         messages.extend(input_chat_messages.clone().into_iter());
 
-        let params = serde_json::from_str::<HashMap<String, Value>>(
+        let model_params = context.resolve_references(
             self.model_params
                 .clone()
                 .unwrap_or("{}".to_string())
                 .as_str(),
-        )
-        .map_err(|e| anyhow::anyhow!("Failed to parse model params: {}", e))?;
+        )?;
+        let params = serde_json::from_str::<HashMap<String, Value>>(&model_params)
+            .map_err(|e| anyhow::anyhow!("Failed to parse model params: {}", e))?;
 
         let params = serde_json::to_value(params).unwrap();
 
-        let env_vars = &context.env;
-
         let tx = if context.tx.is_some() && (self.stream || context.run_type.do_local_stream()) {
             Some(context.tx.clone().unwrap())
         } else {
@@ -177,9 +223,35 @@ impl RunnableNode for LLMNode {
         let model = match (&self.model, inputs.get("model")) {
             (Some(model), _) => model.clone(),
             (_, Some(model)) => model.clone().into(),
-            _ => return Err(anyhow::anyhow!("Model not found in LLM node {}", self.id)),
+            _ => return Err(anyhow::anyhow!("Model not found in LLM node {}", self.id).into()),
         };
         let provider_name = get_provider(&model).unwrap_or_default();
+        let mut env_vars = context.resolved_env()?;
+        if let Some(alias) = &self.credential {
+            let credential = context.credentials.get(alias).ok_or_else(|| {
+                anyhow::anyhow!("Credential {} of LLM node {} is not set", alias, self.name)
+            })?;
+            let provider = LanguageModelProviderName::from_str(provider_name)?;
+            env_vars.insert(
+                provider.api_key_name(),
+                Zeroizing::new(credential.to_string()),
+            );
+        }
+        let mut context_window_log = None;
+        if let Some(context_window) = &self.context_window {
+            let (fitted, log) = context_window
+                .apply(
+                    messages,
+                    model.trim(),
+                    &params,
+                    &context.language_model,
+                    &env_vars,
+                    &node_info,
+                )
+                .await?;
+            messages = fitted;
+            context_window_log = log;
+        }
         loop {
             let completion = context
                 .language_model
@@ -208,6 +280,7 @@ impl RunnableNode for LLMNode {
                         enable_chat_message_output,
                         node_chunk_id,
                         provider_name,
+                        context_window_log,
                     ));
                 } else if retry_counter
                     >= self.structured_output_params.structured_output_max_retries
@@ -217,7 +290,8 @@ impl RunnableNode for LLMNode {
                         retry_counter,
                         response_message,
                         structured_output.as_ref().err().unwrap().to_string(),
-                    ));
+                    )
+                    .into());
                 }
 
                 retry_counter += 1;
@@ -244,6 +318,7 @@ impl RunnableNode for LLMNode {
                     enable_chat_message_output,
                     node_chunk_id,
                     provider_name,
+                    context_window_log,
                 ));
             }
         }
@@ -251,6 +326,7 @@ impl RunnableNode for LLMNode {
 }
 
 impl LLMNode {
+    #[allow(clippy::too_many_arguments)]
     fn build_ok_result(
         &self,
         completion: &ChatCompletion,
@@ -262,6 +338,7 @@ impl LLMNode {
         enable_chat_message_output: bool,
         node_chunk_id: Uuid,
         provider_name: &str,
+        context_window: Option<ContextWindowLog>,
     ) -> RunOutput {
         let usage = completion.usage();
         let input_message_count = messages
@@ -278,6 +355,10 @@ impl LLMNode {
             total_token_count: usage.total_tokens as i64,
             approximate_cost: usage.approximate_cost,
             provider: String::from(provider_name),
+            model_config: self.resolved_config.clone(),
+            credential: self.credential.clone(),
+            context_window,
+            prompt_version: self.prompt_version.clone(),
         };
 
         if enable_chat_message_output {
@@ -293,3 +374,98 @@ impl LLMNode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        engine::Engine,
+        language_model::{LanguageModelRunner, MockFailure, MockLlmProvider},
+        pipeline::{
+            builder::{GraphBuilder, NodeConfig},
+            compiled::CompiledGraph,
+            nodes::NodeInput,
+            RunType,
+        },
+        testing::OfflineServices,
+    };
+
+    use super::*;
+
+    /// Outputs of a run of the question through an LLM node with the config
+    async fn run_llm(mock: &MockLlmProvider, config: Value) -> Option<HashMap<String, NodeInput>> {
+        let llm = NodeConfig::new("LLM", config).input("question", HandleType::String);
+        let mut graph = GraphBuilder::new()
+            .node("question", NodeConfig::graph_input(HandleType::String))
+            .node("llm", llm)
+            .node("answer", NodeConfig::graph_output())
+            .edge("question", "llm.question")
+            .edge("llm", "answer")
+            .build()
+            .unwrap();
+        let inputs = HashMap::from([(
+            "question".to_string(),
+            NodeInput::String("What is 6 times 7?".to_string()),
+        )]);
+        graph
+            .setup(
+                &inputs,
+                &HashMap::new(),
+                &HashMap::new(),
+                &RunType::Workshop,
+            )
+            .unwrap();
+        let compiled = CompiledGraph::compile(&graph).unwrap();
+
+        let services =
+            OfflineServices::with_language_model(LanguageModelRunner::mocked(mock.clone()));
+        let mut context = services.context();
+        context.baml_schemas = compiled.baml_schemas();
+        let tasks = compiled.instantiate(&graph).unwrap();
+        let mut engine = Engine::with_tasks_and_context(tasks, context, None, None, None);
+        let outputs = engine.run(None, None, vec![]).await.ok()?;
+        Some(outputs.output_values())
+    }
+
+    #[tokio::test]
+    async fn test_structured_output_is_retried() {
+        let mock = MockLlmProvider::new()
+            .then_respond("I don't know")
+            .then_respond(r#"{"value": "42"}"#);
+        let config = json!({
+            "model": "openai:gpt-4o-mini",
+            "prompt": "{{question}}",
+            "dynamicInputs": [],
+            "structuredOutputEnabled": true,
+            "structuredOutputMaxRetries": 1,
+            "structuredOutputSchema": "class Answer {\n  value string\n}",
+        });
+
+        let outputs = run_llm(&mock, config).await.unwrap();
+        assert!(outputs.contains_key("answer"));
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        let feedback = calls[1].messages.last().unwrap();
+        assert!(matches!(
+            &feedback.content,
+            ChatMessageContent::Text(text) if text.contains("Json schema validation failed")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_provider_failure_fails_node() {
+        let mock = MockLlmProvider::new()
+            .then_fail(MockFailure::RateLimited)
+            .then_respond("never read");
+        let config = json!({
+            "model": "anthropic:claude-3-haiku",
+            "prompt": "{{question}}",
+            "dynamicInputs": [],
+        });
+
+        assert!(run_llm(&mock, config).await.is_none());
+        assert_eq!(mock.calls().len(), 1);
+        assert_eq!(mock.calls()[0].model, "claude-3-haiku");
+    }
+}
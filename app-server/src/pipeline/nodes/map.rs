@@ -6,10 +6,11 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::prelude::FromRow;
 use tokio::sync::Semaphore;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    engine::{engine::EngineOutput, RunOutput, RunnableNode},
+    engine::{engine::EngineOutput, Input, NodeError, NodeImpl, RunOutput},
     pipeline::{
         context::Context,
         runner::{PipelineRunner, PipelineRunnerError},
@@ -22,7 +23,7 @@ use super::{utils::map_handles, Handle, NodeInput};
 
 const BATCH_SIZE: usize = 50;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MapNode {
     pub id: Uuid,
@@ -38,6 +39,7 @@ pub struct MapNode {
     // Commit pipeline version id, must be immutable
     #[serde(default)]
     pub pipeline_version_id: Option<Uuid>,
+    #[schema(value_type = Object)]
     pub runnable_graph: Value,
 }
 
@@ -73,6 +75,11 @@ impl PipelineRunner {
             let permits = permits.clone();
 
             let mut graph = graph.clone();
+            graph.secrets = context.secrets.clone();
+            graph.credentials = context.credentials.clone();
+            graph.workspace_model_defaults = context.workspace_model_defaults.clone();
+            graph.workspace_id = context.workspace_id;
+            graph.prompts = context.prompts.clone();
             let env = context.env.clone();
             let metadata = context.metadata.clone();
             let run_type = context.run_type.clone();
@@ -116,7 +123,7 @@ impl PipelineRunner {
 }
 
 #[async_trait]
-impl RunnableNode for MapNode {
+impl NodeImpl for MapNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -140,24 +147,16 @@ impl RunnableNode for MapNode {
     }
 
     // TODO: Block infinite recursion (e.g. if depth is too high, return error)
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput> {
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
         if self.pipeline_version_id.is_none() {
-            return Err(anyhow::anyhow!("Pipeline version id is required"));
+            return Err(anyhow::anyhow!("Pipeline version id is required").into());
         }
 
         let graph = serde_json::from_value::<Graph>(self.runnable_graph.clone())?;
         let input_node_names = graph.get_input_node_names();
         let input_node_name = input_node_names.iter().next().unwrap();
 
-        let inp = inputs.get("inputs").unwrap().clone();
-        let input_list = match inp {
-            NodeInput::StringList(input_list) => input_list,
-            _ => return Err(anyhow::anyhow!("Input must be a list of strings")),
-        };
+        let input_list = input.get_json::<Vec<String>>("inputs")?;
 
         let mut outputs_list: Vec<String> = Vec::new();
         let mut total_token_count = 0;
@@ -1,14 +1,18 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::engine::{blocked::BlockedNode, NodeImpl};
+use crate::files::attachment::FileAttachment;
 use crate::language_model::ChatMessage;
 use crate::language_model::{ChatMessageContent, ChatMessageContentPart};
 
+use super::outputs::MissingOutputReason;
 use super::runner::PipelineRunnerError;
 use super::trace::{MetaLog, RunTrace};
 
@@ -16,13 +20,18 @@ mod condition;
 mod error;
 mod extractor;
 mod format_validator;
-mod input;
+pub mod input;
 mod json_extractor;
 pub mod llm;
 pub mod map;
 mod output;
+mod parsed_json;
+pub mod post_processors;
+pub mod registry;
+mod run_store;
+pub mod schema;
 mod semantic_search;
-mod semantic_search_utils;
+pub mod semantic_search_utils;
 mod semantic_similarity;
 mod semantic_switch;
 mod string_template;
@@ -31,8 +40,10 @@ mod switch;
 pub mod utils;
 pub mod zenguard;
 use anyhow::Error;
+pub use parsed_json::ParsedJson;
+use post_processors::PostProcessorEffect;
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, ToSchema)]
 #[serde(untagged)]
 pub enum NodeInput {
     Boolean(bool),
@@ -47,14 +58,32 @@ pub enum NodeInput {
     // Skip deserializing to prevent this behaviour.
     #[serde(skip_deserializing)]
     ConditionedValue(ConditionedValue),
+    // Files can only be uploaded as multipart run inputs, and are serialized as their metadata.
+    #[serde(skip_deserializing)]
+    File(FileAttachment),
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, ToSchema)]
 pub struct ConditionedValue {
     pub condition: String,
     pub value: Box<NodeInput>,
 }
 
+/// Longest route label recorded on spans, so that conditions can't blow up the cardinality of
+/// the route tags
+pub const MAX_ROUTE_LABEL_CHARS: usize = 64;
+
+impl ConditionedValue {
+    /// Label of the route the condition took, trimmed and truncated to `MAX_ROUTE_LABEL_CHARS`
+    pub fn route_label(&self) -> String {
+        self.condition
+            .trim()
+            .chars()
+            .take(MAX_ROUTE_LABEL_CHARS)
+            .collect()
+    }
+}
+
 impl Into<NodeInput> for String {
     fn into(self) -> NodeInput {
         NodeInput::String(self)
@@ -125,31 +154,7 @@ impl Into<String> for NodeInput {
                 .join("\n\n"),
             NodeInput::Float(f) => f.to_string(),
             NodeInput::ConditionedValue(conditioned_value) => (*conditioned_value.value).into(),
-        }
-    }
-}
-
-impl TryInto<Vec<ChatMessage>> for NodeInput {
-    type Error = Error;
-
-    fn try_into(self) -> Result<Vec<ChatMessage>, Self::Error> {
-        match self {
-            NodeInput::Boolean(_b) => Err(anyhow::anyhow!(
-                "Cannot convert GraphInput::Boolean to Vec<ChatMessage>"
-            )),
-            NodeInput::String(_s) => Err(anyhow::anyhow!(
-                "Cannot convert GraphInput::String to Vec<ChatMessage>"
-            )),
-            NodeInput::StringList(_strings) => Err(anyhow::anyhow!(
-                "Cannot convert GraphInput::StringList to Vec<ChatMessage>"
-            )),
-            NodeInput::ChatMessageList(messages) => Ok(messages),
-            NodeInput::Float(_f) => Err(anyhow::anyhow!(
-                "Cannot convert GraphInput::Float to Vec<ChatMessage>"
-            )),
-            NodeInput::ConditionedValue(_v) => Err(anyhow::anyhow!(
-                "Cannot convert GraphInput::ConditionedValue to Vec<ChatMessage>"
-            )),
+            NodeInput::File(file) => file.to_text(),
         }
     }
 }
@@ -175,6 +180,9 @@ impl TryInto<ConditionedValue> for NodeInput {
                 "Cannot convert GraphInput::Float to ConditionedValue"
             )),
             NodeInput::ConditionedValue(v) => Ok(v),
+            NodeInput::File(_file) => Err(anyhow::anyhow!(
+                "Cannot convert GraphInput::File to ConditionedValue"
+            )),
         }
     }
 }
@@ -184,8 +192,8 @@ impl TryInto<ConditionedValue> for NodeInput {
 #[serde(rename_all = "camelCase")]
 pub struct Message {
     pub id: Uuid,
-    /// output value of producing node in form of NodeInput for the following consumer
-    /// it's ok to clone because it's an enum of Arc
+    /// output value of producing node in form of NodeInput for the following consumer.
+    /// Cloning the message copies the value, messages are shared as `Arc<Message>` instead.
     pub value: NodeInput,
     /// all input messages to this node
     pub input_message_ids: Vec<Uuid>,
@@ -196,6 +204,12 @@ pub struct Message {
     pub node_type: String,
     /// all node per-run metadata that needs to be logged at the end of execution
     pub meta_log: Option<MetaLog>,
+    /// JSON of the value, parsed by the first successor reading it
+    #[serde(skip)]
+    pub parsed_json: ParsedJson,
+    /// Effects of the node's post-processors on the value, in the order they were applied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_processing: Vec<PostProcessorEffect>,
 }
 
 impl Message {
@@ -210,11 +224,41 @@ impl Message {
             node_name: String::new(),
             node_type: String::new(),
             meta_log: None,
+            parsed_json: ParsedJson::default(),
+            post_processing: Vec::new(),
+        }
+    }
+
+    /// The message with an empty value, e.g. to keep the metadata of a message once the value
+    /// isn't needed anymore
+    pub fn without_value(&self) -> Self {
+        Self {
+            id: self.id,
+            value: NodeInput::String(String::new()),
+            input_message_ids: self.input_message_ids.clone(),
+            start_time: self.start_time,
+            end_time: self.end_time,
+            node_id: self.node_id,
+            node_name: self.node_name.clone(),
+            node_type: self.node_type.clone(),
+            meta_log: self.meta_log.clone(),
+            parsed_json: ParsedJson::default(),
+            post_processing: self.post_processing.clone(),
         }
     }
+
+    /// JSON of the value, parsed once for all readers of the message
+    pub fn json(&self) -> Option<&Value> {
+        self.parsed_json.get(&self.value)
+    }
+
+    /// Value at the JSON pointer, without parsing the whole value if no reader has yet
+    pub fn json_pointer(&self, pointer: &str) -> Option<Value> {
+        self.parsed_json.pointer(&self.value, pointer)
+    }
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Serialize)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Serialize, ToSchema)]
 pub enum HandleType {
     String,
     StringList,
@@ -223,7 +267,7 @@ pub enum HandleType {
     Any,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, Clone, Serialize, ToSchema)]
 pub struct Handle {
     pub id: Uuid,
     pub name: Option<String>,
@@ -240,8 +284,8 @@ impl Handle {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(tag = "type")]
+/// Node of a graph, loaded by its `type` tag with the installed [`registry::NodeRegistry`]
+#[derive(Clone, Debug)]
 pub enum Node {
     Input(input::InputNode),
     Output(output::OutputNode),
@@ -259,6 +303,9 @@ pub enum Node {
     LLM(llm::LLMNode),
     Switch(switch::SwitchNode),
     SemanticSimilarity(semantic_similarity::SemanticSimilarityNode),
+    RunStore(run_store::RunStoreNode),
+    /// Node of a type registered by the deployment
+    Custom(registry::CustomNode),
 }
 
 impl Node {
@@ -282,21 +329,17 @@ impl Node {
             Self::Switch(node) => node.id,
             Self::JsonExtractor(node) => node.id,
             Self::SemanticSimilarity(node) => node.id,
+            Self::RunStore(node) => node.id,
+            Self::Custom(node) => node.implementation.node_id(),
         }
-        .clone()
     }
 
-    // uses #[serde(tag)] instead of mapping to get the node kind
+    /// The `type` tag of the node
     pub fn node_type(&self) -> String {
-        json!(&self)
-            .as_object()
-            .unwrap()
-            .get("type")
-            .unwrap()
-            .to_owned()
-            .as_str()
-            .unwrap()
-            .to_owned()
+        match self {
+            Self::Custom(node) => node.node_type.clone(),
+            node => node.implementation().node_type(),
+        }
     }
 
     // `enum_dispatch` would take care of this if this was a method, not field;
@@ -319,12 +362,90 @@ impl Node {
             Self::Switch(node) => node.name.as_str(),
             Self::JsonExtractor(node) => node.name.as_str(),
             Self::SemanticSimilarity(node) => node.name.as_str(),
+            Self::RunStore(node) => node.name.as_str(),
+            Self::Custom(node) => return node.implementation.node_name(),
         }
         .to_owned()
     }
+
+    /// Name of the node's output handle, `output` for nodes without one, like output nodes
+    pub fn output_handle_name(&self) -> String {
+        self.config()
+            .ok()
+            .and_then(|config| config["outputs"][0]["name"].as_str().map(String::from))
+            .unwrap_or_else(|| "output".to_string())
+    }
+
+    /// The node itself, to validate its config with. Configs compiled in validation are kept by
+    /// the node, so that tasks created from it reuse them.
+    pub fn implementation(&self) -> &(dyn NodeImpl + Send + Sync) {
+        match self {
+            Self::Input(node) => node,
+            Self::Output(node) => node,
+            Self::Error(node) => node,
+            Self::StringTemplate(node) => node,
+            Self::Subpipeline(node) => node,
+            Self::Map(node) => node,
+            Self::SemanticSearch(node) => node,
+            Self::SemanticSwitch(node) => node,
+            Self::Condition(node) => node,
+            Self::FormatValidator(node) => node,
+            Self::Extractor(node) => node,
+            Self::Zenguard(node) => node,
+            Self::LLM(node) => node,
+            Self::Switch(node) => node,
+            Self::JsonExtractor(node) => node,
+            Self::SemanticSimilarity(node) => node,
+            Self::RunStore(node) => node,
+            Self::Custom(node) => node.implementation.as_ref(),
+        }
+    }
+
+    fn config(&self) -> serde_json::Result<Value> {
+        match self {
+            Self::Input(node) => serde_json::to_value(node),
+            Self::Output(node) => serde_json::to_value(node),
+            Self::Error(node) => serde_json::to_value(node),
+            Self::StringTemplate(node) => serde_json::to_value(node),
+            Self::Subpipeline(node) => serde_json::to_value(node),
+            Self::Map(node) => serde_json::to_value(node),
+            Self::SemanticSearch(node) => serde_json::to_value(node),
+            Self::SemanticSwitch(node) => serde_json::to_value(node),
+            Self::Condition(node) => serde_json::to_value(node),
+            Self::FormatValidator(node) => serde_json::to_value(node),
+            Self::Extractor(node) => serde_json::to_value(node),
+            Self::Zenguard(node) => serde_json::to_value(node),
+            Self::LLM(node) => serde_json::to_value(node),
+            Self::Switch(node) => serde_json::to_value(node),
+            Self::JsonExtractor(node) => serde_json::to_value(node),
+            Self::SemanticSimilarity(node) => serde_json::to_value(node),
+            Self::RunStore(node) => serde_json::to_value(node),
+            Self::Custom(node) => Ok(node.config.clone()),
+        }
+    }
+}
+
+/// Nodes are serialized as their config with the `type` tag, as they are in graphs
+impl Serialize for Node {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut config = self.config().map_err(serde::ser::Error::custom)?;
+        if let Some(config) = config.as_object_mut() {
+            config.insert("type".to_string(), Value::String(self.node_type()));
+        }
+        config.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let config = Value::deserialize(deserializer)?;
+        registry::registry()
+            .load(config)
+            .map_err(serde::de::Error::custom)
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(tag = "type", content = "content")]
 pub enum StreamChunk {
     /// NodeChunk during streaming events during the execution
@@ -344,13 +465,13 @@ pub enum StreamChunk {
     Breakpoint(BreakpointChunk),
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct BreakpointChunk {
     pub node_id: Uuid,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NodeStreamChunk {
     pub id: Uuid,
@@ -360,13 +481,14 @@ pub struct NodeStreamChunk {
     pub content: NodeInput,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NodeStreamEnd {
+    #[schema(value_type = Object)]
     pub message: Message,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RunEndpointEventError {
     pub error: PipelineRunnerError,
@@ -374,20 +496,60 @@ pub struct RunEndpointEventError {
     ///
     /// Option because we don't record logs for some errors
     pub run_id: Option<Uuid>,
+    /// Nodes the run stopped at, with the breakdown of their inputs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocked_nodes: Vec<BlockedNode>,
+}
+
+impl RunEndpointEventError {
+    pub fn new(error: PipelineRunnerError, run_id: Option<Uuid>) -> Self {
+        let blocked_nodes = match &error {
+            PipelineRunnerError::RunningError(e) => e.partial_trace.blocked_nodes.clone(),
+            _ => Vec::new(),
+        };
+        Self {
+            error,
+            run_id,
+            blocked_nodes,
+        }
+    }
 }
 
 /// Lightweight representation of an Output node's result
 ///
 /// This is the truncated version of graph's Message without full trace.
-#[derive(Debug, Serialize, Clone, Deserialize)]
+#[derive(Debug, Serialize, Clone, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct GraphOutput {
-    pub value: NodeInput,
+    /// Null for a declared output whose node didn't produce a value, see `reason`
+    pub value: Option<NodeInput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<MissingOutputReason>,
+}
+
+impl GraphOutput {
+    pub fn new(value: NodeInput) -> Self {
+        Self {
+            value: Some(value),
+            reason: None,
+        }
+    }
+
+    pub fn missing(reason: MissingOutputReason) -> Self {
+        Self {
+            value: None,
+            reason: Some(reason),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Clone, Deserialize)]
+#[derive(Debug, Serialize, Clone, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct GraphRunOutput {
-    pub outputs: HashMap<String, GraphOutput>,
+    /// By the names of the graph's output bindings, or of its output nodes if it declares none
+    pub outputs: BTreeMap<String, GraphOutput>,
     pub run_id: Uuid,
+    /// Pipeline version which was executed
+    pub pipeline_version_id: Uuid,
+    pub pipeline_version_hash: Option<String>,
 }
@@ -1,11 +1,12 @@
 use std::{collections::HashMap, sync::Arc};
 
 use crate::db::event_templates::EventType;
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
-use anyhow::{Ok, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
@@ -29,7 +30,7 @@ pub fn cast(input: NodeInput, output_cast_type: &EventType) -> Result<NodeInput>
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OutputNode {
     pub id: Uuid,
@@ -41,7 +42,7 @@ pub struct OutputNode {
 }
 
 #[async_trait]
-impl RunnableNode for OutputNode {
+impl NodeImpl for OutputNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -63,12 +64,8 @@ impl RunnableNode for OutputNode {
         "Output".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input = inputs.values().next().unwrap();
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input = input.single_value()?;
 
         let output = match &self.output_cast_type {
             None => input.clone(),
@@ -0,0 +1,249 @@
+//! JSON of messages, parsed once per message
+//!
+//! A message is shared by all its successors, so the first node reading its JSON parses it, and
+//! the others reuse the parsed value. Lookups of a single value by JSON pointer don't need the
+//! whole value: if the message isn't parsed yet, they scan its text for the value, skipping
+//! everything else without allocating it.
+
+use std::{fmt, sync::OnceLock};
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::NodeInput;
+
+/// JSON value of a message, parsed on first access
+#[derive(Clone, Default)]
+pub struct ParsedJson(OnceLock<Option<Value>>);
+
+impl fmt::Debug for ParsedJson {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.get() {
+            Some(_) => write!(f, "ParsedJson(parsed)"),
+            None => write!(f, "ParsedJson(not parsed)"),
+        }
+    }
+}
+
+impl ParsedJson {
+    /// Strings are parsed as JSON documents, other values are converted to their JSON. `None` if
+    /// the string isn't JSON.
+    pub fn get(&self, value: &NodeInput) -> Option<&Value> {
+        self.0
+            .get_or_init(|| match value {
+                NodeInput::String(text) => serde_json::from_str(text).ok(),
+                value => serde_json::to_value(value).ok(),
+            })
+            .as_ref()
+    }
+
+    /// Value at the JSON pointer, e.g. `/choices/0/text`. Uses the parsed value if any node
+    /// parsed it already.
+    pub fn pointer(&self, value: &NodeInput, pointer: &str) -> Option<Value> {
+        match (self.0.get(), value) {
+            (None, NodeInput::String(text)) => {
+                let tokens = pointer_tokens(pointer)?;
+                let mut deserializer = serde_json::Deserializer::from_str(text);
+                PointerSeed { tokens: &tokens }
+                    .deserialize(&mut deserializer)
+                    .ok()
+                    .flatten()
+            }
+            _ => self.get(value)?.pointer(pointer).cloned(),
+        }
+    }
+}
+
+/// Unescaped reference tokens of the pointer, `None` if it isn't a valid JSON pointer
+fn pointer_tokens(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(Vec::new());
+    }
+    let tokens = pointer.strip_prefix('/')?;
+    Some(
+        tokens
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+    )
+}
+
+/// Deserializes the value at the remaining tokens, ignoring the rest of the document
+struct PointerSeed<'a> {
+    tokens: &'a [String],
+}
+
+impl<'de> DeserializeSeed<'de> for PointerSeed<'_> {
+    type Value = Option<Value>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        match self.tokens.split_first() {
+            None => Value::deserialize(deserializer).map(Some),
+            Some((token, rest)) => deserializer.deserialize_any(PointerVisitor { token, rest }),
+        }
+    }
+}
+
+struct PointerVisitor<'a> {
+    token: &'a str,
+    rest: &'a [String],
+}
+
+impl<'de> Visitor<'de> for PointerVisitor<'_> {
+    type Value = Option<Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a JSON value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut found = None;
+        // the parser requires the whole object to be read
+        while let Some(key) = map.next_key::<String>()? {
+            // the last of duplicate keys wins, as in parsed values
+            if key == self.token {
+                found = map.next_value_seed(PointerSeed { tokens: self.rest })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(found)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        // indices are plain decimals, as in `Value::pointer`
+        let index = match self.token.as_bytes() {
+            [b'+', ..] | [b'0', _, ..] => None,
+            _ => self.token.parse::<usize>().ok(),
+        };
+        let Some(index) = index else {
+            while seq.next_element::<IgnoredAny>()?.is_some() {}
+            return Ok(None);
+        };
+        for _ in 0..index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Ok(None);
+            }
+        }
+        let found = seq
+            .next_element_seed(PointerSeed { tokens: self.rest })?
+            .flatten();
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(found)
+    }
+
+    // scalars have nothing to point into
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::pipeline::nodes::Message;
+
+    fn message(value: NodeInput) -> Arc<Message> {
+        Arc::new(Message {
+            value,
+            ..Message::empty()
+        })
+    }
+
+    /// About 2 MB of search results, as an LLM or API node would output
+    fn large_document() -> String {
+        let results = (0..10_000)
+            .map(|i| {
+                json!({
+                    "id": i,
+                    "title": format!("Result {}", i),
+                    "snippet": "lorem ipsum dolor sit amet ".repeat(6),
+                    "tags": ["a", "b", "c"],
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({"results": results, "answer": {"text": "42", "score": 0.9}}).to_string()
+    }
+
+    #[test]
+    fn test_pointer_matches_parsed_value() {
+        let document = json!({
+            "a": {"b/c": [1, {"d": null}], "e~f": "g"},
+            "list": [true, 2.5],
+        });
+        let pointers = [
+            "",
+            "/a",
+            "/a/b~1c/1",
+            "/a/b~1c/1/d",
+            "/a/e~0f",
+            "/list/1",
+            "/list/2",
+            "/a/missing",
+            "/list/x",
+            "/list/01",
+            "/a/e~0f/0",
+            "no-slash",
+        ];
+        for pointer in pointers {
+            let value = NodeInput::String(document.to_string());
+            let scanned = ParsedJson::default().pointer(&value, pointer);
+            assert_eq!(scanned, document.pointer(pointer).cloned(), "{}", pointer);
+
+            let parsed = ParsedJson::default();
+            parsed.get(&value);
+            assert_eq!(parsed.pointer(&value, pointer), scanned, "{}", pointer);
+        }
+
+        let not_json = NodeInput::String("not json".to_string());
+        assert!(ParsedJson::default().get(&not_json).is_none());
+        assert!(ParsedJson::default().pointer(&not_json, "/a").is_none());
+        let list = NodeInput::StringList(vec!["x".to_string(), "y".to_string()]);
+        assert_eq!(ParsedJson::default().pointer(&list, "/1"), Some(json!("y")));
+    }
+
+    #[test]
+    fn test_successors_share_parse() {
+        let message = message(NodeInput::String(large_document()));
+        let readers = (0..4)
+            .map(|_| {
+                let message = message.clone();
+                std::thread::spawn(move || message.json().unwrap() as *const Value as usize)
+            })
+            .collect::<Vec<_>>();
+        let parsed = readers
+            .into_iter()
+            .map(|reader| reader.join().unwrap())
+            .collect::<Vec<_>>();
+        assert!(parsed.iter().all(|address| *address == parsed[0]));
+        assert_eq!(message.json_pointer("/answer/text"), Some(json!("42")));
+    }
+}
@@ -0,0 +1,436 @@
+//! Transformations of the output of a node, declared in its config
+//!
+//! Any node may list post-processors in its `postProcessors` config, which are applied in order
+//! to the value it outputs, before its message is passed to its successors:
+//!
+//! ```json
+//! "postProcessors": ["strip_code_fences", "trim", "parse_json", "jsonpath($.answer)"]
+//! ```
+//!
+//! A processor is referenced by its name, with its argument in parentheses if it takes one. The
+//! built-in processors are `strip_code_fences`, `trim`, `parse_json`, `lowercase`,
+//! `truncate_tokens(n)` and `jsonpath(expr)`, and deployments register their own in the
+//! [`NodeRegistry`](super::registry::NodeRegistry). Whether each processor changed the value is
+//! recorded on the node's message, and a processor which fails fails the node.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::{registry::NodeRegistry, schema::ConfigError, NodeInput};
+use crate::language_model::context_window::TokenCounter;
+
+/// Field of node configs which lists their post-processors
+pub const POST_PROCESSORS_FIELD: &str = "postProcessors";
+
+pub trait PostProcessor: Send + Sync {
+    /// Processed value, fails if the processor can't process the value
+    fn process(&self, value: &NodeInput) -> Result<NodeInput>;
+}
+
+/// Builds a processor from the argument it's referenced with, fails if the argument is invalid
+pub type PostProcessorFactory =
+    Arc<dyn Fn(Option<&str>) -> Result<Arc<dyn PostProcessor>> + Send + Sync>;
+
+/// Effect of a post-processor on the output of a node run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PostProcessorEffect {
+    /// The processor as referenced in the node's config
+    pub processor: String,
+    pub changed: bool,
+}
+
+/// Post-processors of a node, in the order they're applied
+#[derive(Clone, Default)]
+pub struct PostProcessors {
+    processors: Vec<(String, Arc<dyn PostProcessor>)>,
+}
+
+impl PostProcessors {
+    /// Processors of the references, or the problems of the invalid ones, pointing into the
+    /// node's config
+    pub fn compile(
+        registry: &NodeRegistry,
+        references: &[String],
+    ) -> Result<Self, Vec<ConfigError>> {
+        let mut processors = Vec::new();
+        let mut errors = Vec::new();
+        for (i, reference) in references.iter().enumerate() {
+            match registry.post_processor(reference) {
+                Ok(processor) => processors.push((reference.clone(), processor)),
+                Err(e) => errors.push(ConfigError {
+                    pointer: format!("/{}/{}", POST_PROCESSORS_FIELD, i),
+                    message: e.to_string(),
+                }),
+            }
+        }
+        if errors.is_empty() {
+            Ok(Self { processors })
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Value processed by each processor in turn, with their effects
+    pub fn apply(&self, value: NodeInput) -> Result<(NodeInput, Vec<PostProcessorEffect>)> {
+        let mut value = value;
+        let mut effects = Vec::with_capacity(self.processors.len());
+        for (reference, processor) in self.processors.iter() {
+            let processed = processor
+                .process(&value)
+                .map_err(|e| anyhow::anyhow!("Post-processor {} failed: {}", reference, e))?;
+            effects.push(PostProcessorEffect {
+                processor: reference.clone(),
+                changed: processed != value,
+            });
+            value = processed;
+        }
+        Ok((value, effects))
+    }
+}
+
+/// References to the post-processors the node config declares, empty if it declares none
+pub fn declared(config: &Value) -> serde_json::Result<Vec<String>> {
+    match config.get(POST_PROCESSORS_FIELD) {
+        Some(references) => serde_json::from_value(references.clone()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Name and argument of a reference to a processor, e.g. `truncate_tokens(100)`
+pub fn parse_reference(reference: &str) -> Result<(&str, Option<&str>)> {
+    let reference = reference.trim();
+    let Some((name, argument)) = reference.split_once('(') else {
+        return Ok((reference, None));
+    };
+    let Some(argument) = argument.strip_suffix(')') else {
+        return Err(anyhow::anyhow!(
+            "Post-processor {} is missing the closing parenthesis of its argument",
+            reference
+        ));
+    };
+    Ok((name.trim(), Some(argument.trim())))
+}
+
+/// Factories of the built-in processors, by name
+pub fn builtins() -> Vec<(&'static str, PostProcessorFactory)> {
+    vec![
+        (
+            "strip_code_fences",
+            text_processor("strip_code_fences", strip_code_fences),
+        ),
+        (
+            "trim",
+            text_processor("trim", |text| text.trim().to_string()),
+        ),
+        (
+            "lowercase",
+            text_processor("lowercase", |text| text.to_lowercase()),
+        ),
+        ("parse_json", factory(parse_json)),
+        ("truncate_tokens", factory(truncate_tokens)),
+        ("jsonpath", factory(jsonpath)),
+    ]
+}
+
+pub fn factory<F>(factory: F) -> PostProcessorFactory
+where
+    F: Fn(Option<&str>) -> Result<Arc<dyn PostProcessor>> + Send + Sync + 'static,
+{
+    Arc::new(factory)
+}
+
+/// Processor of the text of string and string list outputs, which takes no argument
+struct TextProcessor(fn(&str) -> String);
+
+impl PostProcessor for TextProcessor {
+    fn process(&self, value: &NodeInput) -> Result<NodeInput> {
+        match value {
+            NodeInput::String(text) => Ok(NodeInput::String(self.0(text))),
+            NodeInput::StringList(texts) => Ok(NodeInput::StringList(
+                texts.iter().map(|text| self.0(text)).collect(),
+            )),
+            _ => Err(anyhow::anyhow!("Output is not a string or string list")),
+        }
+    }
+}
+
+fn text_processor(name: &'static str, process: fn(&str) -> String) -> PostProcessorFactory {
+    factory(
+        move |argument: Option<&str>| -> Result<Arc<dyn PostProcessor>> {
+            no_argument(name, argument)?;
+            Ok(Arc::new(TextProcessor(process)))
+        },
+    )
+}
+
+fn no_argument(name: &str, argument: Option<&str>) -> Result<()> {
+    match argument {
+        Some(_) => Err(anyhow::anyhow!("Post-processor {} takes no argument", name)),
+        None => Ok(()),
+    }
+}
+
+/// Content of the first fenced code block of the text, the text itself if it has none
+fn strip_code_fences(text: &str) -> String {
+    let Some((_, rest)) = text.split_once("```") else {
+        return text.to_string();
+    };
+    let Some((block, _)) = rest.split_once("```") else {
+        return text.to_string();
+    };
+    // the opening fence may have an info string, e.g. ```json
+    let content = match block.split_once('\n') {
+        Some((_, content)) => content,
+        None => block,
+    };
+    content.trim_end_matches(['\n', '\r']).to_string()
+}
+
+/// Output of a JSON value, strings, booleans, numbers and lists of strings as such, and other
+/// values as compact JSON
+fn json_output(value: Value) -> NodeInput {
+    match value {
+        Value::String(s) => NodeInput::String(s),
+        Value::Bool(b) => NodeInput::Boolean(b),
+        Value::Number(n) => match n.as_f64() {
+            Some(n) => NodeInput::Float(n),
+            None => NodeInput::String(n.to_string()),
+        },
+        Value::Array(values) if values.iter().all(Value::is_string) => NodeInput::StringList(
+            values
+                .into_iter()
+                .filter_map(|value| match value {
+                    Value::String(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        value => NodeInput::String(value.to_string()),
+    }
+}
+
+fn parse_json_text(value: &NodeInput) -> Result<Value> {
+    let NodeInput::String(text) = value else {
+        return Err(anyhow::anyhow!("Output is not a string"));
+    };
+    serde_json::from_str::<Value>(text).map_err(|e| anyhow::anyhow!("Output is not JSON: {}", e))
+}
+
+struct ParseJson;
+
+impl PostProcessor for ParseJson {
+    fn process(&self, value: &NodeInput) -> Result<NodeInput> {
+        Ok(json_output(parse_json_text(value)?))
+    }
+}
+
+fn parse_json(argument: Option<&str>) -> Result<Arc<dyn PostProcessor>> {
+    no_argument("parse_json", argument)?;
+    Ok(Arc::new(ParseJson))
+}
+
+struct TruncateTokens {
+    max_tokens: usize,
+    counter: TokenCounter,
+}
+
+impl PostProcessor for TruncateTokens {
+    fn process(&self, value: &NodeInput) -> Result<NodeInput> {
+        match value {
+            NodeInput::String(text) => Ok(NodeInput::String(
+                self.counter.truncate_text(text, self.max_tokens),
+            )),
+            _ => Err(anyhow::anyhow!("Output is not a string")),
+        }
+    }
+}
+
+fn truncate_tokens(argument: Option<&str>) -> Result<Arc<dyn PostProcessor>> {
+    let max_tokens = argument
+        .and_then(|argument| argument.parse::<usize>().ok())
+        .filter(|max_tokens| *max_tokens > 0)
+        .ok_or_else(|| {
+            anyhow::anyhow!("Post-processor truncate_tokens takes a positive number of tokens")
+        })?;
+    Ok(Arc::new(TruncateTokens {
+        max_tokens,
+        // tokens are counted like the ones of LLM nodes whose model tiktoken doesn't know
+        counter: TokenCounter::for_model("")?,
+    }))
+}
+
+/// Value at a path of the output's JSON, e.g. `$.choices[0].text`
+struct JsonPath {
+    path: String,
+    pointer: String,
+}
+
+impl PostProcessor for JsonPath {
+    fn process(&self, value: &NodeInput) -> Result<NodeInput> {
+        let json = parse_json_text(value)?;
+        match json.pointer(&self.pointer) {
+            Some(value) => Ok(json_output(value.clone())),
+            None => Err(anyhow::anyhow!("Output has no value at {}", self.path)),
+        }
+    }
+}
+
+fn jsonpath(argument: Option<&str>) -> Result<Arc<dyn PostProcessor>> {
+    let path = argument.ok_or_else(|| {
+        anyhow::anyhow!("Post-processor jsonpath takes a path, e.g. jsonpath($.answer)")
+    })?;
+    Ok(Arc::new(JsonPath {
+        path: path.to_string(),
+        pointer: json_pointer(path)?,
+    }))
+}
+
+/// JSON pointer of a path of keys and indexes, e.g. `$.a['b c'][0]`
+fn json_pointer(path: &str) -> Result<String> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid path {}, expected keys and indexes from $, e.g. $.a['b'][0]",
+            path
+        )
+    };
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut pointer = String::new();
+    while !rest.is_empty() {
+        let segment = if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let (key, after) = after.split_at(end);
+            rest = after;
+            key
+        } else if let Some(after) = rest.strip_prefix("['") {
+            let (key, after) = after.split_once("']").ok_or_else(invalid)?;
+            rest = after;
+            key
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let (index, after) = after.split_once(']').ok_or_else(invalid)?;
+            index.parse::<usize>().map_err(|_| invalid())?;
+            rest = after;
+            index
+        } else {
+            return Err(invalid());
+        };
+        if segment.is_empty() {
+            return Err(invalid());
+        }
+        pointer.push('/');
+        pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+    }
+    Ok(pointer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(references: &[&str], value: &str) -> Result<NodeInput> {
+        let references = references
+            .iter()
+            .map(|reference| reference.to_string())
+            .collect::<Vec<_>>();
+        let processors = PostProcessors::compile(&NodeRegistry::with_builtins(), &references)
+            .map_err(|errors| anyhow::anyhow!("{:?}", errors))?;
+        processors
+            .apply(NodeInput::String(value.to_string()))
+            .map(|(value, _)| value)
+    }
+
+    #[test]
+    fn test_apply_builtins() {
+        let output = "Sure:\n```json\n{\"answer\": \"Paris\", \"tags\": [\"a\", \"b\"]}\n```\n";
+        assert_eq!(
+            apply(&["strip_code_fences", "jsonpath($.answer)"], output).unwrap(),
+            NodeInput::String("Paris".to_string())
+        );
+        assert_eq!(
+            apply(&["strip_code_fences", "jsonpath($['tags'])"], output).unwrap(),
+            NodeInput::StringList(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            apply(&["trim", "lowercase"], "  YES \n").unwrap(),
+            NodeInput::String("yes".to_string())
+        );
+        assert_eq!(
+            apply(&["parse_json"], "{ \"a\": [1, 2] }").unwrap(),
+            NodeInput::String("{\"a\":[1,2]}".to_string())
+        );
+        assert_eq!(
+            apply(&["truncate_tokens(1)"], "hello world").unwrap(),
+            NodeInput::String("hello".to_string())
+        );
+
+        let e = apply(&["trim", "parse_json"], "not json").unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("Post-processor parse_json failed"));
+    }
+
+    #[test]
+    fn test_effects() {
+        let references = vec!["trim".to_string(), "lowercase".to_string()];
+        let processors =
+            PostProcessors::compile(&NodeRegistry::with_builtins(), &references).unwrap();
+        let (_, effects) = processors
+            .apply(NodeInput::String("yes ".to_string()))
+            .unwrap();
+        assert_eq!(
+            effects,
+            vec![
+                PostProcessorEffect {
+                    processor: "trim".to_string(),
+                    changed: true,
+                },
+                PostProcessorEffect {
+                    processor: "lowercase".to_string(),
+                    changed: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_reports_invalid_references() {
+        let references = [
+            "trim",
+            "trimm",
+            "truncate_tokens(0)",
+            "jsonpath(a.b)",
+            "trim(1",
+        ]
+        .iter()
+        .map(|reference| reference.to_string())
+        .collect::<Vec<_>>();
+        let errors = PostProcessors::compile(&NodeRegistry::with_builtins(), &references)
+            .err()
+            .unwrap();
+        let pointers = errors
+            .iter()
+            .map(|error| error.pointer.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pointers,
+            vec![
+                "/postProcessors/1",
+                "/postProcessors/2",
+                "/postProcessors/3",
+                "/postProcessors/4"
+            ]
+        );
+        assert_eq!(
+            errors[0].message,
+            "Unknown post-processor trimm, did you mean trim?"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Node types graphs are loaded with
+//!
+//! Nodes of a graph are loaded by their `type` tag from the registry installed at startup, which
+//! has the built-in node types by default. Custom node types implement [`NodeImpl`] and are
+//! loaded from their graph JSON with serde, like built-in nodes:
+//!
+//! ```ignore
+//! let mut registry = NodeRegistry::with_builtins();
+//! registry.register::<RankerNode>("Ranker")?;
+//! registry::install(registry);
+//! ```
+//!
+//! Custom nodes run the same way as built-in ones, and graph validation reports the problems of
+//! their configs from [`NodeImpl::validate_config`]. Each node type has the JSON schema of its
+//! configs, derived with `ToSchema`, which configs in graph JSON are validated against.
+//!
+//! The registry also has the post-processors node outputs are transformed with, see
+//! [`post_processors`](super::post_processors), which deployments extend the same way:
+//!
+//! ```ignore
+//! registry.register_post_processor("redact_emails", |_| Ok(Arc::new(RedactEmails)))?;
+//! ```
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use serde::de::{DeserializeOwned, Error as _};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::{
+    post_processors::{self, PostProcessor, PostProcessorFactory},
+    schema::{self, ConfigError},
+    Node,
+};
+use crate::engine::{task::Action, NodeImpl};
+
+type Loader = Arc<dyn Fn(Value) -> Result<Node, serde_json::Error> + Send + Sync>;
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: ArcSwap<NodeRegistry> =
+        ArcSwap::from_pointee(NodeRegistry::with_builtins());
+}
+
+/// Install the registry graphs are loaded with, before any graph is loaded
+pub fn install(registry: NodeRegistry) {
+    REGISTRY.store(Arc::new(registry));
+}
+
+/// The installed registry
+pub fn registry() -> Arc<NodeRegistry> {
+    REGISTRY.load_full()
+}
+
+/// Loaders of nodes by node type
+#[derive(Clone)]
+pub struct NodeRegistry {
+    loaders: HashMap<String, Loader>,
+    /// JSON schemas of the configs of the node types, see `schema::node_schema`
+    schemas: HashMap<String, Value>,
+    post_processors: HashMap<String, PostProcessorFactory>,
+}
+
+impl NodeRegistry {
+    pub fn with_builtins() -> Self {
+        let mut registry = Self {
+            loaders: HashMap::new(),
+            schemas: HashMap::new(),
+            post_processors: post_processors::builtins()
+                .into_iter()
+                .map(|(name, factory)| (name.to_string(), factory))
+                .collect(),
+        };
+        registry.add_builtin("Input", Node::Input);
+        registry.add_builtin("Output", Node::Output);
+        registry.add_builtin("Error", Node::Error);
+        registry.add_builtin("StringTemplate", Node::StringTemplate);
+        registry.add_builtin("Subpipeline", Node::Subpipeline);
+        registry.add_builtin("Map", Node::Map);
+        registry.add_builtin("SemanticSearch", Node::SemanticSearch);
+        registry.add_builtin("SemanticSwitch", Node::SemanticSwitch);
+        registry.add_builtin("Condition", Node::Condition);
+        registry.add_builtin("FormatValidator", Node::FormatValidator);
+        registry.add_builtin("Extractor", Node::Extractor);
+        registry.add_builtin("JsonExtractor", Node::JsonExtractor);
+        registry.add_builtin("Zenguard", Node::Zenguard);
+        registry.add_builtin("LLM", Node::LLM);
+        registry.add_builtin("Switch", Node::Switch);
+        registry.add_builtin("SemanticSimilarity", Node::SemanticSimilarity);
+        registry.add_builtin("RunStore", Node::RunStore);
+        registry
+    }
+
+    fn add_builtin<T>(&mut self, node_type: &str, node: fn(T) -> Node)
+    where
+        T: DeserializeOwned + for<'s> ToSchema<'s> + 'static,
+    {
+        self.schemas.insert(
+            node_type.to_string(),
+            schema::node_schema(node_type, T::schema().1),
+        );
+        self.loaders.insert(
+            node_type.to_string(),
+            Arc::new(move |config: Value| serde_json::from_value(config).map(node)),
+        );
+    }
+
+    /// Register a custom node type, whose nodes are loaded as `T` from the graph JSON
+    pub fn register<T>(&mut self, node_type: &str) -> Result<()>
+    where
+        T: NodeImpl + DeserializeOwned + for<'s> ToSchema<'s> + Send + Sync + 'static,
+    {
+        if self.contains(node_type) {
+            return Err(anyhow::anyhow!(
+                "Node type {} is already registered",
+                node_type
+            ));
+        }
+        self.schemas.insert(
+            node_type.to_string(),
+            schema::node_schema(node_type, T::schema().1),
+        );
+        let name = node_type.to_string();
+        self.loaders.insert(
+            node_type.to_string(),
+            Arc::new(move |config: Value| -> Result<Node, serde_json::Error> {
+                let implementation = serde_json::from_value::<T>(config.clone())?;
+                Ok(Node::Custom(CustomNode {
+                    node_type: name.clone(),
+                    config,
+                    implementation: Arc::new(implementation),
+                }))
+            }),
+        );
+        Ok(())
+    }
+
+    /// Register a custom post-processor, built by the factory from the argument it's referenced
+    /// with in node configs
+    pub fn register_post_processor<F>(&mut self, name: &str, factory: F) -> Result<()>
+    where
+        F: Fn(Option<&str>) -> Result<Arc<dyn PostProcessor>> + Send + Sync + 'static,
+    {
+        if self.post_processors.contains_key(name) {
+            return Err(anyhow::anyhow!(
+                "Post-processor {} is already registered",
+                name
+            ));
+        }
+        self.post_processors
+            .insert(name.to_string(), post_processors::factory(factory));
+        Ok(())
+    }
+
+    /// Post-processor of a reference in a node config, e.g. `truncate_tokens(100)`
+    pub fn post_processor(&self, reference: &str) -> Result<Arc<dyn PostProcessor>> {
+        let (name, argument) = post_processors::parse_reference(reference)?;
+        match self.post_processors.get(name) {
+            Some(factory) => factory(argument),
+            None => {
+                let mut names = self
+                    .post_processors
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                names.sort();
+                Err(match schema::closest(name, &names) {
+                    Some(closest) => anyhow::anyhow!(
+                        "Unknown post-processor {}, did you mean {}?",
+                        name,
+                        closest
+                    ),
+                    None => anyhow::anyhow!("Unknown post-processor {}", name),
+                })
+            }
+        }
+    }
+
+    pub fn contains(&self, node_type: &str) -> bool {
+        self.loaders.contains_key(node_type)
+    }
+
+    /// Registered node types, sorted
+    pub fn node_types(&self) -> Vec<&str> {
+        let mut node_types = self.loaders.keys().map(String::as_str).collect::<Vec<_>>();
+        node_types.sort();
+        node_types
+    }
+
+    /// JSON schema of the configs of the node type
+    pub fn schema(&self, node_type: &str) -> Option<&Value> {
+        self.schemas.get(node_type)
+    }
+
+    /// Problems of the node's graph JSON against the schema of its node type
+    pub fn validate_config(&self, config: &Value) -> Vec<ConfigError> {
+        let Some(node_type) = config.get("type").and_then(Value::as_str) else {
+            return vec![ConfigError {
+                pointer: "/type".to_string(),
+                message: "missing required field".to_string(),
+            }];
+        };
+        match self.schemas.get(node_type) {
+            Some(schema) => schema::validate(schema, config),
+            None => vec![ConfigError {
+                pointer: "/type".to_string(),
+                message: self.unknown_node_type(node_type),
+            }],
+        }
+    }
+
+    /// Load a node from its graph JSON by its `type` tag
+    pub fn load(&self, config: Value) -> Result<Node, serde_json::Error> {
+        let node_type = config
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or_else(|| serde_json::Error::missing_field("type"))?;
+        let loader = self
+            .loaders
+            .get(node_type)
+            .ok_or_else(|| serde_json::Error::custom(self.unknown_node_type(node_type)))?;
+        loader(config.clone()).map_err(|e| {
+            // serde's errors don't tell where in the config the invalid field is
+            let errors = self.validate_config(&config);
+            if errors.is_empty() {
+                return e;
+            }
+            let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+            serde_json::Error::custom(format!(
+                "Invalid config of {} node {}: {}",
+                node_type,
+                config
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                errors.join("; ")
+            ))
+        })
+    }
+
+    fn unknown_node_type(&self, node_type: &str) -> String {
+        match schema::closest(node_type, &self.node_types()) {
+            Some(closest) => format!("Unknown node type {}, did you mean {}?", node_type, closest),
+            None => format!("Unknown node type {}", node_type),
+        }
+    }
+}
+
+/// Node of a custom type, with the graph JSON it was loaded from, which it is serialized as
+#[derive(Clone)]
+pub struct CustomNode {
+    pub node_type: String,
+    pub config: Value,
+    pub implementation: Action,
+}
+
+impl fmt::Debug for CustomNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomNode")
+            .field("node_type", &self.node_type)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,155 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
+use crate::pipeline::context::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::utils::map_handles;
+use super::{Handle, NodeInput};
+
+/// What a [`RunStoreNode`] does with its key
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RunStoreOperation {
+    /// Output the value of the key, null if it's unset. The input only triggers the read.
+    Get,
+    /// Set the key to the input, and output it
+    Set,
+    /// Append the input to the array of the key, and output the array
+    Append,
+}
+
+/// Reads or writes a key of the run's store, see `pipeline::run_store`, e.g. to collect the
+/// results of the iterations of a loop. Inputs are stored as their JSON if they're JSON text.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunStoreNode {
+    pub id: Uuid,
+    pub name: String,
+    pub inputs: Vec<Handle>,
+    pub outputs: Vec<Handle>,
+    pub inputs_mappings: HashMap<Uuid, Uuid>,
+    pub key: String,
+    pub operation: RunStoreOperation,
+}
+
+#[async_trait]
+impl NodeImpl for RunStoreNode {
+    fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+        map_handles(&self.inputs, &self.inputs_mappings)
+    }
+
+    fn output_handle_id(&self) -> Uuid {
+        self.outputs.first().unwrap().id
+    }
+
+    fn node_name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn node_id(&self) -> Uuid {
+        self.id
+    }
+
+    fn node_type(&self) -> String {
+        "RunStore".to_string()
+    }
+
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let message = input.single_message()?;
+        let store = &context.run_store;
+        let value = match self.operation {
+            RunStoreOperation::Get => store.get(&self.key).unwrap_or(Value::Null),
+            RunStoreOperation::Set => {
+                let value = stored_value(message.json(), &message.value);
+                store.set(&self.key, value.clone())?;
+                value
+            }
+            RunStoreOperation::Append => {
+                let value = stored_value(message.json(), &message.value);
+                store.append_to_array(&self.key, value)?;
+                store.get(&self.key).unwrap_or(Value::Null)
+            }
+        };
+
+        let output = match value {
+            Value::String(text) => text,
+            value => value.to_string(),
+        };
+        Ok(RunOutput::Success((output.into(), None)))
+    }
+}
+
+fn stored_value(json: Option<&Value>, value: &NodeInput) -> Value {
+    json.cloned().unwrap_or_else(|| value.clone().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        pipeline::run_store::RunStore,
+        testing::{run_node_with_context, InputBuilder, OfflineServices},
+    };
+
+    use super::*;
+
+    fn node(operation: RunStoreOperation) -> RunStoreNode {
+        serde_json::from_value(json!({
+            "id": Uuid::new_v4(),
+            "name": "answers",
+            "inputs": [{"id": Uuid::new_v4(), "name": "input", "type": "Any"}],
+            "outputs": [{"id": Uuid::new_v4(), "name": "output", "type": "String"}],
+            "inputsMappings": {},
+            "key": "answers",
+            "operation": operation,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_nodes_share_key() {
+        let services = OfflineServices::default();
+        let store = Arc::new(RunStore::default());
+        let run = |operation, input| {
+            let context = Context {
+                run_store: store.clone(),
+                ..services.context()
+            };
+            async move {
+                let state = run_node_with_context(&node(operation), input, context)
+                    .await
+                    .unwrap();
+                state.get_out().value.clone()
+            }
+        };
+
+        let input = || InputBuilder::new().json("input", json!({"answer": 42}));
+        let output = run(RunStoreOperation::Get, input().build()).await;
+        assert_eq!(output, NodeInput::String("null".to_string()));
+        run(RunStoreOperation::Append, input().build()).await;
+        let output = run(
+            RunStoreOperation::Append,
+            InputBuilder::new()
+                .value("input", "why".to_string())
+                .build(),
+        )
+        .await;
+        assert_eq!(
+            output,
+            NodeInput::String(r#"[{"answer":42},"why"]"#.to_string())
+        );
+        assert_eq!(store.get("answers"), Some(json!([{"answer": 42}, "why"])));
+
+        let output = run(RunStoreOperation::Set, input().build()).await;
+        assert_eq!(output, NodeInput::String(r#"{"answer":42}"#.to_string()));
+        let output = run(RunStoreOperation::Get, input().build()).await;
+        assert_eq!(output, NodeInput::String(r#"{"answer":42}"#.to_string()));
+    }
+}
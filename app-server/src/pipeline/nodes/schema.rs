@@ -0,0 +1,410 @@
+//! JSON schemas of node configs, and validation of graph JSON configs against them
+//!
+//! Schemas are derived from the config structs with `ToSchema`, the same way as the schemas of
+//! the API, with the types configs share inlined. A node's config is closed: fields which the
+//! node type doesn't have are reported instead of being ignored, so that a typo doesn't silently
+//! fall back to the default.
+
+use std::{collections::HashMap, fmt};
+
+use serde_json::{Map, Value};
+use utoipa::{
+    openapi::{RefOr, Schema},
+    ToSchema,
+};
+use uuid::Uuid;
+
+use super::{
+    llm::StructuredOutputParams, post_processors::POST_PROCESSORS_FIELD, Handle, HandleType,
+};
+use crate::{datasets::Dataset, db::event_templates::EventType};
+
+const REF_PREFIX: &str = "#/components/schemas/";
+/// Refs nested deeper are left as is, and accept any value
+const MAX_REF_DEPTH: usize = 8;
+
+/// Invalid field of a node config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// JSON pointer to the field in the config, empty for the config itself
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pointer.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.pointer, self.message)
+        }
+    }
+}
+
+/// Schemas of the types configs of several node types have, by the name they are referenced by
+fn components() -> HashMap<&'static str, Value> {
+    [
+        Handle::schema(),
+        HandleType::schema(),
+        StructuredOutputParams::schema(),
+        EventType::schema(),
+        Dataset::schema(),
+    ]
+    .into_iter()
+    .map(|(name, schema)| {
+        (
+            name,
+            serde_json::to_value(schema).unwrap_or(Value::Bool(true)),
+        )
+    })
+    .collect()
+}
+
+/// Closed schema of the configs of the node type, with its `type` tag
+pub fn node_schema(node_type: &str, schema: RefOr<Schema>) -> Value {
+    let components = components();
+    let schema = serde_json::to_value(schema).unwrap_or(Value::Bool(true));
+    let mut schema = resolve_refs(schema, &components, 0);
+    let Value::Object(object) = &mut schema else {
+        return schema;
+    };
+    merge_all_of(object);
+
+    object.insert("type".to_string(), Value::from("object"));
+    if let Value::Object(properties) = object
+        .entry("properties")
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        properties.insert(
+            "type".to_string(),
+            serde_json::json!({"type": "string", "enum": [node_type]}),
+        );
+        // post-processors are declared by any node, see `post_processors`
+        properties.insert(
+            POST_PROCESSORS_FIELD.to_string(),
+            serde_json::json!({"type": "array", "items": {"type": "string"}}),
+        );
+    }
+    if let Value::Array(required) = object
+        .entry("required")
+        .or_insert_with(|| Value::Array(Vec::new()))
+    {
+        required.insert(0, Value::from("type"));
+    }
+    // fields of unresolved flattened types are unknown
+    if !object.contains_key("allOf") {
+        object.insert("additionalProperties".to_string(), Value::Bool(false));
+    }
+    schema
+}
+
+fn resolve_refs(schema: Value, components: &HashMap<&str, Value>, depth: usize) -> Value {
+    match schema {
+        Value::Object(object) => {
+            let component = object
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix(REF_PREFIX))
+                .and_then(|name| components.get(name));
+            match component {
+                Some(component) if depth < MAX_REF_DEPTH => {
+                    resolve_refs(component.clone(), components, depth + 1)
+                }
+                _ => Value::Object(
+                    object
+                        .into_iter()
+                        .map(|(key, value)| (key, resolve_refs(value, components, depth)))
+                        .collect(),
+                ),
+            }
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| resolve_refs(value, components, depth))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Merge the object schemas `allOf` is made of, e.g. by flattened fields, into the schema
+fn merge_all_of(object: &mut Map<String, Value>) {
+    let Some(Value::Array(members)) = object.remove("allOf") else {
+        return;
+    };
+    let mut rest = Vec::new();
+    for member in members {
+        let Value::Object(mut member) = member else {
+            rest.push(member);
+            continue;
+        };
+        merge_all_of(&mut member);
+        let Some(Value::Object(properties)) = member.remove("properties") else {
+            rest.push(Value::Object(member));
+            continue;
+        };
+        if let Value::Object(merged) = object
+            .entry("properties")
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            merged.extend(properties);
+        }
+        if let Some(Value::Array(required)) = member.remove("required") {
+            if let Value::Array(merged) = object
+                .entry("required")
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                merged.extend(required);
+            }
+        }
+    }
+    if !rest.is_empty() {
+        object.insert("allOf".to_string(), Value::Array(rest));
+    }
+}
+
+/// Problems of the value against the schema, by the JSON pointers of the fields they are at
+pub fn validate(schema: &Value, value: &Value) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, value: &Value, pointer: &str, errors: &mut Vec<ConfigError>) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    let mut error = |message: String| {
+        errors.push(ConfigError {
+            pointer: pointer.to_string(),
+            message,
+        })
+    };
+    if value.is_null() && schema.get("nullable") == Some(&Value::Bool(true)) {
+        return;
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if !values.contains(value) {
+            let expected = values.iter().map(Value::to_string).collect::<Vec<_>>();
+            error(format!(
+                "expected one of {}, got {}",
+                expected.join(", "),
+                value
+            ));
+        }
+        return;
+    }
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        if !has_type(value, expected) {
+            error(format!("expected {}, got {}", expected, type_name(value)));
+            return;
+        }
+    }
+    if let (Some("uuid"), Value::String(s)) = (schema.get("format").and_then(Value::as_str), value)
+    {
+        if Uuid::parse_str(s).is_err() {
+            error(format!("expected a UUID, got {}", value));
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+            if number < minimum {
+                error(format!("expected at least {}, got {}", minimum, number));
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+            if number > maximum {
+                error(format!("expected at most {}, got {}", maximum, number));
+            }
+        }
+    }
+
+    if let Some(Value::Array(members)) = schema.get("allOf") {
+        for member in members {
+            validate_at(member, value, pointer, errors);
+        }
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(members)) = schema.get(key) {
+            if !members.is_empty()
+                && members
+                    .iter()
+                    .all(|member| !validate(member, value).is_empty())
+            {
+                errors.push(ConfigError {
+                    pointer: pointer.to_string(),
+                    message: format!("{} doesn't match any of the expected values", value),
+                });
+            }
+        }
+    }
+
+    match value {
+        Value::Object(fields) => validate_object(schema, fields, pointer, errors),
+        Value::Array(values) => {
+            if let Some(items) = schema.get("items") {
+                for (i, item) in values.iter().enumerate() {
+                    validate_at(items, item, &format!("{}/{}", pointer, i), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    fields: &Map<String, Value>,
+    pointer: &str,
+    errors: &mut Vec<ConfigError>,
+) {
+    let empty = Map::new();
+    let properties = match schema.get("properties") {
+        Some(Value::Object(properties)) => properties,
+        _ => &empty,
+    };
+    for (key, field) in fields {
+        let field_pointer = format!("{}/{}", pointer, escape_pointer_token(key));
+        match (properties.get(key), schema.get("additionalProperties")) {
+            (Some(property), _) => validate_at(property, field, &field_pointer, errors),
+            (None, Some(Value::Bool(false))) => {
+                let names = properties.keys().map(String::as_str).collect::<Vec<_>>();
+                let message = match closest(key, &names) {
+                    Some(name) => format!("unknown field {:?}, did you mean {:?}?", key, name),
+                    None => format!(
+                        "unknown field {:?}, expected one of {}",
+                        key,
+                        names
+                            .iter()
+                            .map(|name| format!("{:?}", name))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                };
+                errors.push(ConfigError {
+                    pointer: field_pointer,
+                    message,
+                });
+            }
+            (None, Some(additional)) => validate_at(additional, field, &field_pointer, errors),
+            (None, None) => {}
+        }
+    }
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !fields.contains_key(name) {
+                errors.push(ConfigError {
+                    pointer: format!("{}/{}", pointer, escape_pointer_token(name)),
+                    message: "missing required field".to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Whether the value has the OpenAPI type, unknown types accept any value
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Candidate closest to the misspelled name, if any is close enough to be a typo of it
+pub fn closest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance, ignoring case
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.to_lowercase().chars().collect::<Vec<_>>();
+    let b = b.to_lowercase().chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::nodes::llm::LLMNode;
+
+    #[test]
+    fn test_llm_config_errors_have_pointers() {
+        let schema = node_schema("LLM", LLMNode::schema().1);
+        let handle = serde_json::json!({"id": Uuid::new_v4(), "name": "output", "type": "String"});
+        let mut config = serde_json::json!({
+            "type": "LLM",
+            "id": Uuid::new_v4(),
+            "name": "llm",
+            "inputs": [],
+            "dynamicInputs": [],
+            "outputs": [handle],
+            "inputsMappings": {},
+            "prompt": "Answer the question",
+            "structuredOutputEnabled": false,
+        });
+        assert_eq!(validate(&schema, &config), vec![]);
+
+        config["promt"] = serde_json::json!("Answer briefly");
+        config["stream"] = serde_json::json!("yes");
+        config["outputs"][0]["type"] = serde_json::json!("Text");
+        let mut errors = validate(&schema, &config);
+        errors.sort_by(|a, b| a.pointer.cmp(&b.pointer));
+        let pointers = errors
+            .iter()
+            .map(|error| error.pointer.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(pointers, vec!["/outputs/0/type", "/promt", "/stream"]);
+        assert!(errors[0].message.contains("\"StringList\""));
+        assert!(errors[1].message.contains("did you mean \"prompt\""));
+        assert_eq!(errors[2].message, "expected boolean, got string");
+    }
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(closest("LMM", &["Input", "LLM", "Map"]), Some("LLM"));
+        assert_eq!(
+            closest("stringtemplate", &["StringTemplate"]),
+            Some("StringTemplate")
+        );
+        assert_eq!(closest("Ranker", &["Input", "LLM", "Map"]), None);
+    }
+}
@@ -3,21 +3,23 @@ use std::{collections::HashMap, sync::Arc};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
-use super::NodeInput;
 use super::{
-    semantic_search_utils::{query_datasources, render_query_res_point},
+    semantic_search_utils::{
+        query_datasources, render_query_res_point, EmbeddingNodeMetaLog, RetrievedDocument,
+    },
     Handle,
 };
 use crate::datasets::Dataset;
-use crate::engine::{RunOutput, RunnableNode};
-use crate::pipeline::context::Context;
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
+use crate::pipeline::{context::Context, trace::MetaLog};
 
 static DEFAULT_SEPARATOR: &str = "\n";
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SemanticSearchNode {
     pub id: Uuid,
@@ -28,12 +30,12 @@ pub struct SemanticSearchNode {
     pub limit: u32,
     pub threshold: f32,
     pub template: String,
-    #[serde[default]]
+    #[serde(default)]
     datasets: Vec<Dataset>,
 }
 
 #[async_trait]
-impl RunnableNode for SemanticSearchNode {
+impl NodeImpl for SemanticSearchNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -54,25 +56,21 @@ impl RunnableNode for SemanticSearchNode {
         "SemanticSearch".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput> {
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
         if self.datasets.is_empty() {
-            return Err(anyhow::anyhow!("Semantic search datasets missing."));
+            return Err(anyhow::anyhow!("Semantic search datasets missing.").into());
         }
 
-        let query: String = inputs.get("query").unwrap().clone().try_into()?;
+        let query = input.get_text("query")?;
 
         let collection_name = context.env.get("collection_name");
         if collection_name.is_none() {
-            return Err(anyhow::anyhow!("If you are using semantic search in a public pipeine, fork it to private pipeline, add your private data, and search over it."));
+            return Err(anyhow::anyhow!("If you are using semantic search in a public pipeine, fork it to private pipeline, add your private data, and search over it.").into());
         }
         let collection_name = collection_name.unwrap();
 
         // Points are returned from semantic search sorted by relevance
-        let points = query_datasources(
+        let response = query_datasources(
             &self.datasets,
             context.semantic_search.clone(),
             query,
@@ -82,7 +80,8 @@ impl RunnableNode for SemanticSearchNode {
         )
         .await?;
 
-        let templated_results: Vec<String> = points
+        let templated_results: Vec<String> = response
+            .results
             .iter()
             .enumerate()
             .map(|(index, point)| render_query_res_point(&self.template, point, index + 1))
@@ -90,6 +89,18 @@ impl RunnableNode for SemanticSearchNode {
 
         let res = templated_results.join(DEFAULT_SEPARATOR);
 
-        return Ok(RunOutput::Success((res.into(), None)));
+        let meta_log = EmbeddingNodeMetaLog {
+            input_token_count: response.input_token_count as i64,
+            documents: response
+                .results
+                .iter()
+                .map(RetrievedDocument::from)
+                .collect(),
+        };
+
+        return Ok(RunOutput::Success((
+            res.into(),
+            Some(MetaLog::Embedding(meta_log)),
+        )));
     }
 }
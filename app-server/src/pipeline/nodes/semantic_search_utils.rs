@@ -2,15 +2,48 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
 use crate::semantic_search::SemanticSearch;
 use crate::{
-    datasets::Dataset, pipeline::nodes::utils::render_template,
-    semantic_search::semantic_search_grpc::query_response::QueryPoint,
+    datasets::Dataset,
+    pipeline::nodes::utils::render_template,
+    semantic_search::semantic_search_grpc::{query_response::QueryPoint, QueryResponse},
 };
 
 use super::NodeInput;
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddingNodeMetaLog {
+    /// Billed tokens of the node's inputs. Concurrent requests are embedded in batches, whose
+    /// tokens are split between the requests by the length of their inputs.
+    pub input_token_count: i64,
+    /// Documents a semantic search node retrieved, in order of relevance
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub documents: Vec<RetrievedDocument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrievedDocument {
+    pub content: String,
+    pub score: f32,
+    pub datasource_id: String,
+    pub data: HashMap<String, String>,
+}
+
+impl From<&QueryPoint> for RetrievedDocument {
+    fn from(point: &QueryPoint) -> Self {
+        Self {
+            content: point.content.clone(),
+            score: point.score,
+            datasource_id: point.datasource_id.clone(),
+            data: point.data.clone(),
+        }
+    }
+}
+
 pub(super) async fn query_datasources(
     datasets: &Vec<Dataset>,
     semantic_search: Arc<SemanticSearch>,
@@ -18,17 +51,15 @@ pub(super) async fn query_datasources(
     collection_name: String,
     limit: u32,
     threshold: f32,
-) -> Result<Vec<QueryPoint>> {
+) -> Result<QueryResponse> {
     let payloads = datasets
         .iter()
         .map(|dataset| HashMap::from([("datasource_id".to_string(), dataset.id.to_string())]))
         .collect();
 
-    let res = semantic_search
+    semantic_search
         .query(&collection_name, query, limit, threshold, payloads)
-        .await?;
-
-    Ok(res.results)
+        .await
 }
 
 pub(super) fn render_query_res_point(
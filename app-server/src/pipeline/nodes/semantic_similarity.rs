@@ -2,16 +2,17 @@ use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    engine::{RunOutput, RunnableNode},
-    pipeline::context::Context,
+    engine::{Input, NodeError, NodeImpl, RunOutput},
+    pipeline::{context::Context, trace::MetaLog},
 };
 
-use super::{utils::map_handles, Handle, NodeInput};
+use super::{semantic_search_utils::EmbeddingNodeMetaLog, utils::map_handles, Handle, NodeInput};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SemanticSimilarityNode {
     pub id: Uuid,
@@ -23,7 +24,7 @@ pub struct SemanticSimilarityNode {
 }
 
 #[async_trait]
-impl RunnableNode for SemanticSimilarityNode {
+impl NodeImpl for SemanticSimilarityNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -44,13 +45,9 @@ impl RunnableNode for SemanticSimilarityNode {
         "SemanticSimilarity".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let first: String = inputs.get("first").unwrap().clone().try_into()?;
-        let second: String = inputs.get("second").unwrap().clone().try_into()?;
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let first = input.get_text("first")?;
+        let second = input.get_text("second")?;
 
         let resp = context
             .semantic_search
@@ -60,9 +57,16 @@ impl RunnableNode for SemanticSimilarityNode {
         match resp {
             Ok(response) => {
                 let score = response.scores.get(0).unwrap().clone();
-                Ok(RunOutput::Success((NodeInput::Float(score as f64), None)))
+                let meta_log = EmbeddingNodeMetaLog {
+                    input_token_count: response.input_token_count as i64,
+                    documents: vec![],
+                };
+                Ok(RunOutput::Success((
+                    NodeInput::Float(score as f64),
+                    Some(MetaLog::Embedding(meta_log)),
+                )))
             }
-            Err(e) => Err(anyhow::anyhow!("Failed to call semantic search {}", e)),
+            Err(e) => Err(anyhow::anyhow!("Failed to call semantic search {}", e).into()),
         }
     }
 }
@@ -1,17 +1,18 @@
 use std::{collections::HashMap, sync::Arc};
 
-use anyhow::{Ok, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
 use super::{ConditionedValue, Handle, NodeInput};
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SemanticSwitchNode {
     pub id: Uuid,
@@ -19,10 +20,11 @@ pub struct SemanticSwitchNode {
     pub inputs: Vec<Handle>,
     pub outputs: Vec<Handle>,
     pub inputs_mappings: HashMap<Uuid, Uuid>,
+    #[schema(inline)]
     pub routes: Vec<SemanticSwitchRoute>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct SemanticSwitchRoute {
     pub name: String,
     pub examples: Vec<String>,
@@ -41,7 +43,7 @@ pub struct CohereClassification {
 }
 
 #[async_trait]
-impl RunnableNode for SemanticSwitchNode {
+impl NodeImpl for SemanticSwitchNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -62,12 +64,8 @@ impl RunnableNode for SemanticSwitchNode {
         "SemanticSwitch".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input: String = inputs.values().next().unwrap().clone().try_into()?;
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input = input.single()?.text()?;
 
         let examples = self
             .routes
@@ -83,11 +81,10 @@ impl RunnableNode for SemanticSwitchNode {
             .flatten()
             .collect::<Vec<Value>>();
 
-        let client = reqwest::Client::new();
-
         let cohere_api_key = std::env::var("COHERE_API_KEY").unwrap();
 
-        let res = client
+        let res = context
+            .http_client
             .post("https://api.cohere.ai/v1/classify")
             .header("accept", "application/json")
             .header("content-type", "application/json")
@@ -103,7 +100,7 @@ impl RunnableNode for SemanticSwitchNode {
         if res.status() != 200 {
             let error_text = res.text().await.unwrap();
             log::error!("Failed to classify input: {}", error_text);
-            return Err(anyhow::anyhow!("Failed to classify input: {}", error_text));
+            return Err(anyhow::anyhow!("Failed to classify input: {}", error_text).into());
         }
 
         let json = res.json::<CohereClassifcationResponse>().await.unwrap();
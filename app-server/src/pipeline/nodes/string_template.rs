@@ -3,18 +3,19 @@ use std::{collections::HashMap, sync::Arc};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::{
-    utils::{map_handles, render_template},
-    Handle, NodeInput,
+    utils::{map_handles, CompiledTemplate},
+    Handle,
 };
 use crate::{
-    engine::{RunOutput, RunnableNode},
-    pipeline::context::Context,
+    engine::{Input, NodeError, NodeImpl, RunOutput},
+    pipeline::{context::Context, validation::GraphDiagnostic},
 };
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StringTemplateNode {
     pub id: Uuid,
@@ -26,10 +27,12 @@ pub struct StringTemplateNode {
     // mapping from node's input handle's id to the external handle id.
     pub inputs_mappings: HashMap<Uuid, Uuid>,
     pub text: String,
+    #[serde(skip)]
+    pub compiled_text: CompiledTemplate,
 }
 
 #[async_trait]
-impl RunnableNode for StringTemplateNode {
+impl NodeImpl for StringTemplateNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         let combined_inputs = self
             .inputs
@@ -57,12 +60,15 @@ impl RunnableNode for StringTemplateNode {
         "StringTemplate".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let rendered_text = render_template(&self.text, &inputs);
+    fn validate_config(&self) -> Vec<GraphDiagnostic> {
+        match self.compiled_text.compile(&self.text) {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![GraphDiagnostic::of_config(self.id, &self.name, e)],
+        }
+    }
+
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let rendered_text = self.compiled_text.render(&self.text, &input.values());
         Ok(RunOutput::Success((rendered_text.into(), None)))
     }
 }
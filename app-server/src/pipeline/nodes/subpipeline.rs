@@ -1,6 +1,6 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::runner::PipelineRunner;
 use crate::pipeline::trace::RunTraceStats;
 use crate::pipeline::{context::Context, trace::MetaLog, Graph};
@@ -9,12 +9,13 @@ use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::prelude::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
-use super::{Handle, NodeInput};
+use super::Handle;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SubpipelineNode {
     pub id: Uuid,
@@ -30,6 +31,7 @@ pub struct SubpipelineNode {
     // Commit pipeline version id, must be immutable
     #[serde(default)]
     pub pipeline_version_id: Option<Uuid>,
+    #[schema(value_type = Object)]
     pub runnable_graph: Value,
 }
 
@@ -42,7 +44,7 @@ pub struct SubpipelineNodeMetaLog {
 }
 
 #[async_trait]
-impl RunnableNode for SubpipelineNode {
+impl NodeImpl for SubpipelineNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -67,19 +69,20 @@ impl RunnableNode for SubpipelineNode {
     }
 
     // TODO: Block infinite recursion (e.g. if depth is too high, return error)
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput> {
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
         if self.pipeline_version_id.is_none() {
-            return Err(anyhow::anyhow!("Pipeline version id is required"));
+            return Err(anyhow::anyhow!("Pipeline version id is required").into());
         }
 
         let env = &context.env;
 
         let mut graph = serde_json::from_value::<Graph>(self.runnable_graph.clone())?;
-        graph.setup(&inputs, &env, &context.metadata, &context.run_type)?;
+        graph.setup(&input.values(), env, &context.metadata, &context.run_type)?;
+        graph.secrets = context.secrets.clone();
+        graph.credentials = context.credentials.clone();
+        graph.workspace_model_defaults = context.workspace_model_defaults.clone();
+        graph.workspace_id = context.workspace_id;
+        graph.prompts = context.prompts.clone();
         // TODO: Add streaming and websocket streaming here so that subpipelines can stream and use external functions.
         let run_result = context.pipeline_runner.run(graph, context.tx.clone()).await;
 
@@ -104,10 +107,10 @@ impl RunnableNode for SubpipelineNode {
             } else {
                 // TODO: Partial trace must be returned, modify engine's Err from anyhow to custom error type,
                 // which will contain error type and optional meta_log/error trace fields
-                Err(anyhow::anyhow!("Subpipeline run failed"))
+                Err(anyhow::anyhow!("Subpipeline run failed").into())
             }
         } else {
-            Err(anyhow::anyhow!("Subpipeline run failed"))
+            Err(anyhow::anyhow!("Subpipeline run failed").into())
         }
     }
 }
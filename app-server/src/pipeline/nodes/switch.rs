@@ -1,16 +1,17 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use crate::pipeline::context::Context;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use tonic::async_trait;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::utils::map_handles;
-use super::{ConditionedValue, Handle, NodeInput};
+use super::{ConditionedValue, Handle};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SwitchNode {
     pub id: Uuid,
@@ -18,18 +19,26 @@ pub struct SwitchNode {
     pub inputs: Vec<Handle>,
     pub outputs: Vec<Handle>,
     pub inputs_mappings: HashMap<Uuid, Uuid>,
+    #[schema(inline)]
     routes: Vec<Route>,
     #[serde(default)]
     has_default_route: bool,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 struct Route {
     name: String,
 }
 
+impl SwitchNode {
+    /// Names of the routes, the default route last if the node has one
+    pub fn route_names(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|route| route.name.as_str())
+    }
+}
+
 #[async_trait]
-impl RunnableNode for SwitchNode {
+impl NodeImpl for SwitchNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -50,13 +59,9 @@ impl RunnableNode for SwitchNode {
         "Switch".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        _context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let condition: String = inputs.get("condition").unwrap().clone().try_into()?;
-        let input = inputs.get("input").unwrap().clone();
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let condition = input.get_text("condition")?;
+        let value = input.value("input")?.clone();
 
         let output_condition = if self.routes.iter().any(|route| route.name == condition) {
             condition
@@ -64,14 +69,11 @@ impl RunnableNode for SwitchNode {
             // default output is always the last as ensured by the front-end
             self.routes.last().unwrap().name.clone()
         } else {
-            return Err(anyhow::anyhow!(
-                "No route found for condition: {}",
-                condition
-            ));
+            return Err(anyhow::anyhow!("No route found for condition: {}", condition).into());
         };
 
         let condition_value = ConditionedValue {
-            value: Box::new(input.clone()),
+            value: Box::new(value),
             condition: output_condition,
         };
 
@@ -1,5 +1,9 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
 
+use fancy_regex::Regex;
 use handlebars::Handlebars;
 use uuid::Uuid;
 
@@ -32,3 +36,73 @@ pub fn render_template(template: &String, inputs: &HashMap<String, NodeInput>) -
         .render_template(template, inputs)
         .unwrap_or_default()
 }
+
+const TEMPLATE_NAME: &str = "template";
+const COMPILED_CACHE_SIZE: u64 = 10_000;
+
+lazy_static::lazy_static! {
+    /// Compiled templates and regexes by their source, so that nodes of any graph with the same
+    /// config share one compilation
+    static ref TEMPLATES: moka::sync::Cache<String, Arc<Result<Handlebars<'static>, String>>> =
+        moka::sync::Cache::new(COMPILED_CACHE_SIZE);
+    static ref REGEXES: moka::sync::Cache<String, Arc<Result<Regex, String>>> =
+        moka::sync::Cache::new(COMPILED_CACHE_SIZE);
+}
+
+/// Template of a node config, compiled on its first render or when its graph is compiled
+///
+/// Runs of a compiled graph share its node configs, so only the first run compiles it. Renders
+/// the same as `render_template`, i.e. invalid templates render as empty strings.
+#[derive(Debug, Clone, Default)]
+pub struct CompiledTemplate(OnceLock<Arc<Result<Handlebars<'static>, String>>>);
+
+impl CompiledTemplate {
+    /// Compile the template ahead of its first render, returns why it's invalid if it is
+    pub fn compile(&self, template: &str) -> Result<(), String> {
+        match self.compiled(template) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    pub fn render(&self, template: &str, inputs: &HashMap<String, NodeInput>) -> String {
+        self.compiled(template)
+            .as_ref()
+            .ok()
+            .and_then(|handlebars| handlebars.render(TEMPLATE_NAME, inputs).ok())
+            .unwrap_or_default()
+    }
+
+    fn compiled(&self, template: &str) -> &Result<Handlebars<'static>, String> {
+        self.0.get_or_init(|| {
+            TEMPLATES.get_with(template.to_string(), || {
+                let mut handlebars = Handlebars::new();
+                handlebars.register_escape_fn(handlebars::no_escape);
+                Arc::new(
+                    handlebars
+                        .register_template_string(TEMPLATE_NAME, template)
+                        .map(|_| handlebars)
+                        .map_err(|e| e.to_string()),
+                )
+            })
+        })
+    }
+}
+
+/// Regex of a node config, compiled on its first use, like `CompiledTemplate`
+#[derive(Debug, Clone, Default)]
+pub struct CompiledRegex(OnceLock<Arc<Result<Regex, String>>>);
+
+impl CompiledRegex {
+    pub fn get(&self, pattern: &str) -> anyhow::Result<&Regex> {
+        self.0
+            .get_or_init(|| {
+                REGEXES.get_with(pattern.to_string(), || {
+                    Arc::new(Regex::new(pattern).map_err(|e| e.to_string()))
+                })
+            })
+            .as_ref()
+            .as_ref()
+            .map_err(|e| anyhow::anyhow!("Failed to compile regex: {}", e))
+    }
+}
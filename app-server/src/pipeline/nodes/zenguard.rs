@@ -1,24 +1,25 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::engine::{RunOutput, RunnableNode};
+use crate::engine::{Input, NodeError, NodeImpl, RunOutput};
 use anyhow::Result;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tonic::async_trait;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use super::{utils::map_handles, ConditionedValue, Handle, NodeInput};
 use crate::pipeline::{context::Context, trace::MetaLog};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 struct Detector {
     #[serde(rename = "type")]
     detector_type: String,
     enabled: bool,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ZenguardNode {
     pub id: Uuid,
@@ -26,7 +27,11 @@ pub struct ZenguardNode {
     pub inputs: Vec<Handle>,
     pub outputs: Vec<Handle>,
     pub inputs_mappings: HashMap<Uuid, Uuid>,
+    #[schema(inline)]
     detectors: Vec<Detector>,
+    /// Finish the run with the blocked input, instead of routing it to the `block` condition
+    #[serde(default)]
+    finish_on_block: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -152,7 +157,7 @@ impl ZenguardNode {
 }
 
 #[async_trait]
-impl RunnableNode for ZenguardNode {
+impl NodeImpl for ZenguardNode {
     fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
         map_handles(&self.inputs, &self.inputs_mappings)
     }
@@ -173,12 +178,8 @@ impl RunnableNode for ZenguardNode {
         "Zenguard".to_string()
     }
 
-    async fn run(
-        &self,
-        inputs: HashMap<String, NodeInput>,
-        context: Arc<Context>,
-    ) -> Result<RunOutput> {
-        let input: String = inputs.values().next().unwrap().clone().try_into()?;
+    async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input = input.single()?.text()?;
 
         let api_key = context.env.get("ZENGUARD_API_KEY").unwrap();
 
@@ -186,8 +187,6 @@ impl RunnableNode for ZenguardNode {
         let mut redacted_input = input.clone();
         let mut condition = String::from("passthrough");
 
-        // TODO: Implement a pool of reqwest clients or make shared client
-        let client = Client::new();
         let mut responses: HashMap<String, HashMap<String, Value>> = HashMap::new();
 
         // It's inevitable to call one-by-one sequentially, because message possibly will get
@@ -198,7 +197,12 @@ impl RunnableNode for ZenguardNode {
             }
 
             let detector_response = self
-                .call_detector(&client, &detector.detector_type, &redacted_input, api_key)
+                .call_detector(
+                    &context.http_client,
+                    &detector.detector_type,
+                    &redacted_input,
+                    api_key,
+                )
                 .await;
             match detector_response {
                 Err(e) => {
@@ -206,7 +210,8 @@ impl RunnableNode for ZenguardNode {
                         "Error when calling Zenguard detector {}: {}",
                         &detector.detector_type,
                         e,
-                    ));
+                    )
+                    .into());
                 }
                 Ok(response) => {
                     let mut response_map = HashMap::new();
@@ -249,9 +254,10 @@ impl RunnableNode for ZenguardNode {
             detectors_order,
         };
 
-        Ok(RunOutput::Success((
-            condition_value.into(),
-            Some(MetaLog::Zenguard(meta_log)),
-        )))
+        let meta_log = Some(MetaLog::Zenguard(meta_log));
+        if self.finish_on_block && condition_value.condition == "block" {
+            return Ok(RunOutput::Finish((*condition_value.value, meta_log)));
+        }
+        Ok(RunOutput::Success((condition_value.into(), meta_log)))
     }
 }
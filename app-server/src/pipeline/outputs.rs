@@ -0,0 +1,262 @@
+//! Named outputs of the runs of a graph
+//!
+//! A graph JSON may declare `outputBindings`, names of run outputs bound to the outputs of its
+//! nodes, e.g. `{"answer": "llm_final.output", "sources": "retriever"}`, as `node.handle` or
+//! just `node`. The outputs of a run of such a graph are then keyed by the names of the
+//! bindings, whether or not the nodes are output nodes, and output nodes without a binding run
+//! as before but are left out.
+//!
+//! Every binding has an output in the response, the final chunk of the stream and the stored
+//! result of the run. Outputs of nodes which didn't produce a value are null, with the reason,
+//! e.g. a node whose branch a condition terminated.
+//!
+//! A run which a node finished early, e.g. a guardrail, only has the outputs bound to that node,
+//! so names can be bound to the nodes which may finish runs of the graph.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::engine::blocked::{BlockReason, BlockedNode};
+
+use super::{
+    nodes::{schema, GraphOutput, Message},
+    Graph,
+};
+
+/// Why the node of an output binding has no value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum MissingOutputReason {
+    /// The node ran and failed
+    Failed,
+    /// The node's branch was terminated before it, e.g. by a condition which didn't match
+    Skipped,
+    /// The node waited for an input whose predecessor never produced it
+    Stuck,
+    /// The node itself terminated its branch
+    Terminated,
+    /// The run finished or stopped before the node was scheduled
+    NotExecuted,
+    /// Another node finished the run with its own output, see `RunOutput::Finish`
+    Finished,
+}
+
+impl From<BlockReason> for MissingOutputReason {
+    fn from(reason: BlockReason) -> Self {
+        match reason {
+            BlockReason::Failed => Self::Failed,
+            BlockReason::Skipped => Self::Skipped,
+            BlockReason::Stuck => Self::Stuck,
+            BlockReason::Cancelled => Self::Finished,
+        }
+    }
+}
+
+/// Output bindings of a graph, resolved to the ids of their nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputBindings(Vec<OutputBinding>);
+
+#[derive(Debug, Clone, PartialEq)]
+struct OutputBinding {
+    name: String,
+    node_id: Uuid,
+}
+
+impl OutputBindings {
+    /// Nodes the outputs are bound to
+    pub fn node_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.0.iter().map(|binding| binding.node_id)
+    }
+
+    /// Bindings of the graph, unset if it declares none
+    pub fn of_graph(graph: &Graph) -> anyhow::Result<Option<Self>> {
+        if graph.output_bindings.is_empty() {
+            return Ok(None);
+        }
+        let nodes = graph
+            .nodes
+            .values()
+            .map(|node| (node.name(), node))
+            .collect::<HashMap<_, _>>();
+        let names = nodes.keys().map(String::as_str).collect::<Vec<_>>();
+
+        let mut bindings = Vec::new();
+        for (name, target) in graph.output_bindings.iter() {
+            // node names may contain dots, so a target which is a node name has no handle
+            let (node_name, handle) = match target.rsplit_once('.') {
+                Some((node_name, handle)) if !nodes.contains_key(target) => {
+                    (node_name, Some(handle))
+                }
+                _ => (target.as_str(), None),
+            };
+            let Some(node) = nodes.get(node_name) else {
+                return Err(anyhow::anyhow!(
+                    "Output {} is bound to unknown node {}{}",
+                    name,
+                    node_name,
+                    schema::closest(node_name, &names)
+                        .map(|closest| format!(", did you mean {:?}?", closest))
+                        .unwrap_or_default()
+                ));
+            };
+            if let Some(handle) = handle {
+                let output_handle = node.output_handle_name();
+                if handle != output_handle {
+                    return Err(anyhow::anyhow!(
+                        "Output {} is bound to unknown output {} of node {}, its output is {}",
+                        name,
+                        handle,
+                        node_name,
+                        output_handle
+                    ));
+                }
+            }
+            bindings.push(OutputBinding {
+                name: name.clone(),
+                node_id: node.id(),
+            });
+        }
+        Ok(Some(Self(bindings)))
+    }
+
+    /// Outputs of the run by binding name, from the latest message of each bound node
+    pub fn bind(
+        &self,
+        messages: &HashMap<Uuid, Message>,
+        blocked_nodes: &[BlockedNode],
+        terminated_node_ids: &HashSet<Uuid>,
+        finished_by: Option<Uuid>,
+    ) -> BTreeMap<String, GraphOutput> {
+        let mut latest = HashMap::<Uuid, &Message>::new();
+        for message in messages.values() {
+            let entry = latest.entry(message.node_id).or_insert(message);
+            if message.end_time > entry.end_time {
+                *entry = message;
+            }
+        }
+
+        self.0
+            .iter()
+            .map(|binding| {
+                let blocked = blocked_nodes
+                    .iter()
+                    .find(|node| node.node_id == binding.node_id);
+                let output = match (blocked, latest.get(&binding.node_id)) {
+                    _ if finished_by.is_some_and(|node_id| node_id != binding.node_id) => {
+                        GraphOutput::missing(MissingOutputReason::Finished)
+                    }
+                    (Some(blocked), _) => GraphOutput::missing(blocked.reason.into()),
+                    _ if terminated_node_ids.contains(&binding.node_id) => {
+                        GraphOutput::missing(MissingOutputReason::Terminated)
+                    }
+                    (None, Some(message)) => GraphOutput::new(message.value.clone()),
+                    (None, None) => GraphOutput::missing(MissingOutputReason::NotExecuted),
+                };
+                (binding.name.clone(), output)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::pipeline::nodes::NodeInput;
+
+    fn graph(output_bindings: Value) -> Graph {
+        let handle = || json!([{"id": Uuid::new_v4(), "name": "output", "type": "String"}]);
+        serde_json::from_value(json!({
+            "nodes": {
+                "a": {
+                    "type": "Input",
+                    "id": Uuid::new_v4(),
+                    "name": "question",
+                    "outputs": handle(),
+                    "inputType": "String",
+                },
+                "b": {
+                    "type": "Output",
+                    "id": Uuid::new_v4(),
+                    "name": "output",
+                    "inputs": handle(),
+                    "inputsMappings": {},
+                },
+            },
+            "pred": {},
+            "outputBindings": output_bindings,
+        }))
+        .unwrap()
+    }
+
+    fn node_id(graph: &Graph, name: &str) -> Uuid {
+        graph
+            .nodes
+            .values()
+            .find(|node| node.name() == name)
+            .unwrap()
+            .id()
+    }
+
+    #[test]
+    fn test_bindings_reference_nodes_and_handles() {
+        assert_eq!(OutputBindings::of_graph(&graph(json!({}))).unwrap(), None);
+        assert!(
+            OutputBindings::of_graph(&graph(json!({"q": "question.output", "a": "output"})))
+                .unwrap()
+                .is_some()
+        );
+
+        let error = OutputBindings::of_graph(&graph(json!({"q": "questoin"}))).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Output q is bound to unknown node questoin, did you mean \"question\"?"
+        );
+        assert!(OutputBindings::of_graph(&graph(json!({"q": "question.chunks"}))).is_err());
+    }
+
+    #[test]
+    fn test_missing_outputs_are_null_with_reason() {
+        let graph = graph(json!({"answer": "output", "question": "question"}));
+        let bindings = OutputBindings::of_graph(&graph).unwrap().unwrap();
+        let question = Message {
+            node_id: node_id(&graph, "question"),
+            node_name: "question".to_string(),
+            value: NodeInput::String("Why?".to_string()),
+            ..Message::empty()
+        };
+        let messages = HashMap::from([(question.id, question)]);
+
+        let outputs = bindings.bind(&messages, &[], &HashSet::new(), None);
+        assert_eq!(
+            serde_json::to_value(&outputs).unwrap(),
+            json!({
+                "answer": {"value": null, "reason": "NotExecuted"},
+                "question": {"value": "Why?"},
+            })
+        );
+
+        let terminated = HashSet::from([node_id(&graph, "output")]);
+        let outputs = bindings.bind(&messages, &[], &terminated, None);
+        assert_eq!(
+            outputs["answer"].reason,
+            Some(MissingOutputReason::Terminated)
+        );
+
+        // a run finished by the question node only has its output
+        let outputs = bindings.bind(
+            &messages,
+            &[],
+            &terminated,
+            Some(node_id(&graph, "question")),
+        );
+        assert_eq!(
+            outputs["answer"].reason,
+            Some(MissingOutputReason::Finished)
+        );
+        assert_eq!(outputs["question"].reason, None);
+    }
+}
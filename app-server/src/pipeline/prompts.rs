@@ -0,0 +1,370 @@
+//! Prompts of the project's registry, referenced by template and LLM nodes
+//!
+//! The `prompt` of an LLM node or the `text` of a template node may reference a prompt of the
+//! registry rather than inline its text: `prompt://summarize` for its latest version,
+//! `prompt://summarize@production` for the version the `production` label points to, or
+//! `prompt://summarize@3` for version 3. References are resolved from the registry when the
+//! graph of a run is set up, and substituted with their templates when it's compiled, so moving
+//! a label changes the prompt of the next runs without a new pipeline version. Graphs which
+//! aren't set up for a run, e.g. of version diffs, keep their references as text.
+//!
+//! Runs record the resolved versions in their metadata as `prompt.{name}`, and LLM nodes in
+//! their meta log and on their spans. Versions in the metadata a run is set up with are pinned,
+//! over the labels the nodes reference, so that queued runs and replays run with the versions
+//! they recorded, and evaluations with the candidate versions they compare.
+
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db;
+
+use super::{
+    nodes::Node, runner::PipelineRunnerError, utils::get_graph_content_hash, Graph, GraphError,
+};
+
+pub const PROMPT_REFERENCE_PREFIX: &str = "prompt://";
+/// Prefix of the run metadata keys of the resolved prompt versions
+const METADATA_PREFIX: &str = "prompt.";
+
+/// Which version of a prompt a reference resolves to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PromptSelector {
+    Latest,
+    Label(String),
+    Version(i32),
+}
+
+/// Reference of a node to a prompt of the registry, e.g. `prompt://summarize@production`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PromptReference {
+    pub name: String,
+    pub selector: PromptSelector,
+}
+
+impl PromptReference {
+    /// Reference of the text of a node, unset if the text is inline
+    pub fn parse(text: &str) -> anyhow::Result<Option<Self>> {
+        let Some(reference) = text.trim().strip_prefix(PROMPT_REFERENCE_PREFIX) else {
+            return Ok(None);
+        };
+        let (name, selector) = match reference.split_once('@') {
+            Some((name, selector)) => match selector.parse::<i32>() {
+                Ok(version) => (name, PromptSelector::Version(version)),
+                Err(_) => (name, PromptSelector::Label(selector.to_string())),
+            },
+            None => (reference, PromptSelector::Latest),
+        };
+        let valid_label = match &selector {
+            PromptSelector::Label(label) => is_valid_name(label),
+            _ => true,
+        };
+        if !is_valid_name(name) || !valid_label {
+            return Err(anyhow::anyhow!("Invalid prompt reference {}", text.trim()));
+        }
+        Ok(Some(Self {
+            name: name.to_string(),
+            selector,
+        }))
+    }
+}
+
+impl fmt::Display for PromptReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PROMPT_REFERENCE_PREFIX, self.name)?;
+        match &self.selector {
+            PromptSelector::Latest => Ok(()),
+            PromptSelector::Label(label) => write!(f, "@{}", label),
+            PromptSelector::Version(version) => write!(f, "@{}", version),
+        }
+    }
+}
+
+/// Names of prompts and labels are letters, digits, `_`, `-` and `.`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Labels which are numbers would be taken for versions in references
+pub fn is_valid_label(label: &str) -> bool {
+    is_valid_name(label) && label.parse::<i32>().is_err()
+}
+
+/// Version of a prompt which a reference resolved to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedPrompt {
+    pub name: String,
+    pub version: i32,
+    pub version_id: Uuid,
+    /// Label the version was resolved by, unset for references to the latest or a pinned version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip)]
+    pub template: String,
+}
+
+/// Resolved versions of the references of a graph
+pub type RegistryPrompts = HashMap<PromptReference, ResolvedPrompt>;
+
+fn node_text(node: &Node) -> Option<&str> {
+    match node {
+        Node::LLM(node) => Some(&node.prompt),
+        Node::StringTemplate(node) => Some(&node.text),
+        _ => None,
+    }
+}
+
+/// References of the nodes of the graph, and of the graphs of its subpipelines and maps
+pub fn prompt_references(graph: &Graph) -> anyhow::Result<Vec<PromptReference>> {
+    let mut references = Vec::new();
+    for node in graph.nodes.values() {
+        let subgraph = match node {
+            Node::Subpipeline(subpipeline_node) => &subpipeline_node.runnable_graph,
+            Node::Map(map_node) => &map_node.runnable_graph,
+            _ => {
+                if let Some(text) = node_text(node) {
+                    references.extend(PromptReference::parse(text)?);
+                }
+                continue;
+            }
+        };
+        if let Ok(subgraph) = serde_json::from_value::<Graph>(subgraph.clone()) {
+            references.extend(prompt_references(&subgraph)?);
+        }
+    }
+    Ok(references)
+}
+
+/// Resolve the references of the graph from the project's registry, with the pinned versions
+/// of prompts by name over the selectors of their references
+pub async fn load_prompts(
+    pool: &PgPool,
+    project_id: &Uuid,
+    graph: &mut Graph,
+    pins: &HashMap<String, i32>,
+) -> Result<(), PipelineRunnerError> {
+    let references = prompt_references(graph).map_err(GraphError::UnhandledError)?;
+    if references.is_empty() {
+        return Ok(());
+    }
+
+    let mut prompts = RegistryPrompts::new();
+    let mut unknown = Vec::new();
+    for reference in references {
+        if prompts.contains_key(&reference) {
+            continue;
+        }
+        let selector = match pins.get(&reference.name) {
+            Some(version) => PromptSelector::Version(*version),
+            None => reference.selector.clone(),
+        };
+        let version =
+            db::prompts::get_selected_prompt_version(pool, project_id, &reference.name, &selector)
+                .await?;
+        let Some(version) = version else {
+            unknown.push(reference.to_string());
+            continue;
+        };
+        let label = match selector {
+            PromptSelector::Label(label) => Some(label),
+            _ => None,
+        };
+        let prompt = ResolvedPrompt {
+            name: reference.name.clone(),
+            version: version.version,
+            version_id: version.id,
+            label,
+            template: version.template,
+        };
+        prompts.insert(reference, prompt);
+    }
+    if !unknown.is_empty() {
+        unknown.sort();
+        unknown.dedup();
+        return Err(GraphError::UnknownPrompts(unknown).into());
+    }
+
+    let versions = prompts
+        .values()
+        .map(|prompt| (prompt.name.clone(), prompt.version))
+        .collect::<HashMap<_, _>>();
+    pin_versions(&mut graph.metadata, &versions);
+    graph.prompts = Some(prompts);
+    Ok(())
+}
+
+/// Pin the versions of prompts by name in the metadata of runs
+pub fn pin_versions(metadata: &mut HashMap<String, String>, versions: &HashMap<String, i32>) {
+    for (name, version) in versions {
+        metadata.insert(format!("{}{}", METADATA_PREFIX, name), version.to_string());
+    }
+}
+
+/// Versions of prompts by name pinned in the metadata of a run
+pub fn recorded_versions(metadata: &HashMap<String, String>) -> HashMap<String, i32> {
+    metadata
+        .iter()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(METADATA_PREFIX)?;
+            Some((name.to_string(), value.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Graph whose references are substituted with the templates of their resolved versions
+pub fn resolve_graph(graph: &Graph) -> anyhow::Result<Cow<'_, Graph>> {
+    let Some(prompts) = &graph.prompts else {
+        return Ok(Cow::Borrowed(graph));
+    };
+
+    let resolve = |text: &str| -> anyhow::Result<Option<ResolvedPrompt>> {
+        let Some(reference) = PromptReference::parse(text)? else {
+            return Ok(None);
+        };
+        match prompts.get(&reference) {
+            Some(prompt) => Ok(Some(prompt.clone())),
+            None => Err(anyhow::anyhow!("Prompt {} is not resolved", reference)),
+        }
+    };
+    let mut resolved = graph.clone();
+    for node in resolved.nodes.values_mut() {
+        match node {
+            Node::LLM(node) => {
+                if let Some(prompt) = resolve(&node.prompt)? {
+                    node.prompt = prompt.template.clone();
+                    node.prompt_version = Some(prompt);
+                }
+            }
+            Node::StringTemplate(node) => {
+                if let Some(prompt) = resolve(&node.text)? {
+                    node.text = prompt.template;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Cow::Owned(resolved))
+}
+
+/// Key of the compiled graph of a COMMIT version, which depends on the resolved versions too
+pub fn compiled_cache_key(key: String, graph: &Graph) -> String {
+    let Some(prompts) = &graph.prompts else {
+        return key;
+    };
+    let mut versions = prompts
+        .iter()
+        .map(|(reference, prompt)| format!("{}={}", reference, prompt.version_id))
+        .collect::<Vec<_>>();
+    versions.sort();
+    format!("{}:{}", key, get_graph_content_hash(&json!(versions)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_references() {
+        assert_eq!(PromptReference::parse("Summarize {{text}}").unwrap(), None);
+        assert_eq!(
+            PromptReference::parse("prompt://summarize@production").unwrap(),
+            Some(PromptReference {
+                name: "summarize".to_string(),
+                selector: PromptSelector::Label("production".to_string()),
+            })
+        );
+        let reference = PromptReference::parse(" prompt://summarize@3\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(reference.selector, PromptSelector::Version(3));
+        assert_eq!(reference.to_string(), "prompt://summarize@3");
+        assert_eq!(
+            PromptReference::parse("prompt://summarize")
+                .unwrap()
+                .unwrap()
+                .selector,
+            PromptSelector::Latest
+        );
+        assert!(PromptReference::parse("prompt://sum marize").is_err());
+        assert!(PromptReference::parse("prompt://summarize@").is_err());
+    }
+
+    fn graph() -> Graph {
+        let handle = || json!([{"id": Uuid::new_v4(), "type": "String"}]);
+        serde_json::from_value(json!({
+            "nodes": {
+                "a": {
+                    "type": "StringTemplate",
+                    "id": Uuid::new_v4(),
+                    "name": "template",
+                    "inputs": [],
+                    "outputs": handle(),
+                    "inputsMappings": {},
+                    "text": "prompt://summarize@production",
+                },
+                "b": {
+                    "type": "Output",
+                    "id": Uuid::new_v4(),
+                    "name": "output",
+                    "inputs": handle(),
+                    "inputsMappings": {},
+                },
+            },
+            "pred": {},
+        }))
+        .unwrap()
+    }
+
+    fn template(graph: &Graph) -> String {
+        match &graph.nodes["a"] {
+            Node::StringTemplate(node) => node.text.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_references_are_substituted_once_resolved() {
+        let mut graph = graph();
+        assert_eq!(
+            template(&resolve_graph(&graph).unwrap()),
+            "prompt://summarize@production"
+        );
+
+        let reference = prompt_references(&graph).unwrap().remove(0);
+        let prompt = |version: i32| ResolvedPrompt {
+            name: "summarize".to_string(),
+            version,
+            version_id: Uuid::new_v4(),
+            label: Some("production".to_string()),
+            template: format!("Summarize v{}: {{{{text}}}}", version),
+        };
+        graph.prompts = Some(HashMap::from([(reference.clone(), prompt(1))]));
+        assert_eq!(
+            template(&resolve_graph(&graph).unwrap()),
+            "Summarize v1: {{text}}"
+        );
+
+        // moving the label compiles the same version of the pipeline again
+        let key = compiled_cache_key("hash".to_string(), &graph);
+        graph.prompts = Some(HashMap::from([(reference, prompt(2))]));
+        assert_ne!(compiled_cache_key("hash".to_string(), &graph), key);
+
+        graph.prompts = Some(HashMap::new());
+        assert!(resolve_graph(&graph).is_err());
+        assert_eq!(
+            recorded_versions(&HashMap::from([
+                ("prompt.summarize".to_string(), "2".to_string()),
+                ("evaluation_id".to_string(), Uuid::new_v4().to_string()),
+            ])),
+            HashMap::from([("summarize".to_string(), 2)])
+        );
+    }
+}
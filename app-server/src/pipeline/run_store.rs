@@ -0,0 +1,367 @@
+//! Key/value scratch store of a run, shared by its nodes
+//!
+//! Nodes reach the store through `Context::run_store`, e.g. to accumulate results across the
+//! iterations of a loop or to hand state between branches without wiring it through edges, and
+//! graphs through `RunStore` nodes, which get, set or append to a key. Keys are strings and
+//! values are JSON, and the store holds at most `RUN_STORE_MAX_BYTES` of keys and serialized
+//! values, writes which would exceed it fail.
+//!
+//! Each operation is atomic, and writes to a key are last-write-wins: of concurrent `set`s of
+//! parallel branches, the one applied last is kept. `append_to_array` and `compare_and_swap` read
+//! and write the key in one step, so concurrent appends are all kept, and a `compare_and_swap`
+//! retried until it succeeds updates a key without losing writes of other branches.
+//!
+//! Checkpointed runs include the store in their checkpoints, see `runs::checkpoints`. A run
+//! resumed from its checkpoint continues with the store as it was checkpointed, and the tasks
+//! it re-runs, e.g. the iteration which was in flight, apply their writes again. Graphs with
+//! `traceRunStore` set record the store as of the end of the run on the run's span.
+
+use std::{collections::BTreeMap, env, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RunStoreError {
+    #[error("Run store would hold {bytes} bytes with key {key}, over its limit of {max_bytes}")]
+    TooLarge {
+        key: String,
+        bytes: usize,
+        max_bytes: usize,
+    },
+    #[error("Value of key {0} in the run store is not an array")]
+    NotAnArray(String),
+}
+
+/// Values of the store, as checkpointed and restored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunStoreSnapshot {
+    /// Number of writes to the store, a snapshot with a higher version is later
+    pub version: u64,
+    pub values: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Values with the size of their key and serialized value
+    values: BTreeMap<String, (Value, usize)>,
+    bytes: usize,
+    version: u64,
+}
+
+#[derive(Debug)]
+pub struct RunStore {
+    entries: Mutex<Entries>,
+    max_bytes: usize,
+}
+
+impl Default for RunStore {
+    fn default() -> Self {
+        let max_bytes = env::var("RUN_STORE_MAX_BYTES")
+            .ok()
+            .and_then(|bytes| bytes.parse().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        Self::with_max_bytes(max_bytes)
+    }
+}
+
+impl RunStore {
+    pub fn with_max_bytes(max_bytes: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            max_bytes,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let entries = self.entries.lock().unwrap();
+        entries.values.get(key).map(|(value, _)| value.clone())
+    }
+
+    pub fn set(&self, key: &str, value: Value) -> Result<(), RunStoreError> {
+        let size = entry_size(key, &value);
+        let mut entries = self.entries.lock().unwrap();
+        self.put(&mut entries, key, value, size)
+    }
+
+    /// Append the value to the array of the key, which is created if the key is unset. Returns the
+    /// length of the array.
+    pub fn append_to_array(&self, key: &str, value: Value) -> Result<usize, RunStoreError> {
+        let value_size = serde_json::to_vec(&value).map_or(0, |value| value.len());
+        let mut entries = self.entries.lock().unwrap();
+        let (len, size) = match entries.values.get(key) {
+            None => (0, key.len() + "[]".len()),
+            Some((Value::Array(array), size)) => (array.len(), *size),
+            Some(_) => return Err(RunStoreError::NotAnArray(key.to_string())),
+        };
+        // with the comma separating it from the previous element
+        let size = size + value_size + usize::from(len > 0);
+        let bytes = self.bytes_with(&entries, key, size)?;
+        match entries.values.get_mut(key) {
+            Some((Value::Array(array), array_size)) => {
+                array.push(value);
+                *array_size = size;
+            }
+            _ => {
+                entries
+                    .values
+                    .insert(key.to_string(), (Value::Array(vec![value]), size));
+            }
+        }
+        entries.bytes = bytes;
+        entries.version += 1;
+        Ok(len + 1)
+    }
+
+    /// Set the key to `new` if its value is `expected`, or if it's unset and `expected` is None.
+    /// Returns whether the key was set.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&Value>,
+        new: Value,
+    ) -> Result<bool, RunStoreError> {
+        let size = entry_size(key, &new);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.values.get(key).map(|(value, _)| value) != expected {
+            return Ok(false);
+        }
+        self.put(&mut entries, key, new, size)?;
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().values.is_empty()
+    }
+
+    /// Number of writes to the store so far, including the ones of the restored snapshot
+    pub fn version(&self) -> u64 {
+        self.entries.lock().unwrap().version
+    }
+
+    pub fn snapshot(&self) -> RunStoreSnapshot {
+        let entries = self.entries.lock().unwrap();
+        RunStoreSnapshot {
+            version: entries.version,
+            values: entries
+                .values
+                .iter()
+                .map(|(key, (value, _))| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Replace the values with the snapshot's, e.g. of the checkpoint a run is resumed from
+    pub fn restore(&self, snapshot: RunStoreSnapshot) {
+        let values = snapshot
+            .values
+            .into_iter()
+            .map(|(key, value)| {
+                let size = entry_size(&key, &value);
+                (key, (value, size))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut entries = self.entries.lock().unwrap();
+        entries.bytes = values.values().map(|(_, size)| size).sum();
+        entries.values = values;
+        entries.version = snapshot.version;
+    }
+
+    fn put(
+        &self,
+        entries: &mut Entries,
+        key: &str,
+        value: Value,
+        size: usize,
+    ) -> Result<(), RunStoreError> {
+        let bytes = self.bytes_with(entries, key, size)?;
+        entries.values.insert(key.to_string(), (value, size));
+        entries.bytes = bytes;
+        entries.version += 1;
+        Ok(())
+    }
+
+    /// Bytes of the store with the key's entry of `size`, which fails if they're over the limit
+    fn bytes_with(
+        &self,
+        entries: &Entries,
+        key: &str,
+        size: usize,
+    ) -> Result<usize, RunStoreError> {
+        let replaced = entries.values.get(key).map_or(0, |(_, size)| *size);
+        let bytes = entries.bytes - replaced + size;
+        if bytes > self.max_bytes {
+            return Err(RunStoreError::TooLarge {
+                key: key.to_string(),
+                bytes,
+                max_bytes: self.max_bytes,
+            });
+        }
+        Ok(bytes)
+    }
+}
+
+fn entry_size(key: &str, value: &Value) -> usize {
+    key.len() + serde_json::to_vec(value).map_or(0, |value| value.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use async_trait::async_trait;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        engine::{task::Action, Engine, Input, NodeError, NodeImpl, RunOutput},
+        pipeline::{context::Context, nodes::Handle},
+        testing::{NoopBehavior, NoopGraph, OfflineServices},
+    };
+
+    use super::*;
+
+    /// Increment the counter of the key, retrying until no other writer got in between
+    fn increment(store: &RunStore, key: &str) {
+        loop {
+            let current = store.get(key);
+            let count = current.as_ref().and_then(Value::as_u64).unwrap_or(0);
+            if store
+                .compare_and_swap(key, current.as_ref(), json!(count + 1))
+                .unwrap()
+            {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_writers_keep_all_writes() {
+        let store = Arc::new(RunStore::with_max_bytes(DEFAULT_MAX_BYTES));
+        let writers = (0..8)
+            .map(|writer| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        store.append_to_array("seen", json!([writer, i])).unwrap();
+                        increment(&store, "count");
+                        store.set("last", json!(writer)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(store.get("count"), Some(json!(800)));
+        assert_eq!(store.get("seen").unwrap().as_array().unwrap().len(), 800);
+        assert!(store.get("last").unwrap().as_u64().unwrap() < 8);
+        assert_eq!(store.version(), 3 * 800);
+    }
+
+    #[test]
+    fn test_store_is_capped() {
+        let store = RunStore::with_max_bytes(24);
+        store.set("key", json!("value")).unwrap();
+        assert!(matches!(
+            store.set("other", json!("too long a value")),
+            Err(RunStoreError::TooLarge { .. })
+        ));
+        // replacing a value frees its bytes
+        store.set("key", json!("another")).unwrap();
+        assert!(matches!(
+            store.append_to_array("key", json!(1)),
+            Err(RunStoreError::NotAnArray(_))
+        ));
+        assert_eq!(store.append_to_array("list", json!(1)).unwrap(), 1);
+        assert!(store.append_to_array("list", json!("too long")).is_err());
+        assert_eq!(store.get("list"), Some(json!([1])));
+
+        let restored = RunStore::with_max_bytes(24);
+        restored.restore(store.snapshot());
+        assert_eq!(restored.snapshot(), store.snapshot());
+        assert!(restored.set("other", json!(1)).is_err());
+    }
+
+    /// Runs the wrapped node after recording its run in the run store
+    struct Record {
+        node: Action,
+    }
+
+    #[async_trait]
+    impl NodeImpl for Record {
+        fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+            self.node.handles_mapping()
+        }
+
+        fn output_handle_id(&self) -> Uuid {
+            self.node.output_handle_id()
+        }
+
+        fn node_name(&self) -> String {
+            self.node.node_name()
+        }
+
+        fn node_id(&self) -> Uuid {
+            self.node.node_id()
+        }
+
+        fn node_type(&self) -> String {
+            self.node.node_type()
+        }
+
+        async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+            context
+                .run_store
+                .append_to_array("ran", json!(self.node_name()))?;
+            tokio::task::yield_now().await;
+            increment(&context.run_store, "count");
+            self.node.run(input, context).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parallel_branches_share_store() {
+        const BRANCHES: usize = 10;
+
+        let mut graph = NoopGraph::default();
+        let input = graph.node(NoopBehavior::Forward);
+        let branches = (0..BRANCHES)
+            .map(|_| {
+                let branch = graph.node(NoopBehavior::Forward);
+                graph.edge(input, branch);
+                graph.id(branch)
+            })
+            .collect::<Vec<_>>();
+        let mut tasks = graph.tasks();
+        for branch_id in branches.iter() {
+            let task = tasks.get_mut(branch_id).unwrap();
+            task.action = Arc::new(Record {
+                node: task.action.clone(),
+            });
+        }
+
+        let services = OfflineServices::default();
+        let mut engine =
+            Engine::with_tasks_and_context(tasks, services.context(), None, None, None);
+        engine.run(None, None, vec![]).await.unwrap();
+
+        let store = engine.run_store();
+        assert_eq!(store.get("count"), Some(json!(BRANCHES)));
+        let mut ran = store
+            .get("ran")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|name| name.as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        ran.sort();
+        ran.dedup();
+        assert_eq!(ran.len(), BRANCHES);
+    }
+}
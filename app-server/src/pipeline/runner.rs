@@ -1,32 +1,60 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use crate::{
     api::v1::traces::RabbitMqSpanMessage,
-    db::trace::Span,
+    ch::exporter::SpanExporter,
+    db::{pipelines::PipelineVersion, trace::Span, workspace::WorkspaceId},
     engine::{engine::EngineOutput, Engine},
     routes::pipelines::GraphInterruptMessage,
-    traces::{OBSERVATIONS_EXCHANGE, OBSERVATIONS_ROUTING_KEY},
+    runs::{
+        checkpoints::{CheckpointStore, RunCheckpoints},
+        node_io::NodeIoStore,
+        replay::ReplayPlan,
+    },
+    traces::{
+        attributes::{
+            LMNR_PIPELINE_ID, LMNR_PIPELINE_VERSION_HASH, LMNR_PIPELINE_VERSION_ID,
+            LMNR_ROUTE_NAME, LMNR_RUN_REPLAY_OF, LMNR_RUN_STORE, LMNR_WORKSPACE_ID,
+        },
+        OBSERVATIONS_EXCHANGE, OBSERVATIONS_ROUTING_KEY,
+    },
 };
 use anyhow::Result;
 use itertools::Itertools;
 use lapin::{options::BasicPublishOptions, BasicProperties, Connection};
 use serde::Serialize;
 use tokio::sync::mpsc::Sender;
+use utoipa::{
+    openapi::{ObjectBuilder, RefOr, Schema, SchemaType},
+    ToSchema,
+};
 use uuid::Uuid;
 
 use crate::{
-    chunk::runner::ChunkerRunner, language_model::LanguageModelRunner,
+    chunk::runner::ChunkerRunner,
+    clock::{Clock, SystemClock},
+    language_model::LanguageModelRunner,
     semantic_search::SemanticSearch,
 };
 
 use super::{
+    compiled::CompiledGraph,
     context::Context,
+    credentials::MissingCredentialsError,
+    diff::GraphDiff,
+    model_defaults,
     nodes::{Message, StreamChunk},
-    trace::{RunTrace, RunTraceStats},
-    utils::parse_graph,
+    prompts,
+    run_store::RunStore,
+    trace::{NodeRunStats, RunTrace, RunTraceStats},
     Graph, GraphError, InvalidSchemasError,
 };
 
+const GRAPH_CACHE_SIZE: u64 = 100;
+
 #[derive(Debug)]
 pub struct RunningError {
     pub partial_trace: EngineOutput,
@@ -37,6 +65,11 @@ pub struct MissingEnvVarsError {
     pub missing_env_vars: HashSet<String>,
 }
 
+#[derive(Debug)]
+pub struct MissingSecretsError {
+    pub missing_secrets: HashSet<String>,
+}
+
 // TODO: this one must serialize `RunTraceRepresentation`, with `node_errors`
 //       set to traces outputs
 impl std::fmt::Display for RunningError {
@@ -51,6 +84,12 @@ impl std::fmt::Display for MissingEnvVarsError {
     }
 }
 
+impl std::fmt::Display for MissingSecretsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.missing_secrets.iter().join(", "))
+    }
+}
+
 impl std::fmt::Display for InvalidSchemasError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -73,6 +112,10 @@ pub enum PipelineRunnerError {
     UnhandledError(#[from] anyhow::Error),
     #[error("Missing env vars: {0}")]
     MissingEnvVarsError(MissingEnvVarsError),
+    #[error("Missing secrets: {0}")]
+    MissingSecretsError(MissingSecretsError),
+    #[error("Missing credentials: {0}")]
+    MissingCredentialsError(MissingCredentialsError),
     #[error("{0}")]
     TraceWritingError(#[from] tokio::sync::mpsc::error::SendError<RunTrace>),
     #[error("Invalid templates: {0}")]
@@ -88,12 +131,39 @@ impl Serialize for PipelineRunnerError {
     }
 }
 
+impl<'s> ToSchema<'s> for PipelineRunnerError {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        (
+            "PipelineRunnerError",
+            RefOr::T(Schema::Object(
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::String)
+                    .description(Some("Error message"))
+                    .build(),
+            )),
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PipelineRunner {
     language_model: Arc<LanguageModelRunner>,
     chunker_runner: Arc<ChunkerRunner>,
     semantic_search: Arc<SemanticSearch>,
-    rabbitmq_connection: Arc<Connection>,
+    /// Shared by the nodes calling external APIs, so that they reuse connections
+    http_client: reqwest::Client,
+    /// Observations of runs are published to it, runners without one don't record observations
+    rabbitmq_connection: Option<Arc<Connection>>,
+    /// Deserialized graphs of COMMIT pipeline versions, keyed by content hash
+    graph_cache: Arc<moka::sync::Cache<String, Graph>>,
+    /// Execution plans of the graphs of COMMIT pipeline versions, keyed by content hash and the
+    /// workspace's model defaults
+    compiled_cache: Arc<moka::sync::Cache<String, Arc<CompiledGraph>>>,
+    node_io_store: Arc<dyn NodeIoStore>,
+    checkpoint_store: Arc<dyn CheckpointStore>,
+    clock: Arc<dyn Clock>,
+    /// Exporter the spans of runs in strict trace mode are recorded with, see `runs::TraceMode`
+    span_exporter: Option<Arc<SpanExporter>>,
 }
 
 impl PipelineRunner {
@@ -101,14 +171,139 @@ impl PipelineRunner {
         language_model: Arc<LanguageModelRunner>,
         chunker_runner: Arc<ChunkerRunner>,
         semantic_search: Arc<SemanticSearch>,
-        rabbitmq_connection: Arc<Connection>,
+        http_client: reqwest::Client,
+        rabbitmq_connection: Option<Arc<Connection>>,
+        node_io_store: Arc<dyn NodeIoStore>,
+        checkpoint_store: Arc<dyn CheckpointStore>,
     ) -> Self {
         Self {
             language_model,
             chunker_runner,
             semantic_search,
+            http_client,
             rabbitmq_connection,
+            graph_cache: Arc::new(moka::sync::Cache::new(GRAPH_CACHE_SIZE)),
+            compiled_cache: Arc::new(moka::sync::Cache::new(GRAPH_CACHE_SIZE)),
+            node_io_store,
+            checkpoint_store,
+            clock: SystemClock::shared(),
+            span_exporter: None,
+        }
+    }
+
+    /// Runner whose runs take the time from the clock, e.g. a `ManualClock` in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Runner which records the spans of runs in strict trace mode with the exporter
+    pub fn with_span_exporter(mut self, span_exporter: Arc<SpanExporter>) -> Self {
+        self.span_exporter = Some(span_exporter);
+        self
+    }
+
+    pub fn span_exporter(&self) -> Option<Arc<SpanExporter>> {
+        self.span_exporter.clone()
+    }
+
+    pub fn language_model(&self) -> Arc<LanguageModelRunner> {
+        self.language_model.clone()
+    }
+
+    /// HTTP client shared by the nodes of runs, see `http_client`
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    /// Store of node I/O of runs with `Graph::record_node_io` set
+    pub fn node_io_store(&self) -> &dyn NodeIoStore {
+        self.node_io_store.as_ref()
+    }
+
+    /// Store of checkpoints of runs which can be paused
+    pub fn checkpoint_store(&self) -> Arc<dyn CheckpointStore> {
+        self.checkpoint_store.clone()
+    }
+
+    /// Get the runnable graph of a pipeline version
+    ///
+    /// COMMIT versions are immutable, so their graphs are deserialized once per content hash.
+    pub fn get_version_graph(
+        &self,
+        pipeline_version: &PipelineVersion,
+    ) -> Result<Graph, serde_json::Error> {
+        let Some(content_hash) = &pipeline_version.content_hash else {
+            return serde_json::from_value::<Graph>(pipeline_version.runnable_graph.clone());
+        };
+
+        if let Some(graph) = self.graph_cache.get(content_hash) {
+            return Ok(graph);
         }
+        let mut graph = serde_json::from_value::<Graph>(pipeline_version.runnable_graph.clone())?;
+        graph.content_hash = Some(content_hash.clone());
+        self.graph_cache.insert(content_hash.clone(), graph.clone());
+        Ok(graph)
+    }
+
+    /// Get the execution plan of the graph
+    ///
+    /// Plans of graphs of COMMIT versions are compiled once per content hash, the least recently
+    /// used ones are evicted once `GRAPH_CACHE_SIZE` versions are cached.
+    fn get_compiled_graph(&self, graph: &Graph) -> Result<Arc<CompiledGraph>, PipelineRunnerError> {
+        let Some(content_hash) = &graph.content_hash else {
+            return Ok(Arc::new(CompiledGraph::compile(graph)?));
+        };
+
+        let key = prompts::compiled_cache_key(
+            model_defaults::compiled_cache_key(content_hash, graph),
+            graph,
+        );
+        if let Some(compiled) = self.compiled_cache.get(&key) {
+            return Ok(compiled);
+        }
+        let compiled = Arc::new(CompiledGraph::compile(graph)?);
+        self.compiled_cache.insert(key, compiled.clone());
+        Ok(compiled)
+    }
+
+    /// Graphviz DOT of the version's graph, overlaid with the node stats of a finished run of it
+    pub fn version_dot(
+        &self,
+        pipeline_version: &PipelineVersion,
+        node_stats: Option<&[NodeRunStats]>,
+    ) -> Result<String, PipelineRunnerError> {
+        let graph = self.get_version_graph(pipeline_version)?;
+        Ok(self.get_compiled_graph(&graph)?.to_dot(node_stats))
+    }
+
+    /// Changes of the graph of the `to` version from the `from` one
+    pub fn diff_versions(
+        &self,
+        from: &PipelineVersion,
+        to: &PipelineVersion,
+    ) -> Result<GraphDiff, PipelineRunnerError> {
+        let from = self.get_compiled_graph(&self.get_version_graph(from)?)?;
+        let to = self.get_compiled_graph(&self.get_version_graph(to)?)?;
+        Ok(from.diff(&to))
+    }
+
+    /// Check that the graph is valid, and that all env vars, secrets and credentials it
+    /// references are set
+    pub fn check_graph_values(&self, graph: &Graph) -> Result<(), PipelineRunnerError> {
+        let compiled = self.get_compiled_graph(graph)?;
+        compiled.check_values(&graph.env, &graph.secrets)?;
+        compiled.resolve_credentials(&graph.credentials, &graph.secrets)?;
+        Ok(())
+    }
+
+    /// Whether the graph has loops, whose runs from the run queue are checkpointed
+    pub fn has_cycles(&self, graph: &Graph) -> Result<bool, PipelineRunnerError> {
+        Ok(self.get_compiled_graph(graph)?.has_cycles())
     }
 
     pub async fn run(
@@ -116,32 +311,139 @@ impl PipelineRunner {
         graph: Graph,
         stream_send: Option<Sender<StreamChunk>>,
     ) -> Result<EngineOutput, PipelineRunnerError> {
-        let missing_env_vars = graph.get_missing_env_vars();
-        if !missing_env_vars.is_empty() {
-            return Err(PipelineRunnerError::MissingEnvVarsError(
-                MissingEnvVarsError { missing_env_vars },
-            ));
-        }
+        self.run_with_interrupt(graph, stream_send, None).await
+    }
 
-        let validated_schemas = graph.validate_baml_schemas()?;
+    /// Run the graph, which can be cancelled by sending `GraphInterruptMessage::Cancel`
+    pub async fn run_with_interrupt(
+        &self,
+        graph: Graph,
+        stream_send: Option<Sender<StreamChunk>>,
+        interrupt_recv: Option<tokio::sync::mpsc::Receiver<GraphInterruptMessage>>,
+    ) -> Result<EngineOutput, PipelineRunnerError> {
+        self.run_with_checkpoints(graph, stream_send, interrupt_recv, None)
+            .await
+    }
+
+    /// Run the graph, checkpointing it, or resuming it from the checkpoint of an engine which
+    /// stopped, see [`checkpoints`](crate::runs::checkpoints)
+    pub async fn run_with_checkpoints(
+        &self,
+        graph: Graph,
+        stream_send: Option<Sender<StreamChunk>>,
+        interrupt_recv: Option<tokio::sync::mpsc::Receiver<GraphInterruptMessage>>,
+        checkpoints: Option<RunCheckpoints>,
+    ) -> Result<EngineOutput, PipelineRunnerError> {
+        let compiled = self.get_compiled_graph(&graph)?;
+        self.run_graph(
+            graph,
+            compiled,
+            stream_send,
+            interrupt_recv,
+            None,
+            checkpoints,
+        )
+        .await
+    }
+
+    /// Run the graph with its plan compiled beforehand, e.g. of a pipeline loaded from a file
+    ///
+    /// The run keeps the plan, so that it isn't affected by the file changing during the run.
+    pub async fn run_compiled(
+        &self,
+        graph: Graph,
+        compiled: Arc<CompiledGraph>,
+        stream_send: Option<Sender<StreamChunk>>,
+    ) -> Result<EngineOutput, PipelineRunnerError> {
+        self.run_graph(graph, compiled, stream_send, None, None, None)
+            .await
+    }
+
+    /// Replay a recorded run, executing only the tasks the plan starts from and their descendants
+    pub async fn run_replay(
+        &self,
+        graph: Graph,
+        plan: ReplayPlan,
+        stream_send: Option<Sender<StreamChunk>>,
+        interrupt_recv: Option<tokio::sync::mpsc::Receiver<GraphInterruptMessage>>,
+    ) -> Result<EngineOutput, PipelineRunnerError> {
+        let compiled = self.get_compiled_graph(&graph)?;
+        self.run_graph(
+            graph,
+            compiled,
+            stream_send,
+            interrupt_recv,
+            Some(plan),
+            None,
+        )
+        .await
+    }
+
+    async fn run_graph(
+        &self,
+        graph: Graph,
+        compiled: Arc<CompiledGraph>,
+        stream_send: Option<Sender<StreamChunk>>,
+        interrupt_recv: Option<tokio::sync::mpsc::Receiver<GraphInterruptMessage>>,
+        replay: Option<ReplayPlan>,
+        checkpoints: Option<RunCheckpoints>,
+    ) -> Result<EngineOutput, PipelineRunnerError> {
+        compiled.check_values(&graph.env, &graph.secrets)?;
+        let credentials = compiled.resolve_credentials(&graph.credentials, &graph.secrets)?;
+        let tasks = compiled.instantiate(&graph)?;
+        let record_node_io = graph.record_node_io;
+        let trace_run_store = graph.trace_run_store;
 
         let context = Context {
             language_model: self.language_model.clone(),
             chunker_runner: self.chunker_runner.clone(),
             semantic_search: self.semantic_search.clone(),
-            env: graph.env.clone(),
+            http_client: self.http_client.clone(),
+            env: graph.env,
+            secrets: graph.secrets,
+            credentials,
             tx: stream_send.clone(),
-            metadata: graph.metadata.clone(),
-            run_type: graph.run_type.clone(),
+            metadata: graph.metadata,
+            run_type: graph.run_type,
             pipeline_runner: self.clone(),
-            baml_schemas: validated_schemas,
+            baml_schemas: compiled.baml_schemas(),
+            workspace_model_defaults: graph.workspace_model_defaults,
+            workspace_id: graph.workspace_id,
+            prompts: graph.prompts,
+            clock: self.clock.clone(),
+            run_store: Arc::new(RunStore::default()),
         };
 
-        let tasks = parse_graph(graph)?;
-
         let mut engine = Engine::with_tasks_and_context(tasks, context, None, None, None);
+        engine.bind_outputs(compiled.output_bindings());
+        if record_node_io {
+            engine.record_task_inputs();
+        }
+        if trace_run_store {
+            engine.trace_run_store();
+        }
+        // runs recording their node I/O or checkpoints persist the values of all messages
+        if !record_node_io && checkpoints.is_none() {
+            engine.compact_messages(compiled.traced_node_ids());
+        }
+        let mut start_task_ids = match replay {
+            Some(plan) => {
+                engine.seed(plan.inputs, plan.outputs);
+                plan.start_task_ids
+            }
+            None => vec![],
+        };
+        if let Some(checkpoints) = checkpoints {
+            match checkpoint_engine(&mut engine, checkpoints, start_task_ids)? {
+                Some(task_ids) => start_task_ids = task_ids,
+                None => return Ok(engine.get_outputs()),
+            }
+        }
 
-        match engine.run(stream_send, None, None).await {
+        match engine
+            .run(stream_send, interrupt_recv, start_task_ids)
+            .await
+        {
             Ok(result) => Ok(result),
             Err(errors) => Err(PipelineRunnerError::RunningError(RunningError {
                 partial_trace: errors,
@@ -149,6 +451,8 @@ impl PipelineRunner {
         }
     }
 
+    /// Run the graph in the workshop, or resume it from the checkpoint it was paused at
+    #[allow(clippy::too_many_arguments)]
     pub async fn run_workshop(
         &self,
         graph: Graph,
@@ -157,30 +461,34 @@ impl PipelineRunner {
         start_task_id: Option<Uuid>,
         breakpoint_task_ids: Option<Vec<Uuid>>,
         interrupt_recv: tokio::sync::mpsc::Receiver<GraphInterruptMessage>,
+        checkpoints: Option<RunCheckpoints>,
     ) -> Result<EngineOutput, PipelineRunnerError> {
-        let missing_env_vars = graph.get_missing_env_vars();
-        if !missing_env_vars.is_empty() {
-            return Err(PipelineRunnerError::MissingEnvVarsError(
-                MissingEnvVarsError { missing_env_vars },
-            ));
-        }
-
-        let validated_schemas = graph.validate_baml_schemas()?;
+        let compiled = self.get_compiled_graph(&graph)?;
+        compiled.check_values(&graph.env, &graph.secrets)?;
+        let credentials = compiled.resolve_credentials(&graph.credentials, &graph.secrets)?;
+        let tasks = compiled.instantiate(&graph)?;
+        let trace_run_store = graph.trace_run_store;
 
         let context = Context {
             language_model: self.language_model.clone(),
             chunker_runner: self.chunker_runner.clone(),
             semantic_search: self.semantic_search.clone(),
-            env: graph.env.clone(),
+            http_client: self.http_client.clone(),
+            env: graph.env,
+            secrets: graph.secrets,
+            credentials,
             tx: stream_send.clone(),
-            metadata: graph.metadata.clone(),
-            run_type: graph.run_type.clone(),
+            metadata: graph.metadata,
+            run_type: graph.run_type,
             pipeline_runner: self.clone(),
-            baml_schemas: validated_schemas,
+            baml_schemas: compiled.baml_schemas(),
+            workspace_model_defaults: graph.workspace_model_defaults,
+            workspace_id: graph.workspace_id,
+            prompts: graph.prompts,
+            clock: self.clock.clone(),
+            run_store: Arc::new(RunStore::default()),
         };
 
-        let tasks = parse_graph(graph)?;
-
         let mut engine = Engine::with_tasks_and_context(
             tasks,
             context,
@@ -188,9 +496,20 @@ impl PipelineRunner {
             start_task_id,
             breakpoint_task_ids,
         );
+        engine.bind_outputs(compiled.output_bindings());
+        if trace_run_store {
+            engine.trace_run_store();
+        }
+        let mut start_task_ids = start_task_id.into_iter().collect::<Vec<_>>();
+        if let Some(checkpoints) = checkpoints {
+            match checkpoint_engine(&mut engine, checkpoints, start_task_ids)? {
+                Some(task_ids) => start_task_ids = task_ids,
+                None => return Ok(engine.get_outputs()),
+            }
+        }
 
         match engine
-            .run(stream_send, Some(interrupt_recv), start_task_id)
+            .run(stream_send, Some(interrupt_recv), start_task_ids)
             .await
         {
             Ok(result) => Ok(result),
@@ -200,62 +519,38 @@ impl PipelineRunner {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn record_observations(
         &self,
         run_output: &Result<EngineOutput, PipelineRunnerError>,
         project_id: &Uuid,
-        pipeline_version_name: &String,
+        workspace_id: Option<WorkspaceId>,
+        pipeline_version: &PipelineVersion,
         parent_span_id: Option<Uuid>,
         trace_id: Option<Uuid>,
+        replay_of: Option<Uuid>,
+        secrets: &HashMap<String, String>,
     ) -> Result<()> {
-        let engine_output = match run_output {
-            Ok(engine_output) => engine_output,
-            Err(PipelineRunnerError::RunningError(e)) => &e.partial_trace,
-            _ => return Ok(()), // nothing to record
+        let Some(rabbitmq_connection) = &self.rabbitmq_connection else {
+            return Ok(());
         };
-        let run_stats = RunTraceStats::from_messages(&engine_output.messages);
-        let parent_span = Span::create_parent_span_in_run_trace(
-            trace_id.unwrap_or_else(Uuid::new_v4),
-            &run_stats,
+        let messages = Self::run_span_messages(
+            run_output,
+            project_id,
+            workspace_id,
+            pipeline_version,
             parent_span_id,
-            pipeline_version_name,
-        );
-
-        let message_spans = Span::from_messages(
-            &engine_output.messages,
-            parent_span.trace_id,
-            parent_span.span_id,
+            trace_id,
+            replay_of,
+            secrets,
         );
-        let parent_span_mq_message = RabbitMqSpanMessage {
-            project_id: *project_id,
-            span: parent_span,
-            events: vec![],
-            evaluate_events: vec![],
-        };
-
-        let channel = self.rabbitmq_connection.create_channel().await?;
-        let payload = serde_json::to_string(&parent_span_mq_message)?;
-        let payload = payload.as_bytes();
-        channel
-            .basic_publish(
-                OBSERVATIONS_EXCHANGE,
-                OBSERVATIONS_ROUTING_KEY,
-                BasicPublishOptions::default(),
-                payload,
-                BasicProperties::default(),
-            )
-            .await?
-            .await?;
-
-        for message_span in message_spans {
-            let message_mq_message = RabbitMqSpanMessage {
-                project_id: *project_id,
-                span: message_span,
-                events: vec![],
-                evaluate_events: vec![],
-            };
+        if messages.is_empty() {
+            return Ok(()); // nothing to record
+        }
 
-            let payload = serde_json::to_string(&message_mq_message)?;
+        let channel = rabbitmq_connection.create_channel().await?;
+        for message in messages {
+            let payload = serde_json::to_string(&message)?;
             let payload = payload.as_bytes();
             channel
                 .basic_publish(
@@ -272,6 +567,69 @@ impl PipelineRunner {
         Ok(())
     }
 
+    /// Spans of the run's trace, the parent span of the run first, none if it didn't start
+    #[allow(clippy::too_many_arguments)]
+    pub fn run_span_messages(
+        run_output: &Result<EngineOutput, PipelineRunnerError>,
+        project_id: &Uuid,
+        workspace_id: Option<WorkspaceId>,
+        pipeline_version: &PipelineVersion,
+        parent_span_id: Option<Uuid>,
+        trace_id: Option<Uuid>,
+        replay_of: Option<Uuid>,
+        secrets: &HashMap<String, String>,
+    ) -> Vec<RabbitMqSpanMessage> {
+        let engine_output = match run_output {
+            Ok(engine_output) => engine_output,
+            Err(PipelineRunnerError::RunningError(e)) => &e.partial_trace,
+            _ => return Vec::new(),
+        };
+        let run_stats = RunTraceStats::from_messages(&engine_output.messages);
+        let mut parent_span = Span::create_parent_span_in_run_trace(
+            trace_id.unwrap_or_else(Uuid::new_v4),
+            &run_stats,
+            parent_span_id,
+            &pipeline_version.name,
+        );
+        parent_span.attributes = serde_json::json!({
+            LMNR_PIPELINE_VERSION_ID: pipeline_version.id,
+            LMNR_PIPELINE_VERSION_HASH: pipeline_version.content_hash,
+        });
+        if let Some(workspace_id) = workspace_id {
+            parent_span.attributes[LMNR_WORKSPACE_ID] = serde_json::json!(workspace_id);
+        }
+        if let Some(replay_of) = replay_of {
+            parent_span.attributes[LMNR_RUN_REPLAY_OF] = serde_json::json!(replay_of);
+        }
+        if let Some(run_store) = &engine_output.run_store {
+            parent_span.attributes[LMNR_RUN_STORE] = serde_json::json!(run_store);
+            parent_span.scrub_secrets(secrets);
+        }
+
+        let mut message_spans = Span::from_messages(
+            &engine_output.messages,
+            parent_span.trace_id,
+            parent_span.span_id,
+        );
+        message_spans.iter_mut().for_each(|span| {
+            span.scrub_secrets(secrets);
+            // routes are counted by pipeline, across its versions
+            if span.attributes.get(LMNR_ROUTE_NAME).is_some() {
+                span.attributes[LMNR_PIPELINE_ID] = serde_json::json!(pipeline_version.pipeline_id);
+            }
+        });
+
+        std::iter::once(parent_span)
+            .chain(message_spans)
+            .map(|span| RabbitMqSpanMessage {
+                project_id: *project_id,
+                span,
+                events: vec![],
+                evaluate_events: vec![],
+            })
+            .collect()
+    }
+
     pub fn get_trace_from_result(
         res: &Result<EngineOutput, PipelineRunnerError>,
     ) -> Option<EngineOutput> {
@@ -282,3 +640,30 @@ impl PipelineRunner {
         }
     }
 }
+
+/// Send the engine's snapshots to the checkpoints, and restore the run from the checkpoint it's
+/// resumed from. Returns the tasks to start from, None if the run was stopped at its last task.
+fn checkpoint_engine(
+    engine: &mut Engine,
+    checkpoints: RunCheckpoints,
+    start_task_ids: Vec<Uuid>,
+) -> Result<Option<Vec<Uuid>>> {
+    let first_seq = checkpoints
+        .resume_from
+        .as_ref()
+        .map_or(0, |checkpoint| checkpoint.next_seq());
+    // restored first, so that only later writes are checkpointed again
+    if let Some(run_store) = checkpoints
+        .resume_from
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.run_store.clone())
+    {
+        engine.run_store().restore(run_store);
+    }
+    engine.record_checkpoints(checkpoints.events, first_seq);
+    let Some(checkpoint) = checkpoints.resume_from else {
+        return Ok(Some(start_task_ids));
+    };
+    let start_task_ids = engine.restore(checkpoint.snapshots, checkpoint.paused_task_id)?;
+    Ok((!start_task_ids.is_empty()).then_some(start_task_ids))
+}
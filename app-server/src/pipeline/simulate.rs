@@ -0,0 +1,769 @@
+//! Simulated runs of a pipeline, which estimate its cost and latency without calling providers
+//!
+//! The graph is walked in the order its nodes run, with the inputs of a representative run.
+//! Templates and prompts are rendered with the values known so far, and the prompt tokens of
+//! LLM calls are counted with the tokenizer of their model. Completions aren't generated: each
+//! LLM node is assumed to complete with the p50 and p90 tokens of its completion lengths, and
+//! nodes downstream of it count the p50 tokens without knowing their text. Costs are priced by
+//! the providers of the language model runner, and latencies are the p50s of the project's LLM
+//! spans by model, if it has any.
+//!
+//! Every route of a switch is simulated, weighted by its probability, uniform unless supplied.
+//! Nodes are weighted by the probability they're reached, so the cost of a run is the expected
+//! one, and its latency is the one of the slowest path which may be taken. Loops are simulated
+//! once, subpipelines with their inputs, and maps once per item of their input list, if it's
+//! known. Structured output instructions and their retries aren't counted.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::language_model::{
+    context_window::TokenCounter, ChatMessage, ChatMessageContent, ExecuteChatCompletion,
+    LanguageModelProviderName, LanguageModelRunner,
+};
+
+use super::{
+    model_defaults,
+    nodes::{Node, NodeInput},
+    prompts, Graph,
+};
+
+const DEFAULT_COMPLETION_TOKENS_P50: u32 = 256;
+const DEFAULT_COMPLETION_TOKENS_P90: u32 = 1024;
+
+/// Distribution of the completion tokens of an LLM node, capped by its `max_tokens`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct CompletionLengths {
+    pub p50: u32,
+    pub p90: u32,
+}
+
+impl Default for CompletionLengths {
+    fn default() -> Self {
+        Self {
+            p50: DEFAULT_COMPLETION_TOKENS_P50,
+            p90: DEFAULT_COMPLETION_TOKENS_P90,
+        }
+    }
+}
+
+impl CompletionLengths {
+    fn capped(self, max_tokens: Option<u32>) -> Self {
+        match max_tokens {
+            Some(max_tokens) => Self {
+                p50: self.p50.min(max_tokens),
+                p90: self.p90.min(max_tokens),
+            },
+            None => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationConfig {
+    /// Inputs of a representative run by input node name, inputs which aren't set count no tokens
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub inputs: HashMap<String, NodeInput>,
+    /// Completion lengths of LLM nodes by node name
+    #[serde(default)]
+    pub completion_tokens: HashMap<String, CompletionLengths>,
+    /// Completion lengths of the LLM nodes which aren't in `completionTokens`
+    #[serde(default)]
+    pub default_completion_tokens: CompletionLengths,
+    /// Relative probabilities of the routes of switch nodes, by node name and route name. Routes
+    /// which aren't set are never taken, the routes of switches which aren't set are uniform.
+    #[serde(default)]
+    pub branch_probabilities: HashMap<String, HashMap<String, f64>>,
+}
+
+impl SimulationConfig {
+    pub fn validate(&self) -> Result<()> {
+        let mut lengths = self
+            .completion_tokens
+            .values()
+            .chain(std::iter::once(&self.default_completion_tokens));
+        if lengths.any(|lengths| lengths.p90 < lengths.p50) {
+            return Err(anyhow::anyhow!(
+                "p90 of completion tokens must be at least their p50"
+            ));
+        }
+        for (node_name, probabilities) in &self.branch_probabilities {
+            if probabilities.values().any(|p| !p.is_finite() || *p < 0.0)
+                || probabilities.values().sum::<f64>() <= 0.0
+            {
+                return Err(anyhow::anyhow!(
+                    "Branch probabilities of {} must be non-negative, and not all zero",
+                    node_name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Estimate at the p50 and p90 of the completion lengths
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, ToSchema)]
+pub struct Estimate {
+    pub p50: f64,
+    pub p90: f64,
+}
+
+impl Estimate {
+    fn add(&mut self, other: Estimate, weight: f64) {
+        self.p50 += other.p50 * weight;
+        self.p90 += other.p90 * weight;
+    }
+
+    fn times(self, factor: f64) -> Self {
+        Self {
+            p50: self.p50 * factor,
+            p90: self.p90 * factor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeSimulation {
+    pub node_id: Uuid,
+    pub node_name: String,
+    pub node_type: String,
+    /// Probability the node runs in a run
+    pub probability: f64,
+    /// Model of LLM nodes, unset if it's taken from the `model` input
+    pub model: Option<String>,
+    /// Prompt tokens of the node's calls, of all calls of subpipelines and maps
+    pub prompt_tokens: u32,
+    pub completion_tokens: Estimate,
+    /// Cost of the node when it runs, unset if it makes calls which aren't priced
+    pub cost: Option<Estimate>,
+    /// p50 latency of the node when it runs, unset if it's not known for its model
+    pub latency_seconds: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationReport {
+    /// Nodes in the order they run
+    pub nodes: Vec<NodeSimulation>,
+    /// Expected prompt tokens of a run
+    pub prompt_tokens: f64,
+    pub completion_tokens: Estimate,
+    /// Expected cost of a run, of its priced calls
+    pub cost: Estimate,
+    /// p50 latency of the slowest path of a run, without the nodes whose latency isn't known
+    pub latency_seconds: f64,
+    /// Runs the cost is multiplied by, e.g. the rows of a dataset
+    pub row_count: u64,
+    /// `cost` of all runs
+    pub total_cost: Estimate,
+    /// Nodes which make calls which aren't priced, so that the cost is too low
+    pub unpriced_nodes: Vec<String>,
+    /// LLM nodes without historical latencies, so that the latency is too low
+    pub nodes_without_latency: Vec<String>,
+}
+
+/// Prices and latencies the calls of the simulated nodes are estimated with
+pub struct Estimator<'a> {
+    pub language_model: &'a LanguageModelRunner,
+    /// p50 latency in seconds by model name, without the provider
+    pub latencies: &'a HashMap<String, f64>,
+}
+
+/// Simulate a run of the graph, and estimate `row_count` of them
+pub fn simulate(
+    graph: &Graph,
+    config: &SimulationConfig,
+    estimator: &Estimator,
+    row_count: u64,
+) -> Result<SimulationReport> {
+    let simulated = simulate_graph(graph, &config.inputs, config, estimator)?;
+
+    let mut prompt_tokens = 0.0;
+    let mut completion_tokens = Estimate::default();
+    let mut cost = Estimate::default();
+    for node in &simulated.nodes {
+        prompt_tokens += node.prompt_tokens as f64 * node.probability;
+        completion_tokens.add(node.completion_tokens, node.probability);
+        if let Some(node_cost) = node.cost {
+            cost.add(node_cost, node.probability);
+        }
+    }
+    let unpriced_nodes = simulated
+        .nodes
+        .iter()
+        .filter(|node| node.cost.is_none())
+        .map(|node| node.node_name.clone())
+        .collect();
+
+    Ok(SimulationReport {
+        prompt_tokens,
+        completion_tokens,
+        cost,
+        latency_seconds: simulated.latency_seconds,
+        row_count,
+        total_cost: cost.times(row_count as f64),
+        unpriced_nodes,
+        nodes_without_latency: simulated.nodes_without_latency,
+        nodes: simulated.nodes,
+    })
+}
+
+/// Value of a node's output, as far as the simulation knows it
+#[derive(Debug, Clone, Default)]
+struct SimValue {
+    /// Known value, e.g. a run input or a template rendered with known values
+    known: Option<NodeInput>,
+    /// Tokens of the value which aren't known as text, e.g. of completions
+    unknown_tokens: u32,
+}
+
+/// State of a node once it's simulated
+#[derive(Debug, Clone)]
+struct Reached {
+    value: SimValue,
+    probability: f64,
+    /// Probabilities of the routes of switches, which condition nodes pass values on
+    routes: Option<BTreeMap<String, f64>>,
+    /// p50 seconds from the start of the run until the node finishes
+    finished_at: f64,
+}
+
+struct SimulatedGraph {
+    nodes: Vec<NodeSimulation>,
+    /// Value of the graph's output node, if there's exactly one
+    output: Option<SimValue>,
+    latency_seconds: f64,
+    nodes_without_latency: Vec<String>,
+}
+
+/// Cost, tokens and latency of the calls of one node, or of the graph of a subpipeline or map
+#[derive(Default)]
+struct Calls {
+    model: Option<String>,
+    prompt_tokens: u32,
+    completion_tokens: Estimate,
+    cost: Option<Estimate>,
+    latency_seconds: Option<f64>,
+    output: SimValue,
+}
+
+fn simulate_graph(
+    graph: &Graph,
+    inputs: &HashMap<String, NodeInput>,
+    config: &SimulationConfig,
+    estimator: &Estimator,
+) -> Result<SimulatedGraph> {
+    let graph = prompts::resolve_graph(graph)?;
+    let graph = model_defaults::resolve_graph(&graph);
+    let graph = graph.as_ref();
+
+    let nodes = graph
+        .nodes
+        .values()
+        .map(|node| (node.id(), node))
+        .collect::<HashMap<_, _>>();
+    // output handles of the nodes which are predecessors, the others may have none
+    let handle_nodes = graph
+        .pred
+        .values()
+        .flatten()
+        .filter_map(|id| nodes.get(id))
+        .map(|node| (node.implementation().output_handle_id(), node.id()))
+        .collect::<HashMap<_, _>>();
+
+    let mut reached = HashMap::<Uuid, Reached>::new();
+    let mut simulations = Vec::new();
+    let mut nodes_without_latency = Vec::new();
+    let mut output = None;
+    let mut output_count = 0;
+    for id in run_order(graph, &nodes, &handle_nodes)? {
+        let node = nodes[&id];
+        // values of the inputs by handle name, cyclic ones aren't simulated yet on the first pass
+        let node_inputs = node
+            .implementation()
+            .handles_mapping()
+            .into_iter()
+            .filter_map(|(from_handle, handle)| {
+                let from = reached.get(handle_nodes.get(&from_handle)?)?;
+                Some((handle.name_force(), from))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut probability = node_inputs
+            .values()
+            .map(|input| input.probability)
+            .reduce(f64::min)
+            .unwrap_or(1.0);
+        let started_at = node_inputs
+            .values()
+            .map(|input| input.finished_at)
+            .fold(0.0, f64::max);
+        let known_inputs = node_inputs
+            .iter()
+            .filter_map(|(name, input)| Some((name.clone(), input.value.known.clone()?)))
+            .collect::<HashMap<_, _>>();
+        let unknown_tokens = node_inputs
+            .values()
+            .map(|input| input.value.unknown_tokens)
+            .sum::<u32>();
+        let single_input = (node_inputs.len() == 1)
+            .then(|| node_inputs.values().next().unwrap())
+            .map(|input| input.value.clone());
+        let mut routes = None;
+
+        let calls = match node {
+            Node::Input(input_node) => Calls {
+                output: SimValue {
+                    known: inputs.get(&input_node.name).cloned(),
+                    unknown_tokens: 0,
+                },
+                ..Default::default()
+            },
+            Node::StringTemplate(template_node) => Calls {
+                output: SimValue {
+                    known: Some(NodeInput::String(
+                        template_node
+                            .compiled_text
+                            .render(&template_node.text, &known_inputs),
+                    )),
+                    unknown_tokens,
+                },
+                ..Default::default()
+            },
+            Node::LLM(llm_node) => {
+                let prompt = llm_node
+                    .compiled_prompt
+                    .render(&llm_node.prompt, &known_inputs);
+                let mut messages = vec![ChatMessage {
+                    role: String::from("system"),
+                    content: ChatMessageContent::Text(prompt),
+                }];
+                if let Some(NodeInput::ChatMessageList(chat)) = known_inputs.get("chat_messages") {
+                    messages.extend(chat.iter().cloned());
+                }
+                let model = llm_node
+                    .model
+                    .clone()
+                    .or_else(|| match known_inputs.get("model") {
+                        Some(NodeInput::String(model)) => Some(model.clone()),
+                        _ => None,
+                    });
+                let max_tokens = llm_node
+                    .model_params
+                    .as_deref()
+                    .and_then(|params| serde_json::from_str::<Value>(params).ok())
+                    .and_then(|params| params["max_tokens"].as_u64())
+                    .map(|max_tokens| max_tokens.min(u32::MAX as u64) as u32);
+                let lengths = config
+                    .completion_tokens
+                    .get(&llm_node.name)
+                    .copied()
+                    .unwrap_or(config.default_completion_tokens)
+                    .capped(max_tokens);
+                let calls = estimate_call(model, &messages, unknown_tokens, lengths, estimator)?;
+                if calls.latency_seconds.is_none() {
+                    nodes_without_latency.push(llm_node.name.clone());
+                }
+                calls
+            }
+            Node::Switch(switch_node) => {
+                routes = Some(route_probabilities(
+                    &switch_node.name,
+                    switch_node.route_names(),
+                    config,
+                ));
+                Calls {
+                    output: node_inputs
+                        .get("input")
+                        .map(|input| input.value.clone())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                }
+            }
+            Node::SemanticSwitch(switch_node) => {
+                routes = Some(route_probabilities(
+                    &switch_node.name,
+                    switch_node.routes.iter().map(|route| route.name.as_str()),
+                    config,
+                ));
+                Calls {
+                    output: single_input.unwrap_or_default(),
+                    ..Default::default()
+                }
+            }
+            Node::Condition(condition_node) => {
+                if let Some(routes) = node_inputs.values().find_map(|input| input.routes.as_ref()) {
+                    probability *= routes
+                        .get(&condition_node.condition)
+                        .copied()
+                        .unwrap_or(0.0);
+                }
+                Calls {
+                    output: single_input.unwrap_or_default(),
+                    ..Default::default()
+                }
+            }
+            Node::Subpipeline(subpipeline_node) => {
+                let subgraph = subgraph_of(graph, &subpipeline_node.runnable_graph)?;
+                let simulated = simulate_graph(&subgraph, &known_inputs, config, estimator)?;
+                nodes_without_latency.extend(simulated.nodes_without_latency.iter().cloned());
+                Calls::of_subgraph(&simulated)
+            }
+            Node::Map(map_node) => {
+                let subgraph = subgraph_of(graph, &map_node.runnable_graph)?;
+                let items = match known_inputs.get("inputs") {
+                    Some(NodeInput::StringList(items)) => items.clone(),
+                    Some(NodeInput::String(items)) => {
+                        serde_json::from_str::<Vec<String>>(items).unwrap_or_default()
+                    }
+                    _ => Vec::new(),
+                };
+                let input_name = subgraph.get_input_node_names().into_iter().next();
+                // an unknown list is simulated as a single item without text
+                let item_inputs: Vec<HashMap<String, NodeInput>> = if items.is_empty() {
+                    vec![HashMap::new()]
+                } else {
+                    items
+                        .into_iter()
+                        .map(|item| {
+                            input_name
+                                .iter()
+                                .map(|name| (name.clone(), NodeInput::String(item.clone())))
+                                .collect()
+                        })
+                        .collect()
+                };
+                let mut simulated_items = Vec::new();
+                for item_inputs in &item_inputs {
+                    simulated_items.push(simulate_graph(
+                        &subgraph,
+                        item_inputs,
+                        config,
+                        estimator,
+                    )?);
+                }
+                if let Some(first) = simulated_items.first() {
+                    nodes_without_latency.extend(first.nodes_without_latency.iter().cloned());
+                }
+                Calls::of_items(&simulated_items)
+            }
+            Node::Output(_)
+            | Node::Error(_)
+            | Node::Extractor(_)
+            | Node::JsonExtractor(_)
+            | Node::FormatValidator(_)
+            | Node::Zenguard(_)
+            | Node::SemanticSearch(_)
+            | Node::SemanticSimilarity(_)
+            | Node::RunStore(_)
+            | Node::Custom(_) => Calls {
+                output: single_input.unwrap_or(SimValue {
+                    known: None,
+                    unknown_tokens,
+                }),
+                ..Default::default()
+            },
+        };
+
+        if let Node::Output(_) = node {
+            output_count += 1;
+            output = Some(calls.output.clone());
+        }
+        let is_call = matches!(node, Node::LLM(_) | Node::Subpipeline(_) | Node::Map(_));
+        if is_call {
+            simulations.push(NodeSimulation {
+                node_id: id,
+                node_name: node.name(),
+                node_type: node.node_type(),
+                probability,
+                model: calls.model.clone(),
+                prompt_tokens: calls.prompt_tokens,
+                completion_tokens: calls.completion_tokens,
+                cost: calls.cost,
+                latency_seconds: calls.latency_seconds,
+            });
+        }
+        // switches pass routes on, the conditions after them take one
+        let routes = routes.or_else(|| match node {
+            Node::Condition(_) => None,
+            _ => node_inputs.values().find_map(|input| input.routes.clone()),
+        });
+        reached.insert(
+            id,
+            Reached {
+                value: calls.output,
+                probability,
+                routes,
+                finished_at: started_at + calls.latency_seconds.unwrap_or(0.0),
+            },
+        );
+    }
+
+    let latency_seconds = reached
+        .values()
+        .filter(|reached| reached.probability > 0.0)
+        .map(|reached| reached.finished_at)
+        .fold(0.0, f64::max);
+    Ok(SimulatedGraph {
+        nodes: simulations,
+        output: (output_count == 1).then_some(output).flatten(),
+        latency_seconds,
+        nodes_without_latency,
+    })
+}
+
+impl Calls {
+    /// Calls of a run of a simulated subgraph
+    fn of_subgraph(simulated: &SimulatedGraph) -> Self {
+        let mut calls = Calls {
+            cost: Some(Estimate::default()),
+            latency_seconds: Some(simulated.latency_seconds),
+            output: simulated.output.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+        for node in &simulated.nodes {
+            calls.prompt_tokens += (node.prompt_tokens as f64 * node.probability).round() as u32;
+            calls
+                .completion_tokens
+                .add(node.completion_tokens, node.probability);
+            calls.cost = match (calls.cost, node.cost) {
+                (Some(mut cost), Some(node_cost)) => {
+                    cost.add(node_cost, node.probability);
+                    Some(cost)
+                }
+                _ => None,
+            };
+        }
+        calls
+    }
+
+    /// Calls of the items of a map, whose runs are concurrent
+    fn of_items(items: &[SimulatedGraph]) -> Self {
+        let mut calls = Calls {
+            cost: Some(Estimate::default()),
+            ..Default::default()
+        };
+        let mut latency_seconds = 0.0;
+        let mut output_tokens = 0;
+        for item in items {
+            let item_calls = Self::of_subgraph(item);
+            calls.prompt_tokens += item_calls.prompt_tokens;
+            calls
+                .completion_tokens
+                .add(item_calls.completion_tokens, 1.0);
+            calls.cost = match (calls.cost, item_calls.cost) {
+                (Some(mut cost), Some(item_cost)) => {
+                    cost.add(item_cost, 1.0);
+                    Some(cost)
+                }
+                _ => None,
+            };
+            latency_seconds = item.latency_seconds.max(latency_seconds);
+            output_tokens += item_calls.output.unknown_tokens;
+        }
+        calls.latency_seconds = Some(latency_seconds);
+        // the outputs of the items are a list, which counts the tokens of their completions
+        calls.output = SimValue {
+            known: None,
+            unknown_tokens: output_tokens,
+        };
+        calls
+    }
+}
+
+/// Prompt tokens, completion tokens and cost of an LLM call, and its p50 latency
+fn estimate_call(
+    model: Option<String>,
+    messages: &[ChatMessage],
+    unknown_tokens: u32,
+    lengths: CompletionLengths,
+    estimator: &Estimator,
+) -> Result<Calls> {
+    let model_name = model.as_deref().map(|model| {
+        model
+            .trim()
+            .split_once(':')
+            .map_or(model.trim(), |(_, name)| name)
+    });
+    let counter = TokenCounter::for_model(model.as_deref().unwrap_or_default())?;
+    let prompt_tokens = counter.count_chat(messages) + unknown_tokens;
+
+    let provider = model
+        .as_deref()
+        .and_then(|model| model.trim().split_once(':'))
+        .and_then(|(provider, _)| LanguageModelProviderName::from_str(provider).ok())
+        .and_then(|provider| estimator.language_model.models.get(&provider));
+    let cost = match (provider, model_name) {
+        (Some(provider), Some(model_name)) => {
+            let input_cost = provider.estimate_input_cost(model_name, prompt_tokens);
+            let output_cost = |tokens| provider.estimate_output_cost(model_name, tokens);
+            match (
+                input_cost,
+                output_cost(lengths.p50),
+                output_cost(lengths.p90),
+            ) {
+                (Some(input_cost), Some(p50), Some(p90)) => Some(Estimate {
+                    p50: input_cost + p50,
+                    p90: input_cost + p90,
+                }),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+
+    Ok(Calls {
+        latency_seconds: model_name.and_then(|name| model_latency(estimator.latencies, name)),
+        model,
+        prompt_tokens,
+        completion_tokens: Estimate {
+            p50: lengths.p50 as f64,
+            p90: lengths.p90 as f64,
+        },
+        cost,
+        output: SimValue {
+            known: None,
+            unknown_tokens: lengths.p50,
+        },
+    })
+}
+
+/// Latency of the model, or of its versions, e.g. `gpt-4o-2024-08-06` of `gpt-4o`
+fn model_latency(latencies: &HashMap<String, f64>, model_name: &str) -> Option<f64> {
+    latencies.get(model_name).copied().or_else(|| {
+        latencies
+            .iter()
+            .filter(|(model, _)| model.starts_with(model_name))
+            .min_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, latency)| *latency)
+    })
+}
+
+/// Probabilities of the routes of the switch, normalized to sum to 1
+fn route_probabilities<'a>(
+    node_name: &str,
+    route_names: impl Iterator<Item = &'a str>,
+    config: &SimulationConfig,
+) -> BTreeMap<String, f64> {
+    let route_names = route_names.collect::<Vec<_>>();
+    let weights = route_names
+        .iter()
+        .map(|name| {
+            let weight = match config.branch_probabilities.get(node_name) {
+                Some(probabilities) => probabilities.get(*name).copied().unwrap_or(0.0),
+                None => 1.0,
+            };
+            (name.to_string(), weight)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let total = weights.values().sum::<f64>();
+    weights
+        .into_iter()
+        .map(|(name, weight)| {
+            let probability = if total > 0.0 { weight / total } else { 0.0 };
+            (name, probability)
+        })
+        .collect()
+}
+
+/// Graph of a subpipeline or map node, run with the defaults and prompts of its parent
+fn subgraph_of(graph: &Graph, runnable_graph: &Value) -> Result<Graph> {
+    let mut subgraph = serde_json::from_value::<Graph>(runnable_graph.clone())?;
+    subgraph.workspace_model_defaults = graph.workspace_model_defaults.clone();
+    subgraph.prompts = graph.prompts.clone();
+    Ok(subgraph)
+}
+
+/// Ids of the nodes in an order they run in, edges into cyclic inputs are left out
+fn run_order(
+    graph: &Graph,
+    nodes: &HashMap<Uuid, &Node>,
+    handle_nodes: &HashMap<Uuid, Uuid>,
+) -> Result<Vec<Uuid>> {
+    let mut in_degrees = nodes
+        .keys()
+        .map(|id| (*id, 0))
+        .collect::<HashMap<_, usize>>();
+    let mut next = HashMap::<Uuid, Vec<Uuid>>::new();
+    for (id, node) in nodes {
+        for (from_handle, handle) in node.implementation().handles_mapping() {
+            let Some(from) = handle_nodes.get(&from_handle) else {
+                continue;
+            };
+            if handle.is_cyclic || !graph.pred.get(id).is_some_and(|pred| pred.contains(from)) {
+                continue;
+            }
+            *in_degrees.get_mut(id).unwrap() += 1;
+            next.entry(*from).or_default().push(*id);
+        }
+    }
+
+    // nodes without inputs start in name order, so that reports are stable
+    let mut ready = in_degrees
+        .iter()
+        .filter(|(_, in_degree)| **in_degree == 0)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    ready.sort_by_key(|id| nodes[id].name());
+    let mut ready = VecDeque::from(ready);
+    let mut order = Vec::with_capacity(nodes.len());
+    while let Some(id) = ready.pop_front() {
+        order.push(id);
+        for to in next.get(&id).into_iter().flatten() {
+            let in_degree = in_degrees.get_mut(to).unwrap();
+            *in_degree -= 1;
+            if *in_degree == 0 {
+                ready.push_back(*to);
+            }
+        }
+    }
+
+    if order.len() < nodes.len() {
+        return Err(anyhow::anyhow!(
+            "Graph has a cycle without a cyclic input, it can't be simulated"
+        ));
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_probabilities() {
+        let routes = ["yes", "no", "maybe"];
+        let mut config = SimulationConfig::default();
+
+        let uniform = route_probabilities("router", routes.into_iter(), &config);
+        assert!(uniform.values().all(|p| (p - 1.0 / 3.0).abs() < 1e-9));
+
+        config.branch_probabilities.insert(
+            "router".to_string(),
+            HashMap::from([("yes".to_string(), 3.0), ("no".to_string(), 1.0)]),
+        );
+        let supplied = route_probabilities("router", routes.into_iter(), &config);
+        assert_eq!(supplied["yes"], 0.75);
+        assert_eq!(supplied["no"], 0.25);
+        assert_eq!(supplied["maybe"], 0.0);
+    }
+
+    #[test]
+    fn test_model_latency() {
+        let latencies = HashMap::from([
+            ("gpt-4o-2024-08-06".to_string(), 2.0),
+            ("gpt-4o-mini".to_string(), 0.5),
+        ]);
+        assert_eq!(model_latency(&latencies, "gpt-4o-mini"), Some(0.5));
+        assert_eq!(model_latency(&latencies, "gpt-4o"), Some(2.0));
+        assert_eq!(model_latency(&latencies, "claude-3-haiku"), None);
+    }
+}
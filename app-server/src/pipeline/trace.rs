@@ -1,11 +1,18 @@
-use crate::{engine::engine::EngineOutput, pipeline::RunType};
+use crate::{
+    engine::{blocked::BlockReason, engine::EngineOutput},
+    pipeline::RunType,
+};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use utoipa::{
+    openapi::{ObjectBuilder, RefOr, Schema, SchemaType},
+    ToSchema,
+};
 use uuid::Uuid;
 
-use super::nodes::{llm, map, subpipeline, zenguard, Message};
+use super::nodes::{llm, map, semantic_search_utils, subpipeline, zenguard, Message};
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -16,6 +23,33 @@ pub struct RunTraceStats {
     pub approximate_cost: Option<f64>,
 }
 
+/// Execution stats of a single node in a run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeRunStats {
+    pub node_id: Uuid,
+    pub node_name: String,
+    pub node_type: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub total_token_count: i64,
+    pub approximate_cost: Option<f64>,
+    /// Stats of runs finished before outcomes were recorded are all successes
+    #[serde(default)]
+    pub outcome: NodeOutcome,
+}
+
+/// Outcome of a node's execution which produced a message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NodeOutcome {
+    #[default]
+    Success,
+    Error,
+    /// The node was cancelled before it produced a message, because another node finished the
+    /// run. Its stats start and end when the run finished, without tokens.
+    Skipped,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 #[serde(rename_all = "camelCase")]
@@ -24,6 +58,8 @@ pub enum MetaLog {
     Zenguard(zenguard::ZenguardNodeMetaLog),
     Subpipeline(subpipeline::SubpipelineNodeMetaLog),
     Map(map::MapNodeMetaLog),
+    // untagged, so it must come after the variants with more fields
+    Embedding(semantic_search_utils::EmbeddingNodeMetaLog),
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -43,6 +79,99 @@ pub struct RunTrace {
     pub trace_id: Option<Uuid>,
 }
 
+// Only sent to workshop runs, so it's not described in detail
+impl<'s> ToSchema<'s> for RunTrace {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        (
+            "RunTrace",
+            RefOr::T(Schema::Object(
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Object)
+                    .description(Some(
+                        "Trace of a workshop run, with the messages of all nodes",
+                    ))
+                    .build(),
+            )),
+        )
+    }
+}
+
+impl NodeRunStats {
+    /// Stats of every executed node, ordered by start time
+    pub fn from_messages(messages: &HashMap<Uuid, Message>) -> Vec<Self> {
+        let mut stats = messages
+            .values()
+            .map(|message| {
+                let (total_token_count, approximate_cost) = match &message.meta_log {
+                    Some(MetaLog::LLM(llm_meta)) => {
+                        (llm_meta.total_token_count, llm_meta.approximate_cost)
+                    }
+                    Some(MetaLog::Zenguard(_)) => (0, Some(0.0)),
+                    Some(MetaLog::Subpipeline(subpipeline_meta)) => (
+                        subpipeline_meta.total_token_count,
+                        subpipeline_meta.approximate_cost,
+                    ),
+                    Some(MetaLog::Map(map_meta)) => {
+                        (map_meta.total_token_count, map_meta.approximate_cost)
+                    }
+                    Some(MetaLog::Embedding(embedding_meta)) => {
+                        (embedding_meta.input_token_count, Some(0.0))
+                    }
+                    None => (0, Some(0.0)),
+                };
+                Self {
+                    node_id: message.node_id,
+                    node_name: message.node_name.clone(),
+                    node_type: message.node_type.clone(),
+                    start_time: message.start_time,
+                    end_time: message.end_time,
+                    total_token_count,
+                    approximate_cost,
+                    outcome: NodeOutcome::Success,
+                }
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|stats| stats.start_time);
+        stats
+    }
+
+    /// Stats of the run's messages, the last message of each node which failed is its error.
+    /// Nodes which a finish of the run cancelled are skipped.
+    pub fn from_engine_output(engine_output: &EngineOutput) -> Vec<Self> {
+        let mut stats = Self::from_messages(&engine_output.messages);
+        let finished_at = engine_output
+            .finish_message_id
+            .and_then(|message_id| engine_output.messages.get(&message_id))
+            .map(|message| message.end_time);
+        for blocked in engine_output.blocked_nodes.iter() {
+            match (blocked.reason, finished_at) {
+                (BlockReason::Failed, _) => {
+                    if let Some(node_stats) = stats
+                        .iter_mut()
+                        .rev()
+                        .find(|node_stats| node_stats.node_id == blocked.node_id)
+                    {
+                        node_stats.outcome = NodeOutcome::Error;
+                    }
+                }
+                (BlockReason::Cancelled, Some(finished_at)) => stats.push(Self {
+                    node_id: blocked.node_id,
+                    node_name: blocked.node_name.clone(),
+                    node_type: blocked.node_type.clone(),
+                    start_time: finished_at,
+                    end_time: finished_at,
+                    total_token_count: 0,
+                    approximate_cost: Some(0.0),
+                    outcome: NodeOutcome::Skipped,
+                }),
+                _ => {}
+            }
+        }
+        stats.sort_by_key(|stats| stats.start_time);
+        stats
+    }
+}
+
 impl RunTraceStats {
     pub fn from_messages(messages: &HashMap<Uuid, Message>) -> Self {
         let mut earliest_start_time = Utc::now();
@@ -62,6 +191,7 @@ impl RunTraceStats {
                 Some(MetaLog::Zenguard(_)) => 0,
                 Some(MetaLog::Subpipeline(subpipeline_meta)) => subpipeline_meta.total_token_count,
                 Some(MetaLog::Map(map_meta)) => map_meta.total_token_count,
+                Some(MetaLog::Embedding(embedding_meta)) => embedding_meta.input_token_count,
                 None => 0,
             };
 
@@ -72,6 +202,8 @@ impl RunTraceStats {
                 Some(MetaLog::Zenguard(_)) => Some(0.0),
                 Some(MetaLog::Subpipeline(subpipeline_meta)) => subpipeline_meta.approximate_cost,
                 Some(MetaLog::Map(map_meta)) => map_meta.approximate_cost,
+                // embedding models aren't priced yet, same as Zenguard
+                Some(MetaLog::Embedding(_)) => Some(0.0),
                 None => Some(0.0),
             };
             if let Some(cost) = approximate_cost {
@@ -1,92 +1,39 @@
 use anyhow::Result;
+use sha2::{Digest, Sha256};
 use std::{collections::HashMap, sync::Arc};
 use uuid::Uuid;
 
-use crate::engine::Task;
+use crate::engine::{task::Action, Task};
 
-use super::{nodes::Node, Graph};
+use super::{compiled::CompiledGraph, nodes::Node, Graph};
 
+/// Tasks of a run of the graph, compiled for this run only
 pub fn parse_graph(graph: Graph) -> Result<HashMap<Uuid, Task>> {
-    validate_graph(&graph)?;
-
-    let mut tasks: HashMap<Uuid, Task> = graph
-        .nodes
-        .into_iter()
-        .map(|(_, node)| (node.id(), task_from_node(node)))
-        .collect();
-
-    for (to, from) in graph.pred {
-        for from_node in from {
-            tasks.get_mut(&to).unwrap().add_prev(from_node);
-            tasks.get_mut(&from_node).unwrap().add_next(to);
-        }
-    }
-
-    Ok(tasks)
+    let compiled = CompiledGraph::compile(&graph).map_err(|e| anyhow::anyhow!("{}", e))?;
+    compiled.instantiate(&graph)
 }
 
-fn validate_graph(graph: &Graph) -> Result<()> {
-    if !graph
-        .nodes
-        .to_owned()
-        .into_iter()
-        .any(|(_node_name, node)| matches!(node, Node::Output(_)) || matches!(node, Node::Error(_)))
-    {
-        return Err(anyhow::anyhow!(
-            "Graph must contain at least one output node"
-        ));
-    };
-    Ok(())
-}
-
-fn task_from_node(node: Node) -> Task {
+/// Action running the node in a task
+pub fn action_from_node(node: Node) -> Action {
     match node {
-        Node::Input(input_node) => Task::with_action(input_node.id.clone(), Arc::new(input_node)),
-        Node::Output(output_node) => {
-            Task::with_action(output_node.id.clone(), Arc::new(output_node))
-        }
-        Node::Error(error_node) => Task::with_action(error_node.id.clone(), Arc::new(error_node)),
-        Node::LLM(llm_node) => Task::with_action(llm_node.id.clone(), Arc::new(llm_node)),
-        Node::Condition(condition_node) => {
-            Task::with_action(condition_node.id.clone(), Arc::new(condition_node))
-        }
-        Node::Extractor(extractor_node) => {
-            Task::with_action(extractor_node.id.clone(), Arc::new(extractor_node))
-        }
-        Node::JsonExtractor(json_extractor_node) => Task::with_action(
-            json_extractor_node.id.clone(),
-            Arc::new(json_extractor_node),
-        ),
-        Node::Switch(router_node) => {
-            Task::with_action(router_node.id.clone(), Arc::new(router_node))
-        }
-        Node::SemanticSearch(semantic_search_node) => Task::with_action(
-            semantic_search_node.id.clone(),
-            Arc::new(semantic_search_node),
-        ),
-        Node::SemanticSwitch(semantic_router_node) => Task::with_action(
-            semantic_router_node.id.clone(),
-            Arc::new(semantic_router_node),
-        ),
-        Node::StringTemplate(string_template_node) => Task::with_action(
-            string_template_node.id.clone(),
-            Arc::new(string_template_node),
-        ),
-        Node::Subpipeline(subpipeline_node) => {
-            Task::with_action(subpipeline_node.id.clone(), Arc::new(subpipeline_node))
-        }
-        Node::Map(map_node) => Task::with_action(map_node.id.clone(), Arc::new(map_node)),
-        Node::Zenguard(zenguard_node) => {
-            Task::with_action(zenguard_node.id.clone(), Arc::new(zenguard_node))
-        }
-        Node::FormatValidator(format_validator_node) => Task::with_action(
-            format_validator_node.id.clone(),
-            Arc::new(format_validator_node),
-        ),
-        Node::SemanticSimilarity(semantic_similarity_node) => Task::with_action(
-            semantic_similarity_node.id.clone(),
-            Arc::new(semantic_similarity_node),
-        ),
+        Node::Input(input_node) => Arc::new(input_node),
+        Node::Output(output_node) => Arc::new(output_node),
+        Node::Error(error_node) => Arc::new(error_node),
+        Node::LLM(llm_node) => Arc::new(llm_node),
+        Node::Condition(condition_node) => Arc::new(condition_node),
+        Node::Extractor(extractor_node) => Arc::new(extractor_node),
+        Node::JsonExtractor(json_extractor_node) => Arc::new(json_extractor_node),
+        Node::Switch(router_node) => Arc::new(router_node),
+        Node::SemanticSearch(semantic_search_node) => Arc::new(semantic_search_node),
+        Node::SemanticSwitch(semantic_router_node) => Arc::new(semantic_router_node),
+        Node::StringTemplate(string_template_node) => Arc::new(string_template_node),
+        Node::Subpipeline(subpipeline_node) => Arc::new(subpipeline_node),
+        Node::Map(map_node) => Arc::new(map_node),
+        Node::Zenguard(zenguard_node) => Arc::new(zenguard_node),
+        Node::FormatValidator(format_validator_node) => Arc::new(format_validator_node),
+        Node::SemanticSimilarity(semantic_similarity_node) => Arc::new(semantic_similarity_node),
+        Node::RunStore(run_store_node) => Arc::new(run_store_node),
+        Node::Custom(custom_node) => custom_node.implementation,
     }
 }
 
@@ -134,3 +81,29 @@ fn task_from_node(node: Node) -> Task {
 pub fn get_target_pipeline_version_cache_key(project_id: &str, pipeline_name: &str) -> String {
     format!("{}:{}", project_id, pipeline_name)
 }
+
+/// Hash of the runnable graph which identifies an immutable pipeline version
+pub fn get_graph_content_hash(runnable_graph: &serde_json::Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(canonicalize_json(runnable_graph).to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// serde_json preserves insertion order of keys, so sort them to hash equal graphs the same
+pub fn canonicalize_json(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            serde_json::Value::Object(
+                keys.into_iter()
+                    .map(|key| (key.clone(), canonicalize_json(&map[key])))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.iter().map(canonicalize_json).collect())
+        }
+        _ => value.clone(),
+    }
+}
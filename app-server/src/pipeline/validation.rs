@@ -0,0 +1,421 @@
+//! Validation of pipeline graphs
+//!
+//! Topology checks, i.e. edges, handle wiring and cycles, are cheap and run first on one thread.
+//! Node configs are then validated in parallel, which compiles their templates and regexes, along
+//! with structured output schemas.
+//! Compilations are shared by content, so nodes with the same config compile it once, and the
+//! nodes keep them, so runs of the compiled graph reuse them. Diagnostics are ordered by node id,
+//! so that the same graph always gets the same output.
+//! Configs in graph JSON are checked against the schemas of their node types when the graph is
+//! loaded, see `nodes::schema`, which reports the fields serde would ignore or fail on.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use lmnr_baml::BamlContext;
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{
+    model_defaults,
+    nodes::{post_processors::PostProcessors, registry, Node},
+    outputs::OutputBindings,
+    utils::action_from_node,
+    Graph,
+};
+use crate::engine::task::InputHandle;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphDiagnostic {
+    /// Unset for problems of the whole graph
+    pub node_id: Option<Uuid>,
+    pub node_name: Option<String>,
+    pub message: String,
+    /// JSON pointer to the invalid field in the node's config, or in the graph JSON if there's no
+    /// node, if the problem is of a field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+}
+
+impl GraphDiagnostic {
+    /// Problem of the config of a node, see `NodeImpl::validate_config`
+    pub fn of_config(node_id: Uuid, node_name: &str, message: String) -> Self {
+        Self {
+            node_id: Some(node_id),
+            node_name: Some(node_name.to_string()),
+            message,
+            pointer: None,
+        }
+    }
+
+    fn of_node(node: &Node, message: String) -> Self {
+        Self::of_config(node.id(), &node.name(), message)
+    }
+}
+
+/// Node configs compiled by `compile_nodes`
+pub struct CompiledNodes {
+    pub baml_schemas: HashMap<Uuid, Arc<BamlContext>>,
+    /// Errors of invalid structured output schemas by node name, which fail the compilation
+    pub invalid_schemas: HashMap<String, String>,
+    /// Post-processors of the nodes which declare valid ones, by node id
+    pub post_processors: HashMap<Uuid, Arc<PostProcessors>>,
+    /// Problems of node configs, e.g. fields their node types don't have, invalid templates and
+    /// regexes, which nodes fail or render empty with at runtime
+    pub diagnostics: Vec<GraphDiagnostic>,
+}
+
+/// Problems of the configs of graph JSON nodes, by graph node key, against the schemas of their
+/// node types
+pub fn config_diagnostics(nodes: &HashMap<String, Value>) -> Vec<GraphDiagnostic> {
+    let registry = registry::registry();
+    let mut diagnostics = Vec::new();
+    for (key, config) in nodes {
+        let node_id = config
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| Uuid::parse_str(id).ok());
+        let node_name = config.get("name").and_then(Value::as_str).unwrap_or(key);
+        for error in registry.validate_config(config) {
+            diagnostics.push(GraphDiagnostic {
+                node_id,
+                node_name: Some(node_name.to_string()),
+                message: format!("Invalid config at {}: {}", error.pointer, error.message),
+                pointer: Some(error.pointer),
+            });
+        }
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// All problems of the graph JSON, including the configs of all nodes if some of them can't be
+/// loaded
+pub fn validate_graph_json(value: Value) -> Vec<GraphDiagnostic> {
+    let nodes = value
+        .get("nodes")
+        .cloned()
+        .and_then(|nodes| serde_json::from_value::<HashMap<String, Value>>(nodes).ok())
+        .unwrap_or_default();
+    match serde_json::from_value::<Graph>(value) {
+        Ok(graph) => validate_graph(&graph),
+        Err(e) => {
+            let mut diagnostics = config_diagnostics(&nodes);
+            if diagnostics.is_empty() {
+                diagnostics.push(GraphDiagnostic {
+                    node_id: None,
+                    node_name: None,
+                    message: format!("Invalid graph: {}", e),
+                    pointer: None,
+                });
+            }
+            diagnostics
+        }
+    }
+}
+
+/// All problems of the graph, ordered by node id. LLM nodes are validated with their effective
+/// model configs.
+pub fn validate_graph(graph: &Graph) -> Vec<GraphDiagnostic> {
+    let graph = model_defaults::resolve_graph(graph);
+    let graph = graph.as_ref();
+    let mut diagnostics = check_topology(graph);
+    if let Err(e) = graph.model_defaults.validate() {
+        diagnostics.push(GraphDiagnostic {
+            node_id: None,
+            node_name: None,
+            message: format!("Invalid model defaults: {}", e),
+            pointer: Some("/modelDefaults".to_string()),
+        });
+    }
+    if let Err(e) = OutputBindings::of_graph(graph) {
+        diagnostics.push(GraphDiagnostic {
+            node_id: None,
+            node_name: None,
+            message: format!("Invalid output bindings: {}", e),
+            pointer: Some("/outputBindings".to_string()),
+        });
+    }
+    let compiled = compile_nodes(graph);
+    diagnostics.extend(compiled.diagnostics);
+    for (node_name, e) in compiled.invalid_schemas {
+        let node = graph.nodes.values().find(|node| node.name() == node_name);
+        diagnostics.push(match node {
+            Some(node) => GraphDiagnostic::of_node(node, format!("Invalid schema: {}", e)),
+            None => GraphDiagnostic {
+                node_id: None,
+                node_name: Some(node_name),
+                message: format!("Invalid schema: {}", e),
+                pointer: None,
+            },
+        });
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// Missing outputs, edges and input handles not connected to existing nodes, and cycles which
+/// don't go through a cyclic input, which would wait for their own output forever
+pub fn check_topology(graph: &Graph) -> Vec<GraphDiagnostic> {
+    let mut diagnostics = Vec::new();
+    if !graph
+        .nodes
+        .values()
+        .any(|node| matches!(node, Node::Output(_) | Node::Error(_)))
+    {
+        diagnostics.push(GraphDiagnostic {
+            node_id: None,
+            node_name: None,
+            message: "Graph must contain at least one output node".to_string(),
+            pointer: None,
+        });
+    }
+
+    let actions = graph
+        .nodes
+        .values()
+        .map(|node| (node.id(), (node, action_from_node(node.clone()))))
+        .collect::<HashMap<_, _>>();
+    let mut edges = HashSet::new();
+    for (to, from) in graph.pred.iter() {
+        for from_node in from {
+            if !actions.contains_key(to) || !actions.contains_key(from_node) {
+                diagnostics.push(GraphDiagnostic {
+                    node_id: Some(*to),
+                    node_name: actions.get(to).map(|(node, _)| node.name()),
+                    message: format!("Edge from {} to {} has no node", from_node, to),
+                    pointer: None,
+                });
+                continue;
+            }
+            edges.insert((*from_node, *to));
+        }
+    }
+
+    // edges into cyclic inputs close loops, the graph without them must be acyclic
+    let mut acyclic_edges = edges.clone();
+    for (id, (node, action)) in actions.iter() {
+        let predecessors = graph.pred.get(id).cloned().unwrap_or_default();
+        for (from_handle_id, handle) in action.handles_mapping() {
+            let from_node = predecessors.iter().find(|from_node| {
+                actions.get(from_node).is_some_and(|(_, from_action)| {
+                    from_action.output_handle_id() == from_handle_id
+                })
+            });
+            match from_node {
+                Some(from_node) if handle.is_cyclic => {
+                    acyclic_edges.remove(&(*from_node, *id));
+                }
+                Some(_) => {}
+                None => diagnostics.push(GraphDiagnostic::of_node(
+                    node,
+                    format!(
+                        "Input {} is not connected to the output of a preceding node",
+                        InputHandle::new(*id, handle.name_force())
+                    ),
+                )),
+            }
+        }
+    }
+
+    let mut in_degrees = actions
+        .keys()
+        .map(|id| (*id, 0))
+        .collect::<HashMap<_, usize>>();
+    for (_, to) in acyclic_edges.iter() {
+        *in_degrees.get_mut(to).unwrap() += 1;
+    }
+    let mut ready = in_degrees
+        .iter()
+        .filter(|(_, in_degree)| **in_degree == 0)
+        .map(|(id, _)| *id)
+        .collect::<VecDeque<_>>();
+    while let Some(id) = ready.pop_front() {
+        for (from, to) in acyclic_edges.iter() {
+            if *from == id {
+                let in_degree = in_degrees.get_mut(to).unwrap();
+                *in_degree -= 1;
+                if *in_degree == 0 {
+                    ready.push_back(*to);
+                }
+            }
+        }
+    }
+    for (id, in_degree) in in_degrees {
+        if in_degree > 0 {
+            diagnostics.push(GraphDiagnostic::of_node(
+                actions[&id].0,
+                "Node is on a cycle without a cyclic input".to_string(),
+            ));
+        }
+    }
+
+    diagnostics.sort();
+    diagnostics
+}
+
+/// Validate the node configs, compiling their templates and regexes, and the structured output
+/// schemas of the nodes in parallel
+pub fn compile_nodes(graph: &Graph) -> CompiledNodes {
+    let mut nodes = graph.nodes.values().collect::<Vec<_>>();
+    nodes.sort_by_key(|node| node.id());
+
+    let compiled = nodes
+        .par_iter()
+        .map(|node| {
+            (
+                *node,
+                node.implementation().validate_config(),
+                graph.validate_baml_schemas_for_node(node),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut compiled_nodes = CompiledNodes {
+        baml_schemas: HashMap::new(),
+        invalid_schemas: HashMap::new(),
+        post_processors: HashMap::new(),
+        diagnostics: graph.config_diagnostics.clone(),
+    };
+    for (node, diagnostics, schema) in compiled {
+        compiled_nodes.diagnostics.extend(diagnostics);
+        match schema {
+            Some(Ok(schema)) => {
+                compiled_nodes.baml_schemas.insert(node.id(), schema);
+            }
+            Some(Err(e)) => {
+                compiled_nodes.invalid_schemas.insert(node.name(), e);
+            }
+            None => {}
+        }
+        let Some(references) = graph.post_processors.get(&node.id()) else {
+            continue;
+        };
+        match PostProcessors::compile(&registry::registry(), references) {
+            Ok(post_processors) => {
+                compiled_nodes
+                    .post_processors
+                    .insert(node.id(), Arc::new(post_processors));
+            }
+            Err(errors) => {
+                compiled_nodes
+                    .diagnostics
+                    .extend(errors.into_iter().map(|e| GraphDiagnostic {
+                        node_id: Some(node.id()),
+                        node_name: Some(node.name()),
+                        message: format!("Invalid post-processor: {}", e.message),
+                        pointer: Some(e.pointer),
+                    }));
+            }
+        }
+    }
+    compiled_nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::wide_graph;
+
+    use super::*;
+
+    fn handle(id: Uuid, name: &str) -> serde_json::Value {
+        serde_json::json!({"id": id, "name": name, "type": "String"})
+    }
+
+    #[test]
+    fn test_diagnostics_are_ordered() {
+        let mut value = wide_graph(8);
+        let nodes = value["nodes"].as_object_mut().unwrap();
+        nodes["node1"]["text"] = serde_json::json!("{{#if}}");
+        nodes["node2"]["format"] = serde_json::json!("(unclosed");
+        // the input isn't connected to the question anymore
+        let input = nodes["node3"]["inputs"][0]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        nodes["node3"]["inputsMappings"] = serde_json::json!({input: Uuid::new_v4()});
+        let graph = serde_json::from_value::<Graph>(value).unwrap();
+
+        let diagnostics = validate_graph(&graph);
+        let node_names = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.node_name.clone().unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            node_names,
+            HashSet::from([
+                "node1".to_string(),
+                "node2".to_string(),
+                "node3".to_string()
+            ])
+        );
+        assert!(diagnostics
+            .windows(2)
+            .all(|pair| pair[0].node_id <= pair[1].node_id));
+        assert_eq!(validate_graph(&graph), diagnostics);
+    }
+
+    #[test]
+    fn test_cycle_needs_cyclic_input() {
+        let (input_id, input_output) = (Uuid::new_v4(), Uuid::new_v4());
+        let (first_id, first_inputs, first_output) = (
+            Uuid::new_v4(),
+            [Uuid::new_v4(), Uuid::new_v4()],
+            Uuid::new_v4(),
+        );
+        let (second_id, second_input) = (Uuid::new_v4(), Uuid::new_v4());
+        let graph = |is_cyclic: bool| {
+            let mut loop_input = handle(first_inputs[1], "previous");
+            loop_input["isCyclic"] = serde_json::json!(is_cyclic);
+            serde_json::from_value::<Graph>(serde_json::json!({
+                "nodes": {
+                    "question": {
+                        "type": "Input",
+                        "id": input_id,
+                        "name": "question",
+                        "outputs": [handle(input_output, "output")],
+                        "inputType": "String",
+                    },
+                    "first": {
+                        "type": "StringTemplate",
+                        "id": first_id,
+                        "name": "first",
+                        "inputs": [handle(first_inputs[0], "question"), loop_input],
+                        "outputs": [handle(first_output, "output")],
+                        "inputsMappings": {
+                            first_inputs[0].to_string(): input_output,
+                            // outputs are mapped by their node's id
+                            first_inputs[1].to_string(): second_id,
+                        },
+                        "text": "{{question}} {{previous}}",
+                    },
+                    "second": {
+                        "type": "Output",
+                        "id": second_id,
+                        "name": "second",
+                        "inputs": [handle(second_input, "output")],
+                        "inputsMappings": {second_input.to_string(): first_output},
+                    },
+                },
+                "pred": {
+                    first_id.to_string(): [input_id, second_id],
+                    second_id.to_string(): [first_id],
+                },
+            }))
+            .unwrap()
+        };
+
+        assert!(check_topology(&graph(true)).is_empty());
+        let diagnostics = check_topology(&graph(false));
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.message.contains("cycle")));
+    }
+}
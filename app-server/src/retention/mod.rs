@@ -0,0 +1,329 @@
+//! Retention of traced data per workspace
+//!
+//! A workspace's policy has separate TTLs of the captured content of traces, i.e. span inputs and
+//! outputs, event inputs, run outputs and node I/O, of span metadata, i.e. the traces, spans and
+//! events themselves, and of rollup stats, i.e. the spans in ClickHouse which metrics are
+//! computed from. A sweeper purges expired data of all projects of workspaces with a policy in
+//! batches, and records each purge.
+//!
+//! Purged traces are kept in `purged_traces`, so that links to them, e.g. the traces of
+//! evaluation results, show the content as purged instead of failing. The content of an end
+//! user's traces can also be erased on request, regardless of the policy.
+
+use std::{env, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    ch,
+    db::{
+        self,
+        retention::{ProjectRetention, TraceSelector},
+        DB,
+    },
+    runs::node_io::NodeIoStore,
+};
+
+const DEFAULT_SWEEP_INTERVAL_SECONDS: u64 = 3600;
+const DEFAULT_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// Days captured content of traces is kept, forever if not set
+    #[serde(default)]
+    pub content_ttl_days: Option<i32>,
+    /// Days traces, spans and events are kept, forever if not set
+    #[serde(default)]
+    pub span_metadata_ttl_days: Option<i32>,
+    /// Days rollup stats of spans are kept, forever if not set
+    #[serde(default)]
+    pub rollup_ttl_days: Option<i32>,
+}
+
+impl RetentionPolicy {
+    pub fn validate(&self) -> Result<()> {
+        let ttls = [
+            self.content_ttl_days,
+            self.span_metadata_ttl_days,
+            self.rollup_ttl_days,
+        ];
+        if ttls.iter().flatten().any(|days| *days <= 0) {
+            return Err(anyhow::anyhow!("TTLs must be positive"));
+        }
+        // content is stored in spans, so it can't be kept longer than them
+        if let (Some(content), Some(metadata)) =
+            (self.content_ttl_days, self.span_metadata_ttl_days)
+        {
+            if content > metadata {
+                return Err(anyhow::anyhow!(
+                    "Content TTL must not exceed the span metadata TTL"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[sqlx(type_name = "retention_purge_kind", rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub enum PurgeKind {
+    Content,
+    SpanMetadata,
+    Rollups,
+    UserErasure,
+}
+
+fn sweep_interval() -> Duration {
+    let seconds = env::var("RETENTION_SWEEP_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_SWEEP_INTERVAL_SECONDS);
+    Duration::from_secs(seconds)
+}
+
+fn batch_size() -> i64 {
+    env::var("RETENTION_BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+fn cutoff(ttl_days: i32) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::days(ttl_days as i64)
+}
+
+/// Purge expired data of workspaces with a retention policy, forever
+pub async fn sweep_expired_data(
+    db: Arc<DB>,
+    clickhouse: clickhouse::Client,
+    node_io_store: Arc<dyn NodeIoStore>,
+) {
+    let batch_size = batch_size();
+    let mut interval = tokio::time::interval(sweep_interval());
+    loop {
+        interval.tick().await;
+        let projects = match db::retention::get_project_retentions(&db.pool).await {
+            Ok(projects) => projects,
+            Err(e) => {
+                log::error!("Failed to get retention policies: {}", e);
+                continue;
+            }
+        };
+        for project in projects {
+            if let Err(e) = enforce_policy(
+                &db,
+                &clickhouse,
+                node_io_store.as_ref(),
+                &project,
+                batch_size,
+            )
+            .await
+            {
+                log::error!(
+                    "Failed to purge expired data of project {}: {}",
+                    project.project_id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn enforce_policy(
+    db: &DB,
+    clickhouse: &clickhouse::Client,
+    node_io_store: &dyn NodeIoStore,
+    project: &ProjectRetention,
+    batch_size: i64,
+) -> Result<()> {
+    let project_id = &project.project_id;
+
+    if let Some(ttl_days) = project.policy.content_ttl_days {
+        let cutoff = cutoff(ttl_days);
+        let purged = purge_content(
+            db,
+            node_io_store,
+            project_id,
+            &TraceSelector::StartedBefore(cutoff),
+            batch_size,
+        )
+        .await?;
+        if purged > 0 {
+            log::info!(
+                "Purged content of {} traces of project {}",
+                purged,
+                project_id
+            );
+            db::retention::record_purge(
+                &db.pool,
+                project_id,
+                PurgeKind::Content,
+                Some(cutoff),
+                None,
+                purged,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(ttl_days) = project.policy.span_metadata_ttl_days {
+        let cutoff = cutoff(ttl_days);
+        let deleted = delete_traces(db, node_io_store, project_id, &cutoff, batch_size).await?;
+        if deleted > 0 {
+            log::info!(
+                "Deleted {} expired traces of project {}",
+                deleted,
+                project_id
+            );
+            db::retention::record_purge(
+                &db.pool,
+                project_id,
+                PurgeKind::SpanMetadata,
+                Some(cutoff),
+                None,
+                deleted,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(ttl_days) = project.policy.rollup_ttl_days {
+        let cutoff = cutoff(ttl_days);
+        let deleted =
+            ch::spans::delete_spans_before(clickhouse.clone(), *project_id, cutoff).await? as i64;
+        if deleted > 0 {
+            log::info!(
+                "Deleted {} expired span rollups of project {}",
+                deleted,
+                project_id
+            );
+            db::retention::record_purge(
+                &db.pool,
+                project_id,
+                PurgeKind::Rollups,
+                Some(cutoff),
+                None,
+                deleted,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Purge the content of the traces of the selector, `batch_size` traces at a time. Returns the
+/// number of purged traces.
+async fn purge_content(
+    db: &DB,
+    node_io_store: &dyn NodeIoStore,
+    project_id: &Uuid,
+    selector: &TraceSelector,
+    batch_size: i64,
+) -> Result<i64> {
+    let mut purged = 0;
+    loop {
+        let trace_ids =
+            db::retention::get_traces_to_purge(&db.pool, project_id, selector, batch_size).await?;
+        if trace_ids.is_empty() {
+            return Ok(purged);
+        }
+        purge_batch_content(db, node_io_store, project_id, &trace_ids).await?;
+        purged += trace_ids.len() as i64;
+    }
+}
+
+/// Node I/O is deleted before the traces are marked as purged, so that a failed batch is
+/// purged again on the next sweep
+async fn purge_batch_content(
+    db: &DB,
+    node_io_store: &dyn NodeIoStore,
+    project_id: &Uuid,
+    trace_ids: &[Uuid],
+) -> Result<()> {
+    let run_ids = db::retention::get_runs_of_traces(&db.pool, trace_ids).await?;
+    node_io_store.delete_runs(&run_ids).await?;
+    db::retention::purge_trace_content(&db.pool, project_id, trace_ids).await
+}
+
+async fn delete_traces(
+    db: &DB,
+    node_io_store: &dyn NodeIoStore,
+    project_id: &Uuid,
+    cutoff: &DateTime<Utc>,
+    batch_size: i64,
+) -> Result<i64> {
+    let mut deleted = 0;
+    loop {
+        let trace_ids =
+            db::retention::get_traces_to_delete(&db.pool, project_id, cutoff, batch_size).await?;
+        if trace_ids.is_empty() {
+            return Ok(deleted);
+        }
+        purge_batch_content(db, node_io_store, project_id, &trace_ids).await?;
+        db::retention::delete_traces(&db.pool, &trace_ids).await?;
+        deleted += trace_ids.len() as i64;
+    }
+}
+
+/// Erase the captured content of all traces of the end user in the project, and record it
+pub async fn erase_end_user_content(
+    db: &DB,
+    node_io_store: &dyn NodeIoStore,
+    project_id: &Uuid,
+    end_user_id: &str,
+) -> Result<db::retention::RetentionPurge> {
+    let purged = purge_content(
+        db,
+        node_io_store,
+        project_id,
+        &TraceSelector::EndUser(end_user_id.to_string()),
+        batch_size(),
+    )
+    .await?;
+
+    db::retention::record_purge(
+        &db.pool,
+        project_id,
+        PurgeKind::UserErasure,
+        None,
+        Some(end_user_id),
+        purged,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_policy() {
+        assert!(RetentionPolicy::default().validate().is_ok());
+        let policy = RetentionPolicy {
+            content_ttl_days: Some(30),
+            span_metadata_ttl_days: Some(90),
+            rollup_ttl_days: Some(365),
+        };
+        assert!(policy.validate().is_ok());
+
+        let content_outlives_spans = RetentionPolicy {
+            content_ttl_days: Some(90),
+            span_metadata_ttl_days: Some(30),
+            ..policy
+        };
+        assert!(content_outlives_spans.validate().is_err());
+        let non_positive = RetentionPolicy {
+            rollup_ttl_days: Some(0),
+            ..policy
+        };
+        assert!(non_positive.validate().is_err());
+    }
+}
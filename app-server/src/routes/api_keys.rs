@@ -1,34 +1,46 @@
-use crate::db::{self, api_keys::ProjectApiKey, utils::generate_random_key, DB};
+use crate::db::{
+    self,
+    api_keys::{ApiKeySettings, ProjectApiKey},
+    utils::generate_random_key,
+    DB,
+};
 use actix_web::{delete, get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::ResponseResult;
 use crate::cache::Cache;
 
-#[derive(serde::Deserialize)]
-struct CreateProjectApiKeyRequest {
-    name: Option<String>,
+const DEFAULT_USAGE_DAYS: i64 = 30;
+
+/// Created key with its value, the value is returned only once and only its hash is stored
+#[derive(Serialize)]
+struct CreateProjectApiKeyResponse {
+    value: String,
+    #[serde(flatten)]
+    api_key: ProjectApiKey,
 }
 
 #[post("api-keys")]
 async fn create_project_api_key(
     project_id: web::Path<Uuid>,
     db: web::Data<DB>,
-    req: web::Json<CreateProjectApiKeyRequest>,
+    req: web::Json<ApiKeySettings>,
     cache: web::Data<Cache>,
 ) -> ResponseResult {
-    let req = req.into_inner();
-
-    // TODO: value should be a hash of genereted key
-    let api_key = ProjectApiKey {
-        value: generate_random_key(),
-        project_id: project_id.into_inner(),
-        name: req.name,
-    };
+    let settings = req.into_inner();
+    let value = generate_random_key();
 
-    db::api_keys::create_project_api_key(&db.pool, &api_key, cache.into_inner()).await?;
+    let api_key = db::api_keys::create_project_api_key(
+        &db.pool,
+        &value,
+        project_id.into_inner(),
+        &settings,
+        cache.into_inner(),
+    )
+    .await?;
 
-    Ok(HttpResponse::Ok().json(api_key))
+    Ok(HttpResponse::Ok().json(CreateProjectApiKeyResponse { value, api_key }))
 }
 
 #[get("api-keys")]
@@ -41,23 +53,41 @@ async fn get_api_keys_for_project(
     Ok(HttpResponse::Ok().json(api_keys))
 }
 
-#[derive(serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct DeleteProjectApiKeyRequest {
-    api_key: String,
-}
-
-#[delete("api-keys")]
+#[delete("api-keys/{api_key_id}")]
 async fn revoke_project_api_key(
-    project_id: web::Path<Uuid>,
+    path: web::Path<(Uuid, Uuid)>,
     db: web::Data<DB>,
-    req: web::Json<DeleteProjectApiKeyRequest>,
     cache: web::Data<Cache>,
 ) -> ResponseResult {
-    let req = req.into_inner();
-    let _ = cache.remove::<ProjectApiKey>(&req.api_key).await;
+    let (project_id, api_key_id) = path.into_inner();
 
-    db::api_keys::delete_api_key(&db.pool, &req.api_key, &project_id).await?;
+    let hash = db::api_keys::delete_api_key(&db.pool, &api_key_id, &project_id).await?;
+    if let Some(hash) = hash {
+        let _ = cache.remove::<ProjectApiKey>(&hash).await;
+    }
 
     Ok(HttpResponse::Ok().finish())
 }
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetApiKeyUsageParams {
+    #[serde(default)]
+    past_days: Option<i64>,
+}
+
+/// Daily requests and tokens of the key
+#[get("api-keys/{api_key_id}/usage")]
+async fn get_api_key_usage(
+    path: web::Path<(Uuid, Uuid)>,
+    params: web::Query<GetApiKeyUsageParams>,
+    db: web::Data<DB>,
+) -> ResponseResult {
+    let (project_id, api_key_id) = path.into_inner();
+    let past_days = params.past_days.unwrap_or(DEFAULT_USAGE_DAYS);
+
+    let usage =
+        db::api_keys::get_api_key_usage(&db.pool, &api_key_id, &project_id, past_days).await?;
+
+    Ok(HttpResponse::Ok().json(usage))
+}
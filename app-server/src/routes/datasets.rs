@@ -2,27 +2,45 @@ use std::{collections::HashMap, sync::Arc};
 
 use actix_multipart::Multipart;
 use actix_web::{delete, get, post, web, HttpResponse};
+use anyhow::Context;
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 use crate::{
-    datasets::datapoints::{self, Datapoint},
+    datasets::datapoints::{self, split_raw_value, Datapoint},
+    datasets::import::{self, DatasetImporter, ImportFormat},
+    datasets::schema::{DatasetSchema, RowFields},
     datasets::utils::read_multipart_file,
+    datasets::DatasetLimits,
     db::{self, datasets, DB},
     files::FileManager,
-    routes::ResponseResult,
+    routes::{error::Error, ResponseResult},
     semantic_search::SemanticSearch,
 };
 
 const DEFAULT_PAGE_SIZE: usize = 50;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct CreateDatasetRequest {
     name: String,
+    /// Columns of the datapoints, inferred from the first imported rows if not set
+    #[serde(default)]
+    schema: DatasetSchema,
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets",
+    tag = "datasets",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(content = inline(CreateDatasetRequest)),
+    responses((status = 200, body = Dataset)),
+    security(("user_api_key" = [])),
+)]
 #[post("datasets")]
 async fn create_dataset(
     db: web::Data<DB>,
@@ -32,11 +50,19 @@ async fn create_dataset(
     let project_id = project_id.into_inner();
     let req = req.into_inner();
 
-    let dataset = datasets::create_dataset(&db.pool, &req.name, project_id).await?;
+    let dataset = datasets::create_dataset(&db.pool, &req.name, project_id, &req.schema).await?;
 
     Ok(HttpResponse::Ok().json(dataset))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/datasets",
+    tag = "datasets",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    responses((status = 200, body = [Dataset])),
+    security(("user_api_key" = [])),
+)]
 #[get("datasets")]
 async fn get_datasets(db: web::Data<DB>, project_id: web::Path<Uuid>) -> ResponseResult {
     let datasets = datasets::get_datasets(&db.pool, project_id.into_inner()).await?;
@@ -44,6 +70,17 @@ async fn get_datasets(db: web::Data<DB>, project_id: web::Path<Uuid>) -> Respons
     Ok(HttpResponse::Ok().json(datasets))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    responses((status = 200, body = Dataset)),
+    security(("user_api_key" = [])),
+)]
 #[get("datasets/{dataset_id}")]
 async fn get_dataset(db: web::Data<DB>, path: web::Path<(Uuid, Uuid)>) -> ResponseResult {
     let (project_id, dataset_id) = path.into_inner();
@@ -52,12 +89,25 @@ async fn get_dataset(db: web::Data<DB>, path: web::Path<(Uuid, Uuid)>) -> Respon
     Ok(HttpResponse::Ok().json(dataset))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct UpdateDatasetRequest {
     new_name: String,
 }
 
+/// Rename the dataset
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    request_body(content = inline(UpdateDatasetRequest)),
+    responses((status = 200, body = Dataset)),
+    security(("user_api_key" = [])),
+)]
 #[post("datasets/{dataset_id}")]
 async fn rename_dataset(
     db: web::Data<DB>,
@@ -73,6 +123,18 @@ async fn rename_dataset(
     Ok(HttpResponse::Ok().json(updated_dataset))
 }
 
+/// Delete the dataset with its datapoints and their embeddings
+#[utoipa::path(
+    delete,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    responses((status = 200)),
+    security(("user_api_key" = [])),
+)]
 #[delete("datasets/{dataset_id}")]
 async fn delete_dataset(
     db: web::Data<DB>,
@@ -99,6 +161,19 @@ async fn delete_dataset(
 // NOTE: this endpoint currently assumes one file upload.
 // If we want to support multiple files, we will need to keep a list of filename -> bytes links.
 // and potentially batch process, so that we don't hold enormous files in memory
+/// Add datapoints from a CSV, JSON or JSONL file, or from the chunks of an unstructured file
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/file-upload",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    request_body(content = String, content_type = "multipart/form-data", description = "Single file part"),
+    responses((status = 200, body = [Datapoint])),
+    security(("user_api_key" = [])),
+)]
 #[post("datasets/{dataset_id}/file-upload")]
 async fn upload_datapoint_file(
     payload: Multipart,
@@ -124,10 +199,31 @@ async fn upload_datapoint_file(
         }
     }
 
+    if !is_unstructured_file {
+        let rows = import::parse_file(&filename, &bytes)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+        let mut importer =
+            DatasetImporter::new(dataset, db, semantic_search.as_ref().clone(), true).await?;
+        for row in rows {
+            importer.push(row).await?;
+        }
+        let (report, datapoints) = importer.finish().await?;
+        if report.failed_rows > 0 || report.truncated {
+            log::warn!(
+                "Skipped {} malformed rows of file {} in dataset {}, truncated: {}",
+                report.failed_rows,
+                filename,
+                dataset_id,
+                report.truncated
+            );
+        }
+
+        return Ok(HttpResponse::Ok().json(datapoints));
+    }
+
     let datapoints = datapoints::insert_datapoints_from_file(
         &bytes,
         &filename,
-        is_unstructured_file,
         dataset_id,
         db.clone(),
         file_manager.as_ref().clone(),
@@ -148,11 +244,123 @@ async fn upload_datapoint_file(
     Ok(HttpResponse::Ok().json(datapoints))
 }
 
-#[derive(Deserialize)]
+/// Import datapoints from a CSV or JSONL file, parsed while it's uploaded
+///
+/// The multipart form has an optional `schema` part with the JSON of columns to add to the
+/// dataset schema, and a `file` part after it. CSV headers are the names of the data columns,
+/// JSONL lines are datapoints, i.e. objects with `data` and optional `target`, or just data.
+/// Malformed rows are skipped and reported.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/import",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    request_body(content = String, content_type = "multipart/form-data", description = "Optional schema part and a file part"),
+    responses((status = 200, body = ImportReport)),
+    security(("user_api_key" = [])),
+)]
+#[post("datasets/{dataset_id}/import")]
+async fn import_datapoints(
+    mut payload: Multipart,
+    path: web::Path<(Uuid, Uuid)>,
+    db: web::Data<DB>,
+    semantic_search: web::Data<Arc<SemanticSearch>>,
+) -> ResponseResult {
+    let (project_id, dataset_id) = path.into_inner();
+    let db = db.into_inner();
+
+    let dataset = db::datasets::get_dataset(&db.pool, project_id, dataset_id).await?;
+    let mut importer =
+        DatasetImporter::new(dataset, db, semantic_search.as_ref().clone(), false).await?;
+    let mut has_file = false;
+
+    while let Some(field) = payload.next().await {
+        let mut field = field?;
+        let content_disposition = field.content_disposition();
+        let name = content_disposition
+            .get_name()
+            .ok_or_else(|| Error::invalid_request(Some("Multipart part has no name")))?
+            .to_string();
+        // This does not handle filename_ext ("filename*")
+        let filename = content_disposition.get_filename().map(String::from);
+
+        match name.as_str() {
+            "schema" if !has_file => {
+                let mut value = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    value.extend_from_slice(&chunk?);
+                }
+                let schema = serde_json::from_slice::<DatasetSchema>(&value)
+                    .map_err(|e| Error::deserialization_error(Some(e)))?;
+                importer
+                    .set_schema(&schema)
+                    .map_err(|e| Error::invalid_request(Some(&e)))?;
+            }
+            "schema" => {
+                return Err(Error::invalid_request(Some(
+                    "The schema part must be before the file part",
+                )));
+            }
+            "file" if !has_file => {
+                let filename = filename.context("filename not found")?;
+                let format = ImportFormat::from_filename(&filename).ok_or_else(|| {
+                    Error::invalid_request(Some("Imported files must be CSV or JSONL"))
+                })?;
+                has_file = true;
+
+                let mut parser = import::RowParser::new(format);
+                while let Some(chunk) = field.next().await {
+                    let chunk = chunk?;
+                    // Read the rest of the upload without parsing it once the dataset is full
+                    if importer.is_full() {
+                        continue;
+                    }
+                    for row in parser.feed(&chunk) {
+                        importer.push(row).await?;
+                    }
+                }
+                for row in parser.finish() {
+                    importer.push(row).await?;
+                }
+            }
+            _ => {
+                return Err(Error::invalid_request(Some(&format!(
+                    "Unexpected multipart part '{name}'"
+                ))));
+            }
+        }
+    }
+
+    if !has_file {
+        return Err(Error::invalid_request(Some(
+            "Multipart form has no file part",
+        )));
+    }
+    let (report, _) = importer.finish().await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Deserialize, ToSchema)]
 struct CreateDatapointsRequest {
     datapoints: Vec<serde_json::Value>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/datapoints",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    request_body(content = inline(CreateDatapointsRequest)),
+    responses((status = 200, body = [Datapoint])),
+    security(("user_api_key" = [])),
+)]
 #[post("datasets/{dataset_id}/datapoints")]
 async fn create_datapoints(
     path: web::Path<(Uuid, Uuid)>,
@@ -165,8 +373,38 @@ async fn create_datapoints(
 
     let dataset = db::datasets::get_dataset(&db.pool, project_id, dataset_id).await?;
 
+    let mut schema = dataset.schema.clone();
+    let mut new_datapoints = Vec::with_capacity(input_datapoints.len());
+    for (i, raw) in input_datapoints.iter().enumerate() {
+        let (data, target) = split_raw_value(raw).ok_or_else(|| {
+            Error::invalid_request(Some(&format!("Datapoint {i} must be an object")))
+        })?;
+        let data = schema
+            .apply(RowFields::Json(data))
+            .map_err(|e| Error::invalid_request(Some(&format!("Datapoint {i}: {e}"))))?;
+        new_datapoints.push(Datapoint {
+            id: Uuid::new_v4(),
+            dataset_id,
+            data: Value::Object(data),
+            target,
+        });
+    }
+
+    let limits = DatasetLimits::from_env();
+    let usage = db::datapoints::get_dataset_usage(&db.pool, dataset_id).await?;
+    let size = new_datapoints.iter().map(import::datapoint_size).sum();
+    if !limits.allows(&usage, new_datapoints.len() as u64, size) {
+        return Err(Error::limit_error(&format!(
+            "Datasets can have at most {} datapoints and {} bytes of data",
+            limits.max_rows, limits.max_size
+        )));
+    }
+
     let datapoints =
-        db::datapoints::insert_raw_data(&db.pool, &dataset_id, &input_datapoints).await?;
+        db::datapoints::insert_datapoints(&db.pool, &dataset_id, new_datapoints).await?;
+    if schema != dataset.schema {
+        db::datasets::update_dataset_schema(&db.pool, dataset_id, &schema).await?;
+    }
 
     if dataset.indexed_on.is_some() {
         dataset
@@ -182,12 +420,25 @@ async fn create_datapoints(
     Ok(HttpResponse::Ok().json(datapoints))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct UpdateDatapointRequest {
     data: Value,
     target: Value,
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/datapoints/{datapoint_id}",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+        ("datapoint_id" = Uuid, Path, description = "Id of the datapoint"),
+    ),
+    request_body(content = inline(UpdateDatapointRequest)),
+    responses((status = 200, body = Datapoint)),
+    security(("user_api_key" = [])),
+)]
 #[post("datasets/{dataset_id}/datapoints/{datapoint_id}")]
 async fn update_datapoint_data(
     path: web::Path<(Uuid, Uuid, Uuid)>,
@@ -226,11 +477,23 @@ async fn update_datapoint_data(
     Ok(HttpResponse::Ok().json(updated_datapoint))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct DeleteDatapointRequest {
     pub ids: Vec<Uuid>,
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/datapoints",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    request_body(content = inline(DeleteDatapointRequest)),
+    responses((status = 200)),
+    security(("user_api_key" = [])),
+)]
 #[delete("datasets/{dataset_id}/datapoints")]
 async fn delete_datapoints(
     path: web::Path<(Uuid, Uuid)>,
@@ -256,6 +519,17 @@ async fn delete_datapoints(
     Ok(HttpResponse::Ok().finish())
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/datapoints/all",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    responses((status = 200)),
+    security(("user_api_key" = [])),
+)]
 #[delete("datasets/{dataset_id}/datapoints/all")]
 async fn delete_all_datapoints(
     path: web::Path<(Uuid, Uuid)>,
@@ -279,8 +553,9 @@ async fn delete_all_datapoints(
     Ok(HttpResponse::Ok().finish())
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, IntoParams)]
 #[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
 struct GetDatapointsQueryParams {
     /// page number starting from 0
     #[serde(default)]
@@ -288,13 +563,25 @@ struct GetDatapointsQueryParams {
     page_size: Option<usize>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct DatapointsResponse {
     total_entries: u64,
     datapoints: Vec<Datapoint>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/datapoints",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+        GetDatapointsQueryParams,
+    ),
+    responses((status = 200, body = inline(DatapointsResponse))),
+    security(("user_api_key" = [])),
+)]
 #[get("datasets/{dataset_id}/datapoints")]
 async fn get_datapoints(
     db: web::Data<DB>,
@@ -316,12 +603,25 @@ async fn get_datapoints(
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct IndexDatasetRequest {
     index_column: Option<String>,
 }
 
+/// Index the datapoints on the column for semantic search, or remove the index if it's not set
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/datasets/{dataset_id}/index",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("dataset_id" = Uuid, Path, description = "Id of the dataset"),
+    ),
+    request_body(content = inline(IndexDatasetRequest)),
+    responses((status = 200, body = Dataset)),
+    security(("user_api_key" = [])),
+)]
 #[post("datasets/{dataset_id}/index")]
 async fn index_dataset(
     db: web::Data<DB>,
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    ch::exporter::SpanExporter,
+    db::{self, DB},
+    dead_letters::{self, DeadLetterKind, RetryError},
+    routes::{error::Error, ResponseResult},
+    runs::queue::RunExecution,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Number of dead letters of each kind, to alert on their growth
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/dead_letters/stats",
+    tag = "admin",
+    responses((status = 200, body = [DeadLetterStats])),
+    security(("shared_secret" = [])),
+)]
+#[get("dead_letters/stats")]
+async fn get_dead_letter_stats(db: web::Data<DB>) -> ResponseResult {
+    let stats = db::dead_letters::get_dead_letter_stats(&db.pool).await?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct GetDeadLettersParams {
+    #[serde(default)]
+    kind: Option<DeadLetterKind>,
+    #[serde(default)]
+    page_size: Option<i64>,
+    #[serde(default)]
+    offset: Option<i64>,
+}
+
+/// Dead letters without their payloads, latest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/dead_letters",
+    tag = "admin",
+    params(GetDeadLettersParams),
+    responses((status = 200, body = [DeadLetterInfo])),
+    security(("shared_secret" = [])),
+)]
+#[get("dead_letters")]
+async fn get_dead_letters(
+    db: web::Data<DB>,
+    params: web::Query<GetDeadLettersParams>,
+) -> ResponseResult {
+    let params = params.into_inner();
+    let page_size = params
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let dead_letters =
+        db::dead_letters::get_dead_letters(&db.pool, params.kind, page_size, offset).await?;
+
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct PurgeDeadLettersParams {
+    /// Only purge dead letters of the kind
+    #[serde(default)]
+    kind: Option<DeadLetterKind>,
+    /// Only purge dead letters created before the time
+    #[serde(default)]
+    created_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PurgeDeadLettersResponse {
+    purged: u64,
+}
+
+/// Delete dead letters, all of them if no filter is set
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/dead_letters",
+    tag = "admin",
+    params(PurgeDeadLettersParams),
+    responses((status = 200, body = inline(PurgeDeadLettersResponse))),
+    security(("shared_secret" = [])),
+)]
+#[delete("dead_letters")]
+async fn purge_dead_letters(
+    db: web::Data<DB>,
+    params: web::Query<PurgeDeadLettersParams>,
+) -> ResponseResult {
+    let params = params.into_inner();
+    let purged =
+        db::dead_letters::purge_dead_letters(&db.pool, params.kind, params.created_before).await?;
+
+    Ok(HttpResponse::Ok().json(PurgeDeadLettersResponse { purged }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/dead_letters/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Id of the dead letter")),
+    responses(
+        (status = 200, body = DeadLetter),
+        (status = 404, description = "Dead letter not found"),
+    ),
+    security(("shared_secret" = [])),
+)]
+#[get("dead_letters/{id}")]
+async fn get_dead_letter(db: web::Data<DB>, id: web::Path<Uuid>) -> ResponseResult {
+    let Some(dead_letter) = db::dead_letters::get_dead_letter(&db.pool, &id.into_inner()).await?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok().json(dead_letter))
+}
+
+/// Process the dead letter again
+///
+/// The dead letter is deleted once its spans are handed back to the exporter, or its run job is
+/// pushed to the run queue. If it still can't be processed, it's kept with the new error and an
+/// incremented retry count. Spans which fail to be exported again are captured as a new dead
+/// letter.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/dead_letters/{id}/retry",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Id of the dead letter")),
+    responses(
+        (status = 204, description = "Dead letter was retried and deleted"),
+        (status = 404, description = "Dead letter not found"),
+    ),
+    security(("shared_secret" = [])),
+)]
+#[post("dead_letters/{id}/retry")]
+async fn retry_dead_letter(
+    db: web::Data<DB>,
+    span_exporter: web::Data<Arc<SpanExporter>>,
+    run_execution: web::Data<RunExecution>,
+    id: web::Path<Uuid>,
+) -> ResponseResult {
+    let Some(dead_letter) = db::dead_letters::get_dead_letter(&db.pool, &id.into_inner()).await?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    match dead_letters::retry(&db, dead_letter, &span_exporter, &run_execution).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(RetryError::UnhandledError(e)) => Err(e.into()),
+        Err(e) => Err(Error::invalid_request(Some(&e.to_string()))),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/dead_letters/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Id of the dead letter")),
+    responses(
+        (status = 204, description = "Dead letter was deleted"),
+        (status = 404, description = "Dead letter not found"),
+    ),
+    security(("shared_secret" = [])),
+)]
+#[delete("dead_letters/{id}")]
+async fn delete_dead_letter(db: web::Data<DB>, id: web::Path<Uuid>) -> ResponseResult {
+    if !db::dead_letters::delete_dead_letter(&db.pool, &id.into_inner()).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
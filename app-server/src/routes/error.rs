@@ -5,8 +5,12 @@ use log::error;
 use serde_json::Value;
 use uuid::Uuid;
 
+use crate::db::api_keys::ApiKeyScope;
 use crate::db::workspace::WorkspaceError;
 use crate::engine::engine::EngineOutput;
+use crate::pipeline::bundle::BundleValidationErrors;
+use crate::pipeline::credentials::CredentialRequirement;
+use crate::pipeline::inputs::InputError;
 use crate::pipeline::runner::PipelineRunnerError;
 use crate::pipeline::GraphError;
 
@@ -21,6 +25,10 @@ pub enum Error {
         error_code: String,
         error_message: Option<serde_json::Value>,
     },
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
 }
 
 // This can be refactored, but for now it can be used as a single source to see
@@ -54,6 +62,20 @@ impl Error {
         }
     }
 
+    pub fn invalid_graph_inputs(errors: &[InputError]) -> Self {
+        Self::RequestError {
+            error_code: "api.invalidGraphInputs".to_string(),
+            error_message: serde_json::to_value(errors).ok(),
+        }
+    }
+
+    pub fn missing_credentials(missing: &[CredentialRequirement]) -> Self {
+        Self::RequestError {
+            error_code: "api.missingCredentials".to_string(),
+            error_message: serde_json::to_value(missing).ok(),
+        }
+    }
+
     pub fn no_target_pipeline(pipeline_name: &String) -> Self {
         Self::RequestError {
             error_code: "api.noTargetPipeline".to_string(),
@@ -65,6 +87,15 @@ Set the target version for the pipeline in the pipeline builder."),
         }
     }
 
+    pub fn no_pipeline_version(pipeline_name: &String, pipeline_version: &String) -> Self {
+        Self::RequestError {
+            error_code: "api.noPipelineVersion".to_string(),
+            error_message: Some(Value::String(format!(
+                "There is no commit version '{pipeline_version}' of pipeline '{pipeline_name}'."
+            ))),
+        }
+    }
+
     pub fn graph_running_error(trace: EngineOutput, run_id: Uuid) -> Self {
         Self::RequestError {
             error_code: "api.GraphRunningError".to_string(),
@@ -83,6 +114,40 @@ Set the target version for the pipeline in the pipeline builder."),
         }
     }
 
+    pub fn missing_api_key_scope(scope: ApiKeyScope) -> Self {
+        Self::Forbidden(format!("API key does not have '{scope}' scope"))
+    }
+
+    pub fn pipeline_not_allowed(pipeline_name: &String) -> Self {
+        Self::Forbidden(format!(
+            "API key is not allowed to run pipeline '{pipeline_name}'"
+        ))
+    }
+
+    pub fn invalid_pipeline_bundle(errors: &BundleValidationErrors) -> Self {
+        Self::RequestError {
+            error_code: "api.invalidPipelineBundle".to_string(),
+            error_message: serde_json::to_value(errors).ok(),
+        }
+    }
+
+    pub fn unsupported_content_type(received: &str, supported: &[&str]) -> Self {
+        Self::RequestError {
+            error_code: "api.unsupportedContentType".to_string(),
+            error_message: Some(Value::String(format!(
+                "Unsupported content type '{received}', expected one of: {}",
+                supported.join(", ")
+            ))),
+        }
+    }
+
+    pub fn upload_too_large(error_message: &str) -> Self {
+        Self::RequestError {
+            error_code: "api.uploadTooLarge".to_string(),
+            error_message: Some(Value::String(error_message.to_string())),
+        }
+    }
+
     pub fn limit_error(error_message: &str) -> Self {
         Self::RequestError {
             error_code: "api.LimitReached".to_string(),
@@ -111,6 +176,10 @@ pub fn graph_error_to_http_error(e: GraphError) -> Error {
         GraphError::InputMissing(input_name) => {
             Error::runner_missing_graph_input(Some(&input_name))
         }
+        GraphError::InvalidInputs(errors) => Error::invalid_graph_inputs(&errors),
+        GraphError::UnknownPrompts(prompts) => {
+            Error::invalid_request(Some(&format!("Unknown prompts: {}", prompts.join(", "))))
+        }
         GraphError::UnhandledError(e) => Error::InternalAnyhowError(e),
     }
 }
@@ -128,6 +197,16 @@ pub fn pipeline_runner_to_http_error(e: PipelineRunnerError, run_id: Uuid) -> Er
             )
             .as_str(),
         )),
+        PipelineRunnerError::MissingSecretsError(e) => Error::invalid_request(Some(
+            format!(
+                "Missing secrets: {}",
+                e.missing_secrets.into_iter().join(", ")
+            )
+            .as_str(),
+        )),
+        PipelineRunnerError::MissingCredentialsError(e) => {
+            Error::missing_credentials(&e.missing_credentials)
+        }
         // TODO: rethink how trace writing errors are handled. For now,
         // trace write results are ignored using `let _ =`
         PipelineRunnerError::TraceWritingError(e) => Error::InternalAnyhowError(anyhow::anyhow!(e)),
@@ -147,20 +226,40 @@ impl ResponseError for Error {
             Self::InternalAnyhowError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::MultipartError(_) => StatusCode::BAD_REQUEST,
             Self::RequestError { .. } => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         error!("Error: {:?}", self.to_string());
+        match self.response_body() {
+            Some(body) => HttpResponse::build(self.status_code()).json(body),
+            None => HttpResponse::build(self.status_code()).finish(),
+        }
+    }
+}
+
+impl Error {
+    /// JSON body of the error response, None for errors which are responded without a body
+    pub fn response_body(&self) -> Option<Value> {
         match &self {
             Self::RequestError {
                 error_code,
                 error_message,
-            } => HttpResponse::BadRequest().json(serde_json::json!({
-                    "error_code": error_code.clone(),
-                    "error_message": error_message.clone(),
+            } => Some(serde_json::json!({
+                "error_code": error_code.clone(),
+                "error_message": error_message.clone(),
+            })),
+            Self::Forbidden(message) => Some(serde_json::json!({
+                "error_code": "api.Forbidden",
+                "error_message": message,
+            })),
+            Self::Conflict(message) => Some(serde_json::json!({
+                "error_code": "api.Conflict",
+                "error_message": message,
             })),
-            _ => HttpResponse::build(self.status_code()).finish(),
+            _ => None,
         }
     }
 }
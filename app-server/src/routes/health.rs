@@ -0,0 +1,194 @@
+use std::{
+    env,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{get, web, HttpResponse};
+use lapin::{options::QueueDeclareOptions, types::FieldTable, Connection};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::ResponseResult;
+use crate::{
+    db::DB,
+    runs::{EngineStats, InterruptSenders},
+    traces::OBSERVATIONS_QUEUE,
+};
+
+/// Every check is bounded, so that the probe answers even if a dependency hangs
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_MAX_QUEUED_OBSERVATIONS: u32 = 10_000;
+
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckResult {
+    status: CheckStatus,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadinessChecks {
+    database: CheckResult,
+    clickhouse: CheckResult,
+    observations_queue: CheckResult,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EngineStatsResponse {
+    active_runs: usize,
+    queued_runs: usize,
+    draining: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadinessResponse {
+    ready: bool,
+    checks: ReadinessChecks,
+    engine: EngineStatsResponse,
+}
+
+async fn run_check<F>(check: F) -> CheckResult
+where
+    F: Future<Output = anyhow::Result<Option<Value>>>,
+{
+    let start = Instant::now();
+    let res = tokio::time::timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let (status, error, details) = match res {
+        Ok(Ok(details)) => (CheckStatus::Ok, None, details),
+        Ok(Err(e)) => (CheckStatus::Failed, Some(e.to_string()), None),
+        Err(_) => (
+            CheckStatus::Failed,
+            Some(format!("Timed out after {}ms", CHECK_TIMEOUT.as_millis())),
+            None,
+        ),
+    };
+
+    CheckResult {
+        status,
+        latency_ms,
+        error,
+        details,
+    }
+}
+
+async fn check_database(db: &DB) -> anyhow::Result<Option<Value>> {
+    sqlx::query("SELECT 1").execute(&db.pool).await?;
+    Ok(None)
+}
+
+async fn check_clickhouse(clickhouse: &clickhouse::Client) -> anyhow::Result<Option<Value>> {
+    clickhouse.query("SELECT 1").execute().await?;
+    Ok(None)
+}
+
+/// Observations are exported to ClickHouse in the background through RabbitMQ. If the
+/// collector falls behind, new traces would only show up with a growing delay.
+async fn check_observations_queue(
+    rabbitmq_connection: &Connection,
+) -> anyhow::Result<Option<Value>> {
+    if !rabbitmq_connection.status().connected() {
+        return Err(anyhow::anyhow!("RabbitMQ connection is closed"));
+    }
+    let max_message_count = env::var("MAX_QUEUED_OBSERVATIONS")
+        .ok()
+        .and_then(|count| count.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_MAX_QUEUED_OBSERVATIONS);
+
+    let channel = rabbitmq_connection.create_channel().await?;
+    let queue = channel
+        .queue_declare(
+            OBSERVATIONS_QUEUE,
+            QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await;
+    let _ = channel.close(200, "OK").await;
+    let message_count = queue?.message_count();
+
+    if message_count > max_message_count {
+        return Err(anyhow::anyhow!(
+            "{} observations are queued, more than {}",
+            message_count,
+            max_message_count
+        ));
+    }
+    Ok(Some(serde_json::json!({
+        "messageCount": message_count,
+        "maxMessageCount": max_message_count,
+    })))
+}
+
+/// The process is up and serving requests
+#[get("live")]
+async fn live() -> ResponseResult {
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The instance can serve runs: its dependencies are reachable, the background exporter keeps
+/// up, and it is not draining before shutdown. Responds with 503 otherwise, with per-check
+/// status and latency in both cases.
+#[get("ready")]
+async fn ready(
+    db: web::Data<DB>,
+    clickhouse: web::Data<clickhouse::Client>,
+    rabbitmq_connection: web::Data<Arc<Connection>>,
+    engine_stats: web::Data<Arc<EngineStats>>,
+    interrupt_senders: web::Data<Arc<InterruptSenders>>,
+) -> ResponseResult {
+    let (database, clickhouse, observations_queue) = tokio::join!(
+        run_check(check_database(&db)),
+        run_check(check_clickhouse(&clickhouse)),
+        run_check(check_observations_queue(&rabbitmq_connection)),
+    );
+    let checks = ReadinessChecks {
+        database,
+        clickhouse,
+        observations_queue,
+    };
+    let engine = EngineStatsResponse {
+        active_runs: interrupt_senders.len(),
+        queued_runs: engine_stats.queued_runs(),
+        draining: engine_stats.is_draining(),
+    };
+
+    let ready = !engine.draining
+        && [
+            &checks.database,
+            &checks.clickhouse,
+            &checks.observations_queue,
+        ]
+        .iter()
+        .all(|check| check.status == CheckStatus::Ok);
+    let res = ReadinessResponse {
+        ready,
+        checks,
+        engine,
+    };
+
+    if ready {
+        Ok(HttpResponse::Ok().json(res))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(res))
+    }
+}
@@ -0,0 +1,336 @@
+use std::sync::Arc;
+
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    datasets::{datapoints::Datapoint, import, schema::RowFields, DatasetLimits},
+    db::{self, labeling_queues::RunLease, runs::Run, user::User, DB},
+    labeling::{self, LabelSchema, QueueFilter, DEFAULT_LEASE_SECONDS},
+    pipeline::runner::PipelineRunner,
+    routes::{error::Error, ResponseResult},
+    semantic_search::SemanticSearch,
+};
+
+/// Leasing is retried when a concurrent request leased the selected run first
+const LEASE_ATTEMPTS: usize = 3;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct CreateQueueRequest {
+    name: String,
+    #[serde(default)]
+    filter: QueueFilter,
+    #[serde(default)]
+    label_schema: LabelSchema,
+    /// How long a reviewer holds the run they got with `next`, 600 by default
+    #[serde(default)]
+    lease_seconds: Option<i32>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/queues",
+    tag = "labeling",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(content = inline(CreateQueueRequest)),
+    responses((status = 200, body = LabelingQueue)),
+    security(("user_api_key" = [])),
+)]
+#[post("queues")]
+async fn create_queue(
+    db: web::Data<DB>,
+    project_id: web::Path<Uuid>,
+    req: web::Json<CreateQueueRequest>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let req = req.into_inner();
+    req.filter
+        .validate()
+        .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+    req.label_schema
+        .validate()
+        .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+    let lease_seconds = req.lease_seconds.unwrap_or(DEFAULT_LEASE_SECONDS);
+    if lease_seconds <= 0 {
+        return Err(Error::invalid_request(Some(
+            "Lease duration must be positive",
+        )));
+    }
+
+    let queue = db::labeling_queues::create_queue(
+        &db.pool,
+        &project_id,
+        &req.name,
+        &req.filter,
+        &req.label_schema,
+        lease_seconds,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(queue))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/queues",
+    tag = "labeling",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    responses((status = 200, body = [LabelingQueue])),
+    security(("user_api_key" = [])),
+)]
+#[get("queues")]
+async fn get_queues(db: web::Data<DB>, project_id: web::Path<Uuid>) -> ResponseResult {
+    let queues = db::labeling_queues::get_queues(&db.pool, &project_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(queues))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LeasedRun {
+    pub lease: RunLease,
+    pub run: Run,
+}
+
+/// Lease the next run of the queue to label
+///
+/// The run isn't given to other reviewers until the lease expires or the run is labeled.
+/// Requests of a reviewer who holds a lease in the queue renew it and get the same run. Returns
+/// 204 if there are no unlabeled runs left which aren't leased.
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/queues/{queue_id}/next",
+    tag = "labeling",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("queue_id" = Uuid, Path, description = "Id of the labeling queue"),
+    ),
+    responses(
+        (status = 200, body = LeasedRun),
+        (status = 204, description = "No runs left to label"),
+        (status = 404, description = "Queue not found"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("queues/{queue_id}/next")]
+async fn lease_next_run(
+    user: User,
+    db: web::Data<DB>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> ResponseResult {
+    let (project_id, queue_id) = path.into_inner();
+    let Some(queue) = db::labeling_queues::get_queue(&db.pool, &project_id, &queue_id).await?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    for _ in 0..LEASE_ATTEMPTS {
+        let Some(lease) = db::labeling_queues::lease_next_run(&db.pool, &queue, &user.id).await?
+        else {
+            continue;
+        };
+        let Some(run) = db::runs::get_run(&db.pool, &lease.run_id, &project_id).await? else {
+            continue;
+        };
+        return Ok(HttpResponse::Ok().json(LeasedRun { lease, run }));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SubmitLabelRequest {
+    run_id: Uuid,
+    /// Values of the fields of the queue's label schema
+    values: serde_json::Map<String, Value>,
+}
+
+/// Label a run of the queue, releasing its lease
+///
+/// Labeling a run again replaces its label. Fails with 409 if another reviewer holds the lease.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/queues/{queue_id}/labels",
+    tag = "labeling",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("queue_id" = Uuid, Path, description = "Id of the labeling queue"),
+    ),
+    request_body(content = inline(SubmitLabelRequest)),
+    responses(
+        (status = 200, body = RunLabel),
+        (status = 404, description = "Queue not found, or the run is not in it"),
+        (status = 409, description = "Run is leased by another reviewer"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[post("queues/{queue_id}/labels")]
+async fn submit_label(
+    user: User,
+    db: web::Data<DB>,
+    path: web::Path<(Uuid, Uuid)>,
+    req: web::Json<SubmitLabelRequest>,
+) -> ResponseResult {
+    let (project_id, queue_id) = path.into_inner();
+    let req = req.into_inner();
+    let Some(queue) = db::labeling_queues::get_queue(&db.pool, &project_id, &queue_id).await?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if !db::labeling_queues::is_queue_run(&db.pool, &queue, &req.run_id).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    queue
+        .label_schema
+        .check_values(&req.values)
+        .map_err(|e| Error::invalid_request(Some(&e)))?;
+
+    let label = db::labeling_queues::submit_label(
+        &db.pool,
+        &queue_id,
+        &req.run_id,
+        &user.id,
+        &Value::Object(req.values),
+    )
+    .await?
+    .ok_or_else(|| Error::Conflict("Run is leased by another reviewer".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(label))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/queues/{queue_id}/labels",
+    tag = "labeling",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("queue_id" = Uuid, Path, description = "Id of the labeling queue"),
+    ),
+    responses((status = 200, body = [RunLabel]), (status = 404, description = "Queue not found")),
+    security(("user_api_key" = [])),
+)]
+#[get("queues/{queue_id}/labels")]
+async fn get_labels(db: web::Data<DB>, path: web::Path<(Uuid, Uuid)>) -> ResponseResult {
+    let (project_id, queue_id) = path.into_inner();
+    if db::labeling_queues::get_queue(&db.pool, &project_id, &queue_id)
+        .await?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let labels = db::labeling_queues::get_labels(&db.pool, &queue_id).await?;
+
+    Ok(HttpResponse::Ok().json(labels))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ExportLabelsRequest {
+    dataset_id: Uuid,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LabelExport {
+    pub datapoints: usize,
+    /// Runs without recorded node inputs and outputs, exported with empty data
+    pub runs_without_inputs: usize,
+}
+
+/// Export the labels of the queue to a dataset
+///
+/// Each labeled run becomes a datapoint, with the inputs of the run as data and the label values
+/// as target. Inputs are read from the run's recorded node inputs and outputs, see
+/// `recordNodeIo`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/queues/{queue_id}/export",
+    tag = "labeling",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("queue_id" = Uuid, Path, description = "Id of the labeling queue"),
+    ),
+    request_body(content = inline(ExportLabelsRequest)),
+    responses((status = 200, body = LabelExport), (status = 404, description = "Queue not found")),
+    security(("user_api_key" = [])),
+)]
+#[post("queues/{queue_id}/export")]
+async fn export_labels(
+    db: web::Data<DB>,
+    path: web::Path<(Uuid, Uuid)>,
+    req: web::Json<ExportLabelsRequest>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    semantic_search: web::Data<Arc<SemanticSearch>>,
+) -> ResponseResult {
+    let (project_id, queue_id) = path.into_inner();
+    let dataset_id = req.dataset_id;
+    if db::labeling_queues::get_queue(&db.pool, &project_id, &queue_id)
+        .await?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let dataset = db::datasets::get_dataset(&db.pool, project_id, dataset_id).await?;
+
+    let labels = db::labeling_queues::get_labels(&db.pool, &queue_id).await?;
+    let mut schema = dataset.schema.clone();
+    let mut new_datapoints = Vec::with_capacity(labels.len());
+    let mut runs_without_inputs = 0;
+    for label in labels {
+        let records = pipeline_runner
+            .node_io_store()
+            .get_run(&label.run_id)
+            .await?;
+        let inputs = labeling::run_inputs(&records);
+        if inputs.is_empty() {
+            runs_without_inputs += 1;
+        }
+        let data = schema.apply(RowFields::Json(inputs)).map_err(|e| {
+            Error::invalid_request(Some(&format!("Inputs of run {}: {e}", label.run_id)))
+        })?;
+        new_datapoints.push(Datapoint {
+            id: Uuid::new_v4(),
+            dataset_id,
+            data: Value::Object(data),
+            target: label.values,
+        });
+    }
+
+    let limits = DatasetLimits::from_env();
+    let usage = db::datapoints::get_dataset_usage(&db.pool, dataset_id).await?;
+    let size = new_datapoints.iter().map(import::datapoint_size).sum();
+    if !limits.allows(&usage, new_datapoints.len() as u64, size) {
+        return Err(Error::limit_error(&format!(
+            "Datasets can have at most {} datapoints and {} bytes of data",
+            limits.max_rows, limits.max_size
+        )));
+    }
+
+    let datapoints =
+        db::datapoints::insert_datapoints(&db.pool, &dataset_id, new_datapoints).await?;
+    if schema != dataset.schema {
+        db::datasets::update_dataset_schema(&db.pool, dataset_id, &schema).await?;
+    }
+    let export = LabelExport {
+        datapoints: datapoints.len(),
+        runs_without_inputs,
+    };
+
+    if dataset.indexed_on.is_some() {
+        dataset
+            .index_new_points(
+                datapoints,
+                semantic_search.as_ref().clone(),
+                project_id.to_string(),
+                dataset.indexed_on.clone(),
+            )
+            .await?;
+    }
+
+    Ok(HttpResponse::Ok().json(export))
+}
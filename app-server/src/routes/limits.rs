@@ -2,7 +2,7 @@ use actix_web::{get, web, HttpResponse};
 
 use crate::db::{self, user::User, DB};
 
-use super::ResponseResult;
+use super::{error::Error, ResponseResult};
 
 #[get("user")]
 pub async fn get_user_stats(user: User, db: web::Data<DB>) -> ResponseResult {
@@ -18,10 +18,16 @@ pub async fn get_user_storage_stats(user: User, db: web::Data<DB>) -> ResponseRe
 
 #[get("workspace/{workspace_id}")]
 pub async fn get_workspace_stats(
+    user: User,
     workspace_id: web::Path<uuid::Uuid>,
     db: web::Data<DB>,
 ) -> ResponseResult {
     let workspace_id = workspace_id.into_inner();
+    if !user.is_member_of_workspace(&workspace_id) {
+        return Err(Error::Forbidden(
+            "User is not a member of the workspace".to_string(),
+        ));
+    }
     let stats = db::limits::get_workspace_stats(&db.pool, &workspace_id).await?;
     Ok(HttpResponse::Ok().json(stats))
 }
@@ -1,14 +1,22 @@
 pub mod api_keys;
 pub mod auth;
 pub mod datasets;
+pub mod dead_letters;
 pub mod error;
 pub mod evaluations;
 pub mod events;
+pub mod health;
+pub mod labeling_queues;
 pub mod limits;
+pub mod node_types;
 pub mod pipelines;
 pub mod projects;
+pub mod prompts;
+pub mod secrets;
+pub mod semantic_index;
 pub mod traces;
 pub mod types;
+pub mod webhooks;
 pub mod workspace;
 
 use types::*;
@@ -0,0 +1,39 @@
+use actix_web::{get, HttpResponse};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::pipeline::nodes::registry;
+
+use super::ResponseResult;
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeTypeSchema {
+    pub node_type: String,
+    /// JSON schema of the node type's config, as in graph JSON
+    #[schema(value_type = Object)]
+    pub schema: Value,
+}
+
+/// Node types graphs can have, with the schemas to render and validate their configs with
+#[utoipa::path(
+    get,
+    path = "/api/v1/node_types",
+    tag = "pipelines",
+    responses((status = 200, body = Vec<NodeTypeSchema>)),
+    security(("user_api_key" = [])),
+)]
+#[get("")]
+pub async fn get_node_types() -> ResponseResult {
+    let registry = registry::registry();
+    let node_types = registry
+        .node_types()
+        .into_iter()
+        .map(|node_type| NodeTypeSchema {
+            node_type: node_type.to_string(),
+            schema: registry.schema(node_type).cloned().unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(node_types))
+}
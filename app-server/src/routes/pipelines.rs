@@ -1,31 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 use super::ResponseResult;
 use crate::db::pipelines::pipeline_version::PipelineVersionInfo;
+use crate::db::records::RecordOwners;
+use crate::db::runs::{RunCursor, RunFilters, RunStatus, RunSummary};
+use crate::pipeline::bundle::{BundlePipeline, PipelineBundle};
 use crate::pipeline::nodes::Message;
 use crate::pipeline::trace::{RunTrace, RunTraceStats};
-use crate::pipeline::utils::get_target_pipeline_version_cache_key;
+use crate::pipeline::utils::{get_graph_content_hash, get_target_pipeline_version_cache_key};
+use crate::pipeline::validation::{validate_graph_json, GraphDiagnostic};
 use crate::{
     cache::Cache,
+    ch,
     db::{
         self,
+        modifiers::GroupByInterval,
         pipelines::{pipeline_version, write_pipeline, Pipeline, PipelineVersion},
         DB,
     },
+    language_model::LanguageModelRunner,
     pipeline::{
-        nodes::{NodeInput, StreamChunk},
+        inputs, model_defaults,
+        nodes::{Node, NodeInput, StreamChunk},
+        prompts,
         runner::PipelineRunner,
+        simulate::{self, Estimator, SimulationConfig},
         templates::insert_node_ids_to_template,
         Graph, RunType,
     },
     routes::error::{self, graph_error_to_http_error},
+    runs::checkpoints::{self, RunCheckpoints},
+    secrets,
 };
 
 const DEFAULT_NEW_PIPELINE_VERSION_ID_STRING: &str = "db6d1708-9836-42f2-a3ea-732ca7709039";
@@ -65,13 +79,18 @@ async fn run_pipeline_interrupt_graph(
 #[serde(rename_all(deserialize = "camelCase"))]
 struct GraphRunRequest {
     run_id: Uuid,
-    graph: Graph,
+    /// Graph JSON, its content hash identifies the graph of a paused run
+    graph: serde_json::Value,
     inputs: HashMap<String, NodeInput>,
     env: HashMap<String, String>,
     pipeline_version_id: Uuid,
     prefilled_messages: Option<Vec<Message>>,
     breakpoint_task_ids: Option<Vec<Uuid>>,
     start_task_id: Option<Uuid>,
+    /// Resume the run with `run_id` paused at a breakpoint from its checkpoint, which works on
+    /// any instance, unlike `Continue` interrupts of the instance running it
+    #[serde(default)]
+    resume: bool,
 }
 
 #[post("pipelines/run/graph")]
@@ -80,14 +99,29 @@ async fn run_pipeline_graph(
     pipeline_runner: web::Data<Arc<PipelineRunner>>,
     params: web::Json<GraphRunRequest>,
     interrupt_senders: web::Data<Arc<DashMap<Uuid, mpsc::Sender<GraphInterruptMessage>>>>,
+    db: web::Data<DB>,
+    record_owners: web::Data<dyn RecordOwners>,
 ) -> ResponseResult {
     let project_id = project_id.into_inner();
     let params = params.into_inner();
-    let mut graph = params.graph;
+    let graph_hash = get_graph_content_hash(&params.graph);
+    let mut graph = serde_json::from_value::<Graph>(params.graph)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
     let inputs = params.inputs;
     let pipeline_version_id = params.pipeline_version_id;
     let run_id = params.run_id;
     let run_type = RunType::Workshop;
+    // the run is traced as a run of the version, which must be of the project
+    let version = db::records::ProjectRecord::PipelineVersion(pipeline_version_id);
+    if record_owners
+        .get_owner(&version)
+        .await?
+        .is_some_and(|owner| owner.project_id != project_id)
+    {
+        return Err(error::Error::Forbidden(
+            "Pipeline version is not part of the project".to_string(),
+        ));
+    }
     let prefilled_messages = params.prefilled_messages;
     let start_task_id = params.start_task_id;
     let breakpoint_task_ids = params.breakpoint_task_ids;
@@ -101,6 +135,50 @@ async fn run_pipeline_graph(
     graph
         .setup(&inputs, &env, &HashMap::new(), &run_type)
         .map_err(graph_error_to_http_error)?;
+    graph.secrets = secrets::get_project_secrets(&db.pool, &project_id).await?;
+    graph.workspace_model_defaults =
+        db::model_defaults::get_project_model_defaults(&db.pool, &project_id).await?;
+    graph.workspace_id =
+        Some(db::workspace::get_workspace_id_of_project(&db.pool, &project_id).await?);
+    prompts::load_prompts(&db.pool, &project_id, &mut graph, &HashMap::new())
+        .await
+        .map_err(|e| error::pipeline_runner_to_http_error(e, run_id))?;
+
+    let checkpoint_store = pipeline_runner.checkpoint_store();
+    let checkpoint = if params.resume {
+        let Some(checkpoint) = checkpoint_store.claim(&run_id, &graph_hash).await? else {
+            return Err(error::Error::invalid_request(Some(
+                "Run is not paused, or it was paused with a different graph",
+            )));
+        };
+        Some((checkpoint.generation, Some(checkpoint)))
+    } else if breakpoint_task_ids
+        .as_ref()
+        .is_some_and(|task_ids| !task_ids.is_empty())
+    {
+        let generation = checkpoint_store.start(&run_id, &graph_hash).await?;
+        Some((generation, None))
+    } else {
+        None
+    };
+    let checkpoints = checkpoint.map(|(generation, resume_from)| {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(checkpoints::write_checkpoints(
+            checkpoint_store,
+            run_id,
+            generation,
+            events_rx,
+        ));
+        RunCheckpoints {
+            events: events_tx,
+            resume_from,
+        }
+    });
+    let (prefilled_messages, start_task_id) = if params.resume {
+        (None, None)
+    } else {
+        (prefilled_messages, start_task_id)
+    };
 
     let stream = async_stream::stream! {
         let (tx, mut rx) = mpsc::channel::<StreamChunk>(100);
@@ -114,6 +192,7 @@ async fn run_pipeline_graph(
                     start_task_id,
                     breakpoint_task_ids,
                     interrupt_rx,
+                    checkpoints,
                 ).await;
 
             let trace = PipelineRunner::get_trace_from_result(&run_result);
@@ -170,6 +249,14 @@ async fn run_pipeline_graph(
         .streaming(stream))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines",
+    tag = "pipelines",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    responses((status = 200, body = [PipelineWithTargetVersion])),
+    security(("user_api_key" = [])),
+)]
 #[get("pipelines")]
 async fn get_pipelines(project_id: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
     let pipelines =
@@ -179,7 +266,7 @@ async fn get_pipelines(project_id: web::Path<Uuid>, db: web::Data<DB>) -> Respon
     Ok(HttpResponse::Ok().json(pipelines))
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct CreatePipelineRequest {
     pub project_id: Uuid,
@@ -189,6 +276,16 @@ struct CreatePipelineRequest {
     pub template_id: Option<Uuid>,
 }
 
+/// Create a pipeline, with a first version from the template
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/pipelines",
+    tag = "pipelines",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(content = inline(CreatePipelineRequest)),
+    responses((status = 200, body = Pipeline)),
+    security(("user_api_key" = [])),
+)]
 #[post("pipelines")]
 async fn create_pipeline(
     request: web::Json<CreatePipelineRequest>,
@@ -222,12 +319,24 @@ async fn create_pipeline(
         pipeline_version_name,
         &template.displayable_graph,
         &template.runnable_graph,
+        None,
     )
     .await?;
 
     Ok(HttpResponse::Ok().json(pipeline))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+    ),
+    responses((status = 200, body = PipelineWithTargetVersion)),
+    security(("user_api_key" = [])),
+)]
 #[get("pipelines/{pipeline_id}")]
 async fn get_pipeline_by_id(params: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
     let (_project_id, pipeline_id) = params.into_inner();
@@ -238,6 +347,18 @@ async fn get_pipeline_by_id(params: web::Path<(Uuid, Uuid)>, db: web::Data<DB>)
 }
 
 /// Update pipeline name or visibility (public/private) or both.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+    ),
+    request_body(content = Pipeline),
+    responses((status = 200, body = Pipeline)),
+    security(("user_api_key" = [])),
+)]
 #[post("pipelines/{pipeline_id}")]
 async fn update_pipeline(
     params: web::Path<(Uuid, Uuid)>,
@@ -262,6 +383,17 @@ async fn update_pipeline(
     Ok(HttpResponse::Ok().json(updated_pipeline))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+    ),
+    responses((status = 200)),
+    security(("user_api_key" = [])),
+)]
 #[delete("pipelines/{pipeline_id}")]
 async fn delete_pipeline(params: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
     let (_project_id, pipeline_id) = params.into_inner();
@@ -287,21 +419,60 @@ async fn update_target_pipeline_version(
 ) -> ResponseResult {
     let req = req.into_inner();
     let (project_id, pipeline_id) = params.into_inner();
-    let cache = cache.into_inner();
-    let pipeline_version_id = req.pipeline_version_id;
 
+    let target_pipeline_version = set_target_pipeline_version(
+        &db,
+        cache.into_inner(),
+        project_id,
+        pipeline_id,
+        req.pipeline_version_id,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(target_pipeline_version))
+}
+
+/// Promote a COMMIT pipeline version to the `production` alias, i.e. make it the target version
+#[post("pipelines/{pipeline_id}/versions/{version_id}/promote")]
+async fn promote_pipeline_version(
+    path: web::Path<(Uuid, Uuid, Uuid)>,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+) -> ResponseResult {
+    let (project_id, pipeline_id, version_id) = path.into_inner();
+
+    let target_pipeline_version =
+        set_target_pipeline_version(&db, cache.into_inner(), project_id, pipeline_id, version_id)
+            .await?;
+
+    Ok(HttpResponse::Ok().json(target_pipeline_version))
+}
+
+async fn set_target_pipeline_version(
+    db: &DB,
+    cache: Arc<Cache>,
+    project_id: Uuid,
+    pipeline_id: Uuid,
+    pipeline_version_id: Uuid,
+) -> Result<pipeline_version::TargetPipelineVersion, error::Error> {
     let pipeline_version =
         db::pipelines::pipeline_version::get_pipeline_version_with_pipeline_name(
             &db.pool,
             &pipeline_version_id,
         )
         .await?;
+    if pipeline_version.pipeline_id != pipeline_id {
+        return Err(error::Error::invalid_request(Some(
+            "Pipeline version does not belong to the pipeline",
+        )));
+    }
     if pipeline_version.pipeline_type != "COMMIT" {
         return Err(error::Error::invalid_request(Some(
             "Only COMMIT pipeline versions can be set as target",
         )));
     }
 
+    // Single upsert, so runs see either the old or the new target version
     let target_pipeline_version =
         db::pipelines::pipeline_version::create_or_update_target_pipeline_version(
             &db.pool,
@@ -316,7 +487,7 @@ async fn update_target_pipeline_version(
     );
     let _ = cache.remove::<PipelineVersion>(&cache_key).await;
 
-    Ok(HttpResponse::Ok().json(target_pipeline_version))
+    Ok(target_pipeline_version)
 }
 
 #[derive(Deserialize)]
@@ -346,13 +517,70 @@ async fn create_pipeline_version(
         )));
     }
 
-    db::pipelines::pipeline_version::clone_pipeline_version(
+    let ref_pipeline_version =
+        pipeline_version::get_pipeline_version(&db.pool, &ref_pipeline_version_id).await?;
+    let content_hash = get_graph_content_hash(&ref_pipeline_version.runnable_graph);
+
+    // COMMIT versions are immutable, so committing an unchanged graph returns the existing version
+    if let Some(existing_version) = pipeline_version::get_commit_pipeline_version_by_hash(
+        &db.pool,
+        &ref_pipeline_version.pipeline_id,
+        &content_hash,
+    )
+    .await?
+    {
+        return Ok(HttpResponse::Ok().json(existing_version));
+    }
+
+    let new_pipeline_version_id = db::pipelines::pipeline_version::clone_pipeline_version(
         &db.pool,
         ref_pipeline_version_id,
         &new_pipeline_name,
         &new_pipeline_type,
+        Some(&content_hash),
     )
     .await?;
+    let new_pipeline_version =
+        pipeline_version::get_pipeline_version(&db.pool, &new_pipeline_version_id).await?;
+
+    Ok(HttpResponse::Ok().json(new_pipeline_version))
+}
+
+/// Delete a COMMIT pipeline version
+///
+/// Versions which are the target version or were executed by recorded runs cannot be deleted.
+#[delete("pipelines/{pipeline_id}/versions/{version_id}")]
+async fn delete_pipeline_version(
+    db: web::Data<DB>,
+    path: web::Path<(Uuid, Uuid, Uuid)>,
+) -> ResponseResult {
+    let (_project_id, pipeline_id, version_id) = path.into_inner();
+
+    let version = pipeline_version::get_pipeline_version(&db.pool, &version_id).await?;
+    if version.pipeline_id != pipeline_id {
+        return Err(error::Error::invalid_request(Some(
+            "Pipeline version does not belong to the pipeline",
+        )));
+    }
+    if version.pipeline_type != "COMMIT" {
+        return Err(error::Error::invalid_request(Some(
+            "Only COMMIT pipeline versions can be deleted",
+        )));
+    }
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.target_version_id == Some(version_id) {
+        return Err(error::Error::invalid_request(Some(
+            "Target pipeline version cannot be deleted, promote another version first",
+        )));
+    }
+    if pipeline_version::is_pipeline_version_referenced(&db.pool, &version_id).await? {
+        return Err(error::Error::invalid_request(Some(
+            "Pipeline version is referenced by recorded runs or evaluations and cannot be deleted",
+        )));
+    }
+
+    pipeline_version::delete_pipeline_version(&db.pool, &version_id).await?;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -415,6 +643,753 @@ async fn get_pipeline_versions_info(
     }))
 }
 
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct GraphDotParams {
+    /// Version to render, the target version of the pipeline by default
+    #[serde(default)]
+    version_id: Option<Uuid>,
+}
+
+/// Graph of the pipeline version in Graphviz DOT
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/graph.dot",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        GraphDotParams,
+    ),
+    responses(
+        (status = 200, description = "DOT of the graph", content_type = "text/vnd.graphviz", body = String),
+        (status = 400, description = "Pipeline or version not found, or the graph is invalid"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/graph.dot")]
+async fn get_pipeline_graph_dot(
+    path: web::Path<(Uuid, Uuid)>,
+    params: web::Query<GraphDotParams>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    let Some(version_id) = params.version_id.or(pipeline.target_version_id) else {
+        return Err(error::Error::invalid_request(Some(
+            "Pipeline has no target version, set versionId",
+        )));
+    };
+    let version = pipeline_version::get_pipeline_version(&db.pool, &version_id).await?;
+    if version.pipeline_id != pipeline_id {
+        return Err(error::Error::invalid_request(Some("Version not found")));
+    }
+    let dot = pipeline_runner
+        .version_dot(&version, None)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/vnd.graphviz")
+        .body(dot))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GraphDiffParams {
+    /// Id or name of the old version
+    from: String,
+    /// Id or name of the new version
+    to: String,
+}
+
+/// Semantic diff of the graphs of two versions of the pipeline
+///
+/// Lists the added and removed nodes and edges, and the config fields of the changed nodes.
+/// Canvas positions and other fields which don't change how nodes run are ignored.
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/diff",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        GraphDiffParams,
+    ),
+    responses(
+        (status = 200, description = "Changes of the new version", body = GraphDiff),
+        (status = 400, description = "Pipeline or version not found, or a graph is invalid"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/diff")]
+async fn get_pipeline_diff(
+    path: web::Path<(Uuid, Uuid)>,
+    params: web::Query<GraphDiffParams>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    let versions = db::pipelines::get_pipeline_versions(&db.pool, &pipeline_id).await?;
+    let find_version = |version: &str| {
+        versions
+            .iter()
+            .find(|v| v.id.to_string() == version)
+            .or_else(|| versions.iter().find(|v| v.name == version))
+            .ok_or_else(|| {
+                error::Error::invalid_request(Some(&format!("Version {} not found", version)))
+            })
+    };
+    let from = find_version(&params.from)?;
+    let to = find_version(&params.to)?;
+
+    let diff = pipeline_runner
+        .diff_versions(from, to)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+
+    Ok(HttpResponse::Ok().json(diff))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct InputSchemaParams {
+    /// Version whose inputs to describe, the target version of the pipeline by default
+    #[serde(default)]
+    version_id: Option<Uuid>,
+}
+
+/// JSON schema of the inputs of a run of the pipeline version
+///
+/// Properties are the `inputSchema` of each input node, or the schema of its input type if it
+/// declares none. Unknown inputs are disallowed if the graph has `strictInputs`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/input_schema",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        InputSchemaParams,
+    ),
+    responses(
+        (status = 200, description = "JSON schema of the run inputs", body = Object),
+        (status = 400, description = "Pipeline or version not found, or the graph is invalid"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/input_schema")]
+async fn get_pipeline_input_schema(
+    path: web::Path<(Uuid, Uuid)>,
+    params: web::Query<InputSchemaParams>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    let Some(version_id) = params.version_id.or(pipeline.target_version_id) else {
+        return Err(error::Error::invalid_request(Some(
+            "Pipeline has no target version, set versionId",
+        )));
+    };
+    let version = pipeline_version::get_pipeline_version(&db.pool, &version_id).await?;
+    if version.pipeline_id != pipeline_id {
+        return Err(error::Error::invalid_request(Some("Version not found")));
+    }
+    let graph = pipeline_runner
+        .get_version_graph(&version)
+        .map_err(|e| error::Error::deserialization_error(Some(e)))?;
+
+    Ok(HttpResponse::Ok().json(inputs::graph_input_schema(&graph)))
+}
+
+/// Effective model config of an LLM node of the version, with the layer each value comes from
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/versions/{version_id}/nodes/{node_id}/model-config",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        ("version_id" = Uuid, Path, description = "Id of the pipeline version"),
+        ("node_id" = Uuid, Path, description = "Id of the node"),
+    ),
+    responses(
+        (status = 200, description = "Resolved model config", body = ResolvedModelConfig),
+        (status = 400, description = "Version or LLM node not found"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/versions/{version_id}/nodes/{node_id}/model-config")]
+async fn get_node_model_config(
+    path: web::Path<(Uuid, Uuid, Uuid, Uuid)>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+) -> ResponseResult {
+    let (project_id, pipeline_id, version_id, node_id) = path.into_inner();
+
+    let version = pipeline_version::get_pipeline_version(&db.pool, &version_id).await?;
+    if version.pipeline_id != pipeline_id {
+        return Err(error::Error::invalid_request(Some("Version not found")));
+    }
+    let mut graph = pipeline_runner
+        .get_version_graph(&version)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+    graph.workspace_model_defaults =
+        db::model_defaults::get_project_model_defaults(&db.pool, &project_id).await?;
+
+    let graph = model_defaults::resolve_graph(&graph);
+    let config = graph.nodes.values().find_map(|node| match node {
+        Node::LLM(node) if node.id == node_id => Some(node.resolved_config.clone()),
+        _ => None,
+    });
+    match config {
+        Some(Some(config)) => Ok(HttpResponse::Ok().json(config)),
+        // params which aren't a JSON object aren't resolved
+        Some(None) => Err(error::Error::invalid_request(Some(
+            "Model params of the node aren't a JSON object",
+        ))),
+        None => Err(error::Error::invalid_request(Some("LLM node not found"))),
+    }
+}
+
+const SIMULATION_LATENCY_HOURS: i64 = 7 * 24;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct SimulatePipelineRequest {
+    #[serde(flatten)]
+    config: SimulationConfig,
+    /// Version to simulate, the target version of the pipeline by default
+    #[serde(default)]
+    version_id: Option<Uuid>,
+    /// Dataset whose rows are the runs to estimate, instead of `rowCount`
+    #[serde(default)]
+    dataset_id: Option<Uuid>,
+    /// Runs to estimate, 1 by default
+    #[serde(default)]
+    row_count: Option<u64>,
+}
+
+/// Estimate the cost and latency of runs of the pipeline version without calling providers
+///
+/// LLM calls are priced with the prompt tokens of their rendered prompts and the completion
+/// lengths of the config, and their latency is the p50 of the project's LLM spans of the past
+/// week by model. Every route of a switch is simulated, weighted by its branch probability. The
+/// cost of a run is multiplied by the rows of `datasetId`, or by `rowCount`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/simulate",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+    ),
+    request_body(content = inline(SimulatePipelineRequest)),
+    responses(
+        (status = 200, description = "Estimated cost and latency", body = SimulationReport),
+        (status = 400, description = "Pipeline, version or dataset not found, or invalid config"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[post("pipelines/{pipeline_id}/simulate")]
+async fn simulate_pipeline(
+    path: web::Path<(Uuid, Uuid)>,
+    req: web::Json<SimulatePipelineRequest>,
+    db: web::Data<DB>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+    language_model_runner: web::Data<Arc<LanguageModelRunner>>,
+    clickhouse: web::Data<clickhouse::Client>,
+) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+    let req = req.into_inner();
+    req.config
+        .validate()
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    let Some(version_id) = req.version_id.or(pipeline.target_version_id) else {
+        return Err(error::Error::invalid_request(Some(
+            "Pipeline has no target version, set versionId",
+        )));
+    };
+    let version = pipeline_version::get_pipeline_version(&db.pool, &version_id).await?;
+    if version.pipeline_id != pipeline_id {
+        return Err(error::Error::invalid_request(Some("Version not found")));
+    }
+    let mut graph = pipeline_runner
+        .get_version_graph(&version)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+    graph.workspace_model_defaults =
+        db::model_defaults::get_project_model_defaults(&db.pool, &project_id).await?;
+    prompts::load_prompts(&db.pool, &project_id, &mut graph, &HashMap::new())
+        .await
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+
+    let row_count = match req.dataset_id {
+        Some(dataset_id) => {
+            db::datasets::get_dataset(&db.pool, project_id, dataset_id)
+                .await
+                .map_err(|_| error::Error::invalid_request(Some("Dataset not found")))?;
+            db::datapoints::count_datapoints(&db.pool, dataset_id).await?
+        }
+        None => req.row_count.unwrap_or(1),
+    };
+
+    let latencies = match ch::spans::get_model_latency_p50s(
+        clickhouse.as_ref().clone(),
+        project_id,
+        SIMULATION_LATENCY_HOURS,
+    )
+    .await
+    {
+        Ok(latencies) => latencies,
+        Err(e) => {
+            log::warn!(
+                "Failed to get model latencies, simulating without them: {}",
+                e
+            );
+            HashMap::new()
+        }
+    };
+    let estimator = Estimator {
+        language_model: &language_model_runner,
+        latencies: &latencies,
+    };
+    let report = simulate::simulate(&graph, &req.config, &estimator, row_count)
+        .map_err(|e| error::Error::invalid_request(Some(&e.to_string())))?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+const DEFAULT_ROUTES_WINDOW: &str = "7d";
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct NodeRoutesParams {
+    /// Window to count executions over, such as `7d` or `24h`, `7d` by default
+    #[serde(default)]
+    window: Option<String>,
+    /// `minute`, `hour` or `day`, by hour for windows up to 2 days and by day otherwise
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    group_by_interval: Option<GroupByInterval>,
+}
+
+/// Executions of each route of a switch node of the pipeline, over the window and by bucket
+///
+/// Routes are counted across the versions of the pipeline from the spans of the node. The
+/// 20 most executed routes are counted on their own, and the others as `<other>`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/nodes/{node_id}/routes",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        ("node_id" = Uuid, Path, description = "Id of the node"),
+        NodeRoutesParams,
+    ),
+    responses(
+        (status = 200, body = RouteStats),
+        (status = 400, description = "Pipeline not found, or invalid window"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/nodes/{node_id}/routes")]
+async fn get_node_routes(
+    path: web::Path<(Uuid, Uuid, Uuid)>,
+    params: web::Query<NodeRoutesParams>,
+    db: web::Data<DB>,
+    clickhouse: web::Data<clickhouse::Client>,
+) -> ResponseResult {
+    let (project_id, pipeline_id, node_id) = path.into_inner();
+    let params = params.into_inner();
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    let window = params.window.as_deref().unwrap_or(DEFAULT_ROUTES_WINDOW);
+    let Some(past_hours) = ch::routes::parse_window(window) else {
+        return Err(error::Error::invalid_request(Some(&format!(
+            "Invalid window {}, expected e.g. 7d or 24h, of at most {} days",
+            window,
+            ch::routes::MAX_WINDOW_HOURS / 24
+        ))));
+    };
+    let group_by_interval = params.group_by_interval.unwrap_or(if past_hours <= 48 {
+        GroupByInterval::Hour
+    } else {
+        GroupByInterval::Day
+    });
+
+    let stats = ch::routes::get_route_stats(
+        clickhouse.as_ref().clone(),
+        project_id,
+        pipeline_id,
+        node_id,
+        past_hours,
+        group_by_interval,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Export the pipeline with all its versions as a portable JSON bundle
+#[get("pipelines/{pipeline_id}/export")]
+async fn export_pipeline(params: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
+    let (project_id, pipeline_id) = params.into_inner();
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    let versions = db::pipelines::get_pipeline_versions(&db.pool, &pipeline_id).await?;
+
+    let bundle = PipelineBundle::new(
+        BundlePipeline {
+            name: pipeline.name,
+            visibility: pipeline.visibility,
+        },
+        &versions,
+        pipeline.target_version_id,
+    );
+
+    Ok(HttpResponse::Ok().json(bundle))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportPipelineRequest {
+    bundle: PipelineBundle,
+    /// Add the COMMIT versions of the bundle to the existing pipeline with the same name,
+    /// instead of failing if it exists
+    #[serde(default)]
+    as_new_version: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportPipelineResponse {
+    pipeline: db::pipelines::PipelineWithTargetVersion,
+    /// Versions created by the import, COMMIT versions already present in the pipeline are reused
+    versions: Vec<PipelineVersion>,
+}
+
+/// Import a pipeline from a bundle created by `pipelines/{pipeline_id}/export`
+///
+/// The bundle is validated as a whole before anything is written, so node types unknown to this
+/// deployment, invalid node configs and secrets missing in the project are all reported at once.
+#[post("pipelines/import")]
+async fn import_pipeline(
+    project_id: web::Path<Uuid>,
+    req: web::Json<ImportPipelineRequest>,
+    db: web::Data<DB>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let ImportPipelineRequest {
+        bundle,
+        as_new_version,
+    } = req.into_inner();
+
+    let secret_names = db::secrets::get_secrets(&db.pool, &project_id)
+        .await?
+        .into_iter()
+        .map(|secret| secret.name)
+        .collect::<HashSet<_>>();
+    bundle
+        .validate(&secret_names)
+        .map_err(|e| error::Error::invalid_pipeline_bundle(&e))?;
+
+    let existing_pipeline =
+        db::pipelines::get_pipeline_by_name(&db.pool, &project_id, &bundle.pipeline.name).await?;
+    let (pipeline_id, is_new_pipeline) = match existing_pipeline {
+        Some(pipeline) if as_new_version => (pipeline.id, false),
+        Some(_) => {
+            return Err(error::Error::invalid_request(Some(
+                "Pipeline with this name already exists, import it as a new version instead",
+            )))
+        }
+        None => {
+            let pipeline_id = Uuid::new_v4();
+            write_pipeline(
+                &db.pool,
+                pipeline_id,
+                project_id,
+                &bundle.pipeline.name,
+                &bundle.pipeline.visibility,
+            )
+            .await?;
+            (pipeline_id, true)
+        }
+    };
+
+    let mut versions = Vec::new();
+    let mut target_version_id = None;
+    for version in &bundle.versions {
+        let content_hash = version.content_hash.clone().or_else(|| {
+            (version.pipeline_type == "COMMIT")
+                .then(|| get_graph_content_hash(&version.runnable_graph))
+        });
+
+        // The existing pipeline keeps its WORKSHOP version
+        if !is_new_pipeline {
+            if version.pipeline_type != "COMMIT" {
+                continue;
+            }
+            if pipeline_version::get_commit_pipeline_version_by_hash(
+                &db.pool,
+                &pipeline_id,
+                content_hash.as_deref().unwrap_or_default(),
+            )
+            .await?
+            .is_some()
+            {
+                continue;
+            }
+        }
+
+        let new_version = pipeline_version::create_pipeline_version(
+            &db.pool,
+            Uuid::new_v4(),
+            pipeline_id,
+            &version.pipeline_type,
+            &version.name,
+            &version.displayable_graph,
+            &version.runnable_graph,
+            content_hash.as_deref(),
+        )
+        .await?;
+        if content_hash.is_some() && content_hash == bundle.target_version_hash {
+            target_version_id = Some(new_version.id);
+        }
+        versions.push(new_version);
+    }
+
+    if is_new_pipeline {
+        // Pipelines are always edited through a WORKSHOP version, bundles may contain commits only
+        if !versions
+            .iter()
+            .any(|version| version.pipeline_type == "WORKSHOP")
+        {
+            if let Some(latest_version) = bundle.versions.last() {
+                let workshop_version = pipeline_version::create_pipeline_version(
+                    &db.pool,
+                    Uuid::new_v4(),
+                    pipeline_id,
+                    "WORKSHOP",
+                    DEFAULT_PIPELINE_VERSION_NAME,
+                    &latest_version.displayable_graph,
+                    &latest_version.runnable_graph,
+                    None,
+                )
+                .await?;
+                versions.push(workshop_version);
+            }
+        }
+        if let Some(target_version_id) = target_version_id {
+            pipeline_version::create_or_update_target_pipeline_version(
+                &db.pool,
+                pipeline_id,
+                target_version_id,
+            )
+            .await?;
+        }
+    }
+
+    let pipeline = db::pipelines::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+
+    Ok(HttpResponse::Ok().json(ImportPipelineResponse { pipeline, versions }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ValidateGraphRequest {
+    /// Graph JSON, as sent to `pipelines/run/graph`
+    graph: serde_json::Value,
+}
+
+#[derive(Serialize, ToSchema)]
+struct GraphValidation {
+    diagnostics: Vec<GraphDiagnostic>,
+}
+
+/// Validate a graph without running it
+///
+/// Reports all problems of the graph at once, ordered by node id: node configs which don't
+/// match the schemas of their node types, missing outputs, unconnected inputs, cycles without a
+/// cyclic input, and invalid templates, regexes and structured output schemas.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/pipelines/validate",
+    tag = "pipelines",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(content = inline(ValidateGraphRequest)),
+    responses((status = 200, body = inline(GraphValidation))),
+    security(("user_api_key" = [])),
+)]
+#[post("pipelines/validate")]
+async fn validate_pipeline_graph(req: web::Json<ValidateGraphRequest>) -> ResponseResult {
+    let graph = req.into_inner().graph;
+    // compiling large graphs keeps all cores busy, so it runs off the async workers
+    let diagnostics = tokio::task::spawn_blocking(move || validate_graph_json(graph))
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    Ok(HttpResponse::Ok().json(GraphValidation { diagnostics }))
+}
+
+const DEFAULT_RUNS_PAGE_SIZE: i64 = 50;
+const MAX_RUNS_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct GetPipelineRunsParams {
+    #[serde(default)]
+    status: Option<RunStatus>,
+    #[serde(default)]
+    start_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    end_date: Option<DateTime<Utc>>,
+    /// JSON object of metadata tags, all of which must match
+    #[serde(default)]
+    metadata: Option<String>,
+    /// `nextCursor` from the previous page
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default)]
+    page_size: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct PipelineRunSummary {
+    #[serde(flatten)]
+    run: RunSummary,
+    trace_url: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct GetPipelineRunsResponse {
+    #[schema(inline)]
+    runs: Vec<PipelineRunSummary>,
+    /// None if there are no more runs
+    next_cursor: Option<String>,
+}
+
+fn encode_runs_cursor(cursor: &RunCursor) -> String {
+    format!("{}_{}", cursor.created_at.timestamp_micros(), cursor.id)
+}
+
+fn decode_runs_cursor(cursor: &str) -> Option<RunCursor> {
+    let (created_at, id) = cursor.split_once('_')?;
+    Some(RunCursor {
+        created_at: DateTime::from_timestamp_micros(created_at.parse().ok()?)?,
+        id: Uuid::parse_str(id).ok()?,
+    })
+}
+
+/// Runs of the pipeline, most recent first
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/runs",
+    tag = "pipelines",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        GetPipelineRunsParams,
+    ),
+    responses((status = 200, body = inline(GetPipelineRunsResponse))),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/runs")]
+async fn get_pipeline_runs(
+    path: web::Path<(Uuid, Uuid)>,
+    params: web::Query<GetPipelineRunsParams>,
+    db: web::Data<DB>,
+) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+    let params = params.into_inner();
+    let page_size = params
+        .page_size
+        .unwrap_or(DEFAULT_RUNS_PAGE_SIZE)
+        .clamp(1, MAX_RUNS_PAGE_SIZE);
+    let cursor = match &params.cursor {
+        Some(cursor) => Some(
+            decode_runs_cursor(cursor)
+                .ok_or_else(|| error::Error::invalid_request(Some("Invalid cursor")))?,
+        ),
+        None => None,
+    };
+    let metadata = match &params.metadata {
+        Some(metadata) => Some(
+            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(metadata)
+                .map_err(|e| error::Error::deserialization_error(Some(e)))?
+                .into(),
+        ),
+        None => None,
+    };
+    let filters = RunFilters {
+        status: params.status,
+        start_date: params.start_date,
+        end_date: params.end_date,
+        metadata,
+    };
+
+    // fetch one more run to know if there is a next page
+    let mut runs = db::runs::get_pipeline_runs(
+        &db.pool,
+        &project_id,
+        &pipeline_id,
+        &filters,
+        cursor.as_ref(),
+        page_size + 1,
+    )
+    .await?;
+    let next_cursor = if runs.len() as i64 > page_size {
+        runs.truncate(page_size as usize);
+        runs.last().map(|run| {
+            encode_runs_cursor(&RunCursor {
+                created_at: run.created_at,
+                id: run.id,
+            })
+        })
+    } else {
+        None
+    };
+
+    let runs = runs
+        .into_iter()
+        .map(|run| PipelineRunSummary {
+            trace_url: format!("/api/v1/projects/{}/traces/{}", project_id, run.trace_id),
+            run,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(GetPipelineRunsResponse { runs, next_cursor }))
+}
+
 #[get("pipelines/{pipeline_id}/versions/{version_id}")]
 async fn get_pipeline_version(
     db: web::Data<DB>,
@@ -7,7 +7,7 @@ use uuid::Uuid;
 use crate::{
     cache::Cache,
     db::{self, user::User, DB},
-    routes::ResponseResult,
+    routes::{error::Error, ResponseResult},
     semantic_search::SemanticSearch,
 };
 
@@ -71,6 +71,11 @@ async fn create_project(
     project: web::Json<db::projects::Project>,
     semantic_search: web::Data<Arc<SemanticSearch>>,
 ) -> ResponseResult {
+    if !user.is_member_of_workspace(&project.workspace_id) {
+        return Err(Error::Forbidden(
+            "Projects can only be created in workspaces of the user".to_string(),
+        ));
+    }
     let project = db::projects::create_project(&db.pool, &user.id, &project).await?;
     info!("Created new project: {:?}", project);
 
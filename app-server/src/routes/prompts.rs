@@ -0,0 +1,175 @@
+use actix_web::{get, post, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        self,
+        prompts::{Prompt, PromptLabel, PromptVersion},
+        DB,
+    },
+    pipeline::prompts::{is_valid_label, is_valid_name},
+    routes::{error::Error, ResponseResult},
+};
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct CreatePromptVersionRequest {
+    name: String,
+    template: String,
+    /// Labels to point to the new version, e.g. `production`
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+/// Create a prompt, or a new version of the prompt with the same name
+///
+/// Versions are numbered from 1 within their prompt. Nodes referencing the prompt without a
+/// label or version run with the new version from their next run.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/prompts",
+    tag = "prompts",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(content = inline(CreatePromptVersionRequest)),
+    responses((status = 200, body = PromptVersion)),
+    security(("user_api_key" = [])),
+)]
+#[post("prompts")]
+async fn create_prompt_version(
+    db: web::Data<DB>,
+    project_id: web::Path<Uuid>,
+    req: web::Json<CreatePromptVersionRequest>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let req = req.into_inner();
+    if !is_valid_name(&req.name) {
+        return Err(Error::invalid_request(Some(
+            "Prompt names may only contain letters, digits, `_`, `-` and `.`",
+        )));
+    }
+    if let Some(label) = req.labels.iter().find(|label| !is_valid_label(label)) {
+        return Err(Error::invalid_request(Some(&format!(
+            "Invalid label {}, labels are names which aren't numbers",
+            label
+        ))));
+    }
+
+    let version =
+        db::prompts::create_prompt_version(&db.pool, &project_id, &req.name, &req.template).await?;
+    for label in &req.labels {
+        db::prompts::set_prompt_label(&db.pool, &version.prompt_id, label, version.version).await?;
+    }
+
+    Ok(HttpResponse::Ok().json(version))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/prompts",
+    tag = "prompts",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    responses((status = 200, body = [Prompt])),
+    security(("user_api_key" = [])),
+)]
+#[get("prompts")]
+async fn get_prompts(db: web::Data<DB>, project_id: web::Path<Uuid>) -> ResponseResult {
+    let prompts = db::prompts::get_prompts(&db.pool, &project_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(prompts))
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptWithVersions {
+    #[serde(flatten)]
+    pub prompt: Prompt,
+    /// Latest first
+    pub versions: Vec<PromptVersion>,
+    pub labels: Vec<PromptLabel>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/prompts/{name}",
+    tag = "prompts",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("name" = String, Path, description = "Name of the prompt"),
+    ),
+    responses(
+        (status = 200, body = PromptWithVersions),
+        (status = 404, description = "Prompt not found"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[get("prompts/{name}")]
+async fn get_prompt(db: web::Data<DB>, path: web::Path<(Uuid, String)>) -> ResponseResult {
+    let (project_id, name) = path.into_inner();
+    let Some(prompt) = db::prompts::get_prompt(&db.pool, &project_id, &name).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let versions = db::prompts::get_prompt_versions(&db.pool, &prompt.id).await?;
+    let labels = db::prompts::get_prompt_labels(&db.pool, &prompt.id).await?;
+
+    Ok(HttpResponse::Ok().json(PromptWithVersions {
+        prompt,
+        versions,
+        labels,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct SetPromptLabelRequest {
+    version: i32,
+}
+
+/// Point the label of the prompt to a version
+///
+/// Nodes referencing the prompt by the label run with the version from their next run, without
+/// a new version of their pipeline.
+#[utoipa::path(
+    put,
+    path = "/api/v1/projects/{project_id}/prompts/{name}/labels/{label}",
+    tag = "prompts",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("name" = String, Path, description = "Name of the prompt"),
+        ("label" = String, Path, description = "Label of the prompt version"),
+    ),
+    request_body(content = inline(SetPromptLabelRequest)),
+    responses(
+        (status = 200, body = PromptLabel),
+        (status = 404, description = "Prompt not found"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[put("prompts/{name}/labels/{label}")]
+async fn set_prompt_label(
+    db: web::Data<DB>,
+    path: web::Path<(Uuid, String, String)>,
+    req: web::Json<SetPromptLabelRequest>,
+) -> ResponseResult {
+    let (project_id, name, label) = path.into_inner();
+    if !is_valid_label(&label) {
+        return Err(Error::invalid_request(Some(&format!(
+            "Invalid label {}, labels are names which aren't numbers",
+            label
+        ))));
+    }
+    let Some(prompt) = db::prompts::get_prompt(&db.pool, &project_id, &name).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let version = req.into_inner().version;
+    let Some(label) = db::prompts::set_prompt_label(&db.pool, &prompt.id, &label, version).await?
+    else {
+        return Err(Error::invalid_request(Some(&format!(
+            "Prompt {} has no version {}",
+            name, version
+        ))));
+    };
+
+    Ok(HttpResponse::Ok().json(label))
+}
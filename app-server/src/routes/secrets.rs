@@ -0,0 +1,56 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::ResponseResult;
+use crate::{
+    db::{self, DB},
+    routes::error,
+    secrets,
+};
+
+#[derive(Deserialize)]
+struct CreateSecretRequest {
+    name: String,
+    value: String,
+}
+
+/// Create a secret or replace the value of an existing one with the same name
+#[post("secrets")]
+async fn create_secret(
+    project_id: web::Path<Uuid>,
+    db: web::Data<DB>,
+    req: web::Json<CreateSecretRequest>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let req = req.into_inner();
+
+    if !secrets::is_valid_name(&req.name) {
+        return Err(error::Error::invalid_request(Some(
+            "Secret name can only contain letters, digits, '_', '-' and '.'",
+        )));
+    }
+
+    let (nonce, value) = secrets::encrypt(&req.name, &req.value)?;
+    let secret =
+        db::secrets::create_or_update_secret(&db.pool, &project_id, &req.name, &value, &nonce)
+            .await?;
+
+    Ok(HttpResponse::Ok().json(secret))
+}
+
+#[get("secrets")]
+async fn get_secrets(project_id: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
+    let secrets = db::secrets::get_secrets(&db.pool, &project_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(secrets))
+}
+
+#[delete("secrets/{secret_id}")]
+async fn delete_secret(path: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
+    let (project_id, secret_id) = path.into_inner();
+
+    db::secrets::delete_secret(&db.pool, &project_id, &secret_id).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
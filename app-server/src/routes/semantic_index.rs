@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use actix_web::{get, post, put, web, HttpResponse};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    db::{self, semantic_indexes::ReindexJob, DB},
+    routes::{error::Error, ResponseResult},
+    semantic_search::{
+        index::{IndexCollection, IndexConfig},
+        reindex::{self, STALE_JOB_SECONDS},
+        SemanticSearch,
+    },
+};
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticIndex {
+    /// Collection searches of the project hit
+    pub live: IndexCollection,
+    /// Latest reindex job of the project
+    pub latest_job: Option<ReindexJob>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/semantic-index",
+    tag = "datasets",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    responses((status = 200, body = SemanticIndex)),
+    security(("user_api_key" = [])),
+)]
+#[get("semantic-index")]
+async fn get_semantic_index(db: web::Data<DB>, project_id: web::Path<Uuid>) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let live = db::semantic_indexes::get_live_collection(&db.pool, &project_id.to_string()).await?;
+    let latest_job = db::semantic_indexes::get_latest_reindex_job(&db.pool, &project_id).await?;
+
+    Ok(HttpResponse::Ok().json(SemanticIndex { live, latest_job }))
+}
+
+/// Start a reindex job of the project's index with the config, in the background
+async fn start_reindex(
+    db: Arc<DB>,
+    semantic_search: Arc<SemanticSearch>,
+    project_id: Uuid,
+    config: IndexConfig,
+) -> ResponseResult {
+    config
+        .validate()
+        .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+
+    let job_id = Uuid::new_v4();
+    let shadow = IndexCollection {
+        collection_name: reindex::shadow_collection_name(&project_id.to_string(), &job_id),
+        config,
+    };
+    // the collection must exist before the job, writes to the index go to it once the job exists
+    semantic_search.create_index_collection(&shadow).await?;
+    let Some(started) = db::semantic_indexes::start_reindex_job(
+        &db.pool,
+        &project_id,
+        &job_id,
+        &config,
+        &shadow.collection_name,
+    )
+    .await?
+    else {
+        semantic_search
+            .delete_index_collection(shadow.collection_name)
+            .await?;
+        return Err(Error::Conflict(
+            "A reindex job of the project is running".to_string(),
+        ));
+    };
+
+    if let Some(superseded) = started.superseded {
+        if let Err(e) = semantic_search
+            .delete_index_collection(superseded.collection_name)
+            .await
+        {
+            error!(
+                "Failed to delete collection of superseded reindex job {}: {}",
+                superseded.id, e
+            );
+        }
+    }
+    tokio::spawn(reindex::run_reindex_job(
+        db,
+        semantic_search,
+        started.job.clone(),
+    ));
+
+    Ok(HttpResponse::Accepted().json(started.job))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct UpdateSemanticIndexRequest {
+    config: IndexConfig,
+}
+
+/// Set the config of the project's index
+///
+/// If the config changed, existing embeddings are incompatible with it, and a reindex job is
+/// started. Searches hit the current index until the job finishes. Returns the job with 202, or
+/// 200 if the config is unchanged.
+#[utoipa::path(
+    put,
+    path = "/api/v1/projects/{project_id}/semantic-index",
+    tag = "datasets",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(content = inline(UpdateSemanticIndexRequest)),
+    responses(
+        (status = 200, description = "Config is unchanged", body = SemanticIndex),
+        (status = 202, description = "Reindex job is started", body = ReindexJob),
+        (status = 409, description = "A reindex job of the project is running"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[put("semantic-index")]
+async fn update_semantic_index(
+    db: web::Data<DB>,
+    project_id: web::Path<Uuid>,
+    req: web::Json<UpdateSemanticIndexRequest>,
+    semantic_search: web::Data<Arc<SemanticSearch>>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let config = req.into_inner().config;
+
+    let live = db::semantic_indexes::get_live_collection(&db.pool, &project_id.to_string()).await?;
+    if live.config == config {
+        let latest_job =
+            db::semantic_indexes::get_latest_reindex_job(&db.pool, &project_id).await?;
+        return Ok(HttpResponse::Ok().json(SemanticIndex { live, latest_job }));
+    }
+
+    start_reindex(
+        db.into_inner(),
+        semantic_search.as_ref().clone(),
+        project_id,
+        config,
+    )
+    .await
+}
+
+/// Rebuild the project's index with its current config
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/semantic-index/reindex",
+    tag = "datasets",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    responses(
+        (status = 202, description = "Reindex job is started", body = ReindexJob),
+        (status = 409, description = "A reindex job of the project is running"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[post("semantic-index/reindex")]
+async fn reindex_semantic_index(
+    db: web::Data<DB>,
+    project_id: web::Path<Uuid>,
+    semantic_search: web::Data<Arc<SemanticSearch>>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let live = db::semantic_indexes::get_live_collection(&db.pool, &project_id.to_string()).await?;
+
+    start_reindex(
+        db.into_inner(),
+        semantic_search.as_ref().clone(),
+        project_id,
+        live.config,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/semantic-index/jobs/{job_id}",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("job_id" = Uuid, Path, description = "Id of the indexing job"),
+    ),
+    responses((status = 200, body = ReindexJob), (status = 404)),
+    security(("user_api_key" = [])),
+)]
+#[get("semantic-index/jobs/{job_id}")]
+async fn get_reindex_job(db: web::Data<DB>, path: web::Path<(Uuid, Uuid)>) -> ResponseResult {
+    let (project_id, job_id) = path.into_inner();
+    match db::semantic_indexes::get_reindex_job(&db.pool, &project_id, &job_id).await? {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Resume a failed reindex job after its last reindexed batch
+///
+/// Running jobs which made no progress for 5 minutes, e.g. because their instance stopped, can
+/// be resumed too.
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/semantic-index/jobs/{job_id}/resume",
+    tag = "datasets",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("job_id" = Uuid, Path, description = "Id of the indexing job"),
+    ),
+    responses(
+        (status = 202, description = "Job is resumed", body = ReindexJob),
+        (status = 404),
+        (status = 409, description = "Job is running or finished"),
+    ),
+    security(("user_api_key" = [])),
+)]
+#[post("semantic-index/jobs/{job_id}/resume")]
+async fn resume_reindex_job(
+    db: web::Data<DB>,
+    path: web::Path<(Uuid, Uuid)>,
+    semantic_search: web::Data<Arc<SemanticSearch>>,
+) -> ResponseResult {
+    let (project_id, job_id) = path.into_inner();
+    if db::semantic_indexes::get_reindex_job(&db.pool, &project_id, &job_id)
+        .await?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let job =
+        db::semantic_indexes::claim_reindex_job(&db.pool, &project_id, &job_id, STALE_JOB_SECONDS)
+            .await?
+            .ok_or_else(|| Error::Conflict("Reindex job is running or finished".to_string()))?;
+
+    tokio::spawn(reindex::run_reindex_job(
+        db.into_inner(),
+        semantic_search.as_ref().clone(),
+        job.clone(),
+    ));
+
+    Ok(HttpResponse::Accepted().json(job))
+}
@@ -13,10 +13,15 @@ use crate::{
         trace::{Span, Trace, TraceWithEvents},
         DB,
     },
+    pipeline::runner::PipelineRunner,
+    retention,
+    routes::error::Error,
 };
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{delete, get, post, web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::Arc;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 const DEFAULT_PAGE_SIZE: usize = 50;
@@ -35,7 +40,7 @@ struct GetTracesQueryParams {
     pub date_range: Option<DateRange>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct GetTracesResponse {
     total_entries: i64,
@@ -44,6 +49,22 @@ struct GetTracesResponse {
 }
 
 /// Get traces generated by Laminar tracing
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/traces",
+    tag = "traces",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pageNumber" = Option<usize>, Query, description = "Page number starting from 0"),
+        ("pageSize" = Option<usize>, Query, description = "Number of traces per page"),
+        ("filter" = Option<String>, Query, description = "JSON array of filters"),
+        ("pastHours" = Option<String>, Query, description = "Number of hours, or `all`"),
+        ("startDate" = Option<DateTime<Utc>>, Query, description = "Instead of `pastHours`, with `endDate`"),
+        ("endDate" = Option<DateTime<Utc>>, Query, description = "End of the range started by `startDate`"),
+    ),
+    responses((status = 200, body = inline(GetTracesResponse))),
+    security(("user_api_key" = [])),
+)]
 #[get("traces")]
 pub async fn get_traces(
     path: web::Path<Uuid>,
@@ -83,15 +104,32 @@ pub async fn get_traces(
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TraceWithSpanPreviews {
     #[serde(flatten)]
     pub trace: Trace,
     pub spans: Vec<Span>,
+    /// Inputs and outputs of the spans were purged by retention or on request
+    pub content_purged: bool,
 }
 
 /// Get a single trace generated by Laminar tracing instrumentation
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/traces/{trace_id}",
+    tag = "traces",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("trace_id" = Uuid, Path, description = "Id of the trace"),
+    ),
+    responses(
+        (status = 200, body = TraceWithSpanPreviews),
+        (status = 404),
+        (status = 410, description = "Trace was deleted by retention", body = PurgedTrace),
+    ),
+    security(("user_api_key" = [])),
+)]
 #[get("traces/{trace_id}")]
 pub async fn get_single_trace(
     params: web::Path<(Uuid, Uuid)>,
@@ -99,34 +137,109 @@ pub async fn get_single_trace(
 ) -> ResponseResult {
     let (_project_id, trace_id) = params.into_inner();
 
-    let trace = db::trace::get_single_trace(&db.pool, trace_id).await?;
+    let purged = db::retention::get_purged_trace(&db.pool, &trace_id).await?;
+    let Some(trace) = db::trace::get_single_trace(&db.pool, trace_id).await? else {
+        // links to deleted traces, e.g. from evaluation results, show their content as purged
+        return Ok(match purged {
+            Some(purged) => HttpResponse::Gone().json(purged),
+            None => HttpResponse::NotFound().finish(),
+        });
+    };
     let span_previews = db::trace::get_span_previews(&db.pool, trace_id).await?;
 
     let trace_with_spans = TraceWithSpanPreviews {
         trace,
         spans: span_previews,
+        content_purged: purged.is_some(),
     };
 
     Ok(HttpResponse::Ok().json(trace_with_spans))
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize)]
+struct DeleteTracesQueryParams {
+    #[serde(alias = "userId")]
+    user_id: String,
+}
+
+/// Erase the captured content of all traces of an end user, e.g. for right-to-erasure requests
+///
+/// Span inputs and outputs, event inputs, run outputs and recorded node I/O of the traces with the
+/// user id are purged. The traces are kept with their metadata and show the content as purged.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/projects/{project_id}/traces",
+    tag = "traces",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("user_id" = String, Query, description = "User id of the end user set in tracing"),
+    ),
+    responses((status = 200, description = "Purge of the user's traces", body = RetentionPurge)),
+    security(("user_api_key" = [])),
+)]
+#[delete("traces")]
+pub async fn delete_user_traces(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    query_params: web::Query<DeleteTracesQueryParams>,
+    pipeline_runner: web::Data<Arc<PipelineRunner>>,
+) -> ResponseResult {
+    let project_id = path.into_inner();
+    let user_id = query_params.into_inner().user_id;
+    if user_id.is_empty() {
+        return Err(Error::invalid_request(Some("user_id must not be empty")));
+    }
+
+    let purge = retention::erase_end_user_content(
+        &db,
+        pipeline_runner.node_io_store(),
+        &project_id,
+        &user_id,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(purge))
+}
+
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SpanWithEvents {
     #[serde(flatten)]
     pub span: Span,
     pub events: Vec<EventWithTemplateName>,
+    /// Input and output of the span were purged by retention or on request
+    pub content_purged: bool,
 }
 
 /// Get a single span generated by Laminar tracing instrumentation
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/spans/{span_id}",
+    tag = "traces",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("span_id" = Uuid, Path, description = "Id of the span"),
+    ),
+    responses((status = 200, body = SpanWithEvents), (status = 404)),
+    security(("user_api_key" = [])),
+)]
 #[get("spans/{span_id}")]
 pub async fn get_single_span(params: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
     let (_project_id, span_id) = params.into_inner();
 
-    let span = db::trace::get_span(&db.pool, span_id).await?;
+    let Some(span) = db::trace::get_span(&db.pool, span_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
     let events = db::events::get_events_for_span(&db.pool, span_id).await?;
+    let content_purged = db::retention::get_purged_trace(&db.pool, &span.trace_id)
+        .await?
+        .is_some();
 
-    let span_with_events = SpanWithEvents { span, events };
+    let span_with_events = SpanWithEvents {
+        span,
+        events,
+        content_purged,
+    };
 
     Ok(HttpResponse::Ok().json(span_with_events))
 }
@@ -148,6 +261,18 @@ struct GetTraceMetricsParams {
 }
 
 /// Get metrics for a single metric type (e.g. for average trace latency)
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/traces/metrics",
+    tag = "traces",
+    params(("project_id" = Uuid, Path, description = "Id of the project")),
+    request_body(
+        content = Object,
+        description = "`metric`, `aggregation`, `groupByInterval`, and the `pastHours` date range",
+    ),
+    responses((status = 200, description = "Values of the metric over time, `[{time, value}]`")),
+    security(("user_api_key" = [])),
+)]
 #[post("traces/metrics")]
 pub async fn get_traces_metrics(
     params: web::Path<Uuid>,
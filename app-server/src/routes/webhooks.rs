@@ -0,0 +1,177 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::ResponseResult;
+use crate::{
+    db::{
+        self,
+        webhooks::{NewWebhook, WebhookInfo},
+        DB,
+    },
+    routes::error,
+    secrets,
+    webhooks::{generate_secret, secret_name, WebhookEventType},
+};
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct CreateWebhookRequest {
+    url: String,
+    /// Generated if not set
+    #[serde(default)]
+    secret: Option<String>,
+    event_types: Vec<WebhookEventType>,
+    /// Include run outputs in payloads, up to `WEBHOOK_MAX_OUTPUT_SIZE` bytes
+    #[serde(default)]
+    include_output: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct CreateWebhookResponse {
+    #[serde(flatten)]
+    webhook: WebhookInfo,
+    /// Only returned here, to verify signatures of payloads
+    secret: String,
+}
+
+/// Create a webhook, notified of finished API runs of the pipeline
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/webhooks",
+    tag = "webhooks",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+    ),
+    request_body(content = inline(CreateWebhookRequest)),
+    responses((status = 200, body = inline(CreateWebhookResponse))),
+    security(("user_api_key" = [])),
+)]
+#[post("pipelines/{pipeline_id}/webhooks")]
+async fn create_webhook(
+    path: web::Path<(Uuid, Uuid)>,
+    db: web::Data<DB>,
+    req: web::Json<CreateWebhookRequest>,
+) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+    let req = req.into_inner();
+
+    let pipeline = db::pipelines::pipeline::get_pipeline_by_id(&db.pool, &pipeline_id).await?;
+    if pipeline.project_id != project_id {
+        return Err(error::Error::invalid_request(Some("Pipeline not found")));
+    }
+    match url::Url::parse(&req.url) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => {}
+        _ => {
+            return Err(error::Error::invalid_request(Some(
+                "Webhook URL must be an http or https URL",
+            )))
+        }
+    }
+    if req.event_types.is_empty() {
+        return Err(error::Error::invalid_request(Some(
+            "Webhook must be subscribed to at least one event type",
+        )));
+    }
+
+    let id = Uuid::new_v4();
+    let secret = match req.secret {
+        Some(secret) if !secret.is_empty() => secret,
+        _ => generate_secret()?,
+    };
+    let (secret_nonce, secret_value) = secrets::encrypt(&secret_name(&id), &secret)?;
+    let event_types = req
+        .event_types
+        .iter()
+        .map(|event_type| event_type.as_str().to_string())
+        .collect::<Vec<_>>();
+
+    let webhook = db::webhooks::create_webhook(
+        &db.pool,
+        &NewWebhook {
+            id,
+            pipeline_id,
+            url: &req.url,
+            secret_nonce: &secret_nonce,
+            secret_value: &secret_value,
+            event_types: &event_types,
+            include_output: req.include_output,
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(CreateWebhookResponse { webhook, secret }))
+}
+
+/// Webhooks of the pipeline, with their delivery state
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/webhooks",
+    tag = "webhooks",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+    ),
+    responses((status = 200, body = [WebhookInfo])),
+    security(("user_api_key" = [])),
+)]
+#[get("pipelines/{pipeline_id}/webhooks")]
+async fn get_webhooks(path: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
+    let (project_id, pipeline_id) = path.into_inner();
+
+    let webhooks = db::webhooks::get_webhooks(&db.pool, &project_id, &pipeline_id).await?;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+/// Enable a webhook which was disabled after failed deliveries
+#[utoipa::path(
+    post,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/webhooks/{webhook_id}/enable",
+    tag = "webhooks",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        ("webhook_id" = Uuid, Path, description = "Id of the webhook"),
+    ),
+    responses((status = 200, body = WebhookInfo)),
+    security(("user_api_key" = [])),
+)]
+#[post("pipelines/{pipeline_id}/webhooks/{webhook_id}/enable")]
+async fn enable_webhook(path: web::Path<(Uuid, Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
+    let (project_id, pipeline_id, webhook_id) = path.into_inner();
+
+    let webhook = db::webhooks::enable_webhook(&db.pool, &project_id, &pipeline_id, &webhook_id)
+        .await?
+        .ok_or_else(|| error::Error::invalid_request(Some("Webhook not found")))?;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/projects/{project_id}/pipelines/{pipeline_id}/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(
+        ("project_id" = Uuid, Path, description = "Id of the project"),
+        ("pipeline_id" = Uuid, Path, description = "Id of the pipeline"),
+        ("webhook_id" = Uuid, Path, description = "Id of the webhook"),
+    ),
+    responses((status = 200)),
+    security(("user_api_key" = [])),
+)]
+#[delete("pipelines/{pipeline_id}/webhooks/{webhook_id}")]
+async fn delete_webhook(path: web::Path<(Uuid, Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
+    let (project_id, pipeline_id, webhook_id) = path.into_inner();
+
+    let deleted =
+        db::webhooks::delete_webhook(&db.pool, &project_id, &pipeline_id, &webhook_id).await?;
+    if !deleted {
+        return Err(error::Error::invalid_request(Some("Webhook not found")));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,16 +1,19 @@
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{get, post, put, web, HttpResponse};
 use serde::Deserialize;
 use uuid::Uuid;
 
-use super::error::workspace_error_to_http_error;
+use super::error::{workspace_error_to_http_error, Error};
 use crate::{
     cache::Cache,
     db::{
-        self, limits,
+        self,
+        limits::{self, WorkspaceRunLimits},
         user::{get_by_email, User},
-        workspace::WorkspaceError,
+        workspace::{WorkspaceError, WorkspaceId},
         DB,
     },
+    pipeline::model_defaults::ModelDefaults,
+    retention::RetentionPolicy,
     routes::ResponseResult,
 };
 
@@ -160,3 +163,132 @@ pub async fn can_add_users_to_workspace(
         owned_workspaces.iter().any(|w| w.id == workspace_id) && existing_members < max_users;
     Ok(HttpResponse::Ok().json(can_create))
 }
+
+#[get("{workspace_id}/retention")]
+async fn get_retention_policy(path: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
+    let workspace_id = path.into_inner();
+
+    let policy = db::retention::get_retention_policy(&db.pool, &workspace_id).await?;
+
+    Ok(HttpResponse::Ok().json(policy))
+}
+
+/// Set TTLs of the workspace's traced data, expired data is purged by the retention sweeper.
+/// Only owners of the workspace can change them.
+#[put("{workspace_id}/retention")]
+async fn update_retention_policy(
+    user: User,
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    req: web::Json<RetentionPolicy>,
+) -> ResponseResult {
+    let workspace_id = path.into_inner();
+    let policy = req.into_inner();
+    policy
+        .validate()
+        .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+
+    let owned_workspaces = db::workspace::get_owned_workspaces(&db.pool, &user.id).await?;
+    if !owned_workspaces.iter().any(|w| w.id == workspace_id) {
+        return Err(Error::Forbidden(
+            "Only owners can change the retention policy of the workspace".to_string(),
+        ));
+    }
+    db::retention::set_retention_policy(&db.pool, &workspace_id, &policy).await?;
+
+    Ok(HttpResponse::Ok().json(policy))
+}
+
+#[get("{workspace_id}/model-defaults")]
+async fn get_model_defaults(path: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
+    let workspace_id = path.into_inner();
+
+    let defaults =
+        db::model_defaults::get_workspace_model_defaults(&db.pool, &workspace_id).await?;
+
+    Ok(HttpResponse::Ok().json(defaults))
+}
+
+/// Set the default model and params of LLM nodes in the workspace's pipelines, which pipelines
+/// and nodes override. Only owners of the workspace can change them.
+#[put("{workspace_id}/model-defaults")]
+async fn update_model_defaults(
+    user: User,
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    req: web::Json<ModelDefaults>,
+) -> ResponseResult {
+    let workspace_id = path.into_inner();
+    let defaults = req.into_inner();
+    defaults
+        .validate()
+        .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+
+    let owned_workspaces = db::workspace::get_owned_workspaces(&db.pool, &user.id).await?;
+    if !owned_workspaces.iter().any(|w| w.id == workspace_id) {
+        return Err(Error::Forbidden(
+            "Only owners can change the model defaults of the workspace".to_string(),
+        ));
+    }
+    db::model_defaults::set_workspace_model_defaults(&db.pool, &workspace_id, &defaults).await?;
+
+    Ok(HttpResponse::Ok().json(defaults))
+}
+
+#[get("{workspace_id}/run-limits")]
+async fn get_run_limits(path: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
+    let workspace_id = WorkspaceId(path.into_inner());
+
+    let limits = limits::get_workspace_run_limits(&db.pool, &workspace_id).await?;
+
+    Ok(HttpResponse::Ok().json(limits))
+}
+
+/// Set the limits which the runs of all projects of the workspace count towards, on top of the
+/// limits of their API keys. They apply within a minute. Only owners of the workspace can
+/// change them.
+#[put("{workspace_id}/run-limits")]
+async fn update_run_limits(
+    user: User,
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    req: web::Json<WorkspaceRunLimits>,
+) -> ResponseResult {
+    let workspace_id = WorkspaceId(path.into_inner());
+    let limits = req.into_inner();
+    limits
+        .validate()
+        .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+
+    let owned_workspaces = db::workspace::get_owned_workspaces(&db.pool, &user.id).await?;
+    if !owned_workspaces.iter().any(|w| w.id == workspace_id.0) {
+        return Err(Error::Forbidden(
+            "Only owners can change the run limits of the workspace".to_string(),
+        ));
+    }
+    limits::set_workspace_run_limits(&db.pool, &workspace_id, &limits).await?;
+
+    Ok(HttpResponse::Ok().json(limits))
+}
+
+const DEFAULT_PURGES_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+struct GetPurgesParams {
+    limit: Option<i64>,
+}
+
+/// Latest purges of data of the workspace's projects, by retention or on request
+#[get("{workspace_id}/retention/purges")]
+async fn get_retention_purges(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    params: web::Query<GetPurgesParams>,
+) -> ResponseResult {
+    let workspace_id = path.into_inner();
+    let limit = params.limit.unwrap_or(DEFAULT_PURGES_LIMIT);
+
+    let purges = db::retention::get_purges_of_workspace(&db.pool, &workspace_id, limit).await?;
+
+    Ok(HttpResponse::Ok().json(purges))
+}
@@ -0,0 +1,604 @@
+//! Checkpoints of runs which can be paused, so that any instance can resume them
+//!
+//! Workshop runs with breakpoints send snapshots of their tasks' states as tasks finish, see
+//! `engine::snapshot`. They're written to the `CheckpointStore` in batches, and when the run is
+//! paused at a breakpoint, the checkpoint is marked paused. A run request with `resume` claims the
+//! paused run on whichever instance handles it, and continues the run on a fresh engine restored
+//! from the snapshots. Writes of the engine the run was claimed from are ignored from then on.
+//!
+//! Runs from the run queue with loops are checkpointed the same way. When the worker executing
+//! one stops, the worker which takes the run over claims the checkpoint with `take_over` and
+//! continues from the latest snapshots, re-running the tasks which were in flight, see
+//! `queue::lease`. Checkpoints of completed runs are removed after `RUN_CHECKPOINT_TTL_SECONDS`.
+//!
+//! The run store is checkpointed with the snapshots, the latest version of it in each batch, and
+//! restored before the run is resumed, see `pipeline::run_store`.
+
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+use uuid::Uuid;
+
+use crate::{
+    engine::snapshot::{CheckpointEvent, StateSnapshot},
+    pipeline::run_store::RunStoreSnapshot,
+};
+
+mod postgres;
+
+pub use postgres::PostgresCheckpointStore;
+
+const DEFAULT_CHECKPOINT_TTL_SECONDS: i64 = 24 * 60 * 60;
+/// Snapshots of a batch are written once the batch is this old, or this large
+const CHECKPOINT_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+const CHECKPOINT_BATCH_SIZE: usize = 100;
+
+/// Checkpoint of a paused run, claimed to resume the run
+#[derive(Debug)]
+pub struct Checkpoint {
+    /// Generation of the engine which resumes the run
+    pub generation: i32,
+    pub paused_task_id: Option<Uuid>,
+    pub snapshots: Vec<StateSnapshot>,
+    /// Run store as of the latest snapshots, unset if the run didn't write to it
+    pub run_store: Option<RunStoreSnapshot>,
+}
+
+impl Checkpoint {
+    /// Seq of the first snapshot of the resumed run, after all snapshots of the checkpoint
+    pub fn next_seq(&self) -> u64 {
+        self.snapshots
+            .iter()
+            .map(|snapshot| snapshot.seq + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Checkpoints of a run, and the checkpoint the run is resumed from
+pub struct RunCheckpoints {
+    pub events: UnboundedSender<CheckpointEvent>,
+    pub resume_from: Option<Checkpoint>,
+}
+
+/// Storage of snapshots of the tasks of runs, keyed by run id and task id
+#[async_trait]
+pub trait CheckpointStore: Send + Sync + std::fmt::Debug {
+    /// Start checkpoints of the run, replacing earlier ones of the run id. Returns the generation
+    /// the engine writes snapshots with.
+    async fn start(&self, run_id: &Uuid, graph_hash: &str) -> Result<i32>;
+
+    /// Write the snapshots, and the run store if it's set, in a transaction, false if another
+    /// engine claimed the run
+    async fn put(
+        &self,
+        run_id: &Uuid,
+        generation: i32,
+        snapshots: &[StateSnapshot],
+        run_store: Option<&RunStoreSnapshot>,
+    ) -> Result<bool>;
+
+    /// Mark the run paused at the task, false if another engine claimed the run
+    async fn pause(&self, run_id: &Uuid, generation: i32, task_id: &Uuid) -> Result<bool>;
+
+    async fn complete(&self, run_id: &Uuid, generation: i32) -> Result<bool>;
+
+    /// Claim the run paused with the graph to resume it, None if there's no such paused run
+    async fn claim(&self, run_id: &Uuid, graph_hash: &str) -> Result<Option<Checkpoint>>;
+
+    /// Claim the run checkpointed with the graph from the engine which wrote the checkpoint, to
+    /// resume it after that engine stopped, None if there's no such run or it's paused
+    async fn take_over(&self, run_id: &Uuid, graph_hash: &str) -> Result<Option<Checkpoint>>;
+
+    /// Remove checkpoints of runs completed longer than `ttl` ago, returns the number of runs
+    async fn delete_completed(&self, ttl: chrono::Duration) -> Result<u64>;
+}
+
+pub fn checkpoint_ttl() -> chrono::Duration {
+    let seconds = env::var("RUN_CHECKPOINT_TTL_SECONDS")
+        .ok()
+        .and_then(|ttl| ttl.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_CHECKPOINT_TTL_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+/// Snapshots to write, the latest one of each task, and the latest run store
+#[derive(Default)]
+struct Batch {
+    snapshots: HashMap<Uuid, StateSnapshot>,
+    run_store: Option<RunStoreSnapshot>,
+}
+
+impl Batch {
+    fn is_empty(&self) -> bool {
+        self.snapshots.is_empty() && self.run_store.is_none()
+    }
+
+    fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    fn add(&mut self, snapshot: StateSnapshot) {
+        if self
+            .snapshots
+            .get(&snapshot.task_id)
+            .map_or(true, |current| current.seq < snapshot.seq)
+        {
+            self.snapshots.insert(snapshot.task_id, snapshot);
+        }
+    }
+
+    fn add_run_store(&mut self, run_store: RunStoreSnapshot) {
+        if self
+            .run_store
+            .as_ref()
+            .map_or(true, |current| current.version < run_store.version)
+        {
+            self.run_store = Some(run_store);
+        }
+    }
+}
+
+/// Write the checkpoint events of a run's engine to the store, until the engine is dropped
+///
+/// Snapshots are batched, keeping the latest one of each task, and the batch is written before
+/// the run is marked paused, so that the checkpoint of a paused run is complete.
+pub async fn write_checkpoints(
+    store: Arc<dyn CheckpointStore>,
+    run_id: Uuid,
+    generation: i32,
+    mut events: UnboundedReceiver<CheckpointEvent>,
+) {
+    let mut batch = Batch::default();
+    let mut flush_at = Instant::now();
+
+    loop {
+        let event = if batch.is_empty() {
+            events.recv().await
+        } else {
+            match tokio::time::timeout_at(flush_at, events.recv()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    if !flush(store.as_ref(), &run_id, generation, &mut batch).await {
+                        return;
+                    }
+                    continue;
+                }
+            }
+        };
+
+        if matches!(
+            event,
+            Some(CheckpointEvent::Snapshot(_) | CheckpointEvent::RunStore(_))
+        ) && batch.is_empty()
+        {
+            flush_at = Instant::now() + CHECKPOINT_FLUSH_INTERVAL;
+        }
+        match event {
+            Some(CheckpointEvent::Snapshot(snapshot)) => {
+                batch.add(snapshot);
+                if batch.len() >= CHECKPOINT_BATCH_SIZE
+                    && !flush(store.as_ref(), &run_id, generation, &mut batch).await
+                {
+                    return;
+                }
+            }
+            Some(CheckpointEvent::RunStore(run_store)) => batch.add_run_store(run_store),
+            Some(CheckpointEvent::Paused { task_id }) => {
+                if !flush(store.as_ref(), &run_id, generation, &mut batch).await {
+                    return;
+                }
+                match store.pause(&run_id, generation, &task_id).await {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(e) => log::error!("Failed to pause checkpoint of run {}: {}", run_id, e),
+                }
+            }
+            None => {
+                if flush(store.as_ref(), &run_id, generation, &mut batch).await {
+                    if let Err(e) = store.complete(&run_id, generation).await {
+                        log::error!("Failed to complete checkpoint of run {}: {}", run_id, e);
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Write the batch, false if the run was claimed by another engine and writes should stop
+async fn flush(
+    store: &dyn CheckpointStore,
+    run_id: &Uuid,
+    generation: i32,
+    batch: &mut Batch,
+) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+    let Batch {
+        snapshots,
+        run_store,
+    } = std::mem::take(batch);
+    let snapshots = snapshots.into_values().collect::<Vec<_>>();
+    match store
+        .put(run_id, generation, &snapshots, run_store.as_ref())
+        .await
+    {
+        Ok(true) => true,
+        Ok(false) => {
+            log::info!(
+                "Run {} was resumed by another engine, stopped writing its checkpoint",
+                run_id
+            );
+            false
+        }
+        Err(e) => {
+            log::error!("Failed to write checkpoint of run {}: {}", run_id, e);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        engine::{
+            blocked::BlockReason, task::Action, Engine, Input, NodeError, NodeImpl, RunOutput,
+        },
+        pipeline::{
+            context::Context,
+            nodes::{Handle, NodeInput, StreamChunk},
+            trace::{NodeOutcome, NodeRunStats},
+        },
+        testing::{NoopBehavior, NoopGraph, OfflineServices},
+    };
+
+    use super::*;
+
+    /// Keeps the writes as the Postgres store would, claimed by generation 1 after a pause
+    #[derive(Debug, Default)]
+    struct MemoryStore {
+        snapshots: Mutex<HashMap<Uuid, StateSnapshot>>,
+        run_store: Mutex<Option<RunStoreSnapshot>>,
+        status: Mutex<Vec<&'static str>>,
+        claimed: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl CheckpointStore for MemoryStore {
+        async fn start(&self, _run_id: &Uuid, _graph_hash: &str) -> Result<i32> {
+            Ok(0)
+        }
+
+        async fn put(
+            &self,
+            _run_id: &Uuid,
+            generation: i32,
+            snapshots: &[StateSnapshot],
+            run_store: Option<&RunStoreSnapshot>,
+        ) -> Result<bool> {
+            if *self.claimed.lock().unwrap() && generation == 0 {
+                return Ok(false);
+            }
+            let mut stored = self.snapshots.lock().unwrap();
+            for snapshot in snapshots {
+                stored.insert(snapshot.task_id, snapshot.clone());
+            }
+            if let Some(run_store) = run_store {
+                *self.run_store.lock().unwrap() = Some(run_store.clone());
+            }
+            Ok(true)
+        }
+
+        async fn pause(&self, _run_id: &Uuid, _generation: i32, _task_id: &Uuid) -> Result<bool> {
+            self.status.lock().unwrap().push("paused");
+            Ok(true)
+        }
+
+        async fn complete(&self, _run_id: &Uuid, _generation: i32) -> Result<bool> {
+            self.status.lock().unwrap().push("completed");
+            Ok(true)
+        }
+
+        async fn claim(&self, _run_id: &Uuid, _graph_hash: &str) -> Result<Option<Checkpoint>> {
+            *self.claimed.lock().unwrap() = true;
+            Ok(None)
+        }
+
+        async fn take_over(&self, _run_id: &Uuid, _graph_hash: &str) -> Result<Option<Checkpoint>> {
+            *self.claimed.lock().unwrap() = true;
+            Ok(Some(Checkpoint {
+                generation: 1,
+                paused_task_id: None,
+                snapshots: self.snapshots.lock().unwrap().values().cloned().collect(),
+                run_store: self.run_store.lock().unwrap().clone(),
+            }))
+        }
+
+        async fn delete_completed(&self, _ttl: chrono::Duration) -> Result<u64> {
+            Ok(0)
+        }
+    }
+
+    fn snapshot(task_id: Uuid, seq: u64) -> CheckpointEvent {
+        CheckpointEvent::Snapshot(StateSnapshot {
+            task_id,
+            seq,
+            inputs: HashMap::new(),
+            executions: seq as usize,
+            messages: vec![],
+            scheduled: false,
+            finished: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_is_written_before_pause() {
+        let store = Arc::new(MemoryStore::default());
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_checkpoints(
+            store.clone(),
+            Uuid::new_v4(),
+            0,
+            events_rx,
+        ));
+        let task_id = Uuid::new_v4();
+
+        let run_store = |version| {
+            CheckpointEvent::RunStore(RunStoreSnapshot {
+                version,
+                values: Default::default(),
+            })
+        };
+
+        events.send(snapshot(task_id, 1)).unwrap();
+        events.send(run_store(2)).unwrap();
+        events.send(snapshot(task_id, 0)).unwrap();
+        events.send(run_store(1)).unwrap();
+        events.send(CheckpointEvent::Paused { task_id }).unwrap();
+        drop(events);
+        writer.await.unwrap();
+
+        // the latest snapshot of the task is kept, even if it arrived first
+        assert_eq!(store.snapshots.lock().unwrap()[&task_id].seq, 1);
+        assert_eq!(store.run_store.lock().unwrap().as_ref().unwrap().version, 2);
+        assert_eq!(*store.status.lock().unwrap(), vec!["paused", "completed"]);
+    }
+
+    #[tokio::test]
+    async fn test_writes_stop_once_run_is_claimed() {
+        let store = Arc::new(MemoryStore::default());
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_checkpoints(
+            store.clone(),
+            Uuid::new_v4(),
+            0,
+            events_rx,
+        ));
+        store.claim(&Uuid::new_v4(), "").await.unwrap();
+
+        events.send(snapshot(Uuid::new_v4(), 0)).unwrap();
+        drop(events);
+        writer.await.unwrap();
+
+        assert!(store.snapshots.lock().unwrap().is_empty());
+        assert!(store.status.lock().unwrap().is_empty());
+    }
+
+    /// Runs the wrapped node, but never finishes on the first input of `value`, as the engine of
+    /// a worker which stops mid-cycle
+    struct Stall {
+        node: Action,
+        value: f64,
+        stalled: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl NodeImpl for Stall {
+        fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+            self.node.handles_mapping()
+        }
+
+        fn output_handle_id(&self) -> Uuid {
+            self.node.output_handle_id()
+        }
+
+        fn node_name(&self) -> String {
+            self.node.node_name()
+        }
+
+        fn node_id(&self) -> Uuid {
+            self.node.node_id()
+        }
+
+        fn node_type(&self) -> String {
+            self.node.node_type()
+        }
+
+        async fn run(&self, input: Input, context: Arc<Context>) -> Result<RunOutput, NodeError> {
+            let stalls = input
+                .messages()
+                .values()
+                .any(|message| message.value == NodeInput::Float(self.value));
+            if stalls && !self.stalled.swap(true, Ordering::SeqCst) {
+                std::future::pending::<()>().await;
+            }
+            self.node.run(input, context).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_taken_over_mid_cycle_reruns_iteration_in_flight() {
+        const ITERATIONS: f64 = 3.0;
+
+        let mut graph = NoopGraph::default();
+        let input = graph.node(NoopBehavior::Forward);
+        let counter = graph.node(NoopBehavior::Count);
+        let again = graph.node(NoopBehavior::Below(ITERATIONS));
+        let done = graph.node(NoopBehavior::AtLeast(ITERATIONS));
+        let output = graph.node(NoopBehavior::Forward);
+        graph.cyclic_edge(input, counter, "count");
+        graph.edge(counter, again);
+        graph.cyclic_edge(again, counter, "count");
+        graph.edge(counter, done);
+        graph.edge(done, output);
+        let (counter_id, again_id) = (graph.id(counter), graph.id(again));
+        let stalled = Arc::new(AtomicBool::new(false));
+        let tasks = || {
+            let mut tasks = graph.tasks();
+            let task = tasks.get_mut(&again_id).unwrap();
+            task.action = Arc::new(Stall {
+                node: task.action.clone(),
+                value: 2.0,
+                stalled: stalled.clone(),
+            });
+            tasks
+        };
+        let services = OfflineServices::default();
+        let store = Arc::new(MemoryStore::default());
+        let run_id = Uuid::new_v4();
+
+        // the first worker stops once the second iteration is in flight and checkpointed
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_checkpoints(store.clone(), run_id, 0, events_rx));
+        let mut engine =
+            Engine::with_tasks_and_context(tasks(), services.context(), None, None, None);
+        engine.record_checkpoints(events, 0);
+        let worker = tokio::spawn(async move { engine.run(None, None, vec![]).await });
+        let in_flight = |snapshots: &HashMap<Uuid, StateSnapshot>| {
+            snapshots
+                .get(&again_id)
+                .is_some_and(|snapshot| snapshot.scheduled && snapshot.executions == 1)
+        };
+        while !in_flight(&store.snapshots.lock().unwrap()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        writer.abort();
+        worker.abort();
+
+        // another worker takes the run over once the lease of the first one expired
+        let checkpoint = store.take_over(&run_id, "").await.unwrap().unwrap();
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_checkpoints(
+            store.clone(),
+            run_id,
+            checkpoint.generation,
+            events_rx,
+        ));
+        let mut engine =
+            Engine::with_tasks_and_context(tasks(), services.context(), None, None, None);
+        engine.record_checkpoints(events, checkpoint.next_seq());
+        let start_task_ids = engine.restore(checkpoint.snapshots, None).unwrap();
+        assert!(start_task_ids.contains(&again_id));
+        let outputs = engine.run(None, None, start_task_ids).await.unwrap();
+        drop(engine);
+        writer.await.unwrap();
+
+        assert_eq!(
+            outputs.output_values().into_values().collect::<Vec<_>>(),
+            vec![NodeInput::Float(ITERATIONS)]
+        );
+        // each iteration counted once, none from the input of a previous one
+        let mut counts = outputs
+            .messages
+            .values()
+            .filter(|message| message.node_id == counter_id)
+            .map(|message| match message.value {
+                NodeInput::Float(count) => count,
+                _ => panic!("Expected a count"),
+            })
+            .collect::<Vec<_>>();
+        counts.sort_by(f64::total_cmp);
+        assert_eq!(counts, vec![1.0, 2.0, 3.0]);
+        assert_eq!(*store.status.lock().unwrap(), vec!["completed"]);
+    }
+
+    #[tokio::test]
+    async fn test_finished_run_cancels_streaming_branch_and_completes() {
+        let mut graph = NoopGraph::default();
+        let input = graph.node(NoopBehavior::Forward);
+        let counter = graph.node(NoopBehavior::Count);
+        let guard = graph.node(NoopBehavior::FinishAtLeast(1.0));
+        let answer = graph.node(NoopBehavior::Forward);
+        let slow = graph.node(NoopBehavior::Forward);
+        let output = graph.node(NoopBehavior::Forward);
+        graph.edge(input, counter);
+        graph.edge(counter, guard);
+        graph.edge(guard, answer);
+        graph.edge(counter, slow);
+        graph.edge(slow, output);
+        let (guard_id, slow_id) = (graph.id(guard), graph.id(slow));
+        let stalled = Arc::new(AtomicBool::new(false));
+        let tasks = || {
+            let mut tasks = graph.tasks();
+            let task = tasks.get_mut(&slow_id).unwrap();
+            task.action = Arc::new(Stall {
+                node: task.action.clone(),
+                value: 1.0,
+                stalled: stalled.clone(),
+            });
+            tasks
+        };
+        let services = OfflineServices::default();
+        let store = Arc::new(MemoryStore::default());
+        let run_id = Uuid::new_v4();
+
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_checkpoints(store.clone(), run_id, 0, events_rx));
+        let (stream_send, mut stream) = mpsc::channel(100);
+        let mut engine =
+            Engine::with_tasks_and_context(tasks(), services.context(), None, None, None);
+        engine.record_checkpoints(events, 0);
+        let outputs = engine.run(Some(stream_send), None, vec![]).await.unwrap();
+        drop(engine);
+        writer.await.unwrap();
+
+        // the guard's output is the only output, and the branch still streaming is cancelled
+        assert_eq!(
+            outputs.output_values(),
+            HashMap::from([(String::from("node2"), NodeInput::Float(1.0))])
+        );
+        assert_eq!(outputs.blocked_nodes.len(), 1);
+        assert_eq!(outputs.blocked_nodes[0].node_id, slow_id);
+        assert_eq!(outputs.blocked_nodes[0].reason, BlockReason::Cancelled);
+        let node_stats = NodeRunStats::from_engine_output(&outputs);
+        let slow_stats = node_stats
+            .iter()
+            .find(|stats| stats.node_id == slow_id)
+            .unwrap();
+        assert_eq!(slow_stats.outcome, NodeOutcome::Skipped);
+        let mut ended = Vec::new();
+        while let Ok(chunk) = stream.try_recv() {
+            if let StreamChunk::NodeEnd(end) = chunk {
+                ended.push(end.message.node_id);
+            }
+        }
+        assert!(ended.contains(&guard_id));
+        assert!(!ended.contains(&slow_id));
+
+        // the run is complete, and an engine taking it over returns the finished run
+        assert_eq!(*store.status.lock().unwrap(), vec!["completed"]);
+        let checkpoint = store.take_over(&run_id, "").await.unwrap().unwrap();
+        let engine = Engine::with_tasks_and_context(tasks(), services.context(), None, None, None);
+        assert!(engine
+            .restore(checkpoint.snapshots, None)
+            .unwrap()
+            .is_empty());
+        let restored = engine.get_outputs();
+        assert_eq!(restored.finish_message_id, outputs.finish_message_id);
+        assert_eq!(restored.output_values(), outputs.output_values());
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    db::{self, checkpoints::ClaimedCheckpoint, DB},
+    engine::snapshot::StateSnapshot,
+    pipeline::run_store::RunStoreSnapshot,
+};
+
+use super::{Checkpoint, CheckpointStore};
+
+/// Stores snapshots in the `run_checkpoint_states` table, one row per task of the run, and the run
+/// store with the run's checkpoint
+#[derive(Debug)]
+pub struct PostgresCheckpointStore {
+    db: Arc<DB>,
+}
+
+impl PostgresCheckpointStore {
+    pub fn new(db: Arc<DB>) -> Self {
+        Self { db }
+    }
+}
+
+impl PostgresCheckpointStore {
+    async fn checkpoint(
+        &self,
+        run_id: &Uuid,
+        claimed: Option<ClaimedCheckpoint>,
+    ) -> Result<Option<Checkpoint>> {
+        let Some(claimed) = claimed else {
+            return Ok(None);
+        };
+        let snapshots = db::checkpoints::get_snapshots(&self.db.pool, run_id)
+            .await?
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<Vec<StateSnapshot>, _>>()?;
+        let run_store = claimed
+            .run_store
+            .map(serde_json::from_value::<RunStoreSnapshot>)
+            .transpose()?;
+
+        Ok(Some(Checkpoint {
+            generation: claimed.generation,
+            paused_task_id: claimed.paused_task_id,
+            snapshots,
+            run_store,
+        }))
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for PostgresCheckpointStore {
+    async fn start(&self, run_id: &Uuid, graph_hash: &str) -> Result<i32> {
+        db::checkpoints::start_checkpoints(&self.db.pool, run_id, graph_hash).await
+    }
+
+    async fn put(
+        &self,
+        run_id: &Uuid,
+        generation: i32,
+        snapshots: &[StateSnapshot],
+        run_store: Option<&RunStoreSnapshot>,
+    ) -> Result<bool> {
+        let task_ids = snapshots.iter().map(|s| s.task_id).collect::<Vec<_>>();
+        let seqs = snapshots.iter().map(|s| s.seq as i64).collect::<Vec<_>>();
+        let snapshots = snapshots
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()?;
+        let run_store = run_store.map(serde_json::to_value).transpose()?;
+
+        db::checkpoints::write_snapshots(
+            &self.db.pool,
+            run_id,
+            generation,
+            task_ids,
+            seqs,
+            snapshots,
+            run_store,
+        )
+        .await
+    }
+
+    async fn pause(&self, run_id: &Uuid, generation: i32, task_id: &Uuid) -> Result<bool> {
+        db::checkpoints::set_checkpoint_status(
+            &self.db.pool,
+            run_id,
+            generation,
+            "paused",
+            Some(*task_id),
+        )
+        .await
+    }
+
+    async fn complete(&self, run_id: &Uuid, generation: i32) -> Result<bool> {
+        db::checkpoints::set_checkpoint_status(&self.db.pool, run_id, generation, "completed", None)
+            .await
+    }
+
+    async fn claim(&self, run_id: &Uuid, graph_hash: &str) -> Result<Option<Checkpoint>> {
+        let claimed =
+            db::checkpoints::claim_paused_checkpoint(&self.db.pool, run_id, graph_hash).await?;
+        self.checkpoint(run_id, claimed).await
+    }
+
+    async fn take_over(&self, run_id: &Uuid, graph_hash: &str) -> Result<Option<Checkpoint>> {
+        let claimed =
+            db::checkpoints::claim_running_checkpoint(&self.db.pool, run_id, graph_hash).await?;
+        self.checkpoint(run_id, claimed).await
+    }
+
+    async fn delete_completed(&self, ttl: chrono::Duration) -> Result<u64> {
+        db::checkpoints::delete_completed_checkpoints(&self.db.pool, Utc::now() - ttl).await
+    }
+}
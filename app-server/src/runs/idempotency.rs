@@ -0,0 +1,292 @@
+use std::env;
+
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    db::{self, runs::RunStatus, DB},
+    routes::error,
+};
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Set on responses which are replayed from an earlier request with the same key
+const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const DEFAULT_IDEMPOTENCY_KEY_TTL_SECONDS: i64 = 24 * 60 * 60;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Idempotency key of a run request, scoped to the API key which sent it
+///
+/// The first request with a key is executed, and its response is stored for
+/// `IDEMPOTENCY_KEY_TTL_SECONDS`. Retries with the same key and request get the stored response,
+/// or the id of the run if the first request is still executing. Requests which fail release
+/// the key, so that they can be retried.
+pub struct IdempotencyKey {
+    api_key_id: Uuid,
+    key: String,
+    request_hash: String,
+}
+
+pub enum IdempotencyClaim {
+    /// No other request with the key, this one should be executed
+    New,
+    /// Response of the first request with the key
+    Completed { status: StatusCode, response: Value },
+    /// The first request with the key is still executing
+    InProgress { run_id: Option<Uuid> },
+}
+
+fn idempotency_key_ttl() -> chrono::Duration {
+    let seconds = env::var("IDEMPOTENCY_KEY_TTL_SECONDS")
+        .ok()
+        .and_then(|ttl| ttl.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_IDEMPOTENCY_KEY_TTL_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+impl IdempotencyKey {
+    /// Key from the `Idempotency-Key` header, or else from the `idempotencyKey` request field
+    ///
+    /// `content_hash` identifies the request content, the method, path and query are added to it.
+    pub fn from_request(
+        req: &HttpRequest,
+        field: Option<String>,
+        api_key_id: Uuid,
+        content_hash: &str,
+    ) -> Result<Option<Self>, error::Error> {
+        let header = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+            Some(header) => Some(
+                header
+                    .to_str()
+                    .map_err(|_| {
+                        error::Error::invalid_request(Some(
+                            "Idempotency key must be a visible ASCII string",
+                        ))
+                    })?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        Self::new(
+            header.or(field),
+            api_key_id,
+            &[
+                req.method().as_str(),
+                req.path(),
+                req.query_string(),
+                content_hash,
+            ],
+        )
+    }
+
+    /// Key of a request which isn't an HTTP one, `request_parts` identify the request
+    pub fn new(
+        key: Option<String>,
+        api_key_id: Uuid,
+        request_parts: &[&str],
+    ) -> Result<Option<Self>, error::Error> {
+        let Some(key) = key else {
+            return Ok(None);
+        };
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+            return Err(error::Error::invalid_request(Some(&format!(
+                "Idempotency key must have between 1 and {MAX_IDEMPOTENCY_KEY_LENGTH} characters"
+            ))));
+        }
+
+        let mut hasher = Sha256::new();
+        for part in request_parts {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+
+        Ok(Some(Self {
+            api_key_id,
+            key,
+            request_hash: hex::encode(hasher.finalize()),
+        }))
+    }
+
+    /// Fails with a conflict if the key was used for a different request
+    pub async fn claim(&self, db: &DB) -> Result<IdempotencyClaim, error::Error> {
+        let expires_at = Utc::now() + idempotency_key_ttl();
+        // The key can be released by the first request in between, then it's claimed again
+        for _ in 0..2 {
+            if db::idempotency_keys::claim_idempotency_key(
+                &db.pool,
+                &self.api_key_id,
+                &self.key,
+                &self.request_hash,
+                expires_at,
+            )
+            .await?
+            {
+                return Ok(IdempotencyClaim::New);
+            }
+
+            let Some(existing) =
+                db::idempotency_keys::get_idempotency_key(&db.pool, &self.api_key_id, &self.key)
+                    .await?
+            else {
+                continue;
+            };
+            if existing.request_hash != self.request_hash {
+                return Err(error::Error::Conflict(format!(
+                    "Idempotency key '{}' was already used for a different request",
+                    self.key
+                )));
+            }
+            return Ok(match (existing.response_status, existing.response) {
+                (Some(status), Some(response)) => IdempotencyClaim::Completed {
+                    status: StatusCode::from_u16(status as u16).unwrap_or(StatusCode::OK),
+                    response,
+                },
+                _ => IdempotencyClaim::InProgress {
+                    run_id: existing.run_id,
+                },
+            });
+        }
+
+        Ok(IdempotencyClaim::InProgress { run_id: None })
+    }
+
+    pub async fn set_run(&self, db: &DB, run_id: &Uuid) {
+        if let Err(e) = db::idempotency_keys::set_idempotency_key_run(
+            &db.pool,
+            &self.api_key_id,
+            &self.key,
+            run_id,
+        )
+        .await
+        {
+            log::error!("Failed to set run of idempotency key: {}", e);
+        }
+    }
+
+    /// Store the response to be replayed to retries
+    pub async fn complete(&self, db: &DB, status: StatusCode, response: &Value) {
+        if let Err(e) = db::idempotency_keys::set_idempotency_key_response(
+            &db.pool,
+            &self.api_key_id,
+            &self.key,
+            status.as_u16() as i16,
+            response,
+        )
+        .await
+        {
+            log::error!("Failed to store response of idempotency key: {}", e);
+        }
+    }
+
+    /// Allow the request to be retried with the same key, after it failed
+    pub async fn release(&self, db: &DB) {
+        if let Err(e) =
+            db::idempotency_keys::delete_idempotency_key(&db.pool, &self.api_key_id, &self.key)
+                .await
+        {
+            log::error!("Failed to release idempotency key: {}", e);
+        }
+    }
+}
+
+impl IdempotencyClaim {
+    /// Response to a retry, None if the request should be executed
+    ///
+    /// Streamed responses are replayed as a single event with the run output.
+    pub fn replay_response(self, stream: bool) -> Result<Option<HttpResponse>, error::Error> {
+        match self {
+            IdempotencyClaim::New => Ok(None),
+            IdempotencyClaim::Completed { status, response } => {
+                let mut res = HttpResponse::build(status);
+                res.insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"));
+                if stream {
+                    let event = format!("data: {}\n\n", response);
+                    Ok(Some(res.content_type("text/event-stream").body(event)))
+                } else {
+                    Ok(Some(res.json(response)))
+                }
+            }
+            IdempotencyClaim::InProgress {
+                run_id: Some(run_id),
+            } => Ok(Some(
+                HttpResponse::Accepted()
+                    .insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"))
+                    .json(serde_json::json!({
+                        "runId": run_id,
+                        "status": RunStatus::Running,
+                    })),
+            )),
+            IdempotencyClaim::InProgress { run_id: None } => Err(error::Error::Conflict(
+                "A request with this idempotency key is being processed, retry later".to_string(),
+            )),
+        }
+    }
+}
+
+/// Release the key if the request failed before it was executed
+pub async fn release_on_error<T, E>(
+    idempotency_key: Option<&IdempotencyKey>,
+    db: &DB,
+    res: Result<T, E>,
+) -> Result<T, E> {
+    if res.is_err() {
+        if let Some(idempotency_key) = idempotency_key {
+            idempotency_key.release(db).await;
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn key(req: &HttpRequest, field: Option<&str>, content_hash: &str) -> Option<IdempotencyKey> {
+        IdempotencyKey::from_request(req, field.map(String::from), Uuid::nil(), content_hash)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_header_takes_precedence_over_field() {
+        let req = TestRequest::post()
+            .uri("/v1/runs")
+            .insert_header((IDEMPOTENCY_KEY_HEADER, "from-header"))
+            .to_http_request();
+        assert_eq!(
+            key(&req, Some("from-field"), "a").unwrap().key,
+            "from-header"
+        );
+
+        let req = TestRequest::post().uri("/v1/runs").to_http_request();
+        assert_eq!(
+            key(&req, Some("from-field"), "a").unwrap().key,
+            "from-field"
+        );
+        assert!(key(&req, None, "a").is_none());
+    }
+
+    #[test]
+    fn test_request_hash() {
+        let req = TestRequest::post()
+            .uri("/v1/runs?mode=async")
+            .to_http_request();
+        let sync_req = TestRequest::post().uri("/v1/runs").to_http_request();
+
+        let hash = key(&req, Some("k"), "a").unwrap().request_hash;
+        assert_eq!(hash, key(&req, Some("k"), "a").unwrap().request_hash);
+        assert_ne!(hash, key(&req, Some("k"), "b").unwrap().request_hash);
+        assert_ne!(hash, key(&sync_req, Some("k"), "a").unwrap().request_hash);
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let req = TestRequest::post().uri("/v1/runs").to_http_request();
+        let too_long = "k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH + 1);
+        assert!(IdempotencyKey::from_request(&req, Some(too_long), Uuid::nil(), "a").is_err());
+        assert!(IdempotencyKey::from_request(&req, Some(String::new()), Uuid::nil(), "a").is_err());
+    }
+}
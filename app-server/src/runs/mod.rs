@@ -0,0 +1,685 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub mod checkpoints;
+pub mod idempotency;
+pub mod node_io;
+pub mod queue;
+pub mod replay;
+
+use crate::{
+    api::v1::traces::RabbitMqSpanMessage,
+    auth::rate_limit::ApiKeyRateLimiter,
+    ch::exporter::SpanExporter,
+    db::{
+        self,
+        api_keys::ProjectApiKey,
+        pipelines::PipelineVersion,
+        runs::{RunResult, RunStatus},
+        DB,
+    },
+    engine::engine::EngineOutput,
+    language_model::LanguageModelRunner,
+    pipeline::{
+        nodes::{NodeInput, StreamChunk},
+        prompts,
+        runner::{PipelineRunner, PipelineRunnerError},
+        trace::{NodeRunStats, RunTraceStats},
+        Graph, RunType,
+    },
+    routes::pipelines::GraphInterruptMessage,
+    secrets, traces, webhooks,
+};
+
+use self::{checkpoints::CheckpointStore, node_io::NodeIoStore, queue::RunExecution};
+
+const DEFAULT_RUN_RESULT_TTL_SECONDS: i64 = 24 * 60 * 60;
+const RUN_RESULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_SHUTDOWN_DRAIN_SECONDS: u64 = 30;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_STRICT_TRACE_TIMEOUT_MS: u64 = 5000;
+
+pub type InterruptSenders = DashMap<Uuid, mpsc::Sender<GraphInterruptMessage>>;
+
+/// State of runs on this instance, reported by the readiness probe
+#[derive(Default)]
+pub struct EngineStats {
+    queued_runs: AtomicUsize,
+    draining: AtomicBool,
+}
+
+impl EngineStats {
+    pub fn run_queued(&self) {
+        self.queued_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn run_dequeued(&self) {
+        self.queued_runs.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Async runs accepted by this instance which haven't started yet
+    pub fn queued_runs(&self) -> usize {
+        self.queued_runs.load(Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+}
+
+/// When the trace of a run is persisted, relative to its response
+///
+/// Spans are recorded by the observation collector from RabbitMQ, and exported to ClickHouse in
+/// batches, so the trace of an `async` run may only be queryable seconds after it responds. A
+/// `strict` run records its spans itself and waits on a flush barrier of the exporter before it
+/// responds. If that takes longer than `STRICT_TRACE_TIMEOUT_MS`, it goes on in the background
+/// and the run's `traceWarning` says so.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceMode {
+    Strict,
+    #[default]
+    Async,
+}
+
+/// Run with resolved pipeline version and a graph ready to be executed
+pub struct PreparedRun {
+    pub run_id: Uuid,
+    pub project_id: Uuid,
+    pub pipeline_version: PipelineVersion,
+    pub graph: Graph,
+    pub secrets: HashMap<String, String>,
+    /// Inputs and env the graph is set up with, kept to push the run to the run queue
+    pub inputs: HashMap<String, NodeInput>,
+    pub env: HashMap<String, String>,
+    pub metadata: HashMap<String, String>,
+    pub parent_span_id: Option<Uuid>,
+    pub trace_id: Uuid,
+    /// Set for replays of recorded runs, which are executed locally
+    pub replay: Option<replay::ReplayPlan>,
+    pub trace_mode: TraceMode,
+    /// Worker whose lease the run from the run queue is executed under, see `queue::lease`
+    pub leased_by: Option<Uuid>,
+    /// Checkpoints of a run from the run queue with loops, which another worker resumes it from
+    pub checkpoints: Option<checkpoints::RunCheckpoints>,
+}
+
+/// Graph of the pipeline version, set up with the run's inputs and the project's secrets, and
+/// the registry prompts it references
+pub async fn setup_graph(
+    pipeline_runner: &PipelineRunner,
+    db: &DB,
+    pipeline_version: &PipelineVersion,
+    inputs: &HashMap<String, NodeInput>,
+    env: &HashMap<String, String>,
+    metadata: &HashMap<String, String>,
+    project_id: &Uuid,
+) -> Result<Graph, PipelineRunnerError> {
+    let mut graph = pipeline_runner.get_version_graph(pipeline_version)?;
+    graph.setup(inputs, env, metadata, &RunType::Endpoint)?;
+    graph.secrets = secrets::get_project_secrets(&db.pool, project_id).await?;
+    graph.workspace_model_defaults =
+        db::model_defaults::get_project_model_defaults(&db.pool, project_id).await?;
+    graph.workspace_id =
+        Some(db::workspace::get_workspace_id_of_project(&db.pool, project_id).await?);
+    prompts::load_prompts(
+        &db.pool,
+        project_id,
+        &mut graph,
+        &prompts::recorded_versions(metadata),
+    )
+    .await?;
+
+    Ok(graph)
+}
+
+fn strict_trace_timeout() -> Duration {
+    let millis = env::var("STRICT_TRACE_TIMEOUT_MS")
+        .ok()
+        .and_then(|timeout| timeout.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_STRICT_TRACE_TIMEOUT_MS);
+    Duration::from_millis(millis)
+}
+
+fn run_result_ttl() -> chrono::Duration {
+    let seconds = env::var("RUN_RESULT_TTL_SECONDS")
+        .ok()
+        .and_then(|ttl| ttl.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_RUN_RESULT_TTL_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+/// Execute the run, record its trace, token usage and result
+///
+/// The run is registered in `interrupt_senders` under its id while executing, so it can be
+/// cancelled the same way as workshop runs, regardless of whether it was submitted in sync or
+/// async mode. It counts towards the concurrent runs of its workspace until it's recorded. Its
+/// trace is persisted before this returns if it's in strict trace mode, see `TraceMode`.
+pub async fn execute_run(
+    run: PreparedRun,
+    stream_send: Option<mpsc::Sender<StreamChunk>>,
+    pipeline_runner: &PipelineRunner,
+    db: &DB,
+    rate_limiter: &ApiKeyRateLimiter,
+    project_api_key: &ProjectApiKey,
+    interrupt_senders: &InterruptSenders,
+) -> Result<EngineOutput, PipelineRunnerError> {
+    let PreparedRun {
+        run_id,
+        project_id,
+        pipeline_version,
+        graph,
+        secrets,
+        parent_span_id,
+        trace_id,
+        replay,
+        trace_mode,
+        leased_by,
+        checkpoints,
+        ..
+    } = run;
+
+    let _run_slot = rate_limiter.start_run(project_api_key.workspace_id);
+    let record_node_io = graph.record_node_io;
+    let replay_of = replay.as_ref().map(|plan| plan.replay_of);
+    let (interrupt_tx, interrupt_rx) = mpsc::channel::<GraphInterruptMessage>(1);
+    interrupt_senders.insert(run_id, interrupt_tx);
+    let run_result = match replay {
+        Some(plan) => {
+            pipeline_runner
+                .run_replay(graph, plan, stream_send, Some(interrupt_rx))
+                .await
+        }
+        None => {
+            pipeline_runner
+                .run_with_checkpoints(graph, stream_send, Some(interrupt_rx), checkpoints)
+                .await
+        }
+    };
+    interrupt_senders.remove(&run_id);
+
+    record_token_usage(db, rate_limiter, project_api_key, &run_result).await;
+
+    let strict_exporter = match trace_mode {
+        TraceMode::Strict => pipeline_runner.span_exporter(),
+        TraceMode::Async => None,
+    };
+    let trace_warning = match strict_exporter {
+        Some(span_exporter) => {
+            let messages = PipelineRunner::run_span_messages(
+                &run_result,
+                &project_id,
+                Some(project_api_key.workspace_id),
+                &pipeline_version,
+                parent_span_id,
+                Some(trace_id),
+                replay_of,
+                &secrets,
+            );
+            persist_trace(
+                db,
+                pipeline_runner.language_model(),
+                span_exporter,
+                messages,
+            )
+            .await
+        }
+        None => {
+            if let Err(e) = pipeline_runner
+                .record_observations(
+                    &run_result,
+                    &project_id,
+                    Some(project_api_key.workspace_id),
+                    &pipeline_version,
+                    parent_span_id,
+                    Some(trace_id),
+                    replay_of,
+                    &secrets,
+                )
+                .await
+            {
+                log::error!("Failed to record observations from pipeline output: {}", e);
+            }
+            (trace_mode == TraceMode::Strict).then(|| {
+                String::from("Strict trace mode isn't available, the trace is recorded async")
+            })
+        }
+    };
+
+    if record_node_io {
+        record_node_io_of_run(
+            pipeline_runner,
+            db,
+            run_id,
+            &project_id,
+            &run_result,
+            &secrets,
+        )
+        .await;
+    }
+
+    record_run_result(
+        db,
+        pipeline_runner.http_client(),
+        run_id,
+        project_id,
+        &run_result,
+        trace_warning,
+        leased_by.as_ref(),
+    )
+    .await;
+
+    run_result
+}
+
+/// Record the spans of the run's trace and wait until they're persisted, for strict trace mode
+///
+/// Returns a warning if they aren't persisted within `STRICT_TRACE_TIMEOUT_MS`, in which case
+/// they're persisted in the background, or if any of them fails to be.
+async fn persist_trace(
+    db: &DB,
+    language_model: Arc<LanguageModelRunner>,
+    span_exporter: Arc<SpanExporter>,
+    messages: Vec<RabbitMqSpanMessage>,
+) -> Option<String> {
+    let db = db.clone();
+    let persisted = tokio::spawn(async move {
+        let mut error = None;
+        for message in &messages {
+            let recorded = traces::record_span(
+                &db,
+                language_model.clone(),
+                &span_exporter,
+                message.project_id,
+                &message.span,
+            )
+            .await;
+            if let Err(e) = recorded {
+                error.get_or_insert(e);
+            }
+        }
+        if let Err(e) = span_exporter.flush().await {
+            error.get_or_insert(e);
+        }
+        error.map_or(Ok(()), Err)
+    });
+
+    let timeout = strict_trace_timeout();
+    match tokio::time::timeout(timeout, persisted).await {
+        Ok(Ok(Ok(()))) => None,
+        Ok(Ok(Err(e))) => Some(format!("Trace failed to be persisted: {}", e)),
+        Ok(Err(e)) => Some(format!("Trace failed to be persisted: {}", e)),
+        Err(_) => {
+            log::warn!(
+                "Trace wasn't persisted within {}ms, persisting it in the background",
+                timeout.as_millis()
+            );
+            Some(format!(
+                "Trace wasn't persisted within {}ms, it's persisted async",
+                timeout.as_millis()
+            ))
+        }
+    }
+}
+
+async fn record_node_io_of_run(
+    pipeline_runner: &PipelineRunner,
+    db: &DB,
+    run_id: Uuid,
+    project_id: &Uuid,
+    run_result: &Result<EngineOutput, PipelineRunnerError>,
+    secrets: &HashMap<String, String>,
+) {
+    let engine_output = match run_result {
+        Ok(engine_output) => engine_output,
+        Err(PipelineRunnerError::RunningError(e)) => &e.partial_trace,
+        _ => return,
+    };
+    if let Err(e) = node_io::record_run(
+        pipeline_runner.node_io_store(),
+        db,
+        run_id,
+        project_id,
+        engine_output,
+        secrets,
+    )
+    .await
+    {
+        log::error!("Failed to record node I/O of run {}: {}", run_id, e);
+    }
+}
+
+/// Write the result of the run, and notify webhooks that it's finished
+async fn record_run_result(
+    db: &DB,
+    http_client: &reqwest::Client,
+    run_id: Uuid,
+    project_id: Uuid,
+    run_result: &Result<EngineOutput, PipelineRunnerError>,
+    trace_warning: Option<String>,
+    leased_by: Option<&Uuid>,
+) {
+    let result = get_run_result(run_result, trace_warning);
+    if let Err(e) = db::runs::finish_run(&db.pool, &run_id, &result, leased_by).await {
+        log::error!("Failed to write result of run {}: {}", run_id, e);
+    }
+    tokio::spawn(webhooks::notify_run_finished(
+        db.clone(),
+        http_client.clone(),
+        run_id,
+        project_id,
+    ));
+}
+
+fn get_run_result(
+    run_result: &Result<EngineOutput, PipelineRunnerError>,
+    trace_warning: Option<String>,
+) -> RunResult {
+    let result_expires_at = Utc::now() + run_result_ttl();
+    let engine_output = match run_result {
+        Ok(engine_output) => Some(engine_output),
+        Err(PipelineRunnerError::RunningError(e)) => Some(&e.partial_trace),
+        Err(_) => None,
+    };
+    let (node_stats, run_stats) = match engine_output {
+        Some(engine_output) => (
+            NodeRunStats::from_engine_output(engine_output),
+            Some(RunTraceStats::from_messages(&engine_output.messages)),
+        ),
+        None => (vec![], None),
+    };
+
+    let (status, outputs, error) = match run_result {
+        Ok(engine_output) => (
+            RunStatus::Succeeded,
+            serde_json::to_value(engine_output.graph_outputs()).ok(),
+            None,
+        ),
+        Err(PipelineRunnerError::RunningError(_)) => (
+            RunStatus::Failed,
+            None,
+            Some("Pipeline run failed".to_string()),
+        ),
+        Err(e) => (RunStatus::Failed, None, Some(e.to_string())),
+    };
+
+    RunResult {
+        status,
+        outputs,
+        node_stats: serde_json::to_value(node_stats).unwrap_or_default(),
+        error,
+        total_token_count: run_stats
+            .as_ref()
+            .map(|stats| stats.total_token_count)
+            .unwrap_or(0),
+        approximate_cost: run_stats.and_then(|stats| stats.approximate_cost),
+        result_expires_at,
+        trace_warning,
+    }
+}
+
+/// Count tokens used by the run towards the daily quotas of the API key and its workspace
+pub async fn record_token_usage(
+    db: &DB,
+    rate_limiter: &ApiKeyRateLimiter,
+    project_api_key: &ProjectApiKey,
+    run_result: &Result<EngineOutput, PipelineRunnerError>,
+) {
+    let engine_output = match run_result {
+        Ok(engine_output) => engine_output,
+        Err(PipelineRunnerError::RunningError(e)) => &e.partial_trace,
+        _ => return,
+    };
+    let token_count = RunTraceStats::from_messages(&engine_output.messages).total_token_count;
+    if token_count == 0 {
+        return;
+    }
+
+    rate_limiter.record_tokens(project_api_key, token_count);
+    if let Err(e) =
+        db::api_keys::record_api_key_usage(&db.pool, &project_api_key.id, 0, token_count).await
+    {
+        log::error!("Error recording API key token usage: {}", e);
+    }
+}
+
+/// Cancel the queued or running run, returns false if it's not found or already finished
+///
+/// Runs executed by another instance in queue mode are interrupted through the run queue.
+pub async fn cancel_run(
+    db: &DB,
+    http_client: &reqwest::Client,
+    interrupt_senders: &InterruptSenders,
+    run_execution: &RunExecution,
+    run_id: Uuid,
+    project_id: &Uuid,
+) -> anyhow::Result<bool> {
+    let Some(previous_status) = db::runs::cancel_run(&db.pool, &run_id, project_id).await? else {
+        return Ok(false);
+    };
+
+    // Queued runs are not started once cancelled, running ones are interrupted
+    // and notify webhooks when they stop
+    let sender = interrupt_senders.get(&run_id).map(|sender| sender.clone());
+    match (sender, run_execution.queue()) {
+        (Some(sender), _) => {
+            let _ = sender.send(GraphInterruptMessage::Cancel).await;
+        }
+        (None, Some(queue)) if previous_status == RunStatus::Running => {
+            queue.publish_cancellation(&run_id).await?;
+        }
+        (None, _) => {
+            tokio::spawn(webhooks::notify_run_finished(
+                db.clone(),
+                http_client.clone(),
+                run_id,
+                *project_id,
+            ));
+        }
+    }
+
+    Ok(true)
+}
+
+/// Periodically clear outputs of runs older than `RUN_RESULT_TTL_SECONDS`, and remove
+/// expired idempotency keys, node I/O records and checkpoints of completed runs
+pub async fn sweep_expired_run_results(
+    db: Arc<DB>,
+    node_io_store: Arc<dyn NodeIoStore>,
+    checkpoint_store: Arc<dyn CheckpointStore>,
+) {
+    let checkpoint_ttl = checkpoints::checkpoint_ttl();
+    let mut interval = tokio::time::interval(RUN_RESULT_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match db::runs::clear_expired_run_results(&db.pool).await {
+            Ok(0) => {}
+            Ok(cleared) => log::info!("Cleared results of {} expired runs", cleared),
+            Err(e) => log::error!("Failed to clear expired run results: {}", e),
+        }
+        match db::idempotency_keys::delete_expired_idempotency_keys(&db.pool).await {
+            Ok(0) => {}
+            Ok(deleted) => log::info!("Deleted {} expired idempotency keys", deleted),
+            Err(e) => log::error!("Failed to delete expired idempotency keys: {}", e),
+        }
+        match node_io_store.delete_expired().await {
+            Ok(0) => {}
+            Ok(deleted) => log::info!("Deleted {} expired node I/O records", deleted),
+            Err(e) => log::error!("Failed to delete expired node I/O records: {}", e),
+        }
+        match checkpoint_store.delete_completed(checkpoint_ttl).await {
+            Ok(0) => {}
+            Ok(deleted) => log::info!("Deleted checkpoints of {} completed runs", deleted),
+            Err(e) => log::error!("Failed to delete checkpoints of completed runs: {}", e),
+        }
+    }
+}
+
+/// Wait for SIGTERM or Ctrl-C, then drain the instance before stopping the server
+///
+/// While draining, the readiness probe fails so that no new requests are routed here, and the
+/// server keeps serving until active and queued runs finish or `SHUTDOWN_DRAIN_SECONDS` pass.
+pub async fn drain_on_shutdown(
+    server: actix_web::dev::ServerHandle,
+    engine_stats: Arc<EngineStats>,
+    interrupt_senders: Arc<InterruptSenders>,
+) {
+    wait_for_shutdown_signal().await;
+
+    let drain_timeout = env::var("SHUTDOWN_DRAIN_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SHUTDOWN_DRAIN_SECONDS);
+    log::info!(
+        "Shutdown signal received, draining for up to {}s",
+        drain_timeout
+    );
+    engine_stats.draining.store(true, Ordering::Relaxed);
+
+    let drained = async {
+        while !interrupt_senders.is_empty() || engine_stats.queued_runs() > 0 {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    };
+    if tokio::time::timeout(Duration::from_secs(drain_timeout), drained)
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "Stopping with {} active and {} queued runs",
+            interrupt_senders.len(),
+            engine_stats.queued_runs()
+        );
+    }
+
+    server.stop(true).await;
+}
+
+async fn wait_for_shutdown_signal() {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::workspace::WorkspaceId,
+        testing::{template_chain_graph, MemorySink, OfflineServices},
+    };
+
+    use super::*;
+
+    /// Run of a graph of an input and a template, whose spans the runner exports to the sink
+    async fn execute(trace_mode: TraceMode, sink: Arc<MemorySink>) -> Uuid {
+        // the database is never reachable, so its writes fail fast and the spans are exported
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(50))
+            .connect_lazy("postgres://localhost/laminar")
+            .unwrap();
+        let db = Arc::new(DB::new(pool));
+        let span_exporter = Arc::new(SpanExporter::start_with_sink(sink, db.clone()));
+        let pipeline_runner = OfflineServices::default()
+            .context()
+            .pipeline_runner
+            .with_span_exporter(span_exporter.clone());
+
+        let runnable_graph = template_chain_graph(1);
+        let mut graph = serde_json::from_value::<Graph>(runnable_graph.clone()).unwrap();
+        let inputs =
+            HashMap::from([("question".to_string(), NodeInput::String("why".to_string()))]);
+        graph
+            .setup(
+                &inputs,
+                &HashMap::new(),
+                &HashMap::new(),
+                &RunType::Endpoint,
+            )
+            .unwrap();
+        let project_api_key = ProjectApiKey {
+            id: Uuid::new_v4(),
+            project_id: Uuid::new_v4(),
+            workspace_id: WorkspaceId(Uuid::new_v4()),
+            name: None,
+            shorthand: "lm...key".to_string(),
+            scopes: vec![],
+            pipeline_ids: None,
+            requests_per_minute: None,
+            tokens_per_day: None,
+            last_used_at: None,
+        };
+        let trace_id = Uuid::new_v4();
+        let run = PreparedRun {
+            run_id: Uuid::new_v4(),
+            project_id: project_api_key.project_id,
+            pipeline_version: PipelineVersion {
+                id: Uuid::new_v4(),
+                pipeline_id: Uuid::new_v4(),
+                pipeline_type: "COMMIT".to_string(),
+                name: "v1".to_string(),
+                displayable_graph: runnable_graph.clone(),
+                runnable_graph,
+                created_at: Utc::now(),
+                content_hash: None,
+            },
+            graph,
+            secrets: HashMap::new(),
+            inputs,
+            env: HashMap::new(),
+            metadata: HashMap::new(),
+            parent_span_id: None,
+            trace_id,
+            replay: None,
+            trace_mode,
+            leased_by: None,
+            checkpoints: None,
+        };
+
+        execute_run(
+            run,
+            None,
+            &pipeline_runner,
+            &db,
+            &ApiKeyRateLimiter::default(),
+            &project_api_key,
+            &InterruptSenders::new(),
+        )
+        .await
+        .unwrap();
+        trace_id
+    }
+
+    #[tokio::test]
+    async fn test_strict_run_persists_trace_before_returning() {
+        let sink = Arc::new(MemorySink::default());
+        let trace_id = execute(TraceMode::Strict, sink.clone()).await;
+
+        // the run span, templates have no spans of their own
+        assert_eq!(sink.trace_ids(), vec![trace_id]);
+    }
+
+    #[tokio::test]
+    async fn test_async_run_leaves_trace_to_collector() {
+        let sink = Arc::new(MemorySink::default());
+        execute(TraceMode::Async, sink.clone()).await;
+
+        assert!(sink.span_ids().is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::fs;
+use uuid::Uuid;
+
+use super::{NodeIoRecord, NodeIoStore};
+
+const EXPIRES_AT_FILE: &str = "expires_at";
+const RECORD_EXTENSION: &str = ".json.gz";
+
+/// Stores records as `{run_id}/{node_id}.{execution}.json.gz` files under the directory, with
+/// the expiry of the run's records in `{run_id}/expires_at`
+#[derive(Debug)]
+pub struct FsNodeIoStore {
+    dir: PathBuf,
+}
+
+impl FsNodeIoStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn run_dir(&self, run_id: &Uuid) -> PathBuf {
+        self.dir.join(run_id.to_string())
+    }
+
+    /// Records of the run whose file names start with the prefix, in order of execution
+    async fn read_records(&self, run_id: &Uuid, prefix: &str) -> Result<Vec<NodeIoRecord>> {
+        let run_dir = self.run_dir(run_id);
+        // records of runs without expiry are still being written
+        if !fs::try_exists(&run_dir).await? || is_expired(&run_dir).await.unwrap_or(true) {
+            return Ok(vec![]);
+        }
+
+        let mut records = Vec::new();
+        let mut entries = fs::read_dir(&run_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name.starts_with(prefix) && file_name.ends_with(RECORD_EXTENSION) {
+                records.push(NodeIoRecord::decode(&fs::read(entry.path()).await?)?);
+            }
+        }
+        records.sort_by_key(|record| (record.node_id, record.execution));
+
+        Ok(records)
+    }
+}
+
+async fn is_expired(run_dir: &Path) -> Result<bool> {
+    let expires_at = fs::read_to_string(run_dir.join(EXPIRES_AT_FILE)).await?;
+    let expires_at = DateTime::parse_from_rfc3339(expires_at.trim())?;
+    Ok(expires_at < Utc::now())
+}
+
+#[async_trait]
+impl NodeIoStore for FsNodeIoStore {
+    async fn put(
+        &self,
+        run_id: &Uuid,
+        records: &[NodeIoRecord],
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let run_dir = self.run_dir(run_id);
+        fs::create_dir_all(&run_dir).await?;
+        for record in records {
+            let file_name = format!(
+                "{}.{}{}",
+                record.node_id, record.execution, RECORD_EXTENSION
+            );
+            fs::write(run_dir.join(file_name), record.encode()?).await?;
+        }
+        fs::write(run_dir.join(EXPIRES_AT_FILE), expires_at.to_rfc3339()).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, run_id: &Uuid, node_id: &Uuid) -> Result<Vec<NodeIoRecord>> {
+        self.read_records(run_id, &format!("{}.", node_id)).await
+    }
+
+    async fn get_run(&self, run_id: &Uuid) -> Result<Vec<NodeIoRecord>> {
+        self.read_records(run_id, "").await
+    }
+
+    async fn delete_expired(&self) -> Result<u64> {
+        if !fs::try_exists(&self.dir).await? {
+            return Ok(0);
+        }
+
+        let mut deleted = 0;
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let run_dir = entry.path();
+            // directories without expiry are being written, or aren't of a run
+            if let Ok(true) = is_expired(&run_dir).await {
+                fs::remove_dir_all(&run_dir).await?;
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn delete_runs(&self, run_ids: &[Uuid]) -> Result<()> {
+        for run_id in run_ids {
+            let run_dir = self.run_dir(run_id);
+            if fs::try_exists(&run_dir).await? {
+                fs::remove_dir_all(&run_dir).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::runs::node_io::{InputState, RecordedInput, RecordedOutput};
+
+    use super::*;
+
+    fn record(run_id: Uuid, node_id: Uuid, execution: i32) -> NodeIoRecord {
+        NodeIoRecord {
+            run_id,
+            node_id,
+            node_name: "node".to_string(),
+            node_type: "StringTemplate".to_string(),
+            execution,
+            inputs: HashMap::from([(
+                "input".to_string(),
+                RecordedInput {
+                    state: InputState::Empty,
+                    message_id: Uuid::new_v4(),
+                    value: Some(serde_json::json!("")),
+                    scrubbed_secrets: Vec::new(),
+                },
+            )]),
+            output: RecordedOutput {
+                message_id: Uuid::new_v4(),
+                value: Some(serde_json::json!(execution)),
+                scrubbed_secrets: Vec::new(),
+                meta_log: None,
+                start_time: Utc::now(),
+                end_time: Utc::now(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fs_store() {
+        let store = FsNodeIoStore::new(std::env::temp_dir().join(Uuid::new_v4().to_string()));
+        let run_id = Uuid::new_v4();
+        let node_id = Uuid::new_v4();
+        let records = [
+            record(run_id, node_id, 1),
+            record(run_id, node_id, 0),
+            record(run_id, Uuid::new_v4(), 0),
+        ];
+        store
+            .put(&run_id, &records, Utc::now() + chrono::Duration::days(1))
+            .await
+            .unwrap();
+
+        let node_records = store.get(&run_id, &node_id).await.unwrap();
+        assert_eq!(
+            node_records.iter().map(|r| r.execution).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(node_records[0].inputs["input"].state, InputState::Empty);
+        assert_eq!(store.get_run(&run_id).await.unwrap().len(), 3);
+        assert_eq!(store.delete_expired().await.unwrap(), 0);
+
+        store
+            .put(&run_id, &[], Utc::now() - chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert!(store.get_run(&run_id).await.unwrap().is_empty());
+        assert_eq!(store.delete_expired().await.unwrap(), 1);
+        fs::remove_dir_all(&store.dir).await.unwrap();
+    }
+}
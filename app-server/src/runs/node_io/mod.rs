@@ -0,0 +1,386 @@
+//! Inputs and outputs of the nodes of runs, persisted for debugging and replay
+//!
+//! Recording is enabled per pipeline with `record_node_io`, or per run with `recordNodeIo` in the
+//! run request. The engine keeps the input each node received on each handle, including empty
+//! ones, and once the run is finished a record per node execution is written to the
+//! `NodeIoStore`, selected with `NODE_IO_STORE`. Records have secret values scrubbed, values
+//! dropped to fit into `NODE_IO_MAX_RECORD_BYTES`, and are stored gzip-compressed until the
+//! log retention of the project's tier passes, same as traces.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    db::{self, DB},
+    engine::engine::{EngineOutput, TaskInput},
+    pipeline::nodes::{Message, NodeInput},
+    secrets::{self, ScrubbedSecret},
+};
+
+mod fs;
+mod postgres;
+
+pub use fs::FsNodeIoStore;
+pub use postgres::PostgresNodeIoStore;
+
+const DEFAULT_MAX_RECORD_BYTES: usize = 1024 * 1024;
+const DEFAULT_NODE_IO_DIR: &str = "./node-io";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InputState {
+    Success,
+    /// The handle had no message, e.g. it's on a branch which wasn't taken, and the node received
+    /// the value of an empty message
+    Empty,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedInput {
+    pub state: InputState,
+    /// Id of the message the input came from
+    pub message_id: Uuid,
+    /// Serialized `NodeInput`, None if it was dropped to fit the record into the size cap
+    pub value: Option<Value>,
+    /// Secrets scrubbed from the value, which are restored when the run is replayed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scrubbed_secrets: Vec<ScrubbedSecret>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedOutput {
+    pub message_id: Uuid,
+    /// Serialized `NodeInput`, the error if the node failed. None if it was dropped to fit the
+    /// record into the size cap.
+    pub value: Option<Value>,
+    /// Secrets scrubbed from the value, which are restored when the run is replayed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scrubbed_secrets: Vec<ScrubbedSecret>,
+    /// None if the node has no meta log, or it was dropped to fit the record into the size cap
+    pub meta_log: Option<Value>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+/// Inputs and output of an execution of a node
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeIoRecord {
+    pub run_id: Uuid,
+    pub node_id: Uuid,
+    pub node_name: String,
+    pub node_type: String,
+    /// Index of the node's execution in the run, nodes in cycles run more than once
+    pub execution: i32,
+    /// Inputs by handle name
+    pub inputs: HashMap<String, RecordedInput>,
+    pub output: RecordedOutput,
+}
+
+impl NodeIoRecord {
+    fn new(
+        run_id: Uuid,
+        execution: i32,
+        message: &Message,
+        inputs: &HashMap<String, TaskInput>,
+        secrets: &HashMap<String, String>,
+    ) -> Self {
+        let redacted = |mut value: Value| {
+            secrets::scrub_secrets(&mut value, secrets);
+            value
+        };
+        let scrubbed = |value: &NodeInput| match serde_json::to_value(value) {
+            Ok(mut value) => {
+                let scrubbed_secrets = secrets::scrub_marked_secrets(&mut value, secrets);
+                (Some(value), scrubbed_secrets)
+            }
+            Err(_) => (None, Vec::new()),
+        };
+        let inputs = inputs
+            .iter()
+            .map(|(handle_name, input)| {
+                let (value, scrubbed_secrets) = scrubbed(&input.message.value);
+                let recorded = RecordedInput {
+                    state: if input.empty {
+                        InputState::Empty
+                    } else {
+                        InputState::Success
+                    },
+                    message_id: input.message.id,
+                    value,
+                    scrubbed_secrets,
+                };
+                (handle_name.clone(), recorded)
+            })
+            .collect();
+
+        let (value, scrubbed_secrets) = scrubbed(&message.value);
+        Self {
+            run_id,
+            node_id: message.node_id,
+            node_name: message.node_name.clone(),
+            node_type: message.node_type.clone(),
+            execution,
+            inputs,
+            output: RecordedOutput {
+                message_id: message.id,
+                value,
+                scrubbed_secrets,
+                meta_log: message
+                    .meta_log
+                    .as_ref()
+                    .and_then(|meta_log| serde_json::to_value(meta_log).ok())
+                    .map(redacted),
+                start_time: message.start_time,
+                end_time: message.end_time,
+            },
+        }
+    }
+
+    /// Whether values were dropped by the size cap, such records can't be replayed
+    pub fn is_truncated(&self) -> bool {
+        self.output.value.is_none() || self.inputs.values().any(|input| input.value.is_none())
+    }
+
+    /// Drop the largest values until the serialized record fits into `max_bytes`
+    fn truncate(&mut self, max_bytes: usize) {
+        let mut size = serde_json::to_vec(&*self)
+            .map(|json| json.len())
+            .unwrap_or(0);
+        while size > max_bytes {
+            let largest = self
+                .inputs
+                .values_mut()
+                .map(|input| &mut input.value)
+                .chain([&mut self.output.value, &mut self.output.meta_log])
+                .filter_map(|value| {
+                    let value_size = value.as_ref()?.to_string().len();
+                    Some((value_size, value))
+                })
+                .max_by_key(|(value_size, _)| *value_size);
+            let Some((value_size, value)) = largest else {
+                break;
+            };
+            *value = None;
+            size = size.saturating_sub(value_size);
+        }
+    }
+
+    /// Gzip-compressed JSON of the record
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(self)?)?;
+        Ok(encoder.finish()?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut json = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut json)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// Records of the nodes executed in the run, empty if the engine didn't record their inputs
+pub fn records_of_run(
+    run_id: Uuid,
+    engine_output: &EngineOutput,
+    secrets: &HashMap<String, String>,
+    max_bytes: usize,
+) -> Vec<NodeIoRecord> {
+    let mut messages = engine_output
+        .task_inputs
+        .iter()
+        .filter_map(|(message_id, inputs)| {
+            engine_output
+                .messages
+                .get(message_id)
+                .map(|message| (message, inputs))
+        })
+        .collect::<Vec<_>>();
+    messages.sort_by_key(|(message, _)| message.start_time);
+
+    let mut executions = HashMap::<Uuid, i32>::new();
+    messages
+        .into_iter()
+        .map(|(message, inputs)| {
+            let execution = executions.entry(message.node_id).or_insert(0);
+            let mut record = NodeIoRecord::new(run_id, *execution, message, inputs, secrets);
+            record.truncate(max_bytes);
+            *execution += 1;
+            record
+        })
+        .collect()
+}
+
+/// Storage of node I/O records, keyed by run id and node id
+#[async_trait]
+pub trait NodeIoStore: Send + Sync + std::fmt::Debug {
+    /// Write the records of a run, they're removed by `delete_expired` after `expires_at`
+    async fn put(
+        &self,
+        run_id: &Uuid,
+        records: &[NodeIoRecord],
+        expires_at: DateTime<Utc>,
+    ) -> Result<()>;
+
+    /// Records of the node's executions in the run, in order of execution
+    async fn get(&self, run_id: &Uuid, node_id: &Uuid) -> Result<Vec<NodeIoRecord>>;
+
+    /// Records of all nodes of the run
+    async fn get_run(&self, run_id: &Uuid) -> Result<Vec<NodeIoRecord>>;
+
+    /// Remove expired records, returns the number of removed runs or records
+    async fn delete_expired(&self) -> Result<u64>;
+
+    /// Remove all records of the runs, e.g. when their content is purged
+    async fn delete_runs(&self, run_ids: &[Uuid]) -> Result<()>;
+}
+
+/// Store selected with `NODE_IO_STORE`, `postgres` by default, or `fs` to write records to
+/// files under `NODE_IO_DIR`
+pub fn store_from_env(db: Arc<DB>) -> Arc<dyn NodeIoStore> {
+    match env::var("NODE_IO_STORE").as_deref() {
+        Ok("fs") => {
+            let dir = env::var("NODE_IO_DIR").unwrap_or_else(|_| DEFAULT_NODE_IO_DIR.to_string());
+            log::info!("Storing node I/O of runs in {}", dir);
+            Arc::new(FsNodeIoStore::new(dir.into()))
+        }
+        Ok("postgres") | Err(_) => Arc::new(PostgresNodeIoStore::new(db)),
+        Ok(other) => panic!("Unknown NODE_IO_STORE: {}", other),
+    }
+}
+
+fn max_record_bytes() -> usize {
+    env::var("NODE_IO_MAX_RECORD_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RECORD_BYTES)
+}
+
+/// Persist node I/O recorded by the engine, partial runs of failed pipelines included
+pub async fn record_run(
+    store: &dyn NodeIoStore,
+    db: &DB,
+    run_id: Uuid,
+    project_id: &Uuid,
+    engine_output: &EngineOutput,
+    secrets: &HashMap<String, String>,
+) -> Result<()> {
+    let records = records_of_run(run_id, engine_output, secrets, max_record_bytes());
+    if records.is_empty() {
+        return Ok(());
+    }
+    let log_retention_days = db::limits::get_log_retention_days(&db.pool, project_id).await?;
+    let expires_at = Utc::now() + chrono::Duration::days(log_retention_days);
+
+    store.put(&run_id, &records, expires_at).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::task::State, pipeline::nodes::NodeInput};
+
+    use super::*;
+
+    fn message(node_name: &str, value: NodeInput) -> Message {
+        Message {
+            value,
+            node_id: Uuid::new_v4(),
+            node_name: node_name.to_string(),
+            node_type: "StringTemplate".to_string(),
+            ..Message::empty()
+        }
+    }
+
+    /// Output of a run in which `node` received `question` and an empty `context`
+    fn engine_output(question: &Message, node: &Message) -> EngineOutput {
+        let inputs = HashMap::from([
+            (
+                "question".to_string(),
+                TaskInput::from_state(&State::new(question.clone())),
+            ),
+            (
+                "context".to_string(),
+                TaskInput::from_state(&State::empty()),
+            ),
+        ]);
+        EngineOutput {
+            output_message_ids: vec![node.id],
+            messages: HashMap::from([(question.id, question.clone()), (node.id, node.clone())]),
+            task_inputs: HashMap::from([(node.id, inputs)]),
+            blocked_nodes: Vec::new(),
+            bound_outputs: None,
+            finish_message_id: None,
+            run_store: None,
+        }
+    }
+
+    #[test]
+    fn test_records_match_node_inputs() {
+        let question = message("question", NodeInput::String("key is sk-123".to_string()));
+        let node = message("answer", NodeInput::String("answer".to_string()));
+        let secrets = HashMap::from([("KEY".to_string(), "sk-123".to_string())]);
+        let run_id = Uuid::new_v4();
+
+        let records = records_of_run(
+            run_id,
+            &engine_output(&question, &node),
+            &secrets,
+            DEFAULT_MAX_RECORD_BYTES,
+        );
+        assert_eq!(records.len(), 1);
+        let record = NodeIoRecord::decode(&records[0].encode().unwrap()).unwrap();
+        assert_eq!(record.node_id, node.node_id);
+        assert_eq!(record.output.message_id, node.id);
+        assert_eq!(
+            record.output.value,
+            Some(Value::String("answer".to_string()))
+        );
+
+        let input = &record.inputs["question"];
+        assert_eq!(input.state, InputState::Success);
+        assert_eq!(input.message_id, question.id);
+        assert_eq!(
+            input.value,
+            Some(Value::String("key is {{secret:KEY}}".to_string()))
+        );
+        // the node received the value of an empty message on the handle
+        let empty = &record.inputs["context"];
+        assert_eq!(empty.state, InputState::Empty);
+        assert_eq!(empty.value, Some(Value::String(String::new())));
+        assert!(!record.is_truncated());
+    }
+
+    #[test]
+    fn test_truncate_largest_values() {
+        let question = message("question", NodeInput::String("q".repeat(1000)));
+        let node = message("answer", NodeInput::String("answer".to_string()));
+
+        let records = records_of_run(
+            Uuid::new_v4(),
+            &engine_output(&question, &node),
+            &HashMap::new(),
+            800,
+        );
+        let record = &records[0];
+        assert!(record.is_truncated());
+        assert!(record.inputs["question"].value.is_none());
+        assert!(record.inputs["context"].value.is_some());
+        assert!(record.output.value.is_some());
+    }
+}
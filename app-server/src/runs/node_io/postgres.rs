@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::db::{self, node_io::NewNodeIo, DB};
+
+use super::{NodeIoRecord, NodeIoStore};
+
+/// Stores records in the `run_node_io` table, removed with their run
+#[derive(Debug)]
+pub struct PostgresNodeIoStore {
+    db: Arc<DB>,
+}
+
+impl PostgresNodeIoStore {
+    pub fn new(db: Arc<DB>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl NodeIoStore for PostgresNodeIoStore {
+    async fn put(
+        &self,
+        run_id: &Uuid,
+        records: &[NodeIoRecord],
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let records = records
+            .iter()
+            .map(|record| {
+                Ok(NewNodeIo {
+                    node_id: record.node_id,
+                    execution: record.execution,
+                    data: record.encode()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        db::node_io::insert_node_io(&self.db.pool, run_id, records, expires_at).await
+    }
+
+    async fn get(&self, run_id: &Uuid, node_id: &Uuid) -> Result<Vec<NodeIoRecord>> {
+        db::node_io::get_node_io(&self.db.pool, run_id, Some(node_id))
+            .await?
+            .iter()
+            .map(|data| NodeIoRecord::decode(data))
+            .collect()
+    }
+
+    async fn get_run(&self, run_id: &Uuid) -> Result<Vec<NodeIoRecord>> {
+        db::node_io::get_node_io(&self.db.pool, run_id, None)
+            .await?
+            .iter()
+            .map(|data| NodeIoRecord::decode(data))
+            .collect()
+    }
+
+    async fn delete_expired(&self) -> Result<u64> {
+        db::node_io::delete_expired_node_io(&self.db.pool).await
+    }
+
+    async fn delete_runs(&self, run_ids: &[Uuid]) -> Result<()> {
+        db::node_io::delete_node_io_of_runs(&self.db.pool, run_ids).await
+    }
+}
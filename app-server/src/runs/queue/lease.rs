@@ -0,0 +1,89 @@
+//! Leases of runs from the run queue on the workers executing them
+//!
+//! A worker claims a run with a lease of `RUN_LEASE_SECONDS`, and renews it while it executes
+//! the run, so that the run, and the state of its loops which only exists in the worker's engine,
+//! stays on the worker. A job redelivered while the lease is held waits for it to expire, i.e. for
+//! the worker to stop, and then takes the run over. Runs with loops are checkpointed, so the
+//! worker which takes one over resumes it from the last snapshots of its tasks, re-running at
+//! most the iteration which was in flight, see `checkpoints`.
+//!
+//! A worker which loses the lease of its run, e.g. because it couldn't reach the database for
+//! longer than the lease, stops the run. Only the worker holding the lease writes the result.
+
+use std::{env, sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    db::{self, DB},
+    routes::pipelines::GraphInterruptMessage,
+    runs::InterruptSenders,
+};
+
+const DEFAULT_LEASE_SECONDS: i64 = 30;
+/// Renewals in each lease period, so that a renewal which fails once doesn't lose the lease
+const RENEWALS_PER_LEASE: u32 = 3;
+
+pub fn lease_seconds() -> i64 {
+    env::var("RUN_LEASE_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse::<i64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .unwrap_or(DEFAULT_LEASE_SECONDS)
+}
+
+/// Renews the worker's lease of the run until it's dropped
+pub struct LeaseHeartbeat {
+    renewals: JoinHandle<()>,
+}
+
+impl LeaseHeartbeat {
+    /// Start renewing the lease the worker claimed the run with. If the lease is lost, the run is
+    /// interrupted once it's registered in `interrupt_senders`.
+    pub fn start(
+        db: Arc<DB>,
+        run_id: Uuid,
+        worker_id: Uuid,
+        interrupt_senders: Arc<InterruptSenders>,
+    ) -> Self {
+        let renewals = tokio::spawn(renew(db, run_id, worker_id, interrupt_senders));
+        Self { renewals }
+    }
+}
+
+impl Drop for LeaseHeartbeat {
+    fn drop(&mut self) {
+        self.renewals.abort();
+    }
+}
+
+async fn renew(
+    db: Arc<DB>,
+    run_id: Uuid,
+    worker_id: Uuid,
+    interrupt_senders: Arc<InterruptSenders>,
+) {
+    let lease_seconds = lease_seconds();
+    let period = Duration::from_secs(lease_seconds as u64) / RENEWALS_PER_LEASE;
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        match db::runs::renew_run_lease(&db.pool, &run_id, &worker_id, lease_seconds).await {
+            Ok(true) => {}
+            Ok(false) => {
+                // also when the run was cancelled or finished, which already stops it
+                log::warn!(
+                    "Run {} is no longer leased to this worker, stopping it",
+                    run_id
+                );
+                let sender = interrupt_senders.get(&run_id).map(|sender| sender.clone());
+                if let Some(sender) = sender {
+                    let _ = sender.send(GraphInterruptMessage::Cancel).await;
+                }
+                return;
+            }
+            Err(e) => log::error!("Failed to renew lease of run {}: {}", run_id, e),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Execution of API runs on worker instances, see `RunExecution`
+//!
+//! In queue mode, instances which receive run requests validate them and push them as jobs to
+//! the run queue, and workers, i.e. instances with `RUN_WORKER_CONCURRENCY` set, execute them
+//! with their local engine. Results and traces are written to the shared stores, so run status
+//! can be polled from any instance. Events of streamed and sync runs are relayed back to the
+//! instance which received the request, and cancellations are broadcast to all instances.
+//!
+//! Jobs are acknowledged once their run is finished. If a worker stops before that, or holds a
+//! job for longer than `RUN_QUEUE_VISIBILITY_TIMEOUT_SECONDS`, the job is redelivered and the
+//! run is retried, until it was started `RUN_QUEUE_MAX_ATTEMPTS` times and is marked interrupted.
+//! A run is leased to the worker executing it, and only retried on another worker once the lease
+//! expired, from its checkpoint if it has loops, see `lease`. Jobs which can't be parsed are
+//! captured as dead letters and acknowledged.
+
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use lapin::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    db::{api_keys::ProjectApiKey, DB},
+    pipeline::{nodes::NodeInput, runner::PipelineRunnerError},
+    secrets,
+};
+
+use super::{record_run_result, PreparedRun, TraceMode};
+
+pub mod lease;
+mod rabbitmq;
+mod worker;
+
+pub use rabbitmq::RabbitMqRunQueue;
+pub use worker::{listen_for_cancellations, RunWorker};
+
+const DEFAULT_VISIBILITY_TIMEOUT_SECONDS: u64 = 30 * 60;
+const DEFAULT_MAX_ATTEMPTS: i32 = 3;
+
+/// Run pushed to the queue, validated and with a resolved pipeline version
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunJob {
+    pub run_id: Uuid,
+    pub pipeline_version_id: Uuid,
+    pub project_api_key: ProjectApiKey,
+    pub inputs: HashMap<String, NodeInput>,
+    /// Env is encrypted, since it usually has provider API keys
+    pub env_nonce: String,
+    pub env_value: String,
+    pub metadata: HashMap<String, String>,
+    pub parent_span_id: Option<Uuid>,
+    pub trace_id: Uuid,
+    /// Relay node chunks while the run executes, not only its end
+    pub stream: bool,
+    /// See `Graph::record_node_io`
+    #[serde(default)]
+    pub record_node_io: bool,
+    #[serde(default)]
+    pub trace_mode: TraceMode,
+}
+
+/// Name under which the env is encrypted, binds the ciphertext to the run
+fn env_secret_name(run_id: &Uuid) -> String {
+    format!("run:{}", run_id)
+}
+
+impl RunJob {
+    pub fn new(run: &PreparedRun, project_api_key: &ProjectApiKey, stream: bool) -> Result<Self> {
+        let (env_nonce, env_value) = secrets::encrypt(
+            &env_secret_name(&run.run_id),
+            &serde_json::to_string(&run.env)?,
+        )?;
+
+        Ok(Self {
+            run_id: run.run_id,
+            pipeline_version_id: run.pipeline_version.id,
+            project_api_key: project_api_key.clone(),
+            inputs: run.inputs.clone(),
+            env_nonce,
+            env_value,
+            metadata: run.metadata.clone(),
+            parent_span_id: run.parent_span_id,
+            trace_id: run.trace_id,
+            stream,
+            record_node_io: run.graph.record_node_io,
+            trace_mode: run.trace_mode,
+        })
+    }
+
+    pub fn env(&self) -> Result<HashMap<String, String>> {
+        let env = secrets::decrypt(
+            &env_secret_name(&self.run_id),
+            &self.env_nonce,
+            &self.env_value,
+        )?;
+        Ok(serde_json::from_str(&env)?)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub enum RunEvent {
+    /// Serialized `StreamChunk` of a node, only relayed for streamed runs
+    Chunk(Value),
+    Finished(RunEnd),
+}
+
+/// Result of the run, in the form it's responded to the request
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunEnd {
+    /// Serialized last `StreamChunk` of streamed runs, with the outputs or the error
+    pub chunk: Value,
+    /// Status and body of the response to sync requests
+    pub status: u16,
+    pub response: Option<Value>,
+}
+
+/// Acknowledges the job once it's processed, so that it's not redelivered
+#[async_trait]
+pub trait JobAcker: Send + Sync {
+    async fn ack(&self) -> Result<()>;
+    /// Put the job back to the queue, to be redelivered
+    async fn requeue(&self) -> Result<()>;
+}
+
+/// Payload of a delivered job which isn't a valid `RunJob`, e.g. pushed before a deploy
+pub struct UnparseableJob {
+    pub payload: Vec<u8>,
+    pub error: String,
+}
+
+pub struct JobDelivery {
+    /// Unparseable jobs are delivered too, so that they're kept as dead letters, not dropped
+    pub job: Result<RunJob, UnparseableJob>,
+    /// The job was delivered before, to a worker which didn't acknowledge it
+    pub redelivered: bool,
+    pub acker: Box<dyn JobAcker>,
+}
+
+/// Queue of run jobs, with channels relaying run events and cancellations between instances
+#[async_trait]
+pub trait RunQueue: Send + Sync {
+    async fn push_job(&self, job: &RunJob) -> Result<()>;
+    /// Jobs for this worker, at most `concurrency` of them unacknowledged at a time
+    ///
+    /// Ends if the connection to the queue is lost, unacknowledged jobs are then redelivered.
+    async fn jobs(&self, concurrency: u16) -> Result<BoxStream<'static, JobDelivery>>;
+    async fn publish_event(&self, run_id: &Uuid, event: &RunEvent) -> Result<()>;
+    /// Events of the run, subscribe before pushing its job so that none are missed
+    async fn events(&self, run_id: &Uuid) -> Result<BoxStream<'static, RunEvent>>;
+    async fn publish_cancellation(&self, run_id: &Uuid) -> Result<()>;
+    /// Ids of runs cancelled on any instance
+    async fn cancellations(&self) -> Result<BoxStream<'static, Uuid>>;
+}
+
+/// Where API runs are executed, set with `RUN_EXECUTION_MODE`
+///
+/// `local` (default) executes runs on the instance which received them, `queue` pushes them
+/// to the run queue to be executed by workers.
+#[derive(Clone)]
+pub enum RunExecution {
+    Local,
+    Queue(Arc<dyn RunQueue>),
+}
+
+fn visibility_timeout() -> Duration {
+    let seconds = env::var("RUN_QUEUE_VISIBILITY_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_VISIBILITY_TIMEOUT_SECONDS);
+    Duration::from_secs(seconds)
+}
+
+fn max_attempts() -> i32 {
+    env::var("RUN_QUEUE_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|attempts| attempts.parse::<i32>().ok())
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+}
+
+/// Number of jobs executed at a time by this instance, 0 if it's not a worker
+pub fn worker_concurrency() -> u16 {
+    env::var("RUN_WORKER_CONCURRENCY")
+        .ok()
+        .and_then(|concurrency| concurrency.parse::<u16>().ok())
+        .unwrap_or(0)
+}
+
+impl RunExecution {
+    pub async fn from_env(rabbitmq_connection: Arc<Connection>) -> Self {
+        match env::var("RUN_EXECUTION_MODE").as_deref() {
+            Ok("queue") => {
+                let queue = RabbitMqRunQueue::new(rabbitmq_connection, visibility_timeout())
+                    .await
+                    .expect("Failed to declare run queue");
+                RunExecution::Queue(Arc::new(queue))
+            }
+            Ok("local") | Err(_) => RunExecution::Local,
+            Ok(mode) => panic!("Unknown RUN_EXECUTION_MODE '{mode}', expected local or queue"),
+        }
+    }
+
+    pub fn queue(&self) -> Option<&dyn RunQueue> {
+        match self {
+            RunExecution::Local => None,
+            RunExecution::Queue(queue) => Some(queue.as_ref()),
+        }
+    }
+
+    /// Queue to push the run to, None if it's executed locally
+    ///
+    /// Runs with file inputs are always executed locally, since the files are stored on the
+    /// disk of the instance which received them, and so are runs with request credentials,
+    /// which are never stored.
+    pub fn queue_for(&self, run: &PreparedRun) -> Option<&dyn RunQueue> {
+        let has_files = run
+            .inputs
+            .values()
+            .any(|input| matches!(input, NodeInput::File(_)));
+        self.queue()
+            .filter(|_| !has_files && run.graph.credentials.is_empty())
+    }
+}
+
+/// Push the job of a created run, the run fails if the job can't be pushed
+pub async fn submit_job(
+    queue: &dyn RunQueue,
+    db: &DB,
+    http_client: &reqwest::Client,
+    job: &RunJob,
+) -> Result<()> {
+    let pushed = queue.push_job(job).await;
+    if let Err(e) = &pushed {
+        let error = anyhow::anyhow!("Failed to push run to the run queue: {}", e);
+        record_run_result(
+            db,
+            http_client,
+            job.run_id,
+            job.project_api_key.project_id,
+            &Err(PipelineRunnerError::UnhandledError(error)),
+            None,
+            None,
+        )
+        .await;
+    }
+    pushed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_event_serialization() {
+        let event = RunEvent::Finished(RunEnd {
+            chunk: serde_json::json!({"type": "GraphRunOutput"}),
+            status: 200,
+            response: None,
+        });
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "Finished");
+        assert_eq!(value["content"]["status"], 200);
+
+        let RunEvent::Finished(end) = serde_json::from_value::<RunEvent>(value).unwrap() else {
+            panic!("Expected Finished event");
+        };
+        assert_eq!(end.chunk["type"], "GraphRunOutput");
+        assert!(end.response.is_none());
+    }
+}
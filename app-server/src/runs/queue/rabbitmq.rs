@@ -0,0 +1,266 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::{stream::BoxStream, StreamExt};
+use lapin::{
+    acker::Acker,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions,
+        BasicQosOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Channel, Connection, ExchangeKind,
+};
+use uuid::Uuid;
+
+use super::{JobAcker, JobDelivery, RunEvent, RunJob, RunQueue, UnparseableJob};
+
+const RUN_JOBS_QUEUE: &str = "run_jobs_queue";
+/// Direct exchange, events are routed by run id to the instance which subscribed to them
+const RUN_EVENTS_EXCHANGE: &str = "run_events_exchange";
+/// Fanout exchange, cancellations are sent to every instance
+const RUN_CANCELLATIONS_EXCHANGE: &str = "run_cancellations_exchange";
+const PERSISTENT_DELIVERY_MODE: u8 = 2;
+
+/// Run queue on RabbitMQ
+///
+/// Jobs are persistent messages in a durable queue, acknowledged manually. The visibility
+/// timeout is the queue's consumer timeout, after which RabbitMQ closes the channel of the
+/// worker and redelivers its unacknowledged jobs.
+pub struct RabbitMqRunQueue {
+    connection: Arc<Connection>,
+    publish_channel: Channel,
+}
+
+impl RabbitMqRunQueue {
+    pub async fn new(connection: Arc<Connection>, visibility_timeout: Duration) -> Result<Self> {
+        let channel = connection.create_channel().await?;
+
+        let mut args = FieldTable::default();
+        args.insert(
+            "x-consumer-timeout".into(),
+            AMQPValue::LongLongInt(visibility_timeout.as_millis() as i64),
+        );
+        channel
+            .queue_declare(
+                RUN_JOBS_QUEUE,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                args,
+            )
+            .await?;
+        channel
+            .exchange_declare(
+                RUN_EVENTS_EXCHANGE,
+                ExchangeKind::Direct,
+                ExchangeDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        channel
+            .exchange_declare(
+                RUN_CANCELLATIONS_EXCHANGE,
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(Self {
+            connection,
+            publish_channel: channel,
+        })
+    }
+
+    /// Exclusive queue bound to the exchange, deleted with its channel
+    async fn subscribe(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+    ) -> Result<(Channel, lapin::Consumer)> {
+        let channel = self.connection.create_channel().await?;
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                exchange,
+                routing_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        let consumer = channel
+            .basic_consume(
+                queue.name().as_str(),
+                "",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok((channel, consumer))
+    }
+
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<()> {
+        self.publish_channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                payload,
+                properties,
+            )
+            .await?
+            .await?;
+        Ok(())
+    }
+}
+
+struct RabbitMqJobAcker(Acker);
+
+#[async_trait]
+impl JobAcker for RabbitMqJobAcker {
+    async fn ack(&self) -> Result<()> {
+        self.0.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
+
+    async fn requeue(&self) -> Result<()> {
+        self.0
+            .nack(BasicNackOptions {
+                requeue: true,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RunQueue for RabbitMqRunQueue {
+    async fn push_job(&self, job: &RunJob) -> Result<()> {
+        self.publish(
+            "",
+            RUN_JOBS_QUEUE,
+            &serde_json::to_vec(job)?,
+            BasicProperties::default().with_delivery_mode(PERSISTENT_DELIVERY_MODE),
+        )
+        .await
+    }
+
+    async fn jobs(&self, concurrency: u16) -> Result<BoxStream<'static, JobDelivery>> {
+        let channel = self.connection.create_channel().await?;
+        channel
+            .basic_qos(concurrency, BasicQosOptions::default())
+            .await?;
+        let mut consumer = channel
+            .basic_consume(
+                RUN_JOBS_QUEUE,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(async_stream::stream! {
+            // Keep the channel open while the stream is consumed
+            let _channel = channel;
+            while let Some(delivery) = consumer.next().await {
+                let delivery = match delivery {
+                    Ok(delivery) => delivery,
+                    Err(e) => {
+                        log::error!("Failed to get run job from RabbitMQ: {}", e);
+                        break;
+                    }
+                };
+                let job = serde_json::from_slice::<RunJob>(&delivery.data).map_err(|e| {
+                    UnparseableJob {
+                        payload: delivery.data.clone(),
+                        error: e.to_string(),
+                    }
+                });
+                yield JobDelivery {
+                    job,
+                    redelivered: delivery.redelivered,
+                    acker: Box::new(RabbitMqJobAcker(delivery.acker)),
+                };
+            }
+        }
+        .boxed())
+    }
+
+    async fn publish_event(&self, run_id: &Uuid, event: &RunEvent) -> Result<()> {
+        self.publish(
+            RUN_EVENTS_EXCHANGE,
+            &run_id.to_string(),
+            &serde_json::to_vec(event)?,
+            BasicProperties::default(),
+        )
+        .await
+    }
+
+    async fn events(&self, run_id: &Uuid) -> Result<BoxStream<'static, RunEvent>> {
+        let (channel, mut consumer) = self
+            .subscribe(RUN_EVENTS_EXCHANGE, &run_id.to_string())
+            .await?;
+
+        Ok(async_stream::stream! {
+            // The exclusive queue is deleted once the stream is dropped and the channel closes
+            let _channel = channel;
+            while let Some(Ok(delivery)) = consumer.next().await {
+                match serde_json::from_slice::<RunEvent>(&delivery.data) {
+                    Ok(event) => yield event,
+                    Err(e) => log::error!("Failed to parse run event: {}", e),
+                }
+            }
+        }
+        .boxed())
+    }
+
+    async fn publish_cancellation(&self, run_id: &Uuid) -> Result<()> {
+        self.publish(
+            RUN_CANCELLATIONS_EXCHANGE,
+            "",
+            run_id.as_bytes(),
+            BasicProperties::default(),
+        )
+        .await
+    }
+
+    async fn cancellations(&self) -> Result<BoxStream<'static, Uuid>> {
+        let (channel, mut consumer) = self.subscribe(RUN_CANCELLATIONS_EXCHANGE, "").await?;
+
+        Ok(async_stream::stream! {
+            let _channel = channel;
+            while let Some(Ok(delivery)) = consumer.next().await {
+                match Uuid::from_slice(&delivery.data) {
+                    Ok(run_id) => yield run_id,
+                    Err(e) => log::error!("Failed to parse run cancellation: {}", e),
+                }
+            }
+        }
+        .boxed())
+    }
+}
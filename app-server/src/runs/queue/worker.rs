@@ -0,0 +1,397 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::ResponseError;
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    auth::rate_limit::ApiKeyRateLimiter,
+    db::{self, runs::RunClaim, DB},
+    dead_letters,
+    pipeline::{
+        nodes::{GraphRunOutput, RunEndpointEventError, StreamChunk},
+        runner::{PipelineRunner, PipelineRunnerError},
+        utils::get_graph_content_hash,
+    },
+    routes::{error::pipeline_runner_to_http_error, pipelines::GraphInterruptMessage},
+    runs::{
+        checkpoints::{self, RunCheckpoints},
+        execute_run, record_run_result, setup_graph, InterruptSenders, PreparedRun,
+    },
+    webhooks,
+};
+
+use super::{
+    lease::{self, LeaseHeartbeat},
+    max_attempts, JobAcker, JobDelivery, RunEnd, RunEvent, RunJob, RunQueue, UnparseableJob,
+};
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+const INTERRUPTED_ERROR: &str = "Run was interrupted by worker restarts too many times";
+
+/// Executes jobs from the run queue with the local engine
+pub struct RunWorker {
+    /// Id the worker leases runs under, see `lease`
+    worker_id: Uuid,
+    queue: Arc<dyn RunQueue>,
+    pipeline_runner: Arc<PipelineRunner>,
+    db: Arc<DB>,
+    rate_limiter: Arc<ApiKeyRateLimiter>,
+    interrupt_senders: Arc<InterruptSenders>,
+}
+
+impl RunWorker {
+    pub fn new(
+        queue: Arc<dyn RunQueue>,
+        pipeline_runner: Arc<PipelineRunner>,
+        db: Arc<DB>,
+        rate_limiter: Arc<ApiKeyRateLimiter>,
+        interrupt_senders: Arc<InterruptSenders>,
+    ) -> Self {
+        Self {
+            worker_id: Uuid::new_v4(),
+            queue,
+            pipeline_runner,
+            db,
+            rate_limiter,
+            interrupt_senders,
+        }
+    }
+
+    /// Execute jobs, `concurrency` at a time, resubscribing if the connection to the queue is lost
+    pub async fn run(self: Arc<Self>, concurrency: u16) {
+        loop {
+            match self.queue.jobs(concurrency).await {
+                Ok(mut jobs) => {
+                    while let Some(delivery) = jobs.next().await {
+                        tokio::spawn(self.clone().process(delivery));
+                    }
+                    log::warn!("Run queue subscription ended, resubscribing");
+                }
+                Err(e) => log::error!("Failed to subscribe to run queue: {}", e),
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    async fn process(self: Arc<Self>, delivery: JobDelivery) {
+        let JobDelivery {
+            job,
+            redelivered,
+            acker,
+        } = delivery;
+        let job = match job {
+            Ok(job) => job,
+            Err(unparseable) => return self.capture_unparseable(unparseable, acker).await,
+        };
+        let run_id = job.run_id;
+
+        match self.claim(&run_id, redelivered).await {
+            Ok(RunClaim::Claimed { attempt }) => self.execute(job, attempt > 1).await,
+            // Cancelled, finished, or out of attempts
+            Ok(_) => self.interrupt_if_exhausted(&job).await,
+            Err(e) => {
+                log::error!("Failed to claim run {}: {}", run_id, e);
+                if let Err(e) = acker.requeue().await {
+                    log::error!("Failed to requeue run {}: {}", run_id, e);
+                }
+                return;
+            }
+        }
+
+        if let Err(e) = acker.ack().await {
+            log::error!("Failed to acknowledge run {}: {}", run_id, e);
+        }
+    }
+
+    /// Lease the run to this worker, waiting for the lease of another worker executing it to
+    /// expire. Ends with `RunClaim::Claimed` or `RunClaim::Unavailable`.
+    async fn claim(&self, run_id: &Uuid, redelivered: bool) -> anyhow::Result<RunClaim> {
+        let lease_seconds = lease::lease_seconds();
+        loop {
+            let claim = db::runs::claim_queued_run(
+                &self.db.pool,
+                run_id,
+                &self.worker_id,
+                lease_seconds,
+                redelivered,
+                max_attempts(),
+            )
+            .await?;
+            if claim != RunClaim::Leased {
+                return Ok(claim);
+            }
+            tokio::time::sleep(Duration::from_secs(lease_seconds as u64)).await;
+        }
+    }
+
+    /// Keep the job as a dead letter, it's only redelivered if it can't be captured
+    async fn capture_unparseable(&self, unparseable: UnparseableJob, acker: Box<dyn JobAcker>) {
+        log::error!(
+            "Failed to parse run job, capturing it as a dead letter: {}",
+            unparseable.error
+        );
+        if let Err(e) =
+            dead_letters::capture_run_job(&self.db, &unparseable.payload, &unparseable.error).await
+        {
+            log::error!("Failed to capture unparseable run job: {}", e);
+            if let Err(e) = acker.requeue().await {
+                log::error!("Failed to requeue unparseable run job: {}", e);
+            }
+            return;
+        }
+        if let Err(e) = acker.ack().await {
+            log::error!("Failed to acknowledge unparseable run job: {}", e);
+        }
+    }
+
+    /// Execute the claimed run, from its checkpoint if it's `resumed` after another worker
+    /// stopped executing it
+    async fn execute(&self, job: RunJob, resumed: bool) {
+        let run_id = job.run_id;
+        let project_api_key = job.project_api_key.clone();
+        let stream = job.stream;
+        let _heartbeat = LeaseHeartbeat::start(
+            self.db.clone(),
+            run_id,
+            self.worker_id,
+            self.interrupt_senders.clone(),
+        );
+
+        let mut run = match self.prepare(job).await {
+            Ok(run) => run,
+            Err(e) => {
+                // The pipeline version or secrets changed since the run was validated
+                let run_result = Err(e);
+                record_run_result(
+                    &self.db,
+                    self.pipeline_runner.http_client(),
+                    run_id,
+                    project_api_key.project_id,
+                    &run_result,
+                    None,
+                    Some(&self.worker_id),
+                )
+                .await;
+                if let Err(e) = run_result {
+                    self.publish_end(run_id, error_end(run_id, e)).await;
+                }
+                return;
+            }
+        };
+        run.leased_by = Some(self.worker_id);
+        run.checkpoints = self.checkpoints(&run, resumed).await;
+        let pipeline_version_id = run.pipeline_version.id;
+        let pipeline_version_hash = run.pipeline_version.content_hash.clone();
+
+        let (stream_send, relayed) = if stream {
+            let (tx, mut rx) = mpsc::channel::<StreamChunk>(8);
+            let queue = self.queue.clone();
+            let relayed = tokio::spawn(async move {
+                while let Some(chunk) = rx.recv().await {
+                    let chunk = serde_json::to_value(&chunk).unwrap_or_default();
+                    if let Err(e) = queue.publish_event(&run_id, &RunEvent::Chunk(chunk)).await {
+                        log::error!("Failed to relay chunk of run {}: {}", run_id, e);
+                    }
+                }
+            });
+            (Some(tx), Some(relayed))
+        } else {
+            (None, None)
+        };
+
+        let run_result = execute_run(
+            run,
+            stream_send,
+            &self.pipeline_runner,
+            &self.db,
+            &self.rate_limiter,
+            &project_api_key,
+            &self.interrupt_senders,
+        )
+        .await;
+        // Chunks are relayed before the end of the run
+        if let Some(relayed) = relayed {
+            let _ = relayed.await;
+        }
+
+        let end = match run_result {
+            Ok(engine_output) => output_end(GraphRunOutput {
+                outputs: engine_output.graph_outputs(),
+                run_id,
+                pipeline_version_id,
+                pipeline_version_hash,
+            }),
+            Err(e) => error_end(run_id, e),
+        };
+        self.publish_end(run_id, end).await;
+    }
+
+    async fn prepare(&self, job: RunJob) -> Result<PreparedRun, PipelineRunnerError> {
+        let project_id = job.project_api_key.project_id;
+        let env = job.env()?;
+        let pipeline_version =
+            db::pipelines::get_pipeline_version(&self.db.pool, &job.pipeline_version_id).await?;
+        let mut graph = setup_graph(
+            &self.pipeline_runner,
+            &self.db,
+            &pipeline_version,
+            &job.inputs,
+            &env,
+            &job.metadata,
+            &project_id,
+        )
+        .await?;
+        graph.record_node_io = job.record_node_io;
+        let metadata = graph.metadata.clone();
+
+        Ok(PreparedRun {
+            run_id: job.run_id,
+            project_id,
+            pipeline_version,
+            secrets: graph.secrets.clone(),
+            graph,
+            inputs: job.inputs,
+            env,
+            metadata,
+            parent_span_id: job.parent_span_id,
+            trace_id: job.trace_id,
+            replay: None,
+            trace_mode: job.trace_mode,
+            leased_by: None,
+            checkpoints: None,
+        })
+    }
+
+    /// Checkpoints of the run if its graph has loops, taken over from the worker which executed
+    /// it before if it's `resumed`. The run is executed without them if they can't be started.
+    async fn checkpoints(&self, run: &PreparedRun, resumed: bool) -> Option<RunCheckpoints> {
+        // an invalid graph fails once the run is executed
+        if !self.pipeline_runner.has_cycles(&run.graph).unwrap_or(false) {
+            return None;
+        }
+        let store = self.pipeline_runner.checkpoint_store();
+        let graph_hash = get_graph_content_hash(&run.pipeline_version.runnable_graph);
+        let resume_from = if resumed {
+            store.take_over(&run.run_id, &graph_hash).await
+        } else {
+            Ok(None)
+        };
+        let checkpoint = match resume_from {
+            Ok(Some(checkpoint)) => Ok((checkpoint.generation, Some(checkpoint))),
+            Ok(None) => store
+                .start(&run.run_id, &graph_hash)
+                .await
+                .map(|generation| (generation, None)),
+            Err(e) => Err(e),
+        };
+        let (generation, resume_from) = match checkpoint {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                log::error!("Failed to start checkpoints of run {}: {}", run.run_id, e);
+                return None;
+            }
+        };
+        if let Some(checkpoint) = &resume_from {
+            log::info!(
+                "Resuming run {} from {} snapshots of its checkpoint",
+                run.run_id,
+                checkpoint.snapshots.len()
+            );
+        }
+
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(checkpoints::write_checkpoints(
+            store, run.run_id, generation, events_rx,
+        ));
+        Some(RunCheckpoints {
+            events: events_tx,
+            resume_from,
+        })
+    }
+
+    /// Mark the run as interrupted if it was redelivered after its last attempt
+    async fn interrupt_if_exhausted(&self, job: &RunJob) {
+        let run_id = job.run_id;
+        match db::runs::interrupt_run(&self.db.pool, &run_id, max_attempts(), INTERRUPTED_ERROR)
+            .await
+        {
+            Ok(true) => {
+                log::warn!(
+                    "Run {} was interrupted after {} attempts",
+                    run_id,
+                    max_attempts()
+                );
+                tokio::spawn(webhooks::notify_run_finished(
+                    self.db.as_ref().clone(),
+                    self.pipeline_runner.http_client().clone(),
+                    run_id,
+                    job.project_api_key.project_id,
+                ));
+                let error = PipelineRunnerError::UnhandledError(anyhow::anyhow!(INTERRUPTED_ERROR));
+                self.publish_end(run_id, error_end(run_id, error)).await;
+            }
+            Ok(false) => {}
+            Err(e) => log::error!("Failed to interrupt run {}: {}", run_id, e),
+        }
+    }
+
+    async fn publish_end(&self, run_id: Uuid, end: RunEnd) {
+        if let Err(e) = self
+            .queue
+            .publish_event(&run_id, &RunEvent::Finished(end))
+            .await
+        {
+            log::error!("Failed to publish end of run {}: {}", run_id, e);
+        }
+    }
+}
+
+/// Responses to the request of a successful run, the same as if it was executed locally
+fn output_end(output: GraphRunOutput) -> RunEnd {
+    let response = serde_json::to_value(&output).unwrap_or_default();
+    RunEnd {
+        chunk: serde_json::to_value(StreamChunk::GraphRunOutput(output)).unwrap_or_default(),
+        status: 200,
+        response: Some(response),
+    }
+}
+
+fn error_end(run_id: Uuid, error: PipelineRunnerError) -> RunEnd {
+    let chunk_run_id = matches!(error, PipelineRunnerError::RunningError(_)).then_some(run_id);
+    let chunk = StreamChunk::RunEndpointEventError(RunEndpointEventError::new(error, chunk_run_id));
+    let chunk_value = serde_json::to_value(&chunk).unwrap_or_default();
+    let StreamChunk::RunEndpointEventError(RunEndpointEventError { error, .. }) = chunk else {
+        unreachable!()
+    };
+
+    let http_error = pipeline_runner_to_http_error(error, run_id);
+    RunEnd {
+        chunk: chunk_value,
+        status: http_error.status_code().as_u16(),
+        response: http_error.response_body(),
+    }
+}
+
+/// Interrupt runs executed by this instance when they're cancelled on another one
+pub async fn listen_for_cancellations(
+    queue: Arc<dyn RunQueue>,
+    interrupt_senders: Arc<InterruptSenders>,
+) {
+    loop {
+        match queue.cancellations().await {
+            Ok(mut cancellations) => {
+                while let Some(run_id) = cancellations.next().await {
+                    let sender = interrupt_senders.get(&run_id).map(|sender| sender.clone());
+                    if let Some(sender) = sender {
+                        let _ = sender.send(GraphInterruptMessage::Cancel).await;
+                    }
+                }
+                log::warn!("Run cancellations subscription ended, resubscribing");
+            }
+            Err(e) => log::error!("Failed to subscribe to run cancellations: {}", e),
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
@@ -0,0 +1,531 @@
+//! Replay of recorded runs with modified node configs
+//!
+//! A replay re-executes the requested nodes, with config overrides merged into them, and every
+//! node downstream of them. The other nodes aren't executed: inputs which re-executed nodes get
+//! from them are seeded from the run's recorded node I/O, using the values the node received in
+//! the recorded run, and outputs of output nodes which aren't re-executed are the recorded ones.
+//! A replay is a run of its own, with its own trace and node I/O, and `replay_of` set to the
+//! recorded run.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    engine::{engine::TaskInput, task::Task},
+    pipeline::{
+        nodes::{Message, Node, NodeInput},
+        Graph,
+    },
+    secrets::{self, ScrubbedSecret},
+};
+
+use super::node_io::{InputState, NodeIoRecord};
+
+/// Which tasks of the graph are executed by the replay, and what they're seeded with
+#[derive(Debug)]
+pub struct ReplayPlan {
+    /// Run whose recorded node I/O seeds the replay
+    pub replay_of: Uuid,
+    /// Re-executed tasks the replay starts from
+    pub start_task_ids: Vec<Uuid>,
+    /// Inputs of re-executed tasks from tasks which aren't re-executed, by task id and handle name
+    pub inputs: HashMap<Uuid, HashMap<String, TaskInput>>,
+    /// Recorded outputs of output tasks which aren't re-executed
+    pub outputs: Vec<Message>,
+}
+
+/// Nodes which can't be replayed, since the recorded values they need are missing, were dropped
+/// by the size cap, or can't be read back as node inputs
+#[derive(Debug, thiserror::Error)]
+#[error("Recorded inputs are missing for nodes: {}", .node_names.join(", "))]
+pub struct MissingInputsError {
+    pub node_names: Vec<String>,
+}
+
+impl MissingInputsError {
+    fn new(node_names: HashSet<String>) -> Self {
+        let mut node_names = node_names.into_iter().collect::<Vec<_>>();
+        node_names.sort();
+        Self { node_names }
+    }
+}
+
+/// Merge the config overrides into the nodes of the graph, by node name
+///
+/// Objects are merged key by key, other values replace the node's ones.
+pub fn apply_overrides(graph: &mut Graph, overrides: &HashMap<String, Value>) -> Result<()> {
+    for (node_name, config) in overrides {
+        let node = graph
+            .nodes
+            .values_mut()
+            .find(|node| node.name() == *node_name)
+            .ok_or_else(|| anyhow::anyhow!("Node {} is not in the pipeline version", node_name))?;
+
+        let mut value = serde_json::to_value(&*node)?;
+        merge_config(&mut value, config);
+        let overridden = serde_json::from_value::<Node>(value)
+            .map_err(|e| anyhow::anyhow!("Invalid overrides of node {}: {}", node_name, e))?;
+        if overridden.id() != node.id()
+            || overridden.name() != node.name()
+            || overridden.node_type() != node.node_type()
+        {
+            return Err(anyhow::anyhow!(
+                "Overrides can't change the id, name or type of node {}",
+                node_name
+            ));
+        }
+        *node = overridden;
+        // the graph is no longer the version's, so its compiled plan doesn't apply
+        graph.content_hash = None;
+    }
+
+    Ok(())
+}
+
+fn merge_config(value: &mut Value, config: &Value) {
+    match (value, config) {
+        (Value::Object(value), Value::Object(config)) => {
+            for (key, config) in config {
+                match value.get_mut(key) {
+                    Some(value) => merge_config(value, config),
+                    None => {
+                        value.insert(key.clone(), config.clone());
+                    }
+                }
+            }
+        }
+        (value, config) => *value = config.clone(),
+    }
+}
+
+/// Values of the graph inputs from the recorded outputs of the input nodes
+///
+/// Inputs which aren't recorded are only needed if their input node is re-executed, otherwise
+/// the nodes reading them are seeded, and they're set to empty strings.
+pub fn recorded_inputs(
+    graph: &Graph,
+    records: &[NodeIoRecord],
+    node_ids: &HashSet<Uuid>,
+    secrets: &HashMap<String, String>,
+) -> Result<HashMap<String, NodeInput>, MissingInputsError> {
+    let mut inputs = HashMap::new();
+    let mut missing = HashSet::new();
+    for node in graph.nodes.values() {
+        let Node::Input(input_node) = node else {
+            continue;
+        };
+        let recorded = records
+            .iter()
+            .filter(|record| record.node_id == input_node.id)
+            .max_by_key(|record| record.execution)
+            .and_then(|record| {
+                decode(
+                    &record.output.value,
+                    &record.output.scrubbed_secrets,
+                    secrets,
+                )
+            });
+        match recorded {
+            Some(value) => {
+                inputs.insert(input_node.name.clone(), value);
+            }
+            None if node_ids.contains(&input_node.id) => {
+                missing.insert(input_node.name.clone());
+            }
+            None => {
+                inputs.insert(input_node.name.clone(), NodeInput::String(String::new()));
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(MissingInputsError::new(missing));
+    }
+    Ok(inputs)
+}
+
+/// Plan the replay of the recorded run, re-executing the nodes and their descendants
+pub fn plan(
+    replay_of: Uuid,
+    tasks: &HashMap<Uuid, Task>,
+    node_ids: &HashSet<Uuid>,
+    records: &[NodeIoRecord],
+    secrets: &HashMap<String, String>,
+) -> Result<ReplayPlan, MissingInputsError> {
+    let descendants_of = node_ids
+        .iter()
+        .map(|node_id| (*node_id, descendants(tasks, node_id)))
+        .collect::<HashMap<_, _>>();
+    let executed = descendants_of
+        .values()
+        .flatten()
+        .chain(node_ids)
+        .copied()
+        .collect::<HashSet<_>>();
+    // nodes downstream of other requested nodes are started by them, unless they're in a cycle
+    let start_task_ids = node_ids
+        .iter()
+        .filter(|node_id| {
+            !node_ids.iter().any(|other_id| {
+                other_id != *node_id
+                    && descendants_of[other_id].contains(*node_id)
+                    && !descendants_of[*node_id].contains(other_id)
+            })
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
+    let mut first_records = HashMap::<Uuid, &NodeIoRecord>::new();
+    let mut last_records = HashMap::<Uuid, &NodeIoRecord>::new();
+    for record in records {
+        first_records
+            .entry(record.node_id)
+            .and_modify(|first| {
+                if record.execution < first.execution {
+                    *first = record;
+                }
+            })
+            .or_insert(record);
+        last_records
+            .entry(record.node_id)
+            .and_modify(|last| {
+                if record.execution > last.execution {
+                    *last = record;
+                }
+            })
+            .or_insert(record);
+    }
+
+    let mut inputs = HashMap::<Uuid, HashMap<String, TaskInput>>::new();
+    let mut missing = HashSet::new();
+    for task_id in &executed {
+        let task = &tasks[task_id];
+        let is_start = start_task_ids.contains(task_id);
+        for prev_id in &task.prev {
+            if executed.contains(prev_id) && !is_start {
+                continue;
+            }
+            let prev = &tasks[prev_id];
+            let handle_names = prev
+                .routes
+                .iter()
+                .filter(|route| route.node_id() == *task_id)
+                .map(|route| route.name().to_string());
+
+            for handle_name in handle_names {
+                // the input the node received in the recorded run, or the output of its source
+                // if the node wasn't executed then
+                let received = first_records
+                    .get(task_id)
+                    .and_then(|record| record.inputs.get(&handle_name))
+                    .and_then(|input| {
+                        Some(TaskInput {
+                            message: seeded_message(
+                                input.message_id,
+                                decode(&input.value, &input.scrubbed_secrets, secrets)?,
+                                prev,
+                            ),
+                            empty: input.state == InputState::Empty,
+                        })
+                    });
+                let seeded = received.or_else(|| {
+                    let output = &last_records.get(prev_id)?.output;
+                    Some(TaskInput {
+                        message: seeded_message(
+                            output.message_id,
+                            decode(&output.value, &output.scrubbed_secrets, secrets)?,
+                            prev,
+                        ),
+                        empty: false,
+                    })
+                });
+                match seeded {
+                    Some(input) => {
+                        inputs
+                            .entry(*task_id)
+                            .or_default()
+                            .insert(handle_name, input);
+                    }
+                    None => {
+                        missing.insert(task.action.node_name());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut outputs = Vec::new();
+    for (task_id, task) in tasks {
+        if !task.next.is_empty() || executed.contains(task_id) {
+            continue;
+        }
+        // output nodes on branches which weren't taken have no records
+        let Some(record) = last_records.get(task_id) else {
+            continue;
+        };
+        match decode(
+            &record.output.value,
+            &record.output.scrubbed_secrets,
+            secrets,
+        ) {
+            Some(value) => outputs.push(Message {
+                id: record.output.message_id,
+                value,
+                node_id: *task_id,
+                node_name: task.action.node_name(),
+                node_type: task.action.node_type(),
+                ..Message::empty()
+            }),
+            None => {
+                missing.insert(task.action.node_name());
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(MissingInputsError::new(missing));
+    }
+    Ok(ReplayPlan {
+        replay_of,
+        start_task_ids,
+        inputs,
+        outputs,
+    })
+}
+
+/// Tasks reachable from the task, the task itself only if it's in a cycle
+fn descendants(tasks: &HashMap<Uuid, Task>, task_id: &Uuid) -> HashSet<Uuid> {
+    let mut descendants = HashSet::new();
+    let mut queue = VecDeque::from_iter(tasks[task_id].next.iter().copied());
+    while let Some(next_id) = queue.pop_front() {
+        if descendants.insert(next_id) {
+            queue.extend(tasks[&next_id].next.iter().copied());
+        }
+    }
+    descendants
+}
+
+/// Message of the task which isn't re-executed, with the recorded value it produced
+fn seeded_message(id: Uuid, value: NodeInput, task: &Task) -> Arc<Message> {
+    Arc::new(Message {
+        id,
+        value,
+        node_id: task.id,
+        node_name: task.action.node_name(),
+        node_type: task.action.node_type(),
+        ..Message::empty()
+    })
+}
+
+/// Recorded value as a node input, with secrets which the recorder scrubbed from it restored
+///
+/// None if it was dropped by the size cap, or it's internal to the engine and can't be
+/// deserialized, e.g. outputs of condition nodes.
+fn decode(
+    value: &Option<Value>,
+    scrubbed_secrets: &[ScrubbedSecret],
+    secrets: &HashMap<String, String>,
+) -> Option<NodeInput> {
+    let mut value = value.clone()?;
+    secrets::restore_scrubbed_secrets(&mut value, scrubbed_secrets, secrets);
+    serde_json::from_value(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::{
+        pipeline::utils::parse_graph,
+        runs::node_io::{RecordedInput, RecordedOutput},
+    };
+
+    use super::*;
+
+    struct Ids {
+        question: Uuid,
+        template: Uuid,
+        answer: Uuid,
+    }
+
+    /// question -> template -> answer
+    fn graph() -> (Graph, Ids) {
+        let ids = Ids {
+            question: Uuid::new_v4(),
+            template: Uuid::new_v4(),
+            answer: Uuid::new_v4(),
+        };
+        let (question_output, template_input, template_output, answer_input) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let graph = serde_json::json!({
+            "nodes": {
+                "question": {
+                    "type": "Input",
+                    "id": ids.question,
+                    "name": "question",
+                    "outputs": [{"id": question_output, "name": "output", "type": "String"}],
+                    "inputType": "String",
+                },
+                "template": {
+                    "type": "StringTemplate",
+                    "id": ids.template,
+                    "name": "template",
+                    "inputs": [{"id": template_input, "name": "question", "type": "String"}],
+                    "outputs": [{"id": template_output, "name": "output", "type": "String"}],
+                    "inputsMappings": {template_input.to_string(): question_output},
+                    "text": "{{question}}?",
+                },
+                "answer": {
+                    "type": "Output",
+                    "id": ids.answer,
+                    "name": "answer",
+                    "inputs": [{"id": answer_input, "name": "output", "type": "String"}],
+                    "inputsMappings": {answer_input.to_string(): template_output},
+                },
+            },
+            "pred": {
+                ids.template.to_string(): [ids.question],
+                ids.answer.to_string(): [ids.template],
+            },
+        });
+        (serde_json::from_value(graph).unwrap(), ids)
+    }
+
+    fn record(
+        node_id: Uuid,
+        inputs: &[(&str, Option<&str>)],
+        output: Option<&str>,
+    ) -> NodeIoRecord {
+        NodeIoRecord {
+            run_id: Uuid::new_v4(),
+            node_id,
+            node_name: String::new(),
+            node_type: String::new(),
+            execution: 0,
+            inputs: inputs
+                .iter()
+                .map(|(handle_name, value)| {
+                    let input = RecordedInput {
+                        state: InputState::Success,
+                        message_id: Uuid::new_v4(),
+                        value: value.map(Value::from),
+                        scrubbed_secrets: Vec::new(),
+                    };
+                    (handle_name.to_string(), input)
+                })
+                .collect(),
+            output: RecordedOutput {
+                message_id: Uuid::new_v4(),
+                value: output.map(Value::from),
+                scrubbed_secrets: Vec::new(),
+                meta_log: None,
+                start_time: Utc::now(),
+                end_time: Utc::now(),
+            },
+        }
+    }
+
+    fn records(ids: &Ids, template_input: Option<&str>) -> Vec<NodeIoRecord> {
+        vec![
+            record(ids.question, &[], Some("why")),
+            record(ids.template, &[("question", template_input)], Some("why?")),
+            record(ids.answer, &[("output", Some("why?"))], Some("why?")),
+        ]
+    }
+
+    #[test]
+    fn test_plan_seeds_inputs_of_re_executed_nodes() {
+        let (graph, ids) = graph();
+        let tasks = parse_graph(graph).unwrap();
+        let replay_of = Uuid::new_v4();
+        let secrets = HashMap::from([("KEY".to_string(), "sk-123".to_string())]);
+        let mut records = records(&ids, Some("key is sk-123, not {{secret:KEY}}"));
+        let input = records[1].inputs.get_mut("question").unwrap();
+        input.scrubbed_secrets =
+            secrets::scrub_marked_secrets(input.value.as_mut().unwrap(), &secrets);
+
+        let plan = plan(
+            replay_of,
+            &tasks,
+            &HashSet::from([ids.template]),
+            &records,
+            &secrets,
+        )
+        .unwrap();
+        assert_eq!(plan.replay_of, replay_of);
+        assert_eq!(plan.start_task_ids, vec![ids.template]);
+        // the answer node is re-executed after the template, only the template is seeded
+        assert_eq!(plan.inputs.len(), 1);
+        let input = &plan.inputs[&ids.template]["question"];
+        assert_eq!(input.message.node_id, ids.question);
+        // references which the recorded value contained aren't resolved
+        assert_eq!(
+            input.message.value,
+            NodeInput::String("key is sk-123, not {{secret:KEY}}".to_string())
+        );
+        assert!(plan.outputs.is_empty());
+    }
+
+    #[test]
+    fn test_plan_refuses_missing_inputs() {
+        let (graph, ids) = graph();
+        let tasks = parse_graph(graph).unwrap();
+        let mut records = records(&ids, None);
+        records[0].output.value = None;
+
+        let err = plan(
+            Uuid::new_v4(),
+            &tasks,
+            &HashSet::from([ids.template]),
+            &records,
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert_eq!(err.node_names, vec!["template".to_string()]);
+
+        // falls back to the output of the source if the node's own input is missing
+        records[0].output.value = Some(Value::from("why"));
+        let plan = plan(
+            Uuid::new_v4(),
+            &tasks,
+            &HashSet::from([ids.template]),
+            &records,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            plan.inputs[&ids.template]["question"].message.value,
+            NodeInput::String("why".to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let (mut graph, _) = graph();
+        let overrides = HashMap::from([(
+            "template".to_string(),
+            serde_json::json!({"text": "{{question}}!"}),
+        )]);
+        apply_overrides(&mut graph, &overrides).unwrap();
+        let Node::StringTemplate(template) = &graph.nodes["template"] else {
+            panic!("template is not a string template node");
+        };
+        assert_eq!(template.text, "{{question}}!");
+
+        let overrides = HashMap::from([(
+            "template".to_string(),
+            serde_json::json!({"type": "Output"}),
+        )]);
+        assert!(apply_overrides(&mut graph, &overrides).is_err());
+    }
+}
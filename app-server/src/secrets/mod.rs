@@ -0,0 +1,411 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::Result;
+use regex::{Captures, Regex};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::db;
+
+const ENCRYPTION_KEY_ENV_VAR: &str = "SECRETS_ENCRYPTION_KEY";
+
+lazy_static::lazy_static! {
+    static ref REFERENCE_REGEX: Regex =
+        Regex::new(r"\{\{\s*(secret|env):([A-Za-z0-9_\-\.]+)\s*\}\}").unwrap();
+    static ref NAME_REGEX: Regex = Regex::new(r"^[A-Za-z0-9_\-\.]+$").unwrap();
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    NAME_REGEX.is_match(name)
+}
+
+/// Reference to a value, which is resolved only when a node is executed
+///
+/// Graphs store `{{secret:NAME}}` and `{{env:NAME}}` references instead of the values,
+/// so exported graphs and validation errors never contain them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Reference {
+    Secret(String),
+    Env(String),
+}
+
+pub fn get_references(value: &str) -> HashSet<Reference> {
+    REFERENCE_REGEX
+        .captures_iter(value)
+        .map(|captures| match &captures[1] {
+            "secret" => Reference::Secret(captures[2].to_string()),
+            _ => Reference::Env(captures[2].to_string()),
+        })
+        .collect()
+}
+
+/// Collect references from all strings in a JSON value, e.g. serialized node config
+pub fn get_json_references(value: &Value) -> HashSet<Reference> {
+    match value {
+        Value::String(s) => get_references(s),
+        Value::Array(values) => values.iter().flat_map(get_json_references).collect(),
+        Value::Object(map) => map.values().flat_map(get_json_references).collect(),
+        _ => HashSet::new(),
+    }
+}
+
+pub fn resolve_references(
+    value: &str,
+    secrets: &HashMap<String, String>,
+    env: &HashMap<String, String>,
+) -> Result<String> {
+    let mut unresolved = None;
+    let resolved = REFERENCE_REGEX.replace_all(value, |captures: &Captures| {
+        let values = match &captures[1] {
+            "secret" => secrets,
+            _ => env,
+        };
+        match values.get(&captures[2]) {
+            Some(value) => value.clone(),
+            None => {
+                unresolved = Some(captures[0].to_string());
+                String::new()
+            }
+        }
+    });
+
+    match unresolved {
+        Some(reference) => Err(anyhow::anyhow!("Unresolved reference {}", reference)),
+        None => Ok(resolved.into_owned()),
+    }
+}
+
+/// Run env with its secret references resolved, which providers are called with. Values are
+/// zeroed once they're dropped, as they include the secrets and credentials of the run.
+#[derive(Clone, Default)]
+pub struct ResolvedEnv(HashMap<String, Zeroizing<String>>);
+
+impl ResolvedEnv {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|value| value.as_str())
+    }
+
+    pub fn insert(&mut self, name: &str, value: Zeroizing<String>) {
+        self.0.insert(name.to_string(), value);
+    }
+}
+
+impl From<HashMap<String, String>> for ResolvedEnv {
+    fn from(env: HashMap<String, String>) -> Self {
+        Self(
+            env.into_iter()
+                .map(|(name, value)| (name, Zeroizing::new(value)))
+                .collect(),
+        )
+    }
+}
+
+/// Lists the names only, so that values never end up in logs
+impl fmt::Debug for ResolvedEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Resolve secret references in run env, e.g. `OPENAI_API_KEY: {{secret:OPENAI_API_KEY}}`
+pub fn resolve_env(
+    env: &HashMap<String, String>,
+    secrets: &HashMap<String, String>,
+) -> Result<ResolvedEnv> {
+    env.iter()
+        .map(|(key, value)| {
+            let value = Zeroizing::new(resolve_references(value, secrets, env)?);
+            Ok((key.clone(), value))
+        })
+        .collect::<Result<_>>()
+        .map(ResolvedEnv)
+}
+
+/// Replace secret values in all strings of a JSON value with references to them
+pub fn scrub_secrets(value: &mut Value, secrets: &HashMap<String, String>) {
+    match value {
+        Value::String(s) => {
+            for (name, secret) in secrets {
+                if !secret.is_empty() && s.contains(secret.as_str()) {
+                    *s = s.replace(secret.as_str(), &format!("{{{{secret:{}}}}}", name));
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| scrub_secrets(value, secrets)),
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|value| scrub_secrets(value, secrets)),
+        _ => {}
+    }
+}
+
+/// Reference which `scrub_marked_secrets` put in place of the value of a secret
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrubbedSecret {
+    /// JSON pointer of the string the secret was scrubbed from
+    pub pointer: String,
+    /// Byte offset of the reference in the scrubbed string
+    pub offset: usize,
+    pub name: String,
+}
+
+/// Replace secret values in all strings of a JSON value with references to them, like
+/// `scrub_secrets`, and return where the references were put. Only these are restored by
+/// `restore_scrubbed_secrets`, references which the value contained already are kept as they are.
+pub fn scrub_marked_secrets(
+    value: &mut Value,
+    secrets: &HashMap<String, String>,
+) -> Vec<ScrubbedSecret> {
+    // longest first, so that a secret containing another one is scrubbed whole
+    let mut secrets = secrets
+        .iter()
+        .filter(|(_, secret)| !secret.is_empty())
+        .collect::<Vec<_>>();
+    secrets.sort_by_key(|(_, secret)| std::cmp::Reverse(secret.len()));
+    let mut scrubbed = Vec::new();
+    scrub_marked_value(value, String::new(), &secrets, &mut scrubbed);
+    scrubbed
+}
+
+fn scrub_marked_value(
+    value: &mut Value,
+    pointer: String,
+    secrets: &[(&String, &String)],
+    scrubbed: &mut Vec<ScrubbedSecret>,
+) {
+    match value {
+        Value::String(s) => {
+            if !secrets
+                .iter()
+                .any(|(_, secret)| s.contains(secret.as_str()))
+            {
+                return;
+            }
+            let mut output = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(c) = rest.chars().next() {
+                match secrets
+                    .iter()
+                    .find(|(_, secret)| rest.starts_with(secret.as_str()))
+                {
+                    Some((name, secret)) => {
+                        scrubbed.push(ScrubbedSecret {
+                            pointer: pointer.clone(),
+                            offset: output.len(),
+                            name: name.to_string(),
+                        });
+                        output.push_str(&format!("{{{{secret:{}}}}}", name));
+                        rest = &rest[secret.len()..];
+                    }
+                    None => {
+                        output.push(c);
+                        rest = &rest[c.len_utf8()..];
+                    }
+                }
+            }
+            *s = output;
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter_mut().enumerate() {
+                scrub_marked_value(value, format!("{}/{}", pointer, index), secrets, scrubbed);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                scrub_marked_value(value, format!("{}/{}", pointer, key), secrets, scrubbed);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Put the values of known secrets back in place of the references `scrub_marked_secrets`
+/// put in the value. Other references are kept, so that a value can't read a secret by
+/// containing a reference to it.
+pub fn restore_scrubbed_secrets(
+    value: &mut Value,
+    scrubbed: &[ScrubbedSecret],
+    secrets: &HashMap<String, String>,
+) {
+    // from the end, so that the offsets of the preceding references stay valid
+    for scrubbed_secret in scrubbed.iter().rev() {
+        let (Some(Value::String(s)), Some(secret)) = (
+            value.pointer_mut(&scrubbed_secret.pointer),
+            secrets.get(&scrubbed_secret.name),
+        ) else {
+            continue;
+        };
+        let reference = format!("{{{{secret:{}}}}}", scrubbed_secret.name);
+        let offset = scrubbed_secret.offset;
+        if s.get(offset..)
+            .is_some_and(|rest| rest.starts_with(&reference))
+        {
+            s.replace_range(offset..offset + reference.len(), secret);
+        }
+    }
+}
+
+fn encryption_key() -> Result<LessSafeKey> {
+    let key = std::env::var(ENCRYPTION_KEY_ENV_VAR)
+        .map_err(|_| anyhow::anyhow!("{} must be set", ENCRYPTION_KEY_ENV_VAR))?;
+    let key = hex::decode(key.trim())?;
+    let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| {
+        anyhow::anyhow!(
+            "{} must be a hex encoded 32 bytes key",
+            ENCRYPTION_KEY_ENV_VAR
+        )
+    })?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypt the secret value, returns hex encoded (nonce, ciphertext)
+///
+/// The secret name is used as associated data, so the ciphertext can't be moved to another name.
+pub fn encrypt(name: &str, value: &str) -> Result<(String, String)> {
+    let key = encryption_key()?;
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow::anyhow!("Failed to generate nonce"))?;
+
+    let mut in_out = value.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(name.as_bytes()),
+        &mut in_out,
+    )
+    .map_err(|_| anyhow::anyhow!("Failed to encrypt secret {}", name))?;
+
+    Ok((hex::encode(nonce), hex::encode(in_out)))
+}
+
+pub fn decrypt(name: &str, nonce: &str, value: &str) -> Result<String> {
+    let key = encryption_key()?;
+    let nonce: [u8; NONCE_LEN] = hex::decode(nonce)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid nonce for secret {}", name))?;
+
+    let mut in_out = hex::decode(value)?;
+    let plaintext = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(name.as_bytes()),
+            &mut in_out,
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt secret {}", name))?;
+
+    Ok(String::from_utf8(plaintext.to_vec())?)
+}
+
+/// Get decrypted secrets of the project by name
+pub async fn get_project_secrets(
+    pool: &PgPool,
+    project_id: &Uuid,
+) -> Result<HashMap<String, String>> {
+    let encrypted_secrets = db::secrets::get_encrypted_secrets(pool, project_id).await?;
+    encrypted_secrets
+        .into_iter()
+        .map(|secret| {
+            let value = decrypt(&secret.name, &secret.nonce, &secret.value)?;
+            Ok((secret.name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_references() {
+        let secrets = HashMap::from([("OPENAI_API_KEY".to_string(), "sk-123".to_string())]);
+        let env = HashMap::from([("REGION".to_string(), "eu".to_string())]);
+
+        let resolved = resolve_references(
+            "Bearer {{secret:OPENAI_API_KEY}} {{ env:REGION }}",
+            &secrets,
+            &env,
+        )
+        .unwrap();
+        assert_eq!(resolved, "Bearer sk-123 eu");
+    }
+
+    #[test]
+    fn test_resolve_missing_reference() {
+        let res = resolve_references("{{secret:MISSING}}", &HashMap::new(), &HashMap::new());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_get_json_references() {
+        let value = serde_json::json!({
+            "url": "https://example.com?key={{env:KEY}}",
+            "headers": ["Authorization: {{secret:TOKEN}}"],
+        });
+
+        let references = get_json_references(&value);
+        assert_eq!(
+            references,
+            HashSet::from([
+                Reference::Env("KEY".to_string()),
+                Reference::Secret("TOKEN".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_scrub_secrets() {
+        let secrets = HashMap::from([("TOKEN".to_string(), "abc".to_string())]);
+        let mut value = serde_json::json!({"output": ["token is abc"]});
+
+        scrub_secrets(&mut value, &secrets);
+        assert_eq!(
+            value,
+            serde_json::json!({"output": ["token is {{secret:TOKEN}}"]})
+        );
+    }
+
+    #[test]
+    fn test_restore_only_scrubbed_secrets() {
+        let secrets = HashMap::from([
+            ("TOKEN".to_string(), "abc".to_string()),
+            ("LONG_TOKEN".to_string(), "abcdef".to_string()),
+        ]);
+        let original = serde_json::json!({
+            "a/b": ["token is abc, not {{secret:TOKEN}}", "abcdef"],
+            "leak": "{{secret:LONG_TOKEN}}",
+        });
+        let mut value = original.clone();
+
+        let scrubbed = scrub_marked_secrets(&mut value, &secrets);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "a/b": [
+                    "token is {{secret:TOKEN}}, not {{secret:TOKEN}}",
+                    "{{secret:LONG_TOKEN}}"
+                ],
+                "leak": "{{secret:LONG_TOKEN}}",
+            })
+        );
+        assert_eq!(scrubbed.len(), 2);
+
+        restore_scrubbed_secrets(&mut value, &scrubbed, &secrets);
+        assert_eq!(value, original);
+    }
+}
@@ -0,0 +1,166 @@
+//! Configs of semantic search indexes, and the collections they're stored in
+//!
+//! An index is named by its project, and lives in a collection of the semantic search service.
+//! Changing the embedding model or chunking of an index makes its embeddings incompatible, so it
+//! is rebuilt by a reindex job in a shadow collection, see `reindex`. Queries hit the live
+//! collection, while writes go to both while the job is unfinished.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::chunk::{
+    character_split::{CharacterSplitChunker, CharacterSplitParams},
+    runner::{Chunk, ChunkParams},
+};
+
+use super::semantic_search_grpc::{index_request::Datapoint, Model};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum EmbeddingModel {
+    GteBase,
+    #[default]
+    CohereMultilingual,
+}
+
+impl EmbeddingModel {
+    pub fn model(&self) -> Model {
+        match self {
+            EmbeddingModel::GteBase => Model::GteBase,
+            EmbeddingModel::CohereMultilingual => Model::CohereMultilingual,
+        }
+    }
+}
+
+/// Character split of the indexed content, each chunk is embedded separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkConfig {
+    pub chunk_size: u32,
+    pub stride: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexConfig {
+    #[serde(default)]
+    pub model: EmbeddingModel,
+    /// Content is embedded whole if not set
+    #[serde(default)]
+    pub chunking: Option<ChunkConfig>,
+}
+
+impl IndexConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(chunking) = &self.chunking {
+            if chunking.chunk_size == 0 || chunking.stride == 0 {
+                return Err(anyhow::anyhow!("Chunk size and stride must be positive"));
+            }
+            if chunking.stride > chunking.chunk_size {
+                return Err(anyhow::anyhow!(
+                    "Stride must not exceed the chunk size, or content is skipped"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collection of the semantic search service an index is stored in, with its config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexCollection {
+    pub collection_name: String,
+    pub config: IndexConfig,
+}
+
+impl IndexCollection {
+    /// Collection of indexes which were never reindexed, named by the index
+    pub fn default_of(index_name: &str) -> Self {
+        Self {
+            collection_name: index_name.to_string(),
+            config: IndexConfig::default(),
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.config.model.model()
+    }
+
+    /// Split the content of the datapoints into chunks, if the config has chunking. Chunks keep
+    /// the id and data of their datapoint, with the chunk as `chunk` in data.
+    pub fn chunk(&self, datapoints: Vec<Datapoint>) -> anyhow::Result<Vec<Datapoint>> {
+        let Some(chunking) = self.config.chunking else {
+            return Ok(datapoints);
+        };
+        let params = ChunkParams::CharacterSplit(CharacterSplitParams {
+            chunk_size: chunking.chunk_size,
+            stride: chunking.stride,
+        });
+        let chunker = CharacterSplitChunker {};
+
+        let mut chunks = Vec::with_capacity(datapoints.len());
+        for datapoint in datapoints {
+            for chunk in chunker.chunk(&datapoint.content, &params)? {
+                let mut data = datapoint.data.clone();
+                data.insert("chunk".to_string(), chunk.clone());
+                chunks.push(Datapoint {
+                    content: chunk,
+                    data,
+                    ..datapoint.clone()
+                });
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_chunking_keeps_datapoint_ids() {
+        let datapoint = Datapoint {
+            content: "abcdefgh".to_string(),
+            datasource_id: "dataset".to_string(),
+            data: HashMap::from([("text".to_string(), "abcdefgh".to_string())]),
+            id: "datapoint".to_string(),
+        };
+        let unchunked = IndexCollection::default_of("project");
+        assert_eq!(unchunked.chunk(vec![datapoint.clone()]).unwrap().len(), 1);
+
+        let chunked = IndexCollection {
+            collection_name: "shadow".to_string(),
+            config: IndexConfig {
+                model: EmbeddingModel::GteBase,
+                chunking: Some(ChunkConfig {
+                    chunk_size: 4,
+                    stride: 4,
+                }),
+            },
+        };
+        let chunks = chunked.chunk(vec![datapoint]).unwrap();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| c.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["abcd", "efgh"]
+        );
+        assert!(chunks.iter().all(|chunk| chunk.id == "datapoint"));
+        assert_eq!(chunks[1].data["chunk"], "efgh");
+        assert_eq!(chunks[1].data["text"], "abcdefgh");
+
+        let skipping = IndexConfig {
+            chunking: Some(ChunkConfig {
+                chunk_size: 2,
+                stride: 3,
+            }),
+            ..Default::default()
+        };
+        assert!(skipping.validate().is_err());
+    }
+}
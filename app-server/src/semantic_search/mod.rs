@@ -9,7 +9,10 @@ use semantic_search_grpc::{
     index_request::Datapoint, IndexRequest, IndexResponse, QueryRequest, QueryResponse,
 };
 
-use crate::language_model::{ChatMessage, ChatMessageContent, ChatMessageContentPart};
+use crate::{
+    db::{self, DB},
+    language_model::{ChatMessage, ChatMessageContent, ChatMessageContentPart},
+};
 
 use self::semantic_search_grpc::{
     calculate_similarity_scores_request::ComparedContents, CalculateSimilarityScoresRequest,
@@ -18,17 +21,26 @@ use self::semantic_search_grpc::{
     DeleteEmbeddingsResponse, Model, RequestPayload,
 };
 
+pub mod index;
+pub mod reindex;
 pub mod semantic_search_grpc;
 pub mod utils;
 
+use index::IndexCollection;
+
+/// Client of the semantic search service
+///
+/// Collection names of the methods are names of indexes, which are stored in the collection and
+/// with the embedding model of their config, see `index`.
 #[derive(Clone, Debug)]
 pub struct SemanticSearch {
     client: Arc<SemanticSearchClient<Channel>>,
+    db: Arc<DB>,
 }
 
 impl SemanticSearch {
-    pub fn new(client: Arc<SemanticSearchClient<Channel>>) -> Self {
-        Self { client }
+    pub fn new(client: Arc<SemanticSearchClient<Channel>>, db: Arc<DB>) -> Self {
+        Self { client, db }
     }
 
     pub async fn query(
@@ -40,6 +52,8 @@ impl SemanticSearch {
         payloads: Vec<HashMap<String, String>>,
     ) -> Result<QueryResponse> {
         let mut client = self.client.as_ref().clone();
+        let collection =
+            db::semantic_indexes::get_live_collection(&self.db.pool, collection_name).await?;
 
         let req_payloads = payloads
             .into_iter()
@@ -49,8 +63,8 @@ impl SemanticSearch {
             query,
             limit,
             threshold,
-            collection_name: collection_name.to_string(),
-            model: Model::CohereMultilingual.into(),
+            model: collection.model().into(),
+            collection_name: collection.collection_name,
             payloads: req_payloads,
         });
         let response = client.query(request).await?;
@@ -62,6 +76,23 @@ impl SemanticSearch {
         &self,
         collection_name: &str,
         payloads: Vec<HashMap<String, String>>,
+    ) -> Result<DeleteEmbeddingsResponse> {
+        let collections =
+            db::semantic_indexes::get_write_collections(&self.db.pool, collection_name).await?;
+        let mut response = DeleteEmbeddingsResponse::default();
+        for collection in collections {
+            response = self
+                .delete_collection_embeddings(&collection, payloads.clone())
+                .await?;
+        }
+
+        Ok(response)
+    }
+
+    pub async fn delete_collection_embeddings(
+        &self,
+        collection: &IndexCollection,
+        payloads: Vec<HashMap<String, String>>,
     ) -> Result<DeleteEmbeddingsResponse> {
         let mut client = self.client.as_ref().clone();
 
@@ -70,8 +101,8 @@ impl SemanticSearch {
             .map(|payload| RequestPayload { payload })
             .collect();
         let request = Request::new(DeleteEmbeddingsRequest {
-            collection_name: collection_name.to_string(),
-            model: Model::CohereMultilingual.into(),
+            collection_name: collection.collection_name.clone(),
+            model: collection.model().into(),
             payloads: req_payloads,
         });
         let response = client.delete_embeddings(request).await?;
@@ -83,12 +114,30 @@ impl SemanticSearch {
         &self,
         datapoints: Vec<Datapoint>,
         collection_name: String,
+    ) -> Result<IndexResponse> {
+        let collections =
+            db::semantic_indexes::get_write_collections(&self.db.pool, &collection_name).await?;
+        let mut response = IndexResponse::default();
+        for collection in collections {
+            response = self
+                .index_collection(&collection, datapoints.clone())
+                .await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Index the datapoints in the collection, chunked by its config
+    pub async fn index_collection(
+        &self,
+        collection: &IndexCollection,
+        datapoints: Vec<Datapoint>,
     ) -> Result<IndexResponse> {
         let mut client = self.client.as_ref().clone();
         let request = Request::new(IndexRequest {
-            datapoints,
-            model: Model::CohereMultilingual.into(),
-            collection_name,
+            datapoints: collection.chunk(datapoints)?,
+            model: collection.model().into(),
+            collection_name: collection.collection_name.clone(),
         });
         let response = client.index(request).await?;
 
@@ -98,11 +147,20 @@ impl SemanticSearch {
     pub async fn create_collection(
         &self,
         collection_name: String,
+    ) -> Result<CreateCollectionResponse> {
+        let collection =
+            db::semantic_indexes::get_live_collection(&self.db.pool, &collection_name).await?;
+        self.create_index_collection(&collection).await
+    }
+
+    pub async fn create_index_collection(
+        &self,
+        collection: &IndexCollection,
     ) -> Result<CreateCollectionResponse> {
         let mut client = self.client.as_ref().clone();
         let request = Request::new(CreateCollectionRequest {
-            collection_name,
-            model: Model::CohereMultilingual.into(),
+            collection_name: collection.collection_name.clone(),
+            model: collection.model().into(),
         });
 
         let response = client.create_collection(request).await?;
@@ -110,9 +168,26 @@ impl SemanticSearch {
         Ok(response.into_inner())
     }
 
+    /// Delete the index, with the collections of its reindex jobs
     pub async fn delete_collections(
         &self,
         collection_name: String,
+    ) -> Result<DeleteCollectionsResponse> {
+        let collections =
+            db::semantic_indexes::get_all_collections(&self.db.pool, &collection_name).await?;
+        let mut response = DeleteCollectionsResponse::default();
+        for collection_name in collections {
+            response = self.delete_index_collection(collection_name).await?;
+        }
+        db::semantic_indexes::delete_index(&self.db.pool, &collection_name).await?;
+
+        Ok(response)
+    }
+
+    /// Delete a collection of the semantic search service, with embeddings of all models
+    pub async fn delete_index_collection(
+        &self,
+        collection_name: String,
     ) -> Result<DeleteCollectionsResponse> {
         let mut client = self.client.as_ref().clone();
         let request = Request::new(DeleteCollectionsRequest { collection_name });
@@ -0,0 +1,149 @@
+//! Reindex jobs, rebuilding the index of a project with a new config
+//!
+//! A job reindexes the datapoints of the project's indexed datasets into the job's shadow
+//! collection in batches, in order of dataset and datapoint ids. Queries keep hitting the live
+//! collection, while datapoints written during the job are also indexed in the shadow one.
+//! Once all datapoints are reindexed, the shadow collection is swapped in as the live one and the
+//! replaced collection is deleted.
+//!
+//! The job records the last datapoint of each reindexed batch. If a batch fails, the job fails
+//! and the live collection is untouched, and a resumed job continues after the last batch.
+//! Batches delete embeddings of their datapoints before indexing them, so reindexing a batch
+//! again doesn't duplicate them.
+
+use std::{collections::HashMap, env, sync::Arc};
+
+use anyhow::Result;
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::db::{self, semantic_indexes::ReindexJob, DB};
+
+use super::{index::IndexCollection, SemanticSearch};
+
+const DEFAULT_BATCH_SIZE: i64 = 100;
+/// Running jobs which weren't updated for this long can be resumed, e.g. their instance stopped
+pub const STALE_JOB_SECONDS: f64 = 300.0;
+
+fn batch_size() -> i64 {
+    env::var("REINDEX_BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+/// Shadow collection of a job, unique per job so that a superseded job's collection can be
+/// deleted without touching the live one
+pub fn shadow_collection_name(index_name: &str, job_id: &Uuid) -> String {
+    format!("{}-{}", index_name, job_id)
+}
+
+/// Run the job to completion or failure, recording the failure on the job
+pub async fn run_reindex_job(db: Arc<DB>, semantic_search: Arc<SemanticSearch>, job: ReindexJob) {
+    let job_id = job.id;
+    match reindex(&db, &semantic_search, job).await {
+        Ok(()) => info!("Reindex job {} finished", job_id),
+        Err(e) => {
+            error!("Reindex job {} failed: {}", job_id, e);
+            if let Err(e) =
+                db::semantic_indexes::fail_reindex_job(&db.pool, &job_id, &e.to_string()).await
+            {
+                error!("Failed to record failure of reindex job {}: {}", job_id, e);
+            }
+        }
+    }
+}
+
+async fn reindex(db: &DB, semantic_search: &SemanticSearch, job: ReindexJob) -> Result<()> {
+    let index_name = job.project_id.to_string();
+    let shadow = IndexCollection {
+        collection_name: job.collection_name.clone(),
+        config: job.config,
+    };
+
+    let batch_size = batch_size();
+    let datasets = db::semantic_indexes::get_indexed_datasets(
+        &db.pool,
+        &job.project_id,
+        job.cursor_dataset_id,
+    )
+    .await?;
+    for (dataset_id, index_column) in datasets {
+        // the cursor's datapoint is only in the dataset the job stopped at
+        let mut after_id = match job.cursor_dataset_id {
+            Some(cursor_dataset_id) if cursor_dataset_id == dataset_id => job.cursor_datapoint_id,
+            _ => None,
+        };
+        loop {
+            let datapoints = db::semantic_indexes::get_datapoints_after(
+                &db.pool,
+                &dataset_id,
+                after_id,
+                batch_size,
+            )
+            .await?;
+            let Some(last) = datapoints.last() else {
+                break;
+            };
+            let last_id = last.id;
+            let count = datapoints.len() as i64;
+
+            semantic_search
+                .delete_collection_embeddings(
+                    &shadow,
+                    datapoints
+                        .iter()
+                        .map(|datapoint| {
+                            HashMap::from([("id".to_string(), datapoint.id.to_string())])
+                        })
+                        .collect(),
+                )
+                .await?;
+            let vector_db_datapoints = datapoints
+                .iter()
+                .filter(|datapoint| datapoint.is_indexable(&index_column))
+                .map(|datapoint| datapoint.into_vector_db_datapoint(&index_column))
+                .collect::<Vec<_>>();
+            if !vector_db_datapoints.is_empty() {
+                semantic_search
+                    .index_collection(&shadow, vector_db_datapoints)
+                    .await?;
+            }
+
+            let running = db::semantic_indexes::advance_reindex_job(
+                &db.pool,
+                &job.id,
+                &dataset_id,
+                &last_id,
+                count,
+            )
+            .await?;
+            if !running {
+                info!("Reindex job {} was stopped", job.id);
+                return Ok(());
+            }
+            if count < batch_size {
+                break;
+            }
+            after_id = Some(last_id);
+        }
+    }
+
+    let Some(replaced) =
+        db::semantic_indexes::swap_live_collection(&db.pool, &job, &index_name).await?
+    else {
+        return Ok(());
+    };
+    if replaced != shadow.collection_name {
+        // searches in flight may still use the replaced collection, they fail the same way as
+        // searches of a deleted dataset
+        if let Err(e) = semantic_search.delete_index_collection(replaced).await {
+            error!(
+                "Failed to delete collection replaced by reindex job {}: {}",
+                job.id, e
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,780 @@
+//! Support for benchmarks and tests of the engine and of nodes
+//!
+//! Graphs of [`NoopNode`]s exercise the scheduler, i.e. input assembly, task spawning and
+//! completion of input states, without any node doing work, and [`OfflineServices`] backs their
+//! runs without model providers, databases or queues. LLM nodes are run by a
+//! [`MockLlmProvider`], if the services are created with one.
+//!
+//! Single nodes, built-in or custom, are tested without an engine: [`InputBuilder`] builds their
+//! input, [`run_node`] runs them and the assertions check their output state:
+//!
+//! ```ignore
+//! let input = InputBuilder::new().json("response", json!({"answer": 42})).build();
+//! let state = run_node(&node, input).await.unwrap();
+//! assert_json_output(&state, json!(42));
+//! ```
+//!
+//! Built with the `testing` feature.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use uuid::Uuid;
+
+use crate::{
+    ch::{exporter::SpanSink, spans::CHSpan},
+    chunk::runner::ChunkerRunner,
+    clock::Clock,
+    db::DB,
+    engine::{
+        task::{Action, InputHandle, TaskHandles},
+        Input, NodeError, NodeImpl, RunOutput, State, Task,
+    },
+    http_client,
+    language_model::{LanguageModelRunner, MockLlmProvider},
+    pipeline::{
+        context::Context,
+        credentials::Credentials,
+        model_defaults::ModelDefaults,
+        nodes::{Handle, HandleType, Message, NodeInput, ParsedJson},
+        run_store::RunStore,
+        runner::PipelineRunner,
+        RunType,
+    },
+    runs::{checkpoints::PostgresCheckpointStore, node_io},
+    semantic_search::{
+        semantic_search_grpc::semantic_search_client::SemanticSearchClient, SemanticSearch,
+    },
+};
+
+/// What a [`NoopNode`] outputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoopBehavior {
+    /// The value of its first input by handle name, an empty string if it has none
+    Forward,
+    /// Its first input plus one, counting from zero if it isn't a number
+    Count,
+    /// Its first input if it's a number below the limit, terminates the branch otherwise
+    Below(f64),
+    /// Its first input if it's a number of at least the limit, terminates the branch otherwise
+    AtLeast(f64),
+    /// Finishes the run with its first input if it's a number of at least the limit, passes the
+    /// input on otherwise
+    FinishAtLeast(f64),
+}
+
+/// Node doing no work besides producing its output
+#[derive(Debug, Clone)]
+pub struct NoopNode {
+    pub id: Uuid,
+    pub name: String,
+    /// Output handle ids of the predecessors, with the input handles they're mapped to
+    pub inputs: Vec<(Uuid, Handle)>,
+    pub output_handle_id: Uuid,
+    pub behavior: NoopBehavior,
+}
+
+impl NoopNode {
+    fn first_input(input: &Input) -> Option<NodeInput> {
+        input
+            .messages()
+            .iter()
+            .min_by_key(|(handle, _)| handle.name())
+            .map(|(_, message)| message.value.clone())
+    }
+}
+
+#[async_trait]
+impl NodeImpl for NoopNode {
+    fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+        self.inputs.clone()
+    }
+
+    fn output_handle_id(&self) -> Uuid {
+        self.output_handle_id
+    }
+
+    fn node_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn node_id(&self) -> Uuid {
+        self.id
+    }
+
+    fn node_type(&self) -> String {
+        "Noop".to_string()
+    }
+
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let input = Self::first_input(&input);
+        let number = match input {
+            Some(NodeInput::Float(number)) => Some(number),
+            _ => None,
+        };
+        let output = match self.behavior {
+            NoopBehavior::Forward => input.unwrap_or(NodeInput::String(String::new())),
+            NoopBehavior::Count => NodeInput::Float(number.unwrap_or(0.0) + 1.0),
+            NoopBehavior::Below(limit) => match number {
+                Some(number) if number < limit => NodeInput::Float(number),
+                _ => return Ok(RunOutput::Termination),
+            },
+            NoopBehavior::AtLeast(limit) => match number {
+                Some(number) if number >= limit => NodeInput::Float(number),
+                _ => return Ok(RunOutput::Termination),
+            },
+            NoopBehavior::FinishAtLeast(limit) => match number {
+                Some(number) if number >= limit => {
+                    return Ok(RunOutput::Finish((NodeInput::Float(number), None)))
+                }
+                _ => input.unwrap_or(NodeInput::String(String::new())),
+            },
+        };
+        Ok(RunOutput::Success((output, None)))
+    }
+}
+
+/// Graph of [`NoopNode`]s, whose nodes are referred to by the order they were added in
+#[derive(Debug, Default)]
+pub struct NoopGraph {
+    nodes: Vec<NoopNode>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl NoopGraph {
+    pub fn node(&mut self, behavior: NoopBehavior) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(NoopNode {
+            id: Uuid::new_v4(),
+            name: format!("node{}", index),
+            inputs: Vec::new(),
+            output_handle_id: Uuid::new_v4(),
+            behavior,
+        });
+        index
+    }
+
+    /// Id of the node, which is the id of its task
+    pub fn id(&self, node: usize) -> Uuid {
+        self.nodes[node].id
+    }
+
+    /// Connect the output of `from` to an input of `to` named after `from`
+    pub fn edge(&mut self, from: usize, to: usize) {
+        let handle_name = self.nodes[from].name.clone();
+        self.connect(from, to, &handle_name, false);
+    }
+
+    /// Connect the output of `from` to the cyclic input `handle_name` of `to`. The entry into a
+    /// loop and the edge closing it are connected to the same cyclic input.
+    pub fn cyclic_edge(&mut self, from: usize, to: usize, handle_name: &str) {
+        self.connect(from, to, handle_name, true);
+    }
+
+    fn connect(&mut self, from: usize, to: usize, handle_name: &str, is_cyclic: bool) {
+        let output_handle_id = self.nodes[from].output_handle_id;
+        self.nodes[to].inputs.push((
+            output_handle_id,
+            Handle {
+                id: Uuid::new_v4(),
+                name: Some(handle_name.to_string()),
+                handle_type: HandleType::Any,
+                is_cyclic,
+            },
+        ));
+        self.edges.push((from, to));
+    }
+
+    /// Tasks of a run of the graph, with fresh input states
+    pub fn tasks(&self) -> HashMap<Uuid, Task> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let prev = self
+                    .edges
+                    .iter()
+                    .filter(|(_, to)| *to == index)
+                    .map(|(from, _)| self.nodes[*from].id)
+                    .collect();
+                let next = self
+                    .edges
+                    .iter()
+                    .filter(|(from, _)| *from == index)
+                    .map(|(_, to)| self.nodes[*to].id)
+                    .collect();
+                let action: Action = Arc::new(node.clone());
+                let successors = self
+                    .edges
+                    .iter()
+                    .filter(|(from, _)| *from == index)
+                    .map(|(_, to)| Arc::new(self.nodes[*to].clone()) as Action)
+                    .collect::<Vec<_>>();
+                let handles = TaskHandles::resolve(&action, "output", &successors);
+                (
+                    node.id,
+                    Task::with_inputs(node.id, action, &handles, prev, next),
+                )
+            })
+            .collect()
+    }
+}
+
+/// JSON of a graph of an input, followed by a chain of `templates` string templates and an
+/// output
+pub fn template_chain_graph(templates: usize) -> serde_json::Value {
+    let mut nodes = serde_json::Map::new();
+    let mut pred = serde_json::Map::new();
+    let (input_id, mut prev_output) = (Uuid::new_v4(), Uuid::new_v4());
+    nodes.insert(
+        "question".to_string(),
+        serde_json::json!({
+            "type": "Input",
+            "id": input_id,
+            "name": "question",
+            "outputs": [{"id": prev_output, "name": "output", "type": "String"}],
+            "inputType": "String",
+        }),
+    );
+    let mut prev_id = input_id;
+    for i in 0..templates {
+        let (id, input, output) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        nodes.insert(
+            format!("template{i}"),
+            serde_json::json!({
+                "type": "StringTemplate",
+                "id": id,
+                "name": format!("template{i}"),
+                "inputs": [{"id": input, "name": "text", "type": "String"}],
+                "outputs": [{"id": output, "name": "output", "type": "String"}],
+                "inputsMappings": {input.to_string(): prev_output},
+                "text": "{{text}}!",
+            }),
+        );
+        pred.insert(id.to_string(), serde_json::json!([prev_id]));
+        (prev_id, prev_output) = (id, output);
+    }
+    let (answer_id, answer_input) = (Uuid::new_v4(), Uuid::new_v4());
+    nodes.insert(
+        "answer".to_string(),
+        serde_json::json!({
+            "type": "Output",
+            "id": answer_id,
+            "name": "answer",
+            "inputs": [{"id": answer_input, "name": "output", "type": "String"}],
+            "inputsMappings": {answer_input.to_string(): prev_output},
+        }),
+    );
+    pred.insert(answer_id.to_string(), serde_json::json!([prev_id]));
+
+    serde_json::json!({"nodes": nodes, "pred": pred})
+}
+
+fn string_handle(id: Uuid, name: &str) -> Value {
+    serde_json::json!({"id": id, "name": name, "type": "String"})
+}
+
+/// JSON of a graph of an input fanned out to `nodes` templates and extractors, half of which share
+/// their config, each followed by an output
+pub fn wide_graph(nodes: usize) -> serde_json::Value {
+    let mut graph_nodes = serde_json::Map::new();
+    let mut pred = serde_json::Map::new();
+    let (input_id, input_output) = (Uuid::new_v4(), Uuid::new_v4());
+    graph_nodes.insert(
+        "question".to_string(),
+        serde_json::json!({
+            "type": "Input",
+            "id": input_id,
+            "name": "question",
+            "outputs": [string_handle(input_output, "output")],
+            "inputType": "String",
+        }),
+    );
+    for i in 0..nodes / 2 {
+        let config = if i % 2 == 0 { 0 } else { i };
+        let (id, input, output) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let (node_type, config_field, config) = if i % 4 < 2 {
+            (
+                "StringTemplate",
+                "text",
+                format!("{{{{#each text}}}}{{{{this}}}} {} {{{{/each}}}}", config),
+            )
+        } else {
+            (
+                "Extractor",
+                "format",
+                format!(r"(?<=answer {}: )\w+", config),
+            )
+        };
+        graph_nodes.insert(
+            format!("node{i}"),
+            serde_json::json!({
+                "type": node_type,
+                "id": id,
+                "name": format!("node{i}"),
+                "inputs": [string_handle(input, "text")],
+                "outputs": [string_handle(output, "output")],
+                "inputsMappings": {input.to_string(): input_output},
+                config_field: config,
+            }),
+        );
+        pred.insert(id.to_string(), serde_json::json!([input_id]));
+
+        let (answer_id, answer_input) = (Uuid::new_v4(), Uuid::new_v4());
+        graph_nodes.insert(
+            format!("answer{i}"),
+            serde_json::json!({
+                "type": "Output",
+                "id": answer_id,
+                "name": format!("answer{i}"),
+                "inputs": [string_handle(answer_input, "output")],
+                "inputsMappings": {answer_input.to_string(): output},
+            }),
+        );
+        pred.insert(answer_id.to_string(), serde_json::json!([id]));
+    }
+    serde_json::json!({"nodes": graph_nodes, "pred": pred})
+}
+
+/// Services of runs which call none of them: no model providers or chunkers, a database and
+/// semantic search which are never connected to, and no observations published. Must be created
+/// in a Tokio runtime.
+pub struct OfflineServices {
+    language_model: Arc<LanguageModelRunner>,
+    chunker_runner: Arc<ChunkerRunner>,
+    semantic_search: Arc<SemanticSearch>,
+    http_client: reqwest::Client,
+    pipeline_runner: PipelineRunner,
+}
+
+impl Default for OfflineServices {
+    fn default() -> Self {
+        Self::with_language_model(LanguageModelRunner::new(HashMap::new()))
+    }
+}
+
+impl OfflineServices {
+    /// Services whose LLM nodes are run by the runner, e.g. a
+    /// [`LanguageModelRunner::mocked`] one
+    pub fn with_language_model(language_model: LanguageModelRunner) -> Self {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/laminar")
+            .expect("Failed to create database pool");
+        let db = Arc::new(DB::new(pool));
+        let channel =
+            tonic::transport::Endpoint::from_static("http://localhost:8080").connect_lazy();
+        let semantic_search = Arc::new(SemanticSearch::new(
+            Arc::new(SemanticSearchClient::new(channel)),
+            db.clone(),
+        ));
+        let language_model = Arc::new(language_model);
+        let chunker_runner = Arc::new(ChunkerRunner::new(HashMap::new()));
+        let http_client = http_client::build_client();
+        let pipeline_runner = PipelineRunner::new(
+            language_model.clone(),
+            chunker_runner.clone(),
+            semantic_search.clone(),
+            http_client.clone(),
+            None,
+            node_io::store_from_env(db.clone()),
+            Arc::new(PostgresCheckpointStore::new(db)),
+        );
+
+        Self {
+            language_model,
+            chunker_runner,
+            semantic_search,
+            http_client,
+            pipeline_runner,
+        }
+    }
+
+    /// Services whose runs take the time from the clock, e.g. a
+    /// [`ManualClock`](crate::clock::ManualClock) the test advances
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.pipeline_runner = self.pipeline_runner.with_clock(clock);
+        self
+    }
+
+    /// Context of a run without env, secrets or structured output schemas
+    pub fn context(&self) -> Context {
+        Context {
+            language_model: self.language_model.clone(),
+            chunker_runner: self.chunker_runner.clone(),
+            semantic_search: self.semantic_search.clone(),
+            http_client: self.http_client.clone(),
+            env: HashMap::new(),
+            secrets: HashMap::new(),
+            credentials: Credentials::default(),
+            tx: None,
+            metadata: HashMap::new(),
+            run_type: RunType::Workshop,
+            pipeline_runner: self.pipeline_runner.clone(),
+            baml_schemas: Arc::new(HashMap::new()),
+            workspace_model_defaults: ModelDefaults::default(),
+            workspace_id: None,
+            prompts: None,
+            clock: self.pipeline_runner.clock(),
+            run_store: Arc::new(RunStore::default()),
+        }
+    }
+}
+
+/// Span table of a [`SpanExporter`](crate::ch::exporter::SpanExporter) in memory, which keeps
+/// the ids of the spans inserted into it
+#[derive(Default)]
+pub struct MemorySink {
+    spans: Mutex<Vec<(Uuid, Uuid)>>,
+}
+
+impl MemorySink {
+    pub fn span_ids(&self) -> Vec<Uuid> {
+        let spans = self.spans.lock().unwrap();
+        spans.iter().map(|(span_id, _)| *span_id).collect()
+    }
+
+    pub fn trace_ids(&self) -> Vec<Uuid> {
+        let spans = self.spans.lock().unwrap();
+        spans.iter().map(|(_, trace_id)| *trace_id).collect()
+    }
+}
+
+#[async_trait]
+impl SpanSink for MemorySink {
+    async fn insert(&self, spans: &[CHSpan]) -> anyhow::Result<()> {
+        let mut inserted = self.spans.lock().unwrap();
+        inserted.extend(spans.iter().map(|span| (span.span_id, span.trace_id)));
+        Ok(())
+    }
+}
+
+const MOCK_HTTP_RESPONSE: &[u8] =
+    b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}";
+
+/// Mock HTTP/1.1 provider counting the connections it accepted, answering requests without a
+/// body on each connection until the client closes it. Returns its URL and the count.
+pub async fn mock_http_provider() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://{}/v1/chat/completions",
+        listener.local_addr().unwrap()
+    );
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                loop {
+                    let Ok(read) = socket.read(&mut buf).await else {
+                        return;
+                    };
+                    if read == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                    if request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        request.clear();
+                        if socket.write_all(MOCK_HTTP_RESPONSE).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (url, connections)
+}
+
+/// Builder of the [`Input`] of a node run, by input handle name
+#[derive(Debug, Default)]
+pub struct InputBuilder {
+    messages: HashMap<String, Arc<Message>>,
+}
+
+impl InputBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Message with the value, as if a predecessor named after the handle produced it
+    pub fn value(self, handle_name: &str, value: impl Into<NodeInput>) -> Self {
+        let message = Message {
+            value: value.into(),
+            node_name: handle_name.to_string(),
+            ..Message::empty()
+        };
+        self.message(handle_name, message)
+    }
+
+    /// Message with the JSON text of the value, as LLM and API nodes output it
+    pub fn json(self, handle_name: &str, value: Value) -> Self {
+        self.value(handle_name, value.to_string())
+    }
+
+    pub fn message(mut self, handle_name: &str, message: Message) -> Self {
+        self.messages
+            .insert(handle_name.to_string(), Arc::new(message));
+        self
+    }
+
+    /// Input whose predecessor produced no message, e.g. a cyclic input before its loop ran,
+    /// which the node receives as an empty string
+    pub fn empty(self, handle_name: &str) -> Self {
+        self.message(handle_name, Message::empty())
+    }
+
+    /// Input whose predecessor terminated its branch. The engine doesn't pass terminations on,
+    /// so the node receives no message for the handle at all.
+    pub fn terminated(mut self, handle_name: &str) -> Self {
+        self.messages.remove(handle_name);
+        self
+    }
+
+    /// Input of no node in particular, whose handles [`run_node`] binds to the node it runs
+    pub fn build(self) -> Input {
+        let messages = self
+            .messages
+            .into_iter()
+            .map(|(handle_name, message)| (InputHandle::new(Uuid::nil(), handle_name), message))
+            .collect();
+        Input::new(Uuid::nil(), messages)
+    }
+}
+
+/// Run the node on the input, with the context of [`OfflineServices`] whose LLM nodes are run
+/// by a mock without responses. Its output is the state its successors would receive.
+pub async fn run_node<N>(node: &N, input: Input) -> Result<State, NodeError>
+where
+    N: NodeImpl + Send + Sync + ?Sized,
+{
+    let services =
+        OfflineServices::with_language_model(LanguageModelRunner::mocked(MockLlmProvider::new()));
+    run_node_with_context(node, input, services.context()).await
+}
+
+/// Run the node on the input in the context, as a task of the engine would
+pub async fn run_node_with_context<N>(
+    node: &N,
+    input: Input,
+    context: Context,
+) -> Result<State, NodeError>
+where
+    N: NodeImpl + Send + Sync + ?Sized,
+{
+    let input = input.of_node(node.node_id());
+    let input_message_ids = input
+        .messages()
+        .values()
+        .map(|message| message.id)
+        .collect();
+    let start_time = Utc::now();
+    let (value, meta_log, finishes) = match node.run(input, Arc::new(context)).await? {
+        RunOutput::Success((value, meta_log)) => (value, meta_log, false),
+        RunOutput::Finish((value, meta_log)) => (value, meta_log, true),
+        RunOutput::Termination => return Ok(State::termination()),
+    };
+    let message = Arc::new(Message {
+        id: Uuid::new_v4(),
+        value,
+        node_id: node.node_id(),
+        node_name: node.node_name(),
+        node_type: node.node_type(),
+        input_message_ids,
+        meta_log,
+        parsed_json: ParsedJson::default(),
+        post_processing: Vec::new(),
+        start_time,
+        end_time: Utc::now(),
+    });
+    if finishes {
+        Ok(State::Finish(message))
+    } else {
+        Ok(State::Success(message))
+    }
+}
+
+/// Assert that the node output a message, whose JSON is `expected`, or finished the run with it.
+/// Strings which aren't JSON are compared as JSON strings.
+#[track_caller]
+pub fn assert_json_output(state: &State, expected: Value) {
+    let (State::Success(message) | State::Finish(message)) = state else {
+        panic!("Expected an output, the node terminated its branch");
+    };
+    let differences = message.diff(&expected);
+    assert!(
+        differences.is_empty(),
+        "Output of {} differs from the expected JSON:\n{}",
+        message.node_name,
+        differences.join("\n")
+    );
+}
+
+#[track_caller]
+pub fn assert_terminated(state: &State) {
+    if let State::Success(message) | State::Finish(message) = state {
+        panic!(
+            "Expected the node to terminate its branch, it output {:?}",
+            message.value
+        );
+    }
+}
+
+impl Message {
+    /// Differences of the message's JSON from the expected JSON, by JSON pointer
+    pub fn diff(&self, expected: &Value) -> Vec<String> {
+        let actual = match self.json() {
+            Some(json) => json.clone(),
+            None => self.value.clone().into(),
+        };
+        let mut differences = Vec::new();
+        diff_json("", &actual, expected, &mut differences);
+        differences
+    }
+}
+
+fn diff_json(pointer: &str, actual: &Value, expected: &Value, differences: &mut Vec<String>) {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => {
+            for (key, expected_value) in expected {
+                let key_pointer =
+                    format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                match actual.get(key) {
+                    Some(actual_value) => {
+                        diff_json(&key_pointer, actual_value, expected_value, differences)
+                    }
+                    None => differences.push(format!(
+                        "{}: missing, expected {}",
+                        key_pointer, expected_value
+                    )),
+                }
+            }
+            for (key, actual_value) in actual {
+                if !expected.contains_key(key) {
+                    differences.push(format!(
+                        "{}/{}: unexpected {}",
+                        pointer,
+                        key.replace('~', "~0").replace('/', "~1"),
+                        actual_value
+                    ));
+                }
+            }
+        }
+        (Value::Array(actual_items), Value::Array(expected_items))
+            if actual_items.len() == expected_items.len() =>
+        {
+            for (i, (actual_item, expected_item)) in
+                actual_items.iter().zip(expected_items).enumerate()
+            {
+                diff_json(
+                    &format!("{}/{}", pointer, i),
+                    actual_item,
+                    expected_item,
+                    differences,
+                );
+            }
+        }
+        _ if actual != expected => differences.push(format!(
+            "{}: expected {}, got {}",
+            pointer, expected, actual
+        )),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::engine::Engine;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_loop_of_noop_nodes_terminates() {
+        const ITERATIONS: f64 = 3.0;
+
+        let mut graph = NoopGraph::default();
+        let input = graph.node(NoopBehavior::Forward);
+        let counter = graph.node(NoopBehavior::Count);
+        let again = graph.node(NoopBehavior::Below(ITERATIONS));
+        let done = graph.node(NoopBehavior::AtLeast(ITERATIONS));
+        let output = graph.node(NoopBehavior::Forward);
+        graph.cyclic_edge(input, counter, "count");
+        graph.edge(counter, again);
+        graph.cyclic_edge(again, counter, "count");
+        graph.edge(counter, done);
+        graph.edge(done, output);
+
+        let services = OfflineServices::default();
+        let mut engine =
+            Engine::with_tasks_and_context(graph.tasks(), services.context(), None, None, None);
+        let outputs = engine.run(None, None, vec![]).await.unwrap();
+        assert_eq!(
+            outputs.output_values().into_values().collect::<Vec<_>>(),
+            vec![NodeInput::Float(ITERATIONS)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_node_on_built_input() {
+        let mut graph = NoopGraph::default();
+        let counter = graph.node(NoopBehavior::Count);
+        let stop = graph.node(NoopBehavior::Below(1.0));
+        let input = InputBuilder::new()
+            .value("a", json!(1.0))
+            .empty("b")
+            .value("c", "ignored".to_string())
+            .terminated("c")
+            .build();
+        assert_eq!(input.messages().len(), 2);
+
+        let state = run_node(&graph.nodes[counter], input.clone())
+            .await
+            .unwrap();
+        assert_json_output(&state, json!(2.0));
+        assert_eq!(state.get_out().input_message_ids.len(), 2);
+        let state = run_node(&graph.nodes[stop], input).await.unwrap();
+        assert_terminated(&state);
+    }
+
+    #[test]
+    fn test_diff_by_pointer() {
+        let message = Message {
+            value: NodeInput::String(r#"{"a": 1, "b": [1, 2], "c/d": "x"}"#.to_string()),
+            ..Message::empty()
+        };
+        assert!(message
+            .diff(&json!({"a": 1, "b": [1, 2], "c/d": "x"}))
+            .is_empty());
+        assert_eq!(
+            message.diff(&json!({"a": 2, "b": [1, 3], "e": null})),
+            vec![
+                "/a: expected 2, got 1",
+                "/b/1: expected 3, got 2",
+                "/e: missing, expected null",
+                "/c~1d: unexpected \"x\"",
+            ]
+        );
+        let text = Message {
+            value: NodeInput::String("not json".to_string()),
+            ..Message::empty()
+        };
+        assert!(text.diff(&json!("not json")).is_empty());
+    }
+}
@@ -5,6 +5,40 @@ pub const GEN_AI_INPUT_TOKENS: &str = "gen_ai.usage.prompt_tokens";
 pub const GEN_AI_OUTPUT_TOKENS: &str = "gen_ai.usage.completion_tokens";
 // pub const GEN_AI_TOTAL_TOKENS: &str = "gen_ai.usage.total_tokens";
 pub const GEN_AI_REQUEST_MODEL: &str = "gen_ai.request.model";
+pub const GEN_AI_REQUEST_TEMPERATURE: &str = "gen_ai.request.temperature";
+pub const GEN_AI_REQUEST_MAX_TOKENS: &str = "gen_ai.request.max_tokens";
 pub const GEN_AI_RESPONSE_MODEL: &str = "gen_ai.response.model";
 // pub const GEN_AI_REQUEST_IS_STREAM: &str = "gen_ai.request.is_stream";
 pub const GEN_AI_SYSTEM: &str = "gen_ai.system";
+
+pub const LMNR_PIPELINE_VERSION_ID: &str = "lmnr.pipeline.version_id";
+pub const LMNR_PIPELINE_VERSION_HASH: &str = "lmnr.pipeline.version_hash";
+/// Effective model config of an LLM node, with the layer each value comes from
+pub const LMNR_LLM_MODEL_CONFIG: &str = "lmnr.llm.model_config";
+/// Alias of the credential which served the call of an LLM node, to attribute its usage
+pub const LMNR_LLM_CREDENTIAL: &str = "lmnr.llm.credential";
+/// Strategy which fit the messages of an LLM node into the context of its model
+pub const LMNR_LLM_CONTEXT_STRATEGY: &str = "lmnr.llm.context.strategy";
+pub const LMNR_LLM_CONTEXT_EVICTED_TOKENS: &str = "lmnr.llm.context.evicted_tokens";
+pub const LMNR_LLM_CONTEXT_EVICTED_MESSAGES: &str = "lmnr.llm.context.evicted_messages";
+/// Registry prompt an LLM node ran with, and the version and label it resolved to
+pub const LMNR_LLM_PROMPT_NAME: &str = "lmnr.llm.prompt.name";
+pub const LMNR_LLM_PROMPT_VERSION: &str = "lmnr.llm.prompt.version";
+pub const LMNR_LLM_PROMPT_LABEL: &str = "lmnr.llm.prompt.label";
+/// Workspace of the project the run of the trace ran in
+pub const LMNR_WORKSPACE_ID: &str = "lmnr.workspace.id";
+/// Id of the run which the run of the trace replays
+pub const LMNR_RUN_REPLAY_OF: &str = "lmnr.run.replay_of";
+/// Values of the run store as of the end of the run, of graphs with `traceRunStore` set
+pub const LMNR_RUN_STORE: &str = "lmnr.run.store";
+/// Pipeline of the run a span of a router node is in
+pub const LMNR_PIPELINE_ID: &str = "lmnr.pipeline.id";
+/// Route a router node, e.g. a switch, took, and the id of the node
+pub const LMNR_ROUTE_NAME: &str = "lmnr.route.name";
+pub const LMNR_ROUTE_NODE_ID: &str = "lmnr.route.node_id";
+/// Post-processors applied to the output of the node, and whether each changed it
+pub const LMNR_NODE_POST_PROCESSING: &str = "lmnr.node.post_processing";
+/// Type of the node a span of a run is of, e.g. `LLM` or `SemanticSearch`
+pub const LMNR_NODE_TYPE: &str = "lmnr.node.type";
+/// Documents a semantic search node retrieved, in order of relevance
+pub const LMNR_RETRIEVAL_DOCUMENTS: &str = "lmnr.retrieval.documents";
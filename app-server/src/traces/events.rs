@@ -14,9 +14,11 @@ use crate::{
     },
     pipeline::{
         nodes::{Node, NodeInput},
+        prompts,
         runner::PipelineRunner,
-        Graph, RunType,
+        RunType,
     },
+    secrets,
 };
 
 pub async fn create_events(
@@ -152,7 +154,7 @@ pub async fn evaluate_event(
     };
 
     let run_type = RunType::EventEvaluation;
-    let mut graph = serde_json::from_value::<Graph>(pipeline_version.runnable_graph)?;
+    let mut graph = pipeline_runner.get_version_graph(&pipeline_version)?;
 
     // TODO: Figure out how to use this metadata and link it to the evaluation event
     let metadata = HashMap::from([("span_id".to_string(), span_id.to_string())]);
@@ -172,6 +174,13 @@ pub async fn evaluate_event(
         .collect();
 
     graph.setup(&inputs, &evaluate_event.env, &metadata, &run_type)?;
+    let secrets = secrets::get_project_secrets(&db.pool, &project_id).await?;
+    graph.secrets = secrets.clone();
+    graph.workspace_model_defaults =
+        db::model_defaults::get_project_model_defaults(&db.pool, &project_id).await?;
+    graph.workspace_id =
+        Some(db::workspace::get_workspace_id_of_project(&db.pool, &project_id).await?);
+    prompts::load_prompts(&db.pool, &project_id, &mut graph, &HashMap::new()).await?;
 
     // Get first output node, expect graph to contain only one output node
     let output_node = graph
@@ -187,14 +196,18 @@ pub async fn evaluate_event(
         }
     };
 
+    let workspace_id = graph.workspace_id;
     let run_result = pipeline_runner.run(graph, None).await;
     pipeline_runner
         .record_observations(
             &run_result,
             &project_id,
-            &pipeline_version.name,
+            workspace_id,
+            &pipeline_version,
             parent_span_id,
             trace_id,
+            None,
+            &secrets,
         )
         .await?;
 
@@ -3,11 +3,12 @@ use std::sync::Arc;
 use events::{create_events, evaluate_and_record_events};
 use futures::StreamExt;
 use lapin::{options::BasicConsumeOptions, options::*, types::FieldTable, Connection};
+use uuid::Uuid;
 
 use crate::{
     api::v1::traces::RabbitMqSpanMessage,
     cache::Cache,
-    ch::{self, spans::CHSpan},
+    ch::{exporter::SpanExporter, spans::CHSpan},
     db::{
         events::EventSource,
         trace::{self, Span, SpanAttributes, SpanType, TraceAttributes},
@@ -19,6 +20,7 @@ use crate::{
 
 pub mod attributes;
 pub mod events;
+pub mod openinference;
 
 pub const OBSERVATIONS_QUEUE: &str = "observations_queue";
 pub const OBSERVATIONS_EXCHANGE: &str = "observations_exchange";
@@ -30,7 +32,7 @@ pub async fn observation_collector(
     cache: Arc<Cache>,
     language_model_runner: Arc<LanguageModelRunner>,
     rabbitmq_connection: Arc<Connection>,
-    clickhouse: clickhouse::Client,
+    span_exporter: Arc<SpanExporter>,
 ) {
     let channel = rabbitmq_connection.create_channel().await.unwrap();
 
@@ -73,44 +75,15 @@ pub async fn observation_collector(
         };
 
         let span: Span = rabbitmq_span_message.span;
-
-        let mut trace_attributes = TraceAttributes::new(span.trace_id);
-        let span_usage =
-            get_llm_usage_for_span(&span.get_attributes(), language_model_runner.clone());
-        trace_attributes.update_start_time(span.start_time);
-        trace_attributes.update_end_time(span.end_time);
-
-        let span_attributes = span.get_attributes();
-
-        trace_attributes.update_user_id(span_attributes.user_id());
-        trace_attributes.update_session_id(span_attributes.session_id());
-
-        if span.span_type == SpanType::LLM {
-            trace_attributes.add_cost(span_usage.total_cost);
-            trace_attributes.add_tokens(span_usage.total_tokens);
-        }
-
-        let update_attrs_res = trace::update_trace_attributes(
-            &db.pool,
-            &rabbitmq_span_message.project_id,
-            &trace_attributes,
+        // failed steps are logged, the events of the span are recorded regardless
+        let _ = record_span(
+            &db,
+            language_model_runner.clone(),
+            &span_exporter,
+            rabbitmq_span_message.project_id,
+            &span,
         )
         .await;
-        if let Err(e) = update_attrs_res {
-            log::error!("Failed to update trace attributes: {:?}", e);
-        }
-
-        let record_spans_res = trace::record_span(&db.pool, &span).await;
-        if let Err(e) = record_spans_res {
-            log::error!("Failed to record spans: {:?}", e);
-        }
-
-        let ch_span = CHSpan::from_db_span(&span, span_usage, rabbitmq_span_message.project_id);
-        // TODO: Queue batches on client-side and send them every 1-2 seconds
-        let insert_span_res = ch::spans::insert_span(clickhouse.clone(), &ch_span).await;
-        if let Err(e) = insert_span_res {
-            log::error!("Failed to insert span into Clickhouse: {:?}", e);
-        }
 
         // Record evaluated events and ordinary events only after all their are recorded
         let eval_res = evaluate_and_record_events(
@@ -146,6 +119,53 @@ pub async fn observation_collector(
     log::info!("Shutting down span listener");
 }
 
+/// Record the span and the attributes of its trace, and queue it to be exported to ClickHouse
+///
+/// Every step is attempted even if an earlier one fails, the error is the one of the last
+/// failed step.
+pub async fn record_span(
+    db: &DB,
+    language_model_runner: Arc<LanguageModelRunner>,
+    span_exporter: &SpanExporter,
+    project_id: Uuid,
+    span: &Span,
+) -> anyhow::Result<()> {
+    let mut result = Ok(());
+
+    let mut trace_attributes = TraceAttributes::new(span.trace_id);
+    let span_usage = get_llm_usage_for_span(&span.get_attributes(), language_model_runner);
+    trace_attributes.update_start_time(span.start_time);
+    trace_attributes.update_end_time(span.end_time);
+
+    let span_attributes = span.get_attributes();
+
+    trace_attributes.update_user_id(span_attributes.user_id());
+    trace_attributes.update_session_id(span_attributes.session_id());
+
+    if span.span_type == SpanType::LLM {
+        trace_attributes.add_cost(span_usage.total_cost);
+        trace_attributes.add_tokens(span_usage.total_tokens);
+    }
+
+    if let Err(e) = trace::update_trace_attributes(&db.pool, &project_id, &trace_attributes).await {
+        log::error!("Failed to update trace attributes: {:?}", e);
+        result = Err(e);
+    }
+
+    if let Err(e) = trace::record_span(&db.pool, span).await {
+        log::error!("Failed to record spans: {:?}", e);
+        result = Err(e);
+    }
+
+    let ch_span = CHSpan::from_db_span(span, span_usage, project_id);
+    if let Err(e) = span_exporter.export(ch_span).await {
+        log::error!("Failed to export span to Clickhouse: {:?}", e);
+        result = Err(e);
+    }
+
+    result
+}
+
 pub struct SpanUsage {
     pub prompt_tokens: i64,
     pub completion_tokens: i64,
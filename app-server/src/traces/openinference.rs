@@ -0,0 +1,606 @@
+//! Export of traces as OTLP, in the attribute conventions of the consumer
+//!
+//! In the `laminar` format, spans carry the attributes they were recorded with, which follow the
+//! `gen_ai` and OpenLLMetry (`traceloop`) conventions, with their input and output as
+//! `traceloop.entity.input` and `traceloop.entity.output`. In the `openinference` format, they
+//! only carry attributes of the OpenInference semantic conventions, for consumers such as Arize
+//! Phoenix: the kind of the span, its input and output, the model, provider, messages and token
+//! counts of LLM spans, the documents of retriever spans, and the session and user of the trace.
+//!
+//! Spans of runs are mapped by the type of their node: LLM nodes are `LLM` spans, semantic search
+//! nodes `RETRIEVER` spans with the documents they retrieved, and the other nodes and the span of
+//! the run are `CHAIN` spans. Spans sent by the SDKs are mapped by their OpenLLMetry kind, tools
+//! are `TOOL` spans and agents `AGENT` spans, or else by their span type.
+//!
+//! OTel span ids are the last 8 bytes of the spans' ids, which for spans sent over OTLP are the
+//! span ids they were sent with.
+//!
+//! See https://github.com/Arize-ai/openinference/blob/main/spec/semantic_conventions.md
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::{
+    db::{
+        trace::{Span, SpanAttributes, SpanType, Trace},
+        utils::uuid_to_span_id,
+    },
+    language_model::{ChatMessage, ChatMessageContent},
+    opentelemetry::{
+        opentelemetry_collector_trace_v1::ExportTraceServiceRequest,
+        opentelemetry_proto_common_v1::{any_value, AnyValue, InstrumentationScope, KeyValue},
+        opentelemetry_proto_resource_v1::Resource,
+        opentelemetry_proto_trace_v1::{
+            span::SpanKind, ResourceSpans, ScopeSpans, Span as OtelSpan,
+        },
+    },
+};
+
+const SERVICE_NAME: &str = "service.name";
+const SCOPE_NAME: &str = "lmnr";
+
+const TRACELOOP_SPAN_KIND: &str = "traceloop.span.kind";
+const TRACELOOP_ENTITY_INPUT: &str = "traceloop.entity.input";
+const TRACELOOP_ENTITY_OUTPUT: &str = "traceloop.entity.output";
+
+const OPENINFERENCE_SPAN_KIND: &str = "openinference.span.kind";
+const INPUT_VALUE: &str = "input.value";
+const INPUT_MIME_TYPE: &str = "input.mime_type";
+const OUTPUT_VALUE: &str = "output.value";
+const OUTPUT_MIME_TYPE: &str = "output.mime_type";
+const LLM_MODEL_NAME: &str = "llm.model_name";
+const LLM_PROVIDER: &str = "llm.provider";
+const LLM_TOKEN_COUNT_PROMPT: &str = "llm.token_count.prompt";
+const LLM_TOKEN_COUNT_COMPLETION: &str = "llm.token_count.completion";
+const LLM_TOKEN_COUNT_TOTAL: &str = "llm.token_count.total";
+const LLM_INPUT_MESSAGES: &str = "llm.input_messages";
+const LLM_OUTPUT_MESSAGES: &str = "llm.output_messages";
+const MESSAGE_ROLE: &str = "message.role";
+const MESSAGE_CONTENT: &str = "message.content";
+const RETRIEVAL_DOCUMENTS: &str = "retrieval.documents";
+const DOCUMENT_CONTENT: &str = "document.content";
+const DOCUMENT_SCORE: &str = "document.score";
+const DOCUMENT_METADATA: &str = "document.metadata";
+const SESSION_ID: &str = "session.id";
+const USER_ID: &str = "user.id";
+
+const MIME_TYPE_TEXT: &str = "text/plain";
+const MIME_TYPE_JSON: &str = "application/json";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Laminar,
+    OpenInference,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OpenInferenceSpanKind {
+    Llm,
+    Retriever,
+    Chain,
+    Tool,
+    Agent,
+}
+
+impl OpenInferenceSpanKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Llm => "LLM",
+            Self::Retriever => "RETRIEVER",
+            Self::Chain => "CHAIN",
+            Self::Tool => "TOOL",
+            Self::Agent => "AGENT",
+        }
+    }
+}
+
+/// OTLP export request of the trace's spans, in the format
+pub fn export_request(
+    trace: &Trace,
+    spans: &[Span],
+    format: ExportFormat,
+) -> ExportTraceServiceRequest {
+    let spans = spans
+        .iter()
+        .map(|span| {
+            let attributes = match format {
+                ExportFormat::Laminar => laminar_attributes(span),
+                ExportFormat::OpenInference => openinference_attributes(trace, span),
+            };
+            OtelSpan {
+                trace_id: span.trace_id.as_bytes().to_vec(),
+                span_id: uuid_to_span_id(&span.span_id),
+                parent_span_id: span
+                    .parent_span_id
+                    .map(|parent_span_id| uuid_to_span_id(&parent_span_id))
+                    .unwrap_or_default(),
+                name: span.name.clone(),
+                kind: SpanKind::Internal as i32,
+                start_time_unix_nano: unix_nanos(span.start_time),
+                end_time_unix_nano: unix_nanos(span.end_time),
+                attributes,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans {
+            resource: Some(Resource {
+                attributes: vec![key_value(SERVICE_NAME, &json!(SCOPE_NAME))],
+                ..Default::default()
+            }),
+            scope_spans: vec![ScopeSpans {
+                scope: Some(InstrumentationScope {
+                    name: SCOPE_NAME.to_string(),
+                    ..Default::default()
+                }),
+                spans,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+fn laminar_attributes(span: &Span) -> Vec<KeyValue> {
+    let mut attributes = span.attributes.as_object().cloned().unwrap_or_default();
+    for (key, value) in [
+        (TRACELOOP_ENTITY_INPUT, &span.input),
+        (TRACELOOP_ENTITY_OUTPUT, &span.output),
+    ] {
+        if let Some(value) = value {
+            attributes
+                .entry(key)
+                .or_insert_with(|| json!(value.to_string()));
+        }
+    }
+
+    attributes
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| key_value(key, value))
+        .collect()
+}
+
+fn openinference_attributes(trace: &Trace, span: &Span) -> Vec<KeyValue> {
+    let span_attributes = span.get_attributes();
+    let kind = span_kind(span, &span_attributes);
+    let mut attributes = vec![(OPENINFERENCE_SPAN_KIND.to_string(), json!(kind.as_str()))];
+
+    for (value_key, mime_type_key, value) in [
+        (INPUT_VALUE, INPUT_MIME_TYPE, &span.input),
+        (OUTPUT_VALUE, OUTPUT_MIME_TYPE, &span.output),
+    ] {
+        match value {
+            None | Some(Value::Null) => {}
+            Some(Value::String(value)) => {
+                attributes.push((value_key.to_string(), json!(value)));
+                attributes.push((mime_type_key.to_string(), json!(MIME_TYPE_TEXT)));
+            }
+            Some(value) => {
+                attributes.push((value_key.to_string(), json!(value.to_string())));
+                attributes.push((mime_type_key.to_string(), json!(MIME_TYPE_JSON)));
+            }
+        }
+    }
+
+    match kind {
+        OpenInferenceSpanKind::Llm => attributes.extend(llm_attributes(span, &span_attributes)),
+        OpenInferenceSpanKind::Retriever => {
+            for (i, document) in span_attributes.retrieved_documents().iter().enumerate() {
+                let prefix = format!("{RETRIEVAL_DOCUMENTS}.{i}");
+                let mut metadata = json!(document.data);
+                metadata["datasource_id"] = json!(document.datasource_id);
+                attributes.extend([
+                    (
+                        format!("{prefix}.{DOCUMENT_CONTENT}"),
+                        json!(document.content),
+                    ),
+                    (format!("{prefix}.{DOCUMENT_SCORE}"), json!(document.score)),
+                    (
+                        format!("{prefix}.{DOCUMENT_METADATA}"),
+                        json!(metadata.to_string()),
+                    ),
+                ]);
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(session_id) = &trace.session_id {
+        attributes.push((SESSION_ID.to_string(), json!(session_id)));
+    }
+    if let Some(user_id) = &trace.user_id {
+        attributes.push((USER_ID.to_string(), json!(user_id)));
+    }
+
+    attributes
+        .iter()
+        .map(|(key, value)| key_value(key, value))
+        .collect()
+}
+
+fn span_kind(span: &Span, attributes: &SpanAttributes) -> OpenInferenceSpanKind {
+    match attributes.node_type().as_deref() {
+        Some("LLM") => return OpenInferenceSpanKind::Llm,
+        Some("SemanticSearch") => return OpenInferenceSpanKind::Retriever,
+        Some(_) => return OpenInferenceSpanKind::Chain,
+        None => {}
+    }
+    match attributes
+        .attributes
+        .get(TRACELOOP_SPAN_KIND)
+        .and_then(Value::as_str)
+    {
+        Some("tool") => OpenInferenceSpanKind::Tool,
+        Some("agent") => OpenInferenceSpanKind::Agent,
+        _ if span.span_type == SpanType::LLM => OpenInferenceSpanKind::Llm,
+        _ => OpenInferenceSpanKind::Chain,
+    }
+}
+
+fn llm_attributes(span: &Span, attributes: &SpanAttributes) -> Vec<(String, Value)> {
+    let prompt_tokens = attributes.prompt_tokens();
+    let completion_tokens = attributes.completion_tokens();
+    let mut llm_attributes = vec![
+        (LLM_TOKEN_COUNT_PROMPT.to_string(), json!(prompt_tokens)),
+        (
+            LLM_TOKEN_COUNT_COMPLETION.to_string(),
+            json!(completion_tokens),
+        ),
+        (
+            LLM_TOKEN_COUNT_TOTAL.to_string(),
+            json!(prompt_tokens + completion_tokens),
+        ),
+    ];
+    if let Some(model) = attributes
+        .response_model()
+        .or_else(|| attributes.request_model())
+    {
+        llm_attributes.push((LLM_MODEL_NAME.to_string(), json!(model)));
+    }
+    if let Some(provider) = attributes.provider_name().and_then(|p| llm_provider(&p)) {
+        llm_attributes.push((LLM_PROVIDER.to_string(), json!(provider)));
+    }
+
+    // spans sent by the SDKs have the prompt's messages as input, LLM nodes their inputs
+    let messages = span
+        .input
+        .clone()
+        .and_then(|input| serde_json::from_value::<Vec<ChatMessage>>(input).ok())
+        .unwrap_or_default();
+    for (i, message) in messages.iter().enumerate() {
+        let prefix = format!("{LLM_INPUT_MESSAGES}.{i}");
+        llm_attributes.push((format!("{prefix}.{MESSAGE_ROLE}"), json!(message.role)));
+        if let ChatMessageContent::Text(content) = &message.content {
+            llm_attributes.push((format!("{prefix}.{MESSAGE_CONTENT}"), json!(content)));
+        }
+    }
+    if let Some(Value::String(completion)) = &span.output {
+        let prefix = format!("{LLM_OUTPUT_MESSAGES}.0");
+        llm_attributes.extend([
+            (format!("{prefix}.{MESSAGE_ROLE}"), json!("assistant")),
+            (format!("{prefix}.{MESSAGE_CONTENT}"), json!(completion)),
+        ]);
+    }
+
+    llm_attributes
+}
+
+/// OpenInference provider of the `gen_ai.system` of a span, None for the ones it doesn't define
+fn llm_provider(system: &str) -> Option<&'static str> {
+    match system.to_lowercase().as_str() {
+        "openai" => Some("openai"),
+        "anthropic" => Some("anthropic"),
+        "mistral" | "mistralai" => Some("mistralai"),
+        "openai-azure" | "azure" => Some("azure"),
+        "gemini" | "google" => Some("google"),
+        "bedrock" | "aws" => Some("aws"),
+        "cohere" => Some("cohere"),
+        _ => None,
+    }
+}
+
+/// OTLP attribute of the JSON value, arrays and objects are JSON strings
+fn key_value(key: &str, value: &Value) -> KeyValue {
+    let value = match value {
+        Value::String(s) => any_value::Value::StringValue(s.clone()),
+        Value::Bool(b) => any_value::Value::BoolValue(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => any_value::Value::IntValue(i),
+            None => any_value::Value::DoubleValue(n.as_f64().unwrap_or_default()),
+        },
+        _ => any_value::Value::StringValue(value.to_string()),
+    };
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+fn unix_nanos(time: DateTime<Utc>) -> u64 {
+    time.timestamp_nanos_opt().unwrap_or_default() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use prost::Message as _;
+    use uuid::Uuid;
+
+    use crate::{
+        db::utils::span_id_to_uuid,
+        pipeline::{
+            nodes::{
+                semantic_search_utils::{EmbeddingNodeMetaLog, RetrievedDocument},
+                Message, NodeInput,
+            },
+            trace::MetaLog,
+        },
+        traces::attributes::{
+            GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_TOKENS, GEN_AI_RESPONSE_MODEL, GEN_AI_SYSTEM,
+        },
+    };
+
+    use super::*;
+
+    /// Span attributes of the OpenInference semantic conventions, `{i}` stands for an index
+    const PUBLISHED_ATTRIBUTES: &[&str] = &[
+        "openinference.span.kind",
+        "input.value",
+        "input.mime_type",
+        "output.value",
+        "output.mime_type",
+        "llm.model_name",
+        "llm.provider",
+        "llm.system",
+        "llm.invocation_parameters",
+        "llm.token_count.prompt",
+        "llm.token_count.completion",
+        "llm.token_count.total",
+        "llm.input_messages.{i}.message.role",
+        "llm.input_messages.{i}.message.content",
+        "llm.output_messages.{i}.message.role",
+        "llm.output_messages.{i}.message.content",
+        "retrieval.documents.{i}.document.id",
+        "retrieval.documents.{i}.document.content",
+        "retrieval.documents.{i}.document.score",
+        "retrieval.documents.{i}.document.metadata",
+        "tool.name",
+        "tool.description",
+        "tool.parameters",
+        "session.id",
+        "user.id",
+        "metadata",
+        "tag.tags",
+    ];
+    const PUBLISHED_SPAN_KINDS: &[&str] = &[
+        "LLM",
+        "EMBEDDING",
+        "CHAIN",
+        "RETRIEVER",
+        "RERANKER",
+        "TOOL",
+        "AGENT",
+        "GUARDRAIL",
+        "EVALUATOR",
+    ];
+    const PUBLISHED_PROVIDERS: &[&str] = &[
+        "openai",
+        "anthropic",
+        "cohere",
+        "mistralai",
+        "google",
+        "azure",
+        "aws",
+    ];
+    const INT_ATTRIBUTES: &[&str] = &[
+        "llm.token_count.prompt",
+        "llm.token_count.completion",
+        "llm.token_count.total",
+    ];
+
+    fn published_key(key: &str) -> String {
+        key.split('.')
+            .map(|part| {
+                if part.chars().all(|c| c.is_ascii_digit()) {
+                    "{i}"
+                } else {
+                    part
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn span(name: &str, span_type: SpanType, attributes: Value) -> Span {
+        Span {
+            version: "0.1.0".to_string(),
+            span_id: span_id_to_uuid(&rand::random::<[u8; 8]>()),
+            trace_id: Uuid::new_v4(),
+            name: name.to_string(),
+            attributes,
+            span_type,
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            ..Default::default()
+        }
+    }
+
+    /// Spans of a run with a semantic search and a switch, and of an SDK trace with an LLM call
+    /// and a tool
+    fn spans() -> Vec<Span> {
+        let run_span = span("pipeline", SpanType::DEFAULT, json!({}));
+        let search = Message {
+            node_name: "search".to_string(),
+            node_type: "SemanticSearch".to_string(),
+            value: NodeInput::String("first\nsecond".to_string()),
+            meta_log: Some(MetaLog::Embedding(EmbeddingNodeMetaLog {
+                input_token_count: 5,
+                documents: vec![
+                    RetrievedDocument {
+                        content: "first".to_string(),
+                        score: 0.9,
+                        datasource_id: Uuid::new_v4().to_string(),
+                        data: HashMap::from([("page".to_string(), "1".to_string())]),
+                    },
+                    RetrievedDocument {
+                        content: "second".to_string(),
+                        score: 0.5,
+                        datasource_id: Uuid::new_v4().to_string(),
+                        data: HashMap::new(),
+                    },
+                ],
+            })),
+            ..Message::empty()
+        };
+        let switch = Message {
+            node_name: "switch".to_string(),
+            node_type: "Switch".to_string(),
+            input_message_ids: vec![search.id],
+            ..Message::empty()
+        };
+        let messages = HashMap::from([(search.id, search), (switch.id, switch)]);
+        let mut spans = Span::from_messages(&messages, run_span.trace_id, run_span.span_id);
+        spans.insert(0, run_span);
+
+        let mut llm = span(
+            "openai.chat",
+            SpanType::LLM,
+            json!({
+                GEN_AI_SYSTEM: "OpenAI",
+                GEN_AI_RESPONSE_MODEL: "gpt-4o-mini",
+                GEN_AI_INPUT_TOKENS: 10,
+                GEN_AI_OUTPUT_TOKENS: 3,
+            }),
+        );
+        llm.input = Some(json!([{"role": "user", "content": "Hi"}]));
+        llm.output = Some(json!("Hello"));
+        let tool = span(
+            "lookup",
+            SpanType::DEFAULT,
+            json!({TRACELOOP_SPAN_KIND: "tool", TRACELOOP_ENTITY_INPUT: "{}"}),
+        );
+        spans.extend([llm, tool]);
+        spans
+    }
+
+    fn trace() -> Trace {
+        serde_json::from_value(json!({
+            "id": Uuid::new_v4(),
+            "version": "0.1.0",
+            "userId": "user",
+            "sessionId": "session",
+        }))
+        .unwrap()
+    }
+
+    fn attributes(span: &OtelSpan) -> HashMap<String, any_value::Value> {
+        span.attributes
+            .iter()
+            .map(|kv| (kv.key.clone(), kv.value.clone().unwrap().value.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_openinference_export_conforms_to_conventions() {
+        let spans = spans();
+        let request = export_request(&trace(), &spans, ExportFormat::OpenInference);
+        let request =
+            ExportTraceServiceRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        let otel_spans = &request.resource_spans[0].scope_spans[0].spans;
+        assert_eq!(otel_spans.len(), spans.len());
+
+        let published = PUBLISHED_ATTRIBUTES.iter().copied().collect::<HashSet<_>>();
+        let mut kinds = HashMap::new();
+        for otel_span in otel_spans {
+            let attributes = attributes(otel_span);
+            for (key, value) in attributes.iter() {
+                let published_key = published_key(key);
+                assert!(
+                    published.contains(published_key.as_str()),
+                    "Unpublished attribute {key}"
+                );
+                match value {
+                    any_value::Value::IntValue(_) => {
+                        assert!(INT_ATTRIBUTES.contains(&published_key.as_str()), "{key}")
+                    }
+                    any_value::Value::DoubleValue(_) => {
+                        assert_eq!(published_key, "retrieval.documents.{i}.document.score")
+                    }
+                    any_value::Value::StringValue(_) => {
+                        assert!(!INT_ATTRIBUTES.contains(&published_key.as_str()), "{key}")
+                    }
+                    _ => panic!("Unexpected value of {key}"),
+                }
+            }
+            for key in [INPUT_MIME_TYPE, OUTPUT_MIME_TYPE] {
+                if let Some(any_value::Value::StringValue(mime_type)) = attributes.get(key) {
+                    assert!([MIME_TYPE_TEXT, MIME_TYPE_JSON].contains(&mime_type.as_str()));
+                }
+            }
+            if let Some(any_value::Value::StringValue(provider)) = attributes.get(LLM_PROVIDER) {
+                assert!(PUBLISHED_PROVIDERS.contains(&provider.as_str()));
+            }
+            let Some(any_value::Value::StringValue(kind)) = attributes.get(OPENINFERENCE_SPAN_KIND)
+            else {
+                panic!("Span {} has no kind", otel_span.name);
+            };
+            assert!(PUBLISHED_SPAN_KINDS.contains(&kind.as_str()));
+            assert_eq!(
+                attributes.get(SESSION_ID),
+                Some(&any_value::Value::StringValue("session".to_string()))
+            );
+            kinds.insert(otel_span.name.clone(), (kind.clone(), attributes));
+        }
+
+        assert_eq!(kinds["pipeline"].0, "CHAIN");
+        assert_eq!(kinds["switch"].0, "CHAIN");
+        assert_eq!(kinds["lookup"].0, "TOOL");
+        let (kind, search) = &kinds["search"];
+        assert_eq!(kind, "RETRIEVER");
+        assert_eq!(
+            search["retrieval.documents.1.document.content"],
+            any_value::Value::StringValue("second".to_string())
+        );
+        let (kind, llm) = &kinds["openai.chat"];
+        assert_eq!(kind, "LLM");
+        assert_eq!(llm[LLM_TOKEN_COUNT_TOTAL], any_value::Value::IntValue(13));
+        assert_eq!(
+            llm[LLM_PROVIDER],
+            any_value::Value::StringValue("openai".to_string())
+        );
+        assert_eq!(
+            llm["llm.input_messages.0.message.content"],
+            any_value::Value::StringValue("Hi".to_string())
+        );
+    }
+
+    #[test]
+    fn test_laminar_export_is_ingested_as_recorded() {
+        let spans = spans();
+        let request = export_request(&trace(), &spans, ExportFormat::Laminar);
+        let otel_spans = &request.resource_spans[0].scope_spans[0].spans;
+
+        // the SDK spans were ingested over OTLP, so they keep their ids
+        for (span, otel_span) in spans.iter().zip(otel_spans).skip(3) {
+            let ingested = Span::from_otel_span(otel_span.clone());
+            assert_eq!(ingested.span_id, span.span_id);
+            assert_eq!(ingested.trace_id, span.trace_id);
+            assert_eq!(ingested.name, span.name);
+            assert_eq!(ingested.span_type, span.span_type);
+        }
+        let tool = attributes(&otel_spans[4]);
+        assert_eq!(
+            tool[TRACELOOP_ENTITY_INPUT],
+            any_value::Value::StringValue("{}".to_string())
+        );
+        assert!(!tool.contains_key(OPENINFERENCE_SPAN_KIND));
+    }
+}
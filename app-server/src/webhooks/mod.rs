@@ -0,0 +1,451 @@
+//! Notifications of finished API runs, sent to webhooks configured per pipeline
+//!
+//! Payloads are JSON, signed with the webhook secret in the `Laminar-Signature` header as
+//! `t=<unix timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`. Deliveries are retried
+//! with backoff, and webhooks are disabled after `MAX_CONSECUTIVE_FAILURES` failed deliveries
+//! in a row.
+
+use std::{env, future::Future, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    db::{
+        self,
+        runs::{Run, RunStatus},
+        webhooks::WebhookEndpoint,
+        DB,
+    },
+    secrets,
+};
+
+/// Version of the payload format, bumped on incompatible changes
+pub const PAYLOAD_VERSION: u32 = 1;
+const SIGNATURE_HEADER: &str = "Laminar-Signature";
+const EVENT_TYPE_HEADER: &str = "Laminar-Event";
+const DELIVERY_ID_HEADER: &str = "Laminar-Delivery";
+const DELIVERY_ATTEMPTS: u32 = 4;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONSECUTIVE_FAILURES: i32 = 10;
+const DEFAULT_MAX_OUTPUT_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum WebhookEventType {
+    #[serde(rename = "run.succeeded")]
+    RunSucceeded,
+    #[serde(rename = "run.failed")]
+    RunFailed,
+    #[serde(rename = "run.cancelled")]
+    RunCancelled,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::RunSucceeded => "run.succeeded",
+            WebhookEventType::RunFailed => "run.failed",
+            WebhookEventType::RunCancelled => "run.cancelled",
+        }
+    }
+
+    fn from_run_status(status: RunStatus) -> Option<Self> {
+        match status {
+            RunStatus::Succeeded => Some(WebhookEventType::RunSucceeded),
+            RunStatus::Failed | RunStatus::Interrupted => Some(WebhookEventType::RunFailed),
+            RunStatus::Cancelled => Some(WebhookEventType::RunCancelled),
+            RunStatus::Queued | RunStatus::Running => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RunUsage {
+    total_token_count: i64,
+    approximate_cost: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RunEventData {
+    run_id: Uuid,
+    pipeline_id: Uuid,
+    pipeline_version_id: Uuid,
+    status: RunStatus,
+    metadata: Value,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    duration_ms: Option<i64>,
+    usage: RunUsage,
+    error: Option<String>,
+    /// Only for webhooks with `includeOutput`, and if it's not larger than
+    /// `WEBHOOK_MAX_OUTPUT_SIZE` bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outputs: Option<Value>,
+    /// Set if outputs were requested, but left out because of their size
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    outputs_truncated: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    version: u32,
+    /// Delivery id, same for all attempts of a delivery
+    id: Uuid,
+    #[serde(rename = "type")]
+    event_type: WebhookEventType,
+    created_at: DateTime<Utc>,
+    data: &'a RunEventData,
+}
+
+fn max_output_size() -> usize {
+    env::var("WEBHOOK_MAX_OUTPUT_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_OUTPUT_SIZE)
+}
+
+/// Random secret for webhooks created without one
+pub fn generate_secret() -> Result<String> {
+    let mut secret = [0u8; 24];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| anyhow::anyhow!("Failed to generate webhook secret"))?;
+    Ok(format!("whsec_{}", hex::encode(secret)))
+}
+
+/// Name under which the secret is encrypted, binds the ciphertext to the webhook
+pub fn secret_name(webhook_id: &Uuid) -> String {
+    format!("webhook:{}", webhook_id)
+}
+
+fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(timestamp.to_string().as_bytes());
+    ctx.update(b".");
+    ctx.update(body);
+    hex::encode(ctx.sign().as_ref())
+}
+
+/// Notify webhooks of the run's pipeline, if the run is finished
+///
+/// Meant to be spawned once the run's result is written, deliveries are sent concurrently with
+/// the shared HTTP client, see `http_client`.
+pub async fn notify_run_finished(
+    db: DB,
+    http_client: reqwest::Client,
+    run_id: Uuid,
+    project_id: Uuid,
+) {
+    let run = match db::runs::get_run(&db.pool, &run_id, &project_id).await {
+        Ok(Some(run)) => run,
+        Ok(None) => return,
+        Err(e) => {
+            log::error!("Failed to get run {} for webhooks: {}", run_id, e);
+            return;
+        }
+    };
+    let Some(event_type) = WebhookEventType::from_run_status(run.status) else {
+        return;
+    };
+
+    let endpoints =
+        match db::webhooks::get_webhook_endpoints(&db.pool, &run.pipeline_id, event_type.as_str())
+            .await
+        {
+            Ok(endpoints) => endpoints,
+            Err(e) => {
+                log::error!(
+                    "Failed to get webhooks of pipeline {}: {}",
+                    run.pipeline_id,
+                    e
+                );
+                return;
+            }
+        };
+    if endpoints.is_empty() {
+        return;
+    }
+
+    let data_without_outputs = run_event_data(&run, false);
+    let data_with_outputs = endpoints
+        .iter()
+        .any(|endpoint| endpoint.include_output)
+        .then(|| run_event_data(&run, true));
+
+    let deliveries = endpoints.iter().map(|endpoint| {
+        let data = match &data_with_outputs {
+            Some(data) if endpoint.include_output => data,
+            _ => &data_without_outputs,
+        };
+        deliver(&db, &http_client, &SystemClock, endpoint, event_type, data)
+    });
+    futures_util::future::join_all(deliveries).await;
+}
+
+fn run_event_data(run: &Run, include_output: bool) -> RunEventData {
+    let (outputs, outputs_truncated) = match (&run.outputs, include_output) {
+        (Some(outputs), true) => {
+            let size = serde_json::to_vec(outputs).map(|o| o.len()).unwrap_or(0);
+            if size <= max_output_size() {
+                (Some(outputs.clone()), false)
+            } else {
+                (None, true)
+            }
+        }
+        _ => (None, false),
+    };
+
+    RunEventData {
+        run_id: run.id,
+        pipeline_id: run.pipeline_id,
+        pipeline_version_id: run.pipeline_version_id,
+        status: run.status,
+        metadata: run.metadata.clone(),
+        started_at: run.started_at,
+        finished_at: run.finished_at,
+        duration_ms: run
+            .started_at
+            .zip(run.finished_at)
+            .map(|(started_at, finished_at)| (finished_at - started_at).num_milliseconds()),
+        usage: RunUsage {
+            total_token_count: run.total_token_count,
+            approximate_cost: run.approximate_cost,
+        },
+        error: run.error.clone(),
+        outputs,
+        outputs_truncated,
+    }
+}
+
+async fn deliver(
+    db: &DB,
+    http_client: &reqwest::Client,
+    clock: &dyn Clock,
+    endpoint: &WebhookEndpoint,
+    event_type: WebhookEventType,
+    data: &RunEventData,
+) {
+    let res = send_with_retries(http_client, clock, endpoint, event_type, data).await;
+
+    let recorded = match res {
+        Ok(()) => db::webhooks::record_webhook_delivery(&db.pool, &endpoint.id).await,
+        Err(e) => {
+            log::warn!("Failed to deliver webhook {}: {}", endpoint.id, e);
+            match db::webhooks::record_webhook_failure(
+                &db.pool,
+                &endpoint.id,
+                &e.to_string(),
+                MAX_CONSECUTIVE_FAILURES,
+            )
+            .await
+            {
+                Ok(true) => {
+                    log::warn!(
+                        "Disabled webhook {} after {} failed deliveries",
+                        endpoint.id,
+                        MAX_CONSECUTIVE_FAILURES
+                    );
+                    Ok(())
+                }
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+    };
+    if let Err(e) = recorded {
+        log::error!(
+            "Failed to record delivery of webhook {}: {}",
+            endpoint.id,
+            e
+        );
+    }
+}
+
+async fn send_with_retries(
+    http_client: &reqwest::Client,
+    clock: &dyn Clock,
+    endpoint: &WebhookEndpoint,
+    event_type: WebhookEventType,
+    data: &RunEventData,
+) -> Result<()> {
+    let secret = secrets::decrypt(
+        &secret_name(&endpoint.id),
+        &endpoint.secret_nonce,
+        &endpoint.secret_value,
+    )?;
+    let payload = WebhookPayload {
+        version: PAYLOAD_VERSION,
+        id: Uuid::new_v4(),
+        event_type,
+        created_at: clock.now(),
+        data,
+    };
+    let body = serde_json::to_vec(&payload)?;
+
+    with_retries(clock, || {
+        send(http_client, clock, endpoint, &secret, &payload, &body)
+    })
+    .await
+}
+
+/// Attempt until an attempt succeeds, fails with an error which isn't retryable, or
+/// `DELIVERY_ATTEMPTS` attempts failed, sleeping on the clock between attempts
+async fn with_retries<F, Fut>(clock: &dyn Clock, mut attempt: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), (anyhow::Error, bool)>>,
+{
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempts = 1;
+    loop {
+        match attempt().await {
+            Ok(()) => return Ok(()),
+            Err((e, retryable)) => {
+                if !retryable || attempts >= DELIVERY_ATTEMPTS {
+                    return Err(e);
+                }
+            }
+        }
+        clock.sleep(delay).await;
+        delay *= 4;
+        attempts += 1;
+    }
+}
+
+/// Returns the error, and whether the delivery should be retried
+async fn send(
+    http_client: &reqwest::Client,
+    clock: &dyn Clock,
+    endpoint: &WebhookEndpoint,
+    secret: &str,
+    payload: &WebhookPayload<'_>,
+    body: &[u8],
+) -> Result<(), (anyhow::Error, bool)> {
+    // Signed per attempt, so that receivers can reject old timestamps
+    let timestamp = clock.now().timestamp();
+    let signature = format!("t={},v1={}", timestamp, sign(secret, timestamp, body));
+
+    let res = http_client
+        .post(&endpoint.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(EVENT_TYPE_HEADER, payload.event_type.as_str())
+        .header(DELIVERY_ID_HEADER, payload.id.to_string())
+        .body(body.to_vec())
+        .send()
+        .await
+        .map_err(|e| (anyhow::Error::from(e), true))?;
+
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    // Other client errors mean the receiver rejected the payload, retrying won't help
+    let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    Err((
+        anyhow::anyhow!("Webhook responded with status {}", status),
+        retryable,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use crate::clock::ManualClock;
+
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // echo -n '1700000000.{"a":1}' | openssl dgst -sha256 -hmac whsec_test
+        assert_eq!(
+            sign("whsec_test", 1700000000, br#"{"a":1}"#),
+            "38877139021993b830af32feea6e18a8da83eb2f6e49ee50bd9e4cf4ca4d3789"
+        );
+        assert_ne!(
+            sign("whsec_test", 1700000001, br#"{"a":1}"#),
+            sign("whsec_test", 1700000000, br#"{"a":1}"#)
+        );
+    }
+
+    #[test]
+    fn test_event_type_serialization() {
+        assert_eq!(
+            serde_json::to_value(WebhookEventType::RunSucceeded).unwrap(),
+            serde_json::json!(WebhookEventType::RunSucceeded.as_str())
+        );
+        assert_eq!(
+            serde_json::from_value::<WebhookEventType>(serde_json::json!("run.cancelled")).unwrap(),
+            WebhookEventType::RunCancelled
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_back_off() {
+        let clock = ManualClock::default();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let delivery = {
+            let clock = clock.clone();
+            let attempts = attempts.clone();
+            tokio::spawn(async move {
+                with_retries(&clock, || {
+                    let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move {
+                        if attempt < DELIVERY_ATTEMPTS {
+                            Err((anyhow::anyhow!("503"), true))
+                        } else {
+                            Ok(())
+                        }
+                    }
+                })
+                .await
+            })
+        };
+
+        // 1s, 4s and 16s between the attempts
+        for (attempt, delay) in [(1, 1), (2, 4), (3, 16)] {
+            clock.wait_for_sleepers(1).await;
+            assert_eq!(attempts.load(Ordering::SeqCst), attempt);
+            clock.advance(Duration::from_secs(delay - 1));
+            tokio::task::yield_now().await;
+            assert_eq!(attempts.load(Ordering::SeqCst), attempt);
+            clock.advance(Duration::from_secs(1));
+        }
+        delivery.await.unwrap().unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), DELIVERY_ATTEMPTS);
+        assert_eq!(clock.now().timestamp(), 21);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let clock = ManualClock::default();
+        let mut attempts = 0;
+        let res = with_retries(&clock, || {
+            attempts += 1;
+            async { Err((anyhow::anyhow!("400"), false)) }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+        assert_eq!(clock.now().timestamp(), 0);
+    }
+}
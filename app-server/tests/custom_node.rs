@@ -0,0 +1,188 @@
+//! Custom node types, registered the way a deployment embedding the server does at startup
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Once},
+};
+
+use app_server::{
+    engine::{Engine, Input, NodeError, NodeImpl, RunOutput},
+    pipeline::{
+        context::Context,
+        nodes::{
+            registry::{self, NodeRegistry},
+            utils::map_handles,
+            Handle, NodeInput,
+        },
+        utils::parse_graph,
+        validation::{validate_graph, GraphDiagnostic},
+        Graph, RunType,
+    },
+    testing::OfflineServices,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Keeps the `top` longest lines of its input
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RankerNode {
+    id: Uuid,
+    name: String,
+    inputs: Vec<Handle>,
+    outputs: Vec<Handle>,
+    inputs_mappings: HashMap<Uuid, Uuid>,
+    top: usize,
+}
+
+#[async_trait]
+impl NodeImpl for RankerNode {
+    fn handles_mapping(&self) -> Vec<(Uuid, Handle)> {
+        map_handles(&self.inputs, &self.inputs_mappings)
+    }
+
+    fn output_handle_id(&self) -> Uuid {
+        self.outputs.first().unwrap().id
+    }
+
+    fn node_name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn node_id(&self) -> Uuid {
+        self.id
+    }
+
+    fn node_type(&self) -> String {
+        "Ranker".to_string()
+    }
+
+    fn validate_config(&self) -> Vec<GraphDiagnostic> {
+        if self.top == 0 {
+            vec![GraphDiagnostic::of_config(
+                self.id,
+                &self.name,
+                "Ranker must keep at least one line".to_string(),
+            )]
+        } else {
+            Vec::new()
+        }
+    }
+
+    async fn run(&self, input: Input, _context: Arc<Context>) -> Result<RunOutput, NodeError> {
+        let text = input.get_text("documents")?;
+        let mut lines = text.lines().collect::<Vec<_>>();
+        lines.sort_by_key(|line| std::cmp::Reverse(line.len()));
+        let ranked = lines.into_iter().take(self.top).map(String::from).collect();
+        Ok(RunOutput::Success((NodeInput::StringList(ranked), None)))
+    }
+}
+
+fn install_ranker() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let mut registry = NodeRegistry::with_builtins();
+        registry.register::<RankerNode>("Ranker").unwrap();
+        registry::install(registry);
+    });
+}
+
+fn handle(id: Uuid, name: &str, handle_type: &str) -> Value {
+    json!({"id": id, "name": name, "type": handle_type})
+}
+
+/// Input of documents, ranked by `node_type`, into an output
+fn ranker_graph(node_type: &str, top: usize) -> Value {
+    let (input_id, input_output) = (Uuid::new_v4(), Uuid::new_v4());
+    let (ranker_id, ranker_input, ranker_output) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let (output_id, output_input) = (Uuid::new_v4(), Uuid::new_v4());
+    json!({
+        "nodes": {
+            "documents": {
+                "type": "Input",
+                "id": input_id,
+                "name": "documents",
+                "outputs": [handle(input_output, "output", "String")],
+                "inputType": "String",
+            },
+            "ranker": {
+                "type": node_type,
+                "id": ranker_id,
+                "name": "ranker",
+                "inputs": [handle(ranker_input, "documents", "String")],
+                "outputs": [handle(ranker_output, "output", "StringList")],
+                "inputsMappings": {ranker_input.to_string(): input_output},
+                "top": top,
+            },
+            "answer": {
+                "type": "Output",
+                "id": output_id,
+                "name": "answer",
+                "inputs": [handle(output_input, "output", "StringList")],
+                "inputsMappings": {output_input.to_string(): ranker_output},
+            },
+        },
+        "pred": {
+            ranker_id.to_string(): [input_id],
+            output_id.to_string(): [ranker_id],
+        },
+    })
+}
+
+#[tokio::test]
+async fn test_custom_node_runs_in_graph() {
+    install_ranker();
+    let mut graph = serde_json::from_value::<Graph>(ranker_graph("Ranker", 2)).unwrap();
+    assert!(validate_graph(&graph).is_empty());
+
+    let inputs = HashMap::from([(
+        "documents".to_string(),
+        NodeInput::String("a\nlongest\nlonger".to_string()),
+    )]);
+    graph
+        .setup(
+            &inputs,
+            &HashMap::new(),
+            &HashMap::new(),
+            &RunType::Workshop,
+        )
+        .unwrap();
+    let services = OfflineServices::default();
+    let mut engine = Engine::with_tasks_and_context(
+        parse_graph(graph).unwrap(),
+        services.context(),
+        None,
+        None,
+        None,
+    );
+    let outputs = engine.run(None, None, vec![]).await.unwrap();
+
+    assert_eq!(
+        outputs.output_values()["answer"],
+        NodeInput::StringList(vec!["longest".to_string(), "longer".to_string()])
+    );
+}
+
+#[test]
+fn test_custom_node_config_is_validated() {
+    install_ranker();
+    let graph = serde_json::from_value::<Graph>(ranker_graph("Ranker", 0)).unwrap();
+    let diagnostics = validate_graph(&graph);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].node_name.as_deref(), Some("ranker"));
+
+    // the custom node is serialized as it was loaded
+    let node = serde_json::to_value(&graph.nodes["ranker"]).unwrap();
+    assert_eq!(node["type"], "Ranker");
+    assert_eq!(node["top"], 0);
+
+    let e = serde_json::from_value::<Graph>(ranker_graph("Reranker", 2)).unwrap_err();
+    assert!(e.to_string().contains("Unknown node type Reranker"));
+
+    let mut registry = NodeRegistry::with_builtins();
+    assert!(registry.register::<RankerNode>("LLM").is_err());
+    assert!(registry::registry().node_types().contains(&"Ranker"));
+}
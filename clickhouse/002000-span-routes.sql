@@ -0,0 +1,8 @@
+--
+-- Route a router node, e.g. a switch, took, on the span of its execution, with the node and
+-- pipeline, to count the executions of each route.
+--
+
+ALTER TABLE spans ADD COLUMN pipeline_id UUID DEFAULT toUUID('00000000-0000-0000-0000-000000000000');
+ALTER TABLE spans ADD COLUMN node_id UUID DEFAULT toUUID('00000000-0000-0000-0000-000000000000');
+ALTER TABLE spans ADD COLUMN route LowCardinality(String) DEFAULT '<null>';
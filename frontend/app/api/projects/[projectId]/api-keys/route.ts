@@ -40,12 +40,10 @@ export async function DELETE(req: NextRequest, { params }: { params: { projectId
 
   const body = await req.json()
 
-  return await fetch(`${process.env.BACKEND_URL}/api/v1/projects/${projectId}/api-keys`, {
+  return await fetch(`${process.env.BACKEND_URL}/api/v1/projects/${projectId}/api-keys/${body.apiKeyId}`, {
     method: 'DELETE',
     headers: {
-      'Content-Type': 'application/json',
       Authorization: `Bearer ${user.apiKey}`
     },
-    body: JSON.stringify(body)
   });
 }
\ No newline at end of file
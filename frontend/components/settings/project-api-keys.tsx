@@ -1,4 +1,4 @@
-import { ProjectApiKey } from "@/lib/api-keys/types"
+import { CreatedProjectApiKey, ProjectApiKey } from "@/lib/api-keys/types"
 import { Button } from "../ui/button"
 import {
   Dialog,
@@ -26,6 +26,7 @@ export default function ProjectApiKeys({ apiKeys }: ApiKeysProps) {
   const [isGenerateKeyDialogOpen, setIsGenerateKeyDialogOpen] = useState(false);
   const [projectApiKeys, setProjectApiKeys] = useState<ProjectApiKey[]>(apiKeys)
   const [newApiKeyName, setNewApiKeyName] = useState<string>('')
+  const [createdApiKey, setCreatedApiKey] = useState<CreatedProjectApiKey | null>(null)
   const { projectId } = useProjectContext()
   const { toast } = useToast();
 
@@ -34,15 +35,16 @@ export default function ProjectApiKeys({ apiKeys }: ApiKeysProps) {
       method: 'POST',
       body: JSON.stringify({ name: newName })
     });
-    await res.json()
+    const apiKey = await res.json() as CreatedProjectApiKey
+    setCreatedApiKey(apiKey)
 
     getProjectApiKeys()
   }, [])
 
-  const deleteApiKey = useCallback(async (apiKeyVal: string) => {
+  const deleteApiKey = useCallback(async (apiKeyId: string) => {
     const res = await fetch(`/api/projects/${projectId}/api-keys`, {
       method: 'DELETE',
-      body: JSON.stringify({ apiKey: apiKeyVal })
+      body: JSON.stringify({ apiKeyId })
     });
     await res.text()
 
@@ -99,6 +101,30 @@ export default function ProjectApiKeys({ apiKeys }: ApiKeysProps) {
             </DialogFooter>
           </DialogContent>
         </Dialog>
+        <Dialog open={createdApiKey !== null} onOpenChange={() => setCreatedApiKey(null)}>
+          <DialogContent className="sm:max-w-[425px]">
+            <DialogHeader>
+              <DialogTitle>API key created</DialogTitle>
+            </DialogHeader>
+            <Label>
+              Copy the key now, it will not be shown again.
+            </Label>
+            <div className="flex items-center">
+              <Input className="font-mono text-xs" value={createdApiKey?.value ?? ''} readOnly />
+              <button
+                className="ml-2 text-gray-400"
+                onClick={() => {
+                  navigator.clipboard.writeText(createdApiKey?.value ?? '')
+                  toast({
+                    title: 'API key copied to clipboard'
+                  })
+                }}
+              >
+                <Copy className="h-4" />
+              </button>
+            </div>
+          </DialogContent>
+        </Dialog>
         <table className="w-1/2 border-t">
           <tbody>
             {
@@ -106,23 +132,12 @@ export default function ProjectApiKeys({ apiKeys }: ApiKeysProps) {
                 <tr className="border-b h-14" key={id}>
                   <td className="">{apiKey.name}</td>
                   <td className="ml-4 text-[16px] font-mono text-xs">
-                    <div>{apiKey.value.slice(0, 4)} ... {apiKey.value.slice(-4)}</div>
+                    <div>{apiKey.shorthand}</div>
                   </td>
+                  <td className="text-xs text-gray-500">{apiKey.scopes.join(', ')}</td>
                   <td>
                     <div className="flex justify-end">
-                      <button
-                        className="mr-4 text-gray-400"
-                        onClick={() => {
-                          // copy api key to clipboard
-                          navigator.clipboard.writeText(apiKey.value)
-                          toast({
-                            title: 'API key copied to clipboard'
-                          })
-                        }}
-                      >
-                        <Copy className="h-4" />
-                      </button>
-                      <RevokeDialog obj={apiKey} onRevoke={deleteApiKey} entity="API key" />
+                      <RevokeDialog obj={{ name: apiKey.name, value: apiKey.id }} onRevoke={deleteApiKey} entity="API key" />
                     </div>
                   </td>
                 </tr>
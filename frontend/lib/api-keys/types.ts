@@ -1,5 +1,18 @@
+export type ApiKeyScope = 'run' | 'read_traces' | 'admin'
+
 export type ProjectApiKey = {
-    value: string
+    id: string
     projectId: string
     name?: string
-}
\ No newline at end of file
+    shorthand: string
+    scopes: ApiKeyScope[]
+    pipelineIds: string[] | null
+    requestsPerMinute: number | null
+    tokensPerDay: number | null
+    lastUsedAt: string | null
+}
+
+/** Returned only once on creation, the key value is not stored */
+export type CreatedProjectApiKey = ProjectApiKey & {
+    value: string
+}
@@ -0,0 +1,18 @@
+--
+-- Content hashes identify immutable (COMMIT) pipeline versions, so that run requests
+-- can pin the exact graph they execute.
+--
+
+ALTER TABLE public.pipeline_versions ADD COLUMN content_hash text;
+
+CREATE UNIQUE INDEX pipeline_versions_pipeline_id_content_hash_idx
+    ON public.pipeline_versions USING btree (pipeline_id, content_hash)
+    WHERE (pipeline_type = 'COMMIT'::text AND content_hash IS NOT NULL);
+
+--
+-- Run parent spans record the executed pipeline version in their attributes.
+-- The index backs the check that refuses to delete versions referenced by runs.
+--
+
+CREATE INDEX spans_pipeline_version_id_idx
+    ON public.spans USING btree (((attributes ->> 'lmnr.pipeline.version_id'::text)));
@@ -0,0 +1,26 @@
+--
+-- Project secrets, referenced from node configs and run env as {{secret:NAME}}.
+-- Values are encrypted by app-server with SECRETS_ENCRYPTION_KEY (AES-256-GCM).
+--
+
+CREATE TABLE public.project_secrets (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    project_id uuid NOT NULL,
+    name text NOT NULL,
+    value text NOT NULL,
+    nonce text NOT NULL
+);
+
+ALTER TABLE public.project_secrets OWNER TO postgres;
+
+ALTER TABLE ONLY public.project_secrets
+    ADD CONSTRAINT project_secrets_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.project_secrets
+    ADD CONSTRAINT project_secrets_project_id_name_key UNIQUE (project_id, name);
+
+ALTER TABLE ONLY public.project_secrets
+    ADD CONSTRAINT project_secrets_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.project_secrets TO service_role;
@@ -0,0 +1,50 @@
+--
+-- Project API keys are stored as sha256 hashes with a display shorthand, and carry
+-- scopes, optional pipeline restrictions and quotas. Existing keys keep full access.
+--
+
+ALTER TABLE public.project_api_keys ADD COLUMN id uuid DEFAULT gen_random_uuid() NOT NULL;
+ALTER TABLE public.project_api_keys ADD COLUMN hash text;
+ALTER TABLE public.project_api_keys ADD COLUMN shorthand text DEFAULT ''::text NOT NULL;
+ALTER TABLE public.project_api_keys ADD COLUMN scopes text[] DEFAULT '{admin}'::text[] NOT NULL;
+ALTER TABLE public.project_api_keys ADD COLUMN pipeline_ids uuid[];
+ALTER TABLE public.project_api_keys ADD COLUMN requests_per_minute bigint;
+ALTER TABLE public.project_api_keys ADD COLUMN tokens_per_day bigint;
+ALTER TABLE public.project_api_keys ADD COLUMN last_used_at timestamp with time zone;
+
+COMMENT ON COLUMN public.project_api_keys.pipeline_ids IS 'Pipelines the key can run, NULL means all pipelines of the project';
+
+UPDATE public.project_api_keys SET
+    hash = encode(sha256(convert_to(value, 'UTF8')), 'hex'),
+    shorthand = left(value, 4) || '...' || right(value, 4);
+
+ALTER TABLE ONLY public.project_api_keys DROP CONSTRAINT project_api_keys_pkey;
+ALTER TABLE public.project_api_keys DROP COLUMN value;
+ALTER TABLE public.project_api_keys ALTER COLUMN hash SET NOT NULL;
+
+ALTER TABLE ONLY public.project_api_keys
+    ADD CONSTRAINT project_api_keys_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.project_api_keys
+    ADD CONSTRAINT project_api_keys_hash_key UNIQUE (hash);
+
+--
+-- Daily usage per API key
+--
+
+CREATE TABLE public.project_api_key_usage (
+    api_key_id uuid NOT NULL,
+    date date DEFAULT CURRENT_DATE NOT NULL,
+    request_count bigint DEFAULT '0'::bigint NOT NULL,
+    token_count bigint DEFAULT '0'::bigint NOT NULL
+);
+
+ALTER TABLE public.project_api_key_usage OWNER TO postgres;
+
+ALTER TABLE ONLY public.project_api_key_usage
+    ADD CONSTRAINT project_api_key_usage_pkey PRIMARY KEY (api_key_id, date);
+
+ALTER TABLE ONLY public.project_api_key_usage
+    ADD CONSTRAINT project_api_key_usage_api_key_id_fkey FOREIGN KEY (api_key_id) REFERENCES public.project_api_keys(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.project_api_key_usage TO service_role;
@@ -0,0 +1,48 @@
+--
+-- Pipeline runs submitted through the API, in sync or async mode.
+-- Outputs are cleared by app-server after RUN_RESULT_TTL_SECONDS, the run summary is kept.
+--
+
+CREATE TYPE public.run_status AS ENUM (
+    'Queued',
+    'Running',
+    'Succeeded',
+    'Failed',
+    'Cancelled'
+);
+
+ALTER TYPE public.run_status OWNER TO postgres;
+
+CREATE TABLE public.runs (
+    id uuid NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    project_id uuid NOT NULL,
+    pipeline_id uuid NOT NULL,
+    pipeline_version_id uuid NOT NULL,
+    status public.run_status DEFAULT 'Queued'::public.run_status NOT NULL,
+    trace_id uuid NOT NULL,
+    metadata jsonb DEFAULT '{}'::jsonb NOT NULL,
+    outputs jsonb,
+    node_stats jsonb,
+    error text,
+    total_token_count bigint DEFAULT '0'::bigint NOT NULL,
+    approximate_cost double precision,
+    started_at timestamp with time zone,
+    finished_at timestamp with time zone,
+    result_expires_at timestamp with time zone
+);
+
+ALTER TABLE public.runs OWNER TO postgres;
+
+ALTER TABLE ONLY public.runs
+    ADD CONSTRAINT runs_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.runs
+    ADD CONSTRAINT runs_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.runs
+    ADD CONSTRAINT runs_pipeline_id_fkey FOREIGN KEY (pipeline_id) REFERENCES public.pipelines(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE INDEX runs_result_expires_at_idx ON public.runs USING btree (result_expires_at) WHERE outputs IS NOT NULL;
+
+GRANT ALL ON TABLE public.runs TO service_role;
@@ -0,0 +1,30 @@
+--
+-- Idempotency keys of run requests, scoped per API key.
+-- Rows are removed by app-server once they expire, after IDEMPOTENCY_KEY_TTL_SECONDS.
+--
+
+CREATE TABLE public.idempotency_keys (
+    api_key_id uuid NOT NULL,
+    key text NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    expires_at timestamp with time zone NOT NULL,
+    request_hash text NOT NULL,
+    run_id uuid,
+    response_status smallint,
+    response jsonb
+);
+
+ALTER TABLE public.idempotency_keys OWNER TO postgres;
+
+COMMENT ON COLUMN public.idempotency_keys.request_hash IS 'Hash of the request, reusing the key for a different request is rejected';
+COMMENT ON COLUMN public.idempotency_keys.response IS 'NULL while the first request with the key is in progress';
+
+ALTER TABLE ONLY public.idempotency_keys
+    ADD CONSTRAINT idempotency_keys_pkey PRIMARY KEY (api_key_id, key);
+
+ALTER TABLE ONLY public.idempotency_keys
+    ADD CONSTRAINT idempotency_keys_api_key_id_fkey FOREIGN KEY (api_key_id) REFERENCES public.project_api_keys(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE INDEX idempotency_keys_expires_at_idx ON public.idempotency_keys USING btree (expires_at);
+
+GRANT ALL ON TABLE public.idempotency_keys TO service_role;
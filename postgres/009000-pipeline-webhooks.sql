@@ -0,0 +1,35 @@
+--
+-- Webhooks notified when API runs of the pipeline finish.
+-- The signing secret is encrypted with SECRETS_ENCRYPTION_KEY, same as project secrets.
+--
+
+CREATE TABLE public.pipeline_webhooks (
+    id uuid NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    pipeline_id uuid NOT NULL,
+    url text NOT NULL,
+    secret_nonce text NOT NULL,
+    secret_value text NOT NULL,
+    event_types text[] NOT NULL,
+    include_output boolean DEFAULT false NOT NULL,
+    consecutive_failures integer DEFAULT 0 NOT NULL,
+    last_error text,
+    last_delivered_at timestamp with time zone,
+    last_failed_at timestamp with time zone,
+    disabled_at timestamp with time zone
+);
+
+ALTER TABLE public.pipeline_webhooks OWNER TO postgres;
+
+COMMENT ON COLUMN public.pipeline_webhooks.consecutive_failures IS 'Deliveries which failed after all retries since the last successful one';
+COMMENT ON COLUMN public.pipeline_webhooks.disabled_at IS 'Set once consecutive_failures reaches the limit, no events are sent until the webhook is re-enabled';
+
+ALTER TABLE ONLY public.pipeline_webhooks
+    ADD CONSTRAINT pipeline_webhooks_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.pipeline_webhooks
+    ADD CONSTRAINT pipeline_webhooks_pipeline_id_fkey FOREIGN KEY (pipeline_id) REFERENCES public.pipelines(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE INDEX pipeline_webhooks_pipeline_id_idx ON public.pipeline_webhooks USING btree (pipeline_id);
+
+GRANT ALL ON TABLE public.pipeline_webhooks TO service_role;
@@ -0,0 +1,9 @@
+--
+-- Runs executed by workers pulling them from the run queue, see RUN_EXECUTION_MODE.
+-- Runs are retried when their worker stops before finishing them, and are interrupted once
+-- they have been started RUN_QUEUE_MAX_ATTEMPTS times.
+--
+
+ALTER TYPE public.run_status ADD VALUE 'Interrupted';
+
+ALTER TABLE public.runs ADD COLUMN attempts integer DEFAULT 0 NOT NULL;
@@ -0,0 +1,6 @@
+--
+-- Column types of dataset rows, inferred from the first imported rows or set explicitly.
+-- Types are one of string, number, boolean or json.
+--
+
+ALTER TABLE public.datasets ADD COLUMN schema jsonb DEFAULT '{"columns": []}'::jsonb NOT NULL;
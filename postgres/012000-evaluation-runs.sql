@@ -0,0 +1,11 @@
+--
+-- Evaluations run by the server, which execute a pipeline version on the rows of a dataset and
+-- score its outputs with the configured evaluators. Evaluations without config only store the
+-- results uploaded by clients.
+--
+
+ALTER TABLE public.evaluations ADD COLUMN config jsonb;
+ALTER TABLE public.evaluations ADD COLUMN stats jsonb;
+
+ALTER TABLE public.evaluation_results ADD COLUMN datapoint_id uuid;
+ALTER TABLE public.evaluation_results ADD COLUMN passed boolean;
@@ -0,0 +1,30 @@
+--
+-- Inputs and outputs of the nodes of runs, recorded if the pipeline has record_node_io set or
+-- the run request has `recordNodeIo`. Records are gzip-compressed JSON with secrets scrubbed.
+-- Rows are removed by app-server once they expire, after the log retention of the project.
+--
+
+ALTER TABLE public.pipelines ADD COLUMN record_node_io boolean DEFAULT false NOT NULL;
+
+CREATE TABLE public.run_node_io (
+    run_id uuid NOT NULL,
+    node_id uuid NOT NULL,
+    execution integer NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    expires_at timestamp with time zone NOT NULL,
+    data bytea NOT NULL
+);
+
+ALTER TABLE public.run_node_io OWNER TO postgres;
+
+COMMENT ON COLUMN public.run_node_io.execution IS 'Index of the node''s execution in the run, nodes in cycles run more than once';
+
+ALTER TABLE ONLY public.run_node_io
+    ADD CONSTRAINT run_node_io_pkey PRIMARY KEY (run_id, node_id, execution);
+
+ALTER TABLE ONLY public.run_node_io
+    ADD CONSTRAINT run_node_io_run_id_fkey FOREIGN KEY (run_id) REFERENCES public.runs(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE INDEX run_node_io_expires_at_idx ON public.run_node_io USING btree (expires_at);
+
+GRANT ALL ON TABLE public.run_node_io TO service_role;
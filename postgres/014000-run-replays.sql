@@ -0,0 +1,12 @@
+--
+-- Replays of runs, which re-execute some nodes of the run with modified configs while the other
+-- nodes are seeded from the run's recorded node I/O. A replay is a run of its own, with its own
+-- trace and node I/O, linked to the run it replays.
+--
+
+ALTER TABLE public.runs ADD COLUMN replay_of uuid;
+
+ALTER TABLE ONLY public.runs
+    ADD CONSTRAINT runs_replay_of_fkey FOREIGN KEY (replay_of) REFERENCES public.runs(id) ON UPDATE CASCADE ON DELETE SET NULL;
+
+CREATE INDEX runs_replay_of_idx ON public.runs USING btree (replay_of) WHERE replay_of IS NOT NULL;
@@ -0,0 +1,45 @@
+--
+-- Checkpoints of runs which can be paused, e.g. workshop runs with breakpoints, so that a paused
+-- run can be resumed by any app-server instance. Each task of the run has the latest snapshot of
+-- its state, written as its predecessors and the task finish. The generation is incremented when
+-- an instance claims the paused run, and writes of older generations are ignored.
+--
+
+CREATE TABLE public.run_checkpoints (
+    run_id uuid NOT NULL,
+    graph_hash text NOT NULL,
+    status text DEFAULT 'running'::text NOT NULL,
+    paused_task_id uuid,
+    generation integer DEFAULT 0 NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.run_checkpoints OWNER TO postgres;
+
+COMMENT ON COLUMN public.run_checkpoints.status IS 'running, paused or completed';
+
+ALTER TABLE ONLY public.run_checkpoints
+    ADD CONSTRAINT run_checkpoints_pkey PRIMARY KEY (run_id);
+
+CREATE INDEX run_checkpoints_completed_idx ON public.run_checkpoints USING btree (updated_at) WHERE status = 'completed';
+
+CREATE TABLE public.run_checkpoint_states (
+    run_id uuid NOT NULL,
+    task_id uuid NOT NULL,
+    seq bigint NOT NULL,
+    snapshot jsonb NOT NULL
+);
+
+ALTER TABLE public.run_checkpoint_states OWNER TO postgres;
+
+COMMENT ON COLUMN public.run_checkpoint_states.seq IS 'Order of the snapshots of the run, a snapshot only replaces one with a lower seq';
+
+ALTER TABLE ONLY public.run_checkpoint_states
+    ADD CONSTRAINT run_checkpoint_states_pkey PRIMARY KEY (run_id, task_id);
+
+ALTER TABLE ONLY public.run_checkpoint_states
+    ADD CONSTRAINT run_checkpoint_states_run_id_fkey FOREIGN KEY (run_id) REFERENCES public.run_checkpoints(run_id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.run_checkpoints TO service_role;
+GRANT ALL ON TABLE public.run_checkpoint_states TO service_role;
@@ -0,0 +1,71 @@
+--
+-- Labeling queues of runs for human review. A queue's filter selects the runs of the project
+-- which are its items, e.g. failed runs or runs with low scores. Reviewers lease the next
+-- unlabeled run, the lease expires after lease_seconds and the run returns to the queue unless
+-- it was labeled. Labels are validated against the queue's label_schema.
+--
+
+CREATE TABLE public.labeling_queues (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    project_id uuid NOT NULL,
+    name text NOT NULL,
+    filter jsonb DEFAULT '{}'::jsonb NOT NULL,
+    label_schema jsonb DEFAULT '{}'::jsonb NOT NULL,
+    lease_seconds integer DEFAULT 600 NOT NULL
+);
+
+ALTER TABLE public.labeling_queues OWNER TO postgres;
+
+ALTER TABLE ONLY public.labeling_queues
+    ADD CONSTRAINT labeling_queues_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.labeling_queues
+    ADD CONSTRAINT labeling_queues_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TABLE public.labeling_queue_leases (
+    queue_id uuid NOT NULL,
+    run_id uuid NOT NULL,
+    leased_by uuid NOT NULL,
+    expires_at timestamp with time zone NOT NULL
+);
+
+ALTER TABLE public.labeling_queue_leases OWNER TO postgres;
+
+COMMENT ON TABLE public.labeling_queue_leases IS 'Expired leases are ignored, and replaced by the next lease of the run';
+
+ALTER TABLE ONLY public.labeling_queue_leases
+    ADD CONSTRAINT labeling_queue_leases_pkey PRIMARY KEY (queue_id, run_id);
+
+ALTER TABLE ONLY public.labeling_queue_leases
+    ADD CONSTRAINT labeling_queue_leases_queue_id_fkey FOREIGN KEY (queue_id) REFERENCES public.labeling_queues(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.labeling_queue_leases
+    ADD CONSTRAINT labeling_queue_leases_run_id_fkey FOREIGN KEY (run_id) REFERENCES public.runs(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TABLE public.run_labels (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    queue_id uuid NOT NULL,
+    run_id uuid NOT NULL,
+    labeled_by uuid NOT NULL,
+    label_values jsonb NOT NULL
+);
+
+ALTER TABLE public.run_labels OWNER TO postgres;
+
+ALTER TABLE ONLY public.run_labels
+    ADD CONSTRAINT run_labels_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.run_labels
+    ADD CONSTRAINT run_labels_queue_id_run_id_key UNIQUE (queue_id, run_id);
+
+ALTER TABLE ONLY public.run_labels
+    ADD CONSTRAINT run_labels_queue_id_fkey FOREIGN KEY (queue_id) REFERENCES public.labeling_queues(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.run_labels
+    ADD CONSTRAINT run_labels_run_id_fkey FOREIGN KEY (run_id) REFERENCES public.runs(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.labeling_queues TO service_role;
+GRANT ALL ON TABLE public.labeling_queue_leases TO service_role;
+GRANT ALL ON TABLE public.run_labels TO service_role;
@@ -0,0 +1,53 @@
+--
+-- Semantic search indexes of projects. A project's index is named by the project id, and
+-- collection_name is the collection of the semantic search service it's stored in. Projects
+-- without a row are in the collection named by the index, with the default config.
+--
+-- Reindex jobs rebuild an index with a new embedding model or chunking config in a shadow
+-- collection, and swap it with the live one once done. Datapoints are reindexed in order of
+-- dataset and datapoint ids, and the jobs keep the last reindexed one to resume from.
+--
+
+CREATE TABLE public.semantic_indexes (
+    name text NOT NULL,
+    collection_name text NOT NULL,
+    config jsonb DEFAULT '{}'::jsonb NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.semantic_indexes OWNER TO postgres;
+
+ALTER TABLE ONLY public.semantic_indexes
+    ADD CONSTRAINT semantic_indexes_pkey PRIMARY KEY (name);
+
+CREATE TABLE public.semantic_reindex_jobs (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL,
+    project_id uuid NOT NULL,
+    status text DEFAULT 'running'::text NOT NULL,
+    config jsonb NOT NULL,
+    collection_name text NOT NULL,
+    total_datapoints bigint DEFAULT '0'::bigint NOT NULL,
+    reindexed_datapoints bigint DEFAULT '0'::bigint NOT NULL,
+    cursor_dataset_id uuid,
+    cursor_datapoint_id uuid,
+    error text
+);
+
+ALTER TABLE public.semantic_reindex_jobs OWNER TO postgres;
+
+COMMENT ON COLUMN public.semantic_reindex_jobs.status IS 'running, failed, succeeded or superseded';
+COMMENT ON COLUMN public.semantic_reindex_jobs.collection_name IS 'Shadow collection the index is rebuilt in';
+
+ALTER TABLE ONLY public.semantic_reindex_jobs
+    ADD CONSTRAINT semantic_reindex_jobs_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.semantic_reindex_jobs
+    ADD CONSTRAINT semantic_reindex_jobs_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+-- At most one job of a project is unfinished, writes to the index also go to its shadow collection
+CREATE UNIQUE INDEX semantic_reindex_jobs_unfinished_idx ON public.semantic_reindex_jobs USING btree (project_id) WHERE status IN ('running', 'failed');
+
+GRANT ALL ON TABLE public.semantic_indexes TO service_role;
+GRANT ALL ON TABLE public.semantic_reindex_jobs TO service_role;
@@ -0,0 +1,79 @@
+--
+-- Retention of traces per workspace, with separate TTLs of captured content (span inputs and
+-- outputs, event inputs, run outputs and node I/O), of span metadata (traces, spans and events)
+-- and of rollup stats (spans in ClickHouse). Expired rows are purged by app-server in batches,
+-- and each purge is recorded. Traces whose content was purged, or which were deleted, are kept
+-- in purged_traces so that links to them, e.g. from evaluation results, show them as purged.
+--
+
+CREATE TABLE public.workspace_retention_policies (
+    workspace_id uuid NOT NULL,
+    content_ttl_days integer,
+    span_metadata_ttl_days integer,
+    rollup_ttl_days integer,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.workspace_retention_policies OWNER TO postgres;
+
+COMMENT ON COLUMN public.workspace_retention_policies.content_ttl_days IS 'Data is kept forever if the TTL is NULL';
+
+ALTER TABLE ONLY public.workspace_retention_policies
+    ADD CONSTRAINT workspace_retention_policies_pkey PRIMARY KEY (workspace_id);
+
+ALTER TABLE ONLY public.workspace_retention_policies
+    ADD CONSTRAINT workspace_retention_policies_workspace_id_fkey FOREIGN KEY (workspace_id) REFERENCES public.workspaces(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TABLE public.purged_traces (
+    trace_id uuid NOT NULL,
+    project_id uuid NOT NULL,
+    content_purged_at timestamp with time zone DEFAULT now() NOT NULL,
+    deleted_at timestamp with time zone
+);
+
+ALTER TABLE public.purged_traces OWNER TO postgres;
+
+ALTER TABLE ONLY public.purged_traces
+    ADD CONSTRAINT purged_traces_pkey PRIMARY KEY (trace_id);
+
+ALTER TABLE ONLY public.purged_traces
+    ADD CONSTRAINT purged_traces_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TYPE public.retention_purge_kind AS ENUM (
+    'content',
+    'spanMetadata',
+    'rollups',
+    'userErasure'
+);
+
+ALTER TYPE public.retention_purge_kind OWNER TO postgres;
+
+CREATE TABLE public.retention_purges (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    project_id uuid NOT NULL,
+    kind public.retention_purge_kind NOT NULL,
+    cutoff timestamp with time zone,
+    end_user_id text,
+    purged_rows bigint NOT NULL
+);
+
+ALTER TABLE public.retention_purges OWNER TO postgres;
+
+COMMENT ON COLUMN public.retention_purges.kind IS 'userErasure purges the content of an end user''s traces on request';
+
+ALTER TABLE ONLY public.retention_purges
+    ADD CONSTRAINT retention_purges_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.retention_purges
+    ADD CONSTRAINT retention_purges_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE INDEX traces_project_id_start_time_idx ON public.traces USING btree (project_id, start_time);
+CREATE INDEX traces_project_id_user_id_idx ON public.traces USING btree (project_id, user_id);
+CREATE INDEX spans_trace_id_idx ON public.spans USING btree (trace_id);
+CREATE INDEX events_span_id_idx ON public.events USING btree (span_id);
+CREATE INDEX runs_trace_id_idx ON public.runs USING btree (trace_id);
+
+GRANT ALL ON TABLE public.workspace_retention_policies TO service_role;
+GRANT ALL ON TABLE public.purged_traces TO service_role;
+GRANT ALL ON TABLE public.retention_purges TO service_role;
@@ -0,0 +1,24 @@
+--
+-- Defaults of the model and params of LLM nodes per workspace. Pipelines override them with
+-- modelDefaults of their graphs, and nodes with their own config. Server defaults, below the
+-- workspace's, are set in app-server's LLM_NODE_DEFAULTS.
+--
+
+CREATE TABLE public.workspace_model_defaults (
+    workspace_id uuid NOT NULL,
+    model text,
+    params jsonb DEFAULT '{}'::jsonb NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.workspace_model_defaults OWNER TO postgres;
+
+COMMENT ON COLUMN public.workspace_model_defaults.model IS 'provider:model, the server default is used if NULL';
+
+ALTER TABLE ONLY public.workspace_model_defaults
+    ADD CONSTRAINT workspace_model_defaults_pkey PRIMARY KEY (workspace_id);
+
+ALTER TABLE ONLY public.workspace_model_defaults
+    ADD CONSTRAINT workspace_model_defaults_workspace_id_fkey FOREIGN KEY (workspace_id) REFERENCES public.workspaces(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.workspace_model_defaults TO service_role;
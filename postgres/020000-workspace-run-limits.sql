@@ -0,0 +1,23 @@
+--
+-- Limits of the runs of all projects of a workspace, on top of the limits of each API key.
+-- NULL limits don't apply.
+--
+
+CREATE TABLE public.workspace_run_limits (
+    workspace_id uuid NOT NULL,
+    max_concurrent_runs bigint,
+    tokens_per_day bigint,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.workspace_run_limits OWNER TO postgres;
+
+COMMENT ON COLUMN public.workspace_run_limits.max_concurrent_runs IS 'Runs executing at once, counted per app-server instance';
+
+ALTER TABLE ONLY public.workspace_run_limits
+    ADD CONSTRAINT workspace_run_limits_pkey PRIMARY KEY (workspace_id);
+
+ALTER TABLE ONLY public.workspace_run_limits
+    ADD CONSTRAINT workspace_run_limits_workspace_id_fkey FOREIGN KEY (workspace_id) REFERENCES public.workspaces(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.workspace_run_limits TO service_role;
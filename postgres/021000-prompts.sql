@@ -0,0 +1,65 @@
+--
+-- Registry of the prompts of a project, which template and LLM nodes reference by name.
+-- Every update of a prompt is a new version, and labels, e.g. production, point to one of them.
+--
+
+CREATE TABLE public.prompts (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    project_id uuid NOT NULL,
+    name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.prompts OWNER TO postgres;
+
+ALTER TABLE ONLY public.prompts
+    ADD CONSTRAINT prompts_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.prompts
+    ADD CONSTRAINT prompts_project_id_name_key UNIQUE (project_id, name);
+
+ALTER TABLE ONLY public.prompts
+    ADD CONSTRAINT prompts_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TABLE public.prompt_versions (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    prompt_id uuid NOT NULL,
+    version integer NOT NULL,
+    template text NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.prompt_versions OWNER TO postgres;
+
+COMMENT ON COLUMN public.prompt_versions.version IS 'Number of the version within its prompt, from 1';
+
+ALTER TABLE ONLY public.prompt_versions
+    ADD CONSTRAINT prompt_versions_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.prompt_versions
+    ADD CONSTRAINT prompt_versions_prompt_id_version_key UNIQUE (prompt_id, version);
+
+ALTER TABLE ONLY public.prompt_versions
+    ADD CONSTRAINT prompt_versions_prompt_id_fkey FOREIGN KEY (prompt_id) REFERENCES public.prompts(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TABLE public.prompt_labels (
+    prompt_id uuid NOT NULL,
+    label text NOT NULL,
+    version_id uuid NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE public.prompt_labels OWNER TO postgres;
+
+ALTER TABLE ONLY public.prompt_labels
+    ADD CONSTRAINT prompt_labels_pkey PRIMARY KEY (prompt_id, label);
+
+ALTER TABLE ONLY public.prompt_labels
+    ADD CONSTRAINT prompt_labels_prompt_id_fkey FOREIGN KEY (prompt_id) REFERENCES public.prompts(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.prompt_labels
+    ADD CONSTRAINT prompt_labels_version_id_fkey FOREIGN KEY (version_id) REFERENCES public.prompt_versions(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+GRANT ALL ON TABLE public.prompts TO service_role;
+GRANT ALL ON TABLE public.prompt_versions TO service_role;
+GRANT ALL ON TABLE public.prompt_labels TO service_role;
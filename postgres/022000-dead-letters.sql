@@ -0,0 +1,35 @@
+--
+-- Data which couldn't be processed and would otherwise be dropped, i.e. span batches which
+-- failed to be exported to ClickHouse and run jobs whose payload couldn't be parsed, kept to be
+-- inspected and retried by admins.
+--
+
+CREATE TYPE public.dead_letter_kind AS ENUM (
+    'spanExport',
+    'runJob'
+);
+
+ALTER TYPE public.dead_letter_kind OWNER TO postgres;
+
+CREATE TABLE public.dead_letters (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL,
+    kind public.dead_letter_kind NOT NULL,
+    payload jsonb NOT NULL,
+    error text NOT NULL,
+    retry_count integer DEFAULT 0 NOT NULL
+);
+
+ALTER TABLE public.dead_letters OWNER TO postgres;
+
+COMMENT ON COLUMN public.dead_letters.payload IS 'Spans of the batch, or the run job as JSON, or as a string if it is not valid JSON';
+COMMENT ON COLUMN public.dead_letters.error IS 'Error of the last attempt';
+COMMENT ON COLUMN public.dead_letters.retry_count IS 'Number of times the entry was retried and failed again';
+
+ALTER TABLE ONLY public.dead_letters
+    ADD CONSTRAINT dead_letters_pkey PRIMARY KEY (id);
+
+CREATE INDEX dead_letters_kind_created_at_idx ON public.dead_letters USING btree (kind, created_at);
+
+GRANT ALL ON TABLE public.dead_letters TO service_role;
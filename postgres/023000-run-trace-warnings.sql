@@ -0,0 +1,6 @@
+--
+-- Warnings of runs in strict trace mode whose trace wasn't persisted before they responded, e.g.
+-- because persisting it timed out and went on in the background.
+--
+
+ALTER TABLE public.runs ADD COLUMN trace_warning text;
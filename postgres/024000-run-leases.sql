@@ -0,0 +1,8 @@
+--
+-- Leases of runs from the run queue on the workers executing them. A worker renews the lease of
+-- its run while executing it, and another worker only takes the run over once the lease expired,
+-- resuming it from its checkpoint if it has one.
+--
+
+ALTER TABLE public.runs ADD COLUMN leased_by uuid;
+ALTER TABLE public.runs ADD COLUMN lease_expires_at timestamp with time zone;
@@ -0,0 +1,6 @@
+--
+-- Run store of checkpointed runs, the key/value store their nodes share, restored with the
+-- snapshots of the tasks when a run is resumed.
+--
+
+ALTER TABLE public.run_checkpoints ADD COLUMN run_store jsonb;
@@ -0,0 +1,65 @@
+--
+-- Backfills score persisted runs of a pipeline in a time range with evaluators, from the
+-- recorded node I/O of the runs, without running the pipeline again. Runs are scored in order of
+-- creation time and id, and the backfill keeps the last scored one with the counts so far.
+-- Scores are kept per run, runs which couldn't be scored have the reason they were skipped.
+--
+
+CREATE TABLE public.evaluation_backfills (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL,
+    project_id uuid NOT NULL,
+    pipeline_id uuid NOT NULL,
+    status text DEFAULT 'running'::text NOT NULL,
+    config jsonb NOT NULL,
+    total_runs bigint DEFAULT '0'::bigint NOT NULL,
+    scored_runs bigint DEFAULT '0'::bigint NOT NULL,
+    skipped_runs bigint DEFAULT '0'::bigint NOT NULL,
+    failed_runs bigint DEFAULT '0'::bigint NOT NULL,
+    skip_reasons jsonb DEFAULT '{}'::jsonb NOT NULL,
+    cursor_created_at timestamp with time zone,
+    cursor_run_id uuid,
+    error text
+);
+
+ALTER TABLE public.evaluation_backfills OWNER TO postgres;
+
+COMMENT ON COLUMN public.evaluation_backfills.status IS 'running, failed or succeeded';
+COMMENT ON COLUMN public.evaluation_backfills.skip_reasons IS 'Number of skipped runs by reason';
+
+ALTER TABLE ONLY public.evaluation_backfills
+    ADD CONSTRAINT evaluation_backfills_pkey PRIMARY KEY (id);
+
+ALTER TABLE ONLY public.evaluation_backfills
+    ADD CONSTRAINT evaluation_backfills_project_id_fkey FOREIGN KEY (project_id) REFERENCES public.projects(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE TABLE public.evaluation_backfill_scores (
+    backfill_id uuid NOT NULL,
+    run_id uuid NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    status text NOT NULL,
+    scores jsonb DEFAULT '{}'::jsonb NOT NULL,
+    passed boolean,
+    skip_reason text,
+    error text,
+    evaluator_trace_id uuid
+);
+
+ALTER TABLE public.evaluation_backfill_scores OWNER TO postgres;
+
+COMMENT ON COLUMN public.evaluation_backfill_scores.status IS 'scored, skipped or failed';
+
+ALTER TABLE ONLY public.evaluation_backfill_scores
+    ADD CONSTRAINT evaluation_backfill_scores_pkey PRIMARY KEY (backfill_id, run_id);
+
+ALTER TABLE ONLY public.evaluation_backfill_scores
+    ADD CONSTRAINT evaluation_backfill_scores_backfill_id_fkey FOREIGN KEY (backfill_id) REFERENCES public.evaluation_backfills(id) ON UPDATE CASCADE ON DELETE CASCADE;
+
+CREATE INDEX evaluation_backfill_scores_run_id_idx ON public.evaluation_backfill_scores USING btree (run_id);
+
+-- Runs of a pipeline in a time range, in the order backfills score them
+CREATE INDEX runs_pipeline_id_created_at_idx ON public.runs USING btree (pipeline_id, created_at, id);
+
+GRANT ALL ON TABLE public.evaluation_backfills TO service_role;
+GRANT ALL ON TABLE public.evaluation_backfill_scores TO service_role;
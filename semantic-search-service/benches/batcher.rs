@@ -0,0 +1,96 @@
+//! Benchmarks of coalescing concurrent embedding requests
+//!
+//! Rounds of concurrent requests of a few inputs each against a mock provider, which takes a
+//! fixed time per call and, as providers rate limit, makes a few calls at once. Requests are
+//! embedded with a call of their own each, with a zero window, and coalesced within a window.
+//! Provider calls per round are printed for both.
+//!
+//! Run with `cargo bench --bench batcher`
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::future::join_all;
+use semantic_search_service::embeddings::{Batcher, Embed, EmbedOutput};
+use tokio::{runtime::Runtime, sync::Semaphore};
+
+const REQUESTS: usize = 200;
+const INPUTS_PER_REQUEST: usize = 4;
+const CALL_LATENCY: Duration = Duration::from_millis(20);
+const CONCURRENT_CALLS: usize = 8;
+const WINDOW: Duration = Duration::from_millis(10);
+
+struct MockProvider {
+    calls: Arc<AtomicUsize>,
+    permits: Semaphore,
+}
+
+impl Embed for MockProvider {
+    async fn embed(&self, inputs: Vec<String>, _is_query: bool) -> Result<EmbedOutput> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let _permit = self.permits.acquire().await?;
+        tokio::time::sleep(CALL_LATENCY).await;
+        Ok(EmbedOutput {
+            embeddings: inputs.iter().map(|_| vec![0.0; 8]).collect(),
+            input_token_count: inputs.len() as u32,
+        })
+    }
+
+    fn max_inputs(&self) -> usize {
+        2048
+    }
+}
+
+fn batcher(window: Duration) -> (Batcher<MockProvider>, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let provider = MockProvider {
+        calls: calls.clone(),
+        permits: Semaphore::new(CONCURRENT_CALLS),
+    };
+    (Batcher::new(provider, window), calls)
+}
+
+async fn embed_concurrently(batcher: &Batcher<MockProvider>) {
+    let requests = (0..REQUESTS).map(|request| {
+        let inputs = (0..INPUTS_PER_REQUEST)
+            .map(|input| format!("input {} of request {}", input, request))
+            .collect();
+        batcher.embed(inputs, false)
+    });
+    for output in join_all(requests).await {
+        output.unwrap();
+    }
+}
+
+fn bench_requests(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let windows = [("unbatched", Duration::ZERO), ("batched", WINDOW)];
+
+    let mut group = c.benchmark_group("concurrent_requests");
+    // unbatched rounds take REQUESTS / CONCURRENT_CALLS * CALL_LATENCY
+    group.sample_size(10);
+    for (name, window) in windows {
+        let (batcher, calls) = batcher(window);
+        rt.block_on(embed_concurrently(&batcher));
+        println!(
+            "{}: {} provider calls for {} requests",
+            name,
+            calls.load(Ordering::SeqCst),
+            REQUESTS
+        );
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| embed_concurrently(&batcher))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_requests);
+criterion_main!(benches);
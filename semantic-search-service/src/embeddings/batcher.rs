@@ -0,0 +1,341 @@
+//! Coalescing of concurrent embedding requests
+//!
+//! Pipelines run many semantic search and similarity nodes at once, each embedding a few inputs.
+//! Requests arriving within a short window of the first one are embedded with one call to the
+//! model, and each request gets back the embeddings of its own inputs. The provider bills the
+//! tokens of the whole call, so they are attributed to the requests in proportion to the length
+//! of their inputs.
+//!
+//! A batch with more inputs than the provider embeds in one call is split into calls of whole
+//! requests, which are made concurrently. If a call fails, each of its requests is embedded with
+//! a call of its own, so that inputs the provider rejects only fail their own request.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+use futures::future::join_all;
+use log::warn;
+use tokio::sync::oneshot;
+
+use super::{Embed, EmbedOutput};
+
+enum Outcome {
+    Embedded(EmbedOutput),
+    /// Embed the inputs with a call of their own
+    Retry,
+    Failed(String),
+}
+
+struct Pending {
+    inputs: Vec<String>,
+    sender: oneshot::Sender<Outcome>,
+}
+
+type Batches = Mutex<HashMap<bool, Vec<Pending>>>;
+
+pub struct Batcher<E> {
+    model: E,
+    window: Duration,
+    /// Requests waiting for the next call, by whether they embed queries
+    batches: Batches,
+}
+
+/// Removes the batch of a leader cancelled while waiting, so that its requests are embedded with
+/// calls of their own instead of waiting forever
+struct LeaderGuard<'a> {
+    batches: &'a Batches,
+    is_query: bool,
+    waiting: bool,
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        if self.waiting {
+            if let Ok(mut batches) = self.batches.lock() {
+                batches.remove(&self.is_query);
+            }
+        }
+    }
+}
+
+impl<E: Embed> Batcher<E> {
+    /// Requests are embedded with calls of their own if the window is zero
+    pub fn new(model: E, window: Duration) -> Self {
+        Self {
+            model,
+            window,
+            batches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Embed the batch with calls of at most `Embed::max_inputs` inputs, unless a request has
+    /// more inputs on its own
+    async fn dispatch(&self, batch: Vec<Pending>, is_query: bool) {
+        let max_inputs = self.model.max_inputs();
+        let mut calls: Vec<Vec<Pending>> = Vec::new();
+        let mut call_inputs = 0;
+        for pending in batch {
+            match calls.last_mut() {
+                Some(call) if call_inputs + pending.inputs.len() <= max_inputs => {
+                    call_inputs += pending.inputs.len();
+                    call.push(pending);
+                }
+                _ => {
+                    call_inputs = pending.inputs.len();
+                    calls.push(vec![pending]);
+                }
+            }
+        }
+        join_all(calls.into_iter().map(|call| self.call(call, is_query))).await;
+    }
+
+    async fn call(&self, batch: Vec<Pending>, is_query: bool) {
+        let inputs = batch
+            .iter()
+            .flat_map(|pending| pending.inputs.iter().cloned())
+            .collect::<Vec<_>>();
+        let input_count = inputs.len();
+
+        let result = match self.model.embed(inputs, is_query).await {
+            Ok(output) if output.embeddings.len() != input_count => Err(anyhow::anyhow!(
+                "Expected {} embeddings, got {}",
+                input_count,
+                output.embeddings.len()
+            )),
+            result => result,
+        };
+
+        match result {
+            Ok(output) => {
+                let weights = batch
+                    .iter()
+                    .map(|pending| {
+                        pending
+                            .inputs
+                            .iter()
+                            .map(|input| input.chars().count() as u64)
+                            .sum()
+                    })
+                    .collect::<Vec<u64>>();
+                let token_counts = split_tokens(output.input_token_count, &weights);
+
+                let mut embeddings = output.embeddings.into_iter();
+                for (pending, input_token_count) in batch.into_iter().zip(token_counts) {
+                    let embeddings = embeddings.by_ref().take(pending.inputs.len()).collect();
+                    // the request may have been cancelled
+                    let _ = pending.sender.send(Outcome::Embedded(EmbedOutput {
+                        embeddings,
+                        input_token_count,
+                    }));
+                }
+            }
+            Err(e) if batch.len() == 1 => {
+                for pending in batch {
+                    let _ = pending.sender.send(Outcome::Failed(e.to_string()));
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Batched embedding of {} requests failed, embedding them separately: {}",
+                    batch.len(),
+                    e
+                );
+                for pending in batch {
+                    let _ = pending.sender.send(Outcome::Retry);
+                }
+            }
+        }
+    }
+}
+
+impl<E: Embed> Embed for Batcher<E> {
+    async fn embed(&self, inputs: Vec<String>, is_query: bool) -> Result<EmbedOutput> {
+        if self.window.is_zero() || inputs.is_empty() {
+            return self.model.embed(inputs, is_query).await;
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let is_leader = {
+            let mut batches = self.batches.lock().unwrap();
+            let batch = batches.entry(is_query).or_default();
+            batch.push(Pending {
+                inputs: inputs.clone(),
+                sender,
+            });
+            batch.len() == 1
+        };
+
+        // the first request of a batch waits for the others, and makes the call for all of them
+        if is_leader {
+            let mut guard = LeaderGuard {
+                batches: &self.batches,
+                is_query,
+                waiting: true,
+            };
+            tokio::time::sleep(self.window).await;
+            let batch = {
+                let mut batches = self.batches.lock().unwrap();
+                batches.remove(&is_query).unwrap_or_default()
+            };
+            guard.waiting = false;
+            self.dispatch(batch, is_query).await;
+        }
+
+        match receiver.await {
+            Ok(Outcome::Embedded(output)) => Ok(output),
+            Ok(Outcome::Failed(e)) => Err(anyhow::anyhow!(e)),
+            // the batched call failed, or its leader was cancelled
+            Ok(Outcome::Retry) | Err(_) => self.model.embed(inputs, is_query).await,
+        }
+    }
+
+    fn max_inputs(&self) -> usize {
+        self.model.max_inputs()
+    }
+}
+
+/// Split the tokens of a call in proportion to the weights, the remainder of rounding down goes to
+/// the last request
+fn split_tokens(total: u32, weights: &[u64]) -> Vec<u32> {
+    let weight_sum = weights.iter().sum::<u64>();
+    let mut counts = weights
+        .iter()
+        .map(|weight| match weight_sum {
+            0 => 0,
+            _ => (total as u64 * weight / weight_sum) as u32,
+        })
+        .collect::<Vec<_>>();
+    let remainder = total - counts.iter().sum::<u32>();
+    if let Some(last) = counts.last_mut() {
+        *last += remainder;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Embeds each input as its length, billing a token per character. Calls with an input
+    /// "invalid" fail, as providers reject calls with inputs they can't embed.
+    struct MockModel {
+        calls: AtomicUsize,
+        latency: Duration,
+        max_inputs: usize,
+    }
+
+    impl MockModel {
+        fn new(latency: Duration) -> Self {
+            Self {
+                calls: AtomicUsize::new(0),
+                latency,
+                max_inputs: 2048,
+            }
+        }
+    }
+
+    impl Embed for MockModel {
+        async fn embed(&self, inputs: Vec<String>, _is_query: bool) -> Result<EmbedOutput> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.latency).await;
+            if inputs.iter().any(|input| input == "invalid") {
+                return Err(anyhow::anyhow!("Invalid input"));
+            }
+            Ok(EmbedOutput {
+                embeddings: inputs
+                    .iter()
+                    .map(|input| vec![input.len() as f32])
+                    .collect(),
+                input_token_count: inputs.iter().map(|input| input.len() as u32).sum(),
+            })
+        }
+
+        fn max_inputs(&self) -> usize {
+            self.max_inputs
+        }
+    }
+
+    fn batcher(latency: Duration, window: Duration) -> Batcher<MockModel> {
+        Batcher::new(MockModel::new(latency), window)
+    }
+
+    #[test]
+    fn test_split_tokens() {
+        assert_eq!(split_tokens(10, &[1, 1, 2]), vec![2, 2, 6]);
+        assert_eq!(split_tokens(7, &[1, 1]), vec![3, 4]);
+        assert_eq!(split_tokens(5, &[0, 0]), vec![0, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_coalesces_concurrent_requests() {
+        let batcher = batcher(Duration::ZERO, Duration::from_millis(10));
+        let requests = [vec!["a", "bb"], vec!["ccc"], vec!["dddd"]];
+        let outputs = join_all(requests.iter().map(|inputs| {
+            let inputs = inputs.iter().map(|input| input.to_string()).collect();
+            batcher.embed(inputs, false)
+        }))
+        .await;
+
+        assert_eq!(batcher.model.calls.load(Ordering::SeqCst), 1);
+        for (inputs, output) in requests.iter().zip(outputs) {
+            let output = output.unwrap();
+            let expected = inputs
+                .iter()
+                .map(|input| vec![input.len() as f32])
+                .collect::<Vec<_>>();
+            assert_eq!(output.embeddings, expected);
+            assert_eq!(
+                output.input_token_count,
+                inputs.iter().map(|input| input.len() as u32).sum::<u32>()
+            );
+        }
+
+        // queries and documents are embedded differently, so they aren't batched together
+        let (document, query) = tokio::join!(
+            batcher.embed(vec!["a".to_string()], false),
+            batcher.embed(vec!["b".to_string()], true)
+        );
+        assert!(document.is_ok() && query.is_ok());
+        assert_eq!(batcher.model.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_splits_batches_at_max_inputs() {
+        let mut batcher = batcher(Duration::ZERO, Duration::from_millis(10));
+        batcher.model.max_inputs = 3;
+        let requests = [vec!["a", "bb"], vec!["ccc"], vec!["dddd", "e"], vec!["ff"]];
+        let outputs = join_all(requests.iter().map(|inputs| {
+            let inputs = inputs.iter().map(|input| input.to_string()).collect();
+            batcher.embed(inputs, false)
+        }))
+        .await;
+
+        assert_eq!(batcher.model.calls.load(Ordering::SeqCst), 2);
+        for (inputs, output) in requests.iter().zip(outputs) {
+            let expected = inputs
+                .iter()
+                .map(|input| vec![input.len() as f32])
+                .collect::<Vec<_>>();
+            assert_eq!(output.unwrap().embeddings, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_isolates_requests() {
+        let batcher = batcher(Duration::ZERO, Duration::from_millis(10));
+        let outputs = join_all(
+            ["first", "invalid", "third"]
+                .iter()
+                .map(|input| batcher.embed(vec![input.to_string()], true)),
+        )
+        .await;
+
+        assert_eq!(outputs[0].as_ref().unwrap().embeddings, vec![vec![5.0]]);
+        assert!(outputs[1].is_err());
+        assert_eq!(outputs[2].as_ref().unwrap().input_token_count, 5);
+        // the batched call, and a call per request
+        assert_eq!(batcher.model.calls.load(Ordering::SeqCst), 4);
+    }
+}
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use tokio::task;
 use futures::future::join_all;
 
-use super::{Embed, Embedding, Endpoint};
+use super::{Embed, EmbedOutput, Embedding, Endpoint};
 
 pub struct Cohere {
     endpoint: Endpoint,
@@ -33,6 +33,20 @@ impl CohereEmbeddingModel {
 #[derive(Deserialize)]
 struct CohereResponse {
     embeddings: Vec<Embedding>,
+    #[serde(default)]
+    meta: Option<CohereMeta>,
+}
+
+#[derive(Deserialize)]
+struct CohereMeta {
+    #[serde(default)]
+    billed_units: Option<CohereBilledUnits>,
+}
+
+#[derive(Deserialize)]
+struct CohereBilledUnits {
+    #[serde(default)]
+    input_tokens: u32,
 }
 
 impl Cohere {
@@ -41,10 +55,14 @@ impl Cohere {
     }
 }
 
+/// Most texts the API embeds in one call
+const MAX_TEXTS: usize = 96;
+
 impl Embed for Cohere {
-    async fn embed(&self, inputs: Vec<String>, is_query: bool) -> Result<Vec<Embedding>> {
+    async fn embed(&self, inputs: Vec<String>, is_query: bool) -> Result<EmbedOutput> {
         // call endpoint in batches of 96
         let mut embeddings: Vec<Embedding> = Vec::new();
+        let mut input_token_count = 0;
         let mut tasks = Vec::new();
 
         let input_type = if is_query {
@@ -53,7 +71,7 @@ impl Embed for Cohere {
             "search_document".to_string()
         };
 
-        for chunk in inputs.chunks(96) {
+        for chunk in inputs.chunks(MAX_TEXTS) {
             let model = self.model.to_string();
             let input_type = input_type.clone();
             let texts = chunk.to_vec();
@@ -68,8 +86,12 @@ impl Embed for Cohere {
     
                 let body = serde_json::to_string(&body).unwrap();
                 let res = endpoint.call::<CohereResponse>(body).await?;
+                let input_tokens = res
+                    .meta
+                    .and_then(|meta| meta.billed_units)
+                    .map_or(0, |billed_units| billed_units.input_tokens);
 
-                Ok(res.embeddings)
+                Ok((res.embeddings, input_tokens))
             });
 
             tasks.push(task);
@@ -77,9 +99,18 @@ impl Embed for Cohere {
 
         let results = join_all(tasks).await;
         for res in results {
-            embeddings.extend(res??);
+            let (chunk_embeddings, input_tokens) = res??;
+            embeddings.extend(chunk_embeddings);
+            input_token_count += input_tokens;
         }
 
-        Ok(embeddings)
+        Ok(EmbedOutput {
+            embeddings,
+            input_token_count,
+        })
+    }
+
+    fn max_inputs(&self) -> usize {
+        MAX_TEXTS
     }
 }
@@ -1,18 +1,30 @@
+mod batcher;
 mod cohere;
 mod endpoint;
 use anyhow::Result;
 use enum_dispatch::enum_dispatch;
 
+pub use batcher::*;
 pub use cohere::*;
 pub use endpoint::*;
 pub type Embedding = Vec<f32>;
 
+/// Embeddings of the inputs, in order, with the tokens the provider billed for them
+pub struct EmbedOutput {
+    pub embeddings: Vec<Embedding>,
+    pub input_token_count: u32,
+}
+
 #[enum_dispatch]
 pub enum EmbeddingModel {
     Cohere(Cohere),
 }
 
 #[enum_dispatch(EmbeddingModel)]
+#[allow(async_fn_in_trait)]
 pub trait Embed {
-    async fn embed(&self, inputs: Vec<String>, is_query: bool) -> Result<Vec<Embedding>>;
+    async fn embed(&self, inputs: Vec<String>, is_query: bool) -> Result<EmbedOutput>;
+
+    /// Most inputs the provider embeds in one call
+    fn max_inputs(&self) -> usize;
 }
@@ -0,0 +1,3 @@
+//! Modules of the service which the benches use
+
+pub mod embeddings;
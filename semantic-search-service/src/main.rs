@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
 use dotenv;
-use embeddings::EmbeddingModel;
+use embeddings::{Batcher, EmbeddingModel};
 use semantic_search::semantic_search_grpc::Model;
 use tokio;
 use tonic::transport::Server;
@@ -47,9 +48,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         embeddings::CohereEmbeddingModel::EmbedMultilingualV3,
     );
 
-    let mut embedding_models: HashMap<Model, EmbeddingModel> = HashMap::new();
+    // concurrent requests within the window are embedded with one call to the model
+    let batch_window = Duration::from_millis(
+        env::var("EMBEDDING_BATCH_WINDOW_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .unwrap_or(10),
+    );
+
+    let mut embedding_models: HashMap<Model, Batcher<EmbeddingModel>> = HashMap::new();
     // embedding_models.insert(Model::GteBase, onnx);
-    embedding_models.insert(Model::CohereMultilingual, EmbeddingModel::Cohere(cohere_multilingual));
+    embedding_models.insert(
+        Model::CohereMultilingual,
+        Batcher::new(EmbeddingModel::Cohere(cohere_multilingual), batch_window),
+    );
 
     let semantic_search_service = SemanticSearchService::new(embedding_models, qdrant);
 
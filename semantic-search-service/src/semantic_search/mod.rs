@@ -8,7 +8,7 @@ use std::sync::Arc;
 use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
-use crate::embeddings::{Embed, Embedding, EmbeddingModel};
+use crate::embeddings::{Batcher, Embed, EmbeddingModel};
 use crate::vectordb::Qdrant;
 use semantic_search_grpc::query_response::QueryPoint;
 use semantic_search_grpc::semantic_search_server::SemanticSearch;
@@ -23,7 +23,7 @@ use self::semantic_search_grpc::Model;
 pub mod semantic_search_grpc;
 
 pub struct SemanticSearchService {
-    embedding_models: HashMap<Model, EmbeddingModel>,
+    embedding_models: HashMap<Model, Batcher<EmbeddingModel>>,
     qdrant: Arc<Qdrant>,
 }
 
@@ -38,7 +38,10 @@ impl Model {
 }
 
 impl SemanticSearchService {
-    pub fn new(embedding_models: HashMap<Model, EmbeddingModel>, qdrant: Arc<Qdrant>) -> Self {
+    pub fn new(
+        embedding_models: HashMap<Model, Batcher<EmbeddingModel>>,
+        qdrant: Arc<Qdrant>,
+    ) -> Self {
         Self {
             embedding_models,
             qdrant,
@@ -68,7 +71,7 @@ impl SemanticSearch for SemanticSearchService {
             .embed(inputs, false)
             .await
         {
-            Ok(embeddings) => embeddings,
+            Ok(output) => output.embeddings,
             Err(e) => {
                 error!("{}", e);
                 return Err(Status::internal(e.to_string()));
@@ -139,21 +142,21 @@ impl SemanticSearch for SemanticSearchService {
         let message = request.into_inner();
         let query = message.query;
 
-        let embeddings: Vec<Embedding> = match self
+        let output = match self
             .embedding_models
             .get(&Model::from_int(message.model))
             .unwrap()
             .embed(vec![query], true)
             .await
         {
-            Ok(embeddings) => embeddings,
+            Ok(output) => output,
             Err(e) => {
                 error!("Error embedding queries: {}", e);
                 return Err(Status::internal(e.to_string()));
             }
         };
 
-        let embedding = if let Some(embedding) = embeddings.first() {
+        let embedding = if let Some(embedding) = output.embeddings.first() {
             embedding
         } else {
             return Err(Status::internal("No embeddings found"));
@@ -197,7 +200,10 @@ impl SemanticSearch for SemanticSearchService {
             })
             .collect();
 
-        Ok(Response::new(QueryResponse { results }))
+        Ok(Response::new(QueryResponse {
+            results,
+            input_token_count: output.input_token_count,
+        }))
     }
 
     async fn create_collection(
@@ -260,7 +266,7 @@ impl SemanticSearch for SemanticSearchService {
             .embed(contents, false)
             .await
         {
-            Ok(embeddings) => embeddings,
+            Ok(output) => output.embeddings,
             Err(e) => {
                 error!("{}", e);
                 return Err(Status::internal(e.to_string()));
@@ -297,20 +303,21 @@ impl SemanticSearch for SemanticSearchService {
             all_contents.push(pair.second.clone());
         }
 
-        let embeddings = match self
+        let output = match self
             .embedding_models
             .get(&model)
             .expect("Failed to get model when calculating similarity scores")
             .embed(all_contents, false)
             .await
         {
-            Ok(embeddings) => embeddings,
+            Ok(output) => output,
             Err(e) => {
                 error!("{}", e);
                 return Err(Status::internal(e.to_string()));
             }
         };
 
+        let embeddings = output.embeddings;
         let mut scores = Vec::new();
 
         for i in 0..embeddings.len() / 2 {
@@ -331,6 +338,9 @@ impl SemanticSearch for SemanticSearchService {
             scores.push(1.0 - score);
         }
 
-        return Ok(Response::new(CalculateSimilarityScoresResponse { scores }));
+        return Ok(Response::new(CalculateSimilarityScoresResponse {
+            scores,
+            input_token_count: output.input_token_count,
+        }));
     }
 }
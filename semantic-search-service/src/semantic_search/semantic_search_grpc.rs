@@ -78,6 +78,9 @@ pub struct QueryRequest {
 pub struct QueryResponse {
     #[prost(message, repeated, tag = "1")]
     pub results: ::prost::alloc::vec::Vec<query_response::QueryPoint>,
+    /// Billed tokens of embedding the query
+    #[prost(uint32, tag = "2")]
+    pub input_token_count: u32,
 }
 /// Nested message and enum types in `QueryResponse`.
 pub mod query_response {
@@ -146,6 +149,9 @@ pub mod calculate_similarity_scores_request {
 pub struct CalculateSimilarityScoresResponse {
     #[prost(float, repeated, tag = "1")]
     pub scores: ::prost::alloc::vec::Vec<f32>,
+    /// Billed tokens of embedding the compared contents
+    #[prost(uint32, tag = "2")]
+    pub input_token_count: u32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]